@@ -0,0 +1,338 @@
+// Load generator for live sessions. Drives a running backend the way real clients do:
+// registers a DM and players per session, seats the players through join requests, then
+// every player sends a steady mix of chat, dice and HP messages over the WebSocket.
+//
+//   cargo bench --bench session_load -- --sessions 20 --players 5 --duration 30 --rate 2
+//
+// LOAD_TEST_URL picks the server (default http://localhost:3000), which must run with
+// REQUIRE_EMAIL_VERIFICATION=false so the accounts can log in. Reports, per message
+// kind, the sender's round trip (send until its own copy of the broadcast arrives) and
+// the fan-out time (send until the last connection in the session has it).
+
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+type Socket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+#[derive(Clone)]
+struct Config {
+    url: String,
+    sessions: usize,
+    players: usize,
+    duration: Duration,
+    // Messages per second from each player; the server allows 30 per connection
+    rate: f64,
+}
+
+impl Config {
+    fn from_args() -> Result<Config, String> {
+        let mut config = Config {
+            url: std::env::var("LOAD_TEST_URL").unwrap_or_else(|_| "http://localhost:3000".to_string()),
+            sessions: 10,
+            players: 4,
+            duration: Duration::from_secs(20),
+            rate: 2.0,
+        };
+        let mut args = std::env::args().skip(1).filter(|arg| arg != "--bench");
+        while let Some(flag) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", flag));
+            match flag.as_str() {
+                "--sessions" => config.sessions = value()?.parse().map_err(|e| format!("--sessions: {}", e))?,
+                "--players" => config.players = value()?.parse().map_err(|e| format!("--players: {}", e))?,
+                "--duration" => config.duration = Duration::from_secs(value()?.parse().map_err(|e| format!("--duration: {}", e))?),
+                "--rate" => config.rate = value()?.parse().map_err(|e| format!("--rate: {}", e))?,
+                other => return Err(format!("Unknown flag {}", other)),
+            }
+        }
+        if config.rate <= 0.0 || config.rate > 30.0 {
+            return Err("--rate must be above 0 and at most 30".to_string());
+        }
+        Ok(config)
+    }
+}
+
+// One message in flight, keyed by a tag the broadcast carries back
+struct Probe {
+    kind: &'static str,
+    sender: usize,
+    sent: Instant,
+    round_trip: Option<Duration>,
+    receipts: usize,
+    fan_out: Duration,
+}
+
+#[derive(Default)]
+struct Stats {
+    probes: Mutex<HashMap<String, Probe>>,
+    // Error messages from the server, e.g. RATE_LIMITED
+    errors: AtomicU64,
+}
+
+struct Seat {
+    token: String,
+    character_id: Option<Uuid>,
+}
+
+struct SessionSetup {
+    session_id: Uuid,
+    // The DM first, then the players
+    seats: Vec<Seat>,
+}
+
+async fn call(http: &reqwest::Client, config: &Config, method: reqwest::Method, path: &str, token: Option<&str>, body: Value) -> Result<Value, String> {
+    let mut request = http.request(method, format!("{}{}", config.url, path)).json(&body);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await.map_err(|e| format!("{}: {}", path, e))?;
+    let status = response.status();
+    let text = response.text().await.map_err(|e| format!("{}: {}", path, e))?;
+    if !status.is_success() {
+        return Err(format!("{} answered {}: {}", path, status, text));
+    }
+    Ok(serde_json::from_str(&text).unwrap_or(Value::Null))
+}
+
+// Registers and logs in, returning the token
+async fn create_user(http: &reqwest::Client, config: &Config, name: &str) -> Result<String, String> {
+    let password = "load-test-password";
+    let email = format!("{}@load.test", name);
+    call(http, config, reqwest::Method::POST, "/auth/register", None, json!({"email": email, "username": name, "password": password})).await?;
+    let login = call(http, config, reqwest::Method::POST, "/auth/login", None, json!({"email": email, "password": password})).await?;
+    login["token"].as_str().map(str::to_string).ok_or_else(|| "Login returned no token".to_string())
+}
+
+async fn set_up_session(http: &reqwest::Client, config: &Config, run: &str, index: usize) -> Result<SessionSetup, String> {
+    let dm_token = create_user(http, config, &format!("load_{}_{}_dm", run, index)).await?;
+    let campaign = call(http, config, reqwest::Method::POST, "/campaigns", Some(&dm_token), json!({
+        "name": format!("Load test {} #{}", run, index),
+        "settings": {"discoverable": true},
+    })).await?;
+    let campaign_id = campaign["id"].as_str().ok_or("Campaign has no id")?.to_string();
+    let session = call(http, config, reqwest::Method::POST, "/sessions", Some(&dm_token), json!({"campaign_id": campaign_id, "name": "Load"})).await?;
+    let session_id = session["id"].as_str().and_then(|id| Uuid::parse_str(id).ok()).ok_or("Session has no id")?;
+
+    let mut seats = vec![Seat { token: dm_token.clone(), character_id: None }];
+    for player in 0..config.players {
+        let token = create_user(http, config, &format!("load_{}_{}_p{}", run, index, player)).await?;
+        let request = call(http, config, reqwest::Method::POST, &format!("/campaigns/{}/join-requests", campaign_id), Some(&token), json!({})).await?;
+        let request_id = request["id"].as_str().ok_or("Join request has no id")?;
+        call(http, config, reqwest::Method::POST, &format!("/campaigns/{}/join-requests/{}/approve", campaign_id, request_id), Some(&dm_token), json!({})).await?;
+        let character = call(http, config, reqwest::Method::POST, "/characters", Some(&token), json!({
+            "campaign_id": campaign_id,
+            "name": format!("Runner {}", player),
+            "hp_max": 20,
+        })).await?;
+        let character_id = character["id"].as_str().and_then(|id| Uuid::parse_str(id).ok());
+        seats.push(Seat { token, character_id });
+    }
+    Ok(SessionSetup { session_id, seats })
+}
+
+async fn connect(http: &reqwest::Client, config: &Config, session_id: Uuid, token: &str) -> Result<Socket, String> {
+    let ticket = call(http, config, reqwest::Method::POST, &format!("/sessions/{}/ws-ticket", session_id), Some(token), Value::Null).await?;
+    let ticket = ticket["ticket"].as_str().ok_or("No ticket in response")?;
+    let url = format!("{}/ws?ticket={}", config.url.replacen("http", "ws", 1), ticket);
+    let (mut socket, _) = tokio_tungstenite::connect_async(url).await.map_err(|e| format!("WebSocket connect: {}", e))?;
+
+    let join = json!({"type": "JoinSession", "data": {"session_id": session_id}});
+    socket.send(Message::Text(join.to_string())).await.map_err(|e| e.to_string())?;
+    while let Some(frame) = socket.next().await {
+        if let Ok(Message::Text(text)) = frame {
+            if serde_json::from_str::<Value>(&text).is_ok_and(|value| value["type"] == "SessionJoined") {
+                return Ok(socket);
+            }
+        }
+    }
+    Err("Socket closed before joining".to_string())
+}
+
+// The tag a broadcast carries back, for the three kinds of traffic we send
+fn probe_tag(message: &Value) -> Option<String> {
+    let data = &message["data"];
+    match message["type"].as_str()? {
+        "ChatMessage" => data["message"].as_str().map(str::to_string),
+        "DiceRolled" => data["result"]["reason"].as_str().map(str::to_string),
+        // HP updates carry no free text, so the tag rides on a distinct hp_max
+        "HPUpdated" => Some(format!("hp:{}:{}", data["character_id"].as_str()?, data["hp_max"])),
+        _ => None,
+    }
+}
+
+async fn read_frames(mut stream: futures::stream::SplitStream<Socket>, connection: usize, stats: Arc<Stats>) {
+    while let Some(Ok(frame)) = stream.next().await {
+        let Message::Text(text) = frame else { continue };
+        let Ok(message) = serde_json::from_str::<Value>(&text) else { continue };
+        if message["type"] == "Error" {
+            stats.errors.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+        let Some(tag) = probe_tag(&message) else { continue };
+        let mut probes = stats.probes.lock().unwrap();
+        if let Some(probe) = probes.get_mut(&tag) {
+            let elapsed = probe.sent.elapsed();
+            probe.receipts += 1;
+            probe.fan_out = probe.fan_out.max(elapsed);
+            if probe.sender == connection {
+                probe.round_trip = Some(elapsed);
+            }
+        }
+    }
+}
+
+async fn send_traffic(
+    mut sink: futures::stream::SplitSink<Socket, Message>,
+    connection: usize,
+    character_id: Option<Uuid>,
+    config: Config,
+    stats: Arc<Stats>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / config.rate));
+    // Spread the players out instead of firing in lockstep
+    tokio::time::sleep(Duration::from_millis(rand::random::<u64>() % 1000)).await;
+    let deadline = Instant::now() + config.duration;
+    let mut count: u64 = 0;
+    while Instant::now() < deadline {
+        interval.tick().await;
+        count += 1;
+        let probe_id = Uuid::new_v4();
+        let (kind, tag, message) = match (count % 3, character_id) {
+            (0, Some(character_id)) => {
+                let hp_max = 20 + count;
+                let tag = format!("hp:{}:{}", character_id, hp_max);
+                (
+                    "hp",
+                    tag,
+                    json!({"type": "UpdateHP", "data": {"character_id": character_id, "hp_current": (count % 20) + 1, "hp_max": hp_max}}),
+                )
+            }
+            (1, _) => {
+                let tag = format!("load {}", probe_id);
+                ("roll", tag.clone(), json!({"type": "DiceRoll", "data": {"dice": "1d20+5", "reason": tag}}))
+            }
+            _ => {
+                let tag = format!("load {}", probe_id);
+                ("chat", tag.clone(), json!({"type": "ChatMessage", "data": {"message": tag}}))
+            }
+        };
+        stats.probes.lock().unwrap().insert(tag, Probe {
+            kind,
+            sender: connection,
+            sent: Instant::now(),
+            round_trip: None,
+            receipts: 0,
+            fan_out: Duration::ZERO,
+        });
+        if sink.send(Message::Text(message.to_string())).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    sorted[((sorted.len() - 1) as f64 * p / 100.0).round() as usize]
+}
+
+fn report(config: &Config, stats: &Stats, elapsed: Duration) {
+    let probes = stats.probes.lock().unwrap();
+    let listeners = config.players + 1;
+    println!(
+        "\n{} sessions x {} players (+ DM) for {:.0?}: {} messages sent, {:.1}/s",
+        config.sessions, config.players, elapsed, probes.len(), probes.len() as f64 / elapsed.as_secs_f64()
+    );
+    println!("{:<6} {:>7} {:>7} {:>10} {:>10} {:>10} {:>10} {:>12}", "kind", "sent", "lost", "rtt p50", "rtt p95", "rtt p99", "fan p95", "delivered");
+    for kind in ["chat", "roll", "hp"] {
+        let of_kind: Vec<&Probe> = probes.values().filter(|probe| probe.kind == kind).collect();
+        if of_kind.is_empty() {
+            continue;
+        }
+        let mut round_trips: Vec<Duration> = of_kind.iter().filter_map(|probe| probe.round_trip).collect();
+        round_trips.sort();
+        let mut fan_outs: Vec<Duration> = of_kind.iter().filter(|probe| probe.receipts > 0).map(|probe| probe.fan_out).collect();
+        fan_outs.sort();
+        let receipts: usize = of_kind.iter().map(|probe| probe.receipts).sum();
+        println!(
+            "{:<6} {:>7} {:>7} {:>10.1?} {:>10.1?} {:>10.1?} {:>10.1?} {:>11.1}%",
+            kind,
+            of_kind.len(),
+            of_kind.len() - round_trips.len(),
+            percentile(&round_trips, 50.0),
+            percentile(&round_trips, 95.0),
+            percentile(&round_trips, 99.0),
+            percentile(&fan_outs, 95.0),
+            100.0 * receipts as f64 / (of_kind.len() * listeners) as f64,
+        );
+    }
+    println!("server errors: {}", stats.errors.load(Ordering::Relaxed));
+}
+
+#[tokio::main]
+async fn main() {
+    // `cargo test --benches` runs this binary too; only generate load under `cargo bench`
+    if !std::env::args().any(|arg| arg == "--bench") {
+        println!("session_load: run with `cargo bench --bench session_load` against a running server");
+        return;
+    }
+    let config = match Config::from_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let http = reqwest::Client::new();
+    let run = Uuid::new_v4().simple().to_string()[..8].to_string();
+
+    println!("Setting up {} sessions against {}", config.sessions, config.url);
+    let setups = futures::future::join_all((0..config.sessions).map(|index| set_up_session(&http, &config, &run, index))).await;
+    let setups: Vec<SessionSetup> = match setups.into_iter().collect() {
+        Ok(setups) => setups,
+        Err(e) => {
+            eprintln!("Setup failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let stats = Arc::new(Stats::default());
+    let mut senders = Vec::new();
+    let mut readers = Vec::new();
+    let mut connection = 0;
+    for setup in &setups {
+        for (seat_index, seat) in setup.seats.iter().enumerate() {
+            let socket = match connect(&http, &config, setup.session_id, &seat.token).await {
+                Ok(socket) => socket,
+                Err(e) => {
+                    eprintln!("Connect failed: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            let (sink, stream) = socket.split();
+            readers.push(tokio::spawn(read_frames(stream, connection, stats.clone())));
+            // The DM only listens
+            if seat_index > 0 {
+                senders.push(tokio::spawn(send_traffic(sink, connection, seat.character_id, config.clone(), stats.clone())));
+            }
+            connection += 1;
+        }
+    }
+
+    println!("Running {} connections for {:?}", connection, config.duration);
+    let started = Instant::now();
+    futures::future::join_all(senders).await;
+    let elapsed = started.elapsed();
+    // Give the last broadcasts time to land before counting
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    for reader in readers {
+        reader.abort();
+    }
+    report(&config, &stats, elapsed);
+}
@@ -0,0 +1,16 @@
+-- Create quests table (quest tracker, seeded from accepted plot hooks)
+CREATE TABLE quests (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    campaign_id UUID NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    character_id UUID REFERENCES characters(id) ON DELETE SET NULL,
+    title VARCHAR(255) NOT NULL,
+    description TEXT,
+    status VARCHAR(50) DEFAULT 'open',
+    source VARCHAR(50) DEFAULT 'manual',
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    updated_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+CREATE INDEX idx_quests_campaign_id ON quests(campaign_id);
+CREATE INDEX idx_quests_character_id ON quests(character_id);
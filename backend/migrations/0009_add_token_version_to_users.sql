@@ -0,0 +1,2 @@
+-- Bumped whenever a user's campaign memberships change, invalidating roles embedded in their JWT
+ALTER TABLE users ADD COLUMN token_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- When the user's last WebSocket connection closed
+ALTER TABLE users ADD COLUMN last_seen_at TIMESTAMPTZ;
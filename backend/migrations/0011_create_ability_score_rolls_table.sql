@@ -0,0 +1,11 @@
+-- Ability scores rolled live by the server in front of the session; one roll per character
+CREATE TABLE ability_score_rolls (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    character_id UUID NOT NULL UNIQUE REFERENCES characters(id) ON DELETE CASCADE,
+    session_id UUID REFERENCES sessions(id) ON DELETE SET NULL,
+    rolled_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    method VARCHAR(50) NOT NULL DEFAULT '4d6_drop_lowest',
+    rolls JSONB NOT NULL,
+    scores JSONB NOT NULL,
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
@@ -0,0 +1,16 @@
+-- Requests to join discoverable campaigns, reviewed by the DM
+CREATE TABLE join_requests (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    campaign_id UUID NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message TEXT,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending',
+    response_message TEXT,
+    reviewed_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    reviewed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+-- At most one open request per user and campaign
+CREATE UNIQUE INDEX idx_join_requests_pending ON join_requests(campaign_id, user_id) WHERE status = 'pending';
+CREATE INDEX idx_join_requests_user_id ON join_requests(user_id);
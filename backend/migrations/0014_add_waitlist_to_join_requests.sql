@@ -0,0 +1,5 @@
+-- Approved requests that found the campaign full wait here (status 'waitlisted'),
+-- in the order they were reviewed, until a seat opens
+DROP INDEX idx_join_requests_pending;
+CREATE UNIQUE INDEX idx_join_requests_open ON join_requests(campaign_id, user_id) WHERE status IN ('pending', 'waitlisted');
+CREATE INDEX idx_join_requests_waitlist ON join_requests(campaign_id, reviewed_at) WHERE status = 'waitlisted';
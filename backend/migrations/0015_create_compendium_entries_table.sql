@@ -0,0 +1,17 @@
+-- Reference content (spells, monsters, items...) imported into a campaign from the SRD or
+-- published modules. An entry is identified by its source and slug so re-imports update
+-- rather than duplicate it.
+CREATE TABLE compendium_entries (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    campaign_id UUID NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    source VARCHAR(100) NOT NULL,
+    slug VARCHAR(200) NOT NULL,
+    kind VARCHAR(50) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    data JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    updated_at TIMESTAMPTZ DEFAULT NOW(),
+    UNIQUE (campaign_id, source, slug)
+);
+
+CREATE INDEX idx_compendium_entries_campaign_kind ON compendium_entries(campaign_id, kind);
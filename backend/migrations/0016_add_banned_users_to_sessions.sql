@@ -0,0 +1,2 @@
+-- Users the DM kicked with a ban; they can't get a WebSocket ticket or rejoin the session
+ALTER TABLE sessions ADD COLUMN banned_user_ids UUID[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,16 @@
+-- Snapshot of a character after every change, for looking it up as of a past session
+CREATE TABLE character_revisions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    character_id UUID NOT NULL REFERENCES characters(id) ON DELETE CASCADE,
+    -- Set when the change was made live in a session
+    session_id UUID REFERENCES sessions(id) ON DELETE SET NULL,
+    changed_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    snapshot JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_character_revisions_character ON character_revisions(character_id, created_at);
+
+-- Existing characters start their history at their last update
+INSERT INTO character_revisions (character_id, snapshot, created_at)
+SELECT id, to_jsonb(characters), updated_at FROM characters;
@@ -0,0 +1,3 @@
+-- Intermissions: when the current pause began, and total time spent paused
+ALTER TABLE sessions ADD COLUMN paused_at TIMESTAMPTZ;
+ALTER TABLE sessions ADD COLUMN paused_seconds INT NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Continuations of a session on a later day link back to the session they pick up from.
+-- A session can be continued only once, so the chain never branches.
+ALTER TABLE sessions ADD COLUMN previous_session_id UUID REFERENCES sessions(id) ON DELETE SET NULL;
+CREATE UNIQUE INDEX idx_sessions_previous_session_id ON sessions(previous_session_id);
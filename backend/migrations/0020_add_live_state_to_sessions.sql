@@ -0,0 +1,3 @@
+-- In-memory realtime state (replay sequence, turn timer settings) saved when the server
+-- evicts an idle session, and restored when someone rejoins
+ALTER TABLE sessions ADD COLUMN live_state JSONB;
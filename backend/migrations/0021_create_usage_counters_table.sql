@@ -0,0 +1,10 @@
+-- Daily usage per campaign and per user, for operators. No foreign keys: counts outlive
+-- deleted campaigns and accounts.
+CREATE TABLE usage_counters (
+    day DATE NOT NULL,
+    subject_type VARCHAR(20) NOT NULL CHECK (subject_type IN ('campaign', 'user')),
+    subject_id UUID NOT NULL,
+    metric VARCHAR(30) NOT NULL,
+    count BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (day, subject_type, subject_id, metric)
+);
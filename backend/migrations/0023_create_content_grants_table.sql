@@ -0,0 +1,27 @@
+-- Edit rights the DM hands to individual players on individual pieces of campaign content,
+-- e.g. the bard maintaining the tavern's page. entity_type names the content table.
+CREATE TABLE content_grants (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    campaign_id UUID NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    entity_type VARCHAR(50) NOT NULL,
+    entity_id UUID NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    granted_by UUID NOT NULL REFERENCES users(id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (entity_type, entity_id, user_id)
+);
+
+CREATE INDEX idx_content_grants_campaign ON content_grants(campaign_id);
+
+-- Who changed what, so player-written lore stays attributed
+CREATE TABLE content_contributions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    campaign_id UUID NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    entity_type VARCHAR(50) NOT NULL,
+    entity_id UUID NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id),
+    changes JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_content_contributions_entity ON content_contributions(entity_type, entity_id, created_at);
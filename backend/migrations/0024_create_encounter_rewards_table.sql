@@ -0,0 +1,29 @@
+ALTER TABLE characters ADD COLUMN xp INTEGER NOT NULL DEFAULT 0;
+
+-- XP and loot suggested when combat ends, waiting for the DM to accept or dismiss them
+CREATE TABLE encounter_rewards (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    campaign_id UUID NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    xp_total INTEGER NOT NULL,
+    defeated JSONB NOT NULL DEFAULT '[]',
+    xp_split JSONB NOT NULL DEFAULT '[]',
+    loot JSONB NOT NULL DEFAULT '[]',
+    status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'accepted', 'dismissed')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_encounter_rewards_session ON encounter_rewards(session_id, created_at);
+
+-- Treasure the party has won but not yet divided
+CREATE TABLE loot_items (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    campaign_id UUID NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    reward_id UUID REFERENCES encounter_rewards(id) ON DELETE SET NULL,
+    name VARCHAR(255) NOT NULL,
+    quantity INTEGER NOT NULL DEFAULT 1,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_loot_items_campaign ON loot_items(campaign_id);
@@ -0,0 +1,41 @@
+-- Initiative entries with a character_id take their HP and AC from the character row
+-- instead of keeping a snapshot that drifts. Whenever a session's game state is written
+-- the linked entries are refreshed, and a change to a character's HP or AC rewrites the
+-- game state of the sessions tracking it.
+CREATE OR REPLACE FUNCTION resolve_linked_initiative()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF jsonb_typeof(NEW.game_state->'initiative_order') = 'array' THEN
+        NEW.game_state = jsonb_set(NEW.game_state, '{initiative_order}', COALESCE((
+            SELECT jsonb_agg(
+                CASE WHEN c.id IS NULL THEN t.entry
+                     ELSE t.entry || jsonb_build_object('hp_current', c.hp_current, 'hp_max', c.hp_max, 'ac', c.ac)
+                END
+                ORDER BY t.position)
+            FROM jsonb_array_elements(NEW.game_state->'initiative_order') WITH ORDINALITY AS t(entry, position)
+            LEFT JOIN characters c ON c.id::text = t.entry->>'character_id' AND c.campaign_id = NEW.campaign_id
+        ), '[]'::jsonb));
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER resolve_sessions_linked_initiative BEFORE INSERT OR UPDATE OF game_state ON sessions
+    FOR EACH ROW EXECUTE FUNCTION resolve_linked_initiative();
+
+CREATE OR REPLACE FUNCTION sync_initiative_from_character()
+RETURNS TRIGGER AS $$
+BEGIN
+    -- Rewriting game_state fires resolve_linked_initiative on each tracking session
+    UPDATE sessions SET game_state = game_state
+    WHERE campaign_id = NEW.campaign_id
+      AND status <> 'ended'
+      AND game_state->'initiative_order' @> jsonb_build_array(jsonb_build_object('character_id', NEW.id));
+    RETURN NULL;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER sync_characters_initiative AFTER UPDATE OF hp_current, hp_max, ac ON characters
+    FOR EACH ROW
+    WHEN (OLD.hp_current IS DISTINCT FROM NEW.hp_current OR OLD.hp_max IS DISTINCT FROM NEW.hp_max OR OLD.ac IS DISTINCT FROM NEW.ac)
+    EXECUTE FUNCTION sync_initiative_from_character();
@@ -0,0 +1,62 @@
+-- Each fight gets its own row instead of living in sessions.game_state. A session runs at
+-- most one combat at a time; ended combats stay behind as the session's history.
+CREATE TABLE combats (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    state JSONB NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ended_at TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_combats_session ON combats(session_id, started_at);
+CREATE UNIQUE INDEX idx_combats_one_active ON combats(session_id) WHERE ended_at IS NULL;
+
+-- Fights running in sessions that haven't ended carry over; the tracker keys then leave
+-- the session's game state, which keeps table-wide settings such as skip_downed
+INSERT INTO combats (session_id, state, started_at)
+SELECT id, game_state, COALESCE(started_at, updated_at)
+FROM sessions
+WHERE status <> 'ended' AND COALESCE((game_state->>'combat_active')::boolean, FALSE);
+
+UPDATE sessions SET game_state = game_state - 'initiative_order' - 'current_turn' - 'round' - 'combat_active' - 'conditions'
+WHERE game_state ?| ARRAY['initiative_order', 'current_turn', 'round', 'combat_active', 'conditions'];
+
+ALTER TABLE encounter_rewards ADD COLUMN combat_id UUID REFERENCES combats(id) ON DELETE SET NULL;
+
+-- Linked initiative entries now resolve against the combat's tracker
+DROP TRIGGER resolve_sessions_linked_initiative ON sessions;
+
+CREATE OR REPLACE FUNCTION resolve_linked_initiative()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF jsonb_typeof(NEW.state->'initiative_order') = 'array' THEN
+        NEW.state = jsonb_set(NEW.state, '{initiative_order}', COALESCE((
+            SELECT jsonb_agg(
+                CASE WHEN c.id IS NULL THEN t.entry
+                     ELSE t.entry || jsonb_build_object('hp_current', c.hp_current, 'hp_max', c.hp_max, 'ac', c.ac)
+                END
+                ORDER BY t.position)
+            FROM jsonb_array_elements(NEW.state->'initiative_order') WITH ORDINALITY AS t(entry, position)
+            LEFT JOIN characters c ON c.id::text = t.entry->>'character_id'
+                AND c.campaign_id = (SELECT campaign_id FROM sessions WHERE id = NEW.session_id)
+        ), '[]'::jsonb));
+    END IF;
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE TRIGGER resolve_combats_linked_initiative BEFORE INSERT OR UPDATE OF state ON combats
+    FOR EACH ROW EXECUTE FUNCTION resolve_linked_initiative();
+
+CREATE OR REPLACE FUNCTION sync_initiative_from_character()
+RETURNS TRIGGER AS $$
+BEGIN
+    -- Rewriting state fires resolve_linked_initiative on each running combat tracking the character
+    UPDATE combats SET state = state
+    WHERE ended_at IS NULL
+      AND session_id IN (SELECT id FROM sessions WHERE campaign_id = NEW.campaign_id)
+      AND state->'initiative_order' @> jsonb_build_array(jsonb_build_object('character_id', NEW.id));
+    RETURN NULL;
+END;
+$$ language 'plpgsql';
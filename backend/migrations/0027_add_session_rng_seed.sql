@@ -0,0 +1,4 @@
+-- Sessions created with a seed roll from it, so every roll can be reproduced from the event
+-- log. Each roll takes the next counter value and is drawn from the seed's stream for it.
+ALTER TABLE sessions ADD COLUMN rng_seed BIGINT;
+ALTER TABLE sessions ADD COLUMN roll_counter BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,15 @@
+-- Server seeds for provably fair rolls. The commitment (SHA-256 of the seed) is published
+-- with every roll drawn from it; the seed itself stays secret until revealed_at.
+CREATE TABLE roll_server_seeds (
+    id UUID PRIMARY KEY,
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    server_seed BYTEA NOT NULL,
+    commitment TEXT NOT NULL,
+    next_nonce BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    revealed_at TIMESTAMPTZ
+);
+
+-- One unrevealed seed per session; rolls take their nonces from it
+CREATE UNIQUE INDEX idx_roll_server_seeds_current ON roll_server_seeds(session_id) WHERE revealed_at IS NULL;
+CREATE INDEX idx_roll_server_seeds_commitment ON roll_server_seeds(session_id, commitment);
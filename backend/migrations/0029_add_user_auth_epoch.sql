@@ -0,0 +1,4 @@
+-- Tokens carry the auth_epoch they were issued at; bumping it revokes every earlier token.
+-- Disabled accounts can't log in.
+ALTER TABLE users ADD COLUMN auth_epoch INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN disabled_at TIMESTAMPTZ;
@@ -0,0 +1,18 @@
+-- The campaign chronicle, written a chapter per ended session so it can grow without
+-- rewriting what is already there
+CREATE TABLE campaign_chronicle_chapters (
+    session_id UUID PRIMARY KEY REFERENCES sessions(id) ON DELETE CASCADE,
+    campaign_id UUID NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    model VARCHAR(100) NOT NULL,
+    generated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_campaign_chronicle_chapters_campaign ON campaign_chronicle_chapters(campaign_id);
+
+-- The chapters assembled into one document
+CREATE TABLE campaign_chronicles (
+    campaign_id UUID PRIMARY KEY REFERENCES campaigns(id) ON DELETE CASCADE,
+    content TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
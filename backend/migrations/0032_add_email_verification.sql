@@ -0,0 +1,19 @@
+-- Accounts registered from now on start unverified and can't log in until the emailed link
+-- is followed; existing accounts are left verified
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE users ALTER COLUMN email_verified SET DEFAULT FALSE;
+
+-- Single-use tokens from verification emails, stored as SHA-256 hashes
+CREATE TABLE email_verifications (
+    token_hash TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_email_verifications_user ON email_verifications(user_id, created_at);
+
+-- Verification emails go out through the outbox
+ALTER TABLE outbox DROP CONSTRAINT outbox_channel_check;
+ALTER TABLE outbox ADD CONSTRAINT outbox_channel_check CHECK (channel IN ('notification', 'webhook', 'email'));
@@ -0,0 +1,5 @@
+-- Throwaway accounts from the public demo; the session janitor deletes them, and the
+-- campaigns they run, once this passes
+ALTER TABLE users ADD COLUMN demo_expires_at TIMESTAMPTZ;
+
+CREATE INDEX idx_users_demo_expires_at ON users(demo_expires_at) WHERE demo_expires_at IS NOT NULL;
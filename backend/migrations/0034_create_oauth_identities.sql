@@ -0,0 +1,10 @@
+-- Discord and Google accounts users sign in with; one user can link several
+CREATE TABLE oauth_identities (
+    provider VARCHAR(20) NOT NULL,
+    provider_user_id TEXT NOT NULL,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (provider, provider_user_id)
+);
+
+CREATE INDEX idx_oauth_identities_user ON oauth_identities(user_id);
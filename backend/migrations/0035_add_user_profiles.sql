@@ -0,0 +1,3 @@
+-- Shown to fellow campaign members alongside the username
+ALTER TABLE users ADD COLUMN display_name VARCHAR(100);
+ALTER TABLE users ADD COLUMN avatar_url TEXT;
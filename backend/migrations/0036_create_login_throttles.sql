@@ -0,0 +1,7 @@
+-- Failed login counts per email ('account:...') and client address ('address:...'), see throttle.rs
+CREATE TABLE login_throttles (
+    key VARCHAR(300) PRIMARY KEY,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_failure_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    locked_until TIMESTAMPTZ
+);
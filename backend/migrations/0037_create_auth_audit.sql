@@ -0,0 +1,13 @@
+-- Security-relevant account activity, see audit.rs. Failed logins for unknown emails have no user.
+CREATE TABLE auth_audit (
+    id UUID PRIMARY KEY,
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    event VARCHAR(50) NOT NULL,
+    ip VARCHAR(45),
+    user_agent TEXT,
+    detail JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_auth_audit_user ON auth_audit(user_id, created_at DESC);
+CREATE INDEX idx_auth_audit_created_at ON auth_audit(created_at);
@@ -0,0 +1,3 @@
+-- Storage keys of an uploaded avatar and its thumbnail; NULL when the avatar URL is external
+ALTER TABLE users ADD COLUMN avatar_key TEXT;
+ALTER TABLE users ADD COLUMN avatar_thumb_key TEXT;
@@ -0,0 +1,2 @@
+-- Client settings (timezone, dice color, notification opt-ins, ...); missing keys read as defaults
+ALTER TABLE users ADD COLUMN preferences JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,10 @@
+-- Single-use passwordless login links, stored as SHA-256 hashes like email verifications
+CREATE TABLE magic_links (
+    token_hash TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_magic_links_user ON magic_links(user_id, created_at);
@@ -0,0 +1,7 @@
+-- Suspensions end on their own at suspended_until; a ban is disabled_at with no end.
+-- Campaigns of a suspended or banned DM are frozen rather than deleted.
+ALTER TABLE users ADD COLUMN suspended_until TIMESTAMPTZ;
+ALTER TABLE users ADD COLUMN suspension_reason TEXT;
+ALTER TABLE campaigns ADD COLUMN frozen_at TIMESTAMPTZ;
+
+CREATE INDEX idx_users_suspended_until ON users(suspended_until) WHERE suspended_until IS NOT NULL;
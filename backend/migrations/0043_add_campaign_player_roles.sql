@@ -0,0 +1,4 @@
+-- Roles within a campaign besides its DM. Co-DMs share the DM's duties; observers follow
+-- along without a seat.
+ALTER TABLE campaign_players ADD COLUMN role TEXT NOT NULL DEFAULT 'player'
+    CHECK (role IN ('player', 'co_dm', 'observer'));
@@ -0,0 +1,4 @@
+-- Campaigns their DM offers as templates for new campaigns
+ALTER TABLE campaigns ADD COLUMN is_template BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX idx_campaigns_template ON campaigns(created_at) WHERE is_template;
@@ -0,0 +1,26 @@
+-- Markdown notes kept with a campaign: session prep, lore, NPC write-ups. Titles are unique
+-- per campaign, ignoring case, since notes link to each other by title.
+CREATE TABLE campaign_notes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    campaign_id UUID NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    title VARCHAR(255) NOT NULL,
+    content TEXT NOT NULL DEFAULT '',
+    tags TEXT[] NOT NULL DEFAULT '{}',
+    visibility TEXT NOT NULL DEFAULT 'dm' CHECK (visibility IN ('dm', 'shared')),
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_campaign_notes_title ON campaign_notes(campaign_id, LOWER(title));
+CREATE INDEX idx_campaign_notes_tags ON campaign_notes USING GIN (tags);
+
+-- [[Title]] links found in each note's content, kept by lower-cased title so links to notes
+-- that don't exist yet start working once the note is written
+CREATE TABLE campaign_note_links (
+    note_id UUID NOT NULL REFERENCES campaign_notes(id) ON DELETE CASCADE,
+    target_title TEXT NOT NULL,
+    PRIMARY KEY (note_id, target_title)
+);
+
+CREATE INDEX idx_campaign_note_links_target ON campaign_note_links(target_title);
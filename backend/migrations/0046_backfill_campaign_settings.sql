@@ -0,0 +1,40 @@
+-- Campaign settings now follow a schema (see campaign_settings.rs). Keys outside it move
+-- under houserules, known keys of the wrong type are dropped or converted, and the defaults
+-- are filled in.
+UPDATE campaigns SET settings = '{}' WHERE settings IS NULL OR jsonb_typeof(settings) <> 'object';
+
+WITH split AS (
+    SELECT id,
+        COALESCE(jsonb_object_agg(key, value) FILTER (WHERE key IN ('system', 'level_range', 'allowed_sources', 'houserules', 'max_players', 'discoverable', 'webhook_url')), '{}') AS known,
+        COALESCE(jsonb_object_agg(key, value) FILTER (WHERE key NOT IN ('system', 'level_range', 'allowed_sources', 'houserules', 'max_players', 'discoverable', 'webhook_url')), '{}') AS extra
+    FROM campaigns, jsonb_each(settings)
+    GROUP BY id
+)
+UPDATE campaigns c
+SET settings = split.known || jsonb_build_object('houserules',
+    CASE WHEN jsonb_typeof(split.known->'houserules') = 'object' THEN split.known->'houserules' ELSE '{}' END || split.extra)
+FROM split
+WHERE c.id = split.id;
+
+UPDATE campaigns SET settings = settings - 'system'
+WHERE settings ? 'system' AND (jsonb_typeof(settings->'system') <> 'string' OR TRIM(settings->>'system') = '');
+UPDATE campaigns SET settings = settings - 'level_range'
+WHERE settings ? 'level_range' AND jsonb_typeof(settings->'level_range') <> 'object';
+UPDATE campaigns SET settings = settings - 'allowed_sources'
+WHERE settings ? 'allowed_sources' AND jsonb_typeof(settings->'allowed_sources') <> 'array';
+UPDATE campaigns SET settings = settings - 'max_players'
+WHERE settings ? 'max_players' AND CASE
+    WHEN jsonb_typeof(settings->'max_players') = 'number' THEN (settings->>'max_players')::NUMERIC < 0 OR (settings->>'max_players')::NUMERIC % 1 <> 0
+    ELSE TRUE
+END;
+UPDATE campaigns SET settings = settings - 'webhook_url'
+WHERE settings ? 'webhook_url' AND (jsonb_typeof(settings->'webhook_url') <> 'string' OR settings->>'webhook_url' !~ '^https?://');
+-- Membership used to read the string "true" as discoverable too
+UPDATE campaigns SET settings = jsonb_set(settings, '{discoverable}', to_jsonb(COALESCE(settings->>'discoverable' = 'true', FALSE)))
+WHERE settings ? 'discoverable' AND jsonb_typeof(settings->'discoverable') <> 'boolean';
+
+UPDATE campaigns SET settings = '{"system": "dnd5e", "level_range": {"min": 1, "max": 20}, "allowed_sources": [], "houserules": {}, "discoverable": false}'::JSONB || settings;
+
+ALTER TABLE campaigns
+    ALTER COLUMN settings SET DEFAULT '{"system": "dnd5e", "level_range": {"min": 1, "max": 20}, "allowed_sources": [], "houserules": {}, "discoverable": false}',
+    ALTER COLUMN settings SET NOT NULL;
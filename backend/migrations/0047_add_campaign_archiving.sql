@@ -0,0 +1,3 @@
+-- Archived campaigns are finished or shelved: they drop out of the default campaign list
+-- and discovery, but nothing is deleted
+ALTER TABLE campaigns ADD COLUMN archived_at TIMESTAMPTZ;
@@ -0,0 +1,15 @@
+-- Full-text search over campaigns and their notes. Names and titles weigh more than
+-- descriptions and note bodies.
+ALTER TABLE campaigns ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', COALESCE(name, '')), 'A') ||
+    setweight(to_tsvector('english', COALESCE(description, '')), 'B')
+) STORED;
+
+CREATE INDEX idx_campaigns_search ON campaigns USING GIN (search_vector);
+
+ALTER TABLE campaign_notes ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', title), 'A') ||
+    setweight(to_tsvector('english', content), 'B')
+) STORED;
+
+CREATE INDEX idx_campaign_notes_search ON campaign_notes USING GIN (search_vector);
@@ -0,0 +1,3 @@
+-- A pending handover of the campaign to another member, waiting for them to accept
+ALTER TABLE campaigns ADD COLUMN transfer_to UUID REFERENCES users(id) ON DELETE SET NULL;
+ALTER TABLE campaigns ADD COLUMN transfer_offered_at TIMESTAMPTZ;
@@ -0,0 +1,3 @@
+-- Parts of the DM's job handed to individual members (see scope.rs)
+ALTER TABLE campaign_players ADD COLUMN permissions TEXT[] NOT NULL DEFAULT '{}'
+    CHECK (permissions <@ ARRAY['edit_npcs', 'award_xp', 'manage_initiative']);
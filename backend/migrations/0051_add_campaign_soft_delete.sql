@@ -0,0 +1,5 @@
+-- Deleted campaigns are hidden everywhere but kept for a grace period, so the DM can
+-- restore them, before the session janitor purges them
+ALTER TABLE campaigns ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE INDEX idx_campaigns_deleted_at ON campaigns (deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,28 @@
+-- The in-world calendar of a campaign: its months, in order, with their lengths, the names of
+-- the days of the week and the table's current date. Months are 1-based.
+CREATE TABLE campaign_calendars (
+    campaign_id UUID PRIMARY KEY REFERENCES campaigns(id) ON DELETE CASCADE,
+    months JSONB NOT NULL,
+    weekdays JSONB NOT NULL DEFAULT '[]',
+    year INTEGER NOT NULL,
+    month INTEGER NOT NULL CHECK (month >= 1),
+    day INTEGER NOT NULL CHECK (day >= 1),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Festivals, deadlines and the like on the campaign's calendar. Events without a year come
+-- round every year.
+CREATE TABLE calendar_events (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    campaign_id UUID NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    title VARCHAR(255) NOT NULL,
+    description TEXT,
+    kind TEXT NOT NULL DEFAULT 'other' CHECK (kind IN ('festival', 'deadline', 'other')),
+    year INTEGER,
+    month INTEGER NOT NULL CHECK (month >= 1),
+    day INTEGER NOT NULL CHECK (day >= 1),
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_calendar_events_campaign ON calendar_events(campaign_id, month, day);
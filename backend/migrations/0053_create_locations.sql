@@ -0,0 +1,17 @@
+-- Places in a campaign's world, nested by parent: a region holds cities, a city its taverns.
+-- Players see the summary; the description and notes are for the DM.
+CREATE TABLE locations (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    campaign_id UUID NOT NULL REFERENCES campaigns(id) ON DELETE CASCADE,
+    parent_id UUID REFERENCES locations(id) ON DELETE SET NULL,
+    name VARCHAR(255) NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    map_ref TEXT,
+    dm_notes TEXT NOT NULL DEFAULT '',
+    summary TEXT NOT NULL DEFAULT '',
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_locations_campaign ON locations(campaign_id, parent_id);
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use std::env;
+
+// Thin client for an OpenAI-compatible chat completions API.
+// Configured with OPENAI_API_KEY (required), OPENAI_MODEL and OPENAI_BASE_URL (optional).
+
+pub struct Completion {
+    pub text: String,
+    pub tokens_used: Option<i32>,
+    pub model: String,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<ChatMessage<'a>>,
+}
+
+#[derive(Serialize)]
+struct ChatMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    model: Option<String>,
+    choices: Vec<ChatChoice>,
+    usage: Option<ChatUsage>,
+}
+
+#[derive(Deserialize)]
+struct ChatChoice {
+    message: ChatChoiceMessage,
+}
+
+#[derive(Deserialize)]
+struct ChatChoiceMessage {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatUsage {
+    total_tokens: Option<i32>,
+}
+
+pub fn is_enabled() -> bool {
+    env::var("OPENAI_API_KEY").map(|key| !key.is_empty()).unwrap_or(false)
+}
+
+pub fn model_name() -> String {
+    env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-4".to_string())
+}
+
+pub async fn complete(system: &str, prompt: &str) -> Result<Completion, String> {
+    let api_key = env::var("OPENAI_API_KEY").map_err(|_| "AI provider is not configured".to_string())?;
+    let base_url = env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
+    let model = model_name();
+
+    let request = ChatRequest {
+        model: &model,
+        messages: vec![
+            ChatMessage { role: "system", content: system },
+            ChatMessage { role: "user", content: prompt },
+        ],
+    };
+
+    let res = reqwest::Client::new()
+        .post(format!("{}/chat/completions", base_url.trim_end_matches('/')))
+        .bearer_auth(api_key)
+        .json(&request)
+        .send()
+        .await
+        .map_err(|e| format!("AI request failed: {}", e))?;
+
+    if !res.status().is_success() {
+        return Err(format!("AI provider returned {}", res.status()));
+    }
+
+    let body: ChatResponse = res
+        .json()
+        .await
+        .map_err(|e| format!("Invalid AI response: {}", e))?;
+
+    let text = body
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.message.content)
+        .ok_or_else(|| "AI response contained no content".to_string())?;
+
+    Ok(Completion {
+        text,
+        tokens_used: body.usage.and_then(|usage| usage.total_tokens),
+        model: body.model.unwrap_or(model),
+    })
+}
@@ -52,6 +52,18 @@ pub async fn api_docs() -> impl IntoResponse {
             </div>
         </div>
 
+        <div class="endpoint">
+            <span class="method get">GET</span>
+            <span class="url">/metrics</span>
+            <div class="description">Realtime load (connections, message rate, broadcast latency, per-session connections, evictions) in the Prometheus text format</div>
+        </div>
+
+        <div class="endpoint">
+            <span class="method get">GET</span>
+            <span class="url">/admin/usage?from=2024-01-01&amp;to=2024-01-31</span>
+            <div class="description">Per-campaign and per-user API calls, WebSocket messages, AI tokens and storage over a period (ADMIN_USER_IDS only)</div>
+        </div>
+
         <h2>🔑 Authentication Endpoints</h2>
         
         <div class="endpoint">
@@ -197,6 +209,24 @@ pub async fn api_docs() -> impl IntoResponse {
             <div class="description">End a session (DM only)</div>
         </div>
 
+        <div class="endpoint">
+            <span class="method post">POST</span>
+            <span class="url">/sessions/:id/pause</span>
+            <div class="description">Pause an active session, freezing turn timers (DM only)</div>
+        </div>
+
+        <div class="endpoint">
+            <span class="method post">POST</span>
+            <span class="url">/sessions/:id/resume</span>
+            <div class="description">Resume a paused session (DM only)</div>
+        </div>
+
+        <div class="endpoint">
+            <span class="method post">POST</span>
+            <span class="url">/sessions/:id/continue</span>
+            <div class="description">End a session and create its continuation with the same game state (DM only)</div>
+        </div>
+
         <h2>👤 Character Management</h2>
         
         <div class="endpoint">
@@ -274,13 +304,29 @@ pub async fn api_docs() -> impl IntoResponse {
       "is_player": false,
       "hp_current": 7,
       "hp_max": 7,
-      "ac": 15
+      "ac": 15,
+      "surprised": true
     }
   ]
-}</code>
+}</code><br><br>
+                <strong>Surprise:</strong> entries with <code>"surprised": true</code> are skipped during round 1 and the flag is cleared automatically when round 2 begins.
             </div>
         </div>
 
+        <h2>📁 Files</h2>
+
+        <div class="endpoint">
+            <span class="method post">POST</span>
+            <span class="url">/files</span>
+            <div class="description">Upload a file (raw body, up to 10 MB). Identical content is stored once; the response has the content key and a signed download URL valid for an hour</div>
+        </div>
+
+        <div class="endpoint">
+            <span class="method get">GET</span>
+            <span class="url">/files/:key?expires=...&amp;signature=...</span>
+            <div class="description">Download a file through a signed URL (local and in-memory storage; S3 URLs point at the bucket)</div>
+        </div>
+
         <h2>🔌 WebSocket Real-time Communication</h2>
         
         <div class="websocket">
@@ -298,13 +344,18 @@ pub async fn api_docs() -> impl IntoResponse {
             <ul>
                 <li><strong>JoinSession:</strong> Join a game session</li>
                 <li><strong>LeaveSession:</strong> Leave a game session</li>
-                <li><strong>DiceRoll:</strong> Roll dice with optional reason</li>
+                <li><strong>DiceRoll:</strong> Roll dice with optional reason; <code>dm_only</code> hides the roll from other players</li>
+                <li><strong>GroupRoll:</strong> Roll the same dice for several initiative entries at once (DM only)</li>
                 <li><strong>ChatMessage:</strong> Send chat message</li>
                 <li><strong>UpdateGameState:</strong> Update game state (DM only)</li>
                 <li><strong>UpdateCharacter:</strong> Update character details</li>
                 <li><strong>UpdateInitiative:</strong> Update initiative order (DM only)</li>
                 <li><strong>NextTurn:</strong> Advance to next turn (DM only)</li>
+                <li><strong>SetTurnTimer:</strong> Set or clear the per-turn time limit (DM only)</li>
                 <li><strong>UpdateHP:</strong> Update character HP</li>
+                <li><strong>PlayerAction:</strong> Declare a character's action (attack, cast, move, use item, custom)</li>
+                <li><strong>KickPlayer / UnbanPlayer:</strong> Remove a player from the session, optionally banning them (DM only)</li>
+                <li><strong>MutePlayer / UnmutePlayer:</strong> Drop a player's chat messages for a while (DM only)</li>
             </ul>
 
             <h4>Server Messages</h4>
@@ -312,12 +363,18 @@ pub async fn api_docs() -> impl IntoResponse {
                 <li><strong>SessionJoined:</strong> Confirmation of session join</li>
                 <li><strong>PlayerJoined:</strong> New player joined session</li>
                 <li><strong>PlayerLeft:</strong> Player left session</li>
+                <li><strong>Kicked:</strong> You were removed from the session by the DM</li>
                 <li><strong>DiceRolled:</strong> Dice roll result</li>
+                <li><strong>GroupRolled:</strong> All results of a group roll in one message</li>
+                <li><strong>ActionDeclared:</strong> A character declared an action</li>
                 <li><strong>ChatMessage:</strong> Chat message from player</li>
                 <li><strong>GameStateUpdated:</strong> Game state changed</li>
                 <li><strong>CharacterUpdated:</strong> Character details updated</li>
                 <li><strong>InitiativeUpdated:</strong> Initiative order updated</li>
                 <li><strong>TurnChanged:</strong> Turn advanced</li>
+                <li><strong>TurnTimerStarted / TurnTimerWarning / TurnTimerExpired:</strong> Turn countdown</li>
+                <li><strong>SessionPaused / SessionResumed:</strong> The DM paused or resumed the session</li>
+                <li><strong>CampaignOwnerChanged:</strong> The campaign was handed over to another DM</li>
                 <li><strong>HPUpdated:</strong> Character HP updated</li>
                 <li><strong>Error:</strong> Error message</li>
             </ul>
@@ -0,0 +1,170 @@
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts, Query},
+    http::{header, request::Parts, Extensions, HeaderMap},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::middleware::AuthUser;
+
+// Security audit log. Sign-ins, failed sign-ins, password changes, token refreshes and
+// refused requests are recorded in auth_audit with the client's address and user agent, and
+// users can review their own recent activity at GET /users/me/activity. Entries are kept for
+// RETENTION_DAYS. Recording is best effort: a failed write is logged and the request goes on.
+
+const RETENTION_DAYS: i64 = 90;
+
+const DEFAULT_ACTIVITY_LIMIT: i64 = 50;
+const MAX_ACTIVITY_LIMIT: i64 = 200;
+
+// User agents are cut to this many characters
+const MAX_USER_AGENT_CHARS: usize = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthEvent {
+    Login,
+    LoginFailed,
+    PasswordChanged,
+    TokenRefreshed,
+    // An already-used refresh token came back, and its family was revoked
+    RefreshTokenReused,
+    PermissionDenied,
+    // An admin was issued a token acting as the user
+    Impersonation,
+}
+
+impl AuthEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthEvent::Login => "login",
+            AuthEvent::LoginFailed => "login_failed",
+            AuthEvent::PasswordChanged => "password_changed",
+            AuthEvent::TokenRefreshed => "token_refreshed",
+            AuthEvent::RefreshTokenReused => "refresh_token_reused",
+            AuthEvent::PermissionDenied => "permission_denied",
+            AuthEvent::Impersonation => "impersonation",
+        }
+    }
+}
+
+// Where a request came from, as far as the server can tell
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn new(extensions: &Extensions, headers: &HeaderMap) -> Self {
+        ClientInfo {
+            address: extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(address)| address.ip()),
+            user_agent: headers
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.chars().take(MAX_USER_AGENT_CHARS).collect()),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ClientInfo::new(&parts.extensions, &parts.headers))
+    }
+}
+
+pub async fn record(pool: &PgPool, user_id: Option<Uuid>, event: AuthEvent, client: &ClientInfo, detail: serde_json::Value) {
+    let recorded = sqlx::query(
+        "INSERT INTO auth_audit (id, user_id, event, ip, user_agent, detail, created_at) VALUES ($1, $2, $3, $4, $5, $6, NOW())"
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(event.as_str())
+    .bind(client.address.map(|address| address.to_string()))
+    .bind(&client.user_agent)
+    .bind(detail)
+    .execute(pool)
+    .await;
+    if let Err(e) = recorded {
+        tracing::error!(error = %e, event = event.as_str(), "Failed to record audit log entry");
+    }
+}
+
+// Run by the maintenance task
+pub async fn prune(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM auth_audit WHERE created_at < NOW() - make_interval(days => $1)")
+        .bind(RETENTION_DAYS as i32)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    pub event: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub detail: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct ActivityQuery {
+    pub limit: Option<i64>,
+}
+
+// Newest first
+pub async fn list_my_activity(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(params): Query<ActivityQuery>,
+) -> impl IntoResponse {
+    let limit = params.limit.unwrap_or(DEFAULT_ACTIVITY_LIMIT).clamp(1, MAX_ACTIVITY_LIMIT);
+    let entries = sqlx::query_as::<_, AuditEntry>(
+        "SELECT id, event, ip, user_agent, detail, created_at FROM auth_audit WHERE user_id = $1 ORDER BY created_at DESC LIMIT $2"
+    )
+    .bind(user.0)
+    .bind(limit)
+    .fetch_all(&pool)
+    .await;
+    match entries {
+        Ok(entries) => Json(entries).into_response(),
+        Err(e) => db_error(e, "Failed to fetch activity"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_users_see_their_own_activity() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let client = ClientInfo { address: Some(IpAddr::from([203, 0, 113, 5])), user_agent: Some("Firefox".to_string()) };
+        record(&pool, Some(f.player_id), AuthEvent::LoginFailed, &client, serde_json::json!({})).await;
+        record(&pool, Some(f.player_id), AuthEvent::Login, &client, serde_json::json!({})).await;
+        record(&pool, Some(f.dm_id), AuthEvent::PasswordChanged, &client, serde_json::json!({})).await;
+
+        let response = list_my_activity(Extension(pool.clone()), Extension(AuthUser(f.player_id)), Query(ActivityQuery { limit: None }))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let entries: Vec<AuditEntry> = serde_json::from_slice(&body).unwrap();
+        let events: Vec<&str> = entries.iter().map(|entry| entry.event.as_str()).collect();
+        assert_eq!(events, ["login", "login_failed"]);
+        assert_eq!(entries[0].ip.as_deref(), Some("203.0.113.5"));
+    }
+}
@@ -0,0 +1,207 @@
+use axum::{
+    extract::{Multipart, Path, Query},
+    http::{header, StatusCode},
+    response::{IntoResponse, Redirect},
+    Extension, Json,
+};
+use image::{imageops::FilterType, ImageFormat};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::env;
+use std::io::Cursor;
+use std::time::Duration;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::middleware::AuthUser;
+use crate::storage::{store, SharedStorage};
+
+// Uploaded avatars. POST /users/me/avatar takes a multipart form with the image in an
+// "avatar" field. The image is decoded, cropped square and re-encoded as PNG at two sizes,
+// which also drops any metadata the original carried. The user's avatar_url then points at
+// GET /users/:id/avatar, which redirects to a short-lived signed URL for the stored file, so
+// the link itself never expires. ?v= changes with every upload so caches pick up new faces.
+
+// Largest accepted upload
+pub const MAX_AVATAR_BYTES: usize = 2 * 1024 * 1024;
+// Bigger images are refused before decoding
+const MAX_DIMENSION: u32 = 4096;
+
+const FULL_SIZE: u32 = 256;
+// Small enough to sit next to chat messages
+const THUMB_SIZE: u32 = 64;
+
+const ACCEPTED_FORMATS: [ImageFormat; 4] = [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP, ImageFormat::Gif];
+
+const AVATAR_URL_TTL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug)]
+struct ProcessedAvatar {
+    full: Vec<u8>,
+    thumb: Vec<u8>,
+}
+
+// The format is read from the bytes rather than trusted from the upload's content type
+fn process_avatar(bytes: &[u8]) -> Result<ProcessedAvatar, &'static str> {
+    let format = image::guess_format(bytes).map_err(|_| "Avatar must be a PNG, JPEG, WebP or GIF image")?;
+    if !ACCEPTED_FORMATS.contains(&format) {
+        return Err("Avatar must be a PNG, JPEG, WebP or GIF image");
+    }
+    let (width, height) = image::io::Reader::with_format(Cursor::new(bytes), format)
+        .into_dimensions()
+        .map_err(|_| "Avatar image is corrupt")?;
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err("Avatar must be at most 4096x4096 pixels");
+    }
+    let image = image::load_from_memory_with_format(bytes, format).map_err(|_| "Avatar image is corrupt")?;
+
+    let encode = |size: u32| {
+        let mut png = Cursor::new(Vec::new());
+        image
+            .resize_to_fill(size, size, FilterType::Lanczos3)
+            .write_to(&mut png, ImageFormat::Png)
+            .map(|()| png.into_inner())
+            .map_err(|_| "Failed to resize avatar")
+    };
+    Ok(ProcessedAvatar { full: encode(FULL_SIZE)?, thumb: encode(THUMB_SIZE)? })
+}
+
+fn avatar_url(user_id: Uuid, version: &str) -> String {
+    // Absolute when STORAGE_PUBLIC_URL is set, like signed file URLs
+    let base = env::var("STORAGE_PUBLIC_URL").unwrap_or_default();
+    format!("{}/users/{}/avatar?v={}", base.trim_end_matches('/'), user_id, version)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AvatarResponse {
+    pub avatar_url: String,
+    pub thumbnail_url: String,
+}
+
+pub async fn upload_avatar(
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Extension(user): Extension<AuthUser>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let mut upload = None;
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.name() == Some("avatar") => match field.bytes().await {
+                Ok(bytes) => {
+                    upload = Some(bytes);
+                    break;
+                }
+                Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid upload: {}", e)).into_response(),
+            },
+            Ok(Some(_)) => continue,
+            Ok(None) => break,
+            Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid upload: {}", e)).into_response(),
+        }
+    }
+    let upload = match upload {
+        Some(upload) if !upload.is_empty() => upload,
+        _ => return (StatusCode::BAD_REQUEST, "Missing avatar field").into_response(),
+    };
+    if upload.len() > MAX_AVATAR_BYTES {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "Avatar must be at most 2 MiB").into_response();
+    }
+
+    // Decoding and resizing is CPU-bound
+    let processed = match tokio::task::spawn_blocking(move || process_avatar(&upload)).await {
+        Ok(Ok(processed)) => processed,
+        Ok(Err(message)) => return (StatusCode::UNPROCESSABLE_ENTITY, message).into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, "Avatar processing panicked");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to process avatar").into_response();
+        }
+    };
+    let stored = async {
+        let full = store(storage.as_ref(), processed.full, "image/png").await?;
+        let thumb = store(storage.as_ref(), processed.thumb, "image/png").await?;
+        Ok::<_, String>((full, thumb))
+    }
+    .await;
+    let (full, thumb) = match stored {
+        Ok(stored) => stored,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to store avatar");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store avatar").into_response();
+        }
+    };
+
+    let url = avatar_url(user.0, &full.sha256[..12]);
+    let updated = sqlx::query("UPDATE users SET avatar_url = $2, avatar_key = $3, avatar_thumb_key = $4, updated_at = NOW() WHERE id = $1")
+        .bind(user.0)
+        .bind(&url)
+        .bind(&full.key)
+        .bind(&thumb.key)
+        .execute(&pool)
+        .await;
+    match updated {
+        Ok(result) if result.rows_affected() == 0 => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Ok(_) => {
+            let thumbnail_url = format!("{}&size=thumb", url);
+            (StatusCode::OK, Json(AvatarResponse { avatar_url: url, thumbnail_url })).into_response()
+        }
+        Err(e) => db_error(e, "Failed to save avatar"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AvatarQuery {
+    // "thumb" for the 64px thumbnail; anything else is the 256px image
+    pub size: Option<String>,
+}
+
+// Public, so avatars can be used straight in <img> tags
+pub async fn get_avatar(
+    Extension(pool): Extension<PgPool>,
+    Extension(storage): Extension<SharedStorage>,
+    Path(user_id): Path<Uuid>,
+    Query(params): Query<AvatarQuery>,
+) -> impl IntoResponse {
+    let keys = sqlx::query_as::<_, (Option<String>, Option<String>)>("SELECT avatar_key, avatar_thumb_key FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&pool)
+        .await;
+    let key = match keys {
+        Ok(Some((full, thumb))) => if params.size.as_deref() == Some("thumb") { thumb } else { full },
+        Ok(None) => None,
+        Err(e) => return db_error(e, "Failed to fetch avatar"),
+    };
+    let Some(key) = key else {
+        return (StatusCode::NOT_FOUND, "No avatar uploaded").into_response();
+    };
+    match storage.signed_url(&key, AVATAR_URL_TTL) {
+        // Browsers may reuse the redirect for a little while, well inside the URL's lifetime
+        Ok(url) => ([(header::CACHE_CONTROL, "private, max-age=600")], Redirect::temporary(&url)).into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, %key, "Failed to sign avatar URL");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to sign avatar URL").into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, RgbImage};
+
+    fn encoded(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height)).write_to(&mut bytes, format).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn test_avatars_are_cropped_square_at_two_sizes() {
+        let processed = process_avatar(&encoded(300, 200, ImageFormat::Jpeg)).unwrap();
+        let full = image::load_from_memory_with_format(&processed.full, ImageFormat::Png).unwrap();
+        let thumb = image::load_from_memory_with_format(&processed.thumb, ImageFormat::Png).unwrap();
+        assert_eq!((full.width(), full.height()), (FULL_SIZE, FULL_SIZE));
+        assert_eq!((thumb.width(), thumb.height()), (THUMB_SIZE, THUMB_SIZE));
+
+        assert!(process_avatar(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>").is_err());
+        assert!(process_avatar(&encoded(MAX_DIMENSION + 1, 1, ImageFormat::Png)).is_err());
+    }
+}
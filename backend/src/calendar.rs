@@ -0,0 +1,548 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::events::{deliver, record, DomainEvent};
+use crate::middleware::AuthUser;
+use crate::socket::SessionState;
+
+// The campaign's in-world calendar. The DM sets up the months and their lengths (and, if the
+// setting has them, named days of the week) and keeps the current date, moving it forward as
+// time passes in the game. Festivals, deadlines and other events sit on the calendar, yearly
+// when they have no year. Every date change goes into the latest session's event log as a
+// `date_changed` entry, with the events the table lived through on the way.
+
+const MAX_MONTHS: usize = 24;
+const MAX_MONTH_DAYS: i32 = 100;
+const MAX_WEEKDAYS: usize = 14;
+const MAX_NAME_CHARS: usize = 64;
+const MAX_TITLE_CHARS: usize = 255;
+const MAX_ADVANCE_DAYS: i32 = 3650;
+// Years run from -MAX_YEAR to MAX_YEAR, well clear of integer overflow
+const MAX_YEAR: i32 = 1_000_000;
+// Events listed as upcoming with the calendar, looking at most a year ahead
+const UPCOMING_EVENTS: usize = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalendarMonth {
+    pub name: String,
+    pub days: i32,
+}
+
+// Months are 1-based; ordering is chronological
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct InWorldDate {
+    pub year: i32,
+    pub month: i32,
+    pub day: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Calendar {
+    pub months: Vec<CalendarMonth>,
+    // Names of the days of the week, if the setting has them
+    #[serde(default)]
+    pub weekdays: Vec<String>,
+    // The current in-world date
+    pub date: InWorldDate,
+}
+
+impl Calendar {
+    fn validate(&self) -> Result<(), String> {
+        if self.months.is_empty() || self.months.len() > MAX_MONTHS {
+            return Err(format!("A calendar has 1 to {} months", MAX_MONTHS));
+        }
+        if self.weekdays.len() > MAX_WEEKDAYS {
+            return Err(format!("A week has at most {} days", MAX_WEEKDAYS));
+        }
+        let mut names = self.months.iter().map(|month| &month.name).chain(&self.weekdays);
+        if names.any(|name| name.trim().is_empty() || name.chars().count() > MAX_NAME_CHARS) {
+            return Err(format!("Month and weekday names must be 1 to {} characters", MAX_NAME_CHARS));
+        }
+        if self.months.iter().any(|month| month.days < 1 || month.days > MAX_MONTH_DAYS) {
+            return Err(format!("Months have 1 to {} days", MAX_MONTH_DAYS));
+        }
+        if !self.has_day(self.date.month, self.date.day) {
+            return Err("The current date isn't on the calendar".to_string());
+        }
+        if !(-MAX_YEAR..=MAX_YEAR).contains(&self.date.year) {
+            return Err(format!("Years run from -{} to {}", MAX_YEAR, MAX_YEAR));
+        }
+        Ok(())
+    }
+
+    fn has_day(&self, month: i32, day: i32) -> bool {
+        month >= 1 && self.months.get(month as usize - 1).is_some_and(|m| day >= 1 && day <= m.days)
+    }
+
+    fn next_day(&self, date: InWorldDate) -> InWorldDate {
+        let days = self.months[date.month as usize - 1].days;
+        if date.day < days {
+            InWorldDate { day: date.day + 1, ..date }
+        } else if (date.month as usize) < self.months.len() {
+            InWorldDate { month: date.month + 1, day: 1, ..date }
+        } else {
+            InWorldDate { year: date.year.saturating_add(1), month: 1, day: 1 }
+        }
+    }
+
+    fn days_in_year(&self) -> i64 {
+        self.months.iter().map(|month| month.days as i64).sum()
+    }
+
+    // Counted from day 1 of month 1 of year 0, which is the first day of the week
+    fn weekday(&self, date: InWorldDate) -> Option<String> {
+        if self.weekdays.is_empty() {
+            return None;
+        }
+        let before: i64 = self.months[..date.month as usize - 1].iter().map(|month| month.days as i64).sum();
+        let days = date.year as i64 * self.days_in_year() + before + date.day as i64 - 1;
+        Some(self.weekdays[days.rem_euclid(self.weekdays.len() as i64) as usize].clone())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct CalendarRow {
+    months: sqlx::types::Json<Vec<CalendarMonth>>,
+    weekdays: sqlx::types::Json<Vec<String>>,
+    year: i32,
+    month: i32,
+    day: i32,
+}
+
+impl From<CalendarRow> for Calendar {
+    fn from(row: CalendarRow) -> Self {
+        Calendar {
+            months: row.months.0,
+            weekdays: row.weekdays.0,
+            date: InWorldDate { year: row.year, month: row.month, day: row.day },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Festival,
+    Deadline,
+    Other,
+}
+
+impl EventKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Festival => "festival",
+            EventKind::Deadline => "deadline",
+            EventKind::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CalendarEvent {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub title: String,
+    pub description: Option<String>,
+    // "festival", "deadline" or "other"
+    pub kind: String,
+    // None for events that come round every year
+    pub year: Option<i32>,
+    pub month: i32,
+    pub day: i32,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl CalendarEvent {
+    fn falls_on(&self, date: InWorldDate) -> bool {
+        self.month == date.month && self.day == date.day && self.year.unwrap_or(date.year) == date.year
+    }
+}
+
+// One event on one date; yearly events occur once a year
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Occurrence {
+    pub event_id: Uuid,
+    pub title: String,
+    pub kind: String,
+    pub date: InWorldDate,
+}
+
+#[derive(Serialize)]
+pub struct CalendarResponse {
+    #[serde(flatten)]
+    pub calendar: Calendar,
+    // The current date's day of the week, if the calendar has weeks
+    pub weekday: Option<String>,
+    // From today on
+    pub upcoming: Vec<Occurrence>,
+}
+
+#[derive(Serialize)]
+pub struct DateChange {
+    pub from: InWorldDate,
+    pub to: InWorldDate,
+    pub weekday: Option<String>,
+    // Events between the two dates, the new date included
+    pub events: Vec<Occurrence>,
+}
+
+// Walks `days` days on from `from`, collecting the events passed on the way
+fn walk(calendar: &Calendar, events: &[CalendarEvent], from: InWorldDate, days: i64, include_start: bool, limit: usize) -> (InWorldDate, Vec<Occurrence>) {
+    let mut date = from;
+    let mut passed = Vec::new();
+    let on = |date: InWorldDate, passed: &mut Vec<Occurrence>| {
+        for event in events.iter().filter(|event| event.falls_on(date)) {
+            if passed.len() < limit {
+                passed.push(Occurrence { event_id: event.id, title: event.title.clone(), kind: event.kind.clone(), date });
+            }
+        }
+    };
+    if include_start {
+        on(date, &mut passed);
+    }
+    for _ in 0..days {
+        date = calendar.next_day(date);
+        on(date, &mut passed);
+    }
+    (date, passed)
+}
+
+async fn load_events(conn: &mut PgConnection, campaign_id: Uuid) -> Result<Vec<CalendarEvent>, sqlx::Error> {
+    sqlx::query_as::<_, CalendarEvent>("SELECT * FROM calendar_events WHERE campaign_id = $1 ORDER BY created_at")
+        .bind(campaign_id)
+        .fetch_all(conn)
+        .await
+}
+
+async fn describe(conn: &mut PgConnection, campaign_id: Uuid, calendar: Calendar) -> Result<CalendarResponse, sqlx::Error> {
+    let events = load_events(conn, campaign_id).await?;
+    let (_, upcoming) = walk(&calendar, &events, calendar.date, calendar.days_in_year() - 1, true, UPCOMING_EVENTS);
+    Ok(CalendarResponse { weekday: calendar.weekday(calendar.date), calendar, upcoming })
+}
+
+// Logged to the campaign's latest session, if it has one, so the table sees the new date
+// in the event log and over the WebSocket. Deliver the returned event once committed.
+async fn log_date_change(
+    conn: &mut PgConnection,
+    campaign_id: Uuid,
+    user_id: Uuid,
+    from: InWorldDate,
+    to: InWorldDate,
+    passed: &[Occurrence],
+) -> Result<Option<DomainEvent>, sqlx::Error> {
+    let session_id = sqlx::query_scalar::<_, Uuid>("SELECT id FROM sessions WHERE campaign_id = $1 ORDER BY created_at DESC LIMIT 1")
+        .bind(campaign_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    let Some(session_id) = session_id else {
+        return Ok(None);
+    };
+    let event = DomainEvent::EventLogged {
+        event_id: Uuid::new_v4(),
+        session_id,
+        created_by: user_id,
+        event_type: "date_changed".to_string(),
+        event_data: serde_json::json!({ "campaign_id": campaign_id, "from": from, "to": to, "events": passed }),
+        at: Utc::now(),
+    };
+    record(conn, &event).await?;
+    Ok(Some(event))
+}
+
+const NO_CALENDAR: &str = "This campaign has no calendar yet";
+
+pub async fn get_calendar(
+    Extension(pool): Extension<PgPool>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let described = async {
+        let mut conn = pool.acquire().await?;
+        let row = sqlx::query_as::<_, CalendarRow>("SELECT * FROM campaign_calendars WHERE campaign_id = $1")
+            .bind(campaign_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        match row {
+            Some(row) => describe(&mut conn, campaign_id, Calendar::from(row)).await.map(Some),
+            None => Ok(None),
+        }
+    }
+    .await;
+    match described {
+        Ok(Some(response)) => Json(response).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, NO_CALENDAR).into_response(),
+        Err(e) => db_error(e, "Failed to fetch calendar"),
+    }
+}
+
+// Sets up the calendar or replaces it. Events on days the new months don't have stay but
+// never come up.
+pub async fn set_calendar(
+    Extension(pool): Extension<PgPool>,
+    Extension(session_state): Extension<SessionState>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Json(calendar): Json<Calendar>,
+) -> impl IntoResponse {
+    if let Err(message) = calendar.validate() {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    let saved = async {
+        let mut tx = pool.begin().await?;
+        let previous = sqlx::query_as::<_, (i32, i32, i32)>("SELECT year, month, day FROM campaign_calendars WHERE campaign_id = $1 FOR UPDATE")
+            .bind(campaign_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO campaign_calendars (campaign_id, months, weekdays, year, month, day) VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (campaign_id) DO UPDATE SET months = $2, weekdays = $3, year = $4, month = $5, day = $6, updated_at = NOW()"
+        )
+        .bind(campaign_id)
+        .bind(sqlx::types::Json(&calendar.months))
+        .bind(sqlx::types::Json(&calendar.weekdays))
+        .bind(calendar.date.year)
+        .bind(calendar.date.month)
+        .bind(calendar.date.day)
+        .execute(&mut *tx)
+        .await?;
+        let mut logged = None;
+        if let Some((year, month, day)) = previous {
+            let from = InWorldDate { year, month, day };
+            if from != calendar.date {
+                logged = log_date_change(&mut tx, campaign_id, user.0, from, calendar.date, &[]).await?;
+            }
+        }
+        let response = describe(&mut tx, campaign_id, calendar).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>((response, logged))
+    }
+    .await;
+
+    match saved {
+        Ok((response, logged)) => {
+            if let Some(event) = logged {
+                deliver(&session_state, &event).await;
+            }
+            Json(response).into_response()
+        }
+        Err(e) => db_error(e, "Failed to save calendar"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AdvanceRequest {
+    pub days: i32,
+}
+
+// Moves the current date forward
+pub async fn advance_calendar(
+    Extension(pool): Extension<PgPool>,
+    Extension(session_state): Extension<SessionState>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Json(payload): Json<AdvanceRequest>,
+) -> impl IntoResponse {
+    if payload.days < 1 || payload.days > MAX_ADVANCE_DAYS {
+        return (StatusCode::BAD_REQUEST, format!("Time moves forward 1 to {} days at once", MAX_ADVANCE_DAYS)).into_response();
+    }
+
+    let advanced = async {
+        let mut tx = pool.begin().await?;
+        let row = sqlx::query_as::<_, CalendarRow>("SELECT * FROM campaign_calendars WHERE campaign_id = $1 FOR UPDATE")
+            .bind(campaign_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(calendar) = row.map(Calendar::from) else {
+            return Ok(Err((StatusCode::NOT_FOUND, NO_CALENDAR.to_string())));
+        };
+        let events = load_events(&mut tx, campaign_id).await?;
+        let from = calendar.date;
+        let (to, passed) = walk(&calendar, &events, from, payload.days as i64, false, usize::MAX);
+        if to.year > MAX_YEAR {
+            return Ok(Err((StatusCode::BAD_REQUEST, format!("Years run up to {}", MAX_YEAR))));
+        }
+        sqlx::query("UPDATE campaign_calendars SET year = $2, month = $3, day = $4, updated_at = NOW() WHERE campaign_id = $1")
+            .bind(campaign_id)
+            .bind(to.year)
+            .bind(to.month)
+            .bind(to.day)
+            .execute(&mut *tx)
+            .await?;
+        let logged = log_date_change(&mut tx, campaign_id, user.0, from, to, &passed).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Ok((DateChange { from, to, weekday: calendar.weekday(to), events: passed }, logged)))
+    }
+    .await;
+
+    match advanced {
+        Ok(Ok((change, logged))) => {
+            if let Some(event) = logged {
+                deliver(&session_state, &event).await;
+            }
+            Json(change).into_response()
+        }
+        Ok(Err(refused)) => refused.into_response(),
+        Err(e) => db_error(e, "Failed to advance time"),
+    }
+}
+
+// In calendar order, yearly events first on each day
+pub async fn list_calendar_events(
+    Extension(pool): Extension<PgPool>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let events = sqlx::query_as::<_, CalendarEvent>(
+        "SELECT * FROM calendar_events WHERE campaign_id = $1 ORDER BY month, day, year NULLS FIRST, created_at"
+    )
+    .bind(campaign_id)
+    .fetch_all(&pool)
+    .await;
+    match events {
+        Ok(events) => Json(events).into_response(),
+        Err(e) => db_error(e, "Failed to fetch calendar events"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateCalendarEventRequest {
+    pub title: String,
+    pub description: Option<String>,
+    pub kind: Option<EventKind>,
+    // Every year when left out
+    pub year: Option<i32>,
+    pub month: i32,
+    pub day: i32,
+}
+
+pub async fn create_calendar_event(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Json(payload): Json<CreateCalendarEventRequest>,
+) -> impl IntoResponse {
+    let title = payload.title.trim();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_CHARS {
+        return (StatusCode::BAD_REQUEST, format!("Title must be 1 to {} characters", MAX_TITLE_CHARS)).into_response();
+    }
+
+    let row = sqlx::query_as::<_, CalendarRow>("SELECT * FROM campaign_calendars WHERE campaign_id = $1")
+        .bind(campaign_id)
+        .fetch_optional(&pool)
+        .await;
+    let calendar = match row {
+        Ok(Some(row)) => Calendar::from(row),
+        Ok(None) => return (StatusCode::CONFLICT, "Set up the calendar first").into_response(),
+        Err(e) => return db_error(e, "Failed to fetch calendar"),
+    };
+    if !calendar.has_day(payload.month, payload.day) || payload.year.is_some_and(|year| !(-MAX_YEAR..=MAX_YEAR).contains(&year)) {
+        return (StatusCode::BAD_REQUEST, "That day isn't on the calendar").into_response();
+    }
+
+    let event = sqlx::query_as::<_, CalendarEvent>(
+        "INSERT INTO calendar_events (campaign_id, title, description, kind, year, month, day, created_by)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *"
+    )
+    .bind(campaign_id)
+    .bind(title)
+    .bind(&payload.description)
+    .bind(payload.kind.unwrap_or(EventKind::Other).as_str())
+    .bind(payload.year)
+    .bind(payload.month)
+    .bind(payload.day)
+    .bind(user.0)
+    .fetch_one(&pool)
+    .await;
+    match event {
+        Ok(event) => (StatusCode::CREATED, Json(event)).into_response(),
+        Err(e) => db_error(e, "Failed to create calendar event"),
+    }
+}
+
+pub async fn delete_calendar_event(
+    Extension(pool): Extension<PgPool>,
+    Path((campaign_id, event_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let res = sqlx::query("DELETE FROM calendar_events WHERE id = $1 AND campaign_id = $2")
+        .bind(event_id)
+        .bind(campaign_id)
+        .execute(&pool)
+        .await;
+    match res {
+        Ok(done) if done.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Calendar event not found").into_response(),
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => db_error(e, "Failed to delete calendar event"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_years_stay_in_range() {
+        let calendar = |year: i32| Calendar {
+            months: vec![CalendarMonth { name: "Hammer".to_string(), days: 1 }],
+            weekdays: Vec::new(),
+            date: InWorldDate { year, month: 1, day: 1 },
+        };
+        assert!(calendar(MAX_YEAR).validate().is_ok());
+        assert!(calendar(i32::MAX).validate().is_err());
+        assert!(calendar(i32::MIN).validate().is_err());
+        // Even past the range, moving on doesn't overflow
+        assert_eq!(calendar(i32::MAX).next_day(InWorldDate { year: i32::MAX, month: 1, day: 1 }).year, i32::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_advancing_time_passes_events_and_logs_the_new_date() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let session_state = SessionState::new(None);
+
+        let calendar: Calendar = serde_json::from_value(serde_json::json!({
+            "months": [{"name": "Hammer", "days": 3}, {"name": "Alturiak", "days": 3}],
+            "weekdays": ["First", "Second"],
+            "date": {"year": 1492, "month": 1, "day": 1}
+        }))
+        .unwrap();
+        let set = |user_id: Uuid| set_calendar(Extension(pool.clone()), Extension(session_state.clone()), Extension(AuthUser(user_id)), Path(f.campaign_id), Json(calendar.clone()));
+        let refused = crate::test_support::call_as(&pool, f.player_id, axum::http::Method::PUT, &format!("/campaigns/{}/calendar", f.campaign_id), Some(serde_json::to_value(&calendar).unwrap())).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        assert_eq!(set(f.dm_id).await.into_response().status(), StatusCode::OK);
+
+        let add = |title: &str, year: Option<i32>, month: i32, day: i32| {
+            let payload = CreateCalendarEventRequest { title: title.to_string(), description: None, kind: None, year, month, day };
+            create_calendar_event(Extension(pool.clone()), Extension(AuthUser(f.dm_id)), Path(f.campaign_id), Json(payload))
+        };
+        assert_eq!(add("Midwinter", None, 1, 2).await.into_response().status(), StatusCode::CREATED);
+        assert_eq!(add("Ransom due", Some(1492), 2, 1).await.into_response().status(), StatusCode::CREATED);
+        assert_eq!(add("Nowhere", None, 2, 4).await.into_response().status(), StatusCode::BAD_REQUEST);
+
+        let advance = |days: i32| advance_calendar(Extension(pool.clone()), Extension(session_state.clone()), Extension(AuthUser(f.dm_id)), Path(f.campaign_id), Json(AdvanceRequest { days }));
+        let response = advance(4).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let change: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(change["to"], serde_json::json!({"year": 1492, "month": 2, "day": 2}));
+        let passed: Vec<&str> = change["events"].as_array().unwrap().iter().map(|event| event["title"].as_str().unwrap()).collect();
+        assert_eq!(passed, vec!["Midwinter", "Ransom due"]);
+
+        // Into the next year: Midwinter comes round again, the ransom doesn't
+        let response = advance(3).await.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let change: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(change["to"], serde_json::json!({"year": 1493, "month": 1, "day": 2}));
+        assert_eq!(change["events"][0]["date"]["year"], 1493);
+        assert_eq!(change["events"].as_array().unwrap().len(), 1);
+
+        let logged = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM event_logs WHERE session_id = $1 AND event_type = 'date_changed'")
+            .bind(f.session_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(logged, 2);
+    }
+}
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+
+// The shape of `campaigns.settings`. Clients send the whole object on create and update; keys
+// left out take their defaults and unknown keys are refused, so a typo doesn't silently do
+// nothing. Anything table-specific that isn't modelled here goes under `houserules`.
+
+const MAX_SYSTEM_LEN: usize = 64;
+const MAX_SOURCES: usize = 50;
+const MAX_SOURCE_LEN: usize = 64;
+const MAX_LEVEL: u8 = 20;
+const MAX_BLURB_CHARS: usize = 1000;
+const MAX_SCHEDULE_CHARS: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LevelRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl Default for LevelRange {
+    fn default() -> Self {
+        LevelRange { min: 1, max: MAX_LEVEL }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CampaignSettings {
+    // Game system the table plays, e.g. "dnd5e"
+    pub system: String,
+    // Character levels the campaign is written for
+    pub level_range: LevelRange,
+    // Compendium sources the table allows, e.g. "srd-5.1"; empty allows any
+    pub allowed_sources: Vec<String>,
+    pub houserules: serde_json::Map<String, serde_json::Value>,
+    // Seat limit for players; none when unlimited (see membership.rs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_players: Option<u32>,
+    // Listed publicly (see GET /lfg) and open to join requests
+    pub discoverable: bool,
+    // Pitch shown in the looking-for-group listing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blurb: Option<String>,
+    // When the table plays, as free text such as "Fridays 19:00 CET, every other week"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    // http(s) URL membership events are POSTed to (see outbox.rs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+}
+
+impl Default for CampaignSettings {
+    fn default() -> Self {
+        CampaignSettings {
+            system: "dnd5e".to_string(),
+            level_range: LevelRange::default(),
+            allowed_sources: Vec::new(),
+            houserules: serde_json::Map::new(),
+            max_players: None,
+            discoverable: false,
+            blurb: None,
+            schedule: None,
+            webhook_url: None,
+        }
+    }
+}
+
+impl CampaignSettings {
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.system.trim().is_empty() || self.system.len() > MAX_SYSTEM_LEN {
+            return Err("System must be a short name such as dnd5e");
+        }
+        let LevelRange { min, max } = self.level_range;
+        if min < 1 || max > MAX_LEVEL || min > max {
+            return Err("Level range must be within 1-20, with min no higher than max");
+        }
+        if self.allowed_sources.len() > MAX_SOURCES {
+            return Err("Too many allowed sources");
+        }
+        if self.allowed_sources.iter().any(|source| source.is_empty() || source.len() > MAX_SOURCE_LEN) {
+            return Err("Allowed sources must be non-empty compendium source names");
+        }
+        if self.blurb.as_ref().is_some_and(|blurb| blurb.chars().count() > MAX_BLURB_CHARS) {
+            return Err("Blurb must be at most 1000 characters");
+        }
+        if self.schedule.as_ref().is_some_and(|schedule| schedule.chars().count() > MAX_SCHEDULE_CHARS) {
+            return Err("Schedule must be at most 200 characters");
+        }
+        if let Some(url) = &self.webhook_url {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err("Webhook URL must be an http(s) URL");
+            }
+        }
+        Ok(())
+    }
+}
+
+// Reads settings sent by a client, with defaults filled in, or what's wrong with them for a 400.
+// Sources are matched the way the compendium stores them: trimmed and lower-cased.
+pub fn parse(value: serde_json::Value) -> Result<CampaignSettings, String> {
+    let mut settings: CampaignSettings = match serde_json::from_value(value) {
+        Ok(settings) => settings,
+        Err(e) => return Err(format!("Invalid settings: {}", e)),
+    };
+    let mut sources: Vec<String> = Vec::new();
+    for source in settings.allowed_sources.iter().map(|source| source.trim().to_lowercase()) {
+        if !sources.contains(&source) {
+            sources.push(source);
+        }
+    }
+    settings.allowed_sources = sources;
+    if let Err(message) = settings.validate() {
+        return Err(message.to_string());
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_settings_fill_defaults_and_refuse_what_they_dont_know() {
+        let settings = parse(json!({"max_players": 5, "allowed_sources": [" SRD-5.1 "]})).unwrap();
+        assert_eq!(settings.system, "dnd5e");
+        assert_eq!(settings.level_range, LevelRange { min: 1, max: 20 });
+        assert_eq!(settings.allowed_sources, vec!["srd-5.1"]);
+        let stored = serde_json::to_value(&settings).unwrap();
+        assert_eq!(stored["max_players"], 5);
+        assert!(stored.get("webhook_url").is_none());
+
+        let refused = [
+            json!({"theme": "dark"}),
+            json!({"max_players": -1}),
+            json!({"level_range": {"min": 5, "max": 3}}),
+            json!({"level_range": {"min": 0}}),
+            json!({"webhook_url": "ftp://example.com"}),
+            json!({"system": " "}),
+            json!({"schedule": "x".repeat(201)}),
+            json!(["not", "an", "object"]),
+        ];
+        for value in refused {
+            assert!(parse(value.clone()).is_err(), "{} was accepted", value);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+// Optional bot check on registration. Set CAPTCHA_PROVIDER to hcaptcha or turnstile
+// (Cloudflare) and CAPTCHA_SECRET to the site's secret key; register then needs the widget's
+// response in `captcha_token`, which is checked with the provider before anything else.
+// Unset, registration works as before.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaptchaProvider {
+    HCaptcha,
+    Turnstile,
+}
+
+impl CaptchaProvider {
+    fn verify_url(&self) -> &'static str {
+        match self {
+            CaptchaProvider::HCaptcha => "https://api.hcaptcha.com/siteverify",
+            CaptchaProvider::Turnstile => "https://challenges.cloudflare.com/turnstile/v0/siteverify",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptchaConfig {
+    pub provider: CaptchaProvider,
+    pub secret: String,
+}
+
+impl CaptchaConfig {
+    pub fn from_env() -> Option<Self> {
+        let provider = match env::var("CAPTCHA_PROVIDER").ok()?.to_lowercase().as_str() {
+            "hcaptcha" => CaptchaProvider::HCaptcha,
+            "turnstile" => CaptchaProvider::Turnstile,
+            other => {
+                tracing::warn!(provider = other, "Unknown CAPTCHA_PROVIDER, registration is not gated. Supported: hcaptcha, turnstile");
+                return None;
+            }
+        };
+        match env::var("CAPTCHA_SECRET") {
+            Ok(secret) if !secret.is_empty() => Some(CaptchaConfig { provider, secret }),
+            _ => {
+                tracing::warn!("CAPTCHA_PROVIDER is set without CAPTCHA_SECRET, registration is not gated");
+                None
+            }
+        }
+    }
+}
+
+pub fn captcha_config() -> Option<&'static CaptchaConfig> {
+    static CONFIG: OnceLock<Option<CaptchaConfig>> = OnceLock::new();
+    CONFIG.get_or_init(CaptchaConfig::from_env).as_ref()
+}
+
+// Sent back as JSON so clients can tell a failed challenge from other bad requests and
+// reset the widget
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "code")]
+pub enum CaptchaRejection {
+    #[serde(rename = "captcha_required")]
+    Required,
+    #[serde(rename = "captcha_failed")]
+    Failed { reasons: Vec<String> },
+    // The provider couldn't be reached; registration fails closed
+    #[serde(rename = "captcha_unavailable")]
+    Unavailable,
+}
+
+impl IntoResponse for CaptchaRejection {
+    fn into_response(self) -> Response {
+        let (status, error) = match &self {
+            CaptchaRejection::Required => (StatusCode::BAD_REQUEST, "Complete the captcha to register"),
+            CaptchaRejection::Failed { .. } => (StatusCode::BAD_REQUEST, "Captcha verification failed"),
+            CaptchaRejection::Unavailable => (StatusCode::SERVICE_UNAVAILABLE, "Captcha verification is unavailable; try again later"),
+        };
+        let mut body = serde_json::to_value(&self).expect("rejection serializes");
+        body["error"] = error.into();
+        (status, Json(body)).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+struct VerifyResponse {
+    success: bool,
+    #[serde(rename = "error-codes", default)]
+    error_codes: Vec<String>,
+}
+
+// Ok when no captcha is configured
+pub async fn check(config: Option<&CaptchaConfig>, token: Option<&str>, address: Option<IpAddr>) -> Result<(), CaptchaRejection> {
+    let Some(config) = config else {
+        return Ok(());
+    };
+    let Some(token) = token.map(str::trim).filter(|token| !token.is_empty()) else {
+        return Err(CaptchaRejection::Required);
+    };
+
+    let address = address.map(|address| address.to_string());
+    let mut form = vec![("secret", config.secret.as_str()), ("response", token)];
+    form.extend(address.as_deref().map(|address| ("remoteip", address)));
+    let verified = async {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+        let res = client
+            .post(config.provider.verify_url())
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| format!("Captcha request failed: {}", e))?;
+        if !res.status().is_success() {
+            return Err(format!("Captcha endpoint returned {}", res.status()));
+        }
+        res.json::<VerifyResponse>().await.map_err(|e| format!("Invalid captcha response: {}", e))
+    }
+    .await;
+    match verified {
+        Ok(response) if response.success => Ok(()),
+        Ok(response) => Err(CaptchaRejection::Failed { reasons: response.error_codes }),
+        Err(e) => {
+            tracing::warn!(error = %e, "CAPTCHA verification unavailable");
+            Err(CaptchaRejection::Unavailable)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tokens_are_required_only_when_configured() {
+        assert_eq!(check(None, None, None).await, Ok(()));
+
+        let config = CaptchaConfig { provider: CaptchaProvider::Turnstile, secret: "secret".to_string() };
+        assert_eq!(check(Some(&config), None, None).await, Err(CaptchaRejection::Required));
+        assert_eq!(check(Some(&config), Some("  "), None).await, Err(CaptchaRejection::Required));
+
+        let response = CaptchaRejection::Failed { reasons: vec!["invalid-input-response".to_string()] }.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "captcha_failed");
+        assert_eq!(body["reasons"][0], "invalid-input-response");
+    }
+}
@@ -0,0 +1,388 @@
+use axum::{extract::{Path, Query}, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::handlers::VISIBLE_EVENT;
+use crate::middleware::AuthUser;
+use crate::models::{Campaign, EventLog, Session};
+use crate::usage::{usage, Metric, Subject};
+
+// The campaign chronicle: one long-form document telling the story so far. Every ended
+// session gets a chapter, written from its description and major event log entries, and the
+// chronicle is the chapters in order. Regenerating only writes chapters for sessions that
+// ended since, so the AI never sees more than one session at a time. A session too long for
+// one prompt is summarized a chunk at a time and the chapter written from the summaries.
+// Without an AI provider, chapters are plain lists of what happened.
+
+// Roughly 3000 tokens of event lines per prompt
+const CHUNK_CHARS: usize = 12_000;
+
+// Routine table traffic; everything else in the event log makes the chronicle
+const MINOR_EVENTS: &[&str] = &["dice_roll", "chat_message", "ai_request", "dm_note"];
+
+const CHRONICLER: &str = "You are the chronicler of a D&D campaign, writing its history as engaging prose.";
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Chronicle {
+    pub campaign_id: Uuid,
+    pub content: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ChronicleChapter {
+    pub session_id: Uuid,
+    pub session_name: String,
+    pub content: String,
+    // AI model that wrote the chapter, or "template"
+    pub model: String,
+    pub generated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct ChronicleResponse {
+    #[serde(flatten)]
+    pub chronicle: Chronicle,
+    pub chapters: Vec<ChronicleChapter>,
+    // Sessions whose chapters this request wrote
+    pub written: Vec<Uuid>,
+    pub tokens_used: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct RegenerateQuery {
+    // Rewrite every chapter instead of only the missing ones
+    pub rebuild: Option<bool>,
+}
+
+// What happened, one line per major event, oldest first
+fn event_lines(events: &[EventLog]) -> Vec<String> {
+    events
+        .iter()
+        .filter(|event| !MINOR_EVENTS.contains(&event.event_type.as_str()))
+        .map(|event| {
+            let kind = event.event_type.replace('_', " ");
+            let text = ["description", "message", "text", "note", "summary"]
+                .iter()
+                .find_map(|key| event.event_data.get(*key).and_then(|value| value.as_str()));
+            match (text, &event.event_data) {
+                (Some(text), _) => format!("[{}] {}: {}", event.created_at.format("%H:%M"), kind, text),
+                (None, serde_json::Value::Object(fields)) if fields.is_empty() => format!("[{}] {}", event.created_at.format("%H:%M"), kind),
+                (None, data) => format!("[{}] {}: {}", event.created_at.format("%H:%M"), kind, data),
+            }
+        })
+        .collect()
+}
+
+// Splits lines into prompts of at most CHUNK_CHARS, cutting lines that are longer on their own
+fn chunk_lines(lines: &[String]) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    for line in lines {
+        let end = line.char_indices().map(|(i, c)| i + c.len_utf8()).take_while(|end| *end < CHUNK_CHARS).last().unwrap_or(0);
+        let line = &line[..end];
+        if !chunk.is_empty() && chunk.len() + line.len() + 1 > CHUNK_CHARS {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+        chunk.push_str(line);
+        chunk.push('\n');
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+// Used when no AI provider is configured or the provider fails
+fn template_chapter(session: &Session, lines: &[String]) -> String {
+    let mut chapter = String::new();
+    if let Some(description) = session.description.as_deref().filter(|description| !description.trim().is_empty()) {
+        chapter.push_str(description.trim());
+        chapter.push_str("\n\n");
+    }
+    if lines.is_empty() {
+        chapter.push_str("Nothing of note was recorded.");
+    }
+    for line in lines {
+        chapter.push_str(&format!("- {}\n", line));
+    }
+    chapter.trim_end().to_string()
+}
+
+fn assemble(campaign: &Campaign, chapters: &[ChronicleChapter]) -> String {
+    let mut content = format!("# {}\n", campaign.name);
+    if let Some(description) = campaign.description.as_deref().filter(|description| !description.trim().is_empty()) {
+        content.push_str(&format!("\n{}\n", description.trim()));
+    }
+    for (number, chapter) in chapters.iter().enumerate() {
+        content.push_str(&format!("\n## Chapter {}: {}\n\n{}\n", number + 1, chapter.session_name, chapter.content));
+    }
+    content
+}
+
+struct Written {
+    content: String,
+    model: String,
+    tokens_used: Option<i32>,
+}
+
+async fn ask(prompt: &str, written: &mut Written) -> Result<String, String> {
+    let completion = crate::ai::complete(CHRONICLER, prompt).await?;
+    written.model = completion.model;
+    if let Some(tokens) = completion.tokens_used {
+        written.tokens_used = Some(written.tokens_used.unwrap_or(0) + tokens);
+    }
+    Ok(completion.text.trim().to_string())
+}
+
+// Writes one session's chapter, carrying on from the one before it
+async fn write_chapter(campaign: &Campaign, session: &Session, lines: &[String], previous: Option<&str>, use_ai: bool) -> Written {
+    let mut written = Written { content: template_chapter(session, lines), model: "template".to_string(), tokens_used: None };
+    if !use_ai || lines.is_empty() {
+        return written;
+    }
+
+    let chunks = chunk_lines(lines);
+    let mut notes = Vec::new();
+    if chunks.len() > 1 {
+        for (part, chunk) in chunks.iter().enumerate() {
+            let prompt = format!(
+                "Summarize part {} of {} of the session \"{}\" of the campaign \"{}\" in a few paragraphs, keeping names, places and outcomes.\n\nEvents:\n{}",
+                part + 1, chunks.len(), session.name, campaign.name, chunk
+            );
+            match ask(&prompt, &mut written).await {
+                Ok(summary) => notes.push(summary),
+                Err(e) => {
+                    tracing::error!(error = %e, session_id = %session.id, "Chronicle summary failed");
+                    written.model = "template".to_string();
+                    return written;
+                }
+            }
+        }
+    } else {
+        notes = chunks;
+    }
+
+    let mut prompt = format!(
+        "Write the chapter of the campaign \"{}\" covering the session \"{}\", as a few paragraphs of past-tense prose. Stick to what the notes say happened.\n",
+        campaign.name, session.name
+    );
+    if let Some(description) = &session.description {
+        prompt.push_str(&format!("\nSession description: {}\n", description));
+    }
+    if let Some(previous) = previous {
+        // The end of the last chapter is enough for continuity
+        let tail: String = previous.chars().rev().take(1500).collect::<Vec<_>>().into_iter().rev().collect();
+        prompt.push_str(&format!("\nThe previous chapter ended:\n{}\n", tail));
+    }
+    prompt.push_str(&format!("\nNotes:\n{}", notes.join("\n")));
+    match ask(&prompt, &mut written).await {
+        Ok(chapter) if !chapter.is_empty() => written.content = chapter,
+        Ok(_) => {}
+        Err(e) => {
+            tracing::error!(error = %e, session_id = %session.id, "Chronicle chapter failed");
+            written.model = "template".to_string();
+        }
+    }
+    written
+}
+
+async fn chapters(pool: &PgPool, campaign_id: Uuid) -> Result<Vec<ChronicleChapter>, sqlx::Error> {
+    sqlx::query_as::<_, ChronicleChapter>(
+        "SELECT c.session_id, s.name AS session_name, c.content, c.model, c.generated_at
+         FROM campaign_chronicle_chapters c INNER JOIN sessions s ON s.id = c.session_id
+         WHERE c.campaign_id = $1 ORDER BY s.ended_at ASC, s.created_at ASC"
+    )
+    .bind(campaign_id)
+    .fetch_all(pool)
+    .await
+}
+
+// DM only, since it spends AI tokens; chapters are only written for sessions that have ended
+pub async fn generate_chronicle(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Query(params): Query<RegenerateQuery>,
+) -> impl IntoResponse {
+    let campaign = match sqlx::query_as::<_, Campaign>("SELECT * FROM campaigns WHERE id = $1").bind(campaign_id).fetch_one(&pool).await {
+        Ok(campaign) => campaign,
+        Err(e) => return db_error(e, "Failed to fetch campaign"),
+    };
+
+    let rebuild = params.rebuild.unwrap_or(false);
+    let sessions = sqlx::query_as::<_, Session>(
+        "SELECT * FROM sessions s WHERE campaign_id = $1 AND status = 'ended'
+         AND ($2 OR NOT EXISTS (SELECT 1 FROM campaign_chronicle_chapters c WHERE c.session_id = s.id))
+         ORDER BY ended_at ASC, created_at ASC"
+    )
+    .bind(campaign_id)
+    .bind(rebuild)
+    .fetch_all(&pool)
+    .await;
+    let sessions = match sessions {
+        Ok(sessions) => sessions,
+        Err(e) => return db_error(e, "Failed to fetch sessions"),
+    };
+
+    let use_ai = crate::demo::ai_enabled_for(&pool, user.0).await;
+    let mut written = Vec::new();
+    let mut tokens_used: Option<i32> = None;
+    // The latest chapter written before the first missing one
+    let mut previous = match chapters(&pool, campaign_id).await {
+        Ok(existing) if !rebuild => existing.into_iter().last().map(|chapter| chapter.content),
+        Ok(_) => None,
+        Err(e) => return db_error(e, "Failed to fetch chronicle"),
+    };
+    for session in &sessions {
+        // The chronicle is for the whole table, so hidden entries stay out
+        let events = sqlx::query_as::<_, EventLog>(&format!(
+            "SELECT * FROM event_logs WHERE session_id = $1 AND {} ORDER BY created_at ASC",
+            VISIBLE_EVENT
+        ))
+        .bind(session.id)
+        .bind(false)
+        .bind(Uuid::nil())
+        .fetch_all(&pool)
+        .await;
+        let events = match events {
+            Ok(events) => events,
+            Err(e) => return db_error(e, "Failed to fetch event logs"),
+        };
+
+        let chapter = write_chapter(&campaign, session, &event_lines(&events), previous.as_deref(), use_ai).await;
+        if let Some(tokens) = chapter.tokens_used {
+            tokens_used = Some(tokens_used.unwrap_or(0) + tokens);
+            usage().record(Subject::User(user.0), Metric::AiTokens, tokens as i64);
+            usage().record(Subject::Campaign(campaign_id), Metric::AiTokens, tokens as i64);
+        }
+        // Saved as it goes, so a failure part way keeps the chapters already paid for
+        let saved = sqlx::query(
+            "INSERT INTO campaign_chronicle_chapters (campaign_id, session_id, content, model, generated_at) VALUES ($1, $2, $3, $4, NOW())
+             ON CONFLICT (session_id) DO UPDATE SET content = EXCLUDED.content, model = EXCLUDED.model, generated_at = EXCLUDED.generated_at"
+        )
+        .bind(campaign_id)
+        .bind(session.id)
+        .bind(&chapter.content)
+        .bind(&chapter.model)
+        .execute(&pool)
+        .await;
+        if let Err(e) = saved {
+            return db_error(e, "Failed to save chronicle chapter");
+        }
+        written.push(session.id);
+        previous = Some(chapter.content);
+    }
+
+    let chapters = match chapters(&pool, campaign_id).await {
+        Ok(chapters) => chapters,
+        Err(e) => return db_error(e, "Failed to fetch chronicle"),
+    };
+    let chronicle = sqlx::query_as::<_, Chronicle>(
+        "INSERT INTO campaign_chronicles (campaign_id, content, updated_at) VALUES ($1, $2, NOW())
+         ON CONFLICT (campaign_id) DO UPDATE SET content = EXCLUDED.content, updated_at = EXCLUDED.updated_at
+         RETURNING campaign_id, content, updated_at"
+    )
+    .bind(campaign_id)
+    .bind(assemble(&campaign, &chapters))
+    .fetch_one(&pool)
+    .await;
+    match chronicle {
+        Ok(chronicle) => Json(ChronicleResponse { chronicle, chapters, written, tokens_used }).into_response(),
+        Err(e) => db_error(e, "Failed to save chronicle"),
+    }
+}
+
+pub async fn get_chronicle(
+    Extension(pool): Extension<PgPool>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let chronicle = sqlx::query_as::<_, Chronicle>("SELECT campaign_id, content, updated_at FROM campaign_chronicles WHERE campaign_id = $1")
+        .bind(campaign_id)
+        .fetch_optional(&pool)
+        .await;
+    let chronicle = match chronicle {
+        Ok(Some(chronicle)) => chronicle,
+        Ok(None) => return (StatusCode::NOT_FOUND, "No chronicle has been written yet").into_response(),
+        Err(e) => return db_error(e, "Failed to fetch chronicle"),
+    };
+    match chapters(&pool, campaign_id).await {
+        Ok(chapters) => Json(ChronicleResponse { chronicle, chapters, written: Vec::new(), tokens_used: None }).into_response(),
+        Err(e) => db_error(e, "Failed to fetch chronicle"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(event_type: &str, event_data: serde_json::Value) -> EventLog {
+        EventLog { id: Uuid::new_v4(), session_id: Uuid::new_v4(), event_type: event_type.to_string(), event_data, created_by: None, created_at: Utc::now() }
+    }
+
+    #[test]
+    fn test_only_major_events_make_the_chronicle() {
+        let lines = event_lines(&[
+            event("combat_start", json!({})),
+            event("dice_roll", json!({"total": 17})),
+            event("chat_message", json!({"message": "lol"})),
+            event("note", json!({"text": "The party reached Phandalin"})),
+            event("player_kicked", json!({"banned": false})),
+        ]);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("] combat start"));
+        assert!(lines[1].ends_with("] note: The party reached Phandalin"));
+        assert!(lines[2].ends_with("] player kicked: {\"banned\":false}"));
+    }
+
+    #[test]
+    fn test_chunks_respect_the_prompt_budget() {
+        let lines: Vec<String> = (0..1000).map(|i| format!("line {} {}", i, "x".repeat(40))).collect();
+        let chunks = chunk_lines(&lines);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= CHUNK_CHARS));
+        assert_eq!(chunks.concat().lines().count(), 1000);
+
+        let huge = vec!["y".repeat(CHUNK_CHARS * 2)];
+        assert!(chunk_lines(&huge).iter().all(|chunk| chunk.len() <= CHUNK_CHARS));
+    }
+
+    #[tokio::test]
+    async fn test_chronicle_grows_as_sessions_end() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let generate = |user_id: Uuid| generate_chronicle(Extension(pool.clone()), Extension(AuthUser(user_id)), Path(f.campaign_id), Query(RegenerateQuery { rebuild: None }));
+        let body = |response: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        let refused = crate::test_support::call_as(&pool, f.player_id, axum::http::Method::POST, &format!("/campaigns/{}/chronicle", f.campaign_id), None).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+
+        sqlx::query("INSERT INTO event_logs (session_id, event_type, event_data, created_by, created_at) VALUES ($1, 'note', $2, $3, NOW())")
+            .bind(f.session_id)
+            .bind(json!({"text": "The goblins fled", "hidden": true}))
+            .bind(f.dm_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE sessions SET status = 'ended', ended_at = NOW() WHERE id = $1").bind(f.session_id).execute(&pool).await.unwrap();
+
+        let first = body(generate(f.dm_id).await.into_response()).await;
+        assert_eq!(first["written"], json!([f.session_id]));
+        assert!(first["content"].as_str().unwrap().contains("## Chapter 1: Session 1"));
+        assert!(!first["content"].as_str().unwrap().contains("goblins"));
+
+        // Nothing new has ended, so nothing is rewritten
+        let again = body(generate(f.dm_id).await.into_response()).await;
+        assert_eq!(again["written"], json!([]));
+        assert_eq!(again["content"], first["content"]);
+
+        let read = get_chronicle(Extension(pool.clone()), Path(f.campaign_id)).await.into_response();
+        assert_eq!(body(read).await["chapters"].as_array().unwrap().len(), 1);
+    }
+}
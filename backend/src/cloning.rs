@@ -0,0 +1,241 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use std::collections::HashMap;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::handlers::CampaignResponse;
+use crate::middleware::{bump_token_versions, AuthUser};
+use crate::models::{Campaign, Character, Quest};
+
+// Copies a campaign's prep into a fresh campaign the caller runs, for rerunning a module with
+// a new group: settings, compendium content (NPCs, monsters, encounters and the rest), notes
+// and quests, reopened. Characters come along on request as pregens owned by the new DM, at full
+// HP. Members, sessions, grants and chronicles stay behind.
+
+// Settings tied to the old group rather than the module
+const GROUP_SETTINGS: [&str; 1] = ["webhook_url"];
+
+#[derive(Deserialize, Default)]
+pub struct CloneCampaignRequest {
+    // "<name> (copy)" when left out
+    pub name: Option<String>,
+    #[serde(default)]
+    pub include_characters: bool,
+}
+
+#[derive(Serialize)]
+pub struct ClonedCampaign {
+    pub campaign: CampaignResponse,
+    pub compendium_entries: u64,
+    pub quests: usize,
+    pub characters: usize,
+}
+
+// Creates the copy of `source`, run by `dm_id`; also used for campaigns made from templates
+pub(crate) async fn clone_into(
+    conn: &mut PgConnection,
+    source: &Campaign,
+    dm_id: Uuid,
+    name: String,
+    include_characters: bool,
+) -> Result<ClonedCampaign, sqlx::Error> {
+    let campaign_id = source.id;
+    let mut settings = source.settings.clone();
+    if let Some(settings) = settings.as_object_mut() {
+        for key in GROUP_SETTINGS {
+            settings.remove(key);
+        }
+    }
+    let now = Utc::now();
+    let campaign = sqlx::query_as::<_, Campaign>(
+        "INSERT INTO campaigns (id, name, description, dm_id, settings, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $6) RETURNING *"
+    )
+    .bind(Uuid::new_v4())
+    .bind(name)
+    .bind(&source.description)
+    .bind(dm_id)
+    .bind(&settings)
+    .bind(now)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let compendium_entries = sqlx::query(
+        "INSERT INTO compendium_entries (campaign_id, source, slug, kind, name, data, created_at, updated_at)
+         SELECT $2, source, slug, kind, name, data, $3, $3 FROM compendium_entries WHERE campaign_id = $1"
+    )
+    .bind(campaign_id)
+    .bind(campaign.id)
+    .bind(now)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    // Notes keep their links, which go by title
+    sqlx::query(
+        "WITH copied AS (
+             INSERT INTO campaign_notes (campaign_id, title, content, tags, visibility, created_by, created_at, updated_at)
+             SELECT $2, title, content, tags, visibility, $3, $4, $4 FROM campaign_notes WHERE campaign_id = $1
+             RETURNING id, title
+         )
+         INSERT INTO campaign_note_links (note_id, target_title)
+         SELECT copied.id, l.target_title FROM copied
+         INNER JOIN campaign_notes original ON original.campaign_id = $1 AND original.title = copied.title
+         INNER JOIN campaign_note_links l ON l.note_id = original.id"
+    )
+    .bind(campaign_id)
+    .bind(campaign.id)
+    .bind(dm_id)
+    .bind(now)
+    .execute(&mut *conn)
+    .await?;
+
+    // Old character id to its copy, so quests keep pointing at the right pregen
+    let mut copies = HashMap::new();
+    if include_characters {
+        let characters = sqlx::query_as::<_, Character>("SELECT * FROM characters WHERE campaign_id = $1 ORDER BY created_at")
+            .bind(campaign_id)
+            .fetch_all(&mut *conn)
+            .await?;
+        for character in characters {
+            let copy_id = Uuid::new_v4();
+            sqlx::query(
+                "INSERT INTO characters (id, campaign_id, player_id, name, race, class, level, hp_current, hp_max, ac, speed, stats, inventory, spells, features, xp, created_at, updated_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $8, $9, $10, $11, $12, $13, $14, $15, $16, $16)"
+            )
+            .bind(copy_id)
+            .bind(campaign.id)
+            .bind(dm_id)
+            .bind(&character.name)
+            .bind(&character.race)
+            .bind(&character.class)
+            .bind(character.level)
+            .bind(character.hp_max)
+            .bind(character.ac)
+            .bind(character.speed)
+            .bind(&character.stats)
+            .bind(&character.inventory)
+            .bind(&character.spells)
+            .bind(&character.features)
+            .bind(character.xp)
+            .bind(now)
+            .execute(&mut *conn)
+            .await?;
+            copies.insert(character.id, copy_id);
+        }
+    }
+
+    let quests = sqlx::query_as::<_, Quest>("SELECT * FROM quests WHERE campaign_id = $1 ORDER BY created_at")
+        .bind(campaign_id)
+        .fetch_all(&mut *conn)
+        .await?;
+    for quest in &quests {
+        sqlx::query(
+            "INSERT INTO quests (campaign_id, character_id, title, description, status, source, created_by, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, 'open', $5, $6, $7, $7)"
+        )
+        .bind(campaign.id)
+        .bind(quest.character_id.and_then(|id| copies.get(&id).copied()))
+        .bind(&quest.title)
+        .bind(&quest.description)
+        .bind(&quest.source)
+        .bind(dm_id)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(ClonedCampaign {
+        campaign: CampaignResponse::from(campaign),
+        compendium_entries,
+        quests: quests.len(),
+        characters: copies.len(),
+    })
+}
+
+pub async fn clone_campaign(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    payload: Option<Json<CloneCampaignRequest>>,
+) -> impl IntoResponse {
+    let Json(payload) = payload.unwrap_or_default();
+    if payload.name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+        return (StatusCode::BAD_REQUEST, "Name can't be empty").into_response();
+    }
+
+    let cloned = async {
+        let mut tx = pool.begin().await?;
+        let source = sqlx::query_as::<_, Campaign>("SELECT * FROM campaigns WHERE id = $1")
+            .bind(campaign_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let name = payload.name.as_deref().map_or_else(|| format!("{} (copy)", source.name), |name| name.trim().to_string());
+        let cloned = clone_into(&mut tx, &source, user.0, name, payload.include_characters).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(cloned)
+    }
+    .await;
+
+    match cloned {
+        Ok(cloned) => {
+            // New DM membership; tokens with embedded roles need refreshing
+            let _ = bump_token_versions(&pool, &[user.0]).await;
+            (StatusCode::CREATED, Json(cloned)).into_response()
+        }
+        Err(e) => db_error(e, "Failed to clone campaign"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_clones_copy_prep_but_not_the_group() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        sqlx::query("UPDATE campaigns SET settings = '{\"max_players\": 4, \"webhook_url\": \"https://chat.example.com/hook\"}' WHERE id = $1")
+            .bind(f.campaign_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO compendium_entries (campaign_id, source, slug, kind, name) VALUES ($1, 'module', 'sildar', 'npc', 'Sildar Hallwinter')")
+            .bind(f.campaign_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO quests (campaign_id, character_id, title, status) VALUES ($1, $2, 'Find Gundren', 'completed')")
+            .bind(f.campaign_id)
+            .bind(f.character_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let clone = |user_id: Uuid, include_characters: bool| {
+            let payload = Json(CloneCampaignRequest { name: None, include_characters });
+            clone_campaign(Extension(pool.clone()), Extension(AuthUser(user_id)), Path(f.campaign_id), Some(payload))
+        };
+        let refused = crate::test_support::call_as(&pool, f.player_id, axum::http::Method::POST, &format!("/campaigns/{}/clone", f.campaign_id), Some(serde_json::json!({"include_characters": false}))).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        let response = clone(f.dm_id, true).await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let cloned: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(cloned["campaign"]["name"], "Isolated (copy)");
+        assert_eq!(cloned["campaign"]["settings"], serde_json::json!({"max_players": 4}));
+        assert_eq!((cloned["compendium_entries"].as_u64(), cloned["quests"].as_u64(), cloned["characters"].as_u64()), (Some(1), Some(1), Some(1)));
+
+        let new_id: Uuid = cloned["campaign"]["id"].as_str().unwrap().parse().unwrap();
+        let players = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM campaign_players WHERE campaign_id = $1").bind(new_id).fetch_one(&pool).await.unwrap();
+        assert_eq!(players, 0);
+        let (status, owner) = sqlx::query_as::<_, (String, Option<Uuid>)>(
+            "SELECT q.status, ch.player_id FROM quests q INNER JOIN characters ch ON q.character_id = ch.id WHERE q.campaign_id = $1"
+        )
+        .bind(new_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!((status.as_str(), owner), ("open", Some(f.dm_id)));
+    }
+}
@@ -0,0 +1,204 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::handlers::{EventLogResponse, VISIBLE_EVENT};
+use crate::middleware::AuthUser;
+use crate::models::{Combat, EventLog, GameState, InitiativeEntry};
+use crate::scope::{authorize, Access, Resource};
+
+// Combat encounters. Each fight's initiative tracker is a row in `combats` rather than part
+// of the session's game state; a session runs one fight at a time and keeps the ended ones
+// as its combat history.
+
+fn decode(combat: Combat) -> Result<(Uuid, GameState), sqlx::Error> {
+    let state = serde_json::from_value(combat.state).map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
+    Ok((combat.id, state))
+}
+
+// The session's running fight, locked until the caller's transaction ends
+pub async fn lock_active(conn: &mut PgConnection, session_id: Uuid) -> Result<Option<(Uuid, GameState)>, sqlx::Error> {
+    sqlx::query_as::<_, Combat>("SELECT * FROM combats WHERE session_id = $1 AND ended_at IS NULL FOR UPDATE")
+        .bind(session_id)
+        .fetch_optional(conn)
+        .await?
+        .map(decode)
+        .transpose()
+}
+
+// Tracker for a fight starting from round 1. skip_downed comes from the session's game
+// state, where the DM sets it for the whole table.
+pub async fn new_state(conn: &mut PgConnection, session_id: Uuid, initiative_order: Vec<InitiativeEntry>) -> Result<GameState, sqlx::Error> {
+    let skip_downed = sqlx::query_scalar::<_, Option<bool>>("SELECT (game_state->>'skip_downed')::boolean FROM sessions WHERE id = $1")
+        .bind(session_id)
+        .fetch_one(conn)
+        .await?;
+    Ok(GameState {
+        initiative_order,
+        current_turn: None,
+        round: 1,
+        combat_active: true,
+        conditions: Vec::new(),
+        skip_downed: skip_downed.unwrap_or(true),
+    })
+}
+
+// Writes the tracker back, starting a fight when `combat_id` is None and ending it once
+// combat_active is off. Returns the stored state, with linked characters' HP and AC filled in.
+pub async fn save(conn: &mut PgConnection, session_id: Uuid, combat_id: Option<Uuid>, state: &GameState) -> Result<(Uuid, GameState), sqlx::Error> {
+    let combat = match combat_id {
+        Some(combat_id) => sqlx::query_as::<_, Combat>(
+            "UPDATE combats SET state = $1, ended_at = CASE WHEN $2 THEN NULL ELSE NOW() END, updated_at = NOW()
+             WHERE id = $3 RETURNING *"
+        )
+        .bind(serde_json::to_value(state).unwrap())
+        .bind(state.combat_active)
+        .bind(combat_id),
+        None => sqlx::query_as::<_, Combat>("INSERT INTO combats (session_id, state) VALUES ($1, $2) RETURNING *")
+            .bind(session_id)
+            .bind(serde_json::to_value(state).unwrap()),
+    };
+    decode(combat.fetch_one(conn).await?)
+}
+
+pub async fn list_session_combats(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(session_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize(&pool, user.0, Resource::Session(session_id), Access::Member, "Access denied to this session").await {
+        return response;
+    }
+
+    // Newest first, so a running fight comes at the top
+    let combats = sqlx::query_as::<_, Combat>("SELECT * FROM combats WHERE session_id = $1 ORDER BY started_at DESC")
+        .bind(session_id)
+        .fetch_all(&pool)
+        .await;
+
+    match combats {
+        Ok(combats) => Json(combats).into_response(),
+        Err(e) => db_error(e, "Failed to fetch combats"),
+    }
+}
+
+// The session's event log from the start of one fight to its end, for replaying it
+pub async fn list_combat_events(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(combat_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let combat = sqlx::query_as::<_, Combat>("SELECT * FROM combats WHERE id = $1")
+        .bind(combat_id)
+        .fetch_optional(&pool)
+        .await;
+    let combat = match combat {
+        Ok(Some(combat)) => combat,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Combat not found").into_response(),
+        Err(e) => return db_error(e, "Failed to fetch combat"),
+    };
+    let scope = match authorize(&pool, user.0, Resource::Session(combat.session_id), Access::Member, "Access denied to this session").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    let events = sqlx::query_as::<_, EventLog>(&format!(
+        "SELECT * FROM event_logs WHERE session_id = $1 AND {} AND created_at >= $4 AND ($5::TIMESTAMPTZ IS NULL OR created_at <= $5)
+         ORDER BY created_at ASC",
+        VISIBLE_EVENT
+    ))
+    .bind(combat.session_id)
+    .bind(scope.is_dm())
+    .bind(user.0)
+    .bind(combat.started_at)
+    .bind(combat.ended_at)
+    .fetch_all(&pool)
+    .await;
+
+    match events {
+        Ok(events) => Json(events.into_iter().map(EventLogResponse::from).collect::<Vec<_>>()).into_response(),
+        Err(e) => db_error(e, "Failed to fetch event logs"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::Response;
+    use serde_json::{json, Value};
+    use crate::handlers::{update_initiative, UpdateInitiativeRequest};
+    use crate::test_support::{campaign_fixture, Fixture};
+
+    async fn create_test_pool() -> PgPool {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        sqlx::postgres::PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&database_url)
+            .await
+            .expect("Failed to create test pool")
+    }
+
+    async fn body_json(response: Response) -> Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    async fn update(pool: &PgPool, f: &Fixture, order: &[InitiativeEntry], combat_active: bool) -> StatusCode {
+        let request = UpdateInitiativeRequest {
+            session_id: f.session_id,
+            initiative_order: order.to_vec(),
+            current_turn: None,
+            round: None,
+            combat_active: Some(combat_active),
+        };
+        update_initiative(Extension(pool.clone()), Extension(AuthUser(f.dm_id)), Json(request)).await.into_response().status()
+    }
+
+    #[tokio::test]
+    async fn test_each_fight_is_kept_as_its_own_combat() {
+        let pool = create_test_pool().await;
+        let f = campaign_fixture(&pool).await;
+
+        let order: Vec<InitiativeEntry> = serde_json::from_value(json!([{
+            "id": Uuid::new_v4(), "name": "Goblin", "initiative": 12, "is_player": false,
+            "character_id": null, "user_id": null, "hp_current": 7, "hp_max": 7, "ac": 15,
+        }]))
+        .unwrap();
+        assert_eq!(update(&pool, &f, &order, true).await, StatusCode::OK);
+        assert_eq!(update(&pool, &f, &order, false).await, StatusCode::OK);
+        assert_eq!(update(&pool, &f, &order, false).await, StatusCode::BAD_REQUEST);
+        assert_eq!(update(&pool, &f, &order, true).await, StatusCode::OK);
+        sqlx::query("INSERT INTO event_logs (session_id, event_type, event_data, created_by, created_at) VALUES ($1, 'dice_roll', '{}', $2, NOW())")
+            .bind(f.session_id)
+            .bind(f.dm_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // The session's own game state is left alone
+        let game_state = sqlx::query_scalar::<_, Value>("SELECT game_state FROM sessions WHERE id = $1").bind(f.session_id).fetch_one(&pool).await.unwrap();
+        assert_eq!(game_state["initiative_order"], json!([]));
+
+        let response = list_session_combats(Extension(pool.clone()), Extension(AuthUser(f.player_id)), Path(f.session_id)).await;
+        let combats = body_json(response.into_response()).await;
+        assert_eq!(combats.as_array().unwrap().len(), 2);
+        let (running, ended) = (&combats[0], &combats[1]);
+        assert!(running["ended_at"].is_null() && !ended["ended_at"].is_null());
+        assert_eq!(running["state"]["combat_active"], true);
+        assert_eq!(ended["state"]["initiative_order"][0]["name"], "Goblin");
+
+        let events = |combat: &Value| {
+            let combat_id: Uuid = serde_json::from_value(combat["id"].clone()).unwrap();
+            let pool = pool.clone();
+            async move {
+                let response = list_combat_events(Extension(pool), Extension(AuthUser(f.player_id)), Path(combat_id)).await;
+                body_json(response.into_response()).await.as_array().unwrap().iter().map(|event| event["event_type"].clone()).collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(events(running).await, vec![json!("dice_roll")]);
+        assert!(events(ended).await.is_empty());
+
+        let response = list_session_combats(Extension(pool.clone()), Extension(AuthUser(f.outsider_id)), Path(f.session_id)).await;
+        assert_eq!(response.into_response().status(), StatusCode::FORBIDDEN);
+    }
+}
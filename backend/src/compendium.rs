@@ -0,0 +1,302 @@
+use axum::{extract::{Path, Query}, http::StatusCode, response::IntoResponse, Extension, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use uuid::Uuid;
+use crate::middleware::AuthUser;
+use crate::models::CompendiumEntry;
+use crate::errors::db_error;
+use crate::content::{record_contribution, ContentKind};
+use crate::scope::{authorize, resolve, Access, Permission, Resource};
+
+// Per-campaign reference content. Imports are idempotent: an entry is keyed by
+// (source, slug), so re-running an SRD or module import only touches what changed.
+
+// Largest import accepted in one request
+const MAX_IMPORT_ENTRIES: usize = 5000;
+
+#[derive(Deserialize)]
+pub struct ImportEntry {
+    // Derived from the name when omitted
+    pub slug: Option<String>,
+    pub kind: String,
+    pub name: String,
+    #[serde(default)]
+    pub data: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+pub struct ImportRequest {
+    pub source: String,
+    pub entries: Vec<ImportEntry>,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct ImportReport {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    // Unchanged entries, and repeats of a slug earlier in the same import
+    pub skipped: Vec<String>,
+}
+
+// "Cure Wounds (2024)" -> "cure-wounds-2024"
+fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.trim().chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if c == '\'' || c == '’' {
+            continue;
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+pub async fn import_compendium(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Json(payload): Json<ImportRequest>,
+) -> impl IntoResponse {
+    // Players allowed to edit NPCs may import them, without touching other entries
+    let npcs_only = payload.entries.iter().all(|entry| entry.kind == "npc");
+    let access = if npcs_only { Access::Permitted(Permission::EditNpcs) } else { Access::Dm };
+    let scope = match authorize(&pool, user.0, Resource::Campaign(campaign_id), access, "Only the DM can import compendium content").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    let source = payload.source.trim().to_lowercase();
+    if source.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Source is required").into_response();
+    }
+    if payload.entries.len() > MAX_IMPORT_ENTRIES {
+        return (StatusCode::BAD_REQUEST, format!("At most {} entries per import", MAX_IMPORT_ENTRIES)).into_response();
+    }
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(e, "Failed to import compendium"),
+    };
+
+    let mut report = ImportReport::default();
+    let mut seen = HashSet::new();
+    for entry in payload.entries {
+        let slug = slugify(entry.slug.as_deref().unwrap_or(&entry.name));
+        if slug.is_empty() {
+            return (StatusCode::BAD_REQUEST, format!("Entry \"{}\" has no usable slug", entry.name)).into_response();
+        }
+        if !seen.insert(slug.clone()) {
+            report.skipped.push(slug);
+            continue;
+        }
+
+        // The update only fires when something changed; xmax = 0 marks a fresh insert
+        let inserted = sqlx::query_scalar::<_, bool>(
+            "INSERT INTO compendium_entries (campaign_id, source, slug, kind, name, data)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (campaign_id, source, slug) DO UPDATE
+             SET kind = EXCLUDED.kind, name = EXCLUDED.name, data = EXCLUDED.data, updated_at = NOW()
+             WHERE (compendium_entries.kind, compendium_entries.name, compendium_entries.data)
+                 IS DISTINCT FROM (EXCLUDED.kind, EXCLUDED.name, EXCLUDED.data)
+               AND ($7 OR compendium_entries.kind = 'npc')
+             RETURNING (xmax = 0)"
+        )
+        .bind(campaign_id)
+        .bind(&source)
+        .bind(&slug)
+        .bind(&entry.kind)
+        .bind(&entry.name)
+        .bind(&entry.data)
+        .bind(scope.is_dm())
+        .fetch_optional(&mut *tx)
+        .await;
+
+        match inserted {
+            Ok(Some(true)) => report.added.push(slug),
+            Ok(Some(false)) => report.updated.push(slug),
+            Ok(None) => report.skipped.push(slug),
+            Err(e) => return db_error(e, "Failed to import compendium"),
+        }
+    }
+
+    if let Err(e) = tx.commit().await {
+        return db_error(e, "Failed to import compendium");
+    }
+    Json(report).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct CompendiumQuery {
+    pub kind: Option<String>,
+    pub source: Option<String>,
+}
+
+pub async fn list_compendium(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Query(params): Query<CompendiumQuery>,
+) -> impl IntoResponse {
+    match resolve(&pool, user.0, Resource::Campaign(campaign_id)).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return (StatusCode::NOT_FOUND, "Campaign not found").into_response(),
+        Err(e) => return db_error(e, "Failed to verify access"),
+    }
+
+    let entries = sqlx::query_as::<_, CompendiumEntry>(
+        "SELECT * FROM compendium_entries
+         WHERE campaign_id = $1 AND ($2::TEXT IS NULL OR kind = $2) AND ($3::TEXT IS NULL OR source = $3)
+         ORDER BY kind, name"
+    )
+    .bind(campaign_id)
+    .bind(&params.kind)
+    .bind(params.source.as_ref().map(|source| source.trim().to_lowercase()))
+    .fetch_all(&pool)
+    .await;
+
+    match entries {
+        Ok(entries) => Json(entries).into_response(),
+        Err(e) => db_error(e, "Failed to fetch compendium"),
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct UpdateEntryRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+// The DM, or a player the DM granted edit rights on this entry
+pub async fn update_compendium_entry(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(entry_id): Path<Uuid>,
+    Json(payload): Json<UpdateEntryRequest>,
+) -> impl IntoResponse {
+    let scope = match authorize(&pool, user.0, Resource::Content(ContentKind::Compendium, entry_id), Access::Modify, "You don't have edit rights on this entry").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    if payload.name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+        return (StatusCode::BAD_REQUEST, "Name cannot be empty").into_response();
+    }
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(e, "Failed to update entry"),
+    };
+
+    let entry = sqlx::query_as::<_, CompendiumEntry>(
+        "UPDATE compendium_entries SET name = COALESCE($2, name), data = COALESCE($3, data), updated_at = NOW()
+         WHERE id = $1 RETURNING *"
+    )
+    .bind(entry_id)
+    .bind(payload.name.as_deref().map(str::trim))
+    .bind(&payload.data)
+    .fetch_one(&mut *tx)
+    .await;
+    let entry = match entry {
+        Ok(entry) => entry,
+        Err(e) => return db_error(e, "Failed to update entry"),
+    };
+
+    let changes = serde_json::to_value(&payload).unwrap_or_default();
+    if let Err(e) = record_contribution(&mut *tx, scope.campaign_id, ContentKind::Compendium, entry_id, user.0, &changes).await {
+        return db_error(e, "Failed to update entry");
+    }
+    if let Err(e) = tx.commit().await {
+        return db_error(e, "Failed to update entry");
+    }
+    Json(entry).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Fireball"), "fireball");
+        assert_eq!(slugify("  Cure Wounds (2024) "), "cure-wounds-2024");
+        assert_eq!(slugify("Mordenkainen's Sword"), "mordenkainens-sword");
+        assert_eq!(slugify("???"), "");
+    }
+
+    async fn create_test_pool() -> PgPool {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        sqlx::postgres::PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&database_url)
+            .await
+            .expect("Failed to create test pool")
+    }
+
+    fn spell(name: &str, level: i32) -> ImportEntry {
+        ImportEntry {
+            slug: None,
+            kind: "spell".to_string(),
+            name: name.to_string(),
+            data: serde_json::json!({"level": level}),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reimport_reports_changes() {
+        let pool = create_test_pool().await;
+
+        let dm_id = Uuid::new_v4();
+        let timestamp = Utc::now().timestamp();
+        sqlx::query("INSERT INTO users (id, email, username, password_hash, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(dm_id)
+            .bind(format!("compendium{}@example.com", timestamp))
+            .bind(format!("compendium{}", timestamp))
+            .bind("hashed_password")
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let campaign_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO campaigns (id, name, description, dm_id, settings, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(campaign_id)
+            .bind("Library")
+            .bind("Compendium import test")
+            .bind(dm_id)
+            .bind(serde_json::json!({}))
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let import = ImportRequest { source: "SRD-5.1".to_string(), entries: vec![spell("Fireball", 3), spell("Shield", 1)] };
+        let response = import_compendium(Extension(pool.clone()), Extension(AuthUser(dm_id)), Path(campaign_id), Json(import)).await;
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+
+        // Same source again: one changed, one unchanged, one new, one repeated
+        let import = ImportRequest {
+            source: "srd-5.1".to_string(),
+            entries: vec![spell("Fireball", 4), spell("Shield", 1), spell("Light", 0), spell("light", 0)],
+        };
+        let response = import_compendium(Extension(pool.clone()), Extension(AuthUser(dm_id)), Path(campaign_id), Json(import)).await;
+        let body = axum::body::to_bytes(response.into_response().into_body(), usize::MAX).await.unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["added"], serde_json::json!(["light"]));
+        assert_eq!(report["updated"], serde_json::json!(["fireball"]));
+        assert_eq!(report["skipped"], serde_json::json!(["shield", "light"]));
+
+        let count = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM compendium_entries WHERE campaign_id = $1")
+            .bind(campaign_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 3);
+    }
+}
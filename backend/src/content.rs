@@ -0,0 +1,234 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::middleware::{AuthUser, CampaignRole};
+use crate::models::{ContentContribution, ContentGrant};
+use crate::scope::{authorize, resolve, Access, Resource};
+
+// Per-entity edit rights on campaign content. The DM stays the only one who can change
+// content by default; a grant lets one player edit one entry, checked through
+// `Access::Modify` on `Resource::Content`. Every edit is recorded as a contribution.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentKind {
+    Compendium,
+}
+
+impl ContentKind {
+    // Stored in content_grants.entity_type and content_contributions.entity_type
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentKind::Compendium => "compendium",
+        }
+    }
+}
+
+pub async fn record_contribution<'e, E>(
+    executor: E,
+    campaign_id: Uuid,
+    kind: ContentKind,
+    entity_id: Uuid,
+    user_id: Uuid,
+    changes: &serde_json::Value,
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    sqlx::query("INSERT INTO content_contributions (campaign_id, entity_type, entity_id, user_id, changes) VALUES ($1, $2, $3, $4, $5)")
+        .bind(campaign_id)
+        .bind(kind.as_str())
+        .bind(entity_id)
+        .bind(user_id)
+        .bind(changes)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct GrantRequest {
+    pub entity_type: ContentKind,
+    pub entity_id: Uuid,
+    pub user_id: Uuid,
+}
+
+pub async fn grant_content_access(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Json(payload): Json<GrantRequest>,
+) -> impl IntoResponse {
+    match resolve(&pool, user.0, Resource::Content(payload.entity_type, payload.entity_id)).await {
+        Ok(Some(scope)) if scope.contains(campaign_id) => {}
+        Ok(_) => return (StatusCode::NOT_FOUND, "Content not found").into_response(),
+        Err(e) => return db_error(e, "Failed to verify access"),
+    }
+
+    match resolve(&pool, payload.user_id, Resource::Campaign(campaign_id)).await {
+        Ok(Some(member)) if member.role != CampaignRole::Dm => {}
+        Ok(_) => return (StatusCode::BAD_REQUEST, "Edit rights can only be granted to players in the campaign").into_response(),
+        Err(e) => return db_error(e, "Failed to grant edit rights"),
+    }
+
+    // Granting twice is a no-op apart from who granted it
+    let grant = sqlx::query_as::<_, ContentGrant>(
+        "INSERT INTO content_grants (campaign_id, entity_type, entity_id, user_id, granted_by)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (entity_type, entity_id, user_id) DO UPDATE SET granted_by = EXCLUDED.granted_by
+         RETURNING *"
+    )
+    .bind(campaign_id)
+    .bind(payload.entity_type.as_str())
+    .bind(payload.entity_id)
+    .bind(payload.user_id)
+    .bind(user.0)
+    .fetch_one(&pool)
+    .await;
+
+    match grant {
+        Ok(grant) => (StatusCode::CREATED, Json(grant)).into_response(),
+        Err(e) => db_error(e, "Failed to grant edit rights"),
+    }
+}
+
+// Visible to the whole table, so everyone knows who keeps which page
+pub async fn list_content_grants(
+    Extension(pool): Extension<PgPool>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let grants = sqlx::query_as::<_, ContentGrant>("SELECT * FROM content_grants WHERE campaign_id = $1 ORDER BY created_at")
+        .bind(campaign_id)
+        .fetch_all(&pool)
+        .await;
+
+    match grants {
+        Ok(grants) => Json(grants).into_response(),
+        Err(e) => db_error(e, "Failed to fetch edit grants"),
+    }
+}
+
+pub async fn revoke_content_grant(
+    Extension(pool): Extension<PgPool>,
+    Path((campaign_id, grant_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let res = sqlx::query("DELETE FROM content_grants WHERE id = $1 AND campaign_id = $2")
+        .bind(grant_id)
+        .bind(campaign_id)
+        .execute(&pool)
+        .await;
+
+    match res {
+        Ok(result) if result.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Grant not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Edit rights revoked").into_response(),
+        Err(e) => db_error(e, "Failed to revoke edit rights"),
+    }
+}
+
+pub async fn list_contributions(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path((kind, entity_id)): Path<(ContentKind, Uuid)>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize(&pool, user.0, Resource::Content(kind, entity_id), Access::Member, "Access denied to this content").await {
+        return response;
+    }
+
+    let contributions = sqlx::query_as::<_, ContentContribution>(
+        "SELECT cc.id, cc.entity_type, cc.entity_id, cc.user_id, u.username, cc.changes, cc.created_at
+         FROM content_contributions cc INNER JOIN users u ON cc.user_id = u.id
+         WHERE cc.entity_type = $1 AND cc.entity_id = $2
+         ORDER BY cc.created_at"
+    )
+    .bind(kind.as_str())
+    .bind(entity_id)
+    .fetch_all(&pool)
+    .await;
+
+    match contributions {
+        Ok(contributions) => Json(contributions).into_response(),
+        Err(e) => db_error(e, "Failed to fetch contribution history"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::Response;
+    use serde_json::{json, Value};
+    use crate::compendium::{update_compendium_entry, UpdateEntryRequest};
+    use crate::test_support::campaign_fixture;
+
+    async fn create_test_pool() -> PgPool {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        sqlx::postgres::PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&database_url)
+            .await
+            .expect("Failed to create test pool")
+    }
+
+    async fn body_json(response: Response) -> Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    async fn edit(pool: &PgPool, user_id: Uuid, entry_id: Uuid, name: &str) -> StatusCode {
+        let payload = UpdateEntryRequest { name: Some(name.to_string()), data: None };
+        update_compendium_entry(Extension(pool.clone()), Extension(AuthUser(user_id)), Path(entry_id), Json(payload))
+            .await
+            .into_response()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_granted_player_can_edit_one_entry() {
+        let pool = create_test_pool().await;
+        let f = campaign_fixture(&pool).await;
+
+        let mut entries = Vec::new();
+        for slug in ["rusty-tankard", "old-mill"] {
+            let id = sqlx::query_scalar::<_, Uuid>(
+                "INSERT INTO compendium_entries (campaign_id, source, slug, kind, name) VALUES ($1, 'homebrew', $2, 'location', $2) RETURNING id"
+            )
+            .bind(f.campaign_id)
+            .bind(slug)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            entries.push(id);
+        }
+        let (tavern, mill) = (entries[0], entries[1]);
+
+        assert_eq!(edit(&pool, f.player_id, tavern, "The Rusty Tankard").await, StatusCode::FORBIDDEN);
+
+        let grant = |user_id| GrantRequest { entity_type: ContentKind::Compendium, entity_id: tavern, user_id };
+        let body = json!({"entity_type": ContentKind::Compendium, "entity_id": tavern, "user_id": f.player_id});
+        let response = crate::test_support::call_as(&pool, f.player_id, axum::http::Method::POST, &format!("/campaigns/{}/content-grants", f.campaign_id), Some(body)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = grant_content_access(Extension(pool.clone()), Extension(AuthUser(f.dm_id)), Path(f.campaign_id), Json(grant(f.outsider_id))).await;
+        assert_eq!(response.into_response().status(), StatusCode::BAD_REQUEST);
+        let response = grant_content_access(Extension(pool.clone()), Extension(AuthUser(f.dm_id)), Path(f.campaign_id), Json(grant(f.player_id))).await;
+        let response = response.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let grant_id: Uuid = serde_json::from_value(body_json(response).await["id"].clone()).unwrap();
+
+        // The grant covers the tavern only
+        assert_eq!(edit(&pool, f.player_id, tavern, "The Rusty Tankard").await, StatusCode::OK);
+        assert_eq!(edit(&pool, f.player_id, mill, "The Old Mill").await, StatusCode::FORBIDDEN);
+        assert_eq!(edit(&pool, f.outsider_id, tavern, "Ours now").await, StatusCode::FORBIDDEN);
+        assert_eq!(edit(&pool, f.dm_id, tavern, "The Rusty Tankard Inn").await, StatusCode::OK);
+
+        let response = list_contributions(Extension(pool.clone()), Extension(AuthUser(f.player_id)), Path((ContentKind::Compendium, tavern))).await;
+        let history = body_json(response.into_response()).await;
+        let authors: Vec<Uuid> = history.as_array().unwrap().iter().map(|c| serde_json::from_value(c["user_id"].clone()).unwrap()).collect();
+        assert_eq!(authors, vec![f.player_id, f.dm_id]);
+        assert_eq!(history[0]["changes"], json!({"name": "The Rusty Tankard"}));
+
+        let response = revoke_content_grant(Extension(pool.clone()), Path((f.campaign_id, grant_id))).await;
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+        assert_eq!(edit(&pool, f.player_id, tavern, "Mine again").await, StatusCode::FORBIDDEN);
+    }
+}
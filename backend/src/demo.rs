@@ -0,0 +1,228 @@
+use axum::{extract::ConnectInfo, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::handlers::LoginResponse;
+use crate::revocation::revoke_user;
+use crate::socket::SessionState;
+
+// Public demo. With DEMO_MODE=true, POST /demo gives an anonymous visitor a throwaway account
+// that is DM of a sandbox campaign with a running session and two characters, so they can try
+// the dice, chat and initiative tracker before registering. Demo accounts never reach the AI,
+// and the maintenance task deletes them, with everything they made, DEMO_TTL_MINUTES (default
+// 60) after creation. Each address gets a few sandboxes an hour, and at most
+// DEMO_MAX_ACTIVE (default 100) exist at once.
+
+const DEFAULT_TTL_MINUTES: i64 = 60;
+const DEFAULT_MAX_ACTIVE: i64 = 100;
+
+// Sandboxes per client address per window
+const PER_ADDRESS_LIMIT: usize = 3;
+const PER_ADDRESS_WINDOW: Duration = Duration::from_secs(3600);
+
+pub fn demo_enabled() -> bool {
+    env::var("DEMO_MODE").map(|value| value == "true").unwrap_or(false)
+}
+
+fn demo_ttl() -> chrono::Duration {
+    let minutes = env::var("DEMO_TTL_MINUTES").ok().and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_TTL_MINUTES);
+    chrono::Duration::minutes(minutes.max(1))
+}
+
+fn max_active() -> i64 {
+    env::var("DEMO_MAX_ACTIVE").ok().and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_MAX_ACTIVE)
+}
+
+// Recent sandbox creations by client address, on this instance
+#[derive(Default)]
+struct AddressLimiter(Mutex<HashMap<IpAddr, Vec<Instant>>>);
+
+impl AddressLimiter {
+    fn try_acquire(&self, address: IpAddr, now: Instant) -> bool {
+        let mut recent = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        recent.retain(|_, times| {
+            times.retain(|time| now.duration_since(*time) < PER_ADDRESS_WINDOW);
+            !times.is_empty()
+        });
+        let times = recent.entry(address).or_default();
+        if times.len() >= PER_ADDRESS_LIMIT {
+            return false;
+        }
+        times.push(now);
+        true
+    }
+}
+
+fn limiter() -> &'static AddressLimiter {
+    static LIMITER: OnceLock<AddressLimiter> = OnceLock::new();
+    LIMITER.get_or_init(AddressLimiter::default)
+}
+
+// Demo accounts are left out of the AI, which costs real money per request
+pub async fn ai_enabled_for(pool: &PgPool, user_id: Uuid) -> bool {
+    if !crate::ai::is_enabled() {
+        return false;
+    }
+    let demo = sqlx::query_scalar::<_, bool>("SELECT demo_expires_at IS NOT NULL FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await;
+    // A lookup failure is treated as a demo account
+    matches!(demo, Ok(Some(false)))
+}
+
+#[derive(Serialize)]
+pub struct DemoResponse {
+    #[serde(flatten)]
+    pub tokens: LoginResponse,
+    pub user_id: Uuid,
+    pub campaign_id: Uuid,
+    pub session_id: Uuid,
+    // When the sandbox and the account are deleted
+    pub expires_at: DateTime<Utc>,
+}
+
+const DEMO_CHARACTERS: &[(&str, &str, &str, i32, i32)] = &[
+    ("Wren Tealeaf", "Halfling", "Rogue", 27, 15),
+    ("Thorin Ironfist", "Dwarf", "Fighter", 44, 18),
+];
+
+pub async fn create_demo(
+    Extension(pool): Extension<PgPool>,
+    client: Option<ConnectInfo<SocketAddr>>,
+) -> impl IntoResponse {
+    if !demo_enabled() {
+        return (StatusCode::NOT_FOUND, "Demo mode is not enabled").into_response();
+    }
+    // Connections without a peer address share one allowance
+    let address = client.map_or(IpAddr::from([0, 0, 0, 0]), |ConnectInfo(address)| address.ip());
+    if !limiter().try_acquire(address, Instant::now()) {
+        return (StatusCode::TOO_MANY_REQUESTS, "Too many demos from this address; try again later").into_response();
+    }
+    match sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE demo_expires_at IS NOT NULL").fetch_one(&pool).await {
+        Ok(active) if active >= max_active() => {
+            return (StatusCode::SERVICE_UNAVAILABLE, "All demo sandboxes are in use; try again shortly").into_response();
+        }
+        Ok(_) => {}
+        Err(e) => return db_error(e, "Failed to create demo"),
+    }
+
+    let (user_id, campaign_id, session_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let expires_at = Utc::now() + demo_ttl();
+    let created = async {
+        let mut tx = pool.begin().await?;
+        let short = user_id.simple().to_string()[..12].to_string();
+        // No usable password, so the account can only be reached through the tokens below
+        sqlx::query(
+            "INSERT INTO users (id, email, username, password_hash, email_verified, demo_expires_at, created_at, updated_at)
+             VALUES ($1, $2, $3, '!', TRUE, $4, NOW(), NOW())"
+        )
+        .bind(user_id)
+        .bind(format!("demo-{}@demo.invalid", short))
+        .bind(format!("demo_{}", short))
+        .bind(expires_at)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT INTO campaigns (id, name, description, dm_id, settings, created_at, updated_at)
+             VALUES ($1, 'Demo Campaign', 'A sandbox to try YoDA in. It disappears when the demo ends.', $2, '{}', NOW(), NOW())"
+        )
+        .bind(campaign_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            "INSERT INTO sessions (id, campaign_id, name, status, started_at, game_state, created_at, updated_at)
+             VALUES ($1, $2, 'The Goblin Ambush', 'active', NOW(), '{}', NOW(), NOW())"
+        )
+        .bind(session_id)
+        .bind(campaign_id)
+        .execute(&mut *tx)
+        .await?;
+        for (name, race, class, hp, ac) in DEMO_CHARACTERS {
+            sqlx::query(
+                "INSERT INTO characters (id, campaign_id, player_id, name, race, class, level, hp_current, hp_max, ac, speed, stats, inventory, spells, features, created_at, updated_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, 3, $7, $7, $8, 30, '{\"str\": 14, \"dex\": 16, \"con\": 14}', '[]', '[]', '[]', NOW(), NOW())"
+            )
+            .bind(Uuid::new_v4())
+            .bind(campaign_id)
+            .bind(user_id)
+            .bind(name)
+            .bind(race)
+            .bind(class)
+            .bind(hp)
+            .bind(ac)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+    .await;
+    if let Err(e) = created {
+        return db_error(e, "Failed to create demo");
+    }
+
+    match crate::refresh::issue_tokens(&pool, user_id).await {
+        Ok(tokens) => (
+            StatusCode::CREATED,
+            Json(DemoResponse { tokens, user_id, campaign_id, session_id, expires_at }),
+        )
+            .into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate token").into_response(),
+    }
+}
+
+// Run by the maintenance task. Tokens are revoked and sockets closed first, so nobody is left
+// using a sandbox as it disappears; deleting the campaigns takes their sessions, characters
+// and content with them.
+pub async fn delete_expired_demos(pool: &PgPool, session_state: &SessionState) {
+    let expired = sqlx::query_scalar::<_, Uuid>("SELECT id FROM users WHERE demo_expires_at <= NOW()")
+        .fetch_all(pool)
+        .await;
+    let expired = match expired {
+        Ok(expired) => expired,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to find expired demos");
+            return;
+        }
+    };
+    for user_id in expired {
+        let deleted = async {
+            revoke_user(pool, session_state, user_id).await?;
+            let mut tx = pool.begin().await?;
+            sqlx::query("DELETE FROM campaigns WHERE dm_id = $1").bind(user_id).execute(&mut *tx).await?;
+            sqlx::query("DELETE FROM users WHERE id = $1").bind(user_id).execute(&mut *tx).await?;
+            tx.commit().await
+        }
+        .await;
+        // Left for the next sweep
+        if let Err(e) = deleted {
+            tracing::error!(error = %e, %user_id, "Failed to delete demo account");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addresses_get_a_few_demos_per_window() {
+        let limiter = AddressLimiter::default();
+        let (address, other) = (IpAddr::from([203, 0, 113, 7]), IpAddr::from([203, 0, 113, 8]));
+        let start = Instant::now();
+        for _ in 0..PER_ADDRESS_LIMIT {
+            assert!(limiter.try_acquire(address, start));
+        }
+        assert!(!limiter.try_acquire(address, start));
+        assert!(limiter.try_acquire(other, start));
+        assert!(limiter.try_acquire(address, start + PER_ADDRESS_WINDOW));
+    }
+}
@@ -0,0 +1,472 @@
+use rand::rngs::OsRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+
+// Dice expressions: terms joined by + and -, each a constant or NdM with optional
+// modifiers. `!` explodes (a die showing its maximum is rolled again and added),
+// `khN`/`klN` keep the highest/lowest N, `dhN`/`dlN` drop them (`kN` and `dN` mean
+// kh and dl). Examples: 4d6kh3, 2d20kl1, 1d8+1d6+4, 1d20-1, 3d6!.
+
+// Limits that keep one expression from tying up the server
+const MAX_TERMS: usize = 20;
+const MAX_DICE: u32 = 100;
+const MAX_SIDES: u32 = 1000;
+const MAX_CONSTANT: u32 = 100_000;
+// Extra dice one exploding term may add
+const MAX_EXPLOSIONS: usize = 100;
+
+// Rolls are drawn from ChaCha20 seeded with a 64-bit seed. The algorithm is pinned (unlike
+// StdRng, which may change between rand releases) because clients replay the seed.
+pub type DiceRng = ChaCha20Rng;
+
+// A fresh seed for a live roll, straight from the operating system's CSPRNG
+pub fn fresh_seed() -> u64 {
+    OsRng.next_u64()
+}
+
+// Seed for the `counter`th roll of a seeded session: the first word of the session seed's
+// ChaCha20 stream number `counter`, so any roll can be recomputed on its own
+pub fn session_roll_seed(session_seed: u64, counter: u64) -> u64 {
+    let mut rng = DiceRng::seed_from_u64(session_seed);
+    rng.set_stream(counter);
+    rng.next_u64()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    KeepHighest(u32),
+    KeepLowest(u32),
+    DropHighest(u32),
+    DropLowest(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TermKind {
+    Dice { count: u32, sides: u32, explode: bool, selection: Option<Selection> },
+    Constant(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    pub negative: bool,
+    pub kind: TermKind,
+}
+
+impl std::fmt::Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.negative {
+            write!(f, "-")?;
+        }
+        match &self.kind {
+            TermKind::Constant(value) => write!(f, "{}", value),
+            TermKind::Dice { count, sides, explode, selection } => {
+                write!(f, "{}d{}", count, sides)?;
+                if *explode {
+                    write!(f, "!")?;
+                }
+                match selection {
+                    Some(Selection::KeepHighest(n)) => write!(f, "kh{}", n),
+                    Some(Selection::KeepLowest(n)) => write!(f, "kl{}", n),
+                    Some(Selection::DropHighest(n)) => write!(f, "dh{}", n),
+                    Some(Selection::DropLowest(n)) => write!(f, "dl{}", n),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+struct Cursor<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Cursor<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.chars.next();
+            return true;
+        }
+        false
+    }
+
+    fn number(&mut self) -> Result<Option<u32>, String> {
+        let mut digits = String::new();
+        while let Some(c) = self.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(c);
+            self.chars.next();
+        }
+        if digits.is_empty() {
+            return Ok(None);
+        }
+        digits.parse().map(Some).map_err(|_| format!("Number too large: {}", digits))
+    }
+
+    fn required_number(&mut self, after: &str) -> Result<u32, String> {
+        self.number()?.ok_or_else(|| format!("Expected a number after '{}'", after))
+    }
+}
+
+pub fn parse(expression: &str) -> Result<Vec<Term>, String> {
+    let text: String = expression.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_lowercase();
+    if text.is_empty() {
+        return Err("Empty dice expression. Use a format like '2d6+3'".to_string());
+    }
+
+    let mut cursor = Cursor { chars: text.chars().peekable() };
+    let mut terms = Vec::new();
+    let mut total_dice = 0;
+    loop {
+        let negative = if cursor.eat('-') {
+            true
+        } else if cursor.eat('+') || terms.is_empty() {
+            false
+        } else {
+            return Err(format!("Unexpected '{}' in dice expression", cursor.peek().unwrap_or_default()));
+        };
+
+        let number = cursor.number()?;
+        let kind = if cursor.eat('d') {
+            let count = number.unwrap_or(1);
+            let sides = cursor.required_number("d")?;
+            let mut explode = false;
+            let mut selection = None;
+            loop {
+                if cursor.eat('!') && !explode {
+                    explode = true;
+                } else if selection.is_none() && cursor.eat('k') {
+                    selection = Some(if cursor.eat('l') {
+                        Selection::KeepLowest(cursor.required_number("kl")?)
+                    } else {
+                        cursor.eat('h');
+                        Selection::KeepHighest(cursor.required_number("kh")?)
+                    });
+                } else if selection.is_none() && cursor.eat('d') {
+                    selection = Some(if cursor.eat('h') {
+                        Selection::DropHighest(cursor.required_number("dh")?)
+                    } else {
+                        cursor.eat('l');
+                        Selection::DropLowest(cursor.required_number("dl")?)
+                    });
+                } else {
+                    break;
+                }
+            }
+
+            if count == 0 || sides == 0 {
+                return Err("Dice count and sides must be positive".to_string());
+            }
+            if sides > MAX_SIDES {
+                return Err(format!("Dice can have at most {} sides", MAX_SIDES));
+            }
+            if explode && sides == 1 {
+                return Err("A d1 can't explode".to_string());
+            }
+            if let Some(Selection::KeepHighest(n) | Selection::KeepLowest(n) | Selection::DropHighest(n) | Selection::DropLowest(n)) = selection {
+                if n > count {
+                    return Err(format!("Can't keep or drop {} of {} dice", n, count));
+                }
+            }
+            total_dice += count;
+            if total_dice > MAX_DICE {
+                return Err(format!("At most {} dice per roll", MAX_DICE));
+            }
+            TermKind::Dice { count, sides, explode, selection }
+        } else {
+            match number {
+                Some(value) if value <= MAX_CONSTANT => TermKind::Constant(value),
+                Some(_) => return Err(format!("Modifiers can be at most {}", MAX_CONSTANT)),
+                None => {
+                    return Err(match cursor.peek() {
+                        Some(c) => format!("Unexpected '{}' in dice expression", c),
+                        None => "Dice expression ends with an operator".to_string(),
+                    })
+                }
+            }
+        };
+
+        terms.push(Term { negative, kind });
+        if terms.len() > MAX_TERMS {
+            return Err(format!("At most {} terms per roll", MAX_TERMS));
+        }
+        if cursor.peek().is_none() {
+            return Ok(terms);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DieFace {
+    // Unique within the roll, e.g. "d20-0"
+    pub id: String,
+    pub sides: i32,
+    pub value: i32,
+    // Set on dice thrown away by keep/drop, such as the lower d20 of an advantage roll
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dropped: bool,
+}
+
+// How one term of the expression came out
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TermResult {
+    // The term as parsed, e.g. "4d6kh3" or "-1"
+    pub term: String,
+    // Every die rolled for the term, extra exploding dice included; empty for constants
+    pub rolls: Vec<i32>,
+    // Indexes into `rolls` of dice that don't count
+    pub dropped: Vec<usize>,
+    // What the term adds to the total, negative for subtracted terms
+    pub subtotal: i32,
+}
+
+pub struct DiceRoll {
+    pub total: i32,
+    // Every die rolled, across all terms and in roll order
+    pub rolls: Vec<i32>,
+    pub faces: Vec<DieFace>,
+    pub terms: Vec<TermResult>,
+}
+
+// Indexes of the dice a selection throws away
+fn dropped_dice(rolls: &[i32], selection: Option<Selection>) -> Vec<usize> {
+    let Some(selection) = selection else {
+        return Vec::new();
+    };
+    // Lowest first; ties keep roll order
+    let mut order: Vec<usize> = (0..rolls.len()).collect();
+    order.sort_by_key(|&i| rolls[i]);
+    let n = rolls.len();
+    let mut dropped: Vec<usize> = match selection {
+        Selection::KeepHighest(k) => order[..n.saturating_sub(k as usize)].to_vec(),
+        Selection::KeepLowest(k) => order[(k as usize).min(n)..].to_vec(),
+        Selection::DropHighest(d) => order[n.saturating_sub(d as usize)..].to_vec(),
+        Selection::DropLowest(d) => order[..(d as usize).min(n)].to_vec(),
+    };
+    dropped.sort_unstable();
+    dropped
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Advantage {
+    Advantage,
+    Disadvantage,
+}
+
+// Rolls the expression's d20 twice: the first plain 1d20 becomes 2d20kh1, or 2d20kl1 for
+// disadvantage. "1d20+5" with advantage is "2d20kh1+5".
+pub fn with_advantage(expression: &str, advantage: Advantage) -> Result<String, String> {
+    let mut terms = parse(expression)?;
+    let plain_d20 = TermKind::Dice { count: 1, sides: 20, explode: false, selection: None };
+    let d20 = terms
+        .iter_mut()
+        .find(|term| !term.negative && term.kind == plain_d20)
+        .ok_or_else(|| "Advantage needs a 1d20 in the expression".to_string())?;
+    let selection = match advantage {
+        Advantage::Advantage => Selection::KeepHighest(1),
+        Advantage::Disadvantage => Selection::KeepLowest(1),
+    };
+    d20.kind = TermKind::Dice { count: 2, sides: 20, explode: false, selection: Some(selection) };
+    Ok(write_terms(&terms))
+}
+
+// Damage for a critical hit: every dice term rolls twice as many dice, modifiers stay as
+// they are. "1d8+2d6+3" becomes "2d8+4d6+3"; keep and drop counts double along with the dice.
+pub fn critical_damage(expression: &str) -> Result<String, String> {
+    let mut terms = parse(expression)?;
+    for term in &mut terms {
+        if let TermKind::Dice { count, selection, .. } = &mut term.kind {
+            *count *= 2;
+            *selection = selection.map(|selection| match selection {
+                Selection::KeepHighest(n) => Selection::KeepHighest(n * 2),
+                Selection::KeepLowest(n) => Selection::KeepLowest(n * 2),
+                Selection::DropHighest(n) => Selection::DropHighest(n * 2),
+                Selection::DropLowest(n) => Selection::DropLowest(n * 2),
+            });
+        }
+    }
+    Ok(write_terms(&terms))
+}
+
+fn write_terms(terms: &[Term]) -> String {
+    let mut written = String::new();
+    for (i, term) in terms.iter().enumerate() {
+        if i > 0 && !term.negative {
+            written.push('+');
+        }
+        written.push_str(&term.to_string());
+    }
+    written
+}
+
+pub fn roll_dice(dice: &str, seed: u64) -> Result<DiceRoll, String> {
+    roll_with(dice, &mut DiceRng::seed_from_u64(seed))
+}
+
+// Rolls with any RNG, so tests can inject a deterministic one
+pub fn roll_with<R: Rng + ?Sized>(dice: &str, rng: &mut R) -> Result<DiceRoll, String> {
+    let terms = parse(dice)?;
+    let mut total = 0;
+    let mut rolls = Vec::new();
+    let mut faces = Vec::new();
+    let mut results = Vec::new();
+
+    for term in &terms {
+        let sign = if term.negative { -1 } else { 1 };
+        let result = match term.kind {
+            TermKind::Constant(value) => TermResult {
+                term: term.to_string(),
+                rolls: Vec::new(),
+                dropped: Vec::new(),
+                subtotal: sign * value as i32,
+            },
+            TermKind::Dice { count, sides, explode, selection } => {
+                let mut term_rolls = Vec::new();
+                let first_face = faces.len();
+                let mut pending = count as usize;
+                while pending > 0 {
+                    pending -= 1;
+                    // Uniform over 1..=sides; a plain modulo would favour the low faces
+                    let roll = rng.gen_range(1..=sides) as i32;
+                    faces.push(DieFace { id: format!("d{}-{}", sides, faces.len()), sides: sides as i32, value: roll, dropped: false });
+                    term_rolls.push(roll);
+                    if explode && roll == sides as i32 && term_rolls.len() < count as usize + MAX_EXPLOSIONS {
+                        pending += 1;
+                    }
+                }
+                let dropped = dropped_dice(&term_rolls, selection);
+                for &i in &dropped {
+                    faces[first_face + i].dropped = true;
+                }
+                let kept: i32 = term_rolls.iter().enumerate().filter(|(i, _)| !dropped.contains(i)).map(|(_, roll)| roll).sum();
+                rolls.extend_from_slice(&term_rolls);
+                TermResult { term: term.to_string(), rolls: term_rolls, dropped, subtotal: sign * kept }
+            }
+        };
+        total += result.subtotal;
+        results.push(result);
+    }
+
+    Ok(DiceRoll { total, rolls, faces, terms: results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dice_rolls_replay_from_seed() {
+        let first = roll_dice("3d6+2", 42).unwrap();
+        let again = roll_dice("3d6+2", 42).unwrap();
+        assert_eq!(first.rolls, again.rolls);
+        assert_eq!(first.total, first.rolls.iter().sum::<i32>() + 2);
+
+        let ids: Vec<&str> = first.faces.iter().map(|face| face.id.as_str()).collect();
+        assert_eq!(ids, vec!["d6-0", "d6-1", "d6-2"]);
+        assert_eq!(first.faces.iter().map(|face| face.value).collect::<Vec<_>>(), first.rolls);
+        assert!(first.faces.iter().all(|face| face.sides == 6));
+    }
+
+    #[test]
+    fn test_session_roll_seeds_are_stable_per_counter() {
+        assert_eq!(session_roll_seed(42, 1), session_roll_seed(42, 1));
+        assert_ne!(session_roll_seed(42, 1), session_roll_seed(42, 2));
+        assert_ne!(session_roll_seed(42, 1), session_roll_seed(43, 1));
+    }
+
+    #[test]
+    fn test_parse_expressions() {
+        let terms = parse("4d6kh3").unwrap();
+        assert_eq!(terms[0].kind, TermKind::Dice { count: 4, sides: 6, explode: false, selection: Some(Selection::KeepHighest(3)) });
+
+        let terms = parse("1d8 + 1d6 - 1").unwrap();
+        assert_eq!(terms.len(), 3);
+        assert!(terms[2].negative);
+        assert_eq!(terms[2].kind, TermKind::Constant(1));
+
+        // Shorthands are normalized
+        let written: Vec<String> = parse("d20+2D20K1+4d6d1+3d6!").unwrap().iter().map(Term::to_string).collect();
+        assert_eq!(written, vec!["1d20", "2d20kh1", "4d6dl1", "3d6!"]);
+
+        for bad in ["", "2d", "d", "2d6+", "2x6", "0d6", "3d6kh4", "1d1!", "101d6", "1d6kh"] {
+            assert!(parse(bad).is_err(), "{} should not parse", bad);
+        }
+    }
+
+    #[test]
+    fn test_keep_drop_and_explode() {
+        let roll = roll_dice("4d6kh3", 7).unwrap();
+        let term = &roll.terms[0];
+        assert_eq!(term.rolls.len(), 4);
+        assert_eq!(term.dropped.len(), 1);
+        let lowest = *term.rolls.iter().min().unwrap();
+        assert_eq!(term.rolls[term.dropped[0]], lowest);
+        assert_eq!(roll.total, term.rolls.iter().sum::<i32>() - lowest);
+
+        let roll = roll_dice("1d20-1d4-2", 7).unwrap();
+        assert_eq!(roll.total, roll.terms[0].subtotal + roll.terms[1].subtotal - 2);
+        assert!(roll.terms[1].subtotal < 0);
+        assert_eq!(roll.faces.len(), 2);
+
+        // Every max roll on an exploding die adds another die
+        for seed in 0..50 {
+            let roll = roll_dice("2d4!", seed).unwrap();
+            let maxes = roll.rolls.iter().filter(|r| **r == 4).count();
+            assert_eq!(roll.rolls.len(), 2 + maxes);
+        }
+    }
+
+    #[test]
+    fn test_injected_rng_and_uniform_faces() {
+        // An RNG that always yields zero lands every die on 1
+        let roll = roll_with("4d6kh3+1d20", &mut rand::rngs::mock::StepRng::new(0, 0)).unwrap();
+        assert_eq!(roll.rolls, vec![1, 1, 1, 1, 1]);
+        assert_eq!(roll.total, 4);
+
+        let mut rng = DiceRng::seed_from_u64(2024);
+        let mut counts = [0u32; 6];
+        for _ in 0..600 {
+            let roll = roll_with("100d6", &mut rng).unwrap();
+            for value in roll.rolls {
+                counts[value as usize - 1] += 1;
+            }
+        }
+        // 60000 rolls: each face should come up about 10000 times
+        for (face, count) in counts.iter().enumerate() {
+            assert!((9500..=10500).contains(count), "face {} came up {} times", face + 1, count);
+        }
+    }
+
+    #[test]
+    fn test_advantage_rolls_the_d20_twice() {
+        assert_eq!(with_advantage("1d20+5", Advantage::Advantage).unwrap(), "2d20kh1+5");
+        assert_eq!(with_advantage("d20 - 1 + 1d4", Advantage::Disadvantage).unwrap(), "2d20kl1-1+1d4");
+        assert_eq!(with_advantage("1d6+1d20", Advantage::Advantage).unwrap(), "1d6+2d20kh1");
+        assert!(with_advantage("2d6+3", Advantage::Advantage).is_err());
+        assert!(with_advantage("2d20kh1", Advantage::Advantage).is_err());
+
+        for seed in 0..50 {
+            let roll = roll_dice(&with_advantage("1d20+2", Advantage::Advantage).unwrap(), seed).unwrap();
+            let kept = roll.faces.iter().find(|face| !face.dropped).unwrap();
+            let dropped = roll.faces.iter().find(|face| face.dropped).unwrap();
+            assert!(kept.value >= dropped.value);
+            assert_eq!(roll.total, kept.value + 2);
+        }
+        let roll = roll_dice(&with_advantage("1d20", Advantage::Disadvantage).unwrap(), 3).unwrap();
+        assert_eq!(roll.total, roll.rolls.iter().copied().min().unwrap());
+        assert_eq!(roll.faces.iter().filter(|face| face.dropped).count(), 1);
+    }
+
+    #[test]
+    fn test_critical_damage_doubles_the_dice() {
+        assert_eq!(critical_damage("1d8+2d6+3").unwrap(), "2d8+4d6+3");
+        assert_eq!(critical_damage("d12 - 1").unwrap(), "2d12-1");
+        assert_eq!(critical_damage("2d6kh1!").unwrap(), "4d6!kh2");
+        assert_eq!(critical_damage("5").unwrap(), "5");
+        assert!(critical_damage("1d6x").is_err());
+    }
+}
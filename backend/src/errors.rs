@@ -0,0 +1,67 @@
+use axum::{http::StatusCode, response::{IntoResponse, Response}};
+use sqlx::error::ErrorKind;
+
+// Turns database failures into HTTP responses. The underlying error is logged (inside the
+// request span, so method, path and user are attached) and never sent to the client.
+
+// Postgres insufficient_privilege, raised by row-level security and missing grants
+const INSUFFICIENT_PRIVILEGE: &str = "42501";
+
+fn status_for(e: &sqlx::Error) -> StatusCode {
+    match e {
+        sqlx::Error::RowNotFound => StatusCode::NOT_FOUND,
+        sqlx::Error::PoolTimedOut => StatusCode::SERVICE_UNAVAILABLE,
+        sqlx::Error::Database(db) => match db.kind() {
+            ErrorKind::UniqueViolation => StatusCode::CONFLICT,
+            ErrorKind::ForeignKeyViolation | ErrorKind::NotNullViolation | ErrorKind::CheckViolation => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            _ if db.code().as_deref() == Some(INSUFFICIENT_PRIVILEGE) => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        },
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn client_message(status: StatusCode, message: &str) -> String {
+    match status {
+        StatusCode::NOT_FOUND => format!("{}: not found", message),
+        StatusCode::CONFLICT => format!("{}: already exists", message),
+        StatusCode::UNPROCESSABLE_ENTITY => format!("{}: invalid or missing reference", message),
+        StatusCode::FORBIDDEN => format!("{}: permission denied", message),
+        StatusCode::SERVICE_UNAVAILABLE => format!("{}: database busy, try again", message),
+        _ => message.to_string(),
+    }
+}
+
+// `message` says what failed ("Failed to create campaign") and is what the client sees
+pub fn db_error(e: sqlx::Error, message: &str) -> Response {
+    let status = status_for(&e);
+    if status.is_server_error() {
+        tracing::error!(error = %e, status = status.as_u16(), "{}", message);
+    } else {
+        tracing::warn!(error = %e, status = status.as_u16(), "{}", message);
+    }
+    (status, client_message(status, message)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_rows_and_timeouts() {
+        let response = db_error(sqlx::Error::RowNotFound, "Failed to fetch campaign");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(db_error(sqlx::Error::PoolTimedOut, "Failed to fetch campaign").status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(db_error(sqlx::Error::PoolClosed, "Failed to fetch campaign").status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_raw_error_is_not_leaked() {
+        let e = sqlx::Error::Protocol("SELECT secret FROM users".to_string());
+        let response = db_error(e, "Failed to fetch users");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "Failed to fetch users");
+    }
+}
@@ -0,0 +1,408 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+use crate::models::{Campaign, JoinRequest};
+use crate::notifications::notify;
+use crate::outbox::enqueue_campaign_webhook;
+use crate::socket::{broadcast_to_session, ActionNumbers, DeclaredAction, DiceResult, Recipients, ServerMessage, SessionState};
+
+// Domain event bus. Handlers and the socket layer describe what happened as a DomainEvent
+// and publish it; the subscribers below turn it into the event log entry, the WebSocket
+// broadcast, the campaign webhook, the notification and the AI's view of the table.
+//
+// Publishing has two halves. `record` runs the transactional subscribers with the
+// publisher's connection, so their writes commit or roll back with the change itself;
+// `deliver` runs the in-memory ones once it has committed. `publish` does both for
+// publishers without a transaction of their own.
+
+#[derive(Debug, Clone)]
+pub enum DomainEvent {
+    // `rolls` are the message's inline [[dice]] rolls
+    ChatPosted {
+        message_id: Uuid,
+        session_id: Uuid,
+        player_id: Uuid,
+        username: String,
+        message: String,
+        rolls: Vec<DiceResult>,
+        at: DateTime<Utc>,
+    },
+    ActionDeclared {
+        event_id: Uuid,
+        session_id: Uuid,
+        player_id: Uuid,
+        character_id: Uuid,
+        character_name: String,
+        action: DeclaredAction,
+        numbers: ActionNumbers,
+        at: DateTime<Utc>,
+    },
+    PlayerKicked {
+        event_id: Uuid,
+        session_id: Uuid,
+        kicked_by: Uuid,
+        user_id: Uuid,
+        banned: bool,
+        at: DateTime<Utc>,
+    },
+    // Free-form entry added through POST /event-logs or CreateEventLog
+    EventLogged {
+        event_id: Uuid,
+        session_id: Uuid,
+        created_by: Uuid,
+        event_type: String,
+        event_data: serde_json::Value,
+        at: DateTime<Utc>,
+    },
+    JoinRequestReceived { campaign: Campaign, request: JoinRequest, username: String },
+    // `request.status` is the decision: approved, waitlisted or denied
+    JoinRequestReviewed { campaign: Campaign, request: JoinRequest, message: Option<String> },
+    WaitlistPromoted { campaign: Campaign, request: JoinRequest },
+    // `campaign` is as it is after the handover; logged to and broadcast in its latest session
+    OwnershipTransferred {
+        event_id: Uuid,
+        session_id: Option<Uuid>,
+        campaign: Campaign,
+        previous_dm_id: Uuid,
+        at: DateTime<Utc>,
+    },
+}
+
+impl DomainEvent {
+    // What the session's WebSocket clients are sent, if anything
+    pub fn broadcast(&self) -> Option<(Uuid, ServerMessage)> {
+        match self {
+            DomainEvent::ChatPosted { message_id, session_id, player_id, message, rolls, at, .. } => Some((*session_id, ServerMessage::ChatMessage {
+                message_id: *message_id,
+                player_id: *player_id,
+                message: message.clone(),
+                rolls: rolls.clone(),
+                timestamp: *at,
+            })),
+            DomainEvent::ActionDeclared { event_id, session_id, player_id, character_id, character_name, action, numbers, at } => {
+                Some((*session_id, ServerMessage::ActionDeclared {
+                    event_id: *event_id,
+                    player_id: *player_id,
+                    character_id: *character_id,
+                    character_name: character_name.clone(),
+                    action: action.clone(),
+                    numbers: numbers.clone(),
+                    created_at: *at,
+                }))
+            }
+            DomainEvent::PlayerKicked { session_id, user_id, .. } => Some((*session_id, ServerMessage::PlayerLeft { player_id: *user_id })),
+            DomainEvent::EventLogged { event_id, session_id, created_by, event_type, event_data, at } => Some((*session_id, ServerMessage::EventLogCreated {
+                event_id: *event_id,
+                event_type: event_type.clone(),
+                event_data: event_data.clone(),
+                created_by: *created_by,
+                created_at: *at,
+            })),
+            DomainEvent::OwnershipTransferred { session_id, campaign, previous_dm_id, .. } => session_id.map(|session_id| (session_id, ServerMessage::CampaignOwnerChanged {
+                campaign_id: campaign.id,
+                previous_dm_id: *previous_dm_id,
+                dm_id: campaign.dm_id,
+            })),
+            DomainEvent::JoinRequestReceived { .. } | DomainEvent::JoinRequestReviewed { .. } | DomainEvent::WaitlistPromoted { .. } => None,
+        }
+    }
+}
+
+// Runs the transactional subscribers with the publisher's connection
+pub async fn record(conn: &mut PgConnection, event: &DomainEvent) -> Result<(), sqlx::Error> {
+    for subscriber in SUBSCRIBERS {
+        subscriber.record(&mut *conn, event).await?;
+    }
+    Ok(())
+}
+
+// Runs the in-memory subscribers; call once the recorded change has committed
+pub async fn deliver(session_state: &SessionState, event: &DomainEvent) {
+    for subscriber in SUBSCRIBERS {
+        subscriber.deliver(session_state, event).await;
+    }
+}
+
+pub async fn publish(pool: &PgPool, session_state: &SessionState, event: &DomainEvent) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    record(&mut tx, event).await?;
+    tx.commit().await?;
+    deliver(session_state, event).await;
+    Ok(())
+}
+
+#[async_trait]
+trait Subscriber: Sync {
+    async fn record(&self, _conn: &mut PgConnection, _event: &DomainEvent) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    async fn deliver(&self, _session_state: &SessionState, _event: &DomainEvent) {}
+}
+
+// In order: the event log goes first so a failure there stops the webhook and notification
+const SUBSCRIBERS: &[&dyn Subscriber] = &[&EventLogWriter, &Webhooks, &Notifications, &Broadcaster, &AiContextBuilder];
+
+struct EventLogWriter;
+
+#[async_trait]
+impl Subscriber for EventLogWriter {
+    async fn record(&self, conn: &mut PgConnection, event: &DomainEvent) -> Result<(), sqlx::Error> {
+        let (id, session_id, event_type, event_data, created_by, at) = match event {
+            DomainEvent::ChatPosted { message_id, session_id, player_id, message, rolls, at, .. } => {
+                // Reactions are added to the entry later
+                let data = serde_json::json!({ "message": message, "rolls": rolls, "reactions": {} });
+                (*message_id, *session_id, "chat_message", data, *player_id, *at)
+            }
+            DomainEvent::ActionDeclared { event_id, session_id, player_id, character_id, character_name, action, numbers, at } => {
+                let data = serde_json::json!({
+                    "character_id": character_id,
+                    "character_name": character_name,
+                    "action": action,
+                    "numbers": numbers,
+                });
+                (*event_id, *session_id, "player_action", data, *player_id, *at)
+            }
+            DomainEvent::PlayerKicked { event_id, session_id, kicked_by, user_id, banned, at } => {
+                let data = serde_json::json!({ "user_id": user_id, "banned": banned });
+                (*event_id, *session_id, "player_kicked", data, *kicked_by, *at)
+            }
+            DomainEvent::EventLogged { event_id, session_id, created_by, event_type, event_data, at } => {
+                (*event_id, *session_id, event_type.as_str(), event_data.clone(), *created_by, *at)
+            }
+            DomainEvent::OwnershipTransferred { event_id, session_id: Some(session_id), campaign, previous_dm_id, at } => {
+                let data = serde_json::json!({ "campaign_id": campaign.id, "previous_dm_id": previous_dm_id, "dm_id": campaign.dm_id });
+                (*event_id, *session_id, "ownership_transferred", data, campaign.dm_id, *at)
+            }
+            DomainEvent::OwnershipTransferred { session_id: None, .. } => return Ok(()),
+            DomainEvent::JoinRequestReceived { .. } | DomainEvent::JoinRequestReviewed { .. } | DomainEvent::WaitlistPromoted { .. } => return Ok(()),
+        };
+
+        sqlx::query(
+            "INSERT INTO event_logs (id, session_id, event_type, event_data, created_by, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)"
+        )
+        .bind(id)
+        .bind(session_id)
+        .bind(event_type)
+        .bind(event_data)
+        .bind(created_by)
+        .bind(at)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+struct Webhooks;
+
+#[async_trait]
+impl Subscriber for Webhooks {
+    async fn record(&self, conn: &mut PgConnection, event: &DomainEvent) -> Result<(), sqlx::Error> {
+        match event {
+            DomainEvent::JoinRequestReceived { campaign, request, username } => {
+                enqueue_campaign_webhook(conn, campaign, "join_request_received", join_request_data(campaign, request, username)).await
+            }
+            DomainEvent::JoinRequestReviewed { campaign, request, .. } => {
+                let kind = format!("join_request_{}", request.status);
+                enqueue_campaign_webhook(conn, campaign, &kind, serde_json::json!({
+                    "request_id": request.id,
+                    "user_id": request.user_id,
+                })).await
+            }
+            DomainEvent::WaitlistPromoted { campaign, request } => {
+                enqueue_campaign_webhook(conn, campaign, "waitlist_promoted", serde_json::json!({
+                    "request_id": request.id,
+                    "user_id": request.user_id,
+                })).await
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Notifications;
+
+#[async_trait]
+impl Subscriber for Notifications {
+    async fn record(&self, conn: &mut PgConnection, event: &DomainEvent) -> Result<(), sqlx::Error> {
+        match event {
+            DomainEvent::JoinRequestReceived { campaign, request, username } => {
+                notify(conn, campaign.dm_id, "join_request_received", join_request_data(campaign, request, username)).await
+            }
+            DomainEvent::JoinRequestReviewed { campaign, request, message } => {
+                let kind = format!("join_request_{}", request.status);
+                notify(conn, request.user_id, &kind, serde_json::json!({
+                    "request_id": request.id,
+                    "campaign_id": campaign.id,
+                    "campaign_name": campaign.name,
+                    "message": message,
+                })).await
+            }
+            DomainEvent::WaitlistPromoted { campaign, request } => {
+                notify(conn, request.user_id, "waitlist_promoted", serde_json::json!({
+                    "request_id": request.id,
+                    "campaign_id": campaign.id,
+                    "campaign_name": campaign.name,
+                })).await
+            }
+            DomainEvent::OwnershipTransferred { campaign, previous_dm_id, .. } => {
+                notify(conn, *previous_dm_id, "campaign_transfer_accepted", serde_json::json!({
+                    "campaign_id": campaign.id,
+                    "campaign_name": campaign.name,
+                    "dm_id": campaign.dm_id,
+                })).await
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// The DM gets the same details from the webhook and the notification
+fn join_request_data(campaign: &Campaign, request: &JoinRequest, username: &str) -> serde_json::Value {
+    serde_json::json!({
+        "request_id": request.id,
+        "campaign_id": campaign.id,
+        "campaign_name": campaign.name,
+        "user_id": request.user_id,
+        "username": username,
+        "message": request.message,
+    })
+}
+
+struct Broadcaster;
+
+#[async_trait]
+impl Subscriber for Broadcaster {
+    async fn deliver(&self, session_state: &SessionState, event: &DomainEvent) {
+        if let Some((session_id, message)) = event.broadcast() {
+            broadcast_to_session(session_state, session_id, &message, Recipients::All).await;
+        }
+    }
+}
+
+// Lines of table talk kept per live session for AI prompts
+pub const AI_CONTEXT_LINES: usize = 30;
+
+// Longest line kept, in characters
+const AI_CONTEXT_LINE_LEN: usize = 300;
+
+struct AiContextBuilder;
+
+#[async_trait]
+impl Subscriber for AiContextBuilder {
+    async fn deliver(&self, session_state: &SessionState, event: &DomainEvent) {
+        let (session_id, line) = match event {
+            DomainEvent::ChatPosted { session_id, username, message, .. } => (*session_id, format!("{}: {}", username, message)),
+            DomainEvent::ActionDeclared { session_id, character_name, action, .. } => {
+                (*session_id, format!("{} declares: {}", character_name, describe_action(action)))
+            }
+            DomainEvent::EventLogged { session_id, event_type, event_data, .. } => (*session_id, format!("[{}] {}", event_type, event_data)),
+            _ => return,
+        };
+        let sessions = session_state.sessions.read().await;
+        if let Some(session_info) = sessions.get(&session_id) {
+            let mut ai_context = session_info.ai_context.write().await;
+            ai_context.push_back(line.chars().take(AI_CONTEXT_LINE_LEN).collect());
+            while ai_context.len() > AI_CONTEXT_LINES {
+                ai_context.pop_front();
+            }
+        }
+    }
+}
+
+fn describe_action(action: &DeclaredAction) -> String {
+    match action {
+        DeclaredAction::Attack { weapon, .. } => match weapon {
+            Some(weapon) => format!("attacks with {}", weapon),
+            None => "attacks".to_string(),
+        },
+        DeclaredAction::Cast { spell, .. } => format!("casts {}", spell),
+        DeclaredAction::Move { distance, destination } => match destination {
+            Some(destination) => format!("moves {} ft to {}", distance, destination),
+            None => format!("moves {} ft", distance),
+        },
+        DeclaredAction::UseItem { item, .. } => format!("uses {}", item),
+        DeclaredAction::Custom { description } => description.clone(),
+    }
+}
+
+// Recent table talk for an AI prompt, oldest first; empty for sessions not in memory
+pub async fn ai_context(session_state: &SessionState, session_id: Uuid) -> Vec<String> {
+    let sessions = session_state.sessions.read().await;
+    match sessions.get(&session_id) {
+        Some(session_info) => session_info.ai_context.read().await.iter().cloned().collect(),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_session_events_are_broadcast() {
+        let session_id = Uuid::new_v4();
+        let kicked = DomainEvent::PlayerKicked {
+            event_id: Uuid::new_v4(),
+            session_id,
+            kicked_by: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            banned: true,
+            at: Utc::now(),
+        };
+        assert!(matches!(kicked.broadcast(), Some((id, ServerMessage::PlayerLeft { .. })) if id == session_id));
+
+        let campaign = Campaign {
+            id: Uuid::new_v4(),
+            name: "Lost Mine".to_string(),
+            description: None,
+            dm_id: Uuid::new_v4(),
+            settings: serde_json::json!({}),
+            archived_at: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let request = JoinRequest {
+            id: Uuid::new_v4(),
+            campaign_id: campaign.id,
+            user_id: Uuid::new_v4(),
+            message: None,
+            status: "waitlisted".to_string(),
+            response_message: None,
+            reviewed_by: None,
+            reviewed_at: None,
+            created_at: Utc::now(),
+        };
+        assert!(DomainEvent::WaitlistPromoted { campaign, request }.broadcast().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ai_context_keeps_the_latest_lines() {
+        let session_state = SessionState::new(None);
+        let session_id = Uuid::new_v4();
+        // Subscribing puts the session in memory, as a connection would
+        let _events = crate::socket::subscribe_events(&session_state, session_id, Uuid::new_v4(), None).await;
+
+        for i in 0..AI_CONTEXT_LINES + 5 {
+            let event = DomainEvent::ActionDeclared {
+                event_id: Uuid::new_v4(),
+                session_id,
+                player_id: Uuid::new_v4(),
+                character_id: Uuid::new_v4(),
+                character_name: "Wren".to_string(),
+                action: DeclaredAction::Move { distance: i as i32, destination: None },
+                numbers: ActionNumbers::default(),
+                at: Utc::now(),
+            };
+            deliver(&session_state, &event).await;
+        }
+
+        let lines = ai_context(&session_state, session_id).await;
+        assert_eq!(lines.len(), AI_CONTEXT_LINES);
+        assert_eq!(lines[0], "Wren declares: moves 5 ft");
+        assert_eq!(lines.last().unwrap(), &format!("Wren declares: moves {} ft", AI_CONTEXT_LINES + 4));
+        assert!(ai_context(&session_state, Uuid::new_v4()).await.is_empty());
+    }
+}
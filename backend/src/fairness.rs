@@ -0,0 +1,127 @@
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Provably fair rolls, by commit and reveal. Each session has a secret server seed, and its
+// SHA-256 commitment is public before any roll is drawn from it. A roll's seed is
+// HMAC-SHA256(server seed, "client_seed:nonce"), where the nonce counts the server seed's
+// rolls and the client seed is whatever the roller sent. The server seed is revealed when a
+// roll is verified, and later rolls move on to a fresh one, so it can't be changed to suit
+// results it has already produced.
+
+// Published with every roll drawn from a server seed
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RollProof {
+    // Hex SHA-256 of the server seed
+    pub commitment: String,
+    pub nonce: u64,
+    pub client_seed: String,
+}
+
+pub fn fresh_server_seed() -> Vec<u8> {
+    let mut seed = vec![0u8; 32];
+    OsRng.fill_bytes(&mut seed);
+    seed
+}
+
+// Hidden rolls get a client seed nobody else sees, so revealing the server seed doesn't
+// reveal them too
+pub fn hidden_client_seed() -> String {
+    let mut seed = [0u8; 16];
+    OsRng.fill_bytes(&mut seed);
+    hex::encode(seed)
+}
+
+pub fn commitment(server_seed: &[u8]) -> String {
+    hex::encode(Sha256::digest(server_seed))
+}
+
+pub fn roll_seed(server_seed: &[u8], client_seed: &str, nonce: u64) -> u64 {
+    let mut mac = Hmac::<Sha256>::new_from_slice(server_seed).expect("HMAC accepts any key length");
+    mac.update(format!("{}:{}", client_seed, nonce).as_bytes());
+    let digest = mac.finalize().into_bytes();
+    u64::from_be_bytes(digest[..8].try_into().expect("SHA-256 is longer than 8 bytes"))
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ServerSeed {
+    pub server_seed: Vec<u8>,
+    pub commitment: String,
+    pub next_nonce: i64,
+}
+
+// Takes `count` nonces from the session's current server seed, starting one if the last
+// was revealed. Returns the seed with `next_nonce` already past the reserved ones.
+pub async fn reserve_nonces(pool: &PgPool, session_id: Uuid, count: usize) -> Result<ServerSeed, sqlx::Error> {
+    loop {
+        let reserved = sqlx::query_as::<_, ServerSeed>(
+            "UPDATE roll_server_seeds SET next_nonce = next_nonce + $1 WHERE session_id = $2 AND revealed_at IS NULL
+             RETURNING server_seed, commitment, next_nonce"
+        )
+        .bind(count as i64)
+        .bind(session_id)
+        .fetch_optional(pool)
+        .await?;
+        if let Some(reserved) = reserved {
+            return Ok(reserved);
+        }
+
+        // Another roll may start the seed first, in which case take nonces from that one
+        let server_seed = fresh_server_seed();
+        let started = sqlx::query_as::<_, ServerSeed>(
+            "INSERT INTO roll_server_seeds (id, session_id, server_seed, commitment, next_nonce) VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (session_id) WHERE revealed_at IS NULL DO NOTHING
+             RETURNING server_seed, commitment, next_nonce"
+        )
+        .bind(Uuid::new_v4())
+        .bind(session_id)
+        .bind(&server_seed)
+        .bind(commitment(&server_seed))
+        .bind(count as i64)
+        .fetch_optional(pool)
+        .await?;
+        if let Some(started) = started {
+            return Ok(started);
+        }
+    }
+}
+
+// The commitment the session's next roll will be drawn from, so players can note it first
+pub async fn current_commitment(pool: &PgPool, session_id: Uuid) -> Result<ServerSeed, sqlx::Error> {
+    reserve_nonces(pool, session_id, 0).await
+}
+
+// Reveals the server seed behind `commitment`; the session's next roll starts a new one.
+// None if the commitment isn't one of the session's.
+pub async fn reveal(pool: &PgPool, session_id: Uuid, commitment: &str) -> Result<Option<Vec<u8>>, sqlx::Error> {
+    sqlx::query_scalar::<_, Vec<u8>>(
+        "UPDATE roll_server_seeds SET revealed_at = COALESCE(revealed_at, NOW())
+         WHERE session_id = $1 AND commitment = $2 RETURNING server_seed"
+    )
+    .bind(session_id)
+    .bind(commitment)
+    .fetch_optional(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roll_seed_depends_on_every_input() {
+        let server_seed = [7u8; 32];
+        let seed = roll_seed(&server_seed, "lucky", 3);
+        assert_eq!(seed, roll_seed(&server_seed, "lucky", 3));
+        assert_ne!(seed, roll_seed(&server_seed, "lucky", 4));
+        assert_ne!(seed, roll_seed(&server_seed, "unlucky", 3));
+        assert_ne!(seed, roll_seed(&[8u8; 32], "lucky", 3));
+
+        assert_eq!(commitment(&server_seed), hex::encode(Sha256::digest(server_seed)));
+        assert_ne!(commitment(&server_seed), commitment(&fresh_server_seed()));
+    }
+}
@@ -0,0 +1,285 @@
+use axum::{extract::Query, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::locations::{insert_location, LocationRequest};
+use crate::middleware::AuthUser;
+use crate::usage::{usage, Metric, Subject};
+
+// Offline generators for quick improv. Everything is built from the wordlists below so it
+// works without network or tokens; `enhance=true` asks the AI for flavor text on top.
+// Taverns and shops can be kept as campaign locations with `campaign_id` (see locations.rs).
+
+const CULTURES: &[&str] = &["human", "elf", "dwarf", "halfling", "orc"];
+
+const HUMAN_FIRST: &[&str] = &["Aldric", "Bran", "Cedric", "Elena", "Gwen", "Harlan", "Isolde", "Marta", "Osric", "Rowena", "Tobias", "Wren"];
+const HUMAN_LAST: &[&str] = &["Ashdown", "Blackwood", "Carver", "Fairweather", "Holt", "Marsh", "Thatcher", "Whitlock"];
+const ELF_FIRST: &[&str] = &["Aelar", "Caelynn", "Erevan", "Ilyana", "Lia", "Naivara", "Sariel", "Thamior", "Varis", "Yaeldrin"];
+const ELF_LAST: &[&str] = &["Amakiir", "Galanodel", "Holimion", "Liadon", "Meliamne", "Nailo", "Siannodel", "Xiloscient"];
+const DWARF_FIRST: &[&str] = &["Adrik", "Bardryn", "Dagnal", "Eberk", "Gunnloda", "Hlin", "Kildrak", "Riswynn", "Thorin", "Vondal"];
+const DWARF_LAST: &[&str] = &["Battlehammer", "Brawnanvil", "Fireforge", "Gorunn", "Ironfist", "Loderr", "Rumnaheim", "Stonehelm"];
+const HALFLING_FIRST: &[&str] = &["Andry", "Cade", "Eldon", "Kithri", "Lavinia", "Merric", "Nedda", "Perrin", "Seraphina", "Wellby"];
+const HALFLING_LAST: &[&str] = &["Brushgather", "Goodbarrel", "Greenbottle", "Highhill", "Tealeaf", "Thorngage", "Tosscobble", "Underbough"];
+const ORC_FIRST: &[&str] = &["Dench", "Feng", "Gell", "Henk", "Holg", "Imsh", "Krusk", "Ovak", "Shautha", "Yevelda"];
+const ORC_LAST: &[&str] = &["Bonebreaker", "Bloodfang", "Gorefist", "Ironhide", "Skullsplitter", "Stormeye"];
+
+const TAVERN_ADJECTIVES: &[&str] = &["Prancing", "Drunken", "Golden", "Rusty", "Sleeping", "Laughing", "Crooked", "Silver", "Howling", "Wandering"];
+const TAVERN_NOUNS: &[&str] = &["Pony", "Dragon", "Goblet", "Anchor", "Griffin", "Barrel", "Lantern", "Stag", "Mermaid", "Boar"];
+const TAVERN_SPECIALTIES: &[&str] = &["spiced mead", "mutton stew", "dwarven stout", "honeyed bread", "smoked eel", "elderberry wine"];
+const TAVERN_ATMOSPHERES: &[&str] = &[
+    "smoky and crowded, with a bard nobody asked for",
+    "quiet, with regulars who go silent when strangers enter",
+    "rowdy, with an arm-wrestling contest in the corner",
+    "cozy, a fire crackling under a mounted owlbear head",
+    "shabby but friendly, the floor sticky with spilled ale",
+];
+
+const SHOP_TYPES: &[(&str, &[&str])] = &[
+    ("blacksmith", &["longsword", "chain shirt", "horseshoes", "hand axe", "shield"]),
+    ("alchemist", &["potion of healing", "antitoxin", "alchemist's fire", "vial of acid", "perfume"]),
+    ("general store", &["rope (50 ft)", "torches", "rations", "bedroll", "lantern", "chalk"]),
+    ("fletcher", &["arrows (20)", "crossbow bolts (20)", "shortbow", "longbow", "quiver"]),
+    ("curiosity shop", &["music box", "tarnished mirror", "old map fragment", "glass eye", "unlabeled key"]),
+];
+const SHOP_NAME_PATTERNS: &[&str] = &["{owner}'s {type}", "The {adjective} {noun}", "{owner} & Sons"];
+
+#[derive(Deserialize)]
+pub struct GenerateQuery {
+    pub culture: Option<String>,
+    pub count: Option<usize>,
+    pub seed: Option<u64>,
+    pub enhance: Option<bool>,
+    // Taverns and shops only: saved as a location of this campaign, under `parent_id`
+    pub campaign_id: Option<Uuid>,
+    pub parent_id: Option<Uuid>,
+}
+
+#[derive(Serialize)]
+pub struct GeneratedNames {
+    pub culture: String,
+    pub names: Vec<String>,
+    pub seed: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct GeneratedTavern {
+    pub name: String,
+    pub keeper: String,
+    pub specialty: String,
+    pub atmosphere: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct GeneratedShop {
+    pub name: String,
+    pub shop_type: String,
+    pub owner: String,
+    pub wares: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct GeneratedPlace<T> {
+    #[serde(flatten)]
+    pub place: T,
+    pub seed: u64,
+    pub description: Option<String>,
+    // The saved location, when asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location_id: Option<Uuid>,
+}
+
+fn name_lists(culture: &str) -> Option<(&'static [&'static str], &'static [&'static str])> {
+    match culture {
+        "human" => Some((HUMAN_FIRST, HUMAN_LAST)),
+        "elf" => Some((ELF_FIRST, ELF_LAST)),
+        "dwarf" => Some((DWARF_FIRST, DWARF_LAST)),
+        "halfling" => Some((HALFLING_FIRST, HALFLING_LAST)),
+        "orc" => Some((ORC_FIRST, ORC_LAST)),
+        _ => None,
+    }
+}
+
+fn pick<R: Rng>(rng: &mut R, list: &[&'static str]) -> &'static str {
+    list.choose(rng).copied().unwrap_or_default()
+}
+
+pub fn generate_name<R: Rng>(rng: &mut R, culture: &str) -> Option<String> {
+    let (first, last) = name_lists(culture)?;
+    Some(format!("{} {}", pick(rng, first), pick(rng, last)))
+}
+
+pub fn generate_tavern<R: Rng>(rng: &mut R) -> GeneratedTavern {
+    let culture = pick(rng, CULTURES);
+    GeneratedTavern {
+        name: format!("The {} {}", pick(rng, TAVERN_ADJECTIVES), pick(rng, TAVERN_NOUNS)),
+        keeper: generate_name(rng, culture).unwrap_or_default(),
+        specialty: pick(rng, TAVERN_SPECIALTIES).to_string(),
+        atmosphere: pick(rng, TAVERN_ATMOSPHERES).to_string(),
+    }
+}
+
+pub fn generate_shop<R: Rng>(rng: &mut R) -> GeneratedShop {
+    let culture = pick(rng, CULTURES);
+    let owner = generate_name(rng, culture).unwrap_or_default();
+    let (shop_type, inventory) = SHOP_TYPES.choose(rng).copied().unwrap_or(SHOP_TYPES[0]);
+    let owner_first = owner.split(' ').next().unwrap_or_default();
+    let name = pick(rng, SHOP_NAME_PATTERNS)
+        .replace("{owner}", owner_first)
+        .replace("{type}", &title_case(shop_type))
+        .replace("{adjective}", pick(rng, TAVERN_ADJECTIVES))
+        .replace("{noun}", pick(rng, TAVERN_NOUNS));
+    let wares = inventory
+        .choose_multiple(rng, 3)
+        .map(|item| item.to_string())
+        .collect();
+    GeneratedShop {
+        name,
+        shop_type: shop_type.to_string(),
+        owner,
+        wares,
+    }
+}
+
+fn title_case(text: &str) -> String {
+    text.split(' ')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+async fn enhance(pool: &PgPool, user_id: Uuid, kind: &str, summary: &str) -> Option<String> {
+    if !crate::demo::ai_enabled_for(pool, user_id).await {
+        return None;
+    }
+    let prompt = format!("Write a vivid two-sentence description a DM can read aloud for this {}: {}", kind, summary);
+    match crate::ai::complete("You are a creative assistant for a Dungeon Master.", &prompt).await {
+        Ok(completion) => {
+            if let Some(tokens) = completion.tokens_used {
+                usage().record(Subject::User(user_id), Metric::AiTokens, tokens as i64);
+            }
+            Some(completion.text.trim().to_string())
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, ?kind, "AI enhancement failed");
+            None
+        }
+    }
+}
+
+// Keeps a generated place as a location; the generated description is the DM's, the one-line
+// `summary` is what players see
+async fn save_location(pool: &PgPool, user_id: Uuid, params: &GenerateQuery, name: &str, summary: String, description: Option<&String>) -> Result<Option<Uuid>, Response> {
+    let Some(campaign_id) = params.campaign_id else {
+        return Ok(None);
+    };
+    let payload = LocationRequest {
+        name: name.to_string(),
+        parent_id: params.parent_id,
+        description: description.cloned().unwrap_or_else(|| summary.clone()),
+        summary,
+        ..Default::default()
+    };
+    insert_location(pool, user_id, campaign_id, payload).await.map(|location| Some(location.id))
+}
+
+pub async fn generate_names(Query(params): Query<GenerateQuery>) -> impl IntoResponse {
+    let culture = params.culture.unwrap_or_else(|| "human".to_string()).to_lowercase();
+    if name_lists(&culture).is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Unknown culture '{}'. Supported: {}", culture, CULTURES.join(", ")),
+        ).into_response();
+    }
+
+    let seed = params.seed.unwrap_or_else(rand::random);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let count = params.count.unwrap_or(5).clamp(1, 50);
+    let names = (0..count)
+        .filter_map(|_| generate_name(&mut rng, &culture))
+        .collect();
+
+    Json(GeneratedNames { culture, names, seed }).into_response()
+}
+
+pub async fn generate_tavern_handler(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(params): Query<GenerateQuery>,
+) -> impl IntoResponse {
+    let seed = params.seed.unwrap_or_else(rand::random);
+    let tavern = generate_tavern(&mut StdRng::seed_from_u64(seed));
+    let summary = format!("{}, run by {}, known for its {}; {}", tavern.name, tavern.keeper, tavern.specialty, tavern.atmosphere);
+    let description = if params.enhance.unwrap_or(false) {
+        enhance(&pool, user.0, "tavern", &summary).await
+    } else {
+        None
+    };
+    let location_id = match save_location(&pool, user.0, &params, &tavern.name, summary, description.as_ref()).await {
+        Ok(location_id) => location_id,
+        Err(response) => return response,
+    };
+    Json(GeneratedPlace { place: tavern, seed, description, location_id }).into_response()
+}
+
+pub async fn generate_shop_handler(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(params): Query<GenerateQuery>,
+) -> impl IntoResponse {
+    let seed = params.seed.unwrap_or_else(rand::random);
+    let shop = generate_shop(&mut StdRng::seed_from_u64(seed));
+    let summary = format!("{}, a {} owned by {}, selling {}", shop.name, shop.shop_type, shop.owner, shop.wares.join(", "));
+    let description = if params.enhance.unwrap_or(false) {
+        enhance(&pool, user.0, "shop", &summary).await
+    } else {
+        None
+    };
+    let location_id = match save_location(&pool, user.0, &params, &shop.name, summary, description.as_ref()).await {
+        Ok(location_id) => location_id,
+        Err(response) => return response,
+    };
+    Json(GeneratedPlace { place: shop, seed, description, location_id }).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_results() {
+        let tavern_a = generate_tavern(&mut StdRng::seed_from_u64(42));
+        let tavern_b = generate_tavern(&mut StdRng::seed_from_u64(42));
+        assert_eq!(tavern_a, tavern_b);
+
+        let shop_a = generate_shop(&mut StdRng::seed_from_u64(7));
+        let shop_b = generate_shop(&mut StdRng::seed_from_u64(7));
+        assert_eq!(shop_a, shop_b);
+    }
+
+    #[test]
+    fn test_names_per_culture() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for culture in CULTURES {
+            let name = generate_name(&mut rng, culture).unwrap();
+            assert_eq!(name.split(' ').count(), 2);
+        }
+        assert!(generate_name(&mut rng, "kobold").is_none());
+    }
+
+    #[test]
+    fn test_shop_wares_come_from_shop_type() {
+        let shop = generate_shop(&mut StdRng::seed_from_u64(3));
+        let (_, inventory) = SHOP_TYPES.iter().find(|(name, _)| *name == shop.shop_type).unwrap();
+        assert_eq!(shop.wares.len(), 3);
+        assert!(shop.wares.iter().all(|item| inventory.contains(&item.as_str())));
+    }
+}
@@ -1,13 +1,25 @@
-use axum::{Json, response::IntoResponse, http::StatusCode, Extension, extract::Path};
+use axum::{Json, response::IntoResponse, http::StatusCode, Extension, extract::{Path, Query}};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use uuid::Uuid;
 use chrono::Utc;
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use crate::models::{User, Campaign, Session, Character, GameState, InitiativeEntry, EventLog};
-use jsonwebtoken::{encode, EncodingKey, Header};
-use std::env;
-use crate::middleware::AuthUser;
+use crate::models::{User, Campaign, Session, Character, InitiativeEntry, EventLog, Quest, AbilityScoreRoll};
+use crate::middleware::{AuthUser, CampaignRole, bump_token_versions};
+use crate::socket::{SessionState, PresenceEntry, WsTicket, WS_TICKET_TTL_SECS, session_presence, issue_ws_ticket};
+use crate::membership::fill_open_seats;
+use crate::campaign_settings;
+use crate::errors::db_error;
+use crate::history::record_revision;
+use crate::scope::{authorize, authorize_member, campaign_roles, resolve, Access, Permission, Permissions, Resource};
+use crate::usage::{usage, Metric, Subject};
+use crate::sheet::{derive, DerivedStats};
+use crate::events::DomainEvent;
+use crate::password::{hash_password, needs_rehash, password_policy, verify_password};
+use crate::throttle;
+use crate::captcha;
+use crate::audit::{self, AuthEvent, ClientInfo};
+use crate::verification::{send_verification, verification_required};
+use crate::profile::{AccountResponse, ACCOUNT_COLUMNS};
 use chrono::DateTime;
 
 // Auth handlers
@@ -16,12 +28,23 @@ pub struct RegisterRequest {
     pub email: String,
     pub username: String,
     pub password: String,
+    // The captcha widget's response, when CAPTCHA_PROVIDER is set
+    #[serde(default)]
+    pub captcha_token: Option<String>,
 }
 
 pub async fn register(
     Extension(pool): Extension<PgPool>,
+    client: ClientInfo,
     Json(payload): Json<RegisterRequest>,
 ) -> impl IntoResponse {
+    if let Err(rejection) = captcha::check(captcha::captcha_config(), payload.captcha_token.as_deref(), client.address).await {
+        return rejection.into_response();
+    }
+    if let Err(rejection) = password_policy().check(&payload.password, &[&payload.email, &payload.username]) {
+        return rejection.into_response();
+    }
+
     // Check for existing user
     let exists = sqlx::query_scalar::<_, i64>(
         "SELECT COUNT(*) FROM users WHERE email = $1 OR username = $2"
@@ -32,34 +55,99 @@ pub async fn register(
     .await
     .unwrap_or(0);
     if exists > 0 {
-        return (StatusCode::CONFLICT, "Email or username already exists");
+        return (StatusCode::CONFLICT, "Email or username already exists").into_response();
     }
 
     // Hash password
-    let argon2 = Argon2::default();
-    let password_hash = match argon2.hash_password(payload.password.as_bytes(), &argon2::password_hash::SaltString::generate(&mut argon2::password_hash::rand_core::OsRng)) {
-        Ok(hash) => hash.to_string(),
-        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password"),
+    let password_hash = match hash_password(&payload.password) {
+        Ok(hash) => hash,
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password").into_response(),
     };
 
-    // Insert user
+    // Insert user, with the verification email committed alongside
     let user_id = Uuid::new_v4();
     let now = Utc::now();
-    let res = sqlx::query(
-        "INSERT INTO users (id, email, username, password_hash, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)"
-    )
-    .bind(user_id)
-    .bind(&payload.email)
-    .bind(&payload.username)
-    .bind(&password_hash)
-    .bind(now)
-    .bind(now)
-    .execute(&pool)
+    let res = async {
+        let mut tx = pool.begin().await?;
+        let account = sqlx::query_as::<_, AccountResponse>(&format!(
+            "INSERT INTO users (id, email, username, password_hash, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING {}",
+            ACCOUNT_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(&payload.email)
+        .bind(&payload.username)
+        .bind(&password_hash)
+        .bind(now)
+        .bind(now)
+        .fetch_one(&mut *tx)
+        .await?;
+        send_verification(&mut tx, user_id, &payload.email).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(account)
+    }
     .await;
 
-    match res {
-        Ok(_) => (StatusCode::CREATED, "Registered"),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to register user"),
+    // A concurrent registration can still hit the unique constraints (409)
+    let user = match res {
+        Ok(user) => user,
+        Err(e) => return db_error(e, "Failed to register user"),
+    };
+    // Accounts that must verify their email first get tokens from login once they have
+    let tokens = if verification_required() {
+        None
+    } else {
+        match crate::refresh::issue_tokens(&pool, user_id).await {
+            Ok(tokens) => Some(tokens),
+            Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate token").into_response(),
+        }
+    };
+    (StatusCode::CREATED, Json(RegisterResponse { user, tokens })).into_response()
+}
+
+#[derive(Serialize)]
+pub struct RegisterResponse {
+    pub user: AccountResponse,
+    #[serde(flatten)]
+    pub tokens: Option<LoginResponse>,
+}
+
+#[derive(Deserialize)]
+pub struct AvailabilityQuery {
+    pub username: Option<String>,
+    pub email: Option<String>,
+}
+
+// Only the fields asked about are answered
+#[derive(Serialize, Deserialize)]
+pub struct AvailabilityResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<bool>,
+}
+
+// Lets the sign-up form flag a taken username or email before submitting
+pub async fn check_availability(
+    Extension(pool): Extension<PgPool>,
+    Query(params): Query<AvailabilityQuery>,
+) -> impl IntoResponse {
+    if params.username.is_none() && params.email.is_none() {
+        return (StatusCode::BAD_REQUEST, "Give a username or an email to check").into_response();
+    }
+    let taken = sqlx::query_as::<_, (bool, bool)>(
+        "SELECT EXISTS(SELECT 1 FROM users WHERE username = $1), EXISTS(SELECT 1 FROM users WHERE email = $2)"
+    )
+    .bind(&params.username)
+    .bind(&params.email)
+    .fetch_one(&pool)
+    .await;
+    match taken {
+        Ok((username_taken, email_taken)) => Json(AvailabilityResponse {
+            username: params.username.as_ref().map(|_| !username_taken),
+            email: params.email.as_ref().map(|_| !email_taken),
+        })
+        .into_response(),
+        Err(e) => db_error(e, "Failed to check availability"),
     }
 }
 
@@ -72,18 +160,22 @@ pub struct LoginRequest {
 #[derive(Serialize, Deserialize)]
 pub struct LoginResponse {
     pub token: String,
-}
-
-#[derive(Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    exp: usize,
+    pub refresh_token: String,
+    // Seconds until `token` expires
+    pub expires_in: i64,
 }
 
 pub async fn login(
     Extension(pool): Extension<PgPool>,
+    client: ClientInfo,
     Json(payload): Json<LoginRequest>,
 ) -> impl IntoResponse {
+    match throttle::locked_for(&pool, &payload.email, client.address).await {
+        Ok(Some(retry_after)) => return throttle::locked_out(retry_after),
+        Ok(None) => {}
+        Err(e) => return db_error(e, "Failed to log in"),
+    }
+
     // Fetch user by email
     let user = sqlx::query_as::<_, User>(
         "SELECT * FROM users WHERE email = $1"
@@ -92,40 +184,120 @@ pub async fn login(
     .fetch_optional(&pool)
     .await;
 
+    // Verify password
     let user = match user {
         Ok(Some(u)) => u,
-        _ => return (StatusCode::UNAUTHORIZED, "Invalid email or password").into_response(),
+        Ok(None) => return failed_login(&pool, &payload.email, None, &client).await,
+        Err(e) => return db_error(e, "Failed to log in"),
     };
+    if !verify_password(&user.password_hash, &payload.password) {
+        return failed_login(&pool, &payload.email, Some(user.id), &client).await;
+    }
+    // Hashes from before the Argon2 parameters were raised are upgraded while the password
+    // is at hand; a failure leaves the old hash, which still works
+    if needs_rehash(&user.password_hash) {
+        if let Ok(password_hash) = hash_password(&payload.password) {
+            let rehashed = sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2 AND password_hash = $3")
+                .bind(&password_hash)
+                .bind(user.id)
+                .bind(&user.password_hash)
+                .execute(&pool)
+                .await;
+            if let Err(e) = rehashed {
+                tracing::error!(error = %e, user_id = %user.id, "Failed to rehash password");
+            }
+        }
+    }
+    if let Err(e) = throttle::clear_account(&pool, &payload.email).await {
+        return db_error(e, "Failed to log in");
+    }
+    if user.disabled_at.is_some() {
+        return crate::moderation::refuse(&pool, user.id).await;
+    }
+    if !user.email_verified && verification_required() {
+        return (StatusCode::FORBIDDEN, "Email not verified").into_response();
+    }
 
-    // Verify password
-    let parsed_hash = match PasswordHash::new(&user.password_hash) {
-        Ok(hash) => hash,
-        Err(_) => return (StatusCode::UNAUTHORIZED, "Invalid email or password").into_response(),
+    // Issue JWT and refresh token
+    let tokens = crate::refresh::issue_tokens(&pool, user.id).await;
+    match tokens {
+        Ok(tokens) => {
+            audit::record(&pool, Some(user.id), AuthEvent::Login, &client, serde_json::json!({})).await;
+            (StatusCode::OK, axum::Json(tokens)).into_response()
+        }
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate token").into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+// Revokes every refresh token the user holds, so a stolen one stops working, and hands the
+// caller a fresh pair in their place
+pub async fn change_password(
+    Extension(pool): Extension<PgPool>,
+    Extension(session_state): Extension<SessionState>,
+    Extension(user): Extension<AuthUser>,
+    client: ClientInfo,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> impl IntoResponse {
+    let account = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(user.0)
+        .fetch_optional(&pool)
+        .await;
+    let account = match account {
+        Ok(Some(account)) => account,
+        Ok(None) => return (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => return db_error(e, "Failed to change password"),
     };
-    let argon2 = Argon2::default();
-    let valid = argon2.verify_password(payload.password.as_bytes(), &parsed_hash).is_ok();
-    if !valid {
-        return (StatusCode::UNAUTHORIZED, "Invalid email or password").into_response();
-    }
-
-    // Issue JWT
-    let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "secret".to_string());
-    let exp = (Utc::now() + chrono::Duration::days(7)).timestamp() as usize;
-    let claims = Claims {
-        sub: user.id.to_string(),
-        exp,
+
+    // Accounts made through OAuth have no password to check against
+    if !verify_password(&account.password_hash, &payload.current_password) {
+        return (StatusCode::FORBIDDEN, "Current password is incorrect").into_response();
+    }
+    if let Err(rejection) = password_policy().check(&payload.new_password, &[&account.email, &account.username]) {
+        return rejection.into_response();
+    }
+
+    let password_hash = match hash_password(&payload.new_password) {
+        Ok(hash) => hash,
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password").into_response(),
     };
-    let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes()));
-    match token {
-        Ok(token) => (
-            StatusCode::OK,
-            axum::Json(LoginResponse { token })
-        ).into_response(),
+    let updated = sqlx::query("UPDATE users SET password_hash = $1, updated_at = NOW() WHERE id = $2")
+        .bind(&password_hash)
+        .bind(user.0)
+        .execute(&pool)
+        .await;
+    if let Err(e) = updated {
+        return db_error(e, "Failed to change password");
+    }
+    // Whoever knew the old password may hold a token or an open socket; both go
+    if let Err(e) = crate::revocation::revoke_user(&pool, &session_state, user.0).await {
+        return db_error(e, "Failed to revoke tokens");
+    }
+    audit::record(&pool, Some(user.0), AuthEvent::PasswordChanged, &client, serde_json::json!({})).await;
+
+    match crate::refresh::issue_tokens(&pool, user.0).await {
+        Ok(tokens) => (StatusCode::OK, Json(tokens)).into_response(),
         Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate token").into_response(),
     }
 }
 
+// Counts towards the throttle (see throttle.rs) and goes in the account's audit log, when
+// there is an account, before answering
+async fn failed_login(pool: &PgPool, email: &str, user_id: Option<Uuid>, client: &ClientInfo) -> axum::response::Response {
+    if let Err(e) = throttle::record_failure(pool, email, client.address).await {
+        return db_error(e, "Failed to log in");
+    }
+    audit::record(pool, user_id, AuthEvent::LoginFailed, client, serde_json::json!({ "email": email })).await;
+    (StatusCode::UNAUTHORIZED, "Invalid email or password").into_response()
+}
+
 // Campaign handlers
+
 #[derive(Deserialize)]
 pub struct CreateCampaignRequest {
     pub name: String,
@@ -140,19 +312,38 @@ pub struct CampaignResponse {
     pub description: Option<String>,
     pub dm_id: Uuid,
     pub settings: serde_json::Value,
+    pub archived_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+impl From<Campaign> for CampaignResponse {
+    fn from(campaign: Campaign) -> Self {
+        CampaignResponse {
+            id: campaign.id,
+            name: campaign.name,
+            description: campaign.description,
+            dm_id: campaign.dm_id,
+            settings: campaign.settings,
+            archived_at: campaign.archived_at,
+            created_at: campaign.created_at,
+            updated_at: campaign.updated_at,
+        }
+    }
+}
+
 pub async fn create_campaign(
     Extension(pool): Extension<PgPool>,
     Extension(user): Extension<AuthUser>,
     Json(payload): Json<CreateCampaignRequest>,
 ) -> impl IntoResponse {
+    let settings = match campaign_settings::parse(payload.settings.unwrap_or_else(|| serde_json::json!({}))) {
+        Ok(settings) => settings,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
     let campaign_id = Uuid::new_v4();
     let now = Utc::now();
-    let settings = payload.settings.unwrap_or_else(|| serde_json::json!({}));
-    
+
     let res = sqlx::query_as::<_, Campaign>(
         "INSERT INTO campaigns (id, name, description, dm_id, settings, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"
     )
@@ -160,54 +351,142 @@ pub async fn create_campaign(
     .bind(&payload.name)
     .bind(&payload.description)
     .bind(user.0)
-    .bind(&settings)
+    .bind(sqlx::types::Json(&settings))
     .bind(now)
     .bind(now)
     .fetch_one(&pool)
     .await;
 
+    if res.is_ok() {
+        // New DM membership; tokens with embedded roles need refreshing
+        let _ = bump_token_versions(&pool, &[user.0]).await;
+    }
+
     match res {
         Ok(campaign) => (
             StatusCode::CREATED,
-            axum::Json(CampaignResponse {
-                id: campaign.id,
-                name: campaign.name,
-                description: campaign.description,
-                dm_id: campaign.dm_id,
-                settings: campaign.settings,
-                created_at: campaign.created_at,
-                updated_at: campaign.updated_at,
-            })
+            axum::Json(CampaignResponse::from(campaign))
         ).into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create campaign").into_response(),
+        Err(e) => db_error(e, "Failed to create campaign"),
     }
 }
 
+const DEFAULT_CAMPAIGN_PAGE: i64 = 50;
+const MAX_CAMPAIGN_PAGE: i64 = 100;
+
+// Which of the user's campaigns to list: the ones they run (as DM or co-DM) or play in
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CampaignRoleFilter {
+    Dm,
+    Player,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CampaignSort {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+    Name,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Deserialize, Default)]
+pub struct CampaignListQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    pub role: Option<CampaignRoleFilter>,
+    // Archived campaigns are only listed when asked for, and then only they are
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub sort: CampaignSort,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
+#[derive(Serialize)]
+pub struct CampaignListItem {
+    #[serde(flatten)]
+    pub campaign: CampaignResponse,
+    // The caller's role in the campaign
+    pub role: CampaignRole,
+}
+
+#[derive(Serialize)]
+pub struct CampaignPage {
+    pub campaigns: Vec<CampaignListItem>,
+    // Campaigns matching the filters, across all pages
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+// The campaigns among $1 matching the filters; $2 is whether to list archived campaigns
+const CAMPAIGN_LIST_FROM: &str = "FROM campaigns c WHERE c.id = ANY($1) AND (c.archived_at IS NOT NULL) = $2 AND c.deleted_at IS NULL";
+
 pub async fn list_campaigns(
     Extension(pool): Extension<PgPool>,
     Extension(user): Extension<AuthUser>,
+    Query(params): Query<CampaignListQuery>,
 ) -> impl IntoResponse {
-    let campaigns = sqlx::query_as::<_, Campaign>(
-        "SELECT * FROM campaigns WHERE dm_id = $1 OR id IN (SELECT campaign_id FROM campaign_players WHERE player_id = $1) ORDER BY created_at DESC"
-    )
-    .bind(user.0)
+    let limit = params.limit.unwrap_or(DEFAULT_CAMPAIGN_PAGE).clamp(1, MAX_CAMPAIGN_PAGE);
+    let offset = params.offset.unwrap_or(0).max(0);
+    let runs = params.role.map(|role| matches!(role, CampaignRoleFilter::Dm));
+    let column = match params.sort {
+        CampaignSort::CreatedAt => "c.created_at",
+        CampaignSort::UpdatedAt => "c.updated_at",
+        CampaignSort::Name => "LOWER(c.name)",
+    };
+    let order = match params.order {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    };
+
+    let roles = match campaign_roles(&pool, user.0).await {
+        Ok(roles) => roles,
+        Err(e) => return db_error(e, "Failed to fetch campaigns"),
+    };
+    let ids: Vec<Uuid> = roles.iter().filter(|(_, role)| runs.is_none_or(|runs| role.is_dm() == runs)).map(|(id, _)| *id).collect();
+
+    let total = sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) {}", CAMPAIGN_LIST_FROM))
+        .bind(&ids)
+        .bind(params.archived)
+        .fetch_one(&pool)
+        .await;
+    let total = match total {
+        Ok(total) => total,
+        Err(e) => return db_error(e, "Failed to fetch campaigns"),
+    };
+    // Ties broken by id so pages don't overlap
+    let rows = sqlx::query_as::<_, Campaign>(&format!(
+        "SELECT c.* {} ORDER BY {column} {order}, c.id {order} LIMIT $3 OFFSET $4",
+        CAMPAIGN_LIST_FROM
+    ))
+    .bind(&ids)
+    .bind(params.archived)
+    .bind(limit)
+    .bind(offset)
     .fetch_all(&pool)
     .await;
 
-    match campaigns {
-        Ok(campaigns) => {
-            let responses: Vec<CampaignResponse> = campaigns.into_iter().map(|c| CampaignResponse {
-                id: c.id,
-                name: c.name,
-                description: c.description,
-                dm_id: c.dm_id,
-                settings: c.settings,
-                created_at: c.created_at,
-                updated_at: c.updated_at,
+    match rows {
+        Ok(rows) => {
+            let campaigns = rows.into_iter().map(|campaign| {
+                let role = roles[&campaign.id];
+                CampaignListItem { campaign: CampaignResponse::from(campaign), role }
             }).collect();
-            axum::Json(responses).into_response()
+            axum::Json(CampaignPage { campaigns, total, limit, offset }).into_response()
         },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch campaigns").into_response(),
+        Err(e) => db_error(e, "Failed to fetch campaigns"),
     }
 }
 
@@ -216,29 +495,21 @@ pub async fn get_campaign(
     Extension(user): Extension<AuthUser>,
     Path(campaign_id): Path<Uuid>,
 ) -> impl IntoResponse {
-    let campaign = sqlx::query_as::<_, Campaign>(
-        "SELECT * FROM campaigns WHERE id = $1 AND (dm_id = $2 OR id IN (SELECT campaign_id FROM campaign_players WHERE player_id = $2))"
-    )
-    .bind(campaign_id)
-    .bind(user.0)
-    .fetch_optional(&pool)
-    .await;
+    if let Err(response) = authorize_member(&pool, user.0, Resource::Campaign(campaign_id), "Campaign not found").await {
+        return response;
+    }
+    let campaign = sqlx::query_as::<_, Campaign>("SELECT * FROM campaigns WHERE id = $1")
+        .bind(campaign_id)
+        .fetch_optional(&pool)
+        .await;
 
     match campaign {
         Ok(Some(campaign)) => {
-            let response = CampaignResponse {
-                id: campaign.id,
-                name: campaign.name,
-                description: campaign.description,
-                dm_id: campaign.dm_id,
-                settings: campaign.settings,
-                created_at: campaign.created_at,
-                updated_at: campaign.updated_at,
-            };
+            let response = CampaignResponse::from(campaign);
             axum::Json(response).into_response()
         },
         Ok(None) => (StatusCode::NOT_FOUND, "Campaign not found").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch campaign").into_response(),
+        Err(e) => db_error(e, "Failed to fetch campaign"),
     }
 }
 
@@ -251,23 +522,14 @@ pub struct UpdateCampaignRequest {
 
 pub async fn update_campaign(
     Extension(pool): Extension<PgPool>,
-    Extension(user): Extension<AuthUser>,
     Path(campaign_id): Path<Uuid>,
     Json(payload): Json<UpdateCampaignRequest>,
 ) -> impl IntoResponse {
-    // Check if user is DM of this campaign
-    let is_dm = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM campaigns WHERE id = $1 AND dm_id = $2)"
-    )
-    .bind(campaign_id)
-    .bind(user.0)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or(false);
-
-    if !is_dm {
-        return (StatusCode::FORBIDDEN, "Only the DM can update campaigns").into_response();
-    }
+    // Replaces the settings as a whole
+    let settings = match payload.settings.map(campaign_settings::parse).transpose() {
+        Ok(settings) => settings,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
 
     let now = Utc::now();
     let res = sqlx::query_as::<_, Campaign>(
@@ -275,7 +537,7 @@ pub async fn update_campaign(
     )
     .bind(&payload.name)
     .bind(&payload.description)
-    .bind(&payload.settings)
+    .bind(settings.as_ref().map(sqlx::types::Json))
     .bind(now)
     .bind(campaign_id)
     .fetch_one(&pool)
@@ -283,49 +545,175 @@ pub async fn update_campaign(
 
     match res {
         Ok(campaign) => {
-            let response = CampaignResponse {
-                id: campaign.id,
-                name: campaign.name,
-                description: campaign.description,
-                dm_id: campaign.dm_id,
-                settings: campaign.settings,
-                created_at: campaign.created_at,
-                updated_at: campaign.updated_at,
-            };
+            // A raised (or removed) seat limit lets waitlisted players in
+            if settings.is_some() {
+                if let Err(e) = fill_open_seats(&pool, campaign.id).await {
+                    tracing::error!(error = %e, %campaign_id, "Failed to promote from waitlist");
+                }
+            }
+            let response = CampaignResponse::from(campaign);
             axum::Json(response).into_response()
         },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update campaign").into_response(),
+        Err(e) => db_error(e, "Failed to update campaign"),
     }
 }
 
+#[derive(Serialize)]
+pub struct DeletedCampaign {
+    pub campaign_id: Uuid,
+    pub restorable_until: DateTime<Utc>,
+}
+
+// Deleted campaigns disappear everywhere at once but are kept this long for
+// POST /campaigns/:id/restore, after which the maintenance task purges them for good
+pub const CAMPAIGN_RESTORE_DAYS: i64 = 14;
+
 pub async fn delete_campaign(
     Extension(pool): Extension<PgPool>,
     Extension(user): Extension<AuthUser>,
     Path(campaign_id): Path<Uuid>,
 ) -> impl IntoResponse {
-    // Check if user is DM of this campaign
-    let is_dm = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM campaigns WHERE id = $1 AND dm_id = $2)"
+    let res = sqlx::query_scalar::<_, DateTime<Utc>>(
+        "UPDATE campaigns SET deleted_at = NOW(), transfer_to = NULL, transfer_offered_at = NULL WHERE id = $1 AND deleted_at IS NULL RETURNING deleted_at"
+    )
+    .bind(campaign_id)
+    .fetch_optional(&pool)
+    .await;
+
+    match res {
+        Ok(Some(deleted_at)) => {
+            // Everyone losing a membership
+            let _ = bump_token_versions(&pool, &campaign_member_ids(&pool, campaign_id, user.0).await).await;
+            let deleted = DeletedCampaign { campaign_id, restorable_until: deleted_at + chrono::Duration::days(CAMPAIGN_RESTORE_DAYS) };
+            axum::Json(deleted).into_response()
+        },
+        Ok(None) => (StatusCode::NOT_FOUND, "Campaign not found").into_response(),
+        Err(e) => db_error(e, "Failed to delete campaign"),
+    }
+}
+
+// Undoes a delete within the grace period; the campaign comes back as it was, members included
+pub async fn restore_campaign(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    // Deleted campaigns don't resolve, so ownership is checked here
+    let res = sqlx::query_as::<_, Campaign>(
+        "UPDATE campaigns SET deleted_at = NULL, updated_at = NOW()
+         WHERE id = $1 AND dm_id = $2 AND deleted_at > NOW() - make_interval(days => $3) RETURNING *"
     )
     .bind(campaign_id)
     .bind(user.0)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or(false);
+    .bind(CAMPAIGN_RESTORE_DAYS as i32)
+    .fetch_optional(&pool)
+    .await;
 
-    if !is_dm {
-        return (StatusCode::FORBIDDEN, "Only the DM can delete campaigns").into_response();
+    match res {
+        Ok(Some(campaign)) => {
+            let _ = bump_token_versions(&pool, &campaign_member_ids(&pool, campaign_id, user.0).await).await;
+            axum::Json(CampaignResponse::from(campaign)).into_response()
+        },
+        Ok(None) => (StatusCode::NOT_FOUND, "No deleted campaign to restore").into_response(),
+        Err(e) => db_error(e, "Failed to restore campaign"),
     }
+}
 
-    let res = sqlx::query("DELETE FROM campaigns WHERE id = $1")
+async fn campaign_member_ids(pool: &PgPool, campaign_id: Uuid, dm_id: Uuid) -> Vec<Uuid> {
+    let mut members = sqlx::query_scalar::<_, Uuid>("SELECT player_id FROM campaign_players WHERE campaign_id = $1")
         .bind(campaign_id)
-        .execute(&pool)
-        .await;
+        .fetch_all(pool)
+        .await
+        .unwrap_or_default();
+    members.push(dm_id);
+    members
+}
+
+// Run by the maintenance task; the cascade takes sessions, characters and the rest with them
+pub async fn purge_deleted_campaigns(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let purged = sqlx::query("DELETE FROM campaigns WHERE deleted_at < NOW() - make_interval(days => $1)")
+        .bind(CAMPAIGN_RESTORE_DAYS as i32)
+        .execute(pool)
+        .await?;
+    Ok(purged.rows_affected())
+}
+
+#[derive(Deserialize)]
+pub struct ArchiveCampaignRequest {
+    pub archived: bool,
+}
+
+// Archiving keeps everything but takes the campaign out of the default list and discovery
+pub async fn archive_campaign(
+    Extension(pool): Extension<PgPool>,
+    Path(campaign_id): Path<Uuid>,
+    Json(payload): Json<ArchiveCampaignRequest>,
+) -> impl IntoResponse {
+    let res = sqlx::query_as::<_, Campaign>(
+        "UPDATE campaigns SET archived_at = CASE WHEN $2 THEN COALESCE(archived_at, NOW()) END, updated_at = NOW() WHERE id = $1 RETURNING *"
+    )
+    .bind(campaign_id)
+    .bind(payload.archived)
+    .fetch_one(&pool)
+    .await;
 
     match res {
-        Ok(_) => (StatusCode::OK, "Campaign deleted").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete campaign").into_response(),
+        Ok(campaign) => axum::Json(CampaignResponse::from(campaign)).into_response(),
+        Err(e) => db_error(e, "Failed to archive campaign"),
+    }
+}
+
+#[derive(Serialize)]
+pub struct CampaignMemberResponse {
+    pub user_id: Uuid,
+    pub username: String,
+    pub role: CampaignRole,
+    // Always empty for the DM and co-DMs, who can do all of it anyway
+    pub permissions: Permissions,
+    pub online: bool,
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+pub async fn list_campaign_members(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Extension(session_state): Extension<SessionState>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize_member(&pool, user.0, Resource::Campaign(campaign_id), "Campaign not found").await {
+        return response;
     }
+    // DM first, then players by name
+    let members = sqlx::query_as::<_, (Uuid, String, bool, Option<String>, Option<Vec<String>>, Option<DateTime<Utc>>)>(
+        "SELECT u.id, u.username, u.id = c.dm_id, cp.role, cp.permissions, u.last_seen_at FROM campaigns c 
+         INNER JOIN users u ON u.id = c.dm_id OR u.id IN (SELECT player_id FROM campaign_players WHERE campaign_id = c.id) 
+         LEFT JOIN campaign_players cp ON cp.campaign_id = c.id AND cp.player_id = u.id
+         WHERE c.id = $1 
+         ORDER BY u.id = c.dm_id DESC, u.username"
+    )
+    .bind(campaign_id)
+    .fetch_all(&pool)
+    .await;
+
+    let members = match members {
+        Ok(members) => members,
+        Err(e) => return db_error(e, "Failed to fetch campaign members"),
+    };
+
+    let user_ids: Vec<Uuid> = members.iter().map(|(id, ..)| *id).collect();
+    let online = session_state.presence.online_users(&user_ids).await;
+    let responses: Vec<CampaignMemberResponse> = members
+        .into_iter()
+        .map(|(user_id, username, is_dm, member_role, permissions, last_seen_at)| CampaignMemberResponse {
+            user_id,
+            username,
+            role: if is_dm { CampaignRole::Dm } else { CampaignRole::from_member_role(member_role.as_deref().unwrap_or_default()) },
+            permissions: Permissions::from_stored(&permissions.unwrap_or_default()),
+            online: online.contains(&user_id),
+            last_seen_at,
+        })
+        .collect();
+    axum::Json(responses).into_response()
 }
 
 // Session handlers
@@ -334,6 +722,8 @@ pub struct CreateSessionRequest {
     pub campaign_id: Uuid,
     pub name: String,
     pub description: Option<String>,
+    // DM only; every roll in the session is then drawn from this seed and can be replayed
+    pub rng_seed: Option<u64>,
 }
 
 #[derive(Serialize)]
@@ -348,6 +738,32 @@ pub struct SessionResponse {
     pub game_state: serde_json::Value,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub paused_at: Option<DateTime<Utc>>,
+    pub paused_seconds: i32,
+    pub previous_session_id: Option<Uuid>,
+    // Whether rolls come from a seed; the seed itself is only given out by the replay
+    pub seeded: bool,
+}
+
+impl From<Session> for SessionResponse {
+    fn from(session: Session) -> Self {
+        SessionResponse {
+            id: session.id,
+            campaign_id: session.campaign_id,
+            name: session.name,
+            description: session.description,
+            status: session.status,
+            started_at: session.started_at,
+            ended_at: session.ended_at,
+            game_state: session.game_state,
+            created_at: session.created_at,
+            updated_at: session.updated_at,
+            paused_at: session.paused_at,
+            paused_seconds: session.paused_seconds,
+            previous_session_id: session.previous_session_id,
+            seeded: session.rng_seed.is_some(),
+        }
+    }
 }
 
 pub async fn create_session(
@@ -356,24 +772,20 @@ pub async fn create_session(
     Json(payload): Json<CreateSessionRequest>,
 ) -> impl IntoResponse {
     // Check if user is DM of this campaign or a player
-    let has_access = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM campaigns WHERE id = $1 AND dm_id = $2) OR EXISTS(SELECT 1 FROM campaign_players WHERE campaign_id = $1 AND player_id = $2)"
-    )
-    .bind(payload.campaign_id)
-    .bind(user.0)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or(false);
-
-    if !has_access {
-        return (StatusCode::FORBIDDEN, "Access denied to this campaign").into_response();
+    let scope = match authorize(&pool, user.0, Resource::Campaign(payload.campaign_id), Access::Member, "Access denied to this campaign").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    // Whoever picks the seed can predict the rolls
+    if payload.rng_seed.is_some() && !scope.is_dm() {
+        return (StatusCode::FORBIDDEN, "Only the DM can seed a session's rolls").into_response();
     }
 
     let session_id = Uuid::new_v4();
     let now = Utc::now();
     
     let res = sqlx::query_as::<_, Session>(
-        "INSERT INTO sessions (id, campaign_id, name, description, status, game_state, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *"
+        "INSERT INTO sessions (id, campaign_id, name, description, status, game_state, created_at, updated_at, rng_seed) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING *"
     )
     .bind(session_id)
     .bind(payload.campaign_id)
@@ -383,26 +795,16 @@ pub async fn create_session(
     .bind(serde_json::json!({}))
     .bind(now)
     .bind(now)
+    .bind(payload.rng_seed.map(|seed| seed as i64))
     .fetch_one(&pool)
     .await;
 
     match res {
         Ok(session) => {
-            let response = SessionResponse {
-                id: session.id,
-                campaign_id: session.campaign_id,
-                name: session.name,
-                description: session.description,
-                status: session.status,
-                started_at: session.started_at,
-                ended_at: session.ended_at,
-                game_state: session.game_state,
-                created_at: session.created_at,
-                updated_at: session.updated_at,
-            };
+            let response = SessionResponse::from(session);
             (StatusCode::CREATED, axum::Json(response)).into_response()
         },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session").into_response(),
+        Err(e) => db_error(e, "Failed to create session"),
     }
 }
 
@@ -410,33 +812,21 @@ pub async fn list_sessions(
     Extension(pool): Extension<PgPool>,
     Extension(user): Extension<AuthUser>,
 ) -> impl IntoResponse {
-    let sessions = sqlx::query_as::<_, Session>(
-        "SELECT s.* FROM sessions s 
-         INNER JOIN campaigns c ON s.campaign_id = c.id 
-         WHERE c.dm_id = $1 OR s.campaign_id IN (SELECT campaign_id FROM campaign_players WHERE player_id = $1)
-         ORDER BY s.created_at DESC"
-    )
-    .bind(user.0)
-    .fetch_all(&pool)
+    let campaign_ids: Vec<Uuid> = match campaign_roles(&pool, user.0).await {
+        Ok(roles) => roles.into_keys().collect(),
+        Err(e) => return db_error(e, "Failed to fetch sessions"),
+    };
+    let sessions = sqlx::query_as::<_, Session>("SELECT * FROM sessions WHERE campaign_id = ANY($1) ORDER BY created_at DESC")
+        .bind(&campaign_ids)
+        .fetch_all(&pool)
     .await;
 
     match sessions {
         Ok(sessions) => {
-            let responses: Vec<SessionResponse> = sessions.into_iter().map(|s| SessionResponse {
-                id: s.id,
-                campaign_id: s.campaign_id,
-                name: s.name,
-                description: s.description,
-                status: s.status,
-                started_at: s.started_at,
-                ended_at: s.ended_at,
-                game_state: s.game_state,
-                created_at: s.created_at,
-                updated_at: s.updated_at,
-            }).collect();
+            let responses: Vec<SessionResponse> = sessions.into_iter().map(SessionResponse::from).collect();
             axum::Json(responses).into_response()
         },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch sessions").into_response(),
+        Err(e) => db_error(e, "Failed to fetch sessions"),
     }
 }
 
@@ -445,34 +835,21 @@ pub async fn get_session(
     Extension(user): Extension<AuthUser>,
     Path(session_id): Path<Uuid>,
 ) -> impl IntoResponse {
-    let session = sqlx::query_as::<_, Session>(
-        "SELECT s.* FROM sessions s 
-         INNER JOIN campaigns c ON s.campaign_id = c.id 
-         WHERE s.id = $1 AND (c.dm_id = $2 OR s.campaign_id IN (SELECT campaign_id FROM campaign_players WHERE player_id = $2))"
-    )
-    .bind(session_id)
-    .bind(user.0)
-    .fetch_optional(&pool)
+    if let Err(response) = authorize_member(&pool, user.0, Resource::Session(session_id), "Session not found").await {
+        return response;
+    }
+    let session = sqlx::query_as::<_, Session>("SELECT * FROM sessions WHERE id = $1")
+        .bind(session_id)
+        .fetch_optional(&pool)
     .await;
 
     match session {
         Ok(Some(session)) => {
-            let response = SessionResponse {
-                id: session.id,
-                campaign_id: session.campaign_id,
-                name: session.name,
-                description: session.description,
-                status: session.status,
-                started_at: session.started_at,
-                ended_at: session.ended_at,
-                game_state: session.game_state,
-                created_at: session.created_at,
-                updated_at: session.updated_at,
-            };
+            let response = SessionResponse::from(session);
             axum::Json(response).into_response()
         },
         Ok(None) => (StatusCode::NOT_FOUND, "Session not found").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch session").into_response(),
+        Err(e) => db_error(e, "Failed to fetch session"),
     }
 }
 
@@ -485,26 +862,9 @@ pub struct UpdateSessionRequest {
 
 pub async fn update_session(
     Extension(pool): Extension<PgPool>,
-    Extension(user): Extension<AuthUser>,
     Path(session_id): Path<Uuid>,
     Json(payload): Json<UpdateSessionRequest>,
 ) -> impl IntoResponse {
-    // Check if user is DM of this session's campaign
-    let is_dm = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM sessions s 
-         INNER JOIN campaigns c ON s.campaign_id = c.id 
-         WHERE s.id = $1 AND c.dm_id = $2)"
-    )
-    .bind(session_id)
-    .bind(user.0)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or(false);
-
-    if !is_dm {
-        return (StatusCode::FORBIDDEN, "Only the DM can update sessions").into_response();
-    }
-
     let now = Utc::now();
     let res = sqlx::query_as::<_, Session>(
         "UPDATE sessions SET name = COALESCE($1, name), status = COALESCE($2, status), game_state = COALESCE($3, game_state), updated_at = $4 WHERE id = $5 RETURNING *"
@@ -519,45 +879,17 @@ pub async fn update_session(
 
     match res {
         Ok(session) => {
-            let response = SessionResponse {
-                id: session.id,
-                campaign_id: session.campaign_id,
-                name: session.name,
-                description: session.description,
-                status: session.status,
-                started_at: session.started_at,
-                ended_at: session.ended_at,
-                game_state: session.game_state,
-                created_at: session.created_at,
-                updated_at: session.updated_at,
-            };
+            let response = SessionResponse::from(session);
             axum::Json(response).into_response()
         },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update session").into_response(),
+        Err(e) => db_error(e, "Failed to update session"),
     }
 }
 
 pub async fn start_session(
     Extension(pool): Extension<PgPool>,
-    Extension(user): Extension<AuthUser>,
     Path(session_id): Path<Uuid>,
 ) -> impl IntoResponse {
-    // Check if user is DM of this session's campaign
-    let is_dm = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM sessions s 
-         INNER JOIN campaigns c ON s.campaign_id = c.id 
-         WHERE s.id = $1 AND c.dm_id = $2)"
-    )
-    .bind(session_id)
-    .bind(user.0)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or(false);
-
-    if !is_dm {
-        return (StatusCode::FORBIDDEN, "Only the DM can start sessions").into_response();
-    }
-
     let now = Utc::now();
     let res = sqlx::query_as::<_, Session>(
         "UPDATE sessions SET status = 'active', started_at = $1, updated_at = $1 WHERE id = $2 RETURNING *"
@@ -569,45 +901,17 @@ pub async fn start_session(
 
     match res {
         Ok(session) => {
-            let response = SessionResponse {
-                id: session.id,
-                campaign_id: session.campaign_id,
-                name: session.name,
-                description: session.description,
-                status: session.status,
-                started_at: session.started_at,
-                ended_at: session.ended_at,
-                game_state: session.game_state,
-                created_at: session.created_at,
-                updated_at: session.updated_at,
-            };
+            let response = SessionResponse::from(session);
             axum::Json(response).into_response()
         },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to start session").into_response(),
+        Err(e) => db_error(e, "Failed to start session"),
     }
 }
 
 pub async fn end_session(
     Extension(pool): Extension<PgPool>,
-    Extension(user): Extension<AuthUser>,
     Path(session_id): Path<Uuid>,
 ) -> impl IntoResponse {
-    // Check if user is DM of this session's campaign
-    let is_dm = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM sessions s 
-         INNER JOIN campaigns c ON s.campaign_id = c.id 
-         WHERE s.id = $1 AND c.dm_id = $2)"
-    )
-    .bind(session_id)
-    .bind(user.0)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or(false);
-
-    if !is_dm {
-        return (StatusCode::FORBIDDEN, "Only the DM can end sessions").into_response();
-    }
-
     let now = Utc::now();
     let res = sqlx::query_as::<_, Session>(
         "UPDATE sessions SET status = 'ended', ended_at = $1, updated_at = $1 WHERE id = $2 RETURNING *"
@@ -619,27 +923,275 @@ pub async fn end_session(
 
     match res {
         Ok(session) => {
-            let response = SessionResponse {
-                id: session.id,
-                campaign_id: session.campaign_id,
-                name: session.name,
-                description: session.description,
-                status: session.status,
-                started_at: session.started_at,
-                ended_at: session.ended_at,
-                game_state: session.game_state,
-                created_at: session.created_at,
-                updated_at: session.updated_at,
-            };
+            let response = SessionResponse::from(session);
             axum::Json(response).into_response()
         },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to end session").into_response(),
+        Err(e) => db_error(e, "Failed to end session"),
     }
 }
 
-// Character handlers
-#[derive(Deserialize)]
-pub struct CreateCharacterRequest {
+// Intermission: turn timers stop where they are and the table is told. Combat state is left alone.
+pub async fn pause_session(
+    Extension(pool): Extension<PgPool>,
+    Extension(session_state): Extension<SessionState>,
+    Path(session_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let now = Utc::now();
+    let res = sqlx::query_as::<_, Session>(
+        "UPDATE sessions SET status = 'paused', paused_at = $1, updated_at = $1 WHERE id = $2 AND status = 'active' RETURNING *"
+    )
+    .bind(now)
+    .bind(session_id)
+    .fetch_optional(&pool)
+    .await;
+
+    match res {
+        Ok(Some(session)) => {
+            crate::socket::pause_session(&session_state, session_id, now).await;
+            axum::Json(SessionResponse::from(session)).into_response()
+        },
+        Ok(None) => (StatusCode::CONFLICT, "Only an active session can be paused").into_response(),
+        Err(e) => db_error(e, "Failed to pause session"),
+    }
+}
+
+pub async fn resume_session(
+    Extension(pool): Extension<PgPool>,
+    Extension(session_state): Extension<SessionState>,
+    Path(session_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let now = Utc::now();
+    let res = sqlx::query_as::<_, Session>(
+        "UPDATE sessions
+         SET status = 'active',
+             paused_seconds = paused_seconds + EXTRACT(EPOCH FROM ($1 - paused_at))::INT,
+             paused_at = NULL,
+             updated_at = $1
+         WHERE id = $2 AND status = 'paused' RETURNING *"
+    )
+    .bind(now)
+    .bind(session_id)
+    .fetch_optional(&pool)
+    .await;
+
+    match res {
+        Ok(Some(session)) => {
+            crate::socket::resume_session(&pool, &session_state, session_id, session.paused_seconds).await;
+            axum::Json(SessionResponse::from(session)).into_response()
+        },
+        Ok(None) => (StatusCode::CONFLICT, "Session is not paused").into_response(),
+        Err(e) => db_error(e, "Failed to resume session"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ContinueSessionRequest {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+// Picks a session up on another day: the original is ended and a planned continuation
+// inherits its game state, combat included, and its bans
+pub async fn continue_session(
+    Extension(pool): Extension<PgPool>,
+    Path(session_id): Path<Uuid>,
+    payload: Option<Json<ContinueSessionRequest>>,
+) -> impl IntoResponse {
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(e, "Failed to continue session"),
+    };
+
+    let previous = sqlx::query_as::<_, Session>("SELECT * FROM sessions WHERE id = $1 FOR UPDATE")
+        .bind(session_id)
+        .fetch_one(&mut *tx)
+        .await;
+    let previous = match previous {
+        Ok(session) if session.started_at.is_none() => {
+            return (StatusCode::CONFLICT, "Only a session that has started can be continued").into_response();
+        }
+        Ok(session) => session,
+        Err(e) => return db_error(e, "Failed to fetch session"),
+    };
+
+    let now = Utc::now();
+    let ended = sqlx::query(
+        "UPDATE sessions
+         SET status = 'ended',
+             ended_at = COALESCE(ended_at, $1),
+             paused_seconds = paused_seconds + COALESCE(EXTRACT(EPOCH FROM ($1 - paused_at))::INT, 0),
+             paused_at = NULL,
+             updated_at = $1
+         WHERE id = $2"
+    )
+    .bind(now)
+    .bind(session_id)
+    .execute(&mut *tx)
+    .await;
+    if let Err(e) = ended {
+        return db_error(e, "Failed to end session");
+    }
+
+    let payload = payload.map(|Json(payload)| payload);
+    let name = payload.as_ref().and_then(|p| p.name.clone()).unwrap_or_else(|| previous.name.clone());
+    let description = payload.and_then(|p| p.description).or(previous.description);
+
+    // The unique index on previous_session_id turns a second continuation into a 409
+    let res = sqlx::query_as::<_, Session>(
+        "INSERT INTO sessions (id, campaign_id, name, description, status, game_state, banned_user_ids, previous_session_id, created_at, updated_at)
+         SELECT $1, campaign_id, $2, $3, 'planned', game_state, banned_user_ids, id, $4, $4 FROM sessions WHERE id = $5
+         RETURNING *"
+    )
+    .bind(Uuid::new_v4())
+    .bind(&name)
+    .bind(&description)
+    .bind(now)
+    .bind(session_id)
+    .fetch_one(&mut *tx)
+    .await;
+
+    let session = match res {
+        Ok(session) => session,
+        Err(e) => return db_error(e, "Failed to continue session"),
+    };
+
+    // A fight still running picks up where it left off; ended ones stay with the old session
+    let moved = sqlx::query("UPDATE combats SET session_id = $1 WHERE session_id = $2 AND ended_at IS NULL")
+        .bind(session.id)
+        .bind(session_id)
+        .execute(&mut *tx)
+        .await;
+    if let Err(e) = moved {
+        return db_error(e, "Failed to continue session");
+    }
+    if let Err(e) = tx.commit().await {
+        return db_error(e, "Failed to continue session");
+    }
+    (StatusCode::CREATED, axum::Json(SessionResponse::from(session))).into_response()
+}
+
+#[derive(Serialize)]
+pub struct PresenceResponse {
+    pub session_id: Uuid,
+    pub participants: Vec<PresenceEntry>,
+}
+
+pub async fn get_session_presence(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Extension(session_state): Extension<SessionState>,
+    Path(session_id): Path<Uuid>,
+) -> impl IntoResponse {
+    // Check if user has access to this session
+    if let Err(response) = authorize(&pool, user.0, Resource::Session(session_id), Access::Member, "Access denied to this session").await {
+        return response;
+    }
+
+    let participants = session_presence(&session_state, session_id).await;
+    axum::Json(PresenceResponse { session_id, participants }).into_response()
+}
+
+#[derive(Serialize)]
+pub struct UserPresenceResponse {
+    pub user_id: Uuid,
+    pub username: String,
+    pub online: bool,
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+// Dashboard view: online status of everyone the user shares a campaign with
+pub async fn get_presence(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Extension(session_state): Extension<SessionState>,
+) -> impl IntoResponse {
+    let campaign_ids: Vec<Uuid> = match campaign_roles(&pool, user.0).await {
+        Ok(roles) => roles.into_keys().collect(),
+        Err(e) => return db_error(e, "Failed to fetch presence"),
+    };
+    let users = sqlx::query_as::<_, (Uuid, String, Option<DateTime<Utc>>)>(
+        "SELECT u.id, u.username, u.last_seen_at FROM users u 
+         WHERE u.id <> $1 AND u.id IN (
+             SELECT dm_id FROM campaigns WHERE id = ANY($2) 
+             UNION 
+             SELECT player_id FROM campaign_players WHERE campaign_id = ANY($2)
+         ) 
+         ORDER BY u.username"
+    )
+    .bind(user.0)
+    .bind(&campaign_ids)
+    .fetch_all(&pool)
+    .await;
+
+    let users = match users {
+        Ok(users) => users,
+        Err(e) => return db_error(e, "Failed to fetch presence"),
+    };
+
+    let user_ids: Vec<Uuid> = users.iter().map(|(id, ..)| *id).collect();
+    let online = session_state.presence.online_users(&user_ids).await;
+    let responses: Vec<UserPresenceResponse> = users
+        .into_iter()
+        .map(|(user_id, username, last_seen_at)| UserPresenceResponse {
+            user_id,
+            username,
+            online: online.contains(&user_id),
+            last_seen_at,
+        })
+        .collect();
+    axum::Json(responses).into_response()
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WsTicketResponse {
+    pub ticket: String,
+    pub expires_in: i64,
+}
+
+pub async fn create_ws_ticket(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Extension(session_state): Extension<SessionState>,
+    Path(session_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let scope = match authorize(&pool, user.0, Resource::Session(session_id), Access::Member, "Access denied to this session").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    let access = sqlx::query_as::<_, (String, bool)>(
+        "SELECT u.username, u.id = ANY(s.banned_user_ids) FROM sessions s INNER JOIN users u ON u.id = $2 WHERE s.id = $1"
+    )
+    .bind(session_id)
+    .bind(user.0)
+    .fetch_one(&pool)
+    .await;
+
+    let username = match access {
+        Ok((_, true)) => return (StatusCode::FORBIDDEN, "You have been banned from this session").into_response(),
+        Ok((username, false)) => username,
+        Err(e) => return db_error(e, "Failed to verify session access"),
+    };
+    let is_dm = scope.is_dm();
+
+    let ticket = WsTicket {
+        user_id: user.0,
+        username,
+        session_id,
+        is_dm,
+        expires_at: Utc::now() + chrono::Duration::seconds(WS_TICKET_TTL_SECS),
+    };
+    match issue_ws_ticket(&session_state, ticket).await {
+        Ok(ticket) => (
+            StatusCode::CREATED,
+            axum::Json(WsTicketResponse { ticket, expires_in: WS_TICKET_TTL_SECS })
+        ).into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create WebSocket ticket").into_response(),
+    }
+}
+
+// Character handlers
+#[derive(Deserialize)]
+pub struct CreateCharacterRequest {
     pub campaign_id: Uuid,
     pub name: String,
     pub race: Option<String>,
@@ -671,8 +1223,37 @@ pub struct CharacterResponse {
     pub inventory: serde_json::Value,
     pub spells: serde_json::Value,
     pub features: serde_json::Value,
+    pub xp: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    // Modifiers, proficiency, spell DC and the like, computed from the fields above
+    pub derived: DerivedStats,
+}
+
+impl From<Character> for CharacterResponse {
+    fn from(c: Character) -> Self {
+        CharacterResponse {
+            derived: derive(&c),
+            id: c.id,
+            campaign_id: c.campaign_id,
+            player_id: c.player_id,
+            name: c.name,
+            race: c.race,
+            class: c.class,
+            level: c.level,
+            hp_current: c.hp_current,
+            hp_max: c.hp_max,
+            ac: c.ac,
+            speed: c.speed,
+            stats: c.stats,
+            inventory: c.inventory,
+            spells: c.spells,
+            features: c.features,
+            xp: c.xp,
+            created_at: c.created_at,
+            updated_at: c.updated_at,
+        }
+    }
 }
 
 pub async fn create_character(
@@ -681,17 +1262,9 @@ pub async fn create_character(
     Json(payload): Json<CreateCharacterRequest>,
 ) -> impl IntoResponse {
     // Check if user has access to this campaign
-    let has_access = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM campaigns WHERE id = $1 AND dm_id = $2) OR EXISTS(SELECT 1 FROM campaign_players WHERE campaign_id = $1 AND player_id = $2)"
-    )
-    .bind(payload.campaign_id)
-    .bind(user.0)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or(false);
-
-    if !has_access {
-        return (StatusCode::FORBIDDEN, "Access denied to this campaign").into_response();
+    // Observers don't play, so they don't get characters
+    if let Err(response) = authorize(&pool, user.0, Resource::Campaign(payload.campaign_id), Access::Play, "Access denied to this campaign").await {
+        return response;
     }
 
     let character_id = Uuid::new_v4();
@@ -724,28 +1297,11 @@ pub async fn create_character(
 
     match res {
         Ok(character) => {
-            let response = CharacterResponse {
-                id: character.id,
-                campaign_id: character.campaign_id,
-                player_id: character.player_id,
-                name: character.name,
-                race: character.race,
-                class: character.class,
-                level: character.level,
-                hp_current: character.hp_current,
-                hp_max: character.hp_max,
-                ac: character.ac,
-                speed: character.speed,
-                stats: character.stats,
-                inventory: character.inventory,
-                spells: character.spells,
-                features: character.features,
-                created_at: character.created_at,
-                updated_at: character.updated_at,
-            };
+            record_revision(&pool, &character, None, user.0).await;
+            let response = CharacterResponse::from(character);
             (StatusCode::CREATED, axum::Json(response)).into_response()
         },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create character").into_response(),
+        Err(e) => db_error(e, "Failed to create character"),
     }
 }
 
@@ -753,40 +1309,21 @@ pub async fn list_characters(
     Extension(pool): Extension<PgPool>,
     Extension(user): Extension<AuthUser>,
 ) -> impl IntoResponse {
-    let characters = sqlx::query_as::<_, Character>(
-        "SELECT c.* FROM characters c 
-         INNER JOIN campaigns cam ON c.campaign_id = cam.id 
-         WHERE cam.dm_id = $1 OR c.campaign_id IN (SELECT campaign_id FROM campaign_players WHERE player_id = $1)
-         ORDER BY c.created_at DESC"
-    )
-    .bind(user.0)
-    .fetch_all(&pool)
+    let campaign_ids: Vec<Uuid> = match campaign_roles(&pool, user.0).await {
+        Ok(roles) => roles.into_keys().collect(),
+        Err(e) => return db_error(e, "Failed to fetch characters"),
+    };
+    let characters = sqlx::query_as::<_, Character>("SELECT * FROM characters WHERE campaign_id = ANY($1) ORDER BY created_at DESC")
+        .bind(&campaign_ids)
+        .fetch_all(&pool)
     .await;
 
     match characters {
         Ok(characters) => {
-            let responses: Vec<CharacterResponse> = characters.into_iter().map(|c| CharacterResponse {
-                id: c.id,
-                campaign_id: c.campaign_id,
-                player_id: c.player_id,
-                name: c.name,
-                race: c.race,
-                class: c.class,
-                level: c.level,
-                hp_current: c.hp_current,
-                hp_max: c.hp_max,
-                ac: c.ac,
-                speed: c.speed,
-                stats: c.stats,
-                inventory: c.inventory,
-                spells: c.spells,
-                features: c.features,
-                created_at: c.created_at,
-                updated_at: c.updated_at,
-            }).collect();
+            let responses: Vec<CharacterResponse> = characters.into_iter().map(CharacterResponse::from).collect();
             axum::Json(responses).into_response()
         },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch characters").into_response(),
+        Err(e) => db_error(e, "Failed to fetch characters"),
     }
 }
 
@@ -795,41 +1332,42 @@ pub async fn get_character(
     Extension(user): Extension<AuthUser>,
     Path(character_id): Path<Uuid>,
 ) -> impl IntoResponse {
-    let character = sqlx::query_as::<_, Character>(
-        "SELECT c.* FROM characters c 
-         INNER JOIN campaigns cam ON c.campaign_id = cam.id 
-         WHERE c.id = $1 AND (cam.dm_id = $2 OR c.campaign_id IN (SELECT campaign_id FROM campaign_players WHERE player_id = $2))"
-    )
-    .bind(character_id)
-    .bind(user.0)
-    .fetch_optional(&pool)
+    if let Err(response) = authorize_member(&pool, user.0, Resource::Character(character_id), "Character not found").await {
+        return response;
+    }
+    let character = sqlx::query_as::<_, Character>("SELECT * FROM characters WHERE id = $1")
+        .bind(character_id)
+        .fetch_optional(&pool)
     .await;
 
     match character {
         Ok(Some(character)) => {
-            let response = CharacterResponse {
-                id: character.id,
-                campaign_id: character.campaign_id,
-                player_id: character.player_id,
-                name: character.name,
-                race: character.race,
-                class: character.class,
-                level: character.level,
-                hp_current: character.hp_current,
-                hp_max: character.hp_max,
-                ac: character.ac,
-                speed: character.speed,
-                stats: character.stats,
-                inventory: character.inventory,
-                spells: character.spells,
-                features: character.features,
-                created_at: character.created_at,
-                updated_at: character.updated_at,
-            };
+            let response = CharacterResponse::from(character);
             axum::Json(response).into_response()
         },
         Ok(None) => (StatusCode::NOT_FOUND, "Character not found").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch character").into_response(),
+        Err(e) => db_error(e, "Failed to fetch character"),
+    }
+}
+
+// The witnessed ability score roll for a character, if it has been rolled live
+pub async fn get_ability_score_roll(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(character_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize_member(&pool, user.0, Resource::Character(character_id), "No ability score roll for this character").await {
+        return response;
+    }
+    let roll = sqlx::query_as::<_, AbilityScoreRoll>("SELECT * FROM ability_score_rolls WHERE character_id = $1")
+        .bind(character_id)
+        .fetch_optional(&pool)
+    .await;
+
+    match roll {
+        Ok(Some(roll)) => axum::Json(roll).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "No ability score roll for this character").into_response(),
+        Err(e) => db_error(e, "Failed to fetch ability score roll"),
     }
 }
 
@@ -856,19 +1394,8 @@ pub async fn update_character(
     Json(payload): Json<UpdateCharacterRequest>,
 ) -> impl IntoResponse {
     // Check if user owns this character or is DM of the campaign
-    let has_access = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM characters c 
-         INNER JOIN campaigns cam ON c.campaign_id = cam.id 
-         WHERE c.id = $1 AND (c.player_id = $2 OR cam.dm_id = $2))"
-    )
-    .bind(character_id)
-    .bind(user.0)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or(false);
-
-    if !has_access {
-        return (StatusCode::FORBIDDEN, "Access denied to this character").into_response();
+    if let Err(response) = authorize(&pool, user.0, Resource::Character(character_id), Access::Modify, "Access denied to this character").await {
+        return response;
     }
 
     let now = Utc::now();
@@ -908,28 +1435,11 @@ pub async fn update_character(
 
     match res {
         Ok(character) => {
-            let response = CharacterResponse {
-                id: character.id,
-                campaign_id: character.campaign_id,
-                player_id: character.player_id,
-                name: character.name,
-                race: character.race,
-                class: character.class,
-                level: character.level,
-                hp_current: character.hp_current,
-                hp_max: character.hp_max,
-                ac: character.ac,
-                speed: character.speed,
-                stats: character.stats,
-                inventory: character.inventory,
-                spells: character.spells,
-                features: character.features,
-                created_at: character.created_at,
-                updated_at: character.updated_at,
-            };
+            record_revision(&pool, &character, None, user.0).await;
+            let response = CharacterResponse::from(character);
             axum::Json(response).into_response()
         },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update character").into_response(),
+        Err(e) => db_error(e, "Failed to update character"),
     }
 }
 
@@ -939,19 +1449,8 @@ pub async fn delete_character(
     Path(character_id): Path<Uuid>,
 ) -> impl IntoResponse {
     // Check if user owns this character or is DM of the campaign
-    let has_access = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM characters c 
-         INNER JOIN campaigns cam ON c.campaign_id = cam.id 
-         WHERE c.id = $1 AND (c.player_id = $2 OR cam.dm_id = $2))"
-    )
-    .bind(character_id)
-    .bind(user.0)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or(false);
-
-    if !has_access {
-        return (StatusCode::FORBIDDEN, "Access denied to this character").into_response();
+    if let Err(response) = authorize(&pool, user.0, Resource::Character(character_id), Access::Modify, "Access denied to this character").await {
+        return response;
     }
 
     let res = sqlx::query("DELETE FROM characters WHERE id = $1")
@@ -961,7 +1460,7 @@ pub async fn delete_character(
 
     match res {
         Ok(_) => (StatusCode::OK, "Character deleted").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete character").into_response(),
+        Err(e) => db_error(e, "Failed to delete character"),
     }
 }
 
@@ -981,43 +1480,26 @@ pub async fn update_initiative(
     Json(payload): Json<UpdateInitiativeRequest>,
 ) -> impl IntoResponse {
     // Check if user is DM of this session's campaign
-    let is_dm = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM sessions s 
-         INNER JOIN campaigns c ON s.campaign_id = c.id 
-         WHERE s.id = $1 AND c.dm_id = $2)"
-    )
-    .bind(payload.session_id)
-    .bind(user.0)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or(false);
-
-    if !is_dm {
-        return (StatusCode::FORBIDDEN, "Only the DM can update initiative").into_response();
-    }
-
-    // Get current game state
-    let session = sqlx::query_as::<_, Session>(
-        "SELECT * FROM sessions WHERE id = $1"
-    )
-    .bind(payload.session_id)
-    .fetch_one(&pool)
-    .await;
+    let scope = match authorize(&pool, user.0, Resource::Session(payload.session_id), Access::Permitted(Permission::ManageInitiative), "Only the DM can update initiative").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
 
-    let session = match session {
-        Ok(s) => s,
-        Err(_) => return (StatusCode::NOT_FOUND, "Session not found").into_response(),
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(e, "Failed to update initiative"),
     };
 
-    // Parse current game state
-    let mut game_state: GameState = serde_json::from_value(session.game_state.clone())
-        .unwrap_or_else(|_| GameState {
-            initiative_order: Vec::new(),
-            current_turn: None,
-            round: 1,
-            combat_active: false,
-            conditions: Vec::new(),
-        });
+    // Update the running fight, or start one
+    let (combat_id, mut game_state) = match crate::combat::lock_active(&mut tx, payload.session_id).await {
+        Ok(Some((combat_id, game_state))) => (Some(combat_id), game_state),
+        Ok(None) if payload.combat_active == Some(false) => return (StatusCode::BAD_REQUEST, "Combat is not active").into_response(),
+        Ok(None) => match crate::combat::new_state(&mut tx, payload.session_id, Vec::new()).await {
+            Ok(game_state) => (None, game_state),
+            Err(e) => return db_error(e, "Failed to update initiative"),
+        },
+        Err(e) => return db_error(e, "Failed to update initiative"),
+    };
 
     // Update game state
     game_state.initiative_order = payload.initiative_order;
@@ -1029,20 +1511,21 @@ pub async fn update_initiative(
         game_state.combat_active = combat_active;
     }
 
-    // Save updated game state
-    let now = Utc::now();
-    let res = sqlx::query_as::<_, Session>(
-        "UPDATE sessions SET game_state = $1, updated_at = $2 WHERE id = $3 RETURNING *"
-    )
-    .bind(serde_json::to_value(game_state).unwrap())
-    .bind(now)
-    .bind(payload.session_id)
-    .fetch_one(&pool)
-    .await;
+    let combat_id = match crate::combat::save(&mut tx, payload.session_id, combat_id, &game_state).await {
+        Ok((combat_id, _)) => combat_id,
+        Err(e) => return db_error(e, "Failed to update initiative"),
+    };
 
-    match res {
+    // Ending combat here suggests rewards just like EndCombat over the socket
+    if !game_state.combat_active {
+        if let Err(e) = crate::rewards::suggest_rewards(&mut tx, payload.session_id, combat_id, scope.campaign_id, &game_state).await {
+            return db_error(e, "Failed to suggest encounter rewards");
+        }
+    }
+
+    match tx.commit().await {
         Ok(_) => (StatusCode::OK, "Initiative updated").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update initiative").into_response(),
+        Err(e) => db_error(e, "Failed to update initiative"),
     }
 }
 
@@ -1059,19 +1542,8 @@ pub async fn update_character_hp(
     Json(payload): Json<UpdateCharacterHPRequest>,
 ) -> impl IntoResponse {
     // Check if user owns this character or is DM of the campaign
-    let has_access = sqlx::query_scalar::<_, bool>(
-        "SELECT EXISTS(SELECT 1 FROM characters c 
-         INNER JOIN campaigns cam ON c.campaign_id = cam.id 
-         WHERE c.id = $1 AND (c.player_id = $2 OR cam.dm_id = $2))"
-    )
-    .bind(character_id)
-    .bind(user.0)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or(false);
-
-    if !has_access {
-        return (StatusCode::FORBIDDEN, "Access denied to this character").into_response();
+    if let Err(response) = authorize(&pool, user.0, Resource::Character(character_id), Access::Modify, "Access denied to this character").await {
+        return response;
     }
 
     let now = Utc::now();
@@ -1087,28 +1559,11 @@ pub async fn update_character_hp(
 
     match res {
         Ok(character) => {
-            let response = CharacterResponse {
-                id: character.id,
-                campaign_id: character.campaign_id,
-                player_id: character.player_id,
-                name: character.name,
-                race: character.race,
-                class: character.class,
-                level: character.level,
-                hp_current: character.hp_current,
-                hp_max: character.hp_max,
-                ac: character.ac,
-                speed: character.speed,
-                stats: character.stats,
-                inventory: character.inventory,
-                spells: character.spells,
-                features: character.features,
-                created_at: character.created_at,
-                updated_at: character.updated_at,
-            };
+            record_revision(&pool, &character, None, user.0).await;
+            let response = CharacterResponse::from(character);
             axum::Json(response).into_response()
         },
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update character HP").into_response(),
+        Err(e) => db_error(e, "Failed to update character HP"),
     }
 }
 
@@ -1130,101 +1585,96 @@ pub struct EventLogResponse {
     pub created_at: DateTime<Utc>,
 }
 
+impl From<EventLog> for EventLogResponse {
+    fn from(e: EventLog) -> Self {
+        EventLogResponse {
+            id: e.id,
+            session_id: e.session_id,
+            event_type: e.event_type,
+            event_data: e.event_data,
+            created_by: e.created_by,
+            created_at: e.created_at,
+        }
+    }
+}
+
 pub async fn create_event_log(
     Extension(pool): Extension<PgPool>,
     Extension(user): Extension<AuthUser>,
+    Extension(session_state): Extension<SessionState>,
     Json(payload): Json<CreateEventLogRequest>,
 ) -> impl IntoResponse {
     // Check if user has access to this session
-    let session_access = sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM sessions s 
-         INNER JOIN campaigns c ON s.campaign_id = c.id 
-         WHERE s.id = $1 AND (c.dm_id = $2 OR s.campaign_id IN (SELECT campaign_id FROM campaign_players WHERE player_id = $2))"
-    )
-    .bind(payload.session_id)
-    .bind(user.0)
-    .fetch_one(&pool)
-    .await;
+    if let Err(response) = authorize(&pool, user.0, Resource::Session(payload.session_id), Access::Member, "Access denied to this session").await {
+        return response;
+    }
 
-    match session_access {
-        Ok(count) if count > 0 => {
-            let event_id = Uuid::new_v4();
-            let now = Utc::now();
-            
-            let event_log = sqlx::query_as::<_, EventLog>(
-                "INSERT INTO event_logs (id, session_id, event_type, event_data, created_by, created_at) 
-                 VALUES ($1, $2, $3, $4, $5, $6) RETURNING *"
-            )
-            .bind(event_id)
-            .bind(payload.session_id)
-            .bind(&payload.event_type)
-            .bind(&payload.event_data)
-            .bind(user.0)
-            .bind(now)
-            .fetch_one(&pool)
-            .await;
-
-            match event_log {
-                Ok(event) => (
-                    StatusCode::CREATED,
-                    axum::Json(EventLogResponse {
-                        id: event.id,
-                        session_id: event.session_id,
-                        event_type: event.event_type,
-                        event_data: event.event_data,
-                        created_by: event.created_by,
-                        created_at: event.created_at,
-                    })
-                ).into_response(),
-                Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create event log").into_response(),
-            }
-        }
-        _ => (StatusCode::FORBIDDEN, "Access denied to this session").into_response(),
+    let event_id = Uuid::new_v4();
+    let now = Utc::now();
+    let response = EventLogResponse {
+        id: event_id,
+        session_id: payload.session_id,
+        event_type: payload.event_type.clone(),
+        event_data: payload.event_data.clone(),
+        created_by: Some(user.0),
+        created_at: now,
+    };
+
+    // Saved and broadcast to the session like a CreateEventLog sent over the socket
+    let event = DomainEvent::EventLogged {
+        event_id,
+        session_id: payload.session_id,
+        created_by: user.0,
+        event_type: payload.event_type,
+        event_data: payload.event_data,
+        at: now,
+    };
+    match crate::events::publish(&pool, &session_state, &event).await {
+        Ok(()) => (StatusCode::CREATED, axum::Json(response)).into_response(),
+        Err(e) => db_error(e, "Failed to create event log"),
     }
 }
 
+// Hidden entries, such as DM-only and secret rolls, are left out for players other than
+// the one who made them. Binds $2 to whether the reader is the DM and $3 to the reader.
+pub(crate) const VISIBLE_EVENT: &str =
+    "($2 OR created_by = $3 OR NOT COALESCE((event_data->>'hidden')::boolean, FALSE))";
+
 pub async fn list_event_logs(
     Extension(pool): Extension<PgPool>,
     Extension(user): Extension<AuthUser>,
     Path(session_id): Path<Uuid>,
 ) -> impl IntoResponse {
     // Check if user has access to this session
-    let session_access = sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM sessions s 
-         INNER JOIN campaigns c ON s.campaign_id = c.id 
-         WHERE s.id = $1 AND (c.dm_id = $2 OR s.campaign_id IN (SELECT campaign_id FROM campaign_players WHERE player_id = $2))"
-    )
+    let scope = match authorize(&pool, user.0, Resource::Session(session_id), Access::Member, "Access denied to this session").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    let events = sqlx::query_as::<_, EventLog>(&format!(
+        "SELECT * FROM event_logs WHERE session_id = $1 AND {} ORDER BY created_at ASC",
+        VISIBLE_EVENT
+    ))
     .bind(session_id)
+    .bind(scope.is_dm())
     .bind(user.0)
-    .fetch_one(&pool)
+    .fetch_all(&pool)
     .await;
 
-    match session_access {
-        Ok(count) if count > 0 => {
-            let events = sqlx::query_as::<_, EventLog>(
-                "SELECT * FROM event_logs WHERE session_id = $1 ORDER BY created_at ASC"
-            )
-            .bind(session_id)
-            .fetch_all(&pool)
-            .await;
-
-            match events {
-                Ok(events) => {
-                    let responses: Vec<EventLogResponse> = events.into_iter().map(|e| EventLogResponse {
-                        id: e.id,
-                        session_id: e.session_id,
-                        event_type: e.event_type,
-                        event_data: e.event_data,
-                        created_by: e.created_by,
-                        created_at: e.created_at,
-                    }).collect();
-                    
-                    axum::Json(responses).into_response()
-                }
-                Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch event logs").into_response(),
-            }
+    match events {
+        Ok(events) => {
+            let responses: Vec<EventLogResponse> = events.into_iter().map(|e| EventLogResponse {
+                id: e.id,
+                session_id: e.session_id,
+                event_type: e.event_type,
+                event_data: e.event_data,
+                created_by: e.created_by,
+                created_at: e.created_at,
+            }).collect();
+            
+            axum::Json(responses).into_response()
         }
-        _ => (StatusCode::FORBIDDEN, "Access denied to this session").into_response(),
+        Err(e) => db_error(e, "Failed to fetch event logs"),
     }
 }
 
@@ -1234,18 +1684,21 @@ pub async fn get_event_log(
     Path(event_id): Path<Uuid>,
 ) -> impl IntoResponse {
     // Check if user has access to this event's session
-    let event = sqlx::query_as::<_, EventLog>(
-        "SELECT el.* FROM event_logs el 
-         INNER JOIN sessions s ON el.session_id = s.id 
-         INNER JOIN campaigns c ON s.campaign_id = c.id 
-         WHERE el.id = $1 AND (c.dm_id = $2 OR s.campaign_id IN (SELECT campaign_id FROM campaign_players WHERE player_id = $2))"
-    )
-    .bind(event_id)
-    .bind(user.0)
-    .fetch_optional(&pool)
-    .await;
+    let scope = match resolve(&pool, user.0, Resource::EventLog(event_id)).await {
+        Ok(Some(scope)) => scope,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Event log not found").into_response(),
+        Err(e) => return db_error(e, "Failed to fetch event log"),
+    };
+
+    let event = sqlx::query_as::<_, EventLog>(&format!("SELECT * FROM event_logs WHERE id = $1 AND {}", VISIBLE_EVENT))
+        .bind(event_id)
+        .bind(scope.is_dm())
+        .bind(user.0)
+        .fetch_optional(&pool)
+        .await;
 
     match event {
+        Ok(None) => (StatusCode::NOT_FOUND, "Event log not found").into_response(),
         Ok(Some(event)) => {
             let response = EventLogResponse {
                 id: event.id,
@@ -1257,9 +1710,352 @@ pub async fn get_event_log(
             };
             axum::Json(response).into_response()
         }
-        Ok(None) => (StatusCode::NOT_FOUND, "Event log not found").into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to fetch event log").into_response(),
+        Err(e) => db_error(e, "Failed to fetch event log"),
+    }
+}
+
+#[derive(Serialize)]
+pub struct DieDistribution {
+    pub sides: i32,
+    pub count: i64,
+    pub average: f64,
+    // faces[i] is how often the die came up i + 1
+    pub faces: Vec<i64>,
+}
+
+#[derive(Serialize)]
+pub struct PlayerRollStats {
+    pub user_id: Uuid,
+    pub username: String,
+    pub rolls: i64,
+    pub average_result: f64,
+    pub nat_1s: i64,
+    pub nat_20s: i64,
+    pub dice: Vec<DieDistribution>,
+}
+
+#[derive(Serialize)]
+pub struct RollStats {
+    pub session_id: Uuid,
+    pub players: Vec<PlayerRollStats>,
+    // Every visible die in the session, whoever rolled it
+    pub dice: Vec<DieDistribution>,
+}
+
+// Every roll the reader can see: `dice_roll` events plus the inline rolls in chat messages
+const VISIBLE_ROLLS: &str = "visible AS (
+        SELECT * FROM event_logs WHERE session_id = $1 AND event_type IN ('dice_roll', 'chat_message') AND {visible}
+    ), rolls AS (
+        SELECT id AS event_id, created_by, event_data AS roll FROM visible WHERE event_type = 'dice_roll'
+        UNION ALL
+        SELECT id, created_by, event_data->'damage' FROM visible WHERE event_type = 'dice_roll' AND event_data ? 'damage'
+        UNION ALL
+        SELECT v.id, v.created_by, r.roll FROM visible v, jsonb_array_elements(COALESCE(v.event_data->'rolls', '[]')) AS r(roll)
+        WHERE v.event_type = 'chat_message'
+    )";
+
+// Face counts come in ordered by die size and face
+fn distributions(rows: &[(Option<Uuid>, i32, i32, i64)], user_id: Option<Uuid>) -> Vec<DieDistribution> {
+    let mut dice: Vec<DieDistribution> = Vec::new();
+    for &(_, sides, value, count) in rows.iter().filter(|row| row.0 == user_id) {
+        if sides < 1 || value < 1 || value > sides {
+            continue;
+        }
+        if dice.last().map(|die| die.sides) != Some(sides) {
+            dice.push(DieDistribution { sides, count: 0, average: 0.0, faces: vec![0; sides as usize] });
+        }
+        let die = dice.last_mut().unwrap();
+        die.faces[value as usize - 1] = count;
+        die.count += count;
     }
+    for die in &mut dice {
+        let sum: i64 = die.faces.iter().enumerate().map(|(i, count)| (i as i64 + 1) * count).sum();
+        die.average = sum as f64 / die.count as f64;
+    }
+    dice
+}
+
+// Every physical die counts, including ones dropped by advantage or keep/drop modifiers
+pub async fn get_roll_stats(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(session_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let scope = match authorize(&pool, user.0, Resource::Session(session_id), Access::Member, "Access denied to this session").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    let ctes = VISIBLE_ROLLS.replace("{visible}", VISIBLE_EVENT);
+
+    let players = sqlx::query_as::<_, (Uuid, String, i64, f64)>(&format!(
+        "WITH {}
+         SELECT r.created_by, u.username, COUNT(*), COALESCE(AVG((r.roll->>'result')::numeric), 0)::float8
+         FROM rolls r INNER JOIN users u ON u.id = r.created_by
+         GROUP BY r.created_by, u.username
+         ORDER BY u.username",
+        ctes
+    ))
+    .bind(session_id)
+    .bind(scope.is_dm())
+    .bind(user.0)
+    .fetch_all(&pool)
+    .await;
+    let players = match players {
+        Ok(players) => players,
+        Err(e) => return db_error(e, "Failed to fetch roll statistics"),
+    };
+
+    // Per player, plus a NULL-player row set for the whole table
+    let faces = sqlx::query_as::<_, (Option<Uuid>, i32, i32, i64)>(&format!(
+        "WITH {}
+         SELECT r.created_by, (f->>'sides')::int AS sides, (f->>'value')::int AS value, COUNT(*)
+         FROM rolls r, jsonb_array_elements(COALESCE(r.roll->'faces', '[]')) AS f
+         WHERE r.created_by IS NOT NULL
+         GROUP BY GROUPING SETS ((r.created_by, sides, value), (sides, value))
+         ORDER BY sides, value",
+        ctes
+    ))
+    .bind(session_id)
+    .bind(scope.is_dm())
+    .bind(user.0)
+    .fetch_all(&pool)
+    .await;
+    let faces = match faces {
+        Ok(faces) => faces,
+        Err(e) => return db_error(e, "Failed to fetch roll statistics"),
+    };
+
+    let players = players
+        .into_iter()
+        .map(|(user_id, username, rolls, average_result)| {
+            let dice = distributions(&faces, Some(user_id));
+            let d20 = dice.iter().find(|die| die.sides == 20);
+            PlayerRollStats {
+                user_id,
+                username,
+                rolls,
+                average_result,
+                nat_1s: d20.map_or(0, |die| die.faces[0]),
+                nat_20s: d20.map_or(0, |die| die.faces[19]),
+                dice,
+            }
+        })
+        .collect();
+
+    axum::Json(RollStats { session_id, players, dice: distributions(&faces, None) }).into_response()
+}
+
+#[derive(Serialize)]
+pub struct ReplayedRoll {
+    // The `dice_roll` or `chat_message` event the roll was saved in
+    pub event_id: Uuid,
+    pub roll_counter: u64,
+    pub dice: String,
+    pub recorded: Vec<i32>,
+    pub recorded_result: i32,
+    pub replayed: Vec<i32>,
+    pub replayed_result: i32,
+    // The recorded seed is the one the counter gives, and rolling it again gives the same dice
+    pub matches: bool,
+}
+
+#[derive(Serialize)]
+pub struct RollReplay {
+    pub session_id: Uuid,
+    pub rng_seed: u64,
+    // Rolls drawn from the seed so far
+    pub roll_counter: u64,
+    pub rolls: Vec<ReplayedRoll>,
+    pub all_match: bool,
+}
+
+// Recomputes every roll of a seeded session from its seed and compares it with the event
+// log. The seed predicts the rolls still to come, so players only get it once the session
+// has ended; the DM can check at any time.
+pub async fn replay_rolls(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(session_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let scope = match authorize(&pool, user.0, Resource::Session(session_id), Access::Member, "Access denied to this session").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    let session = match sqlx::query_as::<_, Session>("SELECT * FROM sessions WHERE id = $1").bind(session_id).fetch_one(&pool).await {
+        Ok(session) => session,
+        Err(e) => return db_error(e, "Failed to fetch session"),
+    };
+    let Some(rng_seed) = session.rng_seed.map(|seed| seed as u64) else {
+        return (StatusCode::CONFLICT, "Session was not created with a seed").into_response();
+    };
+    if !scope.is_dm() && session.status != "ended" {
+        return (StatusCode::FORBIDDEN, "Rolls can be replayed once the session has ended").into_response();
+    }
+
+    let recorded = sqlx::query_as::<_, (Uuid, serde_json::Value)>(&format!(
+        "WITH {}
+         SELECT event_id, roll FROM rolls WHERE roll ? 'roll_counter'
+         ORDER BY (roll->>'roll_counter')::bigint",
+        VISIBLE_ROLLS.replace("{visible}", VISIBLE_EVENT)
+    ))
+    .bind(session_id)
+    .bind(scope.is_dm())
+    .bind(user.0)
+    .fetch_all(&pool)
+    .await;
+    let recorded = match recorded {
+        Ok(recorded) => recorded,
+        Err(e) => return db_error(e, "Failed to fetch rolls"),
+    };
+
+    let rolls: Vec<ReplayedRoll> = recorded
+        .into_iter()
+        .filter_map(|(event_id, roll)| serde_json::from_value::<crate::socket::DiceResult>(roll).ok().map(|roll| (event_id, roll)))
+        .filter_map(|(event_id, roll)| {
+            let roll_counter = roll.roll_counter?;
+            let seed = crate::dice::session_roll_seed(rng_seed, roll_counter);
+            let replayed = crate::dice::roll_dice(&roll.dice, seed).ok();
+            let (replayed, replayed_result) = replayed.map_or((Vec::new(), 0), |replayed| (replayed.rolls, replayed.total));
+            Some(ReplayedRoll {
+                event_id,
+                roll_counter,
+                matches: seed == roll.seed && replayed == roll.rolls && replayed_result == roll.result,
+                dice: roll.dice,
+                recorded: roll.rolls,
+                recorded_result: roll.result,
+                replayed,
+                replayed_result,
+            })
+        })
+        .collect();
+
+    axum::Json(RollReplay {
+        session_id,
+        rng_seed,
+        roll_counter: session.roll_counter as u64,
+        all_match: rolls.iter().all(|roll| roll.matches),
+        rolls,
+    })
+    .into_response()
+}
+
+#[derive(Serialize)]
+pub struct RollCommitment {
+    pub session_id: Uuid,
+    pub commitment: String,
+    // Nonce of the next roll drawn from it
+    pub next_nonce: u64,
+}
+
+// The commitment the session's next rolls will be drawn from, so players can note it before
+// rolling and hold the server to it when verifying
+pub async fn get_roll_commitment(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(session_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize(&pool, user.0, Resource::Session(session_id), Access::Member, "Access denied to this session").await {
+        return response;
+    }
+    match crate::fairness::current_commitment(&pool, session_id).await {
+        Ok(server_seed) => axum::Json(RollCommitment {
+            session_id,
+            commitment: server_seed.commitment,
+            next_nonce: server_seed.next_nonce as u64,
+        })
+        .into_response(),
+        Err(e) => db_error(e, "Failed to fetch roll commitment"),
+    }
+}
+
+#[derive(Serialize)]
+pub struct VerifiedRoll {
+    pub dice: String,
+    pub commitment: String,
+    // Hex; revealed by verifying
+    pub server_seed: String,
+    pub client_seed: String,
+    pub nonce: u64,
+    pub seed: u64,
+    pub recorded: Vec<i32>,
+    pub recorded_result: i32,
+    pub replayed: Vec<i32>,
+    pub replayed_result: i32,
+    // The server seed hashes to the commitment, and the roll follows from it
+    pub verified: bool,
+}
+
+#[derive(Serialize)]
+pub struct RollVerification {
+    pub roll_id: Uuid,
+    // One per roll in the event; a chat message can carry several
+    pub rolls: Vec<VerifiedRoll>,
+    pub verified: bool,
+}
+
+// Verifies a past roll against the server seed it was committed to. This reveals the seed,
+// so the session's later rolls are drawn from a fresh one.
+pub async fn verify_roll(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path((session_id, roll_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let scope = match authorize(&pool, user.0, Resource::Session(session_id), Access::Member, "Access denied to this session").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    let recorded = sqlx::query_scalar::<_, serde_json::Value>(&format!(
+        "WITH {} SELECT roll FROM rolls WHERE event_id = $4",
+        VISIBLE_ROLLS.replace("{visible}", VISIBLE_EVENT)
+    ))
+    .bind(session_id)
+    .bind(scope.is_dm())
+    .bind(user.0)
+    .bind(roll_id)
+    .fetch_all(&pool)
+    .await;
+    let recorded: Vec<crate::socket::DiceResult> = match recorded {
+        Ok(recorded) => recorded.into_iter().filter_map(|roll| serde_json::from_value(roll).ok()).collect(),
+        Err(e) => return db_error(e, "Failed to fetch roll"),
+    };
+    if recorded.is_empty() {
+        return (StatusCode::NOT_FOUND, "Roll not found").into_response();
+    }
+    if recorded.iter().any(|roll| roll.proof.is_none()) {
+        return (StatusCode::CONFLICT, "Roll was not drawn from a committed server seed").into_response();
+    }
+
+    let mut rolls = Vec::with_capacity(recorded.len());
+    for roll in recorded {
+        let Some(proof) = roll.proof else { continue };
+        let server_seed = match crate::fairness::reveal(&pool, session_id, &proof.commitment).await {
+            Ok(Some(server_seed)) => server_seed,
+            Ok(None) => return (StatusCode::CONFLICT, "Roll's commitment is not one of this session's").into_response(),
+            Err(e) => return db_error(e, "Failed to reveal server seed"),
+        };
+        let seed = crate::fairness::roll_seed(&server_seed, &proof.client_seed, proof.nonce);
+        let (replayed, replayed_result) = crate::dice::roll_dice(&roll.dice, seed)
+            .map_or((Vec::new(), 0), |replayed| (replayed.rolls, replayed.total));
+        rolls.push(VerifiedRoll {
+            verified: crate::fairness::commitment(&server_seed) == proof.commitment
+                && seed == roll.seed
+                && replayed == roll.rolls
+                && replayed_result == roll.result,
+            dice: roll.dice,
+            commitment: proof.commitment,
+            server_seed: hex::encode(&server_seed),
+            client_seed: proof.client_seed,
+            nonce: proof.nonce,
+            seed,
+            recorded: roll.rolls,
+            recorded_result: roll.result,
+            replayed,
+            replayed_result,
+        });
+    }
+
+    axum::Json(RollVerification { roll_id, verified: rolls.iter().all(|roll| roll.verified), rolls }).into_response()
 }
 
 // AI Integration handlers
@@ -1272,69 +2068,349 @@ pub struct AIRequest {
 }
 
 #[derive(Serialize)]
-pub struct AIResponse {
-    pub response: String,
-    pub tokens_used: Option<i32>,
-    pub model: String,
+pub struct AIResponse {
+    pub response: String,
+    pub tokens_used: Option<i32>,
+    pub model: String,
+}
+
+pub async fn ai_generate(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<AIRequest>,
+) -> impl IntoResponse {
+    // The request is logged to the session, so it has to be one of the user's
+    if let Some(session_id) = payload.session_id {
+        if let Err(response) = authorize(&pool, user.0, Resource::Session(session_id), Access::Member, "Access denied to this session").await {
+            return response;
+        }
+    }
+
+    // For now, return a mock response
+    // TODO: Implement actual AI integration
+    let response = match payload.request_type.as_str() {
+        "npc" => {
+            "Generated NPC: A mysterious figure with a weathered cloak and piercing eyes. They seem to know more than they let on...".to_string()
+        }
+        "location" => {
+            "Generated Location: A dimly lit tavern with smoke curling from the fireplace. The wooden beams creak with age, and the air is thick with the smell of ale and adventure.".to_string()
+        }
+        "encounter" => {
+            "Generated Encounter: A group of bandits has set up an ambush in the forest. They're well-armed and seem desperate, suggesting they might be open to negotiation.".to_string()
+        }
+        "description" => {
+            "Enhanced Description: The ancient castle looms before you, its weathered stone walls bearing the scars of countless battles. Torches flicker in the arrow slits, casting dancing shadows that seem to move of their own accord.".to_string()
+        }
+        "chat" => {
+            "AI Assistant: Based on the current situation, I'd suggest considering the diplomatic approach. The goblins seem nervous and might be more interested in survival than combat.".to_string()
+        }
+        _ => {
+            "AI Response: I'm here to help with your D&D session. What would you like me to assist with?".to_string()
+        }
+    };
+
+    // Log the AI request as an event if session_id is provided
+    if let Some(session_id) = payload.session_id {
+        let _ = sqlx::query(
+            "INSERT INTO event_logs (id, session_id, event_type, event_data, created_by, created_at) 
+             VALUES ($1, $2, $3, $4, $5, $6)"
+        )
+        .bind(Uuid::new_v4())
+        .bind(session_id)
+        .bind("ai_request")
+        .bind(serde_json::json!({
+            "prompt": payload.prompt,
+            "context": payload.context,
+            "request_type": payload.request_type,
+            "response": response
+        }))
+        .bind(user.0)
+        .bind(Utc::now())
+        .execute(&pool)
+        .await;
+    }
+
+    let ai_response = AIResponse {
+        response,
+        tokens_used: Some(150), // Mock value
+        model: "gpt-4".to_string(),
+    };
+
+    axum::Json(ai_response).into_response()
+}
+
+// Plot hook handlers
+#[derive(Deserialize)]
+pub struct GenerateHooksRequest {
+    pub character_ids: Option<Vec<Uuid>>,
+    pub hooks_per_character: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct CharacterHooks {
+    pub character_id: Uuid,
+    pub character_name: String,
+    pub hooks: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct GenerateHooksResponse {
+    pub campaign_id: Uuid,
+    pub characters: Vec<CharacterHooks>,
+    pub tokens_used: Option<i32>,
+    pub model: String,
+}
+
+// Backstory and bonds live in the free-form stats JSON until characters get dedicated columns
+fn character_story_field(character: &Character, key: &str) -> Vec<String> {
+    match character.stats.get(key) {
+        Some(serde_json::Value::String(text)) if !text.trim().is_empty() => vec![text.trim().to_string()],
+        Some(serde_json::Value::Array(items)) => items
+            .iter()
+            .filter_map(|item| item.as_str())
+            .filter(|text| !text.trim().is_empty())
+            .map(|text| text.trim().to_string())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn build_hooks_prompt(character: &Character, recent_events: &[String], count: usize) -> String {
+    let mut prompt = format!(
+        "Write {} short, distinct plot hooks for a D&D character. One hook per line, no numbering.\n\nCharacter: {} (level {} {} {})\n",
+        count,
+        character.name,
+        character.level,
+        character.race.as_deref().unwrap_or("unknown race"),
+        character.class.as_deref().unwrap_or("adventurer"),
+    );
+    let backstory = character_story_field(character, "backstory");
+    if !backstory.is_empty() {
+        prompt.push_str(&format!("Backstory: {}\n", backstory.join(" ")));
+    }
+    let bonds = character_story_field(character, "bonds");
+    if !bonds.is_empty() {
+        prompt.push_str(&format!("Bonds: {}\n", bonds.join("; ")));
+    }
+    if !recent_events.is_empty() {
+        prompt.push_str("\nRecent events in the campaign:\n");
+        for event in recent_events {
+            prompt.push_str(&format!("- {}\n", event));
+        }
+    }
+    prompt
+}
+
+// Template hooks used when no AI provider is configured or the provider fails
+fn fallback_hooks(character: &Character, recent_events: &[String], count: usize) -> Vec<String> {
+    let mut hooks = Vec::new();
+    for bond in character_story_field(character, "bonds") {
+        hooks.push(format!("A messenger arrives with urgent news concerning {}: {} is needed, and quickly.", bond, character.name));
+    }
+    if let Some(backstory) = character_story_field(character, "backstory").first() {
+        let first_sentence = backstory.split('.').next().unwrap_or(backstory).trim();
+        hooks.push(format!("Someone from {}'s past resurfaces, stirring up old memories: \"{}\".", character.name, first_sentence));
+    }
+    if let Some(event) = recent_events.first() {
+        hooks.push(format!("The fallout from a recent {} draws {} into a matter only they can settle.", event, character.name));
+    }
+    hooks.push(format!(
+        "A rival {} has been asking questions about {} in every tavern in town.",
+        character.class.as_deref().unwrap_or("adventurer"),
+        character.name,
+    ));
+    hooks.truncate(count);
+    hooks
+}
+
+pub async fn generate_plot_hooks(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Json(payload): Json<GenerateHooksRequest>,
+) -> impl IntoResponse {
+    let characters = sqlx::query_as::<_, Character>(
+        "SELECT * FROM characters WHERE campaign_id = $1 AND ($2::uuid[] IS NULL OR id = ANY($2)) ORDER BY name ASC"
+    )
+    .bind(campaign_id)
+    .bind(&payload.character_ids)
+    .fetch_all(&pool)
+    .await;
+
+    let characters = match characters {
+        Ok(characters) => characters,
+        Err(e) => return db_error(e, "Failed to fetch characters"),
+    };
+
+    // Most recent session events give the AI something to tie hooks into
+    let recent_events: Vec<String> = sqlx::query_as::<_, EventLog>(
+        "SELECT el.* FROM event_logs el 
+         INNER JOIN sessions s ON el.session_id = s.id 
+         WHERE s.campaign_id = $1 AND el.event_type <> 'ai_request'
+         ORDER BY el.created_at DESC LIMIT 10"
+    )
+    .bind(campaign_id)
+    .fetch_all(&pool)
+    .await
+    .unwrap_or_default()
+    .into_iter()
+    .map(|e| format!("{}: {}", e.event_type.replace('_', " "), e.event_data))
+    .collect();
+
+    let count = payload.hooks_per_character.unwrap_or(3).clamp(1, 10);
+    let mut model = "template".to_string();
+    let mut tokens_used: Option<i32> = None;
+    let mut results = Vec::new();
+    for character in &characters {
+        let mut hooks = Vec::new();
+        if crate::demo::ai_enabled_for(&pool, user.0).await {
+            let prompt = build_hooks_prompt(character, &recent_events, count);
+            match crate::ai::complete("You are a creative assistant for a Dungeon Master.", &prompt).await {
+                Ok(completion) => {
+                    model = completion.model;
+                    if let Some(tokens) = completion.tokens_used {
+                        tokens_used = Some(tokens_used.unwrap_or(0) + tokens);
+                        usage().record(Subject::User(user.0), Metric::AiTokens, tokens as i64);
+                        usage().record(Subject::Campaign(campaign_id), Metric::AiTokens, tokens as i64);
+                    }
+                    hooks = completion
+                        .text
+                        .lines()
+                        .map(|line| line.trim().trim_start_matches(['-', '*', '•']).trim().to_string())
+                        .filter(|line| !line.is_empty())
+                        .take(count)
+                        .collect();
+                }
+                Err(e) => tracing::warn!(error = %e, character_id = %character.id, "Plot hook generation failed"),
+            }
+        }
+        if hooks.is_empty() {
+            hooks = fallback_hooks(character, &recent_events, count);
+        }
+        results.push(CharacterHooks {
+            character_id: character.id,
+            character_name: character.name.clone(),
+            hooks,
+        });
+    }
+
+    axum::Json(GenerateHooksResponse {
+        campaign_id,
+        characters: results,
+        tokens_used,
+        model,
+    }).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct AcceptHookRequest {
+    pub character_id: Option<Uuid>,
+    pub hook: String,
+    pub title: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct QuestResponse {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub character_id: Option<Uuid>,
+    pub title: String,
+    pub description: Option<String>,
+    pub status: String,
+    pub source: String,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
-pub async fn ai_generate(
+pub async fn accept_plot_hook(
     Extension(pool): Extension<PgPool>,
     Extension(user): Extension<AuthUser>,
-    Json(payload): Json<AIRequest>,
+    Path(campaign_id): Path<Uuid>,
+    Json(payload): Json<AcceptHookRequest>,
 ) -> impl IntoResponse {
-    // For now, return a mock response
-    // TODO: Implement actual AI integration
-    let response = match payload.request_type.as_str() {
-        "npc" => {
-            format!("Generated NPC: A mysterious figure with a weathered cloak and piercing eyes. They seem to know more than they let on...")
-        }
-        "location" => {
-            format!("Generated Location: A dimly lit tavern with smoke curling from the fireplace. The wooden beams creak with age, and the air is thick with the smell of ale and adventure.")
-        }
-        "encounter" => {
-            format!("Generated Encounter: A group of bandits has set up an ambush in the forest. They're well-armed and seem desperate, suggesting they might be open to negotiation.")
-        }
-        "description" => {
-            format!("Enhanced Description: The ancient castle looms before you, its weathered stone walls bearing the scars of countless battles. Torches flicker in the arrow slits, casting dancing shadows that seem to move of their own accord.")
-        }
-        "chat" => {
-            format!("AI Assistant: Based on the current situation, I'd suggest considering the diplomatic approach. The goblins seem nervous and might be more interested in survival than combat.")
+    if payload.hook.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "Hook text is required").into_response();
+    }
+
+    if let Some(character_id) = payload.character_id {
+        let in_campaign = match resolve(&pool, user.0, Resource::Character(character_id)).await {
+            Ok(scope) => scope.is_some_and(|scope| scope.contains(campaign_id)),
+            Err(e) => return db_error(e, "Failed to verify character"),
+        };
+
+        if !in_campaign {
+            return (StatusCode::BAD_REQUEST, "Character does not belong to this campaign").into_response();
         }
-        _ => {
-            format!("AI Response: I'm here to help with your D&D session. What would you like me to assist with?")
+    }
+
+    let title = payload.title.clone().unwrap_or_else(|| {
+        let mut title: String = payload.hook.chars().take(80).collect();
+        if payload.hook.chars().count() > 80 {
+            title.push('…');
         }
-    };
+        title
+    });
 
-    // Log the AI request as an event if session_id is provided
-    if let Some(session_id) = payload.session_id {
-        let _ = sqlx::query(
-            "INSERT INTO event_logs (id, session_id, event_type, event_data, created_by, created_at) 
-             VALUES ($1, $2, $3, $4, $5, $6)"
-        )
-        .bind(Uuid::new_v4())
-        .bind(session_id)
-        .bind("ai_request")
-        .bind(serde_json::json!({
-            "prompt": payload.prompt,
-            "request_type": payload.request_type,
-            "response": response
-        }))
-        .bind(user.0)
-        .bind(Utc::now())
-        .execute(&pool)
-        .await;
+    let now = Utc::now();
+    let res = sqlx::query_as::<_, Quest>(
+        "INSERT INTO quests (id, campaign_id, character_id, title, description, status, source, created_by, created_at, updated_at) 
+         VALUES ($1, $2, $3, $4, $5, 'open', 'plot_hook', $6, $7, $7) RETURNING *"
+    )
+    .bind(Uuid::new_v4())
+    .bind(campaign_id)
+    .bind(payload.character_id)
+    .bind(&title)
+    .bind(&payload.hook)
+    .bind(user.0)
+    .bind(now)
+    .fetch_one(&pool)
+    .await;
+
+    match res {
+        Ok(quest) => (StatusCode::CREATED, axum::Json(QuestResponse::from(quest))).into_response(),
+        Err(e) => db_error(e, "Failed to save plot hook"),
     }
+}
 
-    let ai_response = AIResponse {
-        response,
-        tokens_used: Some(150), // Mock value
-        model: "gpt-4".to_string(),
-    };
+pub async fn list_quests(
+    Extension(pool): Extension<PgPool>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let quests = sqlx::query_as::<_, Quest>(
+        "SELECT * FROM quests WHERE campaign_id = $1 ORDER BY created_at DESC"
+    )
+    .bind(campaign_id)
+    .fetch_all(&pool)
+    .await;
 
-    axum::Json(ai_response).into_response()
+    match quests {
+        Ok(quests) => {
+            let responses: Vec<QuestResponse> = quests.into_iter().map(QuestResponse::from).collect();
+            axum::Json(responses).into_response()
+        }
+        Err(e) => db_error(e, "Failed to fetch quests"),
+    }
+}
+
+impl From<Quest> for QuestResponse {
+    fn from(q: Quest) -> Self {
+        QuestResponse {
+            id: q.id,
+            campaign_id: q.campaign_id,
+            character_id: q.character_id,
+            title: q.title,
+            description: q.description,
+            status: q.status,
+            source: q.source,
+            created_by: q.created_by,
+            created_at: q.created_at,
+            updated_at: q.updated_at,
+        }
+    }
 }
 
+#[cfg(test)]
 mod tests {
     use super::*;
     use sqlx::PgPool;
@@ -1364,14 +2440,45 @@ mod tests {
             email: "test@example.com".to_string(),
             username: "testuser".to_string(),
             password: "testpass".to_string(),
+            captcha_token: None,
         };
 
-        let response = register(Extension(pool), Json(request)).await;
+        let response = register(Extension(pool), ClientInfo::default(), Json(request)).await;
         let response_parts = response.into_response().into_parts();
         
         assert_eq!(response_parts.0.status, StatusCode::CREATED);
     }
 
+    #[tokio::test]
+    async fn test_register_returns_the_user_and_availability() {
+        let pool = create_test_pool().await;
+        let suffix = Uuid::new_v4().simple().to_string();
+        let request = RegisterRequest {
+            email: format!("fresh_{}@example.com", suffix),
+            username: format!("fresh_{}", suffix),
+            password: "correct horse battery staple".to_string(),
+            captcha_token: None,
+        };
+        let available = |username: &str| check_availability(
+            Extension(pool.clone()),
+            Query(AvailabilityQuery { username: Some(username.to_string()), email: None }),
+        );
+        let body = axum::body::to_bytes(available(&request.username).await.into_response().into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), json!({"username": true}));
+
+        let response = register(Extension(pool.clone()), ClientInfo::default(), Json(request.clone())).await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let registered: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(registered["user"]["username"], request.username.as_str());
+        assert!(registered["user"].get("password_hash").is_none());
+        // Tokens only come straight away when nothing needs verifying
+        assert_eq!(registered.get("token").is_some(), !verification_required());
+
+        let body = axum::body::to_bytes(available(&request.username).await.into_response().into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), json!({"username": false}));
+    }
+
     #[tokio::test]
     async fn test_register_duplicate_user() {
         let pool = create_test_pool().await;
@@ -1381,12 +2488,13 @@ mod tests {
             email: "duplicate@example.com".to_string(),
             username: "duplicateuser".to_string(),
             password: "testpass".to_string(),
+            captcha_token: None,
         };
 
-        register(Extension(pool.clone()), Json(request.clone())).await;
+        register(Extension(pool.clone()), ClientInfo::default(), Json(request.clone())).await;
 
         // Try to register the same user again
-        let response = register(Extension(pool), Json(request)).await;
+        let response = register(Extension(pool), ClientInfo::default(), Json(request)).await;
         let response_parts = response.into_response().into_parts();
         
         assert_eq!(response_parts.0.status, StatusCode::CONFLICT);
@@ -1401,9 +2509,11 @@ mod tests {
             email: "login@example.com".to_string(),
             username: "loginuser".to_string(),
             password: "testpass".to_string(),
+            captcha_token: None,
         };
 
-        register(Extension(pool.clone()), Json(register_request)).await;
+        register(Extension(pool.clone()), ClientInfo::default(), Json(register_request)).await;
+        sqlx::query("UPDATE users SET email_verified = TRUE WHERE email = 'login@example.com'").execute(&pool).await.unwrap();
 
         // Try to login
         let login_request = LoginRequest {
@@ -1411,7 +2521,7 @@ mod tests {
             password: "testpass".to_string(),
         };
 
-        let response = login(Extension(pool), Json(login_request)).await;
+        let response = login(Extension(pool), ClientInfo::default(), Json(login_request)).await;
         let response_parts = response.into_response().into_parts();
         
         assert_eq!(response_parts.0.status, StatusCode::OK);
@@ -1422,23 +2532,73 @@ mod tests {
         let login_response: LoginResponse = serde_json::from_str(&response_text).unwrap();
         
         assert!(!login_response.token.is_empty());
+        assert!(!login_response.refresh_token.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_change_password_revokes_refresh_tokens() {
+        let pool = create_test_pool().await;
+        let email = format!("change_{}@example.com", Uuid::new_v4());
+        let old_password = "correct horse battery staple".to_string();
+        let new_password = "purple monkey dishwasher lantern".to_string();
+        let register_request = RegisterRequest { email: email.clone(), username: format!("change_{}", Uuid::new_v4().simple()), password: old_password.clone(), captcha_token: None };
+        register(Extension(pool.clone()), ClientInfo::default(), Json(register_request)).await;
+        let user_id = sqlx::query_scalar::<_, Uuid>("UPDATE users SET email_verified = TRUE WHERE email = $1 RETURNING id")
+            .bind(&email)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let old_tokens = crate::refresh::issue_tokens(&pool, user_id).await.unwrap();
+
+        let change = |current_password: &str| change_password(
+            Extension(pool.clone()),
+            Extension(SessionState::new(None)),
+            Extension(AuthUser(user_id)),
+            ClientInfo::default(),
+            Json(ChangePasswordRequest { current_password: current_password.to_string(), new_password: new_password.clone() }),
+        );
+        assert_eq!(change("wrong password").await.into_response().status(), StatusCode::FORBIDDEN);
+        assert_eq!(change(&old_password).await.into_response().status(), StatusCode::OK);
+
+        let refreshed = crate::refresh::refresh(Extension(pool.clone()), ClientInfo::default(), Json(crate::refresh::RefreshRequest { refresh_token: old_tokens.refresh_token })).await;
+        assert_eq!(refreshed.into_response().status(), StatusCode::UNAUTHORIZED);
+        let login_as = |password: String| login(Extension(pool.clone()), ClientInfo::default(), Json(LoginRequest { email: email.clone(), password }));
+        assert_eq!(login_as(old_password).await.into_response().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(login_as(new_password.clone()).await.into_response().status(), StatusCode::OK);
     }
 
     #[tokio::test]
     async fn test_login_invalid_credentials() {
         let pool = create_test_pool().await;
         
+        // Unique, so failures from earlier runs haven't locked it out
         let login_request = LoginRequest {
-            email: "nonexistent@example.com".to_string(),
+            email: format!("nonexistent_{}@example.com", Uuid::new_v4()),
             password: "wrongpass".to_string(),
         };
 
-        let response = login(Extension(pool), Json(login_request)).await;
+        let response = login(Extension(pool), ClientInfo::default(), Json(login_request)).await;
         let response_parts = response.into_response().into_parts();
         
         assert_eq!(response_parts.0.status, StatusCode::UNAUTHORIZED);
     }
 
+    #[tokio::test]
+    async fn test_repeated_failed_logins_are_locked_out() {
+        let pool = create_test_pool().await;
+        let client = ClientInfo { address: Some(std::net::IpAddr::from([198, 51, 100, 9])), user_agent: None };
+        let email = format!("stuffed_{}@example.com", Uuid::new_v4());
+        let attempt = || login(Extension(pool.clone()), client.clone(), Json(LoginRequest { email: email.clone(), password: "hunter2".to_string() }));
+
+        for _ in 0..6 {
+            assert_eq!(attempt().await.into_response().status(), StatusCode::UNAUTHORIZED);
+        }
+        let locked = attempt().await.into_response();
+        assert_eq!(locked.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(locked.headers().contains_key(axum::http::header::RETRY_AFTER));
+        sqlx::query("DELETE FROM login_throttles WHERE key = $1").bind(format!("address:{}", "198.51.100.9")).execute(&pool).await.unwrap();
+    }
+
     #[tokio::test]
     async fn test_create_campaign() {
         let pool = create_test_pool().await;
@@ -1460,7 +2620,7 @@ mod tests {
         let request = CreateCampaignRequest {
             name: "Test Campaign".to_string(),
             description: Some("A test campaign".to_string()),
-            settings: Some(json!({"theme": "dark"})),
+            settings: Some(json!({"system": "dnd5e", "level_range": {"min": 1, "max": 5}})),
         };
 
         let auth_user = AuthUser(user_id);
@@ -1501,11 +2661,32 @@ mod tests {
             .await
             .unwrap();
 
-        let auth_user = AuthUser(user_id);
-        let response = list_campaigns(Extension(pool), Extension(auth_user)).await;
-        let response_parts = response.into_response().into_parts();
-        
-        assert_eq!(response_parts.0.status, StatusCode::OK);
+        sqlx::query("INSERT INTO campaigns (id, name, dm_id, archived_at) VALUES ($1, 'Shelved Campaign', $2, NOW())")
+            .bind(Uuid::new_v4())
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let list = |params: CampaignListQuery| list_campaigns(Extension(pool.clone()), Extension(AuthUser(user_id)), Query(params));
+        let read = |response: axum::response::Response| async {
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let page = read(list(CampaignListQuery::default()).await.into_response()).await;
+        assert_eq!(page["total"], 1);
+        assert_eq!(page["campaigns"][0]["id"], campaign_id.to_string());
+        assert_eq!(page["campaigns"][0]["role"], "dm");
+        let page = read(list(CampaignListQuery { archived: true, ..Default::default() }).await.into_response()).await;
+        assert_eq!(page["campaigns"][0]["name"], "Shelved Campaign");
+        let page = read(list(CampaignListQuery { role: Some(CampaignRoleFilter::Player), ..Default::default() }).await.into_response()).await;
+        assert_eq!((page["total"].as_i64(), page["campaigns"].as_array().map(Vec::len)), (Some(0), Some(0)));
+        // Past the end: an empty page, still counted
+        let page = read(list(CampaignListQuery { limit: Some(1), offset: Some(5), ..Default::default() }).await.into_response()).await;
+        assert_eq!((page["total"].as_i64(), page["limit"].as_i64()), (Some(1), Some(1)));
+        assert!(page["campaigns"].as_array().unwrap().is_empty());
     }
 
     #[tokio::test]
@@ -1580,11 +2761,10 @@ mod tests {
         let request = UpdateCampaignRequest {
             name: Some("Updated Campaign Name".to_string()),
             description: Some("Updated description".to_string()),
-            settings: Some(json!({"theme": "light"})),
+            settings: Some(json!({"houserules": {"theme": "light"}})),
         };
 
-        let auth_user = AuthUser(user_id);
-        let response = update_campaign(Extension(pool), Extension(auth_user), Path(campaign_id), Json(request)).await;
+        let response = update_campaign(Extension(pool), Path(campaign_id), Json(request)).await;
         let response_parts = response.into_response().into_parts();
         
         assert_eq!(response_parts.0.status, StatusCode::OK);
@@ -1622,10 +2802,34 @@ mod tests {
             .unwrap();
 
         let auth_user = AuthUser(user_id);
-        let response = delete_campaign(Extension(pool), Extension(auth_user), Path(campaign_id)).await;
+        let response = delete_campaign(Extension(pool.clone()), Extension(auth_user.clone()), Path(campaign_id)).await;
         let response_parts = response.into_response().into_parts();
         
         assert_eq!(response_parts.0.status, StatusCode::OK);
+
+        // Gone from reads, but restorable
+        let fetched = get_campaign(Extension(pool.clone()), Extension(auth_user.clone()), Path(campaign_id)).await;
+        assert_eq!(fetched.into_response().status(), StatusCode::NOT_FOUND);
+        // Deleted campaigns don't resolve, so a second delete is refused like any other out-of-scope id
+        let deleted_again = crate::test_support::call_as(&pool, user_id, axum::http::Method::DELETE, &format!("/campaigns/{}", campaign_id), None).await;
+        assert_eq!(deleted_again.status(), StatusCode::FORBIDDEN);
+        let restored = restore_campaign(Extension(pool.clone()), Extension(auth_user.clone()), Path(campaign_id)).await;
+        assert_eq!(restored.into_response().status(), StatusCode::OK);
+        let fetched = get_campaign(Extension(pool.clone()), Extension(auth_user.clone()), Path(campaign_id)).await;
+        assert_eq!(fetched.into_response().status(), StatusCode::OK);
+
+        // Past the grace period it can't come back and is purged
+        sqlx::query("UPDATE campaigns SET deleted_at = NOW() - make_interval(days => $2) WHERE id = $1")
+            .bind(campaign_id)
+            .bind(CAMPAIGN_RESTORE_DAYS as i32 + 1)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let restored = restore_campaign(Extension(pool.clone()), Extension(auth_user), Path(campaign_id)).await;
+        assert_eq!(restored.into_response().status(), StatusCode::NOT_FOUND);
+        assert!(purge_deleted_campaigns(&pool).await.unwrap() >= 1);
+        let remaining = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM campaigns WHERE id = $1").bind(campaign_id).fetch_one(&pool).await.unwrap();
+        assert_eq!(remaining, 0);
     }
 
     #[tokio::test]
@@ -1660,9 +2864,10 @@ mod tests {
             .unwrap();
 
         let request = CreateSessionRequest {
-            campaign_id: campaign_id,
+            campaign_id,
             name: "Test Session".to_string(),
             description: Some("A test session".to_string()),
+            rng_seed: None,
         };
 
         let auth_user = AuthUser(user_id);
@@ -1824,8 +3029,7 @@ mod tests {
             game_state: Some(json!({"test": "updated"})),
         };
 
-        let auth_user = AuthUser(user_id);
-        let response = update_session(Extension(pool), Extension(auth_user), Path(session_id), Json(request)).await;
+        let response = update_session(Extension(pool), Path(session_id), Json(request)).await;
         let response_parts = response.into_response().into_parts();
         
         assert_eq!(response_parts.0.status, StatusCode::OK);
@@ -1875,8 +3079,7 @@ mod tests {
             .await
             .unwrap();
 
-        let auth_user = AuthUser(user_id);
-        let response = start_session(Extension(pool), Extension(auth_user), Path(session_id)).await;
+        let response = start_session(Extension(pool), Path(session_id)).await;
         let response_parts = response.into_response().into_parts();
         
         assert_eq!(response_parts.0.status, StatusCode::OK);
@@ -1926,8 +3129,7 @@ mod tests {
             .await
             .unwrap();
 
-        let auth_user = AuthUser(user_id);
-        let response = end_session(Extension(pool), Extension(auth_user), Path(session_id)).await;
+        let response = end_session(Extension(pool), Path(session_id)).await;
         let response_parts = response.into_response().into_parts();
         
         assert_eq!(response_parts.0.status, StatusCode::OK);
@@ -2079,7 +3281,7 @@ mod tests {
         let auth_user = AuthUser(user_id);
 
         // Test starting the session
-        let start_response = start_session(Extension(pool.clone()), Extension(auth_user.clone()), Path(session_id)).await;
+        let start_response = start_session(Extension(pool.clone()), Path(session_id)).await;
         let start_response_parts = start_response.into_response().into_parts();
         assert_eq!(start_response_parts.0.status, StatusCode::OK);
 
@@ -2089,7 +3291,7 @@ mod tests {
         assert_eq!(get_response_parts.0.status, StatusCode::OK);
 
         // Test ending the session
-        let end_response = end_session(Extension(pool.clone()), Extension(auth_user.clone()), Path(session_id)).await;
+        let end_response = end_session(Extension(pool.clone()), Path(session_id)).await;
         let end_response_parts = end_response.into_response().into_parts();
         assert_eq!(end_response_parts.0.status, StatusCode::OK);
 
@@ -2552,6 +3754,8 @@ mod tests {
                 hp_current: Some(25),
                 hp_max: Some(25),
                 ac: Some(16),
+                surprised: false,
+                cr: None,
             },
             InitiativeEntry {
                 id: Uuid::new_v4(),
@@ -2563,6 +3767,8 @@ mod tests {
                 hp_current: Some(7),
                 hp_max: Some(7),
                 ac: Some(15),
+                surprised: false,
+                cr: None,
             },
         ];
 
@@ -2632,7 +3838,7 @@ mod tests {
         };
 
         let auth_user = AuthUser(user_id);
-        let response = create_event_log(Extension(pool), Extension(auth_user), Json(request)).await;
+        let response = create_event_log(Extension(pool), Extension(auth_user), Extension(SessionState::new(None)), Json(request)).await;
         let response_parts = response.into_response().into_parts();
         
         assert_eq!(response_parts.0.status, StatusCode::CREATED);
@@ -2823,4 +4029,351 @@ mod tests {
         
         assert_eq!(response_parts.0.status, StatusCode::OK);
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_generate_plot_hooks() {
+        let pool = create_test_pool().await;
+        
+        // Create a test user, campaign and character
+        let user_id = Uuid::new_v4();
+        let timestamp = Utc::now().timestamp();
+        let random_suffix = rand::random::<u32>();
+        sqlx::query("INSERT INTO users (id, email, username, password_hash, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(user_id)
+            .bind(format!("hooks_dm{}_{}@example.com", timestamp, random_suffix))
+            .bind(format!("hooks_dmuser{}_{}", timestamp, random_suffix))
+            .bind("hashed_password")
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let campaign_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO campaigns (id, name, description, dm_id, settings, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(campaign_id)
+            .bind("Hooks Campaign")
+            .bind("A campaign for testing plot hooks")
+            .bind(user_id)
+            .bind(json!({}))
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        sqlx::query("INSERT INTO characters (id, campaign_id, player_id, name, level, stats, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)")
+            .bind(Uuid::new_v4())
+            .bind(campaign_id)
+            .bind(user_id)
+            .bind("Hook Target")
+            .bind(1)
+            .bind(json!({"backstory": "Exiled from the mountain hold. Seeks redemption.", "bonds": ["my estranged brother"]}))
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let request = GenerateHooksRequest {
+            character_ids: None,
+            hooks_per_character: Some(2),
+        };
+
+        let auth_user = AuthUser(user_id);
+        let response = generate_plot_hooks(Extension(pool), Extension(auth_user), Path(campaign_id), Json(request)).await;
+        let response_parts = response.into_response().into_parts();
+        
+        assert_eq!(response_parts.0.status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_list_campaign_members() {
+        let pool = create_test_pool().await;
+
+        let dm_id = Uuid::new_v4();
+        let player_id = Uuid::new_v4();
+        let timestamp = Utc::now().timestamp();
+        for (id, name) in [(dm_id, "members_dm"), (player_id, "members_player")] {
+            sqlx::query("INSERT INTO users (id, email, username, password_hash, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)")
+                .bind(id)
+                .bind(format!("{}{}@example.com", name, timestamp))
+                .bind(format!("{}{}", name, timestamp))
+                .bind("hashed_password")
+                .bind(Utc::now())
+                .bind(Utc::now())
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let campaign_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO campaigns (id, name, description, dm_id, settings, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(campaign_id)
+            .bind("Members Campaign")
+            .bind("A campaign for testing member lists")
+            .bind(dm_id)
+            .bind(json!({}))
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO campaign_players (campaign_id, player_id) VALUES ($1, $2)")
+            .bind(campaign_id)
+            .bind(player_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let session_state = SessionState::new(None);
+        session_state.presence.connect(dm_id).await;
+
+        let response = list_campaign_members(Extension(pool.clone()), Extension(AuthUser(player_id)), Extension(session_state.clone()), Path(campaign_id)).await;
+        let response = response.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let members: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(members[0]["user_id"], dm_id.to_string());
+        assert_eq!(members[0]["role"], "dm");
+        assert_eq!(members[0]["online"], true);
+        assert_eq!(members[1]["role"], "player");
+        assert_eq!(members[1]["online"], false);
+
+        // Non-members can't see the list
+        let response = list_campaign_members(Extension(pool), Extension(AuthUser(Uuid::new_v4())), Extension(session_state), Path(campaign_id)).await;
+        assert_eq!(response.into_response().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_continue_session_links_and_inherits_combat() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+
+        let game_state = json!({"skip_downed": false});
+        sqlx::query("UPDATE sessions SET started_at = $1, game_state = $2 WHERE id = $3")
+            .bind(Utc::now())
+            .bind(&game_state)
+            .bind(f.session_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let combat_id = sqlx::query_scalar::<_, Uuid>("INSERT INTO combats (session_id, state) VALUES ($1, $2) RETURNING id")
+            .bind(f.session_id)
+            .bind(json!({"initiative_order": [], "current_turn": null, "round": 4, "combat_active": true, "conditions": []}))
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+        let response = crate::test_support::call_as(&pool, f.player_id, axum::http::Method::POST, &format!("/sessions/{}/continue", f.session_id), None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = continue_session(Extension(pool.clone()), Path(f.session_id), None).await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let next: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(next["previous_session_id"], f.session_id.to_string());
+        assert_eq!(next["status"], "planned");
+        assert_eq!(next["name"], "Session 1");
+        assert_eq!(next["game_state"], game_state);
+        let combat_session = sqlx::query_scalar::<_, Uuid>("SELECT session_id FROM combats WHERE id = $1").bind(combat_id).fetch_one(&pool).await.unwrap();
+        assert_eq!(combat_session.to_string(), next["id"].as_str().unwrap());
+
+        let previous = sqlx::query_as::<_, Session>("SELECT * FROM sessions WHERE id = $1")
+            .bind(f.session_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(previous.status, "ended");
+        assert!(previous.ended_at.is_some());
+
+        // The chain doesn't branch
+        let response = continue_session(Extension(pool), Path(f.session_id), None).await;
+        assert_eq!(response.into_response().status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_roll_stats_respect_hidden_rolls() {
+        let pool = create_test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+
+        let roll = |result: i32, faces: &[(i32, i32)], hidden: bool| {
+            let faces: Vec<_> = faces.iter().enumerate().map(|(i, (sides, value))| json!({"id": format!("d{}-{}", sides, i), "sides": sides, "value": value})).collect();
+            json!({"dice": "", "result": result, "rolls": [], "faces": faces, "hidden": hidden})
+        };
+        let events = [
+            (f.player_id, "dice_roll", roll(1, &[(20, 1)], false)),
+            (f.player_id, "dice_roll", roll(20, &[(20, 20), (20, 3)], false)),
+            (f.player_id, "chat_message", json!({"message": "[[1d6]]", "rolls": [roll(6, &[(6, 6)], false)]})),
+            (f.dm_id, "dice_roll", roll(20, &[(20, 20)], true)),
+        ];
+        for (user_id, event_type, event_data) in events {
+            sqlx::query("INSERT INTO event_logs (id, session_id, event_type, event_data, created_by, created_at) VALUES ($1, $2, $3, $4, $5, NOW())")
+                .bind(Uuid::new_v4())
+                .bind(f.session_id)
+                .bind(event_type)
+                .bind(event_data)
+                .bind(user_id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let stats = |user_id| {
+            let pool = pool.clone();
+            async move {
+                let response = get_roll_stats(Extension(pool), Extension(AuthUser(user_id)), Path(f.session_id)).await.into_response();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        // The DM's hidden roll is left out for the player
+        let seen_by_player = stats(f.player_id).await;
+        assert_eq!(seen_by_player["players"].as_array().unwrap().len(), 1);
+        let player = &seen_by_player["players"][0];
+        assert_eq!(player["rolls"], 3);
+        assert_eq!(player["average_result"], 9.0);
+        assert_eq!((player["nat_1s"].clone(), player["nat_20s"].clone()), (json!(1), json!(1)));
+        assert_eq!(player["dice"][0]["sides"], 6);
+        assert_eq!(player["dice"][1]["count"], 3);
+        assert_eq!(player["dice"][1]["average"], 8.0);
+        assert_eq!(player["dice"][1]["faces"][2], 1);
+
+        let seen_by_dm = stats(f.dm_id).await;
+        assert_eq!(seen_by_dm["players"].as_array().unwrap().len(), 2);
+        assert_eq!(seen_by_dm["dice"][1], json!({"sides": 20, "count": 4, "average": 11.0, "faces": [1, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]}));
+    }
+
+    #[tokio::test]
+    async fn test_replay_recomputes_seeded_rolls() {
+        let pool = create_test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        sqlx::query("UPDATE sessions SET rng_seed = 42, roll_counter = 2, status = 'active' WHERE id = $1")
+            .bind(f.session_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let roll = |counter: u64, tampered: bool| {
+            let seed = crate::dice::session_roll_seed(42, counter);
+            let rolled = crate::dice::roll_dice("2d6", seed).unwrap();
+            let result = if tampered { 12 } else { rolled.total };
+            json!({"dice": "2d6", "result": result, "rolls": rolled.rolls, "reason": null, "seed": seed, "roll_counter": counter, "faces": [], "terms": []})
+        };
+        for (counter, tampered) in [(1, false), (2, true)] {
+            sqlx::query("INSERT INTO event_logs (id, session_id, event_type, event_data, created_by, created_at) VALUES ($1, $2, 'dice_roll', $3, $4, NOW())")
+                .bind(Uuid::new_v4())
+                .bind(f.session_id)
+                .bind(roll(counter, tampered))
+                .bind(f.player_id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let replay = |user_id| replay_rolls(Extension(pool.clone()), Extension(AuthUser(user_id)), Path(f.session_id));
+
+        // The seed would give away the rolls still to come
+        assert_eq!(replay(f.player_id).await.into_response().status(), StatusCode::FORBIDDEN);
+
+        let response = replay(f.dm_id).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let replayed: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(replayed["rng_seed"], 42);
+        assert_eq!(replayed["all_match"], false);
+        assert_eq!(replayed["rolls"][0]["matches"], true);
+        assert_eq!(replayed["rolls"][1]["roll_counter"], 2);
+        assert_eq!(replayed["rolls"][1]["matches"], json!(roll(2, false)["result"] == 12));
+
+        sqlx::query("UPDATE sessions SET status = 'ended' WHERE id = $1").bind(f.session_id).execute(&pool).await.unwrap();
+        assert_eq!(replay(f.player_id).await.into_response().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_verifying_a_roll_reveals_its_server_seed() {
+        let pool = create_test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+
+        let committed = crate::fairness::current_commitment(&pool, f.session_id).await.unwrap();
+        let server_seed = crate::fairness::reserve_nonces(&pool, f.session_id, 1).await.unwrap();
+        assert_eq!(server_seed.commitment, committed.commitment);
+        let nonce = server_seed.next_nonce as u64 - 1;
+        let seed = crate::fairness::roll_seed(&server_seed.server_seed, "lucky", nonce);
+        let rolled = crate::dice::roll_dice("1d20", seed).unwrap();
+        let roll_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO event_logs (id, session_id, event_type, event_data, created_by, created_at) VALUES ($1, $2, 'dice_roll', $3, $4, NOW())")
+            .bind(roll_id)
+            .bind(f.session_id)
+            .bind(json!({
+                "dice": "1d20", "result": rolled.total, "rolls": rolled.rolls, "reason": null, "seed": seed, "faces": [], "terms": [],
+                "proof": {"commitment": server_seed.commitment, "nonce": nonce, "client_seed": "lucky"}
+            }))
+            .bind(f.player_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let response = verify_roll(Extension(pool.clone()), Extension(AuthUser(f.dm_id)), Path((f.session_id, roll_id))).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let verification: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(verification["verified"], true);
+        assert_eq!(verification["rolls"][0]["server_seed"], hex::encode(&server_seed.server_seed));
+
+        // The revealed seed is retired
+        let next = crate::fairness::current_commitment(&pool, f.session_id).await.unwrap();
+        assert_ne!(next.commitment, server_seed.commitment);
+    }
+
+    #[tokio::test]
+    async fn test_initiative_follows_linked_characters() {
+        let pool = create_test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+
+        let entry = |name: &str, character_id: Option<Uuid>| InitiativeEntry {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            initiative: 12,
+            is_player: character_id.is_some(),
+            character_id,
+            user_id: None,
+            hp_current: Some(99),
+            hp_max: Some(99),
+            ac: Some(1),
+            surprised: false,
+            cr: None,
+        };
+        let request = UpdateInitiativeRequest {
+            session_id: f.session_id,
+            initiative_order: vec![entry("Wren", Some(f.character_id)), entry("Goblin", None)],
+            current_turn: None,
+            round: None,
+            combat_active: Some(true),
+        };
+        let response = update_initiative(Extension(pool.clone()), Extension(AuthUser(f.dm_id)), Json(request)).await;
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+
+        let stored = || async {
+            let state = sqlx::query_scalar::<_, serde_json::Value>("SELECT state FROM combats WHERE session_id = $1 AND ended_at IS NULL")
+                .bind(f.session_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            serde_json::from_value::<crate::models::GameState>(state).unwrap().initiative_order
+        };
+        let order = stored().await;
+        assert_eq!((order[0].hp_current, order[0].hp_max, order[0].ac), (Some(10), Some(10), None));
+        assert_eq!((order[1].hp_current, order[1].ac), (Some(99), Some(1)));
+
+        // Healing outside combat reaches the tracker
+        let heal = UpdateCharacterHPRequest { hp_current: 4, hp_max: None };
+        let response = update_character_hp(Extension(pool.clone()), Extension(AuthUser(f.player_id)), Path(f.character_id), Json(heal)).await;
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+        let order = stored().await;
+        assert_eq!(order[0].hp_current, Some(4));
+        assert_eq!(order[1].hp_current, Some(99));
+    }
+}
@@ -0,0 +1,184 @@
+use axum::{extract::{Path, Query}, http::StatusCode, response::IntoResponse, Extension};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::handlers::EventLogResponse;
+use crate::middleware::AuthUser;
+use crate::models::{Character, EventLog, Session};
+use crate::scope::{authorize, Access, Resource};
+
+// Character revision history: every write stores a full snapshot, so the character can be
+// shown as it stood at the end of any past session.
+
+// Best effort, like the other side effects of a write: a failure is logged, not surfaced
+pub async fn record_revision(pool: &PgPool, character: &Character, session_id: Option<Uuid>, changed_by: Uuid) {
+    let snapshot = match serde_json::to_value(character) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            tracing::error!(error = %e, character_id = %character.id, "Failed to snapshot character");
+            return;
+        }
+    };
+    let res = sqlx::query(
+        "INSERT INTO character_revisions (character_id, session_id, changed_by, snapshot, created_at) VALUES ($1, $2, $3, $4, $5)"
+    )
+    .bind(character.id)
+    .bind(session_id)
+    .bind(changed_by)
+    .bind(snapshot)
+    .bind(character.updated_at)
+    .execute(pool)
+    .await;
+
+    if let Err(e) = res {
+        tracing::error!(error = %e, character_id = %character.id, "Failed to record character revision");
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AsOfQuery {
+    pub session_id: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct CharacterAsOfResponse {
+    pub character_id: Uuid,
+    pub session_id: Uuid,
+    // End of the session, or now while it is still running
+    pub as_of: DateTime<Utc>,
+    pub revision_id: Uuid,
+    pub revised_at: DateTime<Utc>,
+    pub character: serde_json::Value,
+    // What happened to the character during the session, from the event log
+    pub session_events: Vec<EventLogResponse>,
+}
+
+pub async fn get_character_as_of(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(character_id): Path<Uuid>,
+    Query(params): Query<AsOfQuery>,
+) -> impl IntoResponse {
+    let scope = match authorize(&pool, user.0, Resource::Character(character_id), Access::Member, "Access denied to this character").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+
+    let session = sqlx::query_as::<_, Session>("SELECT * FROM sessions WHERE id = $1")
+        .bind(params.session_id)
+        .fetch_optional(&pool)
+        .await;
+
+    let session = match session {
+        Ok(Some(session)) if scope.contains(session.campaign_id) => session,
+        Ok(_) => return (StatusCode::NOT_FOUND, "Session not found in this character's campaign").into_response(),
+        Err(e) => return db_error(e, "Failed to fetch session"),
+    };
+
+    let as_of = match (session.started_at, session.ended_at) {
+        (_, Some(ended_at)) => ended_at,
+        (Some(_), None) => Utc::now(),
+        (None, None) => return (StatusCode::BAD_REQUEST, "Session has not started").into_response(),
+    };
+
+    let revision = sqlx::query_as::<_, (Uuid, serde_json::Value, DateTime<Utc>)>(
+        "SELECT id, snapshot, created_at FROM character_revisions
+         WHERE character_id = $1 AND created_at <= $2
+         ORDER BY created_at DESC LIMIT 1"
+    )
+    .bind(character_id)
+    .bind(as_of)
+    .fetch_optional(&pool)
+    .await;
+
+    let (revision_id, character, revised_at) = match revision {
+        Ok(Some(revision)) => revision,
+        Ok(None) => return (StatusCode::NOT_FOUND, "No recorded state for this character at the end of that session").into_response(),
+        Err(e) => return db_error(e, "Failed to fetch character history"),
+    };
+
+    let events = sqlx::query_as::<_, EventLog>(
+        "SELECT * FROM event_logs
+         WHERE session_id = $1 AND event_data->>'character_id' = $2
+         ORDER BY created_at ASC"
+    )
+    .bind(session.id)
+    .bind(character_id.to_string())
+    .fetch_all(&pool)
+    .await;
+
+    let session_events = match events {
+        Ok(events) => events.into_iter().map(EventLogResponse::from).collect(),
+        Err(e) => return db_error(e, "Failed to fetch event logs"),
+    };
+
+    axum::Json(CharacterAsOfResponse {
+        character_id,
+        session_id: session.id,
+        as_of,
+        revision_id,
+        revised_at,
+        character,
+        session_events,
+    })
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{campaign_fixture, test_pool};
+
+    #[tokio::test]
+    async fn test_character_as_of_session_end() {
+        let pool = test_pool().await;
+        let f = campaign_fixture(&pool).await;
+
+        let fetch = |pool: PgPool| async move {
+            sqlx::query_as::<_, Character>("SELECT * FROM characters WHERE id = $1")
+                .bind(f.character_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+        };
+        record_revision(&pool, &fetch(pool.clone()).await, Some(f.session_id), f.dm_id).await;
+
+        // The session ended an hour ago with that snapshot; since then the character picked up
+        // a sword and took damage (the updated_at trigger stamps the change with now)
+        let ended_at = Utc::now() - chrono::Duration::hours(1);
+        sqlx::query("UPDATE sessions SET status = 'ended', started_at = $1, ended_at = $2 WHERE id = $3")
+            .bind(ended_at - chrono::Duration::hours(3))
+            .bind(ended_at)
+            .bind(f.session_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE character_revisions SET created_at = $1 WHERE character_id = $2")
+            .bind(ended_at - chrono::Duration::hours(1))
+            .bind(f.character_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE characters SET hp_current = 3, inventory = '[\"sword\"]' WHERE id = $1")
+            .bind(f.character_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        record_revision(&pool, &fetch(pool.clone()).await, None, f.dm_id).await;
+
+        let query = Query(AsOfQuery { session_id: f.session_id });
+        let response = get_character_as_of(Extension(pool.clone()), Extension(AuthUser(f.player_id)), Path(f.character_id), query).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(value["character"]["hp_current"], 10);
+        assert_eq!(value["character"]["inventory"], serde_json::json!([]));
+
+        // Outsiders can't look
+        let query = Query(AsOfQuery { session_id: f.session_id });
+        let response = get_character_as_of(Extension(pool), Extension(AuthUser(f.outsider_id)), Path(f.character_id), query).await.into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
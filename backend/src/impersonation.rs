@@ -0,0 +1,111 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::audit::{self, AuthEvent, ClientInfo};
+use crate::errors::db_error;
+use crate::middleware::{build_claims, encode_token, is_admin, AuthUser};
+
+// Support access. An admin can get an access token acting as a user, to see a reported
+// campaign problem the way the user does. The token carries `impersonated_by`, expires after
+// IMPERSONATION_TTL_SECS and comes without a refresh token; routes that could take over the
+// account refuse it (see refuse_impersonation). Both the admin's and the user's audit logs
+// record it, so the user can see who looked.
+
+const IMPERSONATION_TTL_SECS: i64 = 10 * 60;
+
+const MAX_REASON_CHARS: usize = 500;
+
+#[derive(Deserialize, Default)]
+pub struct ImpersonateRequest {
+    // The support ticket or report being looked into
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImpersonationResponse {
+    pub token: String,
+    pub expires_in: i64,
+    pub user_id: Uuid,
+    pub impersonated_by: Uuid,
+}
+
+// Admin only (see require_role)
+pub async fn impersonate(
+    Extension(pool): Extension<PgPool>,
+    Extension(admin): Extension<AuthUser>,
+    client: ClientInfo,
+    Path(user_id): Path<Uuid>,
+    payload: Option<Json<ImpersonateRequest>>,
+) -> impl IntoResponse {
+    let Json(payload) = payload.unwrap_or_default();
+    if user_id == admin.0 || is_admin(user_id) {
+        return (StatusCode::FORBIDDEN, "Admins can't be impersonated").into_response();
+    }
+    let exists = sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1)")
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await;
+    match exists {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => return db_error(e, "Failed to impersonate user"),
+    }
+
+    let exp = (Utc::now() + chrono::Duration::seconds(IMPERSONATION_TTL_SECS)).timestamp() as usize;
+    let mut claims = match build_claims(&pool, user_id, exp).await {
+        Ok(claims) => claims,
+        Err(e) => return db_error(e, "Failed to impersonate user"),
+    };
+    claims.impersonated_by = Some(admin.0);
+    let token = match encode_token(&claims) {
+        Ok(token) => token,
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate token").into_response(),
+    };
+
+    let reason: Option<String> = payload.reason.map(|reason| reason.trim().chars().take(MAX_REASON_CHARS).collect());
+    let detail = |key: &str, id: Uuid| serde_json::json!({ key: id, "reason": reason });
+    audit::record(&pool, Some(user_id), AuthEvent::Impersonation, &client, detail("admin_id", admin.0)).await;
+    audit::record(&pool, Some(admin.0), AuthEvent::Impersonation, &client, detail("user_id", user_id)).await;
+
+    Json(ImpersonationResponse { token, expires_in: IMPERSONATION_TTL_SECS, user_id, impersonated_by: admin.0 }).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Claims;
+    use jsonwebtoken::{decode, DecodingKey};
+
+    #[tokio::test]
+    async fn test_impersonation_tokens_are_marked_and_audited() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let impersonate_as = |admin_id: Uuid, user_id: Uuid| {
+            let payload = Json(ImpersonateRequest { reason: Some("Ticket 42: missing character".to_string()) });
+            impersonate(Extension(pool.clone()), Extension(AuthUser(admin_id)), ClientInfo::default(), Path(user_id), Some(payload))
+        };
+
+        let response = impersonate_as(f.outsider_id, f.player_id).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let issued: ImpersonationResponse = serde_json::from_slice(&body).unwrap();
+        let secret = crate::middleware::jwt_secret();
+        let claims = decode::<Claims>(&issued.token, &DecodingKey::from_secret(secret.as_bytes()), &crate::middleware::validation()).unwrap().claims;
+        assert_eq!(claims.sub, f.player_id.to_string());
+        assert_eq!(claims.impersonated_by, Some(f.outsider_id));
+        assert!(claims.exp as i64 <= Utc::now().timestamp() + IMPERSONATION_TTL_SECS);
+
+        let logged = sqlx::query_scalar::<_, serde_json::Value>("SELECT detail FROM auth_audit WHERE user_id = $1 AND event = 'impersonation'")
+            .bind(f.player_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(logged["admin_id"], f.outsider_id.to_string());
+        assert_eq!(logged["reason"], "Ticket 42: missing character");
+
+        assert_eq!(impersonate_as(f.outsider_id, f.outsider_id).await.into_response().status(), StatusCode::FORBIDDEN);
+        assert_eq!(impersonate_as(f.outsider_id, Uuid::new_v4()).await.into_response().status(), StatusCode::NOT_FOUND);
+    }
+}
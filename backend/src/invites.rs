@@ -0,0 +1,289 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::{DateTime, Utc};
+use rand::rngs::OsRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::membership::{count_players, max_players};
+use crate::middleware::{bump_token_versions, AuthUser};
+use crate::models::Campaign;
+use crate::scope::{resolve, Resource, FROZEN_MESSAGE};
+use crate::verification::app_url;
+
+// Invite codes, for DMs who'd rather hand out a code than review join requests. The DM
+// creates a code with an expiry and optionally a use limit, and shares it or the
+// APP_URL/join?code=... link; POST /campaigns/join redeems it and seats the player
+// straight away, as long as the campaign has a free seat.
+
+const CODE_LEN: usize = 8;
+
+// No 0/O or 1/I, so codes read out loud or copied by hand come through intact
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+const DEFAULT_EXPIRY_HOURS: i64 = 72;
+
+const MAX_EXPIRY_HOURS: i64 = 30 * 24;
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct CampaignInvite {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub code: String,
+    pub created_by: Uuid,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InviteResponse {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub code: String,
+    pub link: String,
+    // The DM or co-DM who made it
+    pub created_by: Uuid,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<CampaignInvite> for InviteResponse {
+    fn from(invite: CampaignInvite) -> Self {
+        InviteResponse {
+            link: format!("{}/join?code={}", app_url(), invite.code),
+            id: invite.id,
+            campaign_id: invite.campaign_id,
+            code: invite.code,
+            created_by: invite.created_by,
+            max_uses: invite.max_uses,
+            uses: invite.uses,
+            expires_at: invite.expires_at,
+            created_at: invite.created_at,
+        }
+    }
+}
+
+fn generate_code() -> String {
+    (0..CODE_LEN).map(|_| CODE_ALPHABET[OsRng.gen_range(0..CODE_ALPHABET.len())] as char).collect()
+}
+
+// Codes are shown in upper case but typed any way
+fn normalize_code(code: &str) -> String {
+    code.trim().to_ascii_uppercase()
+}
+
+#[derive(Deserialize, Default)]
+pub struct CreateInviteRequest {
+    pub expires_in_hours: Option<i64>,
+    // Unlimited when left out
+    pub max_uses: Option<i32>,
+}
+
+pub async fn create_invite(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    payload: Option<Json<CreateInviteRequest>>,
+) -> impl IntoResponse {
+    let Json(payload) = payload.unwrap_or_default();
+    let hours = payload.expires_in_hours.unwrap_or(DEFAULT_EXPIRY_HOURS);
+    if !(1..=MAX_EXPIRY_HOURS).contains(&hours) {
+        return (StatusCode::BAD_REQUEST, format!("expires_in_hours must be between 1 and {}", MAX_EXPIRY_HOURS)).into_response();
+    }
+    if payload.max_uses.is_some_and(|max_uses| max_uses < 1) {
+        return (StatusCode::BAD_REQUEST, "max_uses must be at least 1").into_response();
+    }
+
+    let expires_at = Utc::now() + chrono::Duration::hours(hours);
+    // A fresh code colliding with a live one is unlikely, but cheap to retry
+    for _ in 0..3 {
+        let invite = sqlx::query_as::<_, CampaignInvite>(
+            "INSERT INTO campaign_invites (campaign_id, code, created_by, max_uses, expires_at) VALUES ($1, $2, $3, $4, $5) RETURNING *"
+        )
+        .bind(campaign_id)
+        .bind(generate_code())
+        .bind(user.0)
+        .bind(payload.max_uses)
+        .bind(expires_at)
+        .fetch_one(&pool)
+        .await;
+        match invite {
+            Ok(invite) => return (StatusCode::CREATED, Json(InviteResponse::from(invite))).into_response(),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => continue,
+            Err(e) => return db_error(e, "Failed to create invite"),
+        }
+    }
+    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create invite").into_response()
+}
+
+// Invites that can still be redeemed, newest first
+pub async fn list_invites(
+    Extension(pool): Extension<PgPool>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let invites = sqlx::query_as::<_, CampaignInvite>(
+        "SELECT * FROM campaign_invites
+         WHERE campaign_id = $1 AND revoked_at IS NULL AND expires_at > NOW() AND (max_uses IS NULL OR uses < max_uses)
+         ORDER BY created_at DESC"
+    )
+    .bind(campaign_id)
+    .fetch_all(&pool)
+    .await;
+    match invites {
+        Ok(invites) => Json(invites.into_iter().map(InviteResponse::from).collect::<Vec<_>>()).into_response(),
+        Err(e) => db_error(e, "Failed to fetch invites"),
+    }
+}
+
+pub async fn revoke_invite(
+    Extension(pool): Extension<PgPool>,
+    Path((campaign_id, invite_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let res = sqlx::query("UPDATE campaign_invites SET revoked_at = NOW() WHERE id = $1 AND campaign_id = $2 AND revoked_at IS NULL")
+        .bind(invite_id)
+        .bind(campaign_id)
+        .execute(&pool)
+        .await;
+    match res {
+        Ok(done) if done.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Invite not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Invite revoked").into_response(),
+        Err(e) => db_error(e, "Failed to revoke invite"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JoinCampaignRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JoinedCampaign {
+    pub campaign_id: Uuid,
+    pub name: String,
+    pub dm_id: Uuid,
+}
+
+enum Redeemed {
+    Joined(Campaign),
+    Invalid,
+    AlreadyMember,
+    Full,
+    Frozen,
+}
+
+pub async fn join_campaign(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<JoinCampaignRequest>,
+) -> impl IntoResponse {
+    let redeemed = async {
+        let mut tx = pool.begin().await?;
+        // Locking the invite keeps its use count honest; locking the campaign serializes
+        // seat counting with join request approvals
+        let invite = sqlx::query_as::<_, CampaignInvite>(
+            "SELECT * FROM campaign_invites
+             WHERE code = $1 AND revoked_at IS NULL AND expires_at > NOW() AND (max_uses IS NULL OR uses < max_uses)
+             FOR UPDATE"
+        )
+        .bind(normalize_code(&payload.code))
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(invite) = invite else {
+            return Ok(Redeemed::Invalid);
+        };
+        let campaign = sqlx::query_as::<_, Campaign>("SELECT * FROM campaigns WHERE id = $1 AND deleted_at IS NULL FOR UPDATE")
+            .bind(invite.campaign_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(campaign) = campaign else {
+            return Ok(Redeemed::Invalid);
+        };
+        let frozen = sqlx::query_scalar::<_, bool>("SELECT frozen_at IS NOT NULL FROM campaigns WHERE id = $1")
+            .bind(campaign.id)
+            .fetch_one(&mut *tx)
+            .await?;
+        if frozen {
+            return Ok(Redeemed::Frozen);
+        }
+        if resolve(&mut *tx, user.0, Resource::Campaign(campaign.id)).await?.is_some() {
+            return Ok(Redeemed::AlreadyMember);
+        }
+        if let Some(max) = max_players(&campaign) {
+            if count_players(&mut *tx, campaign.id).await? >= max {
+                return Ok(Redeemed::Full);
+            }
+        }
+
+        sqlx::query("INSERT INTO campaign_players (campaign_id, player_id) VALUES ($1, $2)")
+            .bind(campaign.id)
+            .bind(user.0)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE campaign_invites SET uses = uses + 1 WHERE id = $1")
+            .bind(invite.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Redeemed::Joined(campaign))
+    }
+    .await;
+
+    match redeemed {
+        Ok(Redeemed::Joined(campaign)) => {
+            // New membership; tokens with embedded roles need refreshing
+            let _ = bump_token_versions(&pool, &[user.0]).await;
+            Json(JoinedCampaign { campaign_id: campaign.id, name: campaign.name, dm_id: campaign.dm_id }).into_response()
+        }
+        Ok(Redeemed::Invalid) => (StatusCode::NOT_FOUND, "Invalid or expired invite code").into_response(),
+        Ok(Redeemed::AlreadyMember) => (StatusCode::CONFLICT, "Already a member of this campaign").into_response(),
+        Ok(Redeemed::Full) => (StatusCode::CONFLICT, "Campaign is full").into_response(),
+        Ok(Redeemed::Frozen) => (StatusCode::LOCKED, FROZEN_MESSAGE).into_response(),
+        Err(e) => db_error(e, "Failed to join campaign"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_invite_codes_seat_players_until_used_up() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let read = |response: axum::response::Response| async {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<InviteResponse>(&body).unwrap()
+        };
+
+        let response = crate::test_support::call_as(&pool, f.player_id, axum::http::Method::POST, &format!("/campaigns/{}/invites", f.campaign_id), Some(serde_json::json!({"expires_in_hours": 24, "max_uses": 1}))).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let payload = Json(CreateInviteRequest { expires_in_hours: Some(24), max_uses: Some(1) });
+        let response = create_invite(Extension(pool.clone()), Extension(AuthUser(f.dm_id)), Path(f.campaign_id), Some(payload)).await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let invite = read(response).await;
+        assert_eq!(invite.code.len(), CODE_LEN);
+        assert!(invite.link.ends_with(&format!("/join?code={}", invite.code)));
+
+        let join = |user_id: Uuid, code: String| join_campaign(Extension(pool.clone()), Extension(AuthUser(user_id)), Json(JoinCampaignRequest { code }));
+        assert_eq!(join(f.player_id, invite.code.clone()).await.into_response().status(), StatusCode::CONFLICT);
+        assert_eq!(join(f.outsider_id, invite.code.to_lowercase()).await.into_response().status(), StatusCode::OK);
+        assert!(resolve(&pool, f.outsider_id, Resource::Campaign(f.campaign_id)).await.unwrap().is_some());
+
+        // Used up, so it's no longer listed either
+        let response = list_invites(Extension(pool.clone()), Path(f.campaign_id)).await.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(serde_json::from_slice::<Vec<InviteResponse>>(&body).unwrap().is_empty());
+
+        let response = create_invite(Extension(pool.clone()), Extension(AuthUser(f.dm_id)), Path(f.campaign_id), None).await.into_response();
+        let invite = read(response).await;
+        let response = revoke_invite(Extension(pool.clone()), Path((f.campaign_id, invite.id))).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let stranger = Uuid::new_v4();
+        assert_eq!(join(stranger, invite.code).await.into_response().status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,289 @@
+use axum::{extract::{Path, Query}, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::middleware::AuthUser;
+use crate::models::Location;
+use crate::scope::{authorize, Access, Resource};
+
+// Places in the campaign's world: regions, cities, dungeons, taverns. Locations nest under a
+// parent, and can point at a spot on a map. The DM and co-DMs write them; players get the
+// name, where it sits, the map reference and the summary, but never the description or the
+// DM's notes. The tavern and shop generators can save what they come up with here.
+
+const MAX_NAME_CHARS: usize = 255;
+const MAX_TEXT_CHARS: usize = 20_000;
+const MAX_MAP_REF_CHARS: usize = 2048;
+
+// What a member sees; description and notes only for the DM and co-DMs
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LocationResponse {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub parent_id: Option<Uuid>,
+    pub name: String,
+    pub map_ref: Option<String>,
+    pub summary: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dm_notes: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl LocationResponse {
+    fn for_reader(location: Location, is_dm: bool) -> Self {
+        LocationResponse {
+            id: location.id,
+            campaign_id: location.campaign_id,
+            parent_id: location.parent_id,
+            name: location.name,
+            map_ref: location.map_ref,
+            summary: location.summary,
+            description: is_dm.then_some(location.description),
+            dm_notes: is_dm.then_some(location.dm_notes),
+            created_at: location.created_at,
+            updated_at: location.updated_at,
+        }
+    }
+}
+
+// Create and update take the same body; an update replaces every field
+#[derive(Deserialize, Default)]
+pub struct LocationRequest {
+    pub name: String,
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub map_ref: Option<String>,
+    #[serde(default)]
+    pub dm_notes: String,
+    #[serde(default)]
+    pub summary: String,
+}
+
+impl LocationRequest {
+    fn validate(&self) -> Result<(), String> {
+        let name = self.name.trim().chars().count();
+        if name == 0 || name > MAX_NAME_CHARS {
+            return Err(format!("Name must be 1 to {} characters", MAX_NAME_CHARS));
+        }
+        if [&self.description, &self.dm_notes, &self.summary].iter().any(|text| text.chars().count() > MAX_TEXT_CHARS) {
+            return Err(format!("Descriptions, notes and summaries can be at most {} characters", MAX_TEXT_CHARS));
+        }
+        if self.map_ref.as_ref().is_some_and(|map_ref| map_ref.chars().count() > MAX_MAP_REF_CHARS) {
+            return Err("Map reference is too long".to_string());
+        }
+        Ok(())
+    }
+}
+
+// The parent has to be a location of the same campaign and, when `location_id` is being
+// moved, not the location itself or one inside it
+async fn check_parent(pool: &PgPool, campaign_id: Uuid, parent_id: Uuid, location_id: Option<Uuid>) -> Result<(), Response> {
+    let allowed = sqlx::query_scalar::<_, bool>(
+        "WITH RECURSIVE inside AS (
+             SELECT id FROM locations WHERE id = $3
+             UNION
+             SELECT l.id FROM locations l INNER JOIN inside i ON l.parent_id = i.id
+         )
+         SELECT EXISTS(SELECT 1 FROM locations WHERE id = $1 AND campaign_id = $2) AND NOT EXISTS(SELECT 1 FROM inside WHERE id = $1)"
+    )
+    .bind(parent_id)
+    .bind(campaign_id)
+    .bind(location_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| db_error(e, "Failed to check parent location"))?;
+    if allowed {
+        Ok(())
+    } else {
+        Err((StatusCode::BAD_REQUEST, "Parent must be another location of this campaign, outside this one").into_response())
+    }
+}
+
+// Saves a new location for the DM; also used by the generators
+pub(crate) async fn insert_location(pool: &PgPool, user_id: Uuid, campaign_id: Uuid, payload: LocationRequest) -> Result<Location, Response> {
+    authorize(pool, user_id, Resource::Campaign(campaign_id), Access::Dm, "Only the DM can add locations").await?;
+    if let Err(message) = payload.validate() {
+        return Err((StatusCode::BAD_REQUEST, message).into_response());
+    }
+    if let Some(parent_id) = payload.parent_id {
+        check_parent(pool, campaign_id, parent_id, None).await?;
+    }
+    sqlx::query_as::<_, Location>(
+        "INSERT INTO locations (campaign_id, parent_id, name, description, map_ref, dm_notes, summary, created_by)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *"
+    )
+    .bind(campaign_id)
+    .bind(payload.parent_id)
+    .bind(payload.name.trim())
+    .bind(&payload.description)
+    .bind(&payload.map_ref)
+    .bind(&payload.dm_notes)
+    .bind(&payload.summary)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| db_error(e, "Failed to create location"))
+}
+
+#[derive(Deserialize)]
+pub struct LocationQuery {
+    // Only the locations directly inside this one
+    pub parent_id: Option<Uuid>,
+}
+
+// Sorted by name
+pub async fn list_locations(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Query(params): Query<LocationQuery>,
+) -> impl IntoResponse {
+    let scope = match authorize(&pool, user.0, Resource::Campaign(campaign_id), Access::Member, "Access denied to this campaign").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    let locations = sqlx::query_as::<_, Location>(
+        "SELECT * FROM locations WHERE campaign_id = $1 AND ($2::UUID IS NULL OR parent_id = $2) ORDER BY LOWER(name), id"
+    )
+    .bind(campaign_id)
+    .bind(params.parent_id)
+    .fetch_all(&pool)
+    .await;
+    match locations {
+        Ok(locations) => {
+            let locations: Vec<LocationResponse> = locations.into_iter().map(|location| LocationResponse::for_reader(location, scope.is_dm())).collect();
+            Json(locations).into_response()
+        }
+        Err(e) => db_error(e, "Failed to fetch locations"),
+    }
+}
+
+pub async fn create_location(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Json(payload): Json<LocationRequest>,
+) -> impl IntoResponse {
+    match insert_location(&pool, user.0, campaign_id, payload).await {
+        Ok(location) => (StatusCode::CREATED, Json(LocationResponse::for_reader(location, true))).into_response(),
+        Err(response) => response,
+    }
+}
+
+pub async fn get_location(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(location_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let scope = match authorize(&pool, user.0, Resource::Location(location_id), Access::Member, "Access denied to this location").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    let location = sqlx::query_as::<_, Location>("SELECT * FROM locations WHERE id = $1")
+        .bind(location_id)
+        .fetch_one(&pool)
+        .await;
+    match location {
+        Ok(location) => Json(LocationResponse::for_reader(location, scope.is_dm())).into_response(),
+        Err(e) => db_error(e, "Failed to fetch location"),
+    }
+}
+
+pub async fn update_location(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(location_id): Path<Uuid>,
+    Json(payload): Json<LocationRequest>,
+) -> impl IntoResponse {
+    let scope = match authorize(&pool, user.0, Resource::Location(location_id), Access::Dm, "Only the DM can change locations").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    if let Err(message) = payload.validate() {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    if let Some(parent_id) = payload.parent_id {
+        if let Err(response) = check_parent(&pool, scope.campaign_id, parent_id, Some(location_id)).await {
+            return response;
+        }
+    }
+
+    let location = sqlx::query_as::<_, Location>(
+        "UPDATE locations SET parent_id = $2, name = $3, description = $4, map_ref = $5, dm_notes = $6, summary = $7, updated_at = NOW()
+         WHERE id = $1 RETURNING *"
+    )
+    .bind(location_id)
+    .bind(payload.parent_id)
+    .bind(payload.name.trim())
+    .bind(&payload.description)
+    .bind(&payload.map_ref)
+    .bind(&payload.dm_notes)
+    .bind(&payload.summary)
+    .fetch_one(&pool)
+    .await;
+    match location {
+        Ok(location) => Json(LocationResponse::for_reader(location, true)).into_response(),
+        Err(e) => db_error(e, "Failed to update location"),
+    }
+}
+
+// Locations inside it move up to the top level
+pub async fn delete_location(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(location_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize(&pool, user.0, Resource::Location(location_id), Access::Dm, "Only the DM can remove locations").await {
+        return response;
+    }
+    match sqlx::query("DELETE FROM locations WHERE id = $1").bind(location_id).execute(&pool).await {
+        Ok(_) => (StatusCode::OK, "Location deleted").into_response(),
+        Err(e) => db_error(e, "Failed to delete location"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_players_see_summaries_and_parents_stay_acyclic() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+
+        let create = |user_id: Uuid, name: &str, parent_id: Option<Uuid>| {
+            let payload = LocationRequest { name: name.to_string(), parent_id, summary: "A town".to_string(), dm_notes: "Cult hideout".to_string(), ..Default::default() };
+            create_location(Extension(pool.clone()), Extension(AuthUser(user_id)), Path(f.campaign_id), Json(payload))
+        };
+        assert_eq!(create(f.player_id, "Phandalin", None).await.into_response().status(), StatusCode::FORBIDDEN);
+        let response = create(f.dm_id, "Sword Coast", None).await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let region: LocationResponse = serde_json::from_slice(&body).unwrap();
+        let response = create(f.dm_id, "Phandalin", Some(region.id)).await.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let town: LocationResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(town.parent_id, Some(region.id));
+
+        let response = get_location(Extension(pool.clone()), Extension(AuthUser(f.player_id)), Path(town.id)).await.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let seen: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(seen["summary"], "A town");
+        assert!(seen.get("dm_notes").is_none());
+        let outsider = get_location(Extension(pool.clone()), Extension(AuthUser(f.outsider_id)), Path(town.id)).await.into_response();
+        assert_ne!(outsider.status(), StatusCode::OK);
+
+        // The region can't move inside its own town
+        let payload = LocationRequest { name: "Sword Coast".to_string(), parent_id: Some(town.id), ..Default::default() };
+        let moved = update_location(Extension(pool.clone()), Extension(AuthUser(f.dm_id)), Path(region.id), Json(payload)).await.into_response();
+        assert_eq!(moved.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,151 @@
+use axum::{http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::Utc;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::audit::{self, AuthEvent, ClientInfo};
+use crate::errors::db_error;
+use crate::outbox::{enqueue, OutboxMessage};
+use crate::verification::{app_url, token_hash};
+
+// Passwordless login. POST /auth/magic-link emails a single-use link to
+// APP_URL/magic-link?token=..., and the frontend posts the token to /auth/magic-link/verify
+// for the same tokens as password login. Following the link proves the address, so it also
+// verifies an unverified account.
+
+const MAGIC_LINK_TTL_MINS: i64 = 15;
+
+// A new link can't be requested more often than this
+const RESEND_INTERVAL_SECS: i64 = 60;
+
+#[derive(Deserialize)]
+pub struct MagicLinkRequest {
+    pub email: String,
+}
+
+// Always 202, so the response doesn't tell whether an account exists
+pub async fn request_magic_link(
+    Extension(pool): Extension<PgPool>,
+    Json(payload): Json<MagicLinkRequest>,
+) -> impl IntoResponse {
+    let sent = async {
+        let mut tx = pool.begin().await?;
+        let user_id = sqlx::query_scalar::<_, Uuid>(
+            "SELECT id FROM users u WHERE email = $1 AND disabled_at IS NULL
+             AND NOT EXISTS (SELECT 1 FROM magic_links m WHERE m.user_id = u.id AND m.created_at > NOW() - make_interval(secs => $2))
+             FOR UPDATE"
+        )
+        .bind(&payload.email)
+        .bind(RESEND_INTERVAL_SECS as f64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(user_id) = user_id {
+            let mut token = [0u8; 32];
+            OsRng.fill_bytes(&mut token);
+            let token = hex::encode(token);
+            sqlx::query("INSERT INTO magic_links (token_hash, user_id, expires_at) VALUES ($1, $2, $3)")
+                .bind(token_hash(&token))
+                .bind(user_id)
+                .bind(Utc::now() + chrono::Duration::minutes(MAGIC_LINK_TTL_MINS))
+                .execute(&mut *tx)
+                .await?;
+
+            let link = format!("{}/magic-link?token={}", app_url(), token);
+            let message = OutboxMessage::Email {
+                to: payload.email.clone(),
+                subject: "Your YoDA login link".to_string(),
+                body: format!(
+                    "Open this link within {} minutes to log in to YoDA:\n\n{}\n\nThe link works once. If you didn't ask for it, you can ignore this email.\n",
+                    MAGIC_LINK_TTL_MINS, link
+                ),
+            };
+            enqueue(&mut *tx, &message).await?;
+        }
+        tx.commit().await
+    }
+    .await;
+    match sent {
+        Ok(()) => (StatusCode::ACCEPTED, "If the account exists, a login link is on its way").into_response(),
+        Err(e) => db_error(e, "Failed to send login link"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct MagicLinkLoginRequest {
+    pub token: String,
+}
+
+pub async fn login_with_magic_link(
+    Extension(pool): Extension<PgPool>,
+    client: ClientInfo,
+    Json(payload): Json<MagicLinkLoginRequest>,
+) -> impl IntoResponse {
+    let redeemed = async {
+        let mut tx = pool.begin().await?;
+        let user = sqlx::query_as::<_, (Uuid, bool)>(
+            "UPDATE magic_links m SET used_at = NOW() FROM users u
+             WHERE m.token_hash = $1 AND m.used_at IS NULL AND m.expires_at > NOW() AND u.id = m.user_id
+             RETURNING m.user_id, u.disabled_at IS NOT NULL"
+        )
+        .bind(token_hash(&payload.token))
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some((user_id, false)) = user {
+            sqlx::query("UPDATE users SET email_verified = TRUE, updated_at = NOW() WHERE id = $1 AND NOT email_verified")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(user)
+    }
+    .await;
+    let user_id = match redeemed {
+        Ok(Some((user_id, false))) => user_id,
+        Ok(Some((_, true))) => return (StatusCode::FORBIDDEN, "Account disabled").into_response(),
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Invalid or expired login link").into_response(),
+        Err(e) => return db_error(e, "Failed to log in"),
+    };
+
+    match crate::refresh::issue_tokens(&pool, user_id).await {
+        Ok(tokens) => {
+            audit::record(&pool, Some(user_id), AuthEvent::Login, &client, serde_json::json!({ "method": "magic_link" })).await;
+            (StatusCode::OK, Json(tokens)).into_response()
+        }
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate token").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_magic_links_log_in_once() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let email = sqlx::query_scalar::<_, String>("SELECT email FROM users WHERE id = $1").bind(f.player_id).fetch_one(&pool).await.unwrap();
+
+        let request = || request_magic_link(Extension(pool.clone()), Json(MagicLinkRequest { email: email.clone() }));
+        assert_eq!(request().await.into_response().status(), StatusCode::ACCEPTED);
+        // Too soon for another link, but the answer is the same
+        assert_eq!(request().await.into_response().status(), StatusCode::ACCEPTED);
+        let links = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM magic_links WHERE user_id = $1").bind(f.player_id).fetch_one(&pool).await.unwrap();
+        assert_eq!(links, 1);
+
+        // The token only travels in the email
+        let body = sqlx::query_scalar::<_, String>("SELECT message->>'body' FROM outbox WHERE channel = 'email' AND message->>'to' = $1")
+            .bind(&email)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let token = body.split("token=").nth(1).unwrap().split_whitespace().next().unwrap().to_string();
+
+        let log_in = |token: String| login_with_magic_link(Extension(pool.clone()), ClientInfo::default(), Json(MagicLinkLoginRequest { token }));
+        assert_eq!(log_in("not-a-token".to_string()).await.into_response().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(log_in(token.clone()).await.into_response().status(), StatusCode::OK);
+        assert_eq!(log_in(token).await.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,28 +1,80 @@
-use axum::{routing::{get, post, put, delete}, Router, http::StatusCode, response::IntoResponse, extract::Extension};
 use sqlx::postgres::PgPoolOptions;
 use dotenv::dotenv;
 use std::env;
-use std::sync::Arc;
 mod models;
 mod handlers;
 mod middleware;
 mod socket;
 mod api;
-use middleware::{jwt_auth, AuthUser};
-use socket::{SessionState, ws_handler};
-
-async fn health_check() -> impl IntoResponse {
-    (StatusCode::OK, "OK")
-}
-
-async fn protected_route(Extension(user): Extension<AuthUser>) -> impl IntoResponse {
-    (StatusCode::OK, format!("User ID: {}", user.0))
-}
+mod ai;
+mod generators;
+mod presence;
+mod sse;
+mod notifications;
+mod membership;
+mod compendium;
+mod errors;
+mod scope;
+#[cfg(test)]
+mod test_support;
+mod history;
+mod metrics;
+mod usage;
+mod storage;
+mod outbox;
+mod dice;
+mod routes;
+mod sheet;
+mod content;
+mod rewards;
+mod combat;
+mod events;
+mod password;
+mod fairness;
+mod revocation;
+mod printable;
+mod refresh;
+mod chronicle;
+mod verification;
+mod demo;
+mod oauth;
+mod profile;
+mod throttle;
+mod audit;
+mod avatar;
+mod preferences;
+mod captcha;
+mod magic_link;
+mod impersonation;
+mod rate_limit;
+mod moderation;
+mod invites;
+mod cloning;
+mod templates;
+mod notes;
+mod campaign_settings;
+mod search;
+mod ownership;
+mod calendar;
+mod locations;
+mod maintenance;
+use socket::SessionState;
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
+    // Log filter comes from RUST_LOG, e.g. RUST_LOG=backend=debug,tower_http=info
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("backend=info,tower_http=info,campaign_audit=info")),
+        )
+        .init();
+
+    // Refuses to start without JWT_SECRET
+    middleware::check_jwt_config().expect("Invalid JWT configuration");
+
     // Set up PostgreSQL connection pool
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let pool = PgPoolOptions::new()
@@ -30,61 +82,79 @@ async fn main() {
         .connect(&database_url)
         .await
         .expect("Failed to connect to Postgres");
-    println!("Connected to Postgres");
+    tracing::info!("Connected to Postgres");
 
-    // Redis connection will be set up when needed for caching
+    // Redis is optional; when configured it backs shared state across backend instances
+    let redis = match env::var("REDIS_URL") {
+        Ok(redis_url) => match redis::Client::open(redis_url) {
+            Ok(client) => match client.get_connection_manager().await {
+                Ok(manager) => {
+                    tracing::info!("Connected to Redis");
+                    Some((client.clone(), manager))
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "Failed to connect to Redis, continuing without it");
+                    None
+                }
+            },
+            Err(e) => {
+                tracing::warn!(error = %e, "Invalid REDIS_URL, continuing without Redis");
+                None
+            }
+        },
+        Err(_) => None,
+    };
 
     // Create shared session state for WebSocket connections
-    let session_state = SessionState {
-        sessions: Arc::new(tokio::sync::RwLock::new(std::collections::HashMap::new())),
-    };
+    let session_state = SessionState::new(redis.as_ref().map(|(_, manager)| manager.clone()));
+    session_state.revocations.load(&pool).await.expect("Failed to load token revocations");
+    if let Some((client, _)) = redis {
+        revocation::spawn_revocation_listener(client, session_state.clone());
+    }
+    let max_live_sessions = env::var("MAX_LIVE_SESSIONS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(socket::DEFAULT_MAX_LIVE_SESSIONS);
+    socket::spawn_session_janitor(pool.clone(), session_state.clone(), max_live_sessions);
+    maintenance::spawn_maintenance(pool.clone(), session_state.clone());
+    usage::spawn_usage_flusher(pool.clone());
+    outbox::spawn_outbox_runner(pool.clone());
+    let storage = storage::from_env().expect("Invalid storage configuration");
 
-    // Build our application with a health check route
-    let app = Router::new()
-        .route("/ws", get(ws_handler))
-        .route("/auth/register", axum::routing::post(handlers::register))
-        .route("/auth/login", axum::routing::post(handlers::login))
-        .route("/protected", get(protected_route).route_layer(axum::middleware::from_fn(jwt_auth)))
-        // Campaign routes (protected)
-        .route("/campaigns", get(handlers::list_campaigns).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/campaigns", post(handlers::create_campaign).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/campaigns/:id", get(handlers::get_campaign).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/campaigns/:id", put(handlers::update_campaign).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/campaigns/:id", delete(handlers::delete_campaign).route_layer(axum::middleware::from_fn(jwt_auth)))
-        // Session routes (protected)
-        .route("/sessions", get(handlers::list_sessions).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/sessions", post(handlers::create_session).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/sessions/:id", get(handlers::get_session).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/sessions/:id", put(handlers::update_session).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/sessions/:id/start", post(handlers::start_session).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/sessions/:id/end", post(handlers::end_session).route_layer(axum::middleware::from_fn(jwt_auth)))
-        // Character routes (protected)
-        .route("/characters", get(handlers::list_characters).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/characters", post(handlers::create_character).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/characters/:id", get(handlers::get_character).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/characters/:id", put(handlers::update_character).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/characters/:id", delete(handlers::delete_character).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/characters/:id/hp", put(handlers::update_character_hp).route_layer(axum::middleware::from_fn(jwt_auth)))
-        // Game state routes (protected)
-        .route("/initiative", put(handlers::update_initiative).route_layer(axum::middleware::from_fn(jwt_auth)))
-        // Event log routes (protected)
-        .route("/event-logs", post(handlers::create_event_log).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/sessions/:session_id/event-logs", get(handlers::list_event_logs).route_layer(axum::middleware::from_fn(jwt_auth)))
-        .route("/event-logs/:event_id", get(handlers::get_event_log).route_layer(axum::middleware::from_fn(jwt_auth)))
-        // AI routes (protected)
-        .route("/ai/generate", post(handlers::ai_generate).route_layer(axum::middleware::from_fn(jwt_auth)))
-        // Health check endpoint
-        .route("/health", get(health_check))
-        .route("/docs", get(api::docs::api_docs))
-        .layer(Extension(pool))
-        .layer(Extension(session_state));
+    let app = routes::router(pool, session_state.clone(), storage);
 
-    println!("🚀 YoDA Backend Server starting on http://0.0.0.0:3000");
-    println!("📚 API Documentation available at http://localhost:3000/docs");
-    println!("🔌 WebSocket endpoint available at ws://localhost:3000/ws");
+    tracing::info!("🚀 YoDA Backend Server starting on http://0.0.0.0:3000");
+    tracing::info!("📚 API Documentation available at http://localhost:3000/docs");
+    tracing::info!("🔌 WebSocket endpoint available at ws://localhost:3000/ws");
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app.into_make_service())
+    // Peer addresses feed the rate limits
+    axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal(session_state))
         .await
         .unwrap();
+}
+
+// Resolves on Ctrl+C or SIGTERM once WebSocket clients have been told and disconnected;
+// axum then stops accepting connections and lets in-flight requests finish
+async fn shutdown_signal(session_state: SessionState) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("🛑 Shutting down, closing WebSocket connections");
+    socket::shut_down(&session_state).await;
 }
\ No newline at end of file
@@ -0,0 +1,36 @@
+use sqlx::PgPool;
+use std::time::Duration;
+use crate::socket::SessionState;
+
+// Database housekeeping, kept apart from the session janitor so a slow chore never holds up
+// evicting live sessions: expired demo sandboxes, stale login throttles, old audit entries
+// and campaigns past their restore window are deleted, and suspensions that have run out lift.
+
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub fn spawn_maintenance(pool: PgPool, session_state: SessionState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
+        loop {
+            interval.tick().await;
+            run_chores(&pool, &session_state).await;
+        }
+    });
+}
+
+// Each chore runs whether or not the ones before it failed
+async fn run_chores(pool: &PgPool, session_state: &SessionState) {
+    crate::demo::delete_expired_demos(pool, session_state).await;
+    if let Err(e) = crate::throttle::forget_stale(pool).await {
+        tracing::error!(error = %e, "Failed to delete stale login throttles");
+    }
+    if let Err(e) = crate::audit::prune(pool).await {
+        tracing::error!(error = %e, "Failed to prune the audit log");
+    }
+    if let Err(e) = crate::moderation::lift_expired_suspensions(pool).await {
+        tracing::error!(error = %e, "Failed to lift expired suspensions");
+    }
+    if let Err(e) = crate::handlers::purge_deleted_campaigns(pool).await {
+        tracing::error!(error = %e, "Failed to purge deleted campaigns");
+    }
+}
@@ -0,0 +1,932 @@
+use axum::{extract::{Path, Query}, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::campaign_settings::{CampaignSettings, LevelRange};
+use crate::middleware::{bump_token_versions, AuthUser, CampaignRole};
+use crate::models::{Campaign, JoinRequest};
+use crate::events::{record, DomainEvent};
+use crate::errors::db_error;
+use crate::scope::{campaign_roles, resolve, Permissions, Resource};
+
+// How players get into campaigns other than being added by hand: campaigns with
+// `"discoverable": true` in their settings are listed publicly, on the looking-for-group
+// board with their blurb and schedule, and accept join requests, which the DM approves or
+// denies. With `"max_players": n` set, requests approved while the campaign is full are waitlisted and promoted in order as seats open. Only players count
+// toward the limit; the DM can make members co-DMs or observers instead.
+
+pub(crate) fn max_players(campaign: &Campaign) -> Option<i64> {
+    campaign.settings.get("max_players").and_then(|v| v.as_i64()).filter(|max| *max >= 0)
+}
+
+pub(crate) async fn count_players<'e, E>(executor: E, campaign_id: Uuid) -> Result<i64, sqlx::Error>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    // Co-DMs and observers don't take seats
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM campaign_players WHERE campaign_id = $1 AND role = 'player'")
+        .bind(campaign_id)
+        .fetch_one(executor)
+        .await
+}
+
+// Moves waitlisted users into any open seats, oldest first, and notifies them. Call after
+// a player leaves or the seat limit changes. Returns the promoted user ids.
+pub async fn fill_open_seats(pool: &PgPool, campaign_id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // Lock the campaign so concurrent approvals and promotions see the same seat count
+    let campaign = sqlx::query_as::<_, Campaign>("SELECT * FROM campaigns WHERE id = $1 AND deleted_at IS NULL FOR UPDATE")
+        .bind(campaign_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(campaign) = campaign else {
+        return Ok(Vec::new());
+    };
+
+    let open_seats = match max_players(&campaign) {
+        Some(max) => max - count_players(&mut *tx, campaign_id).await?,
+        None => i64::MAX,
+    };
+    if open_seats <= 0 {
+        return Ok(Vec::new());
+    }
+
+    let promoted = sqlx::query_as::<_, JoinRequest>(
+        "UPDATE join_requests SET status = 'approved' WHERE id IN (
+             SELECT id FROM join_requests WHERE campaign_id = $1 AND status = 'waitlisted'
+             ORDER BY reviewed_at, created_at LIMIT $2
+         ) RETURNING *"
+    )
+    .bind(campaign_id)
+    .bind(open_seats)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for request in &promoted {
+        sqlx::query("INSERT INTO campaign_players (campaign_id, player_id) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(campaign_id)
+            .bind(request.user_id)
+            .execute(&mut *tx)
+            .await?;
+        record(&mut tx, &DomainEvent::WaitlistPromoted { campaign: campaign.clone(), request: request.clone() }).await?;
+    }
+    tx.commit().await?;
+
+    let user_ids: Vec<Uuid> = promoted.into_iter().map(|request| request.user_id).collect();
+    if !user_ids.is_empty() {
+        bump_token_versions(pool, &user_ids).await?;
+    }
+    Ok(user_ids)
+}
+
+#[derive(Serialize)]
+pub struct DiscoverableCampaign {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub dm_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+// Discoverable campaigns the user isn't part of yet
+pub async fn discover_campaigns(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+) -> impl IntoResponse {
+    let joined: Vec<Uuid> = match campaign_roles(&pool, user.0).await {
+        Ok(roles) => roles.into_keys().collect(),
+        Err(e) => return db_error(e, "Failed to fetch campaigns"),
+    };
+    let campaigns = sqlx::query_as::<_, Campaign>(
+        "SELECT * FROM campaigns WHERE settings->>'discoverable' = 'true' AND archived_at IS NULL AND deleted_at IS NULL
+         AND id <> ALL($1)
+         ORDER BY created_at DESC"
+    )
+    .bind(&joined)
+    .fetch_all(&pool)
+    .await;
+
+    match campaigns {
+        Ok(campaigns) => {
+            let responses: Vec<DiscoverableCampaign> = campaigns.into_iter().map(|c| DiscoverableCampaign {
+                id: c.id,
+                name: c.name,
+                description: c.description,
+                dm_id: c.dm_id,
+                created_at: c.created_at,
+            }).collect();
+            Json(responses).into_response()
+        }
+        Err(e) => db_error(e, "Failed to fetch campaigns"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateJoinRequest {
+    pub message: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct JoinRequestResponse {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub message: Option<String>,
+    pub status: String,
+    pub response_message: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct JoinRequestRow {
+    #[sqlx(flatten)]
+    request: JoinRequest,
+    username: String,
+}
+
+impl JoinRequestResponse {
+    fn new(request: JoinRequest, username: String) -> Self {
+        JoinRequestResponse {
+            id: request.id,
+            campaign_id: request.campaign_id,
+            user_id: request.user_id,
+            username,
+            message: request.message,
+            status: request.status,
+            response_message: request.response_message,
+            reviewed_at: request.reviewed_at,
+            created_at: request.created_at,
+        }
+    }
+}
+
+const DEFAULT_LFG_PAGE: i64 = 20;
+const MAX_LFG_PAGE: i64 = 50;
+
+#[derive(Deserialize, Default)]
+pub struct LfgQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    // Only tables playing this system
+    pub system: Option<String>,
+}
+
+// A public looking-for-group listing, built from the campaign's settings
+#[derive(Serialize)]
+pub struct LfgListing {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub dm_username: String,
+    pub system: String,
+    pub level_range: LevelRange,
+    pub blurb: Option<String>,
+    pub schedule: Option<String>,
+    pub players: i64,
+    pub max_players: Option<u32>,
+    // None when there's no seat limit; 0 when new requests would be waitlisted
+    pub open_seats: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct LfgPage {
+    pub listings: Vec<LfgListing>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+// Listed campaigns; $1 is the system filter. Archived, frozen and deleted campaigns aren't recruiting.
+const LFG_FROM: &str =
+    "FROM campaigns c INNER JOIN users u ON u.id = c.dm_id
+     WHERE c.settings->>'discoverable' = 'true' AND c.archived_at IS NULL AND c.frozen_at IS NULL AND c.deleted_at IS NULL
+       AND ($1::TEXT IS NULL OR LOWER(c.settings->>'system') = LOWER($1))";
+
+// Public: the looking-for-group board, newest first. Joining still goes through a join request.
+pub async fn list_lfg(
+    Extension(pool): Extension<PgPool>,
+    Query(params): Query<LfgQuery>,
+) -> impl IntoResponse {
+    let limit = params.limit.unwrap_or(DEFAULT_LFG_PAGE).clamp(1, MAX_LFG_PAGE);
+    let offset = params.offset.unwrap_or(0).max(0);
+    let system = params.system.as_deref().map(str::trim).filter(|system| !system.is_empty());
+
+    let total = sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) {}", LFG_FROM))
+        .bind(system)
+        .fetch_one(&pool)
+        .await;
+    let total = match total {
+        Ok(total) => total,
+        Err(e) => return db_error(e, "Failed to fetch listings"),
+    };
+    let rows = sqlx::query_as::<_, (Uuid, String, Option<String>, String, serde_json::Value, i64, DateTime<Utc>)>(&format!(
+        "SELECT c.id, c.name, c.description, u.username, c.settings,
+                (SELECT COUNT(*) FROM campaign_players cp WHERE cp.campaign_id = c.id AND cp.role = 'player'), c.created_at
+         {} ORDER BY c.created_at DESC, c.id LIMIT $2 OFFSET $3",
+        LFG_FROM
+    ))
+    .bind(system)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&pool)
+    .await;
+
+    match rows {
+        Ok(rows) => {
+            let listings = rows.into_iter().filter_map(|(id, name, description, dm_username, settings, players, created_at)| {
+                let settings: CampaignSettings = match serde_json::from_value(settings) {
+                    Ok(settings) => settings,
+                    Err(e) => {
+                        tracing::warn!(error = %e, campaign_id = %id, "Skipping campaign in listings, invalid settings");
+                        return None;
+                    }
+                };
+                let open_seats = settings.max_players.map(|max| (i64::from(max) - players).max(0));
+                Some(LfgListing {
+                    id,
+                    name,
+                    description,
+                    dm_username,
+                    system: settings.system,
+                    level_range: settings.level_range,
+                    blurb: settings.blurb,
+                    schedule: settings.schedule,
+                    players,
+                    max_players: settings.max_players,
+                    open_seats,
+                    created_at,
+                })
+            }).collect();
+            Json(LfgPage { listings, total, limit, offset }).into_response()
+        }
+        Err(e) => db_error(e, "Failed to fetch listings"),
+    }
+}
+
+pub async fn create_join_request(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Json(payload): Json<CreateJoinRequest>,
+) -> impl IntoResponse {
+    let campaign = sqlx::query_as::<_, Campaign>(
+        "SELECT * FROM campaigns WHERE id = $1 AND settings->>'discoverable' = 'true' AND archived_at IS NULL AND deleted_at IS NULL"
+    )
+    .bind(campaign_id)
+    .fetch_optional(&pool)
+    .await;
+
+    let campaign = match campaign {
+        Ok(Some(campaign)) => campaign,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Campaign not found").into_response(),
+        Err(e) => return db_error(e, "Failed to fetch campaign"),
+    };
+
+    match resolve(&pool, user.0, Resource::Campaign(campaign.id)).await {
+        Ok(Some(_)) => return (StatusCode::CONFLICT, "Already a member of this campaign").into_response(),
+        Ok(None) => {}
+        Err(e) => return db_error(e, "Failed to fetch campaign"),
+    }
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(e, "Failed to create join request"),
+    };
+
+    let request = sqlx::query_as::<_, JoinRequest>(
+        "INSERT INTO join_requests (campaign_id, user_id, message) VALUES ($1, $2, $3) RETURNING *"
+    )
+    .bind(campaign_id)
+    .bind(user.0)
+    .bind(&payload.message)
+    .fetch_one(&mut *tx)
+    .await;
+
+    let request = match request {
+        Ok(request) => request,
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            return (StatusCode::CONFLICT, "A join request is already pending or waitlisted").into_response();
+        }
+        Err(e) => return db_error(e, "Failed to create join request"),
+    };
+
+    let username = sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE id = $1")
+        .bind(user.0)
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap_or_default();
+
+    let event = DomainEvent::JoinRequestReceived { campaign, request: request.clone(), username: username.clone() };
+    if let Err(e) = record(&mut tx, &event).await {
+        return db_error(e, "Failed to create join request");
+    }
+    if let Err(e) = tx.commit().await {
+        return db_error(e, "Failed to create join request");
+    }
+
+    (StatusCode::CREATED, Json(JoinRequestResponse::new(request, username))).into_response()
+}
+
+// Pending requests for the DM's review queue, oldest first
+pub async fn list_join_requests(
+    Extension(pool): Extension<PgPool>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let requests = sqlx::query_as::<_, JoinRequestRow>(
+        "SELECT jr.*, u.username FROM join_requests jr
+         INNER JOIN users u ON jr.user_id = u.id
+         WHERE jr.campaign_id = $1 AND jr.status = 'pending'
+         ORDER BY jr.created_at"
+    )
+    .bind(campaign_id)
+    .fetch_all(&pool)
+    .await;
+
+    match requests {
+        Ok(requests) => {
+            let responses: Vec<JoinRequestResponse> = requests
+                .into_iter()
+                .map(|row| JoinRequestResponse::new(row.request, row.username))
+                .collect();
+            Json(responses).into_response()
+        }
+        Err(e) => db_error(e, "Failed to fetch join requests"),
+    }
+}
+
+// The user's own requests, so they can see the DM's decision
+pub async fn list_my_join_requests(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+) -> impl IntoResponse {
+    let requests = sqlx::query_as::<_, JoinRequest>(
+        "SELECT * FROM join_requests WHERE user_id = $1 ORDER BY created_at DESC"
+    )
+    .bind(user.0)
+    .fetch_all(&pool)
+    .await;
+
+    let username = sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE id = $1")
+        .bind(user.0)
+        .fetch_one(&pool)
+        .await
+        .unwrap_or_default();
+
+    match requests {
+        Ok(requests) => {
+            let responses: Vec<JoinRequestResponse> = requests
+                .into_iter()
+                .map(|request| JoinRequestResponse::new(request, username.clone()))
+                .collect();
+            Json(responses).into_response()
+        }
+        Err(e) => db_error(e, "Failed to fetch join requests"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReviewJoinRequest {
+    pub message: Option<String>,
+}
+
+pub async fn approve_join_request(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path((campaign_id, request_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<ReviewJoinRequest>,
+) -> impl IntoResponse {
+    review_join_request(pool, user, campaign_id, request_id, payload.message, true).await
+}
+
+pub async fn deny_join_request(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path((campaign_id, request_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<ReviewJoinRequest>,
+) -> impl IntoResponse {
+    review_join_request(pool, user, campaign_id, request_id, payload.message, false).await
+}
+
+async fn review_join_request(
+    pool: PgPool,
+    user: AuthUser,
+    campaign_id: Uuid,
+    request_id: Uuid,
+    message: Option<String>,
+    approve: bool,
+) -> axum::response::Response {
+    let campaign = match sqlx::query_as::<_, Campaign>("SELECT * FROM campaigns WHERE id = $1").bind(campaign_id).fetch_one(&pool).await {
+        Ok(campaign) => campaign,
+        Err(e) => return db_error(e, "Failed to fetch campaign"),
+    };
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(e, "Failed to review join request"),
+    };
+
+    // Approvals take a seat if one is free; the campaign row lock serializes seat counting
+    let status = if !approve {
+        "denied"
+    } else if let Some(max) = max_players(&campaign) {
+        let locked = sqlx::query("SELECT id FROM campaigns WHERE id = $1 FOR UPDATE")
+            .bind(campaign_id)
+            .execute(&mut *tx)
+            .await;
+        match (locked, count_players(&mut *tx, campaign_id).await) {
+            (Ok(_), Ok(players)) if players >= max => "waitlisted",
+            (Ok(_), Ok(_)) => "approved",
+            _ => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to review join request").into_response(),
+        }
+    } else {
+        "approved"
+    };
+
+    // Only pending requests can be decided, and only once
+    let request = sqlx::query_as::<_, JoinRequest>(
+        "UPDATE join_requests SET status = $1, response_message = $2, reviewed_by = $3, reviewed_at = $4
+         WHERE id = $5 AND campaign_id = $6 AND status = 'pending' RETURNING *"
+    )
+    .bind(status)
+    .bind(&message)
+    .bind(user.0)
+    .bind(Utc::now())
+    .bind(request_id)
+    .bind(campaign_id)
+    .fetch_optional(&mut *tx)
+    .await;
+
+    let request = match request {
+        Ok(Some(request)) => request,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Pending join request not found").into_response(),
+        Err(e) => return db_error(e, "Failed to review join request"),
+    };
+
+    let seated = status == "approved";
+    if seated {
+        let added = sqlx::query(
+            "INSERT INTO campaign_players (campaign_id, player_id) VALUES ($1, $2) ON CONFLICT DO NOTHING"
+        )
+        .bind(campaign_id)
+        .bind(request.user_id)
+        .execute(&mut *tx)
+        .await;
+        if let Err(e) = added {
+            return db_error(e, "Failed to add player to campaign");
+        }
+    }
+
+    let event = DomainEvent::JoinRequestReviewed { campaign, request: request.clone(), message };
+    if let Err(e) = record(&mut tx, &event).await {
+        return db_error(e, "Failed to review join request");
+    }
+    if let Err(e) = tx.commit().await {
+        return db_error(e, "Failed to review join request");
+    }
+
+    if seated {
+        // New membership; tokens with embedded roles need refreshing
+        let _ = bump_token_versions(&pool, &[request.user_id]).await;
+    }
+
+    let username = sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE id = $1")
+        .bind(request.user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap_or_default();
+    Json(JoinRequestResponse::new(request, username)).into_response()
+}
+
+#[derive(Serialize)]
+pub struct WaitlistEntry {
+    pub position: usize,
+    pub request_id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub waitlisted_at: Option<DateTime<Utc>>,
+}
+
+// The DM's view of who is waiting for a seat, in promotion order
+pub async fn list_waitlist(
+    Extension(pool): Extension<PgPool>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let requests = sqlx::query_as::<_, JoinRequestRow>(
+        "SELECT jr.*, u.username FROM join_requests jr
+         INNER JOIN users u ON jr.user_id = u.id
+         WHERE jr.campaign_id = $1 AND jr.status = 'waitlisted'
+         ORDER BY jr.reviewed_at, jr.created_at"
+    )
+    .bind(campaign_id)
+    .fetch_all(&pool)
+    .await;
+
+    match requests {
+        Ok(requests) => {
+            let entries: Vec<WaitlistEntry> = requests
+                .into_iter()
+                .enumerate()
+                .map(|(index, row)| WaitlistEntry {
+                    position: index + 1,
+                    request_id: row.request.id,
+                    user_id: row.request.user_id,
+                    username: row.username,
+                    waitlisted_at: row.request.reviewed_at,
+                })
+                .collect();
+            Json(entries).into_response()
+        }
+        Err(e) => db_error(e, "Failed to fetch waitlist"),
+    }
+}
+
+// The DM removes a player, or a player leaves; the freed seat goes to the waitlist
+pub async fn remove_campaign_player(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path((campaign_id, player_id)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let is_dm = match resolve(&pool, user.0, Resource::Campaign(campaign_id)).await {
+        Ok(scope) => scope.is_some_and(|scope| scope.is_dm()),
+        Err(e) => return db_error(e, "Failed to verify access"),
+    };
+
+    if !is_dm && player_id != user.0 {
+        return (StatusCode::FORBIDDEN, "Only the DM can remove other players").into_response();
+    }
+
+    let res = sqlx::query("DELETE FROM campaign_players WHERE campaign_id = $1 AND player_id = $2")
+        .bind(campaign_id)
+        .bind(player_id)
+        .execute(&pool)
+        .await;
+
+    match res {
+        Ok(result) if result.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Player not found in campaign").into_response(),
+        Ok(_) => {
+            let _ = bump_token_versions(&pool, &[player_id]).await;
+            if let Err(e) = fill_open_seats(&pool, campaign_id).await {
+                tracing::error!(error = %e, %campaign_id, "Failed to promote from waitlist");
+            }
+            (StatusCode::OK, "Player removed").into_response()
+        }
+        Err(e) => db_error(e, "Failed to remove player"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SetPlayerRoleRequest {
+    pub role: CampaignRole,
+}
+
+#[derive(Serialize)]
+pub struct PlayerRoleResponse {
+    pub campaign_id: Uuid,
+    pub user_id: Uuid,
+    pub role: CampaignRole,
+}
+
+// Owning DM only. Moving a player out of a seat lets the waitlist in; taking one needs a free seat.
+pub async fn set_player_role(
+    Extension(pool): Extension<PgPool>,
+    Path((campaign_id, player_id)): Path<(Uuid, Uuid)>,
+    Json(payload): Json<SetPlayerRoleRequest>,
+) -> impl IntoResponse {
+    let role = match payload.role {
+        CampaignRole::CoDm => "co_dm",
+        CampaignRole::Player => "player",
+        CampaignRole::Observer => "observer",
+        CampaignRole::Dm => return (StatusCode::BAD_REQUEST, "Role must be co_dm, player or observer").into_response(),
+    };
+    let changed = async {
+        let mut tx = pool.begin().await?;
+        let campaign = sqlx::query_as::<_, Campaign>("SELECT * FROM campaigns WHERE id = $1 FOR UPDATE")
+            .bind(campaign_id)
+            .fetch_one(&mut *tx)
+            .await?;
+        let current = sqlx::query_scalar::<_, String>("SELECT role FROM campaign_players WHERE campaign_id = $1 AND player_id = $2")
+            .bind(campaign_id)
+            .bind(player_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(current) = current else {
+            return Ok(Err((StatusCode::NOT_FOUND, "Player not found in campaign")));
+        };
+        if role == "player" && current != "player" {
+            if let Some(max) = max_players(&campaign) {
+                if count_players(&mut *tx, campaign_id).await? >= max {
+                    return Ok(Err((StatusCode::CONFLICT, "Campaign is full")));
+                }
+            }
+        }
+        sqlx::query("UPDATE campaign_players SET role = $3 WHERE campaign_id = $1 AND player_id = $2")
+            .bind(campaign_id)
+            .bind(player_id)
+            .bind(role)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Ok(current == "player" && role != "player"))
+    }
+    .await;
+
+    match changed {
+        Ok(Ok(seat_freed)) => {
+            // The role is embedded in tokens
+            let _ = bump_token_versions(&pool, &[player_id]).await;
+            if seat_freed {
+                if let Err(e) = fill_open_seats(&pool, campaign_id).await {
+                    tracing::error!(error = %e, %campaign_id, "Failed to promote from waitlist");
+                }
+            }
+            Json(PlayerRoleResponse { campaign_id, user_id: player_id, role: payload.role }).into_response()
+        }
+        Ok(Err(refused)) => refused.into_response(),
+        Err(e) => db_error(e, "Failed to change role"),
+    }
+}
+
+#[derive(Serialize)]
+pub struct PlayerPermissionsResponse {
+    pub campaign_id: Uuid,
+    pub user_id: Uuid,
+    pub permissions: Permissions,
+}
+
+// Owning DM only; replaces the member's permissions as a whole
+pub async fn set_player_permissions(
+    Extension(pool): Extension<PgPool>,
+    Path((campaign_id, player_id)): Path<(Uuid, Uuid)>,
+    Json(permissions): Json<Permissions>,
+) -> impl IntoResponse {
+    let res = sqlx::query("UPDATE campaign_players SET permissions = $3 WHERE campaign_id = $1 AND player_id = $2")
+        .bind(campaign_id)
+        .bind(player_id)
+        .bind(permissions.to_stored())
+        .execute(&pool)
+        .await;
+    match res {
+        Ok(done) if done.rows_affected() == 0 => (StatusCode::NOT_FOUND, "Player not found in campaign").into_response(),
+        Ok(_) => Json(PlayerPermissionsResponse { campaign_id, user_id: player_id, permissions }).into_response(),
+        Err(e) => db_error(e, "Failed to change permissions"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Method;
+    use crate::scope::Access;
+
+    async fn create_test_pool() -> PgPool {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        sqlx::postgres::PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&database_url)
+            .await
+            .expect("Failed to create test pool")
+    }
+
+    #[tokio::test]
+    async fn test_join_request_approval() {
+        let pool = create_test_pool().await;
+
+        let dm_id = Uuid::new_v4();
+        let player_id = Uuid::new_v4();
+        let timestamp = Utc::now().timestamp();
+        for (id, name) in [(dm_id, "join_dm"), (player_id, "join_player")] {
+            sqlx::query("INSERT INTO users (id, email, username, password_hash, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)")
+                .bind(id)
+                .bind(format!("{}{}@example.com", name, timestamp))
+                .bind(format!("{}{}", name, timestamp))
+                .bind("hashed_password")
+                .bind(Utc::now())
+                .bind(Utc::now())
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let campaign_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO campaigns (id, name, description, dm_id, settings, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(campaign_id)
+            .bind("Open Table")
+            .bind("A discoverable campaign")
+            .bind(dm_id)
+            .bind(serde_json::json!({"discoverable": true}))
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let request = CreateJoinRequest { message: Some("Can I bring my bard?".to_string()) };
+        let response = create_join_request(Extension(pool.clone()), Extension(AuthUser(player_id)), Path(campaign_id), Json(request)).await;
+        assert_eq!(response.into_response().status(), StatusCode::CREATED);
+
+        // A second pending request is rejected
+        let request = CreateJoinRequest { message: None };
+        let response = create_join_request(Extension(pool.clone()), Extension(AuthUser(player_id)), Path(campaign_id), Json(request)).await;
+        assert_eq!(response.into_response().status(), StatusCode::CONFLICT);
+
+        let request_id = sqlx::query_scalar::<_, Uuid>("SELECT id FROM join_requests WHERE campaign_id = $1 AND user_id = $2")
+            .bind(campaign_id)
+            .bind(player_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+
+        // Only the DM can approve
+        let uri = format!("/campaigns/{}/join-requests/{}/approve", campaign_id, request_id);
+        let response = crate::test_support::call_as(&pool, player_id, Method::POST, &uri, Some(serde_json::json!({}))).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let review = ReviewJoinRequest { message: Some("Welcome!".to_string()) };
+        let response = approve_join_request(Extension(pool.clone()), Extension(AuthUser(dm_id)), Path((campaign_id, request_id)), Json(review)).await;
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+
+        assert!(resolve(&pool, player_id, Resource::Campaign(campaign_id)).await.unwrap().is_some());
+
+        crate::test_support::drain_outbox(&pool).await;
+        let kinds = sqlx::query_scalar::<_, String>("SELECT kind FROM notifications WHERE user_id = ANY($1) ORDER BY created_at")
+            .bind(vec![dm_id, player_id])
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(kinds, vec!["join_request_received", "join_request_approved"]);
+    }
+
+    #[tokio::test]
+    async fn test_waitlist_promotion() {
+        let pool = create_test_pool().await;
+
+        let timestamp = Utc::now().timestamp();
+        let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            sqlx::query("INSERT INTO users (id, email, username, password_hash, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)")
+                .bind(id)
+                .bind(format!("seat{}_{}@example.com", i, timestamp))
+                .bind(format!("seat{}_{}", i, timestamp))
+                .bind("hashed_password")
+                .bind(Utc::now())
+                .bind(Utc::now())
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let (dm_id, players) = (ids[0], &ids[1..]);
+
+        let campaign_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO campaigns (id, name, description, dm_id, settings, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(campaign_id)
+            .bind("Small Table")
+            .bind("One seat only")
+            .bind(dm_id)
+            .bind(serde_json::json!({"discoverable": true, "max_players": 1}))
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // Everyone asks to join and the DM approves them all
+        for player_id in players {
+            let request = CreateJoinRequest { message: None };
+            let response = create_join_request(Extension(pool.clone()), Extension(AuthUser(*player_id)), Path(campaign_id), Json(request)).await;
+            assert_eq!(response.into_response().status(), StatusCode::CREATED);
+            let request_id = sqlx::query_scalar::<_, Uuid>("SELECT id FROM join_requests WHERE campaign_id = $1 AND user_id = $2")
+                .bind(campaign_id)
+                .bind(player_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            let review = ReviewJoinRequest { message: None };
+            let response = approve_join_request(Extension(pool.clone()), Extension(AuthUser(dm_id)), Path((campaign_id, request_id)), Json(review)).await;
+            assert_eq!(response.into_response().status(), StatusCode::OK);
+        }
+
+        let waitlisted = sqlx::query_scalar::<_, Uuid>(
+            "SELECT user_id FROM join_requests WHERE campaign_id = $1 AND status = 'waitlisted' ORDER BY reviewed_at"
+        )
+        .bind(campaign_id)
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(waitlisted, vec![players[1], players[2]]);
+
+        let response = crate::test_support::call_as(&pool, players[0], Method::GET, &format!("/campaigns/{}/waitlist", campaign_id), None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // The seated player leaves and the first in line takes the seat
+        let response = remove_campaign_player(Extension(pool.clone()), Extension(AuthUser(players[0])), Path((campaign_id, players[0]))).await;
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+
+        let members = sqlx::query_scalar::<_, Uuid>("SELECT player_id FROM campaign_players WHERE campaign_id = $1")
+            .bind(campaign_id)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(members, vec![players[1]]);
+
+        crate::test_support::drain_outbox(&pool).await;
+        let kinds = sqlx::query_scalar::<_, String>("SELECT kind FROM notifications WHERE user_id = $1 ORDER BY created_at")
+            .bind(players[1])
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(kinds, vec!["join_request_waitlisted", "waitlist_promoted"]);
+    }
+
+    #[tokio::test]
+    async fn test_co_dms_share_dm_duties_but_not_ownership() {
+        let pool = create_test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let uri = format!("/campaigns/{}/players/{}/role", f.campaign_id, f.player_id);
+        let set_role = |by: Uuid, role: CampaignRole| {
+            crate::test_support::call_as(&pool, by, Method::PUT, &uri, Some(serde_json::json!({ "role": role })))
+        };
+
+        assert_eq!(set_role(f.player_id, CampaignRole::CoDm).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(set_role(f.dm_id, CampaignRole::Dm).await.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(set_role(f.dm_id, CampaignRole::CoDm).await.status(), StatusCode::OK);
+
+        let co_dm = resolve(&pool, f.player_id, Resource::Session(f.session_id)).await.unwrap().unwrap();
+        assert!(co_dm.allows(Access::Dm) && !co_dm.allows(Access::Owner));
+        // A co-DM can't promote themselves to owner duties
+        assert_eq!(set_role(f.player_id, CampaignRole::Player).await.status(), StatusCode::FORBIDDEN);
+
+        assert_eq!(set_role(f.dm_id, CampaignRole::Observer).await.status(), StatusCode::OK);
+        let observer = resolve(&pool, f.player_id, Resource::Character(f.character_id)).await.unwrap().unwrap();
+        assert!(observer.allows(Access::Member) && !observer.allows(Access::Play) && !observer.allows(Access::Modify));
+        assert_eq!(count_players(&pool, f.campaign_id).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_lfg_lists_recruiting_campaigns_publicly() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        // A system of its own keeps other tests' campaigns off this board
+        let system = format!("test-{}", Uuid::new_v4());
+        sqlx::query("UPDATE campaigns SET settings = $2 WHERE id = $1")
+            .bind(f.campaign_id)
+            .bind(serde_json::json!({"system": system, "discoverable": true, "max_players": 3, "schedule": "Fridays 19:00 CET"}))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        async fn board(pool: &PgPool, system: &str) -> serde_json::Value {
+            let params = LfgQuery { system: Some(system.to_uppercase()), ..Default::default() };
+            let response = list_lfg(Extension(pool.clone()), Query(params)).await.into_response();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        }
+        let page = board(&pool, &system).await;
+        assert_eq!(page["total"], 1);
+        let listing = &page["listings"][0];
+        assert_eq!(listing["schedule"], "Fridays 19:00 CET");
+        assert_eq!((listing["players"].as_i64(), listing["open_seats"].as_i64()), (Some(1), Some(2)));
+
+        sqlx::query("UPDATE campaigns SET archived_at = NOW() WHERE id = $1").bind(f.campaign_id).execute(&pool).await.unwrap();
+        assert_eq!(board(&pool, &system).await["total"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_permissions_hand_single_dm_powers_to_players() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let npc = sqlx::query_scalar::<_, Uuid>("INSERT INTO compendium_entries (campaign_id, source, slug, kind, name) VALUES ($1, 'module', 'sildar', 'npc', 'Sildar') RETURNING id")
+            .bind(f.campaign_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let initiative = Access::Permitted(crate::scope::Permission::ManageInitiative);
+        let xp = Access::Permitted(crate::scope::Permission::AwardXp);
+        assert!(crate::scope::check(&pool, f.player_id, Resource::Session(f.session_id), initiative).await.is_err());
+
+        let uri = format!("/campaigns/{}/players/{}/permissions", f.campaign_id, f.player_id);
+        let grant = |user_id: Uuid, permissions: Permissions| {
+            crate::test_support::call_as(&pool, user_id, Method::PUT, &uri, Some(serde_json::to_value(permissions).unwrap()))
+        };
+        let permissions = Permissions { can_manage_initiative: true, can_edit_npcs: true, ..Default::default() };
+        assert_eq!(grant(f.player_id, permissions).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(grant(f.dm_id, permissions).await.status(), StatusCode::OK);
+
+        assert!(crate::scope::check(&pool, f.player_id, Resource::Session(f.session_id), initiative).await.is_ok());
+        assert!(crate::scope::check(&pool, f.player_id, Resource::Session(f.session_id), xp).await.is_err());
+        assert!(crate::scope::check(&pool, f.player_id, Resource::Content(crate::content::ContentKind::Compendium, npc), Access::Modify).await.is_ok());
+        // Observers can't use theirs
+        sqlx::query("UPDATE campaign_players SET role = 'observer' WHERE campaign_id = $1 AND player_id = $2")
+            .bind(f.campaign_id)
+            .bind(f.player_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(crate::scope::check(&pool, f.player_id, Resource::Session(f.session_id), initiative).await.is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use axum::{http::header, response::IntoResponse, Extension};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::socket::{session_gauges, SessionState};
+
+// Realtime load in the Prometheus text format, for operators. Unauthenticated like /health;
+// it only exposes counts.
+
+// Upper bounds, in seconds, of the broadcast latency histogram buckets
+const LATENCY_BUCKETS: [f64; 8] = [0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+// WebSocket counters updated by the socket code
+#[derive(Default)]
+pub struct RealtimeMetrics {
+    pub connected_clients: AtomicU64,
+    pub messages_received: AtomicU64,
+    // Clients dropped because their outbound queue filled up
+    pub overflow_disconnects: AtomicU64,
+    pub broadcast_latency: Histogram,
+}
+
+impl RealtimeMetrics {
+    // Counts the socket as connected until the guard is dropped
+    pub fn track_connection(self: &Arc<Self>) -> ConnectionGuard {
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self.clone())
+    }
+}
+
+pub struct ConnectionGuard(Arc<RealtimeMetrics>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.connected_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+pub struct Histogram {
+    // Per bucket, not cumulative; the last slot is for values above every bound
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+        }
+        cumulative += self.buckets[LATENCY_BUCKETS.len()].load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, cumulative);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0);
+        let _ = writeln!(out, "{}_count {}", name, self.count.load(Ordering::Relaxed));
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, u64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
+
+pub async fn metrics(Extension(session_state): Extension<SessionState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(&session_state).await,
+    )
+}
+
+async fn render(session_state: &SessionState) -> String {
+    let gauges = session_gauges(session_state).await;
+    let evictions = &session_state.evictions;
+    let realtime = &session_state.metrics;
+
+    let mut out = String::new();
+    metric(&mut out, "yoda_ws_connected_clients", "gauge", "Open WebSocket connections", &[
+        (String::new(), realtime.connected_clients.load(Ordering::Relaxed)),
+    ]);
+    // Messages per second is rate(yoda_ws_messages_received_total[1m])
+    metric(&mut out, "yoda_ws_messages_received_total", "counter", "Client messages received over WebSockets", &[
+        (String::new(), realtime.messages_received.load(Ordering::Relaxed)),
+    ]);
+    metric(&mut out, "yoda_ws_overflow_disconnects_total", "counter", "Clients disconnected for falling too far behind", &[
+        (String::new(), realtime.overflow_disconnects.load(Ordering::Relaxed)),
+    ]);
+    realtime.broadcast_latency.render(&mut out, "yoda_ws_broadcast_seconds", "Time to encode a session broadcast and send it to every recipient");
+    metric(&mut out, "yoda_live_sessions", "gauge", "Sessions held in memory", &[(String::new(), gauges.sessions as u64)]);
+    metric(&mut out, "yoda_idle_live_sessions", "gauge", "In-memory sessions with no WebSocket connection", &[(String::new(), gauges.idle_sessions as u64)]);
+    let per_session: Vec<(String, u64)> = gauges
+        .connections
+        .iter()
+        .map(|(session_id, count)| (format!("{{session_id=\"{}\"}}", session_id), *count as u64))
+        .collect();
+    metric(&mut out, "yoda_session_connections", "gauge", "WebSocket connections joined to each in-memory session", &per_session);
+    metric(&mut out, "yoda_session_evictions_total", "counter", "In-memory sessions evicted by the janitor", &[
+        ("{reason=\"idle\"}".to_string(), evictions.idle.load(Ordering::Relaxed)),
+        ("{reason=\"capacity\"}".to_string(), evictions.capacity.load(Ordering::Relaxed)),
+    ]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_metrics_render_realtime_load() {
+        let state = SessionState::new(None);
+        let connected = state.metrics.track_connection();
+        state.metrics.messages_received.fetch_add(3, Ordering::Relaxed);
+        state.metrics.broadcast_latency.observe(Duration::from_micros(800));
+        state.metrics.broadcast_latency.observe(Duration::from_secs(2));
+        let session_id = uuid::Uuid::new_v4();
+        let _ = crate::socket::subscribe_events(&state, session_id, uuid::Uuid::new_v4(), None).await;
+
+        let out = render(&state).await;
+        assert!(out.contains("yoda_ws_connected_clients 1\n"));
+        assert!(out.contains("yoda_ws_messages_received_total 3\n"));
+        assert!(out.contains("yoda_ws_broadcast_seconds_bucket{le=\"0.0005\"} 0\n"));
+        assert!(out.contains("yoda_ws_broadcast_seconds_bucket{le=\"0.001\"} 1\n"));
+        assert!(out.contains("yoda_ws_broadcast_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(out.contains("yoda_ws_broadcast_seconds_count 2\n"));
+        assert!(out.contains(&format!("yoda_session_connections{{session_id=\"{}\"}} 0\n", session_id)));
+
+        drop(connected);
+        assert!(render(&state).await.contains("yoda_ws_connected_clients 0\n"));
+    }
+}
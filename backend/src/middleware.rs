@@ -1,38 +1,426 @@
-use axum::{http::{Request, StatusCode}, middleware::Next, response::Response};
-use jsonwebtoken::{decode, DecodingKey, Validation};
+use axum::{extract::{Path, State}, http::{header, HeaderValue, Request, StatusCode}, middleware::Next, response::{IntoResponse, Response}, RequestExt};
+use jsonwebtoken::{decode, encode, errors::ErrorKind, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
 use std::env;
 use uuid::Uuid;
 use axum::body::Body;
+use chrono::Utc;
+use crate::usage::{Metric, Subject};
+use crate::audit::{AuthEvent, ClientInfo};
+use crate::revocation::is_token_denied;
+use crate::scope::{authorize, authorize_campaign, Access, Resource};
+use crate::socket::SessionState;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    // Issued-at, issuer and audience; jwt_auth refuses tokens not meant for this server
+    #[serde(default)]
+    pub iat: usize,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub iss: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub aud: String,
+    // Campaign roles, only embedded when JWT_EMBED_CAMPAIGNS is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub campaigns: Option<HashMap<Uuid, CampaignRole>>,
+    // users.token_version at issue time; embedded roles are stale once it changes
+    #[serde(default)]
+    pub ver: i32,
+    // users.auth_epoch at issue time; tokens from an earlier epoch have been revoked
+    #[serde(default)]
+    pub epoch: i32,
+    // Server admin at issue time, for clients deciding what to show; `require_role` checks
+    // ADMIN_USER_IDS itself, so removing an admin takes effect straight away
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub admin: bool,
+    // Unique per token, so a single token can be denied (see revocation.rs); required
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    // The admin acting as this user, on tokens from POST /admin/impersonate/:user_id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonated_by: Option<Uuid>,
+}
+
+impl Claims {
+    // The registered claims every token needs; build_claims adds the rest
+    pub fn new(user_id: Uuid, exp: usize) -> Self {
+        Claims {
+            sub: user_id.to_string(),
+            exp,
+            iat: Utc::now().timestamp() as usize,
+            iss: jwt_issuer(),
+            aud: jwt_audience(),
+            jti: Some(Uuid::new_v4().to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CampaignRole {
+    // The campaign's owner, campaigns.dm_id
+    Dm,
+    // Shares the DM's duties, but can't delete the campaign or change roles
+    CoDm,
+    Player,
+    // Follows along without a seat or characters of their own
+    Observer,
+}
+
+impl CampaignRole {
+    pub fn is_dm(&self) -> bool {
+        matches!(self, CampaignRole::Dm | CampaignRole::CoDm)
+    }
+
+    // campaign_players.role; the owning DM isn't in that table
+    pub fn from_member_role(role: &str) -> Self {
+        match role {
+            "co_dm" => CampaignRole::CoDm,
+            "observer" => CampaignRole::Observer,
+            _ => CampaignRole::Player,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct AuthUser(pub Uuid);
 
-pub async fn jwt_auth(mut req: Request<Body>, next: Next) -> Result<Response, StatusCode> {
+// The id and expiry of the access token the request came with, when it carries an id
+#[derive(Clone, Debug)]
+pub struct TokenId {
+    pub jti: String,
+    pub exp: usize,
+}
+
+// The admin behind a request made with an impersonation token
+#[derive(Clone, Copy, Debug)]
+pub struct Impersonator(pub Uuid);
+
+// Up-to-date campaign roles from the token, present only when the token embeds them
+#[derive(Clone, Debug)]
+pub struct CampaignRoles(pub HashMap<Uuid, CampaignRole>);
+
+impl CampaignRoles {
+    pub fn role_for(&self, campaign_id: Uuid) -> Option<CampaignRole> {
+        self.0.get(&campaign_id).copied()
+    }
+}
+
+// Response header carrying a re-issued token when memberships changed since the old one
+pub const REFRESHED_TOKEN_HEADER: &str = "x-refreshed-token";
+
+// Token settings: JWT_SECRET signs tokens and has no default, so the server refuses to start
+// without it (see check_jwt_config). JWT_ISSUER and JWT_AUDIENCE (default "yoda" and
+// "yoda-api") go in every token and must match on the way back in, so tokens minted for
+// another deployment sharing the secret are refused. JWT_ACCESS_TTL_SECS sets how long access
+// tokens last (default 15 minutes, at most a day).
+
+// Shorter secrets are accepted with a warning
+const MIN_SECRET_BYTES: usize = 32;
+
+const DEFAULT_ACCESS_TOKEN_TTL_SECS: i64 = 15 * 60;
+const MAX_ACCESS_TOKEN_TTL_SECS: i64 = 24 * 60 * 60;
+
+// How far in the future `iat` may be, for clocks that disagree a little
+const IAT_LEEWAY_SECS: usize = 60;
+
+pub fn jwt_secret() -> String {
+    match env::var("JWT_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret,
+        // Unit tests run without the server's environment
+        _ if cfg!(test) => "test-secret".to_string(),
+        _ => panic!("JWT_SECRET must be set"),
+    }
+}
+
+// Called at startup
+pub fn check_jwt_config() -> Result<(), String> {
+    match env::var("JWT_SECRET") {
+        Ok(secret) if secret.len() >= MIN_SECRET_BYTES => {}
+        Ok(secret) if !secret.is_empty() => {
+            tracing::warn!("JWT_SECRET is shorter than {} bytes; use a long random value in production", MIN_SECRET_BYTES);
+        }
+        _ => return Err("JWT_SECRET must be set".to_string()),
+    }
+    if env::var("JWT_ACCESS_TTL_SECS").is_ok_and(|value| value.parse::<i64>().is_err()) {
+        return Err("JWT_ACCESS_TTL_SECS must be a number of seconds".to_string());
+    }
+    Ok(())
+}
+
+fn jwt_issuer() -> String {
+    env::var("JWT_ISSUER").unwrap_or_else(|_| "yoda".to_string())
+}
+
+fn jwt_audience() -> String {
+    env::var("JWT_AUDIENCE").unwrap_or_else(|_| "yoda-api".to_string())
+}
+
+// Access tokens are short-lived; clients renew them with a refresh token (see refresh.rs)
+pub fn access_token_ttl_secs() -> i64 {
+    env::var("JWT_ACCESS_TTL_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_ACCESS_TOKEN_TTL_SECS)
+        .clamp(60, MAX_ACCESS_TOKEN_TTL_SECS)
+}
+
+pub(crate) fn validation() -> Validation {
+    let mut validation = Validation::default();
+    validation.set_issuer(&[jwt_issuer()]);
+    validation.set_audience(&[jwt_audience()]);
+    validation.set_required_spec_claims(&["exp", "sub", "iss", "aud"]);
+    validation
+}
+
+// What the library doesn't check: a token id, and an issue time that isn't in the future
+fn has_id_and_issue_time(claims: &Claims) -> bool {
+    let now = Utc::now().timestamp() as usize;
+    claims.jti.as_deref().is_some_and(|jti| !jti.is_empty()) && claims.iat > 0 && claims.iat <= now + IAT_LEEWAY_SECS
+}
+
+// Server operators, from ADMIN_USER_IDS (comma-separated user ids)
+pub fn is_admin(user_id: Uuid) -> bool {
+    env::var("ADMIN_USER_IDS")
+        .map(|ids| ids.split(',').any(|id| Uuid::parse_str(id.trim()) == Ok(user_id)))
+        .unwrap_or(false)
+}
+
+// Campaign roles a route can require, lowest last
+#[derive(Clone, Copy, Debug)]
+pub enum Role {
+    // The campaign's own DM
+    Owner,
+    // DM or co-DM
+    Dm,
+    // Anyone in the campaign, observers included
+    Member,
+}
+
+impl Role {
+    fn access(self) -> Access {
+        match self {
+            Role::Owner => Access::Owner,
+            Role::Dm => Access::Dm,
+            Role::Member => Access::Member,
+        }
+    }
+}
+
+// Who a route is for, with the message for everyone else. Campaign and Session roles are
+// checked against the campaign or session named by the route's `:id`.
+#[derive(Clone, Copy, Debug)]
+pub enum RequiredRole {
+    // Server operators (see is_admin)
+    Admin,
+    Campaign(Role, &'static str),
+    Session(Role, &'static str),
+}
+
+// Layered inside jwt_auth with from_fn_with_state, so handlers needn't check the caller's role
+pub async fn require_role(State(required): State<RequiredRole>, mut req: Request<Body>, next: Next) -> Result<Response, Response> {
+    let Some(AuthUser(user_id)) = req.extensions().get::<AuthUser>().cloned() else {
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    };
+    if let RequiredRole::Admin = required {
+        if is_admin(user_id) {
+            return Ok(next.run(req).await);
+        }
+        // Recorded in the background, as scope.rs does
+        if let Some(pool) = req.extensions().get::<PgPool>().cloned() {
+            let client = ClientInfo::new(req.extensions(), req.headers());
+            let detail = serde_json::json!({ "path": req.uri().path() });
+            tokio::spawn(async move {
+                crate::audit::record(&pool, Some(user_id), AuthEvent::PermissionDenied, &client, detail).await;
+            });
+        }
+        return Err((StatusCode::FORBIDDEN, "Only server admins can do this").into_response());
+    }
+    let id = match req.extract_parts::<Path<HashMap<String, String>>>().await {
+        Ok(Path(params)) => params.get("id").and_then(|id| Uuid::parse_str(id).ok()),
+        Err(_) => None,
+    };
+    let Some(id) = id else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid id").into_response());
+    };
+    let Some(pool) = req.extensions().get::<PgPool>().cloned() else {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    };
+    match required {
+        // Token-embedded roles spare the lookup
+        RequiredRole::Campaign(role, message) => {
+            let roles = req.extensions().get::<CampaignRoles>().cloned();
+            authorize_campaign(&pool, roles.as_ref(), user_id, id, role.access(), message).await?;
+        }
+        RequiredRole::Session(role, message) => {
+            authorize(&pool, user_id, Resource::Session(id), role.access(), message).await?;
+        }
+        RequiredRole::Admin => {}
+    }
+    Ok(next.run(req).await)
+}
+
+// Layered inside jwt_auth on routes that could take over the account, such as changing the
+// password, which an admin acting as the user must not do
+pub async fn refuse_impersonation(req: Request<Body>, next: Next) -> Result<Response, Response> {
+    if let Some(Impersonator(admin_id)) = req.extensions().get::<Impersonator>() {
+        tracing::warn!(%admin_id, path = %req.uri().path(), "Refused a request made while impersonating");
+        return Err((StatusCode::FORBIDDEN, "Not allowed while impersonating a user").into_response());
+    }
+    Ok(next.run(req).await)
+}
+
+fn embed_campaigns_enabled() -> bool {
+    env::var("JWT_EMBED_CAMPAIGNS").map(|v| v == "true" || v == "1").unwrap_or(false)
+}
+
+async fn load_campaign_roles(pool: &PgPool, user_id: Uuid) -> Result<(i32, HashMap<Uuid, CampaignRole>), sqlx::Error> {
+    let ver = sqlx::query_scalar::<_, i32>("SELECT token_version FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+    let roles = crate::scope::campaign_roles(pool, user_id).await?;
+    Ok((ver, roles))
+}
+
+pub async fn build_claims(pool: &PgPool, user_id: Uuid, exp: usize) -> Result<Claims, sqlx::Error> {
+    let epoch = sqlx::query_scalar::<_, i32>("SELECT auth_epoch FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+    let mut claims = Claims { epoch, admin: is_admin(user_id), ..Claims::new(user_id, exp) };
+    if embed_campaigns_enabled() {
+        let (ver, roles) = load_campaign_roles(pool, user_id).await?;
+        claims.ver = ver;
+        claims.campaigns = Some(roles);
+    }
+    Ok(claims)
+}
+
+pub fn encode_token(claims: &Claims) -> Result<String, jsonwebtoken::errors::Error> {
+    encode(&Header::default(), claims, &EncodingKey::from_secret(jwt_secret().as_bytes()))
+}
+
+// Issues an access token for the user
+pub async fn issue_token(pool: &PgPool, user_id: Uuid) -> Result<String, String> {
+    let exp = (Utc::now() + chrono::Duration::seconds(access_token_ttl_secs())).timestamp() as usize;
+    let claims = build_claims(pool, user_id, exp)
+        .await
+        .map_err(|e| format!("Failed to load campaign roles: {}", e))?;
+    encode_token(&claims).map_err(|e| format!("Failed to encode token: {}", e))
+}
+
+// Call whenever campaign memberships change so tokens with embedded roles get refreshed
+pub async fn bump_token_versions(pool: &PgPool, user_ids: &[Uuid]) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET token_version = token_version + 1 WHERE id = ANY($1)")
+        .bind(user_ids)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// Whose token a request carries, checking only the signature and registered claims; for
+// layers that run before jwt_auth, such as the rate limiter
+pub fn token_subject(headers: &axum::http::HeaderMap) -> Option<Uuid> {
+    let token = headers.get(header::AUTHORIZATION)?.to_str().ok()?.strip_prefix("Bearer ")?;
+    let data = decode::<Claims>(token, &DecodingKey::from_secret(jwt_secret().as_bytes()), &validation()).ok()?;
+    Uuid::parse_str(&data.claims.sub).ok()
+}
+
+// Sent with the 401 for an expired access token, so clients know to refresh rather than log in
+fn token_expired() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, r#"Bearer error="invalid_token", error_description="The access token expired""#)],
+    )
+        .into_response()
+}
+
+pub async fn jwt_auth(mut req: Request<Body>, next: Next) -> Result<Response, Response> {
     let auth_header = req.headers().get("authorization").and_then(|h| h.to_str().ok());
     if let Some(auth_header) = auth_header {
         if let Some(token) = auth_header.strip_prefix("Bearer ") {
-            let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "secret".to_string());
+            let secret = jwt_secret();
             let token_data = decode::<Claims>(
                 token,
                 &DecodingKey::from_secret(secret.as_bytes()),
-                &Validation::default(),
+                &validation(),
             );
-            if let Ok(data) = token_data {
+            if token_data.as_ref().is_err_and(|e| *e.kind() == ErrorKind::ExpiredSignature) {
+                return Err(token_expired());
+            }
+            if let Some(data) = token_data.ok().filter(|data| has_id_and_issue_time(&data.claims)) {
                 if let Ok(user_id) = Uuid::parse_str(&data.claims.sub) {
+                    // Tokens issued before the user's last revocation are refused
+                    if let Some(session_state) = req.extensions().get::<SessionState>() {
+                        if session_state.revocations.is_revoked(user_id, data.claims.epoch).await {
+                            // Suspended and banned users are told why rather than just logged out
+                            if let Some(pool) = req.extensions().get::<PgPool>() {
+                                if let Ok(Some(restriction)) = crate::moderation::restriction(pool, user_id).await {
+                                    return Err(restriction.into_response());
+                                }
+                            }
+                            return Err(StatusCode::UNAUTHORIZED.into_response());
+                        }
+                        // As are tokens logged out one by one
+                        if let Some(jti) = &data.claims.jti {
+                            if is_token_denied(session_state, jti).await {
+                                return Err(StatusCode::UNAUTHORIZED.into_response());
+                            }
+                        }
+                    }
                     req.extensions_mut().insert(AuthUser(user_id));
-                    return Ok(next.run(req).await);
+                    if let Some(jti) = data.claims.jti.clone() {
+                        req.extensions_mut().insert(TokenId { jti, exp: data.claims.exp });
+                    }
+                    if let Some(admin_id) = data.claims.impersonated_by {
+                        req.extensions_mut().insert(Impersonator(admin_id));
+                    }
+                    tracing::Span::current().record("user_id", tracing::field::display(user_id));
+                    crate::usage::usage().record(Subject::User(user_id), Metric::ApiCalls, 1);
+
+                    // Embedded roles are only trusted while the token version is current
+                    let mut refreshed_token = None;
+                    let pool = req.extensions().get::<PgPool>().cloned();
+                    if let (Some(roles), Some(pool)) = (data.claims.campaigns, pool) {
+                        let current_ver = sqlx::query_scalar::<_, i32>("SELECT token_version FROM users WHERE id = $1")
+                            .bind(user_id)
+                            .fetch_one(&pool)
+                            .await;
+                        match current_ver {
+                            Ok(ver) if ver == data.claims.ver => {
+                                req.extensions_mut().insert(CampaignRoles(roles));
+                            }
+                            Ok(_) => {
+                                // Memberships changed: re-issue with the same expiry
+                                if let Ok(mut claims) = build_claims(&pool, user_id, data.claims.exp).await {
+                                    // An impersonation token stays one
+                                    claims.impersonated_by = data.claims.impersonated_by;
+                                    if let Some(roles) = &claims.campaigns {
+                                        req.extensions_mut().insert(CampaignRoles(roles.clone()));
+                                    }
+                                    refreshed_token = encode_token(&claims).ok();
+                                }
+                            }
+                            Err(_) => {}
+                        }
+                    }
+
+                    let mut response = next.run(req).await;
+                    if let Some(value) = refreshed_token.and_then(|t| HeaderValue::from_str(&t).ok()) {
+                        response.headers_mut().insert(REFRESHED_TOKEN_HEADER, value);
+                    }
+                    return Ok(response);
                 }
             }
         }
     }
-    Err(StatusCode::UNAUTHORIZED)
+    Err(StatusCode::UNAUTHORIZED.into_response())
 }
 
 #[cfg(test)]
@@ -48,6 +436,7 @@ mod tests {
         let claims = Claims {
             sub: user_id.to_string(),
             exp,
+            ..Default::default()
         };
         encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap()
     }
@@ -78,6 +467,7 @@ mod tests {
         let claims = Claims {
             sub: user_id.to_string(),
             exp,
+            ..Default::default()
         };
         let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes())).unwrap();
         
@@ -110,6 +500,7 @@ mod tests {
         let claims = Claims {
             sub: user_id.to_string(),
             exp,
+            ..Default::default()
         };
         
         // Test serialization
@@ -119,4 +510,56 @@ mod tests {
         assert_eq!(deserialized.sub, user_id.to_string());
         assert_eq!(deserialized.exp, exp);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_embedded_campaign_roles_round_trip() {
+        let campaign_id = Uuid::new_v4();
+        let mut campaigns = HashMap::new();
+        campaigns.insert(campaign_id, CampaignRole::Dm);
+        let claims = Claims {
+            sub: Uuid::new_v4().to_string(),
+            exp: (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
+            campaigns: Some(campaigns),
+            ver: 3,
+            ..Default::default()
+        };
+
+        let token = encode_token(&claims).unwrap();
+        let decoded = decode::<Claims>(
+            &token,
+            &DecodingKey::from_secret(jwt_secret().as_bytes()),
+            &Validation::default(),
+        ).unwrap().claims;
+
+        let roles = CampaignRoles(decoded.campaigns.unwrap());
+        assert_eq!(roles.role_for(campaign_id), Some(CampaignRole::Dm));
+        assert_eq!(roles.role_for(Uuid::new_v4()), None);
+        assert_eq!(decoded.ver, 3);
+    }
+
+    #[test]
+    fn test_tokens_without_roles_still_decode() {
+        // Tokens issued before roles were added only carry sub/exp
+        let json = r#"{"sub":"abc","exp":1}"#;
+        let claims: Claims = serde_json::from_str(json).unwrap();
+        assert!(claims.campaigns.is_none());
+        assert_eq!(claims.ver, 0);
+    }
+
+    #[test]
+    fn test_tokens_for_another_server_or_without_an_id_are_refused() {
+        let user_id = Uuid::new_v4();
+        let exp = (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize;
+        let accepted = |claims: Claims| {
+            decode::<Claims>(&encode_token(&claims).unwrap(), &DecodingKey::from_secret(jwt_secret().as_bytes()), &validation())
+                .is_ok_and(|data| has_id_and_issue_time(&data.claims))
+        };
+
+        assert!(accepted(Claims::new(user_id, exp)));
+        assert!(!accepted(Claims { iss: "someone-else".to_string(), ..Claims::new(user_id, exp) }));
+        assert!(!accepted(Claims { aud: "someone-else".to_string(), ..Claims::new(user_id, exp) }));
+        assert!(!accepted(Claims { sub: user_id.to_string(), exp, ..Default::default() }));
+        assert!(!accepted(Claims { jti: None, ..Claims::new(user_id, exp) }));
+        assert!(!accepted(Claims { iat: exp, ..Claims::new(user_id, exp) }));
+    }
+}
@@ -11,15 +11,20 @@ pub struct User {
     pub password_hash: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    // Set by an admin; disabled users can't log in
+    pub disabled_at: Option<DateTime<Utc>>,
+    // Until the emailed link is followed, login is refused (see verification.rs)
+    pub email_verified: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Campaign {
     pub id: Uuid,
     pub name: String,
     pub description: Option<String>,
     pub dm_id: Uuid,
     pub settings: serde_json::Value,
+    pub archived_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -36,13 +41,19 @@ pub struct Session {
     pub game_state: serde_json::Value,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
-}
-
-#[derive(Debug, Serialize, Deserialize, FromRow)]
-pub struct CampaignPlayer {
-    pub campaign_id: Uuid,
-    pub player_id: Uuid,
-    pub joined_at: DateTime<Utc>,
+    pub paused_at: Option<DateTime<Utc>>,
+    pub paused_seconds: i32,
+    pub previous_session_id: Option<Uuid>,
+    // Saved when the in-memory session is evicted; see socket::LiveState
+    #[serde(skip)]
+    pub live_state: Option<serde_json::Value>,
+    // Seed the session's rolls are drawn from, if it was created with one; kept from
+    // players until the session ends, since it predicts every roll
+    #[serde(skip)]
+    pub rng_seed: Option<i64>,
+    // Rolls taken from the seed so far
+    #[serde(skip)]
+    pub roll_counter: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -62,6 +73,9 @@ pub struct Character {
     pub inventory: serde_json::Value,
     pub spells: serde_json::Value,
     pub features: serde_json::Value,
+    // Older revision snapshots predate the column
+    #[serde(default)]
+    pub xp: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -76,6 +90,145 @@ pub struct EventLog {
     pub created_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct AbilityScoreRoll {
+    pub id: Uuid,
+    pub character_id: Uuid,
+    pub session_id: Option<Uuid>,
+    pub rolled_by: Option<Uuid>,
+    pub method: String,
+    pub rolls: serde_json::Value,
+    pub scores: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Notification {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub kind: String,
+    pub data: serde_json::Value,
+    pub read_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct JoinRequest {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub user_id: Uuid,
+    pub message: Option<String>,
+    pub status: String,
+    pub response_message: Option<String>,
+    pub reviewed_by: Option<Uuid>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct CompendiumEntry {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub source: String,
+    pub slug: String,
+    pub kind: String,
+    pub name: String,
+    pub data: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct CampaignNote {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub title: String,
+    // Markdown; [[Other Note]] links to another note by title
+    pub content: String,
+    pub tags: Vec<String>,
+    // "dm" or "shared"
+    pub visibility: String,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Location {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    // The region, city or building this place is in
+    pub parent_id: Option<Uuid>,
+    pub name: String,
+    pub description: String,
+    // Where the place is on a map: an image URL, uploaded asset or grid reference
+    pub map_ref: Option<String>,
+    pub dm_notes: String,
+    // What players are told about the place
+    pub summary: String,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ContentGrant {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub entity_type: String,
+    pub entity_id: Uuid,
+    pub user_id: Uuid,
+    pub granted_by: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ContentContribution {
+    pub id: Uuid,
+    pub entity_type: String,
+    pub entity_id: Uuid,
+    pub user_id: Uuid,
+    pub username: String,
+    pub changes: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct EncounterReward {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub campaign_id: Uuid,
+    pub xp_total: i32,
+    pub defeated: serde_json::Value,
+    pub xp_split: serde_json::Value,
+    pub loot: serde_json::Value,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub combat_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct LootItem {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub reward_id: Option<Uuid>,
+    pub name: String,
+    pub quantity: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+// One fight in a session; `state` is its GameState and ended_at is NULL while it runs
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Combat {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub state: serde_json::Value,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GameState {
     pub initiative_order: Vec<InitiativeEntry>,
@@ -83,6 +236,34 @@ pub struct GameState {
     pub round: i32,
     pub combat_active: bool,
     pub conditions: Vec<Condition>,
+    // Pass over combatants at 0 HP when advancing turns
+    #[serde(default = "default_skip_downed")]
+    pub skip_downed: bool,
+}
+
+fn default_skip_downed() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    Surprised,
+    Down,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SkippedCombatant {
+    pub id: Uuid,
+    pub name: String,
+    pub reason: SkipReason,
+}
+
+// Outcome of advancing the turn, for the TurnChanged broadcast
+#[derive(Debug, Default)]
+pub struct TurnAdvance {
+    pub skipped: Vec<SkippedCombatant>,
+    pub surprise_ended: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -91,11 +272,74 @@ pub struct InitiativeEntry {
     pub name: String,
     pub initiative: i32,
     pub is_player: bool,
+    // When set, the database keeps hp_current, hp_max and ac in step with the character row
     pub character_id: Option<Uuid>,
     pub user_id: Option<Uuid>,
     pub hp_current: Option<i32>,
     pub hp_max: Option<i32>,
     pub ac: Option<i32>,
+    #[serde(default)]
+    pub surprised: bool,
+    // Challenge rating of a monster, as a number or a fraction string ("1/4"); when absent
+    // it is looked up in the campaign's compendium by name
+    #[serde(default, deserialize_with = "crate::rewards::deserialize_cr", skip_serializing_if = "Option::is_none")]
+    pub cr: Option<f64>,
+}
+
+impl GameState {
+    // Moves current_turn to the next combatant who can act. Surprised combatants lose their
+    // turn in round 1 and the surprise wears off when round 2 begins. Combatants at 0 HP are
+    // passed over when skip_downed is set, unless nobody is left standing.
+    pub fn advance_turn(&mut self) -> TurnAdvance {
+        let mut advance = TurnAdvance::default();
+        let len = self.initiative_order.len();
+        if len == 0 {
+            return advance;
+        }
+
+        let skip_downed = self.skip_downed && self.initiative_order.iter().any(|entry| entry.hp_current != Some(0));
+        let mut next = match self.current_turn.and_then(|id| self.initiative_order.iter().position(|entry| entry.id == id)) {
+            Some(index) => index + 1,
+            None => 0,
+        };
+        loop {
+            // Increment round if we've gone through all entries
+            if next >= len {
+                next = 0;
+                self.round += 1;
+                if self.round >= 2 && self.clear_surprise() {
+                    advance.surprise_ended = true;
+                }
+            }
+            let entry = &self.initiative_order[next];
+            let reason = if self.round == 1 && entry.surprised {
+                Some(SkipReason::Surprised)
+            } else if skip_downed && entry.hp_current == Some(0) {
+                Some(SkipReason::Down)
+            } else {
+                None
+            };
+            match reason {
+                Some(reason) => {
+                    advance.skipped.push(SkippedCombatant { id: entry.id, name: entry.name.clone(), reason });
+                    next += 1;
+                }
+                None => break,
+            }
+        }
+
+        self.current_turn = Some(self.initiative_order[next].id);
+        advance
+    }
+
+    fn clear_surprise(&mut self) -> bool {
+        let mut cleared = false;
+        for entry in self.initiative_order.iter_mut().filter(|entry| entry.surprised) {
+            entry.surprised = false;
+            cleared = true;
+        }
+        cleared
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -105,4 +349,123 @@ pub struct Condition {
     pub duration: Option<i32>,
     pub description: String,
     pub applied_at: DateTime<Utc>,
-} 
\ No newline at end of file
+}
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Quest {
+    pub id: Uuid,
+    pub campaign_id: Uuid,
+    pub character_id: Option<Uuid>,
+    pub title: String,
+    pub description: Option<String>,
+    pub status: String,
+    pub source: String,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, surprised: bool) -> InitiativeEntry {
+        InitiativeEntry {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            initiative: 10,
+            is_player: false,
+            character_id: None,
+            user_id: None,
+            hp_current: Some(10),
+            hp_max: Some(10),
+            ac: Some(12),
+            surprised,
+            cr: None,
+        }
+    }
+
+    fn game_state(entries: Vec<InitiativeEntry>) -> GameState {
+        GameState {
+            initiative_order: entries,
+            current_turn: None,
+            round: 1,
+            combat_active: true,
+            conditions: Vec::new(),
+            skip_downed: true,
+        }
+    }
+
+    #[test]
+    fn test_advance_turn_cycles_and_counts_rounds() {
+        let mut state = game_state(vec![entry("Fighter", false), entry("Goblin", false)]);
+        let (first, second) = (state.initiative_order[0].id, state.initiative_order[1].id);
+
+        state.advance_turn();
+        assert_eq!(state.current_turn, Some(first));
+        state.advance_turn();
+        assert_eq!(state.current_turn, Some(second));
+        state.advance_turn();
+        assert_eq!(state.current_turn, Some(first));
+        assert_eq!(state.round, 2);
+    }
+
+    #[test]
+    fn test_surprised_skipped_in_round_one_only() {
+        let mut state = game_state(vec![entry("Rogue", false), entry("Goblin", true), entry("Wolf", false)]);
+        let ids: Vec<Uuid> = state.initiative_order.iter().map(|e| e.id).collect();
+
+        assert!(!state.advance_turn().surprise_ended);
+        assert_eq!(state.current_turn, Some(ids[0]));
+        let advance = state.advance_turn();
+        assert!(!advance.surprise_ended);
+        assert_eq!(state.current_turn, Some(ids[2]));
+        assert_eq!(advance.skipped.len(), 1);
+        assert_eq!(advance.skipped[0].reason, SkipReason::Surprised);
+
+        // Round 2 begins: surprise wears off and the goblin acts normally
+        assert!(state.advance_turn().surprise_ended);
+        assert_eq!(state.round, 2);
+        assert!(state.initiative_order.iter().all(|e| !e.surprised));
+        state.advance_turn();
+        assert_eq!(state.current_turn, Some(ids[1]));
+    }
+
+    #[test]
+    fn test_everyone_surprised_rolls_into_round_two() {
+        let mut state = game_state(vec![entry("Goblin", true), entry("Wolf", true)]);
+        assert!(state.advance_turn().surprise_ended);
+        assert_eq!(state.round, 2);
+        assert_eq!(state.current_turn, Some(state.initiative_order[0].id));
+    }
+
+    #[test]
+    fn test_downed_combatants_are_skipped() {
+        let mut state = game_state(vec![entry("Fighter", false), entry("Goblin", false), entry("Wolf", false)]);
+        state.initiative_order[1].hp_current = Some(0);
+        let ids: Vec<Uuid> = state.initiative_order.iter().map(|e| e.id).collect();
+
+        state.advance_turn();
+        let advance = state.advance_turn();
+        assert_eq!(state.current_turn, Some(ids[2]));
+        assert_eq!(advance.skipped.len(), 1);
+        assert_eq!(advance.skipped[0].id, ids[1]);
+        assert_eq!(advance.skipped[0].reason, SkipReason::Down);
+
+        // With skipping turned off the goblin gets its turn (e.g. for death saves)
+        state.skip_downed = false;
+        state.current_turn = Some(ids[0]);
+        assert!(state.advance_turn().skipped.is_empty());
+        assert_eq!(state.current_turn, Some(ids[1]));
+    }
+
+    #[test]
+    fn test_everyone_down_still_advances() {
+        let mut state = game_state(vec![entry("Goblin", false), entry("Wolf", false)]);
+        for entry in state.initiative_order.iter_mut() {
+            entry.hp_current = Some(0);
+        }
+        let advance = state.advance_turn();
+        assert!(advance.skipped.is_empty());
+        assert_eq!(state.current_turn, Some(state.initiative_order[0].id));
+    }
+}
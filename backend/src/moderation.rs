@@ -0,0 +1,247 @@
+use axum::{extract::Path, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::middleware::{is_admin, AuthUser};
+use crate::revocation::revoke_user;
+use crate::socket::SessionState;
+
+// Account moderation. An admin can suspend a user until a given time or ban them outright;
+// either way they're logged out everywhere, their sockets are closed and jwt_auth refuses
+// them with a JSON body saying why, instead of a bare 401. Campaigns they run are frozen
+// rather than deleted: members can still read them, but nothing changes until the DM is
+// reinstated, and their live sessions are paused. Suspensions lift on their own from the
+// maintenance task.
+
+const MAX_REASON_CHARS: usize = 500;
+
+#[derive(Deserialize)]
+pub struct SuspendRequest {
+    pub until: DateTime<Utc>,
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct BanRequest {
+    pub reason: Option<String>,
+}
+
+// Why a user is locked out, sent to them with 403
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Restriction {
+    pub code: &'static str,
+    pub error: &'static str,
+    // Absent for bans
+    pub until: Option<DateTime<Utc>>,
+    pub reason: Option<String>,
+}
+
+impl IntoResponse for Restriction {
+    fn into_response(self) -> Response {
+        (StatusCode::FORBIDDEN, Json(self)).into_response()
+    }
+}
+
+// None while the account is in good standing. Accounts disabled without a suspension read
+// as banned.
+pub async fn restriction(pool: &PgPool, user_id: Uuid) -> Result<Option<Restriction>, sqlx::Error> {
+    let row = sqlx::query_as::<_, (Option<DateTime<Utc>>, Option<DateTime<Utc>>, Option<String>)>(
+        "SELECT disabled_at, suspended_until, suspension_reason FROM users WHERE id = $1"
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.and_then(|(disabled_at, until, reason)| {
+        disabled_at?;
+        Some(match until {
+            Some(until) => Restriction { code: "account_suspended", error: "Account suspended", until: Some(until), reason },
+            None => Restriction { code: "account_banned", error: "Account banned", until: None, reason },
+        })
+    }))
+}
+
+// For routes that found the account disabled: the restriction when there is one
+pub async fn refuse(pool: &PgPool, user_id: Uuid) -> Response {
+    match restriction(pool, user_id).await {
+        Ok(Some(restriction)) => restriction.into_response(),
+        Ok(None) => (StatusCode::FORBIDDEN, "Account disabled").into_response(),
+        Err(e) => db_error(e, "Failed to check account"),
+    }
+}
+
+fn clean_reason(reason: Option<String>) -> Option<String> {
+    reason.map(|reason| reason.trim().chars().take(MAX_REASON_CHARS).collect::<String>()).filter(|reason| !reason.is_empty())
+}
+
+// Locks the account, freezes the user's campaigns and pauses their live sessions, then
+// revokes everything they hold. None when the user doesn't exist.
+async fn restrict(
+    pool: &PgPool,
+    session_state: &SessionState,
+    user_id: Uuid,
+    until: Option<DateTime<Utc>>,
+    reason: Option<String>,
+) -> Result<Option<()>, sqlx::Error> {
+    let now = Utc::now();
+    let mut tx = pool.begin().await?;
+    let found = sqlx::query(
+        "UPDATE users SET disabled_at = COALESCE(disabled_at, $2), suspended_until = $3, suspension_reason = $4, updated_at = $2 WHERE id = $1"
+    )
+    .bind(user_id)
+    .bind(now)
+    .bind(until)
+    .bind(&reason)
+    .execute(&mut *tx)
+    .await?;
+    if found.rows_affected() == 0 {
+        return Ok(None);
+    }
+    sqlx::query("UPDATE campaigns SET frozen_at = COALESCE(frozen_at, $2) WHERE dm_id = $1")
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+    let paused = sqlx::query_scalar::<_, Uuid>(
+        "UPDATE sessions s SET status = 'paused', paused_at = $2, updated_at = $2
+         FROM campaigns c WHERE s.campaign_id = c.id AND c.dm_id = $1 AND s.status = 'active'
+         RETURNING s.id"
+    )
+    .bind(user_id)
+    .bind(now)
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    for session_id in paused {
+        crate::socket::pause_session(session_state, session_id, now).await;
+    }
+    revoke_user(pool, session_state, user_id).await?;
+    Ok(Some(()))
+}
+
+// Admin only (see require_role)
+pub async fn suspend_user(
+    Extension(pool): Extension<PgPool>,
+    Extension(session_state): Extension<SessionState>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user_id): Path<Uuid>,
+    Json(payload): Json<SuspendRequest>,
+) -> impl IntoResponse {
+    if user_id == admin.0 || is_admin(user_id) {
+        return (StatusCode::FORBIDDEN, "Admins can't be suspended").into_response();
+    }
+    if payload.until <= Utc::now() {
+        return (StatusCode::BAD_REQUEST, "Suspensions must end in the future").into_response();
+    }
+    match restrict(&pool, &session_state, user_id, Some(payload.until), clean_reason(payload.reason)).await {
+        Ok(Some(())) => (StatusCode::OK, "Account suspended").into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => db_error(e, "Failed to suspend account"),
+    }
+}
+
+// Admin only (see require_role); lasts until the user is reinstated
+pub async fn ban_user(
+    Extension(pool): Extension<PgPool>,
+    Extension(session_state): Extension<SessionState>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user_id): Path<Uuid>,
+    payload: Option<Json<BanRequest>>,
+) -> impl IntoResponse {
+    let Json(payload) = payload.unwrap_or_default();
+    if user_id == admin.0 || is_admin(user_id) {
+        return (StatusCode::FORBIDDEN, "Admins can't be banned").into_response();
+    }
+    match restrict(&pool, &session_state, user_id, None, clean_reason(payload.reason)).await {
+        Ok(Some(())) => (StatusCode::OK, "Account banned").into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => db_error(e, "Failed to ban account"),
+    }
+}
+
+// Clears the restriction and thaws the user's campaigns. Paused sessions stay paused for
+// the DM to resume.
+async fn lift(pool: &PgPool, user_ids: &[Uuid]) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let lifted = sqlx::query(
+        "UPDATE users SET disabled_at = NULL, suspended_until = NULL, suspension_reason = NULL, updated_at = NOW() WHERE id = ANY($1)"
+    )
+    .bind(user_ids)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query("UPDATE campaigns SET frozen_at = NULL WHERE dm_id = ANY($1) AND frozen_at IS NOT NULL")
+        .bind(user_ids)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(lifted.rows_affected())
+}
+
+// Admin only (see require_role); ends a suspension or ban early
+pub async fn reinstate_user(
+    Extension(pool): Extension<PgPool>,
+    Path(user_id): Path<Uuid>,
+) -> impl IntoResponse {
+    match lift(&pool, &[user_id]).await {
+        Ok(0) => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Account reinstated").into_response(),
+        Err(e) => db_error(e, "Failed to reinstate account"),
+    }
+}
+
+// Run by the maintenance task
+pub async fn lift_expired_suspensions(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let expired = sqlx::query_scalar::<_, Uuid>("SELECT id FROM users WHERE suspended_until <= NOW()")
+        .fetch_all(pool)
+        .await?;
+    if !expired.is_empty() {
+        lift(pool, &expired).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scope::{check, Access, Denied, Resource};
+
+    #[tokio::test]
+    async fn test_suspended_dms_campaigns_are_frozen_until_reinstated() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let session_state = SessionState::new(None);
+        let suspend = |until: DateTime<Utc>| {
+            let payload = Json(SuspendRequest { until, reason: Some("  Harassment in chat ".to_string()) });
+            suspend_user(Extension(pool.clone()), Extension(session_state.clone()), Extension(AuthUser(f.outsider_id)), Path(f.dm_id), payload)
+        };
+
+        assert_eq!(suspend(Utc::now() - chrono::Duration::hours(1)).await.into_response().status(), StatusCode::BAD_REQUEST);
+        let until = Utc::now() + chrono::Duration::days(3);
+        assert_eq!(suspend(until).await.into_response().status(), StatusCode::OK);
+
+        let restricted = restriction(&pool, f.dm_id).await.unwrap().unwrap();
+        assert_eq!(restricted.code, "account_suspended");
+        assert_eq!(restricted.reason.as_deref(), Some("Harassment in chat"));
+        assert_eq!(restriction(&pool, f.player_id).await.unwrap(), None);
+
+        // Players can still read the campaign but not change their characters
+        assert!(check(&pool, f.player_id, Resource::Campaign(f.campaign_id), Access::Member).await.is_ok());
+        assert!(matches!(check(&pool, f.player_id, Resource::Character(f.character_id), Access::Modify).await, Err(Denied::Frozen)));
+        let status = sqlx::query_scalar::<_, String>("SELECT status FROM sessions WHERE id = $1").bind(f.session_id).fetch_one(&pool).await.unwrap();
+        assert_eq!(status, "paused");
+
+        let response = reinstate_user(Extension(pool.clone()), Path(f.dm_id)).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(restriction(&pool, f.dm_id).await.unwrap(), None);
+        assert!(check(&pool, f.player_id, Resource::Character(f.character_id), Access::Modify).await.is_ok());
+
+        let response = ban_user(Extension(pool.clone()), Extension(session_state.clone()), Extension(AuthUser(f.outsider_id)), Path(f.dm_id), None).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(restriction(&pool, f.dm_id).await.unwrap().unwrap().code, "account_banned");
+        // Bans don't lift on their own
+        lift_expired_suspensions(&pool).await.unwrap();
+        assert!(restriction(&pool, f.dm_id).await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,373 @@
+use axum::{extract::{Path, Query}, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::middleware::AuthUser;
+use crate::models::CampaignNote;
+use crate::scope::{authorize, Access, CampaignScope, Resource};
+
+// Campaign notes, a small wiki for session prep. Notes are markdown with a title and tags;
+// `[[Another Note]]` (or `[[Another Note|shown text]]`) links to another note of the same
+// campaign by title. The DM and co-DMs write them. DM-only notes are hidden from players
+// entirely, links and backlinks included; shared notes are readable by every member.
+
+const MAX_TITLE_CHARS: usize = 255;
+const MAX_CONTENT_CHARS: usize = 100_000;
+const MAX_TAGS: usize = 20;
+const MAX_TAG_CHARS: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    Dm,
+    Shared,
+}
+
+impl Visibility {
+    fn as_str(self) -> &'static str {
+        match self {
+            Visibility::Dm => "dm",
+            Visibility::Shared => "shared",
+        }
+    }
+}
+
+// Titles of the notes `content` links to, lower-cased and without repeats
+fn link_targets(content: &str) -> Vec<String> {
+    let mut targets: Vec<String> = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("[[") {
+        rest = &rest[start + 2..];
+        let Some(end) = rest.find("]]") else {
+            break;
+        };
+        let inner = &rest[..end];
+        rest = &rest[end + 2..];
+        let target = inner.split('|').next().unwrap_or_default().trim().to_lowercase();
+        if !target.is_empty() && target.chars().count() <= MAX_TITLE_CHARS && !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    targets
+}
+
+// Trimmed, lower-cased and without repeats
+fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || normalized.contains(&tag) {
+            continue;
+        }
+        if tag.chars().count() > MAX_TAG_CHARS {
+            return Err(format!("Tags can be at most {} characters", MAX_TAG_CHARS));
+        }
+        normalized.push(tag);
+    }
+    if normalized.len() > MAX_TAGS {
+        return Err(format!("A note can have at most {} tags", MAX_TAGS));
+    }
+    Ok(normalized)
+}
+
+fn check_title(title: &str) -> Result<(), String> {
+    let length = title.trim().chars().count();
+    if length == 0 || length > MAX_TITLE_CHARS {
+        return Err(format!("Title must be 1 to {} characters", MAX_TITLE_CHARS));
+    }
+    if title.contains("[[") || title.contains("]]") || title.contains('|') {
+        return Err("Title can't contain [[, ]] or |".to_string());
+    }
+    Ok(())
+}
+
+fn check_content(content: &str) -> Result<(), String> {
+    if content.chars().count() > MAX_CONTENT_CHARS {
+        return Err(format!("Content can be at most {} characters", MAX_CONTENT_CHARS));
+    }
+    Ok(())
+}
+
+async fn store_links(conn: &mut PgConnection, note_id: Uuid, content: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM campaign_note_links WHERE note_id = $1").bind(note_id).execute(&mut *conn).await?;
+    sqlx::query("INSERT INTO campaign_note_links (note_id, target_title) SELECT $1, UNNEST($2::TEXT[])")
+        .bind(note_id)
+        .bind(link_targets(content))
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct NoteSummary {
+    pub id: Uuid,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub visibility: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteResponse {
+    #[serde(flatten)]
+    pub note: CampaignNote,
+    // Notes this one links to that exist and the user can see
+    pub links: Vec<NoteSummary>,
+    // Notes linking here that the user can see
+    pub backlinks: Vec<NoteSummary>,
+}
+
+#[derive(Deserialize)]
+pub struct NoteQuery {
+    pub tag: Option<String>,
+}
+
+// Sorted by title; players only get shared notes
+pub async fn list_notes(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Query(params): Query<NoteQuery>,
+) -> impl IntoResponse {
+    let scope = match authorize(&pool, user.0, Resource::Campaign(campaign_id), Access::Member, "Access denied to this campaign").await {
+        Ok(scope) => scope,
+        Err(response) => return response,
+    };
+    let notes = sqlx::query_as::<_, NoteSummary>(
+        "SELECT id, title, tags, visibility, updated_at FROM campaign_notes
+         WHERE campaign_id = $1 AND ($2 OR visibility = 'shared') AND ($3::TEXT IS NULL OR $3 = ANY(tags))
+         ORDER BY LOWER(title)"
+    )
+    .bind(campaign_id)
+    .bind(scope.is_dm())
+    .bind(params.tag.as_ref().map(|tag| tag.trim().to_lowercase()))
+    .fetch_all(&pool)
+    .await;
+    match notes {
+        Ok(notes) => Json(notes).into_response(),
+        Err(e) => db_error(e, "Failed to fetch notes"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateNoteRequest {
+    pub title: String,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // DM-only unless shared
+    pub visibility: Option<Visibility>,
+}
+
+pub async fn create_note(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Json(payload): Json<CreateNoteRequest>,
+) -> impl IntoResponse {
+    let tags = match check_title(&payload.title).and_then(|_| check_content(&payload.content)).and_then(|_| normalize_tags(payload.tags)) {
+        Ok(tags) => tags,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    let created = async {
+        let mut tx = pool.begin().await?;
+        let note = sqlx::query_as::<_, CampaignNote>(
+            "INSERT INTO campaign_notes (campaign_id, title, content, tags, visibility, created_by) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *"
+        )
+        .bind(campaign_id)
+        .bind(payload.title.trim())
+        .bind(&payload.content)
+        .bind(&tags)
+        .bind(payload.visibility.unwrap_or(Visibility::Dm).as_str())
+        .bind(user.0)
+        .fetch_one(&mut *tx)
+        .await?;
+        store_links(&mut tx, note.id, &note.content).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(note)
+    }
+    .await;
+    match created {
+        Ok(note) => (StatusCode::CREATED, Json(note)).into_response(),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            (StatusCode::CONFLICT, "A note with this title already exists").into_response()
+        }
+        Err(e) => db_error(e, "Failed to create note"),
+    }
+}
+
+// The note, or a refusal when the user can't see it. DM-only notes look missing to players.
+async fn visible_note(pool: &PgPool, user_id: Uuid, note_id: Uuid, access: Access) -> Result<(CampaignScope, CampaignNote), axum::response::Response> {
+    let scope = authorize(pool, user_id, Resource::Note(note_id), access, "Only the DM can change notes").await?;
+    let note = sqlx::query_as::<_, CampaignNote>("SELECT * FROM campaign_notes WHERE id = $1")
+        .bind(note_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| db_error(e, "Failed to fetch note"))?;
+    match note {
+        Some(note) if scope.is_dm() || note.visibility == Visibility::Shared.as_str() => Ok((scope, note)),
+        _ => Err((StatusCode::NOT_FOUND, "Note not found").into_response()),
+    }
+}
+
+pub async fn get_note(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(note_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let (scope, note) = match visible_note(&pool, user.0, note_id, Access::Member).await {
+        Ok(found) => found,
+        Err(response) => return response,
+    };
+    let links = sqlx::query_as::<_, NoteSummary>(
+        "SELECT n.id, n.title, n.tags, n.visibility, n.updated_at FROM campaign_note_links l
+         INNER JOIN campaign_notes n ON n.campaign_id = $2 AND LOWER(n.title) = l.target_title
+         WHERE l.note_id = $1 AND ($3 OR n.visibility = 'shared')
+         ORDER BY LOWER(n.title)"
+    )
+    .bind(note.id)
+    .bind(note.campaign_id)
+    .bind(scope.is_dm())
+    .fetch_all(&pool)
+    .await;
+    let backlinks = sqlx::query_as::<_, NoteSummary>(
+        "SELECT n.id, n.title, n.tags, n.visibility, n.updated_at FROM campaign_note_links l
+         INNER JOIN campaign_notes n ON n.id = l.note_id
+         WHERE n.campaign_id = $1 AND l.target_title = LOWER($2) AND n.id <> $3 AND ($4 OR n.visibility = 'shared')
+         ORDER BY LOWER(n.title)"
+    )
+    .bind(note.campaign_id)
+    .bind(&note.title)
+    .bind(note.id)
+    .bind(scope.is_dm())
+    .fetch_all(&pool)
+    .await;
+    match (links, backlinks) {
+        (Ok(links), Ok(backlinks)) => Json(NoteResponse { note, links, backlinks }).into_response(),
+        (Err(e), _) | (_, Err(e)) => db_error(e, "Failed to fetch note links"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateNoteRequest {
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub visibility: Option<Visibility>,
+}
+
+// Fields left out are unchanged. Renaming doesn't rewrite links in other notes, which
+// then point at the old title.
+pub async fn update_note(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(note_id): Path<Uuid>,
+    Json(payload): Json<UpdateNoteRequest>,
+) -> impl IntoResponse {
+    if let Err(response) = visible_note(&pool, user.0, note_id, Access::Dm).await {
+        return response;
+    }
+    let checked = payload.title.as_deref().map_or(Ok(()), check_title)
+        .and_then(|_| payload.content.as_deref().map_or(Ok(()), check_content))
+        .and_then(|_| payload.tags.map(normalize_tags).transpose());
+    let tags = match checked {
+        Ok(tags) => tags,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+
+    let updated = async {
+        let mut tx = pool.begin().await?;
+        let note = sqlx::query_as::<_, CampaignNote>(
+            "UPDATE campaign_notes SET title = COALESCE($2, title), content = COALESCE($3, content), tags = COALESCE($4, tags),
+                 visibility = COALESCE($5, visibility), updated_at = NOW()
+             WHERE id = $1 RETURNING *"
+        )
+        .bind(note_id)
+        .bind(payload.title.as_deref().map(str::trim))
+        .bind(&payload.content)
+        .bind(&tags)
+        .bind(payload.visibility.map(Visibility::as_str))
+        .fetch_one(&mut *tx)
+        .await?;
+        if payload.content.is_some() {
+            store_links(&mut tx, note.id, &note.content).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(note)
+    }
+    .await;
+    match updated {
+        Ok(note) => Json(note).into_response(),
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            (StatusCode::CONFLICT, "A note with this title already exists").into_response()
+        }
+        Err(e) => db_error(e, "Failed to update note"),
+    }
+}
+
+pub async fn delete_note(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(note_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(response) = visible_note(&pool, user.0, note_id, Access::Dm).await {
+        return response;
+    }
+    match sqlx::query("DELETE FROM campaign_notes WHERE id = $1").bind(note_id).execute(&pool).await {
+        Ok(_) => (StatusCode::OK, "Note deleted").into_response(),
+        Err(e) => db_error(e, "Failed to delete note"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_notes_link_by_title_and_hide_dm_notes_from_players() {
+        assert_eq!(link_targets("See [[The Rusty Tankard]] and [[rusty tankard|the inn]], [[ Marta ]] [[]] [[unclosed"), vec!["the rusty tankard", "rusty tankard", "marta"]);
+        assert_eq!(normalize_tags(vec![" Lore ".to_string(), "lore".to_string(), "NPC".to_string()]), Ok(vec!["lore".to_string(), "npc".to_string()]));
+
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let create = |user_id: Uuid, title: &str, content: &str, visibility: Visibility| {
+            let payload = CreateNoteRequest { title: title.to_string(), content: content.to_string(), tags: vec!["Prep".to_string()], visibility: Some(visibility) };
+            create_note(Extension(pool.clone()), Extension(AuthUser(user_id)), Path(f.campaign_id), Json(payload))
+        };
+        let read = |response: axum::response::Response| async {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let refused = crate::test_support::call_as(&pool, f.player_id, axum::http::Method::POST, &format!("/campaigns/{}/notes", f.campaign_id), Some(serde_json::json!({"title": "Mine", "content": "", "visibility": Visibility::Shared}))).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        let town = read(create(f.dm_id, "Phandalin", "Home of [[The Rusty Tankard]] and [[Secret Villain]].", Visibility::Shared).await.into_response()).await;
+        let tavern = read(create(f.dm_id, "The Rusty Tankard", "Run by Marta.", Visibility::Shared).await.into_response()).await;
+        create(f.dm_id, "Secret Villain", "It was the mayor. See [[Phandalin]].", Visibility::Dm).await;
+        assert_eq!(create(f.dm_id, "phandalin", "", Visibility::Dm).await.into_response().status(), StatusCode::CONFLICT);
+
+        let town_id: Uuid = town["id"].as_str().unwrap().parse().unwrap();
+        let get = |user_id: Uuid, note_id: Uuid| get_note(Extension(pool.clone()), Extension(AuthUser(user_id)), Path(note_id));
+        let as_dm = read(get(f.dm_id, town_id).await.into_response()).await;
+        assert_eq!(as_dm["links"].as_array().unwrap().len(), 2);
+        assert_eq!(as_dm["backlinks"][0]["title"], "Secret Villain");
+        let as_player = read(get(f.player_id, town_id).await.into_response()).await;
+        assert_eq!(as_player["links"].as_array().unwrap().len(), 1);
+        assert_eq!(as_player["links"][0]["id"], tavern["id"]);
+        assert!(as_player["backlinks"].as_array().unwrap().is_empty());
+
+        let listed = list_notes(Extension(pool.clone()), Extension(AuthUser(f.player_id)), Path(f.campaign_id), Query(NoteQuery { tag: Some("prep".to_string()) }));
+        assert_eq!(read(listed.await.into_response()).await.as_array().unwrap().len(), 2);
+        let villain_id = sqlx::query_scalar::<_, Uuid>("SELECT id FROM campaign_notes WHERE campaign_id = $1 AND title = 'Secret Villain'")
+            .bind(f.campaign_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(get(f.player_id, villain_id).await.into_response().status(), StatusCode::NOT_FOUND);
+        assert_eq!(get(f.outsider_id, town_id).await.into_response().status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,66 @@
+use axum::{extract::{Path, Query}, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::middleware::AuthUser;
+use crate::models::Notification;
+use crate::errors::db_error;
+use crate::outbox::{enqueue, OutboxMessage};
+
+// In-app notifications. Producers call `notify` (inside their own transaction when they
+// have one), which queues the notification in the outbox; users read them through the
+// endpoints below once the outbox runner has delivered them.
+
+pub async fn notify<'e, E>(executor: E, user_id: Uuid, kind: &str, data: serde_json::Value) -> Result<(), sqlx::Error>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    enqueue(executor, &OutboxMessage::Notification { user_id, kind: kind.to_string(), data }).await
+}
+
+#[derive(Deserialize)]
+pub struct NotificationQuery {
+    pub unread: Option<bool>,
+}
+
+pub async fn list_notifications(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(params): Query<NotificationQuery>,
+) -> impl IntoResponse {
+    let notifications = sqlx::query_as::<_, Notification>(
+        "SELECT * FROM notifications WHERE user_id = $1 AND ($2 = FALSE OR read_at IS NULL)
+         ORDER BY created_at DESC LIMIT 100"
+    )
+    .bind(user.0)
+    .bind(params.unread.unwrap_or(false))
+    .fetch_all(&pool)
+    .await;
+
+    match notifications {
+        Ok(notifications) => Json(notifications).into_response(),
+        Err(e) => db_error(e, "Failed to fetch notifications"),
+    }
+}
+
+pub async fn mark_notification_read(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(notification_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let res = sqlx::query_as::<_, Notification>(
+        "UPDATE notifications SET read_at = COALESCE(read_at, $1) WHERE id = $2 AND user_id = $3 RETURNING *"
+    )
+    .bind(Utc::now())
+    .bind(notification_id)
+    .bind(user.0)
+    .fetch_optional(&pool)
+    .await;
+
+    match res {
+        Ok(Some(notification)) => Json(notification).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Notification not found").into_response(),
+        Err(e) => db_error(e, "Failed to update notification"),
+    }
+}
@@ -0,0 +1,415 @@
+use axum::{
+    extract::{Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect},
+    Extension,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::env;
+use std::time::Duration;
+use uuid::Uuid;
+use crate::audit::{self, AuthEvent, ClientInfo};
+use crate::errors::db_error;
+use crate::middleware::jwt_secret;
+use crate::verification::app_url;
+
+// Sign in with Discord or Google. GET /auth/oauth/:provider sends the browser to the
+// provider; its callback exchanges the code, finds or creates the user and hands the same
+// tokens as password login to the frontend, at APP_URL/oauth/callback in the URL fragment.
+// A provider is available once DISCORD_CLIENT_ID and DISCORD_CLIENT_SECRET (or the GOOGLE_
+// pair) are set; OAUTH_REDIRECT_BASE is this server's public URL, which the callback is
+// registered under (default http://localhost:3000).
+//
+// The `state` parameter carries a nonce, its expiry and an HMAC of both; the nonce is also
+// set in a cookie, so a callback only completes in the browser that started it.
+
+const STATE_COOKIE: &str = "yoda_oauth_state";
+
+// How long the user has to get through the provider's consent screen
+const STATE_TTL_SECS: i64 = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Provider {
+    Discord,
+    Google,
+}
+
+impl Provider {
+    fn parse(name: &str) -> Option<Provider> {
+        match name {
+            "discord" => Some(Provider::Discord),
+            "google" => Some(Provider::Google),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Provider::Discord => "discord",
+            Provider::Google => "google",
+        }
+    }
+
+    fn authorize_url(self) -> &'static str {
+        match self {
+            Provider::Discord => "https://discord.com/oauth2/authorize",
+            Provider::Google => "https://accounts.google.com/o/oauth2/v2/auth",
+        }
+    }
+
+    fn token_url(self) -> &'static str {
+        match self {
+            Provider::Discord => "https://discord.com/api/oauth2/token",
+            Provider::Google => "https://oauth2.googleapis.com/token",
+        }
+    }
+
+    fn profile_url(self) -> &'static str {
+        match self {
+            Provider::Discord => "https://discord.com/api/users/@me",
+            Provider::Google => "https://openidconnect.googleapis.com/v1/userinfo",
+        }
+    }
+
+    fn scope(self) -> &'static str {
+        match self {
+            Provider::Discord => "identify email",
+            Provider::Google => "openid email profile",
+        }
+    }
+
+    // Client id and secret, if the provider is configured
+    fn credentials(self) -> Option<(String, String)> {
+        let prefix = self.name().to_uppercase();
+        let id = env::var(format!("{}_CLIENT_ID", prefix)).ok().filter(|id| !id.is_empty())?;
+        let secret = env::var(format!("{}_CLIENT_SECRET", prefix)).ok().filter(|secret| !secret.is_empty())?;
+        Some((id, secret))
+    }
+
+    fn redirect_uri(self) -> String {
+        let base = env::var("OAUTH_REDIRECT_BASE").unwrap_or_else(|_| "http://localhost:3000".to_string());
+        format!("{}/auth/oauth/{}/callback", base.trim_end_matches('/'), self.name())
+    }
+}
+
+fn state_mac(nonce: &str, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(jwt_secret().as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("oauth-state\n{}\n{}", nonce, expires).as_bytes());
+    mac
+}
+
+fn sign_state(nonce: &str, expires: i64) -> String {
+    format!("{}.{}.{}", nonce, expires, hex::encode(state_mac(nonce, expires).finalize().into_bytes()))
+}
+
+// The state's nonce, if it's genuine, unexpired and matches the browser's cookie
+fn verify_state(state: &str, cookie_nonce: Option<&str>) -> bool {
+    let mut parts = state.splitn(3, '.');
+    let (Some(nonce), Some(expires), Some(signature)) = (parts.next(), parts.next(), parts.next()) else {
+        return false;
+    };
+    let Ok(expires) = expires.parse::<i64>() else { return false };
+    if expires < Utc::now().timestamp() || cookie_nonce != Some(nonce) {
+        return false;
+    }
+    match hex::decode(signature) {
+        Ok(signature) => state_mac(nonce, expires).verify_slice(&signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| pair.trim().strip_prefix(name)?.strip_prefix('='))
+}
+
+pub async fn oauth_start(Path(provider): Path<String>) -> impl IntoResponse {
+    let Some(provider) = Provider::parse(&provider) else {
+        return (StatusCode::NOT_FOUND, "Unknown OAuth provider").into_response();
+    };
+    let Some((client_id, _)) = provider.credentials() else {
+        return (StatusCode::NOT_FOUND, "OAuth provider is not configured").into_response();
+    };
+
+    let mut nonce = [0u8; 16];
+    OsRng.fill_bytes(&mut nonce);
+    let nonce = hex::encode(nonce);
+    let state = sign_state(&nonce, Utc::now().timestamp() + STATE_TTL_SECS);
+    let url = reqwest::Url::parse_with_params(
+        provider.authorize_url(),
+        &[
+            ("response_type", "code"),
+            ("client_id", client_id.as_str()),
+            ("redirect_uri", provider.redirect_uri().as_str()),
+            ("scope", provider.scope()),
+            ("state", state.as_str()),
+            ("prompt", "consent"),
+        ],
+    )
+    .expect("provider URLs are valid");
+    let cookie = format!(
+        "{}={}; Path=/auth/oauth; Max-Age={}; HttpOnly; SameSite=Lax",
+        STATE_COOKIE, nonce, STATE_TTL_SECS
+    );
+    ([(header::SET_COOKIE, cookie)], Redirect::to(url.as_str())).into_response()
+}
+
+// Who the provider says signed in
+#[derive(Debug, Clone)]
+pub struct OAuthProfile {
+    pub provider_user_id: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub username: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Deserialize)]
+struct DiscordUser {
+    id: String,
+    username: String,
+    email: Option<String>,
+    verified: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct GoogleUser {
+    sub: String,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    name: Option<String>,
+}
+
+async fn fetch_profile(provider: Provider, code: &str) -> Result<OAuthProfile, String> {
+    let (client_id, client_secret) = provider.credentials().ok_or_else(|| "OAuth provider is not configured".to_string())?;
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+
+    let res = client
+        .post(provider.token_url())
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", provider.redirect_uri().as_str()),
+            ("client_id", client_id.as_str()),
+            ("client_secret", client_secret.as_str()),
+        ])
+        .send()
+        .await
+        .map_err(|e| format!("Token request failed: {}", e))?;
+    if !res.status().is_success() {
+        return Err(format!("Token endpoint returned {}", res.status()));
+    }
+    let token: TokenResponse = res.json().await.map_err(|e| format!("Invalid token response: {}", e))?;
+
+    let res = client
+        .get(provider.profile_url())
+        .bearer_auth(&token.access_token)
+        .send()
+        .await
+        .map_err(|e| format!("Profile request failed: {}", e))?;
+    if !res.status().is_success() {
+        return Err(format!("Profile endpoint returned {}", res.status()));
+    }
+    match provider {
+        Provider::Discord => {
+            let user: DiscordUser = res.json().await.map_err(|e| format!("Invalid Discord profile: {}", e))?;
+            Ok(OAuthProfile { provider_user_id: user.id, email: user.email, email_verified: user.verified.unwrap_or(false), username: user.username })
+        }
+        Provider::Google => {
+            let user: GoogleUser = res.json().await.map_err(|e| format!("Invalid Google profile: {}", e))?;
+            let username = user.name.clone().or_else(|| user.email.as_deref().and_then(|email| email.split('@').next()).map(str::to_string)).unwrap_or_else(|| "adventurer".to_string());
+            Ok(OAuthProfile { provider_user_id: user.sub, email: user.email, email_verified: user.email_verified.unwrap_or(false), username })
+        }
+    }
+}
+
+pub enum SignIn {
+    User(Uuid),
+    // The email belongs to an account, but the provider hasn't verified it, so linking could
+    // hand the account to whoever typed the address in
+    UnverifiedEmailTaken,
+}
+
+// The user linked to the identity; otherwise links the account with the same (verified)
+// email, or creates one
+pub async fn sign_in(pool: &PgPool, provider: Provider, profile: &OAuthProfile) -> Result<SignIn, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let linked = sqlx::query_scalar::<_, Uuid>("SELECT user_id FROM oauth_identities WHERE provider = $1 AND provider_user_id = $2")
+        .bind(provider.name())
+        .bind(&profile.provider_user_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if let Some(user_id) = linked {
+        return Ok(SignIn::User(user_id));
+    }
+
+    let existing = match &profile.email {
+        Some(email) => sqlx::query_scalar::<_, Uuid>("SELECT id FROM users WHERE email = $1").bind(email).fetch_optional(&mut *tx).await?,
+        None => None,
+    };
+    let user_id = match existing {
+        Some(_) if !profile.email_verified => return Ok(SignIn::UnverifiedEmailTaken),
+        Some(user_id) => {
+            // The provider vouches for the address, which is as good as our own link
+            sqlx::query("UPDATE users SET email_verified = TRUE WHERE id = $1").bind(user_id).execute(&mut *tx).await?;
+            user_id
+        }
+        None => {
+            let user_id = Uuid::new_v4();
+            // Accounts without an email from the provider get a placeholder nobody can receive mail at
+            let email = profile.email.clone().unwrap_or_else(|| format!("{}-{}@oauth.invalid", provider.name(), profile.provider_user_id));
+            let base: String = profile.username.chars().filter(|c| c.is_alphanumeric() || *c == '_').take(40).collect();
+            let base = if base.is_empty() { "adventurer".to_string() } else { base };
+            let taken = sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM users WHERE username = $1)")
+                .bind(&base)
+                .fetch_one(&mut *tx)
+                .await?;
+            let username = if taken { format!("{}_{}", base, &user_id.simple().to_string()[..6]) } else { base };
+            // No usable password; this account signs in through the provider
+            sqlx::query(
+                "INSERT INTO users (id, email, username, password_hash, email_verified, created_at, updated_at) VALUES ($1, $2, $3, '!', $4, NOW(), NOW())"
+            )
+            .bind(user_id)
+            .bind(&email)
+            .bind(&username)
+            .bind(profile.email_verified)
+            .execute(&mut *tx)
+            .await?;
+            user_id
+        }
+    };
+    sqlx::query("INSERT INTO oauth_identities (provider, provider_user_id, user_id) VALUES ($1, $2, $3)")
+        .bind(provider.name())
+        .bind(&profile.provider_user_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(SignIn::User(user_id))
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    // Set when the user declined or the provider failed
+    pub error: Option<String>,
+}
+
+pub async fn oauth_callback(
+    Extension(pool): Extension<PgPool>,
+    Path(provider): Path<String>,
+    Query(params): Query<CallbackQuery>,
+    headers: HeaderMap,
+    client: ClientInfo,
+) -> impl IntoResponse {
+    let Some(provider) = Provider::parse(&provider) else {
+        return (StatusCode::NOT_FOUND, "Unknown OAuth provider").into_response();
+    };
+    if let Some(error) = params.error {
+        return (StatusCode::UNAUTHORIZED, format!("Sign-in was not completed: {}", error)).into_response();
+    }
+    let (Some(code), Some(state)) = (params.code, params.state) else {
+        return (StatusCode::BAD_REQUEST, "Missing code or state").into_response();
+    };
+    if !verify_state(&state, cookie(&headers, STATE_COOKIE)) {
+        return (StatusCode::BAD_REQUEST, "Invalid or expired sign-in attempt; start again").into_response();
+    }
+
+    let profile = match fetch_profile(provider, &code).await {
+        Ok(profile) => profile,
+        Err(e) => {
+            tracing::warn!(error = %e, provider = provider.name(), "OAuth sign-in failed");
+            return (StatusCode::BAD_GATEWAY, "Could not complete sign-in with the provider").into_response();
+        }
+    };
+    let user_id = match sign_in(&pool, provider, &profile).await {
+        Ok(SignIn::User(user_id)) => user_id,
+        Ok(SignIn::UnverifiedEmailTaken) => {
+            return (StatusCode::CONFLICT, "An account already uses this email; log in with its password").into_response();
+        }
+        Err(e) => return db_error(e, "Failed to sign in"),
+    };
+    match sqlx::query_scalar::<_, bool>("SELECT disabled_at IS NOT NULL FROM users WHERE id = $1").bind(user_id).fetch_one(&pool).await {
+        Ok(true) => return (StatusCode::FORBIDDEN, "Account disabled").into_response(),
+        Ok(false) => {}
+        Err(e) => return db_error(e, "Failed to sign in"),
+    }
+
+    // In the fragment, which browsers don't send to servers or put in Referer headers
+    let tokens = match crate::refresh::issue_tokens(&pool, user_id).await {
+        Ok(tokens) => tokens,
+        Err(_) => return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate token").into_response(),
+    };
+    audit::record(&pool, Some(user_id), AuthEvent::Login, &client, serde_json::json!({ "provider": provider.name() })).await;
+    let fragment = format!("token={}&refresh_token={}&expires_in={}", tokens.token, tokens.refresh_token, tokens.expires_in);
+    let expired_cookie = format!("{}=; Path=/auth/oauth; Max-Age=0; HttpOnly; SameSite=Lax", STATE_COOKIE);
+    (
+        [(header::SET_COOKIE, expired_cookie)],
+        Redirect::to(&format!("{}/oauth/callback#{}", app_url(), fragment)),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_is_bound_to_the_browser_and_expires() {
+        let expires = Utc::now().timestamp() + 60;
+        let state = sign_state("abc123", expires);
+        assert!(verify_state(&state, Some("abc123")));
+        assert!(!verify_state(&state, Some("other")));
+        assert!(!verify_state(&state, None));
+        assert!(!verify_state(&state.replace("abc123", "abc124"), Some("abc124")));
+        assert!(!verify_state(&sign_state("abc123", Utc::now().timestamp() - 1), Some("abc123")));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, format!("theme=dark; {}=abc123", STATE_COOKIE).parse().unwrap());
+        assert_eq!(cookie(&headers, STATE_COOKIE), Some("abc123"));
+    }
+
+    #[tokio::test]
+    async fn test_sign_in_links_verified_emails_only() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let email = sqlx::query_scalar::<_, String>("SELECT email FROM users WHERE id = $1").bind(f.player_id).fetch_one(&pool).await.unwrap();
+
+        let profile = |id: &str, email: Option<String>, email_verified: bool| OAuthProfile {
+            provider_user_id: id.to_string(),
+            email,
+            email_verified,
+            username: "Wren".to_string(),
+        };
+        let unverified = profile(&Uuid::new_v4().to_string(), Some(email.clone()), false);
+        assert!(matches!(sign_in(&pool, Provider::Discord, &unverified).await.unwrap(), SignIn::UnverifiedEmailTaken));
+
+        let verified = profile(&Uuid::new_v4().to_string(), Some(email), true);
+        assert!(matches!(sign_in(&pool, Provider::Discord, &verified).await.unwrap(), SignIn::User(id) if id == f.player_id));
+        // Signing in again finds the identity, even once the email has changed
+        let again = OAuthProfile { email: None, ..verified };
+        assert!(matches!(sign_in(&pool, Provider::Discord, &again).await.unwrap(), SignIn::User(id) if id == f.player_id));
+
+        let newcomer = profile(&Uuid::new_v4().to_string(), None, false);
+        let SignIn::User(new_id) = sign_in(&pool, Provider::Google, &newcomer).await.unwrap() else { panic!("expected a new user") };
+        assert_ne!(new_id, f.player_id);
+    }
+}
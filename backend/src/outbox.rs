@@ -0,0 +1,307 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{Acquire, PgConnection, PgPool};
+use std::env;
+use std::time::Duration;
+use uuid::Uuid;
+use crate::models::Campaign;
+
+// Transactional outbox. Producers enqueue side effects with the executor of the transaction
+// making the change, so they are saved if and only if the change commits; the runner
+// delivers them afterwards and retries with backoff until they go through.
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub enum OutboxMessage {
+    // In-app notification, read through GET /notifications
+    Notification { user_id: Uuid, kind: String, data: serde_json::Value },
+    // POSTed to a campaign's `webhook_url`
+    Webhook { url: String, campaign_id: Uuid, event: String, data: serde_json::Value },
+    // Plain text email, sent through the provider configured by EMAIL_API_URL
+    Email { to: String, subject: String, body: String },
+}
+
+impl OutboxMessage {
+    fn channel(&self) -> &'static str {
+        match self {
+            OutboxMessage::Notification { .. } => "notification",
+            OutboxMessage::Webhook { .. } => "webhook",
+            OutboxMessage::Email { .. } => "email",
+        }
+    }
+}
+
+pub async fn enqueue<'e, E>(executor: E, message: &OutboxMessage) -> Result<(), sqlx::Error>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let json = serde_json::to_value(message).map_err(|e| sqlx::Error::Protocol(format!("Failed to encode outbox message: {}", e)))?;
+    sqlx::query("INSERT INTO outbox (channel, message) VALUES ($1, $2)")
+        .bind(message.channel())
+        .bind(json)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+// Enqueues `event` for the campaign's webhook, if its settings have an http(s) `webhook_url`
+pub async fn enqueue_campaign_webhook<'e, E>(executor: E, campaign: &Campaign, event: &str, data: serde_json::Value) -> Result<(), sqlx::Error>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let url = campaign.settings.get("webhook_url").and_then(|url| url.as_str());
+    match url {
+        Some(url) if url.starts_with("https://") || url.starts_with("http://") => {
+            let message = OutboxMessage::Webhook { url: url.to_string(), campaign_id: campaign.id, event: event.to_string(), data };
+            enqueue(executor, &message).await
+        }
+        _ => Ok(()),
+    }
+}
+
+// Messages claimed per pass
+const BATCH_SIZE: i64 = 50;
+
+// Attempts before a message is marked failed
+const MAX_ATTEMPTS: i32 = 10;
+
+// Wait before the next attempt: 5s, 10s, 20s, ... capped at an hour
+fn backoff(attempts: i32) -> Duration {
+    let exponent = attempts.clamp(1, 20) as u32 - 1;
+    Duration::from_secs(5u64.saturating_mul(1u64 << exponent).min(3600))
+}
+
+pub fn spawn_outbox_runner(pool: PgPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(2));
+        loop {
+            interval.tick().await;
+            // Keep going while full batches come back
+            loop {
+                match deliver_pending(&pool).await {
+                    Ok(delivered) if delivered as i64 == BATCH_SIZE => continue,
+                    Ok(_) => break,
+                    Err(e) => {
+                        tracing::error!(error = %e, "Outbox delivery pass failed");
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+// Delivers due messages and returns how many were attempted. Rows are locked while being
+// delivered, so several backend instances can run this side by side.
+pub async fn deliver_pending(pool: &PgPool) -> Result<usize, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let due = sqlx::query_as::<_, (Uuid, serde_json::Value, i32, DateTime<Utc>)>(
+        "SELECT id, message, attempts, created_at FROM outbox
+         WHERE delivered_at IS NULL AND failed_at IS NULL AND next_attempt_at <= NOW()
+         ORDER BY created_at LIMIT $1
+         FOR UPDATE SKIP LOCKED"
+    )
+    .bind(BATCH_SIZE)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for (id, message, attempts, created_at) in &due {
+        let result = match serde_json::from_value::<OutboxMessage>(message.clone()) {
+            Ok(message) => deliver(&mut tx, *id, &message, *created_at).await,
+            Err(e) => Err(format!("Unreadable outbox message: {}", e)),
+        };
+
+        match result {
+            Ok(()) => {
+                sqlx::query("UPDATE outbox SET delivered_at = NOW(), attempts = attempts + 1, last_error = NULL WHERE id = $1")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            Err(e) => {
+                let attempts = attempts + 1;
+                tracing::warn!(error = %e, %id, attempts, "Outbox message failed");
+                let next_attempt_at = Utc::now() + chrono::Duration::from_std(backoff(attempts)).unwrap_or_default();
+                sqlx::query(
+                    "UPDATE outbox SET attempts = $1, last_error = $2, next_attempt_at = $3,
+                     failed_at = CASE WHEN $1 >= $4 THEN NOW() END
+                     WHERE id = $5"
+                )
+                .bind(attempts)
+                .bind(e)
+                .bind(next_attempt_at)
+                .bind(MAX_ATTEMPTS)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+    }
+    tx.commit().await?;
+    Ok(due.len())
+}
+
+async fn deliver(conn: &mut PgConnection, id: Uuid, message: &OutboxMessage, created_at: DateTime<Utc>) -> Result<(), String> {
+    match message {
+        OutboxMessage::Notification { user_id, kind, data } => {
+            // In a savepoint so a failure doesn't abort the rest of the batch. The outbox id
+            // doubles as the notification id, so a redelivery can't duplicate it.
+            let mut savepoint = conn.begin().await.map_err(|e| e.to_string())?;
+            sqlx::query(
+                "INSERT INTO notifications (id, user_id, kind, data, created_at) VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (id) DO NOTHING"
+            )
+            .bind(id)
+            .bind(user_id)
+            .bind(kind)
+            .bind(data)
+            .bind(created_at)
+            .execute(&mut *savepoint)
+            .await
+            .map_err(|e| e.to_string())?;
+            savepoint.commit().await.map_err(|e| e.to_string())
+        }
+        OutboxMessage::Webhook { url, campaign_id, event, data } => {
+            let body = serde_json::json!({
+                "id": id,
+                "event": event,
+                "campaign_id": campaign_id,
+                "data": data,
+                "created_at": created_at,
+            })
+            .to_string();
+            send_webhook(url, id, event, body).await
+        }
+        OutboxMessage::Email { to, subject, body } => send_email(id, to, subject, body).await,
+    }
+}
+
+// With WEBHOOK_SECRET set, the body is signed in an X-YoDA-Signature header
+fn webhook_signature(body: &str) -> Option<String> {
+    let secret = env::var("WEBHOOK_SECRET").ok().filter(|secret| !secret.is_empty())?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body.as_bytes());
+    Some(format!("sha256={}", hex::encode(mac.finalize().into_bytes())))
+}
+
+async fn send_webhook(url: &str, id: Uuid, event: &str, body: String) -> Result<(), String> {
+    let mut request = reqwest::Client::new()
+        .post(url)
+        .timeout(Duration::from_secs(10))
+        .header("content-type", "application/json")
+        .header("x-yoda-event", event)
+        // Receivers can drop redeliveries by this id
+        .header("x-yoda-delivery", id.to_string());
+    if let Some(signature) = webhook_signature(&body) {
+        request = request.header("x-yoda-signature", signature);
+    }
+
+    let res = request.body(body).send().await.map_err(|e| format!("Webhook request failed: {}", e))?;
+    if !res.status().is_success() {
+        return Err(format!("Webhook returned {}", res.status()));
+    }
+    Ok(())
+}
+
+// Without an email provider, EMAIL_DEV_LOG=true lets emails through for local development:
+// only the recipient and subject are logged, since bodies carry sign-in and verification
+// tokens. The full message stays readable in the outbox table.
+fn email_dev_log() -> bool {
+    env::var("EMAIL_DEV_LOG").map(|v| v == "true" || v == "1").unwrap_or(false)
+}
+
+// POSTs `{from, to, subject, text}` to EMAIL_API_URL, with EMAIL_API_KEY as a bearer token
+// and EMAIL_FROM as the sender. Without EMAIL_API_URL the email fails and is retried like any
+// other, unless EMAIL_DEV_LOG is set.
+async fn send_email(id: Uuid, to: &str, subject: &str, body: &str) -> Result<(), String> {
+    let Some(url) = env::var("EMAIL_API_URL").ok().filter(|url| !url.is_empty()) else {
+        if email_dev_log() {
+            tracing::info!(%id, %to, %subject, "Email not sent, no EMAIL_API_URL set (EMAIL_DEV_LOG)");
+            return Ok(());
+        }
+        return Err("EMAIL_API_URL is not set".to_string());
+    };
+    let from = env::var("EMAIL_FROM").unwrap_or_else(|_| "YoDA <no-reply@localhost>".to_string());
+    let mut request = reqwest::Client::new()
+        .post(url)
+        .timeout(Duration::from_secs(10))
+        // Providers that support it drop redeliveries by this key
+        .header("idempotency-key", id.to_string())
+        .json(&serde_json::json!({ "from": from, "to": to, "subject": subject, "text": body }));
+    if let Ok(key) = env::var("EMAIL_API_KEY") {
+        request = request.bearer_auth(key);
+    }
+
+    let res = request.send().await.map_err(|e| format!("Email request failed: {}", e))?;
+    if !res.status().is_success() {
+        return Err(format!("Email provider returned {}", res.status()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_and_caps() {
+        assert_eq!(backoff(1), Duration::from_secs(5));
+        assert_eq!(backoff(2), Duration::from_secs(10));
+        assert_eq!(backoff(4), Duration::from_secs(40));
+        assert_eq!(backoff(MAX_ATTEMPTS * 2), Duration::from_secs(3600));
+    }
+
+    #[tokio::test]
+    async fn test_email_without_provider_fails() {
+        // Tests run without EMAIL_API_URL or EMAIL_DEV_LOG
+        let sent = send_email(Uuid::new_v4(), "ada@example.com", "Verify your email", "token=secret").await;
+        assert_eq!(sent.unwrap_err(), "EMAIL_API_URL is not set");
+    }
+
+    #[tokio::test]
+    async fn test_outbox_delivers_only_committed_messages() {
+        let pool = crate::test_support::test_pool().await;
+
+        let user_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO users (id, email, username, password_hash, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(user_id)
+            .bind(format!("{}@example.com", user_id))
+            .bind(format!("outbox_{}", user_id))
+            .bind("hash")
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let message = |kind: &str| OutboxMessage::Notification { user_id, kind: kind.to_string(), data: serde_json::json!({}) };
+
+        // A rolled back change takes its side effects with it
+        let mut tx = pool.begin().await.unwrap();
+        enqueue(&mut *tx, &message("rolled_back")).await.unwrap();
+        tx.rollback().await.unwrap();
+
+        let mut tx = pool.begin().await.unwrap();
+        enqueue(&mut *tx, &message("committed")).await.unwrap();
+        tx.commit().await.unwrap();
+
+        crate::test_support::drain_outbox(&pool).await;
+        let kinds = sqlx::query_scalar::<_, String>("SELECT kind FROM notifications WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(kinds, vec!["committed"]);
+
+        let pending = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM outbox WHERE message->>'user_id' = $1 AND delivered_at IS NULL"
+        )
+        .bind(user_id.to_string())
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(pending, 0);
+    }
+}
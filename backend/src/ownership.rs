@@ -0,0 +1,210 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::events::{deliver, record, DomainEvent};
+use crate::handlers::CampaignResponse;
+use crate::membership::fill_open_seats;
+use crate::middleware::{bump_token_versions, AuthUser};
+use crate::models::Campaign;
+use crate::notifications::notify;
+use crate::scope::{resolve, Resource, FROZEN_MESSAGE};
+use crate::socket::SessionState;
+
+// Handing a campaign over to another DM. The owner offers it to a member, who has a week to
+// accept; until then the owner can withdraw the offer and the member can turn it down. On
+// acceptance the member becomes the DM and the previous DM stays on as a co-DM, free to
+// leave. The table sees it in the latest session's event log and over the WebSocket.
+
+const TRANSFER_TTL_DAYS: i64 = 7;
+
+#[derive(Deserialize)]
+pub struct TransferRequest {
+    pub to: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct TransferOffer {
+    pub campaign_id: Uuid,
+    pub to: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Owner only; replaces any earlier offer
+pub async fn offer_transfer(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+    Json(payload): Json<TransferRequest>,
+) -> impl IntoResponse {
+    if payload.to == user.0 {
+        return (StatusCode::BAD_REQUEST, "You already own this campaign").into_response();
+    }
+
+    let offered = async {
+        let mut tx = pool.begin().await?;
+        if resolve(&mut *tx, payload.to, Resource::Campaign(campaign_id)).await?.is_none() {
+            return Ok(None);
+        }
+        let (name, offered_at) = sqlx::query_as::<_, (String, DateTime<Utc>)>(
+            "UPDATE campaigns SET transfer_to = $2, transfer_offered_at = NOW() WHERE id = $1 RETURNING name, transfer_offered_at"
+        )
+        .bind(campaign_id)
+        .bind(payload.to)
+        .fetch_one(&mut *tx)
+        .await?;
+        notify(&mut *tx, payload.to, "campaign_transfer_offered", serde_json::json!({
+            "campaign_id": campaign_id,
+            "campaign_name": name,
+            "from": user.0,
+        }))
+        .await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Some(offered_at))
+    }
+    .await;
+
+    match offered {
+        Ok(Some(offered_at)) => {
+            let offer = TransferOffer { campaign_id, to: payload.to, expires_at: offered_at + Duration::days(TRANSFER_TTL_DAYS) };
+            (StatusCode::CREATED, Json(offer)).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Player not found in campaign").into_response(),
+        Err(e) => db_error(e, "Failed to offer transfer"),
+    }
+}
+
+// The member the campaign was offered to takes it over
+pub async fn accept_transfer(
+    Extension(pool): Extension<PgPool>,
+    Extension(session_state): Extension<SessionState>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let accepted = async {
+        let mut tx = pool.begin().await?;
+        let pending = sqlx::query_as::<_, (Uuid, bool)>(
+            "SELECT dm_id, frozen_at IS NOT NULL FROM campaigns
+             WHERE id = $1 AND transfer_to = $2 AND transfer_offered_at > $3 AND deleted_at IS NULL FOR UPDATE"
+        )
+        .bind(campaign_id)
+        .bind(user.0)
+        .bind(Utc::now() - Duration::days(TRANSFER_TTL_DAYS))
+        .fetch_optional(&mut *tx)
+        .await?;
+        let previous_dm_id = match pending {
+            Some((_, true)) => return Ok(Err((StatusCode::LOCKED, FROZEN_MESSAGE))),
+            Some((dm_id, false)) => dm_id,
+            None => return Ok(Err((StatusCode::NOT_FOUND, "No pending transfer to you"))),
+        };
+
+        // Offers lapse when the member leaves
+        let left = sqlx::query("DELETE FROM campaign_players WHERE campaign_id = $1 AND player_id = $2")
+            .bind(campaign_id)
+            .bind(user.0)
+            .execute(&mut *tx)
+            .await?;
+        if left.rows_affected() == 0 {
+            return Ok(Err((StatusCode::NOT_FOUND, "No pending transfer to you")));
+        }
+        sqlx::query("INSERT INTO campaign_players (campaign_id, player_id, role) VALUES ($1, $2, 'co_dm')")
+            .bind(campaign_id)
+            .bind(previous_dm_id)
+            .execute(&mut *tx)
+            .await?;
+        let campaign = sqlx::query_as::<_, Campaign>(
+            "UPDATE campaigns SET dm_id = $2, transfer_to = NULL, transfer_offered_at = NULL, updated_at = NOW() WHERE id = $1 RETURNING *"
+        )
+        .bind(campaign_id)
+        .bind(user.0)
+        .fetch_one(&mut *tx)
+        .await?;
+        let session_id = sqlx::query_scalar::<_, Uuid>("SELECT id FROM sessions WHERE campaign_id = $1 ORDER BY created_at DESC LIMIT 1")
+            .bind(campaign_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        let response = CampaignResponse::from(campaign.clone());
+        let event = DomainEvent::OwnershipTransferred { event_id: Uuid::new_v4(), session_id, campaign, previous_dm_id, at: Utc::now() };
+        record(&mut tx, &event).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(Ok((event, previous_dm_id, response)))
+    }
+    .await;
+
+    match accepted {
+        Ok(Ok((event, previous_dm_id, response))) => {
+            deliver(&session_state, &event).await;
+            // Both roles are embedded in tokens
+            let _ = bump_token_versions(&pool, &[user.0, previous_dm_id]).await;
+            // The new DM's seat, if they were a player, opens up
+            if let Err(e) = fill_open_seats(&pool, campaign_id).await {
+                tracing::error!(error = %e, %campaign_id, "Failed to promote from waitlist");
+            }
+            Json(response).into_response()
+        }
+        Ok(Err(refused)) => refused.into_response(),
+        Err(e) => db_error(e, "Failed to transfer campaign"),
+    }
+}
+
+// The owner withdraws the offer, or the member turns it down
+pub async fn cancel_transfer(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let res = sqlx::query(
+        "UPDATE campaigns SET transfer_to = NULL, transfer_offered_at = NULL
+         WHERE id = $1 AND transfer_to IS NOT NULL AND (dm_id = $2 OR transfer_to = $2)"
+    )
+    .bind(campaign_id)
+    .bind(user.0)
+    .execute(&pool)
+    .await;
+    match res {
+        Ok(done) if done.rows_affected() == 0 => (StatusCode::NOT_FOUND, "No pending transfer").into_response(),
+        Ok(_) => (StatusCode::OK, "Transfer cancelled").into_response(),
+        Err(e) => db_error(e, "Failed to cancel transfer"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_campaigns_change_hands_once_the_member_accepts() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let session_state = SessionState::new(None);
+        let offer = |user_id: Uuid, to: Uuid| offer_transfer(Extension(pool.clone()), Extension(AuthUser(user_id)), Path(f.campaign_id), Json(TransferRequest { to }));
+        let accept = |user_id: Uuid| accept_transfer(Extension(pool.clone()), Extension(session_state.clone()), Extension(AuthUser(user_id)), Path(f.campaign_id));
+
+        let refused = crate::test_support::call_as(&pool, f.player_id, axum::http::Method::POST, &format!("/campaigns/{}/transfer", f.campaign_id), Some(serde_json::json!({"to": f.player_id}))).await;
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        assert_eq!(offer(f.dm_id, f.outsider_id).await.into_response().status(), StatusCode::NOT_FOUND);
+        assert_eq!(offer(f.dm_id, f.player_id).await.into_response().status(), StatusCode::CREATED);
+        assert_eq!(accept(f.outsider_id).await.into_response().status(), StatusCode::NOT_FOUND);
+        assert_eq!(accept(f.player_id).await.into_response().status(), StatusCode::OK);
+
+        let dm_id = sqlx::query_scalar::<_, Uuid>("SELECT dm_id FROM campaigns WHERE id = $1").bind(f.campaign_id).fetch_one(&pool).await.unwrap();
+        assert_eq!(dm_id, f.player_id);
+        let roles = sqlx::query_as::<_, (Uuid, String)>("SELECT player_id, role FROM campaign_players WHERE campaign_id = $1")
+            .bind(f.campaign_id)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(roles, vec![(f.dm_id, "co_dm".to_string())]);
+        let logged = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM event_logs WHERE session_id = $1 AND event_type = 'ownership_transferred'")
+            .bind(f.session_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(logged, 1);
+        // Offers are used up
+        assert_eq!(accept(f.player_id).await.into_response().status(), StatusCode::NOT_FOUND);
+    }
+}
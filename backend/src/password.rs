@@ -0,0 +1,225 @@
+use argon2::password_hash::{rand_core::OsRng, SaltString};
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+// Password policy for new passwords, configured from the environment:
+// PASSWORD_MIN_LENGTH (characters, default 8), PASSWORD_BREACH_LIST (a file of breached
+// passwords, one per line, checked on top of the built-in list of the most common ones)
+// and PASSWORD_MIN_SCORE (zxcvbn score from 0 to 4 that passwords must reach; off unless set).
+
+pub const DEFAULT_MIN_LENGTH: usize = 8;
+
+// Longer passwords are refused outright; zxcvbn's cost grows with length (see `check`)
+pub const MAX_LENGTH: usize = 256;
+
+// Passwords at the top of every breach corpus; always refused
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456", "123456789", "12345678", "1234567890", "password", "password1", "password123",
+    "qwerty", "qwerty123", "qwertyuiop", "111111", "11111111", "123123", "abc123", "1q2w3e4r",
+    "iloveyou", "admin", "admin123", "welcome", "welcome1", "letmein", "monkey", "dragon",
+    "football", "baseball", "sunshine", "princess", "trustno1", "passw0rd", "00000000",
+    "master", "superman", "starwars", "whatever", "changeme",
+];
+
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    // Lowercased
+    pub breached: HashSet<String>,
+    pub min_score: Option<u8>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: DEFAULT_MIN_LENGTH,
+            breached: COMMON_PASSWORDS.iter().map(|password| password.to_string()).collect(),
+            min_score: None,
+        }
+    }
+}
+
+pub fn password_policy() -> &'static PasswordPolicy {
+    static POLICY: OnceLock<PasswordPolicy> = OnceLock::new();
+    POLICY.get_or_init(|| {
+        let mut policy = PasswordPolicy::default();
+        if let Some(min_length) = std::env::var("PASSWORD_MIN_LENGTH").ok().and_then(|value| value.parse().ok()) {
+            policy.min_length = min_length;
+        }
+        if let Ok(path) = std::env::var("PASSWORD_BREACH_LIST") {
+            match std::fs::read_to_string(&path) {
+                Ok(list) => policy.breached.extend(
+                    list.lines().map(|line| line.trim().to_lowercase()).filter(|line| !line.is_empty()),
+                ),
+                Err(e) => tracing::warn!(error = %e, %path, "Failed to read PASSWORD_BREACH_LIST"),
+            }
+        }
+        policy.min_score = std::env::var("PASSWORD_MIN_SCORE").ok().and_then(|value| value.parse().ok()).map(|score: u8| score.min(4));
+        policy
+    })
+}
+
+// Why a password was refused
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum PasswordProblem {
+    TooShort { min_length: usize },
+    TooLong { max_length: usize },
+    Breached,
+    TooWeak { score: u8, min_score: u8 },
+}
+
+// Sent back as 422 with every problem found, so the client can show them all at once
+#[derive(Debug, Serialize)]
+pub struct PasswordRejection {
+    pub error: &'static str,
+    pub problems: Vec<PasswordProblem>,
+    // zxcvbn's explanation of what makes the password guessable, when it was scored
+    pub warning: Option<String>,
+    pub suggestions: Vec<String>,
+}
+
+impl IntoResponse for PasswordRejection {
+    fn into_response(self) -> Response {
+        (StatusCode::UNPROCESSABLE_ENTITY, Json(self)).into_response()
+    }
+}
+
+impl PasswordPolicy {
+    // `user_inputs` are the account's other details, such as email and username; zxcvbn
+    // counts a password built from them as easy to guess
+    pub fn check(&self, password: &str, user_inputs: &[&str]) -> Result<(), PasswordRejection> {
+        let mut problems = Vec::new();
+        let mut warning = None;
+        let mut suggestions = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            problems.push(PasswordProblem::TooShort { min_length: self.min_length });
+        }
+        if password.chars().count() > MAX_LENGTH {
+            problems.push(PasswordProblem::TooLong { max_length: MAX_LENGTH });
+        }
+        if self.breached.contains(&password.to_lowercase()) {
+            problems.push(PasswordProblem::Breached);
+            suggestions.push("Pick a password you haven't used on other sites.".to_string());
+        }
+        // zxcvbn gets slow on long input, and a too-long password is refused anyway
+        if let Some(min_score) = self.min_score.filter(|_| password.chars().count() <= MAX_LENGTH) {
+            // zxcvbn refuses empty passwords, which score 0
+            let entropy = zxcvbn::zxcvbn(password, user_inputs).ok();
+            let score = entropy.as_ref().map_or(0, |entropy| entropy.score());
+            if score < min_score {
+                problems.push(PasswordProblem::TooWeak { score, min_score });
+                if let Some(feedback) = entropy.as_ref().and_then(|entropy| entropy.feedback().as_ref()) {
+                    warning = feedback.warning().map(|warning| warning.to_string());
+                    suggestions.extend(feedback.suggestions().iter().map(|suggestion| suggestion.to_string()));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            return Ok(());
+        }
+        if suggestions.is_empty() {
+            suggestions.push("Use a longer password, such as a few unrelated words.".to_string());
+        }
+        Err(PasswordRejection { error: "Password does not meet the password policy", problems, warning, suggestions })
+    }
+}
+
+// Password hashing: Argon2id, tuned with ARGON2_MEMORY_KIB, ARGON2_ITERATIONS and
+// ARGON2_PARALLELISM (defaults 19456, 2 and 1, the library's). The parameters are part of
+// every stored hash, so hashes made before a change still verify, and login rehashes them
+// once the configured parameters are stronger.
+
+fn argon2_params() -> &'static Params {
+    static PARAMS: OnceLock<Params> = OnceLock::new();
+    PARAMS.get_or_init(|| {
+        let setting = |name: &str, default: u32| std::env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default);
+        let memory = setting("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST);
+        let iterations = setting("ARGON2_ITERATIONS", Params::DEFAULT_T_COST);
+        let parallelism = setting("ARGON2_PARALLELISM", Params::DEFAULT_P_COST);
+        Params::new(memory, iterations, parallelism, None).unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "Invalid Argon2 parameters, using the defaults");
+            Params::default()
+        })
+    })
+}
+
+fn hash_with(params: &Params, password: &str) -> Result<String, argon2::password_hash::Error> {
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone());
+    Ok(argon2.hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))?.to_string())
+}
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    hash_with(argon2_params(), password)
+}
+
+// False for anything that isn't a hash, such as the '!' of accounts without a password
+pub fn verify_password(password_hash: &str, password: &str) -> bool {
+    PasswordHash::new(password_hash).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+fn weaker_than(password_hash: &str, params: &Params) -> bool {
+    let Ok(hash) = PasswordHash::new(password_hash) else {
+        return false;
+    };
+    if hash.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+    Params::try_from(&hash).map_or(true, |stored| {
+        stored.m_cost() < params.m_cost() || stored.t_cost() < params.t_cost() || stored.p_cost() < params.p_cost()
+    })
+}
+
+// Whether a hash that just verified should be replaced with one using the current parameters
+pub fn needs_rehash(password_hash: &str) -> bool {
+    weaker_than(password_hash, argon2_params())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_and_common_passwords_are_refused() {
+        let policy = PasswordPolicy::default();
+        let rejection = policy.check("a", &[]).unwrap_err();
+        assert_eq!(rejection.problems, vec![PasswordProblem::TooShort { min_length: DEFAULT_MIN_LENGTH }]);
+        assert!(!rejection.suggestions.is_empty());
+
+        let rejection = policy.check("Password123", &[]).unwrap_err();
+        assert_eq!(rejection.problems, vec![PasswordProblem::Breached]);
+
+        let rejection = policy.check(&"velvet otter ".repeat(40), &[]).unwrap_err();
+        assert_eq!(rejection.problems, vec![PasswordProblem::TooLong { max_length: MAX_LENGTH }]);
+
+        assert!(policy.check("correct horse battery staple", &[]).is_ok());
+    }
+
+    #[test]
+    fn test_zxcvbn_score_is_enforced_when_set() {
+        let policy = PasswordPolicy { min_score: Some(3), ..PasswordPolicy::default() };
+        let rejection = policy.check("wrenwren2024", &["wren@example.com", "wren"]).unwrap_err();
+        assert!(matches!(rejection.problems[..], [PasswordProblem::TooWeak { min_score: 3, .. }]));
+
+        assert!(policy.check("velvet-otter-lantern-93", &[]).is_ok());
+    }
+
+    #[test]
+    fn test_hashes_from_weaker_parameters_are_rehashed() {
+        let weak = Params::new(8 * 1024, 1, 1, None).unwrap();
+        let strong = Params::new(16 * 1024, 2, 1, None).unwrap();
+        let old_hash = hash_with(&weak, "velvet-otter-lantern").unwrap();
+        assert!(verify_password(&old_hash, "velvet-otter-lantern"));
+        assert!(!verify_password(&old_hash, "velvet-otter-lantern-2"));
+        assert!(weaker_than(&old_hash, &strong));
+
+        let new_hash = hash_with(&strong, "velvet-otter-lantern").unwrap();
+        assert!(!weaker_than(&new_hash, &strong));
+        assert!(!weaker_than(&new_hash, &weak));
+        assert!(!verify_password("!", "") && !weaker_than("!", &strong));
+    }
+}
@@ -0,0 +1,159 @@
+use axum::{http::StatusCode, response::IntoResponse, Extension, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use crate::errors::db_error;
+use crate::middleware::AuthUser;
+
+// Per-user settings every client should agree on, stored as JSON on the user row and returned
+// with the account. Settings that were never set read as their defaults, so new settings can
+// be added here without a migration.
+
+const MAX_TIMEZONE_LEN: usize = 64;
+
+// Which roll visibility the dice tray starts on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiceMode {
+    #[default]
+    Public,
+    DmOnly,
+    Secret,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationOptIns {
+    pub join_requests: bool,
+    pub waitlist: bool,
+    pub email: bool,
+}
+
+impl Default for NotificationOptIns {
+    fn default() -> Self {
+        NotificationOptIns { join_requests: true, waitlist: true, email: true }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    // IANA name such as "Europe/Berlin"; clients fall back to the device's zone
+    pub timezone: Option<String>,
+    // "#rrggbb"
+    pub dice_color: Option<String>,
+    pub default_dice_mode: DiceMode,
+    pub notifications: NotificationOptIns,
+}
+
+impl Preferences {
+    fn validate(&self) -> Result<(), &'static str> {
+        if let Some(timezone) = &self.timezone {
+            let valid = !timezone.is_empty()
+                && timezone.len() <= MAX_TIMEZONE_LEN
+                && timezone.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'));
+            if !valid {
+                return Err("Timezone must be an IANA name such as Europe/Berlin");
+            }
+        }
+        if let Some(color) = &self.dice_color {
+            let valid = color.len() == 7 && color.starts_with('#') && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+            if !valid {
+                return Err("Dice color must be a #rrggbb hex color");
+            }
+        }
+        Ok(())
+    }
+}
+
+pub async fn get_preferences(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+) -> impl IntoResponse {
+    let preferences = sqlx::query_scalar::<_, sqlx::types::Json<Preferences>>("SELECT preferences FROM users WHERE id = $1")
+        .bind(user.0)
+        .fetch_optional(&pool)
+        .await;
+    match preferences {
+        Ok(Some(preferences)) => Json(preferences.0).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => db_error(e, "Failed to fetch preferences"),
+    }
+}
+
+// A JSON merge patch (RFC 7396): fields left out are unchanged and null resets one to its default
+pub async fn update_preferences(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Json(patch): Json<serde_json::Value>,
+) -> impl IntoResponse {
+    if !patch.is_object() {
+        return (StatusCode::BAD_REQUEST, "Preferences must be a JSON object").into_response();
+    }
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(e, "Failed to update preferences"),
+    };
+    let stored = sqlx::query_scalar::<_, sqlx::types::Json<Preferences>>("SELECT preferences FROM users WHERE id = $1 FOR UPDATE")
+        .bind(user.0)
+        .fetch_optional(&mut *tx)
+        .await;
+    let stored = match stored {
+        Ok(Some(stored)) => stored.0,
+        Ok(None) => return (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => return db_error(e, "Failed to update preferences"),
+    };
+
+    let mut merged = serde_json::to_value(&stored).expect("preferences serialize");
+    json_patch::merge(&mut merged, &patch);
+    let preferences: Preferences = match serde_json::from_value(merged) {
+        Ok(preferences) => preferences,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("Invalid preferences: {}", e)).into_response(),
+    };
+    if let Err(message) = preferences.validate() {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    let updated = sqlx::query("UPDATE users SET preferences = $2, updated_at = NOW() WHERE id = $1")
+        .bind(user.0)
+        .bind(sqlx::types::Json(&preferences))
+        .execute(&mut *tx)
+        .await;
+    if let Err(e) = updated {
+        return db_error(e, "Failed to update preferences");
+    }
+    match tx.commit().await {
+        Ok(()) => Json(preferences).into_response(),
+        Err(e) => db_error(e, "Failed to update preferences"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_preference_updates_merge_into_the_stored_settings() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let update = |patch: serde_json::Value| update_preferences(Extension(pool.clone()), Extension(AuthUser(f.player_id)), Json(patch));
+        let read = |response: axum::response::Response| async {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<Preferences>(&body).unwrap()
+        };
+
+        let defaults = read(get_preferences(Extension(pool.clone()), Extension(AuthUser(f.player_id))).await.into_response()).await;
+        assert_eq!(defaults, Preferences::default());
+
+        let response = update(serde_json::json!({"timezone": "Europe/Berlin", "dice_color": "#aa33ff", "notifications": {"email": false}})).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = update(serde_json::json!({"default_dice_mode": "dm_only", "dice_color": null})).await.into_response();
+        let preferences = read(response).await;
+        assert_eq!(preferences.timezone.as_deref(), Some("Europe/Berlin"));
+        assert_eq!(preferences.dice_color, None);
+        assert_eq!(preferences.default_dice_mode, DiceMode::DmOnly);
+        assert_eq!(preferences.notifications, NotificationOptIns { email: false, ..NotificationOptIns::default() });
+
+        assert_eq!(update(serde_json::json!({"dice_color": "red"})).await.into_response().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(update(serde_json::json!({"default_dice_mode": "loud"})).await.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+}
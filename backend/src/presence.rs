@@ -0,0 +1,156 @@
+use chrono::Utc;
+use redis::AsyncCommands;
+use sqlx::PgPool;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+// App-wide online status, independent of which session (if any) a user has joined.
+// Each instance counts its own WebSocket connections per user. With Redis configured, each
+// user has a hash with a field per instance holding them, stamped by heartbeats, so every
+// instance sees the same status and one instance losing its last socket doesn't hide the
+// user's connections elsewhere. Stamps older than the TTL don't count, which takes care of
+// instances that went away without cleaning up.
+
+// Clients should send a Heartbeat at least this often
+pub const HEARTBEAT_INTERVAL_SECS: u64 = 30;
+
+// A user without a heartbeat for this long is considered offline
+const ONLINE_TTL_SECS: u64 = 3 * HEARTBEAT_INTERVAL_SECS;
+
+#[derive(Clone)]
+pub struct PresenceService {
+    connections: Arc<RwLock<HashMap<Uuid, usize>>>,
+    redis: Option<redis::aio::ConnectionManager>,
+    // This instance's field in the users' presence hashes
+    instance_id: String,
+}
+
+fn online_key(user_id: Uuid) -> String {
+    format!("yoda:presence:user:{}:instances", user_id)
+}
+
+impl PresenceService {
+    pub fn new(redis: Option<redis::aio::ConnectionManager>) -> Self {
+        PresenceService {
+            connections: Arc::new(RwLock::new(HashMap::new())),
+            redis,
+            instance_id: Uuid::new_v4().to_string(),
+        }
+    }
+
+    // Returns true when this is the user's first open connection, i.e. they just came online
+    pub async fn connect(&self, user_id: Uuid) -> bool {
+        let first = {
+            let mut connections = self.connections.write().await;
+            let count = connections.entry(user_id).or_insert(0);
+            *count += 1;
+            *count == 1
+        };
+        self.heartbeat(user_id).await;
+        first
+    }
+
+    pub async fn heartbeat(&self, user_id: Uuid) {
+        if let Some(redis) = &self.redis {
+            let mut conn = redis.clone();
+            let key = online_key(user_id);
+            let refreshed = redis::pipe()
+                .hset(&key, &self.instance_id, Utc::now().timestamp()).ignore()
+                .expire(&key, ONLINE_TTL_SECS as i64).ignore()
+                .query_async::<_, ()>(&mut conn)
+                .await;
+            if let Err(e) = refreshed {
+                tracing::warn!(error = %e, "Failed to refresh presence in Redis");
+            }
+        }
+    }
+
+    // Returns true when the user's last connection closed; their last-seen time is persisted
+    pub async fn disconnect(&self, pool: &PgPool, user_id: Uuid) -> bool {
+        let last = {
+            let mut connections = self.connections.write().await;
+            match connections.get_mut(&user_id) {
+                Some(count) if *count > 1 => {
+                    *count -= 1;
+                    false
+                }
+                Some(_) => {
+                    connections.remove(&user_id);
+                    true
+                }
+                None => false,
+            }
+        };
+        if !last {
+            return false;
+        }
+
+        if let Some(redis) = &self.redis {
+            let mut conn = redis.clone();
+            // Only this instance's field; connections held by other instances keep the user online
+            if let Err(e) = conn.hdel::<_, _, ()>(online_key(user_id), &self.instance_id).await {
+                tracing::warn!(error = %e, "Failed to clear presence in Redis");
+            }
+        }
+        if let Err(e) = sqlx::query("UPDATE users SET last_seen_at = $1 WHERE id = $2")
+            .bind(Utc::now())
+            .bind(user_id)
+            .execute(pool)
+            .await
+        {
+            tracing::error!(error = %e, "Failed to record last seen time");
+        }
+        true
+    }
+
+    // Which of the given users are currently online
+    pub async fn online_users(&self, user_ids: &[Uuid]) -> HashSet<Uuid> {
+        if user_ids.is_empty() {
+            return HashSet::new();
+        }
+        if let Some(redis) = &self.redis {
+            let mut conn = redis.clone();
+            let mut pipe = redis::pipe();
+            for user_id in user_ids {
+                pipe.hvals(online_key(*user_id));
+            }
+            match pipe.query_async::<_, Vec<Vec<i64>>>(&mut conn).await {
+                Ok(stamps) => {
+                    let cutoff = Utc::now().timestamp() - ONLINE_TTL_SECS as i64;
+                    return user_ids
+                        .iter()
+                        .zip(stamps)
+                        .filter(|(_, stamps)| stamps.iter().any(|stamp| *stamp > cutoff))
+                        .map(|(id, _)| *id)
+                        .collect();
+                }
+                Err(e) => tracing::warn!(error = %e, "Failed to read presence from Redis, using local state"),
+            }
+        }
+        let connections = self.connections.read().await;
+        user_ids.iter().filter(|id| connections.contains_key(id)).copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_online_until_last_connection_closes() {
+        let presence = PresenceService::new(None);
+        let user_id = Uuid::new_v4();
+
+        assert!(presence.connect(user_id).await);
+        assert!(!presence.connect(user_id).await);
+        assert!(presence.online_users(&[user_id]).await.contains(&user_id));
+
+        // Closing one of two connections keeps the user online (and doesn't touch the database)
+        let pool = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        assert!(!presence.disconnect(&pool, user_id).await);
+        assert!(presence.online_users(&[user_id]).await.contains(&user_id));
+        assert!(presence.online_users(&[Uuid::new_v4()]).await.is_empty());
+    }
+}
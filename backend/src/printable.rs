@@ -0,0 +1,303 @@
+use axum::{
+    extract::{Path, Query},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::PgPool;
+use std::fmt::Write;
+use std::time::Duration;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::middleware::{jwt_secret, AuthUser};
+use crate::models::Character;
+use crate::scope::{authorize, Access, Resource};
+use crate::sheet::{ability_score, check_modifier, derive, ABILITIES, SKILLS};
+
+// Printable character sheets. A campaign member mints a share link, and GET
+// /characters/:id/sheet.html renders the sheet as plain HTML for anyone holding it until it
+// expires, so it opens in a browser tab without a token. The link carries an HMAC of the
+// character id and expiry, signed with the JWT secret.
+
+// How long a share link works
+const SHEET_LINK_TTL: Duration = Duration::from_secs(3600);
+
+fn sheet_mac(character_id: Uuid, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(jwt_secret().as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("character-sheet\n{}\n{}", character_id, expires).as_bytes());
+    mac
+}
+
+fn sign_sheet_url(character_id: Uuid, expires: i64) -> String {
+    let signature = hex::encode(sheet_mac(character_id, expires).finalize().into_bytes());
+    format!("/characters/{}/sheet.html?expires={}&signature={}", character_id, expires, signature)
+}
+
+fn verify_sheet_url(character_id: Uuid, expires: i64, signature: &str) -> bool {
+    if expires < Utc::now().timestamp() {
+        return false;
+    }
+    match hex::decode(signature) {
+        Ok(signature) => sheet_mac(character_id, expires).verify_slice(&signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[derive(Serialize)]
+pub struct SheetLink {
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub async fn create_sheet_link(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(character_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize(&pool, user.0, Resource::Character(character_id), Access::Member, "Access denied to this character").await {
+        return response;
+    }
+    let expires_at = Utc::now() + chrono::Duration::seconds(SHEET_LINK_TTL.as_secs() as i64);
+    let url = sign_sheet_url(character_id, expires_at.timestamp());
+    (StatusCode::CREATED, axum::Json(SheetLink { url, expires_at })).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct SignedSheetQuery {
+    pub expires: i64,
+    pub signature: String,
+}
+
+pub async fn character_sheet_html(
+    Extension(pool): Extension<PgPool>,
+    Path(character_id): Path<Uuid>,
+    Query(params): Query<SignedSheetQuery>,
+) -> impl IntoResponse {
+    if !verify_sheet_url(character_id, params.expires, &params.signature) {
+        return (StatusCode::FORBIDDEN, "Invalid or expired link").into_response();
+    }
+    let character = sqlx::query_as::<_, Character>("SELECT * FROM characters WHERE id = $1")
+        .bind(character_id)
+        .fetch_optional(&pool)
+        .await;
+    match character {
+        Ok(Some(character)) => (
+            [
+                (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+                (header::CACHE_CONTROL, "private, no-store"),
+                // The link is the credential; keep it out of Referer headers and search engines
+                (header::REFERRER_POLICY, "no-referrer"),
+                (header::HeaderName::from_static("x-robots-tag"), "noindex"),
+            ],
+            render_sheet(&character),
+        )
+            .into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Character not found").into_response(),
+        Err(e) => db_error(e, "Failed to fetch character"),
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn signed(modifier: i32) -> String {
+    format!("{:+}", modifier)
+}
+
+fn capitalized(name: &str) -> String {
+    let mut words = name.replace('_', " ");
+    words[..1].make_ascii_uppercase();
+    words
+}
+
+// Inventory, spells and features are lists of names or of objects with a `name`; an item's
+// `quantity` is shown when above one
+fn list_entries(value: &serde_json::Value) -> Vec<String> {
+    let Some(entries) = value.as_array() else { return Vec::new() };
+    entries
+        .iter()
+        .filter_map(|entry| match entry {
+            serde_json::Value::String(name) => Some(name.clone()),
+            serde_json::Value::Object(fields) => {
+                let name = fields.get("name")?.as_str()?;
+                match fields.get("quantity").and_then(|quantity| quantity.as_i64()) {
+                    Some(quantity) if quantity > 1 => Some(format!("{} ×{}", name, quantity)),
+                    _ => Some(name.to_string()),
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+const SHEET_STYLE: &str = "
+body { font-family: Georgia, serif; max-width: 52rem; margin: 2rem auto; padding: 0 1rem; color: #222; }
+h1 { margin-bottom: 0; }
+.subtitle { margin-top: 0.25rem; color: #555; }
+.stats { display: flex; flex-wrap: wrap; gap: 0.5rem; margin: 1rem 0; }
+.stat { border: 1px solid #999; border-radius: 4px; padding: 0.4rem 0.8rem; text-align: center; min-width: 5rem; }
+.stat .value { font-size: 1.4rem; font-weight: bold; }
+.columns { display: grid; grid-template-columns: repeat(auto-fit, minmax(15rem, 1fr)); gap: 1.5rem; }
+table { border-collapse: collapse; width: 100%; }
+td { padding: 0.15rem 0.4rem; border-bottom: 1px solid #ddd; }
+td.mod { text-align: right; width: 3rem; }
+@media print { body { margin: 0; max-width: none; } a { color: inherit; } }
+";
+
+fn section(html: &mut String, title: &str, entries: &[String]) {
+    if entries.is_empty() {
+        return;
+    }
+    let _ = write!(html, "<section><h2>{}</h2><ul>", title);
+    for entry in entries {
+        let _ = write!(html, "<li>{}</li>", escape(entry));
+    }
+    html.push_str("</ul></section>");
+}
+
+pub fn render_sheet(character: &Character) -> String {
+    let derived = derive(character);
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html><html lang=\"en\"><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{name}</title><style>{style}</style></head><body><h1>{name}</h1>",
+        name = escape(&character.name),
+        style = SHEET_STYLE,
+    );
+    let subtitle: Vec<String> = [character.race.clone(), character.class.clone()].into_iter().flatten().collect();
+    let _ = write!(html, "<p class=\"subtitle\">Level {} {} · {} XP</p>", character.level, escape(&subtitle.join(" ")), character.xp);
+
+    let hp = match (character.hp_current, character.hp_max) {
+        (Some(current), Some(max)) => format!("{} / {}", current, max),
+        (current, max) => current.or(max).map_or("—".to_string(), |hp| hp.to_string()),
+    };
+    let optional = |value: Option<i32>| value.map_or("—".to_string(), |value| value.to_string());
+    let mut headline = vec![
+        ("Hit Points", hp),
+        ("Armor Class", optional(character.ac)),
+        ("Speed", character.speed.map_or("—".to_string(), |speed| format!("{} ft", speed))),
+        ("Initiative", signed(derived.initiative_modifier)),
+        ("Proficiency", signed(derived.proficiency_bonus)),
+    ];
+    if let (Some(dc), Some(attack)) = (derived.spell_save_dc, derived.spell_attack_bonus) {
+        headline.push(("Spell Save DC", dc.to_string()));
+        headline.push(("Spell Attack", signed(attack)));
+    }
+    html.push_str("<div class=\"stats\">");
+    for (label, value) in &headline {
+        let _ = write!(html, "<div class=\"stat\"><div class=\"value\">{}</div>{}</div>", escape(value), label);
+    }
+    html.push_str("</div><div class=\"stats\">");
+    for ability in ABILITIES {
+        let score = ability_score(&character.stats, ability);
+        let modifier = derived.ability_modifiers.get(ability).unwrap_or_default();
+        let _ = write!(
+            html,
+            "<div class=\"stat\">{}<div class=\"value\">{}</div>{}</div>",
+            ability[..3].to_uppercase(),
+            signed(modifier),
+            score
+        );
+    }
+    html.push_str("</div><div class=\"columns\">");
+
+    // Proficient checks are marked with a dot, expertise with two
+    let checks = |names: Vec<String>| {
+        let mut rows = String::new();
+        for name in names {
+            if let Some(check) = check_modifier(character, &derived, &name) {
+                let mark = if check.expertise { "●●" } else if check.proficient { "●" } else { "" };
+                let _ = write!(
+                    rows,
+                    "<tr><td>{}</td><td>{}</td><td class=\"mod\">{}</td></tr>",
+                    mark,
+                    capitalized(name.trim_end_matches("_save")),
+                    signed(check.modifier)
+                );
+            }
+        }
+        rows
+    };
+    let saves = checks(ABILITIES.iter().map(|ability| format!("{}_save", ability)).collect());
+    let skills = checks(SKILLS.iter().map(|(skill, _)| skill.to_string()).collect());
+    let _ = write!(html, "<section><h2>Saving Throws</h2><table>{}</table></section>", saves);
+    let _ = write!(html, "<section><h2>Skills</h2><table>{}</table></section>", skills);
+    html.push_str("</div><div class=\"columns\">");
+    section(&mut html, "Inventory", &list_entries(&character.inventory));
+    section(&mut html, "Spells", &list_entries(&character.spells));
+    section(&mut html, "Features", &list_entries(&character.features));
+    html.push_str("</div></body></html>");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_sheet_links_expire_and_resist_tampering() {
+        let character_id = Uuid::new_v4();
+        let expires = Utc::now().timestamp() + 60;
+        let url = sign_sheet_url(character_id, expires);
+        let signature = url.split("signature=").nth(1).unwrap();
+        assert!(verify_sheet_url(character_id, expires, signature));
+        assert!(!verify_sheet_url(Uuid::new_v4(), expires, signature));
+        assert!(!verify_sheet_url(character_id, expires + 1, signature));
+
+        let past = Utc::now().timestamp() - 1;
+        let stale = sign_sheet_url(character_id, past);
+        assert!(!verify_sheet_url(character_id, past, stale.split("signature=").nth(1).unwrap()));
+    }
+
+    #[test]
+    fn test_sheet_renders_derived_stats_and_escapes_text() {
+        let character = Character {
+            id: Uuid::new_v4(),
+            campaign_id: Uuid::new_v4(),
+            player_id: None,
+            name: "Wren <script>".to_string(),
+            race: Some("Halfling".to_string()),
+            class: Some("Rogue".to_string()),
+            level: 5,
+            hp_current: Some(27),
+            hp_max: Some(33),
+            ac: Some(15),
+            speed: Some(25),
+            stats: json!({"dex": 18, "wis": 12, "proficiencies": ["perception"], "expertise": ["stealth"]}),
+            inventory: json!([{"name": "Dagger", "quantity": 2}, "Thieves' tools"]),
+            spells: json!([]),
+            features: json!([{"name": "Sneak Attack"}]),
+            xp: 6500,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let html = render_sheet(&character);
+        assert!(html.contains("<h1>Wren &lt;script&gt;</h1>"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("Level 5 Halfling Rogue"));
+        assert!(html.contains("27 / 33"));
+        assert!(html.contains("<td>●</td><td>Perception</td><td class=\"mod\">+4</td>"));
+        assert!(html.contains("<td>●●</td><td>Stealth</td><td class=\"mod\">+10</td>"));
+        assert!(html.contains("<li>Dagger ×2</li>"));
+        assert!(html.contains("Thieves&#39; tools"));
+        assert!(!html.contains("<h2>Spells</h2>"));
+    }
+}
@@ -0,0 +1,370 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::membership::fill_open_seats;
+use crate::password::verify_password;
+use crate::preferences::Preferences;
+use crate::middleware::{AuthUser, CampaignRole};
+use crate::scope::campaign_roles;
+use crate::revocation::revoke_user;
+use crate::socket::SessionState;
+use crate::verification::send_verification;
+
+// The signed-in user's own account, and the small public profile fellow campaign members see
+
+const MAX_USERNAME_LEN: usize = 100;
+const MAX_DISPLAY_NAME_LEN: usize = 100;
+const MAX_AVATAR_URL_LEN: usize = 2048;
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AccountResponse {
+    pub id: Uuid,
+    pub email: String,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub email_verified: bool,
+    pub preferences: sqlx::types::Json<Preferences>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PublicProfile {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+pub(crate) const ACCOUNT_COLUMNS: &str = "id, email, username, display_name, avatar_url, email_verified, preferences, created_at";
+
+pub async fn get_me(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+) -> impl IntoResponse {
+    let account = sqlx::query_as::<_, AccountResponse>(&format!("SELECT {} FROM users WHERE id = $1", ACCOUNT_COLUMNS))
+        .bind(user.0)
+        .fetch_optional(&pool)
+        .await;
+    match account {
+        Ok(Some(account)) => Json(account).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => db_error(e, "Failed to fetch account"),
+    }
+}
+
+// Fields left out are unchanged; an empty display name or avatar URL clears it. Setting the
+// avatar URL replaces an uploaded avatar.
+#[derive(Deserialize)]
+pub struct UpdateMeRequest {
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+fn validate(payload: &UpdateMeRequest) -> Result<(), &'static str> {
+    if let Some(username) = &payload.username {
+        let username = username.trim();
+        if username.is_empty() || username.chars().count() > MAX_USERNAME_LEN {
+            return Err("Username must be 1 to 100 characters");
+        }
+    }
+    if let Some(email) = &payload.email {
+        let email = email.trim();
+        if email.len() > 255 || !email.contains('@') || email.starts_with('@') || email.ends_with('@') {
+            return Err("Invalid email address");
+        }
+    }
+    if payload.display_name.as_ref().is_some_and(|name| name.trim().chars().count() > MAX_DISPLAY_NAME_LEN) {
+        return Err("Display name must be at most 100 characters");
+    }
+    if let Some(url) = payload.avatar_url.as_deref().map(str::trim).filter(|url| !url.is_empty()) {
+        if url.len() > MAX_AVATAR_URL_LEN || !(url.starts_with("https://") || url.starts_with("http://")) {
+            return Err("Avatar URL must be an http(s) URL");
+        }
+    }
+    Ok(())
+}
+
+// Changing the email address unverifies the account until the new address is confirmed
+pub async fn update_me(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<UpdateMeRequest>,
+) -> impl IntoResponse {
+    if let Err(message) = validate(&payload) {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    let username = payload.username.as_deref().map(str::trim);
+    let email = payload.email.as_deref().map(str::trim);
+    let display_name = payload.display_name.as_deref().map(str::trim);
+    let avatar_url = payload.avatar_url.as_deref().map(str::trim);
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(e, "Failed to update account"),
+    };
+    let taken = sqlx::query_as::<_, (bool, bool, Option<String>)>(
+        "SELECT EXISTS(SELECT 1 FROM users WHERE username = $1 AND id <> $3), EXISTS(SELECT 1 FROM users WHERE email = $2 AND id <> $3),
+         (SELECT email FROM users WHERE id = $3)"
+    )
+    .bind(username)
+    .bind(email)
+    .bind(user.0)
+    .fetch_one(&mut *tx)
+    .await;
+    let email_changed = match taken {
+        Ok((true, _, _)) => return (StatusCode::CONFLICT, "Username already taken").into_response(),
+        Ok((_, true, _)) => return (StatusCode::CONFLICT, "Email already in use").into_response(),
+        Ok((_, _, current)) => email.is_some_and(|email| current.as_deref() != Some(email)),
+        Err(e) => return db_error(e, "Failed to update account"),
+    };
+
+    let account = sqlx::query_as::<_, AccountResponse>(&format!(
+        "UPDATE users SET
+             username = COALESCE($1, username),
+             email_verified = email_verified AND ($2::text IS NULL OR $2 = email),
+             email = COALESCE($2, email),
+             display_name = CASE WHEN $3::text IS NULL THEN display_name ELSE NULLIF($3, '') END,
+             avatar_url = CASE WHEN $4::text IS NULL THEN avatar_url ELSE NULLIF($4, '') END,
+             avatar_key = CASE WHEN $4::text IS NULL THEN avatar_key END,
+             avatar_thumb_key = CASE WHEN $4::text IS NULL THEN avatar_thumb_key END,
+             updated_at = NOW()
+         WHERE id = $5 RETURNING {}",
+        ACCOUNT_COLUMNS
+    ))
+    .bind(username)
+    .bind(email)
+    .bind(display_name)
+    .bind(avatar_url)
+    .bind(user.0)
+    .fetch_optional(&mut *tx)
+    .await;
+    let account = match account {
+        Ok(Some(account)) => account,
+        Ok(None) => return (StatusCode::NOT_FOUND, "User not found").into_response(),
+        // A concurrent update can still hit the unique constraints (409)
+        Err(e) => return db_error(e, "Failed to update account"),
+    };
+    if email_changed {
+        if let Err(e) = send_verification(&mut tx, user.0, &account.email).await {
+            return db_error(e, "Failed to send verification email");
+        }
+    }
+    match tx.commit().await {
+        Ok(()) => Json(account).into_response(),
+        Err(e) => db_error(e, "Failed to update account"),
+    }
+}
+
+// Only for users who share a campaign with the caller; everyone else is 404
+pub async fn get_profile(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(user_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let mine: Vec<Uuid> = match campaign_roles(&pool, user.0).await {
+        Ok(roles) => roles.into_keys().collect(),
+        Err(e) => return db_error(e, "Failed to fetch profile"),
+    };
+    let profile = sqlx::query_as::<_, PublicProfile>(
+        "SELECT u.id, u.username, u.display_name, u.avatar_url FROM users u
+         WHERE u.id = $1 AND (
+             u.id = $2
+             OR EXISTS (SELECT 1 FROM campaigns c WHERE c.dm_id = u.id AND c.id = ANY($3))
+             OR EXISTS (SELECT 1 FROM campaign_players cp WHERE cp.player_id = u.id AND cp.campaign_id = ANY($3))
+         )"
+    )
+    .bind(user_id)
+    .bind(user.0)
+    .bind(&mine)
+    .fetch_optional(&pool)
+    .await;
+    match profile {
+        Ok(Some(profile)) => Json(profile).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => db_error(e, "Failed to fetch profile"),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletionMode {
+    // Everything the user made goes: campaigns they run, their characters, chat and event log entries
+    Delete,
+    // The account becomes a nameless tombstone; what the user made stays for the rest of the table
+    Anonymize,
+}
+
+// The confirmation step: the caller types their username, and their password when the account has one
+#[derive(Deserialize)]
+pub struct DeleteMeRequest {
+    pub mode: DeletionMode,
+    pub confirm: String,
+    pub password: Option<String>,
+}
+
+// Tables that reference users without a cascade, plus the user's own content elsewhere
+async fn delete_account(conn: &mut PgConnection, user_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM campaigns WHERE dm_id = $1").bind(user_id).execute(&mut *conn).await?;
+    sqlx::query("DELETE FROM characters WHERE player_id = $1").bind(user_id).execute(&mut *conn).await?;
+    sqlx::query("DELETE FROM event_logs WHERE created_by = $1").bind(user_id).execute(&mut *conn).await?;
+    sqlx::query("DELETE FROM ability_score_rolls WHERE rolled_by = $1").bind(user_id).execute(&mut *conn).await?;
+    sqlx::query("DELETE FROM content_contributions WHERE user_id = $1").bind(user_id).execute(&mut *conn).await?;
+    sqlx::query("DELETE FROM content_grants WHERE granted_by = $1").bind(user_id).execute(&mut *conn).await?;
+    sqlx::query("DELETE FROM users WHERE id = $1").bind(user_id).execute(&mut *conn).await?;
+    Ok(())
+}
+
+// Campaign content keeps pointing at the row, which no longer says who it was or lets anyone in
+async fn anonymize_account(conn: &mut PgConnection, user_id: Uuid) -> Result<(), sqlx::Error> {
+    let short = user_id.simple().to_string()[..12].to_string();
+    sqlx::query(
+        "UPDATE users SET email = $2, username = $3, password_hash = '!', display_name = NULL, avatar_url = NULL,
+             avatar_key = NULL, avatar_thumb_key = NULL, preferences = '{}',
+             email_verified = FALSE, disabled_at = COALESCE(disabled_at, NOW()), updated_at = NOW()
+         WHERE id = $1"
+    )
+    .bind(user_id)
+    .bind(format!("deleted-{}@deleted.invalid", short))
+    .bind(format!("deleted_{}", short))
+    .execute(&mut *conn)
+    .await?;
+    for table in ["oauth_identities", "email_verifications", "magic_links", "join_requests", "notifications", "content_grants", "auth_audit"] {
+        sqlx::query(&format!("DELETE FROM {} WHERE user_id = $1", table)).bind(user_id).execute(&mut *conn).await?;
+    }
+    sqlx::query("DELETE FROM campaign_players WHERE player_id = $1").bind(user_id).execute(&mut *conn).await?;
+    Ok(())
+}
+
+pub async fn delete_me(
+    Extension(pool): Extension<PgPool>,
+    Extension(session_state): Extension<SessionState>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<DeleteMeRequest>,
+) -> impl IntoResponse {
+    let account = sqlx::query_as::<_, (String, String)>("SELECT username, password_hash FROM users WHERE id = $1")
+        .bind(user.0)
+        .fetch_optional(&pool)
+        .await;
+    let (username, password_hash) = match account {
+        Ok(Some(account)) => account,
+        Ok(None) => return (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => return db_error(e, "Failed to delete account"),
+    };
+    if payload.confirm != username {
+        return (StatusCode::BAD_REQUEST, "Type your username to confirm").into_response();
+    }
+    // Accounts made through OAuth or the demo have no password ('!')
+    if password_hash != "!" && !payload.password.as_deref().is_some_and(|password| verify_password(&password_hash, password)) {
+        return (StatusCode::FORBIDDEN, "Password is incorrect").into_response();
+    }
+
+    // Campaigns that lose a player get their seat back for the waitlist
+    let joined: Vec<Uuid> = match campaign_roles(&pool, user.0).await {
+        Ok(roles) => roles.into_iter().filter(|(_, role)| *role != CampaignRole::Dm).map(|(campaign_id, _)| campaign_id).collect(),
+        Err(e) => return db_error(e, "Failed to delete account"),
+    };
+
+    // Logged out everywhere first, so no socket keeps writing as the data goes
+    if let Err(e) = revoke_user(&pool, &session_state, user.0).await {
+        return db_error(e, "Failed to revoke tokens");
+    }
+    let removed = async {
+        let mut tx = pool.begin().await?;
+        match payload.mode {
+            DeletionMode::Delete => delete_account(&mut tx, user.0).await?,
+            DeletionMode::Anonymize => anonymize_account(&mut tx, user.0).await?,
+        }
+        tx.commit().await
+    }
+    .await;
+    if let Err(e) = removed {
+        return db_error(e, "Failed to delete account");
+    }
+
+    for campaign_id in joined {
+        if let Err(e) = fill_open_seats(&pool, campaign_id).await {
+            tracing::error!(error = %e, %campaign_id, "Failed to fill open seats");
+        }
+    }
+    match payload.mode {
+        DeletionMode::Delete => (StatusCode::OK, "Account deleted").into_response(),
+        DeletionMode::Anonymize => (StatusCode::OK, "Account anonymized").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(value: serde_json::Value) -> UpdateMeRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_profiles_update_and_stay_within_campaigns() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let update = |user_id: Uuid, value: serde_json::Value| update_me(Extension(pool.clone()), Extension(AuthUser(user_id)), Json(request(value)));
+
+        let bad = update(f.player_id, serde_json::json!({"avatar_url": "javascript:alert(1)"})).await.into_response();
+        assert_eq!(bad.status(), StatusCode::BAD_REQUEST);
+        let dm_username = sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE id = $1").bind(f.dm_id).fetch_one(&pool).await.unwrap();
+        let taken = update(f.player_id, serde_json::json!({"username": dm_username})).await.into_response();
+        assert_eq!(taken.status(), StatusCode::CONFLICT);
+
+        let response = update(f.player_id, serde_json::json!({"display_name": " Wren ", "avatar_url": "https://example.com/wren.png"})).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let account: AccountResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(account.display_name.as_deref(), Some("Wren"));
+
+        let profile = |viewer: Uuid| get_profile(Extension(pool.clone()), Extension(AuthUser(viewer)), Path(f.player_id));
+        assert_eq!(profile(f.dm_id).await.into_response().status(), StatusCode::OK);
+        assert_eq!(profile(f.outsider_id).await.into_response().status(), StatusCode::NOT_FOUND);
+
+        let cleared = update(f.player_id, serde_json::json!({"display_name": ""})).await.into_response();
+        let body = axum::body::to_bytes(cleared.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<AccountResponse>(&body).unwrap().display_name, None);
+    }
+
+    #[tokio::test]
+    async fn test_deleting_an_account_needs_confirmation() {
+        let pool = crate::test_support::test_pool().await;
+        let state = SessionState::new(None);
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        sqlx::query("UPDATE users SET password_hash = '!' WHERE id = $1").bind(f.player_id).execute(&pool).await.unwrap();
+        let username = |user_id: Uuid| {
+            let pool = pool.clone();
+            async move { sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE id = $1").bind(user_id).fetch_one(&pool).await.unwrap() }
+        };
+        let delete = |user_id: Uuid, value: serde_json::Value| {
+            delete_me(Extension(pool.clone()), Extension(state.clone()), Extension(AuthUser(user_id)), Json(serde_json::from_value(value).unwrap()))
+        };
+
+        let wrong = delete(f.player_id, serde_json::json!({"mode": "anonymize", "confirm": "someone else"})).await.into_response();
+        assert_eq!(wrong.status(), StatusCode::BAD_REQUEST);
+        let confirm = username(f.player_id).await;
+        let anonymized = delete(f.player_id, serde_json::json!({"mode": "anonymize", "confirm": confirm})).await.into_response();
+        assert_eq!(anonymized.status(), StatusCode::OK);
+        assert!(username(f.player_id).await.starts_with("deleted_"));
+        // The character stays with the campaign
+        let characters = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM characters WHERE id = $1").bind(f.character_id).fetch_one(&pool).await.unwrap();
+        assert_eq!(characters, 1);
+
+        // The fixture's DM has a password hash that can never match
+        let confirm = username(f.dm_id).await;
+        let refused = delete(f.dm_id, serde_json::json!({"mode": "delete", "confirm": confirm, "password": "hashed_password"})).await.into_response();
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        sqlx::query("UPDATE users SET password_hash = '!' WHERE id = $1").bind(f.dm_id).execute(&pool).await.unwrap();
+        let deleted = delete(f.dm_id, serde_json::json!({"mode": "delete", "confirm": confirm})).await.into_response();
+        assert_eq!(deleted.status(), StatusCode::OK);
+        let campaigns = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM campaigns WHERE id = $1").bind(f.campaign_id).fetch_one(&pool).await.unwrap();
+        assert_eq!(campaigns, 0);
+    }
+}
@@ -0,0 +1,231 @@
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{header, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+use crate::middleware::token_subject;
+
+// Per-client API rate limits, so a runaway frontend can't flood the database. Requests are
+// counted per user when they carry a valid token and per client address otherwise, in token
+// buckets for each route group, on this instance. Each group's budget is set as
+// "<requests>/<seconds>" in RATE_LIMIT_READ, RATE_LIMIT_WRITE, RATE_LIMIT_AUTH and
+// RATE_LIMIT_AI; RATE_LIMIT=off turns limiting off. Over budget, requests get 429 with
+// Retry-After.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteGroup {
+    // GETs
+    Read,
+    // Everything else
+    Write,
+    // /auth, mostly unauthenticated, so usually counted per address
+    Auth,
+    // /ai, which costs real money per request
+    Ai,
+}
+
+impl RouteGroup {
+    fn of(method: &Method, path: &str) -> Self {
+        if path.starts_with("/auth/") {
+            RouteGroup::Auth
+        } else if path.starts_with("/ai/") {
+            RouteGroup::Ai
+        } else if method == Method::GET || method == Method::HEAD {
+            RouteGroup::Read
+        } else {
+            RouteGroup::Write
+        }
+    }
+
+    fn env_var(&self) -> &'static str {
+        match self {
+            RouteGroup::Read => "RATE_LIMIT_READ",
+            RouteGroup::Write => "RATE_LIMIT_WRITE",
+            RouteGroup::Auth => "RATE_LIMIT_AUTH",
+            RouteGroup::Ai => "RATE_LIMIT_AI",
+        }
+    }
+
+    fn default_budget(&self) -> Budget {
+        let per_minute = |requests| Budget { requests, per: Duration::from_secs(60) };
+        match self {
+            RouteGroup::Read => per_minute(300),
+            RouteGroup::Write => per_minute(120),
+            RouteGroup::Auth => per_minute(30),
+            RouteGroup::Ai => per_minute(20),
+        }
+    }
+}
+
+// Checked by the load balancer and Prometheus, which shouldn't be turned away
+const EXEMPT_PATHS: [&str; 2] = ["/health", "/metrics"];
+
+// Buckets that have refilled are dropped this often
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Budget {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+impl Budget {
+    // "300/60": 300 requests per 60 seconds
+    fn parse(value: &str) -> Option<Self> {
+        let (requests, secs) = value.split_once('/')?;
+        let requests: u32 = requests.trim().parse().ok()?;
+        let secs: u64 = secs.trim().parse().ok()?;
+        (requests > 0 && secs > 0).then(|| Budget { requests, per: Duration::from_secs(secs) })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ClientKey {
+    User(Uuid),
+    Address(IpAddr),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Every client's bucket in every group, and when idle ones were last pruned
+type Buckets = (HashMap<(ClientKey, RouteGroup), Bucket>, Instant);
+
+pub struct RateLimiter {
+    budgets: HashMap<RouteGroup, Budget>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    fn new(budgets: HashMap<RouteGroup, Budget>) -> Self {
+        RateLimiter { budgets, buckets: Mutex::new((HashMap::new(), Instant::now())) }
+    }
+
+    fn from_env() -> Self {
+        let groups = [RouteGroup::Read, RouteGroup::Write, RouteGroup::Auth, RouteGroup::Ai];
+        let budgets = groups
+            .into_iter()
+            .map(|group| {
+                let budget = env::var(group.env_var()).ok().and_then(|value| {
+                    let budget = Budget::parse(&value);
+                    if budget.is_none() {
+                        tracing::warn!(var = group.env_var(), %value, "Invalid rate limit, expected <requests>/<seconds>; using the default");
+                    }
+                    budget
+                });
+                (group, budget.unwrap_or_else(|| group.default_budget()))
+            })
+            .collect();
+        RateLimiter::new(budgets)
+    }
+
+    // Takes one request from the client's bucket; when it's empty, returns how long until
+    // the next request is allowed
+    fn check(&self, key: ClientKey, group: RouteGroup, now: Instant) -> Result<(), Duration> {
+        let budget = self.budgets[&group];
+        let capacity = budget.requests as f64;
+        let refill_per_sec = capacity / budget.per.as_secs_f64();
+
+        let mut guard = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (buckets, last_pruned) = &mut *guard;
+        if now.duration_since(*last_pruned) >= PRUNE_INTERVAL {
+            let budgets = &self.budgets;
+            buckets.retain(|(_, group), bucket| now.duration_since(bucket.updated) < budgets[group].per);
+            *last_pruned = now;
+        }
+
+        let bucket = buckets.entry((key, group)).or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * refill_per_sec).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - bucket.tokens) / refill_per_sec));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+fn enabled() -> bool {
+    env::var("RATE_LIMIT").map(|value| value != "off").unwrap_or(true)
+}
+
+fn limiter() -> &'static RateLimiter {
+    static LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+    LIMITER.get_or_init(RateLimiter::from_env)
+}
+
+pub fn too_many_requests(retry_after: Duration) -> Response {
+    // Rounded up, so clients that wait as told are let in
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, secs.max(1).to_string())],
+        "Too many requests; slow down",
+    )
+        .into_response()
+}
+
+// Layered on the whole router, outside the per-route jwt_auth, so it reads the user from
+// the token itself
+pub async fn rate_limit(req: Request<Body>, next: Next) -> Response {
+    let path = req.uri().path();
+    if !enabled() || EXEMPT_PATHS.contains(&path) {
+        return next.run(req).await;
+    }
+    let key = token_subject(req.headers()).map(ClientKey::User).or_else(|| {
+        req.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(address)| ClientKey::Address(address.ip()))
+    });
+    // Without a user or an address there's nothing to count by
+    let Some(key) = key else {
+        return next.run(req).await;
+    };
+    match limiter().check(key, RouteGroup::of(req.method(), path), Instant::now()) {
+        Ok(()) => next.run(req).await,
+        Err(retry_after) => too_many_requests(retry_after),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_refill_at_the_budgeted_rate() {
+        let budgets = HashMap::from([
+            (RouteGroup::Read, Budget { requests: 3, per: Duration::from_secs(3) }),
+            (RouteGroup::Write, Budget { requests: 1, per: Duration::from_secs(10) }),
+        ]);
+        let limiter = RateLimiter::new(budgets);
+        let (user, other) = (ClientKey::User(Uuid::new_v4()), ClientKey::Address(IpAddr::from([203, 0, 113, 9])));
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check(user, RouteGroup::Read, start).is_ok());
+        }
+        let retry_after = limiter.check(user, RouteGroup::Read, start).unwrap_err();
+        assert!(retry_after <= Duration::from_secs(1));
+        // Other clients and other groups have their own budgets
+        assert!(limiter.check(other, RouteGroup::Read, start).is_ok());
+        assert!(limiter.check(user, RouteGroup::Write, start).is_ok());
+        assert!(limiter.check(user, RouteGroup::Write, start).is_err());
+
+        assert!(limiter.check(user, RouteGroup::Read, start + Duration::from_secs(1)).is_ok());
+        assert!(limiter.check(user, RouteGroup::Read, start + Duration::from_secs(1)).is_err());
+
+        assert_eq!(Budget::parse("300/60"), Some(Budget { requests: 300, per: Duration::from_secs(60) }));
+        assert_eq!(Budget::parse("0/60"), None);
+        assert_eq!(RouteGroup::of(&Method::POST, "/auth/login"), RouteGroup::Auth);
+        assert_eq!(RouteGroup::of(&Method::PUT, "/campaigns/1"), RouteGroup::Write);
+        assert_eq!(too_many_requests(Duration::from_millis(1500)).headers()[header::RETRY_AFTER], "2");
+    }
+}
@@ -0,0 +1,212 @@
+use axum::{http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::{DateTime, Utc};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::audit::{self, AuthEvent, ClientInfo};
+use crate::errors::db_error;
+use crate::handlers::LoginResponse;
+use crate::middleware::{access_token_ttl_secs, issue_token};
+
+// Refresh tokens: opaque random strings, stored only as SHA-256 hashes, that trade for a new
+// access token at POST /auth/refresh. Each one works once and is replaced by the next in its
+// family (the chain started by one login). Presenting a token that was already used means it
+// was copied, so the whole family is revoked and that login has to start over.
+
+pub const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn fresh_token() -> String {
+    let mut token = [0u8; 32];
+    OsRng.fill_bytes(&mut token);
+    hex::encode(token)
+}
+
+async fn store_refresh_token<'c, E>(executor: E, user_id: Uuid, family_id: Uuid) -> Result<(Uuid, String), sqlx::Error>
+where
+    E: sqlx::Executor<'c, Database = sqlx::Postgres>,
+{
+    let (id, token) = (Uuid::new_v4(), fresh_token());
+    sqlx::query(
+        "INSERT INTO refresh_tokens (id, user_id, family_id, token_hash, expires_at) VALUES ($1, $2, $3, $4, $5)"
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(family_id)
+    .bind(token_hash(&token))
+    .bind(Utc::now() + chrono::Duration::days(REFRESH_TOKEN_TTL_DAYS))
+    .execute(executor)
+    .await?;
+    Ok((id, token))
+}
+
+async fn token_pair(pool: &PgPool, user_id: Uuid, refresh_token: String) -> Result<LoginResponse, String> {
+    Ok(LoginResponse {
+        token: issue_token(pool, user_id).await?,
+        refresh_token,
+        expires_in: access_token_ttl_secs(),
+    })
+}
+
+// Access and refresh tokens for a new login, starting a new family
+pub async fn issue_tokens(pool: &PgPool, user_id: Uuid) -> Result<LoginResponse, String> {
+    let (_, refresh_token) = store_refresh_token(pool, user_id, Uuid::new_v4())
+        .await
+        .map_err(|e| format!("Failed to store refresh token: {}", e))?;
+    token_pair(pool, user_id, refresh_token).await
+}
+
+// Used by logging out everywhere and disabling an account
+pub async fn revoke_refresh_tokens(pool: &PgPool, user_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE refresh_tokens SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// Used by logging out; only the family the token belongs to, so other devices stay logged in
+pub async fn revoke_refresh_family(pool: &PgPool, user_id: Uuid, refresh_token: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE refresh_tokens SET revoked_at = COALESCE(revoked_at, NOW())
+         WHERE family_id = (SELECT family_id FROM refresh_tokens WHERE token_hash = $1 AND user_id = $2)"
+    )
+    .bind(token_hash(refresh_token))
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[derive(sqlx::FromRow)]
+struct StoredRefreshToken {
+    id: Uuid,
+    user_id: Uuid,
+    family_id: Uuid,
+    expires_at: DateTime<Utc>,
+    revoked_at: Option<DateTime<Utc>>,
+    replaced_by: Option<Uuid>,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+pub async fn refresh(
+    Extension(pool): Extension<PgPool>,
+    client: ClientInfo,
+    Json(payload): Json<RefreshRequest>,
+) -> impl IntoResponse {
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(e, "Failed to refresh token"),
+    };
+    let stored = sqlx::query_as::<_, StoredRefreshToken>(
+        "SELECT id, user_id, family_id, expires_at, revoked_at, replaced_by FROM refresh_tokens WHERE token_hash = $1 FOR UPDATE"
+    )
+    .bind(token_hash(&payload.refresh_token))
+    .fetch_optional(&mut *tx)
+    .await;
+    let stored = match stored {
+        Ok(Some(stored)) => stored,
+        Ok(None) => return (StatusCode::UNAUTHORIZED, "Invalid refresh token").into_response(),
+        Err(e) => return db_error(e, "Failed to refresh token"),
+    };
+
+    if stored.replaced_by.is_some() {
+        let revoked = sqlx::query("UPDATE refresh_tokens SET revoked_at = COALESCE(revoked_at, NOW()) WHERE family_id = $1")
+            .bind(stored.family_id)
+            .execute(&mut *tx)
+            .await;
+        if let Err(e) = revoked {
+            return db_error(e, "Failed to revoke refresh tokens");
+        }
+        if let Err(e) = tx.commit().await {
+            return db_error(e, "Failed to revoke refresh tokens");
+        }
+        audit::record(&pool, Some(stored.user_id), AuthEvent::RefreshTokenReused, &client, serde_json::json!({})).await;
+        return (StatusCode::UNAUTHORIZED, "Refresh token was already used; log in again").into_response();
+    }
+    if stored.revoked_at.is_some() || stored.expires_at < Utc::now() {
+        return (StatusCode::UNAUTHORIZED, "Refresh token expired or revoked").into_response();
+    }
+    let disabled = sqlx::query_scalar::<_, Option<DateTime<Utc>>>("SELECT disabled_at FROM users WHERE id = $1")
+        .bind(stored.user_id)
+        .fetch_one(&mut *tx)
+        .await;
+    match disabled {
+        Ok(None) => {}
+        Ok(Some(_)) => {
+            drop(tx);
+            return crate::moderation::refuse(&pool, stored.user_id).await;
+        }
+        Err(e) => return db_error(e, "Failed to refresh token"),
+    }
+
+    let rotated = async {
+        let (next_id, next_token) = store_refresh_token(&mut *tx, stored.user_id, stored.family_id).await?;
+        sqlx::query("UPDATE refresh_tokens SET revoked_at = NOW(), replaced_by = $1 WHERE id = $2")
+            .bind(next_id)
+            .bind(stored.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(next_token)
+    }
+    .await;
+    let next_token = match rotated {
+        Ok(next_token) => next_token,
+        Err(e) => return db_error(e, "Failed to refresh token"),
+    };
+
+    match token_pair(&pool, stored.user_id, next_token).await {
+        Ok(tokens) => {
+            audit::record(&pool, Some(stored.user_id), AuthEvent::TokenRefreshed, &client, serde_json::json!({})).await;
+            (StatusCode::OK, Json(tokens)).into_response()
+        }
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate token").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn refresh_with(pool: &PgPool, refresh_token: &str) -> (StatusCode, Option<LoginResponse>) {
+        let response = refresh(Extension(pool.clone()), ClientInfo::default(), Json(RefreshRequest { refresh_token: refresh_token.to_string() }))
+            .await
+            .into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).ok())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_tokens_rotate_and_detect_reuse() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+
+        let login = issue_tokens(&pool, f.player_id).await.unwrap();
+        let (status, rotated) = refresh_with(&pool, &login.refresh_token).await;
+        assert_eq!(status, StatusCode::OK);
+        let rotated = rotated.unwrap();
+        assert_ne!(rotated.refresh_token, login.refresh_token);
+        assert_eq!(rotated.expires_in, access_token_ttl_secs());
+
+        // Replaying the first token gives the theft away, and kills the token that replaced it
+        assert_eq!(refresh_with(&pool, &login.refresh_token).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(refresh_with(&pool, &rotated.refresh_token).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(refresh_with(&pool, "not-a-token").await.0, StatusCode::UNAUTHORIZED);
+
+        let other_login = issue_tokens(&pool, f.player_id).await.unwrap();
+        revoke_refresh_tokens(&pool, f.player_id).await.unwrap();
+        assert_eq!(refresh_with(&pool, &other_login.refresh_token).await.0, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,268 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::Utc;
+use futures::StreamExt;
+use redis::AsyncCommands;
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::middleware::{AuthUser, TokenId};
+use crate::refresh::{revoke_refresh_family, revoke_refresh_tokens};
+use crate::socket::{close_user_connections, SessionState};
+
+// Revoking a user's tokens (logging out everywhere, or an admin disabling the account) bumps
+// users.auth_epoch; jwt_auth refuses tokens carrying an older epoch. Every instance keeps the
+// epochs in memory, and open WebSockets are closed with a `SessionRevoked` close frame. With
+// Redis configured, revocations are announced over pub/sub so every instance hears of them.
+//
+// Logging out kills just the one access token instead: its id (jti) goes on a denylist until
+// the token would have expired anyway, kept in Redis when configured so every instance sees it.
+
+pub const REVOCATION_CHANNEL: &str = "yoda:revocations";
+
+// How long the listener waits before resubscribing after losing Redis
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+// Current auth epoch of every user whose tokens were ever revoked; everyone else is at 0
+#[derive(Clone, Default)]
+pub struct Revocations(Arc<RwLock<HashMap<Uuid, i32>>>);
+
+impl Revocations {
+    // Called at startup, so tokens revoked before a restart stay revoked
+    pub async fn load(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let epochs = sqlx::query_as::<_, (Uuid, i32)>("SELECT id, auth_epoch FROM users WHERE auth_epoch > 0")
+            .fetch_all(pool)
+            .await?;
+        let mut current = self.0.write().await;
+        for (user_id, epoch) in epochs {
+            current.insert(user_id, epoch);
+        }
+        Ok(())
+    }
+
+    pub async fn is_revoked(&self, user_id: Uuid, epoch: i32) -> bool {
+        self.0.read().await.get(&user_id).is_some_and(|current| epoch < *current)
+    }
+
+    // Epochs only move forward, so announcements arriving out of order are harmless
+    async fn advance(&self, user_id: Uuid, epoch: i32) {
+        let mut current = self.0.write().await;
+        let entry = current.entry(user_id).or_insert(epoch);
+        *entry = (*entry).max(epoch);
+    }
+}
+
+fn denied_key(jti: &str) -> String {
+    format!("yoda:denied-token:{}", jti)
+}
+
+// Ids of logged-out access tokens, with their expiry, that this instance knows of
+#[derive(Clone, Default)]
+pub struct Denylist(Arc<RwLock<HashMap<String, usize>>>);
+
+pub async fn deny_token(session_state: &SessionState, jti: &str, exp: usize) {
+    let now = Utc::now().timestamp().max(0) as usize;
+    {
+        let mut denied = session_state.denylist.0.write().await;
+        denied.retain(|_, exp| *exp > now);
+        denied.insert(jti.to_string(), exp);
+    }
+    if let Some(redis) = &session_state.redis {
+        let mut conn = redis.clone();
+        let ttl = exp.saturating_sub(now).max(1) as u64;
+        if let Err(e) = conn.set_ex::<_, _, ()>(denied_key(jti), 1, ttl).await {
+            tracing::error!(error = %e, "Failed to store denied token in Redis");
+        }
+    }
+}
+
+// Redis being unreachable doesn't log everyone out; only the local list is checked then
+pub async fn is_token_denied(session_state: &SessionState, jti: &str) -> bool {
+    if session_state.denylist.0.read().await.contains_key(jti) {
+        return true;
+    }
+    if let Some(redis) = &session_state.redis {
+        let mut conn = redis.clone();
+        match conn.exists::<_, bool>(denied_key(jti)).await {
+            Ok(denied) => return denied,
+            Err(e) => tracing::error!(error = %e, "Failed to check denied token in Redis"),
+        }
+    }
+    false
+}
+
+#[derive(Deserialize, Default)]
+pub struct LogoutRequest {
+    // Revoked along with the rest of its family, so it can't mint new access tokens
+    pub refresh_token: Option<String>,
+}
+
+// Logs out this device only: the access token the request came with stops working
+pub async fn logout(
+    Extension(pool): Extension<PgPool>,
+    Extension(session_state): Extension<SessionState>,
+    Extension(user): Extension<AuthUser>,
+    token: Option<Extension<TokenId>>,
+    payload: Option<Json<LogoutRequest>>,
+) -> impl IntoResponse {
+    let Json(payload) = payload.unwrap_or_default();
+    if let Some(refresh_token) = &payload.refresh_token {
+        if let Err(e) = revoke_refresh_family(&pool, user.0, refresh_token).await {
+            return db_error(e, "Failed to revoke refresh token");
+        }
+    }
+    if let Some(Extension(token)) = token {
+        deny_token(&session_state, &token.jti, token.exp).await;
+    }
+    (StatusCode::OK, "Logged out").into_response()
+}
+
+// Revokes every token the user holds, refresh tokens included, and closes their sockets
+pub async fn revoke_user(pool: &PgPool, session_state: &SessionState, user_id: Uuid) -> Result<(), sqlx::Error> {
+    revoke_refresh_tokens(pool, user_id).await?;
+    let epoch = sqlx::query_scalar::<_, i32>("UPDATE users SET auth_epoch = auth_epoch + 1 WHERE id = $1 RETURNING auth_epoch")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+    announce(session_state, user_id, epoch).await;
+    Ok(())
+}
+
+// Applies the revocation here straight away, since Redis may be missing or down, then tells
+// the other instances
+async fn announce(session_state: &SessionState, user_id: Uuid, epoch: i32) {
+    apply(session_state, user_id, epoch).await;
+    if let Some(redis) = &session_state.redis {
+        let mut conn = redis.clone();
+        if let Err(e) = conn.publish::<_, _, ()>(REVOCATION_CHANNEL, format!("{}:{}", user_id, epoch)).await {
+            tracing::error!(error = %e, "Failed to announce token revocation in Redis");
+        }
+    }
+}
+
+async fn apply(session_state: &SessionState, user_id: Uuid, epoch: i32) {
+    session_state.revocations.advance(user_id, epoch).await;
+    close_user_connections(session_state, user_id).await;
+}
+
+// Applies revocations announced by other instances (and this one, which is a no-op)
+pub fn spawn_revocation_listener(client: redis::Client, session_state: SessionState) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = listen(&client, &session_state).await {
+                tracing::warn!(error = %e, "Token revocation listener lost Redis");
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    });
+}
+
+async fn listen(client: &redis::Client, session_state: &SessionState) -> redis::RedisResult<()> {
+    let mut pubsub = client.get_async_connection().await?.into_pubsub();
+    pubsub.subscribe(REVOCATION_CHANNEL).await?;
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let payload: String = message.get_payload()?;
+        match parse_announcement(&payload) {
+            Some((user_id, epoch)) => apply(session_state, user_id, epoch).await,
+            None => tracing::warn!(%payload, "Ignoring malformed token revocation"),
+        }
+    }
+    Ok(())
+}
+
+fn parse_announcement(payload: &str) -> Option<(Uuid, i32)> {
+    let (user_id, epoch) = payload.split_once(':')?;
+    Some((Uuid::parse_str(user_id).ok()?, epoch.parse().ok()?))
+}
+
+// Logs the user out on every device, including open WebSockets
+pub async fn logout_all(
+    Extension(pool): Extension<PgPool>,
+    Extension(session_state): Extension<SessionState>,
+    Extension(user): Extension<AuthUser>,
+) -> impl IntoResponse {
+    match revoke_user(&pool, &session_state, user.0).await {
+        Ok(()) => (StatusCode::OK, "Logged out everywhere").into_response(),
+        Err(e) => db_error(e, "Failed to revoke tokens"),
+    }
+}
+
+// Admin only (see require_role); the user can no longer log in and is logged out everywhere
+pub async fn disable_user(
+    Extension(pool): Extension<PgPool>,
+    Extension(session_state): Extension<SessionState>,
+    Path(user_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let epoch = sqlx::query_scalar::<_, i32>(
+        "UPDATE users SET disabled_at = COALESCE(disabled_at, NOW()), auth_epoch = auth_epoch + 1 WHERE id = $1 RETURNING auth_epoch"
+    )
+    .bind(user_id)
+    .fetch_optional(&pool)
+    .await;
+    match epoch {
+        Ok(Some(epoch)) => {
+            announce(&session_state, user_id, epoch).await;
+            if let Err(e) = revoke_refresh_tokens(&pool, user_id).await {
+                return db_error(e, "Failed to revoke refresh tokens");
+            }
+            (StatusCode::OK, "Account disabled").into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Err(e) => db_error(e, "Failed to disable account"),
+    }
+}
+
+// Admin only (see require_role); lets a disabled user log in again
+pub async fn enable_user(
+    Extension(pool): Extension<PgPool>,
+    Path(user_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let res = sqlx::query("UPDATE users SET disabled_at = NULL WHERE id = $1").bind(user_id).execute(&pool).await;
+    match res {
+        Ok(done) if done.rows_affected() == 0 => (StatusCode::NOT_FOUND, "User not found").into_response(),
+        Ok(_) => (StatusCode::OK, "Account enabled").into_response(),
+        Err(e) => db_error(e, "Failed to enable account"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_denied_tokens_are_refused_until_they_expire() {
+        let state = SessionState::new(None);
+        let exp = (Utc::now() + chrono::Duration::minutes(15)).timestamp() as usize;
+        assert!(!is_token_denied(&state, "token-a").await);
+        deny_token(&state, "token-a", exp).await;
+        assert!(is_token_denied(&state, "token-a").await);
+        assert!(!is_token_denied(&state, "token-b").await);
+
+        // Expired entries are dropped as new ones arrive
+        deny_token(&state, "token-old", 1).await;
+        deny_token(&state, "token-b", exp).await;
+        assert!(!is_token_denied(&state, "token-old").await);
+    }
+
+    #[tokio::test]
+    async fn test_only_tokens_from_older_epochs_are_revoked() {
+        let revocations = Revocations::default();
+        let (user_id, other) = (Uuid::new_v4(), Uuid::new_v4());
+        assert!(!revocations.is_revoked(user_id, 0).await);
+
+        revocations.advance(user_id, 2).await;
+        revocations.advance(user_id, 1).await;
+        assert!(revocations.is_revoked(user_id, 0).await);
+        assert!(revocations.is_revoked(user_id, 1).await);
+        assert!(!revocations.is_revoked(user_id, 2).await);
+        assert!(!revocations.is_revoked(other, 0).await);
+
+        assert_eq!(parse_announcement(&format!("{}:3", user_id)), Some((user_id, 3)));
+        assert_eq!(parse_announcement("not-a-user:3"), None);
+    }
+}
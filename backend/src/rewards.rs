@@ -0,0 +1,527 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use sqlx::{PgConnection, PgPool};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+use crate::dice::{fresh_seed, roll_with, DiceRng};
+use crate::errors::db_error;
+use crate::handlers::CharacterResponse;
+use crate::history::record_revision;
+use crate::middleware::AuthUser;
+use crate::models::{Character, EncounterReward, GameState, InitiativeEntry, LootItem};
+use crate::scope::{authorize, Access, Permission, Resource};
+
+// Encounter rewards. When combat ends, the defeated monsters' challenge ratings give an XP
+// total that is split across the party, and treasure is rolled for the highest CR. The DM
+// accepts the suggestion (awarding the XP and adding the treasure to the campaign's loot
+// pool) or dismisses it.
+
+// XP for CR 0 to 30; the fractional CRs are handled in xp_for_cr
+const XP_BY_CR: [i32; 31] = [
+    10, 200, 450, 700, 1100, 1800, 2300, 2900, 3900, 5000, 5900, 7200, 8400, 10000, 11500, 13000,
+    15000, 18000, 20000, 22000, 25000, 33000, 41000, 50000, 62000, 75000, 90000, 105000, 120000, 135000, 155000,
+];
+
+pub fn xp_for_cr(cr: f64) -> Option<i32> {
+    match cr {
+        0.125 => Some(25),
+        0.25 => Some(50),
+        0.5 => Some(100),
+        cr if cr.fract() == 0.0 && (0.0..=30.0).contains(&cr) => Some(XP_BY_CR[cr as usize]),
+        _ => None,
+    }
+}
+
+// 5, 0.25 or "1/4"; None unless it's a real challenge rating
+pub fn parse_cr(value: &Value) -> Option<f64> {
+    let cr = match value {
+        Value::Number(n) => n.as_f64()?,
+        Value::String(s) => match s.trim().split_once('/') {
+            Some((num, den)) => num.trim().parse::<f64>().ok()? / den.trim().parse::<f64>().ok()?,
+            None => s.trim().parse().ok()?,
+        },
+        _ => return None,
+    };
+    xp_for_cr(cr).map(|_| cr)
+}
+
+pub fn deserialize_cr<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => parse_cr(&value).map(Some).ok_or_else(|| serde::de::Error::custom("invalid challenge rating")),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DefeatedCreature {
+    pub name: String,
+    // Null when neither the initiative entry nor the compendium knows it; worth no XP
+    pub cr: Option<f64>,
+    pub xp: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct XpShare {
+    pub character_id: Uuid,
+    pub name: String,
+    pub xp: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LootDrop {
+    pub name: String,
+    pub quantity: i32,
+}
+
+// A compendium entry of kind "treasure_table", e.g.
+// {"cr_min": 0, "cr_max": 4, "coins": {"gp": "2d6*10"}, "items": [{"name": "Potion of Healing", "chance": 25, "quantity": "1d4"}]}
+#[derive(Debug, Deserialize)]
+pub struct TreasureTable {
+    #[serde(default)]
+    pub cr_min: f64,
+    #[serde(default = "max_cr")]
+    pub cr_max: f64,
+    // Denomination to a dice expression, optionally multiplied
+    #[serde(default)]
+    pub coins: BTreeMap<String, String>,
+    #[serde(default)]
+    pub items: Vec<TreasureItem>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TreasureItem {
+    pub name: String,
+    // Percent chance the item turns up
+    #[serde(default = "always")]
+    pub chance: u32,
+    // Dice expression; one when omitted
+    pub quantity: Option<String>,
+}
+
+fn max_cr() -> f64 {
+    30.0
+}
+
+fn always() -> u32 {
+    100
+}
+
+// Hoard coins by CR band, for campaigns without treasure tables of their own
+fn default_tables() -> Vec<TreasureTable> {
+    serde_json::from_value(serde_json::json!([
+        {"cr_min": 0, "cr_max": 4, "coins": {"cp": "6d6*100", "sp": "3d6*100", "gp": "2d6*10"}},
+        {"cr_min": 5, "cr_max": 10, "coins": {"cp": "2d6*100", "sp": "2d6*1000", "gp": "6d6*100", "pp": "3d6*10"}},
+        {"cr_min": 11, "cr_max": 16, "coins": {"gp": "4d6*1000", "pp": "5d6*100"}},
+        {"cr_min": 17, "cr_max": 30, "coins": {"gp": "12d6*1000", "pp": "8d6*1000"}}
+    ]))
+    .expect("default treasure tables are valid")
+}
+
+// "4d6*10": the dice total times the multiplier
+fn roll_amount<R: Rng + ?Sized>(expression: &str, rng: &mut R) -> Result<i32, String> {
+    let (dice, multiplier) = match expression.rsplit_once('*') {
+        Some((dice, multiplier)) => (dice, multiplier.trim().parse::<i32>().map_err(|_| format!("Invalid multiplier in {}", expression))?),
+        None => (expression, 1),
+    };
+    Ok(roll_with(dice, rng)?.total.max(0).saturating_mul(multiplier))
+}
+
+// Rolls on the first table covering `cr`, the campaign's own tables before the defaults
+pub fn roll_treasure<R: Rng + ?Sized>(tables: &[TreasureTable], cr: f64, rng: &mut R) -> Vec<LootDrop> {
+    let defaults = default_tables();
+    let Some(table) = tables.iter().chain(&defaults).find(|table| table.cr_min <= cr && cr <= table.cr_max) else {
+        return Vec::new();
+    };
+
+    let mut loot = Vec::new();
+    for (denomination, expression) in &table.coins {
+        match roll_amount(expression, rng) {
+            Ok(quantity) if quantity > 0 => loot.push(LootDrop { name: denomination.clone(), quantity }),
+            Ok(_) => {}
+            Err(e) => tracing::warn!(error = %e, %denomination, "Skipping treasure coins"),
+        }
+    }
+    for item in &table.items {
+        if rng.gen_range(1..=100) > item.chance {
+            continue;
+        }
+        let quantity = match item.quantity.as_deref().map(|expression| roll_amount(expression, rng)) {
+            None => 1,
+            Some(Ok(quantity)) => quantity,
+            Some(Err(e)) => {
+                tracing::warn!(error = %e, item = %item.name, "Skipping treasure item");
+                continue;
+            }
+        };
+        if quantity > 0 {
+            loot.push(LootDrop { name: item.name.clone(), quantity });
+        }
+    }
+    loot
+}
+
+// Even shares; the first characters take one extra XP each until the remainder is used up
+pub fn split_xp(total: i32, party: &[(Uuid, String)]) -> Vec<XpShare> {
+    if party.is_empty() {
+        return Vec::new();
+    }
+    let count = party.len() as i32;
+    party
+        .iter()
+        .enumerate()
+        .map(|(i, (character_id, name))| XpShare {
+            character_id: *character_id,
+            name: name.clone(),
+            xp: total / count + i32::from((i as i32) < total % count),
+        })
+        .collect()
+}
+
+// "Goblin 2" is looked up as "Goblin"
+fn base_name(name: &str) -> &str {
+    let name = name.trim();
+    match name.rsplit_once(' ') {
+        Some((base, number)) if number.chars().all(|c| c.is_ascii_digit()) => base.trim_end(),
+        _ => name,
+    }
+}
+
+async fn compendium_cr(conn: &mut PgConnection, campaign_id: Uuid, name: &str) -> Result<Option<f64>, sqlx::Error> {
+    let cr = sqlx::query_scalar::<_, Option<Value>>(
+        "SELECT COALESCE(data->'cr', data->'challenge_rating') FROM compendium_entries
+         WHERE campaign_id = $1 AND kind = 'monster' AND LOWER(name) = LOWER($2)
+         ORDER BY updated_at DESC LIMIT 1"
+    )
+    .bind(campaign_id)
+    .bind(base_name(name))
+    .fetch_optional(conn)
+    .await?;
+    Ok(cr.flatten().as_ref().and_then(parse_cr))
+}
+
+// The player characters in the fight, or every player character when none were tracked
+async fn party(conn: &mut PgConnection, campaign_id: Uuid, initiative_order: &[InitiativeEntry]) -> Result<Vec<(Uuid, String)>, sqlx::Error> {
+    let ids: Vec<Uuid> = initiative_order.iter().filter(|entry| entry.is_player).filter_map(|entry| entry.character_id).collect();
+    let party = sqlx::query_as::<_, (Uuid, String)>(
+        "SELECT id, name FROM characters WHERE campaign_id = $1 AND id = ANY($2) ORDER BY name, id"
+    )
+    .bind(campaign_id)
+    .bind(&ids)
+    .fetch_all(&mut *conn)
+    .await?;
+    if !party.is_empty() {
+        return Ok(party);
+    }
+    sqlx::query_as::<_, (Uuid, String)>(
+        "SELECT id, name FROM characters WHERE campaign_id = $1 AND player_id IS NOT NULL ORDER BY name, id"
+    )
+    .bind(campaign_id)
+    .fetch_all(conn)
+    .await
+}
+
+// Stores the suggestion for an encounter that just ended. Monsters at 0 HP or below count
+// as defeated; None when there are none.
+pub async fn suggest_rewards(conn: &mut PgConnection, session_id: Uuid, combat_id: Uuid, campaign_id: Uuid, game_state: &GameState) -> Result<Option<EncounterReward>, sqlx::Error> {
+    let mut defeated = Vec::new();
+    for entry in game_state.initiative_order.iter().filter(|entry| !entry.is_player && entry.hp_current.is_some_and(|hp| hp <= 0)) {
+        let cr = match entry.cr {
+            Some(cr) => Some(cr),
+            None => compendium_cr(conn, campaign_id, &entry.name).await?,
+        };
+        defeated.push(DefeatedCreature { name: entry.name.clone(), cr, xp: cr.and_then(xp_for_cr).unwrap_or(0) });
+    }
+    if defeated.is_empty() {
+        return Ok(None);
+    }
+
+    let xp_total = defeated.iter().map(|creature| creature.xp).sum();
+    let xp_split = split_xp(xp_total, &party(conn, campaign_id, &game_state.initiative_order).await?);
+
+    let tables: Vec<TreasureTable> = sqlx::query_scalar::<_, Value>(
+        "SELECT data FROM compendium_entries WHERE campaign_id = $1 AND kind = 'treasure_table' ORDER BY name"
+    )
+    .bind(campaign_id)
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .filter_map(|data| serde_json::from_value(data).ok())
+    .collect();
+    let highest_cr = defeated.iter().filter_map(|creature| creature.cr).reduce(f64::max);
+    let loot = highest_cr.map_or_else(Vec::new, |cr| roll_treasure(&tables, cr, &mut DiceRng::seed_from_u64(fresh_seed())));
+
+    let reward = sqlx::query_as::<_, EncounterReward>(
+        "INSERT INTO encounter_rewards (session_id, combat_id, campaign_id, xp_total, defeated, xp_split, loot)
+         VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *"
+    )
+    .bind(session_id)
+    .bind(combat_id)
+    .bind(campaign_id)
+    .bind(xp_total)
+    .bind(serde_json::json!(defeated))
+    .bind(serde_json::json!(xp_split))
+    .bind(serde_json::json!(loot))
+    .fetch_one(conn)
+    .await?;
+    Ok(Some(reward))
+}
+
+pub async fn list_session_rewards(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(session_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize(&pool, user.0, Resource::Session(session_id), Access::Permitted(Permission::AwardXp), "Only the DM can see encounter rewards").await {
+        return response;
+    }
+
+    let rewards = sqlx::query_as::<_, EncounterReward>("SELECT * FROM encounter_rewards WHERE session_id = $1 ORDER BY created_at DESC")
+        .bind(session_id)
+        .fetch_all(&pool)
+        .await;
+
+    match rewards {
+        Ok(rewards) => Json(rewards).into_response(),
+        Err(e) => db_error(e, "Failed to fetch encounter rewards"),
+    }
+}
+
+// The DM of the reward's session or a player allowed to award XP, or the response refusing them
+async fn authorize_reward(pool: &PgPool, user_id: Uuid, reward_id: Uuid) -> Result<(), axum::response::Response> {
+    let session_id = sqlx::query_scalar::<_, Uuid>("SELECT session_id FROM encounter_rewards WHERE id = $1")
+        .bind(reward_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| db_error(e, "Failed to fetch encounter rewards"))?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Rewards not found").into_response())?;
+    authorize(pool, user_id, Resource::Session(session_id), Access::Permitted(Permission::AwardXp), "Only the DM can award XP and loot").await?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct AcceptedRewards {
+    pub reward: EncounterReward,
+    pub characters: Vec<CharacterResponse>,
+    pub loot: Vec<LootItem>,
+}
+
+pub async fn accept_rewards(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(reward_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize_reward(&pool, user.0, reward_id).await {
+        return response;
+    }
+
+    let mut tx = match pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return db_error(e, "Failed to award rewards"),
+    };
+
+    // Only a pending suggestion can be accepted, so a double click awards once
+    let reward = sqlx::query_as::<_, EncounterReward>(
+        "UPDATE encounter_rewards SET status = 'accepted', resolved_at = NOW() WHERE id = $1 AND status = 'pending' RETURNING *"
+    )
+    .bind(reward_id)
+    .fetch_optional(&mut *tx)
+    .await;
+    let reward = match reward {
+        Ok(Some(reward)) => reward,
+        Ok(None) => return (StatusCode::CONFLICT, "Rewards were already resolved").into_response(),
+        Err(e) => return db_error(e, "Failed to award rewards"),
+    };
+
+    let xp_split: Vec<XpShare> = serde_json::from_value(reward.xp_split.clone()).unwrap_or_default();
+    let mut characters = Vec::new();
+    for share in &xp_split {
+        let character = sqlx::query_as::<_, Character>(
+            "UPDATE characters SET xp = xp + $1, updated_at = NOW() WHERE id = $2 AND campaign_id = $3 RETURNING *"
+        )
+        .bind(share.xp)
+        .bind(share.character_id)
+        .bind(reward.campaign_id)
+        .fetch_optional(&mut *tx)
+        .await;
+        match character {
+            Ok(Some(character)) => characters.push(character),
+            // Deleted since the fight
+            Ok(None) => {}
+            Err(e) => return db_error(e, "Failed to award XP"),
+        }
+    }
+
+    let drops: Vec<LootDrop> = serde_json::from_value(reward.loot.clone()).unwrap_or_default();
+    let mut loot = Vec::new();
+    for drop in drops {
+        let item = sqlx::query_as::<_, LootItem>(
+            "INSERT INTO loot_items (campaign_id, reward_id, name, quantity) VALUES ($1, $2, $3, $4) RETURNING *"
+        )
+        .bind(reward.campaign_id)
+        .bind(reward.id)
+        .bind(&drop.name)
+        .bind(drop.quantity)
+        .fetch_one(&mut *tx)
+        .await;
+        match item {
+            Ok(item) => loot.push(item),
+            Err(e) => return db_error(e, "Failed to add loot"),
+        }
+    }
+
+    if let Err(e) = tx.commit().await {
+        return db_error(e, "Failed to award rewards");
+    }
+
+    for character in &characters {
+        record_revision(&pool, character, Some(reward.session_id), user.0).await;
+    }
+    let characters = characters.into_iter().map(CharacterResponse::from).collect();
+    Json(AcceptedRewards { reward, characters, loot }).into_response()
+}
+
+pub async fn dismiss_rewards(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Path(reward_id): Path<Uuid>,
+) -> impl IntoResponse {
+    if let Err(response) = authorize_reward(&pool, user.0, reward_id).await {
+        return response;
+    }
+
+    let reward = sqlx::query_as::<_, EncounterReward>(
+        "UPDATE encounter_rewards SET status = 'dismissed', resolved_at = NOW() WHERE id = $1 AND status = 'pending' RETURNING *"
+    )
+    .bind(reward_id)
+    .fetch_optional(&pool)
+    .await;
+
+    match reward {
+        Ok(Some(reward)) => Json(reward).into_response(),
+        Ok(None) => (StatusCode::CONFLICT, "Rewards were already resolved").into_response(),
+        Err(e) => db_error(e, "Failed to dismiss rewards"),
+    }
+}
+
+pub async fn list_loot(
+    Extension(pool): Extension<PgPool>,
+    Path(campaign_id): Path<Uuid>,
+) -> impl IntoResponse {
+    let loot = sqlx::query_as::<_, LootItem>("SELECT * FROM loot_items WHERE campaign_id = $1 ORDER BY created_at, name")
+        .bind(campaign_id)
+        .fetch_all(&pool)
+        .await;
+
+    match loot {
+        Ok(loot) => Json(loot).into_response(),
+        Err(e) => db_error(e, "Failed to fetch loot"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::handlers::{update_initiative, UpdateInitiativeRequest};
+    use crate::test_support::{campaign_fixture, test_pool};
+
+    #[test]
+    fn test_xp_and_treasure() {
+        assert_eq!([0.0, 0.125, 0.25, 0.5, 1.0, 5.0, 30.0].map(xp_for_cr), [10, 25, 50, 100, 200, 1800, 155000].map(Some));
+        assert_eq!(xp_for_cr(0.3), None);
+        assert_eq!(parse_cr(&json!("1/4")), Some(0.25));
+        assert_eq!(parse_cr(&json!(" 3 ")), Some(3.0));
+        assert_eq!(parse_cr(&json!(0.5)), Some(0.5));
+        assert_eq!(parse_cr(&json!("1/0")), None);
+        assert_eq!(base_name("Goblin 12"), "Goblin");
+        assert_eq!(base_name("Young Red Dragon"), "Young Red Dragon");
+
+        let party = [(Uuid::new_v4(), "Ash".to_string()), (Uuid::new_v4(), "Bo".to_string()), (Uuid::new_v4(), "Cy".to_string())];
+        assert_eq!(split_xp(700, &party).iter().map(|share| share.xp).collect::<Vec<_>>(), [234, 233, 233]);
+        assert!(split_xp(700, &[]).is_empty());
+
+        let tables: Vec<TreasureTable> = serde_json::from_value(json!([
+            {"cr_min": 0, "cr_max": 2, "coins": {"gp": "3*10", "sp": "0"}, "items": [{"name": "Rope", "quantity": "2"}, {"name": "Crown", "chance": 0}]}
+        ]))
+        .unwrap();
+        let mut rng = DiceRng::seed_from_u64(7);
+        let loot = roll_treasure(&tables, 1.0, &mut rng);
+        assert_eq!(loot, vec![LootDrop { name: "gp".to_string(), quantity: 30 }, LootDrop { name: "Rope".to_string(), quantity: 2 }]);
+        // Past the campaign's tables the default hoard coins apply
+        let hoard = roll_treasure(&tables, 12.0, &mut rng);
+        assert_eq!(hoard.iter().map(|drop| drop.name.as_str()).collect::<Vec<_>>(), ["gp", "pp"]);
+        assert!(hoard[0].quantity >= 4000 && hoard[0].quantity <= 24000);
+    }
+
+    #[tokio::test]
+    async fn test_end_of_combat_rewards_are_accepted_once() {
+        let pool = test_pool().await;
+        let f = campaign_fixture(&pool).await;
+
+        sqlx::query(
+            "INSERT INTO compendium_entries (campaign_id, source, slug, kind, name, data) VALUES
+             ($1, 'srd', 'goblin', 'monster', 'Goblin', '{\"cr\": \"1/4\"}'),
+             ($1, 'homebrew', 'lair-hoard', 'treasure_table', 'Lair hoard', $2)"
+        )
+        .bind(f.campaign_id)
+        .bind(json!({"cr_min": 0, "cr_max": 4, "coins": {"gp": "25"}, "items": [{"name": "Potion of Healing", "quantity": "2"}]}))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let combatant = |name: &str, is_player: bool, hp: i32, cr: Option<f64>| {
+            serde_json::from_value::<InitiativeEntry>(json!({
+                "id": Uuid::new_v4(), "name": name, "initiative": 10, "is_player": is_player,
+                "character_id": if is_player { Some(f.character_id) } else { None }, "user_id": null,
+                "hp_current": hp, "hp_max": 10, "ac": 12, "cr": cr,
+            }))
+            .unwrap()
+        };
+        let initiative_order = vec![
+            combatant("Wren", true, 3, None),
+            combatant("Goblin 1", false, 0, None),
+            combatant("Ogre", false, -4, Some(2.0)),
+            combatant("Wolf", false, 5, Some(0.25)),
+        ];
+        let request = |combat_active| UpdateInitiativeRequest {
+            session_id: f.session_id,
+            initiative_order: initiative_order.clone(),
+            current_turn: None,
+            round: None,
+            combat_active: Some(combat_active),
+        };
+        for combat_active in [true, false] {
+            let response = update_initiative(Extension(pool.clone()), Extension(AuthUser(f.dm_id)), Json(request(combat_active))).await;
+            assert_eq!(response.into_response().status(), StatusCode::OK);
+        }
+
+        let reward = sqlx::query_as::<_, EncounterReward>("SELECT * FROM encounter_rewards WHERE session_id = $1")
+            .bind(f.session_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(reward.status, "pending");
+        assert_eq!(reward.xp_total, 500);
+        assert_eq!(reward.defeated, json!([{"name": "Goblin 1", "cr": 0.25, "xp": 50}, {"name": "Ogre", "cr": 2.0, "xp": 450}]));
+        assert_eq!(reward.xp_split, json!([{"character_id": f.character_id, "name": "Wren", "xp": 500}]));
+        assert_eq!(reward.loot, json!([{"name": "gp", "quantity": 25}, {"name": "Potion of Healing", "quantity": 2}]));
+
+        let response = accept_rewards(Extension(pool.clone()), Extension(AuthUser(f.player_id)), Path(reward.id)).await;
+        assert_eq!(response.into_response().status(), StatusCode::FORBIDDEN);
+        let response = accept_rewards(Extension(pool.clone()), Extension(AuthUser(f.dm_id)), Path(reward.id)).await;
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+        let response = accept_rewards(Extension(pool.clone()), Extension(AuthUser(f.dm_id)), Path(reward.id)).await;
+        assert_eq!(response.into_response().status(), StatusCode::CONFLICT);
+
+        let xp = sqlx::query_scalar::<_, i32>("SELECT xp FROM characters WHERE id = $1").bind(f.character_id).fetch_one(&pool).await.unwrap();
+        assert_eq!(xp, 500);
+        let loot = sqlx::query_as::<_, (String, i32)>("SELECT name, quantity FROM loot_items WHERE campaign_id = $1 ORDER BY name")
+            .bind(f.campaign_id)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(loot, vec![("Potion of Healing".to_string(), 2), ("gp".to_string(), 25)]);
+    }
+}
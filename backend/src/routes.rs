@@ -0,0 +1,451 @@
+use axum::{routing::{get, post, put, delete}, Router, http::StatusCode, response::IntoResponse, extract::Extension};
+use sqlx::PgPool;
+use tower_http::trace::TraceLayer;
+use crate::middleware::{jwt_auth, refuse_impersonation, require_role, AuthUser, RequiredRole, Role};
+use crate::socket::{SessionState, ws_handler};
+use crate::storage::SharedStorage;
+use crate::{api, avatar, calendar, chronicle, cloning, combat, content, demo, generators, handlers, history, impersonation, invites, locations, magic_link, membership, metrics, moderation, notes, notifications, ownership, compendium, audit, oauth, preferences, printable, profile, rate_limit, refresh, revocation, rewards, search, sse, storage, templates, usage, verification};
+
+async fn health_check() -> impl IntoResponse {
+    (StatusCode::OK, "OK")
+}
+
+async fn protected_route(Extension(user): Extension<AuthUser>) -> impl IntoResponse {
+    (StatusCode::OK, format!("User ID: {}", user.0))
+}
+
+// The full application router; main serves it and the tests below drive it over HTTP
+pub fn router(pool: PgPool, session_state: SessionState, storage: SharedStorage) -> Router {
+    Router::new()
+        .route("/ws", get(ws_handler))
+        .route("/auth/register", axum::routing::post(handlers::register))
+        .route("/auth/login", axum::routing::post(handlers::login))
+        .route("/auth/available", get(handlers::check_availability))
+        .route("/auth/refresh", post(refresh::refresh))
+        .route("/auth/verify", post(verification::verify_email))
+        .route("/auth/verify/resend", post(verification::resend_verification))
+        .route("/auth/magic-link", post(magic_link::request_magic_link))
+        .route("/auth/magic-link/verify", post(magic_link::login_with_magic_link))
+        .route("/demo", post(demo::create_demo))
+        .route("/auth/oauth/:provider", get(oauth::oauth_start))
+        .route("/auth/oauth/:provider/callback", get(oauth::oauth_callback))
+        .route(
+            "/users/me",
+            get(profile::get_me)
+                .merge(put(profile::update_me).delete(profile::delete_me).route_layer(axum::middleware::from_fn(refuse_impersonation)))
+                .route_layer(axum::middleware::from_fn(jwt_auth)),
+        )
+        .route("/users/me/preferences", get(preferences::get_preferences).patch(preferences::update_preferences).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/users/me/activity", get(audit::list_my_activity).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route(
+            "/users/me/avatar",
+            post(avatar::upload_avatar)
+                .route_layer(axum::middleware::from_fn(jwt_auth))
+                // Room for the multipart framing around the image
+                .layer(axum::extract::DefaultBodyLimit::max(avatar::MAX_AVATAR_BYTES + 64 * 1024)),
+        )
+        // Public looking-for-group board
+        .route("/lfg", get(membership::list_lfg))
+        // Public, for <img> tags
+        .route("/users/:id/avatar", get(avatar::get_avatar))
+        .route("/users/:id", get(profile::get_profile).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/auth/change-password", post(handlers::change_password).route_layer(axum::middleware::from_fn(refuse_impersonation)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/auth/logout", post(revocation::logout).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/auth/logout-all", post(revocation::logout_all).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/protected", get(protected_route).route_layer(axum::middleware::from_fn(jwt_auth)))
+        // Campaign routes (protected)
+        .route("/campaigns", get(handlers::list_campaigns).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns", post(handlers::create_campaign).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/from-template", post(templates::create_from_template).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaign-templates", get(templates::list_templates).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/join", post(invites::join_campaign).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/search", get(search::search_campaigns).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/discover", get(membership::discover_campaigns).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id", get(handlers::get_campaign).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id", put(handlers::update_campaign).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can update campaigns"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id", delete(handlers::delete_campaign).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Owner, "Only the DM can delete campaigns"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/hooks/generate", post(handlers::generate_plot_hooks).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can generate plot hooks"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/hooks/accept", post(handlers::accept_plot_hook).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can accept plot hooks"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route(
+            "/campaigns/:id/chronicle",
+            get(chronicle::get_chronicle).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Member, "Access denied to this campaign"), require_role))
+                .merge(post(chronicle::generate_chronicle).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can write the chronicle"), require_role)))
+                .route_layer(axum::middleware::from_fn(jwt_auth)),
+        )
+        .route("/campaigns/:id/archive", put(handlers::archive_campaign).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Owner, "Only the DM can archive campaigns"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/restore", post(handlers::restore_campaign).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/template", put(templates::mark_template).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Owner, "Only the DM can share a campaign as a template"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route(
+            "/campaigns/:id/transfer",
+            post(ownership::offer_transfer).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Owner, "Only the DM can transfer the campaign"), require_role))
+                .merge(delete(ownership::cancel_transfer))
+                .route_layer(axum::middleware::from_fn(jwt_auth)),
+        )
+        .route("/campaigns/:id/transfer/accept", post(ownership::accept_transfer).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/clone", post(cloning::clone_campaign).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can clone campaigns"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/members", get(handlers::list_campaign_members).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/join-requests", post(membership::create_join_request).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/join-requests", get(membership::list_join_requests).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can review join requests"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/join-requests/:request_id/approve", post(membership::approve_join_request).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Owner, "Only the DM can review join requests"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/join-requests/:request_id/deny", post(membership::deny_join_request).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Owner, "Only the DM can review join requests"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route(
+            "/campaigns/:id/invites",
+            get(invites::list_invites).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can view invites"), require_role))
+                .merge(post(invites::create_invite).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can invite players"), require_role)))
+                .route_layer(axum::middleware::from_fn(jwt_auth)),
+        )
+        .route("/campaigns/:id/invites/:invite_id", delete(invites::revoke_invite).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can revoke invites"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/waitlist", get(membership::list_waitlist).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can view the waitlist"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/players/:player_id", delete(membership::remove_campaign_player).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/players/:player_id/role", put(membership::set_player_role).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Owner, "Only the DM can change roles"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/players/:player_id/permissions", put(membership::set_player_permissions).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Owner, "Only the DM can change permissions"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/join-requests", get(membership::list_my_join_requests).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/notifications", get(notifications::list_notifications).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/notifications/:id/read", post(notifications::mark_notification_read).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route(
+            "/campaigns/:id/notes",
+            get(notes::list_notes)
+                .merge(post(notes::create_note).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can write notes"), require_role)))
+                .route_layer(axum::middleware::from_fn(jwt_auth)),
+        )
+        .route("/notes/:id", get(notes::get_note).put(notes::update_note).delete(notes::delete_note).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route(
+            "/campaigns/:id/calendar",
+            get(calendar::get_calendar).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Member, "Access denied to this campaign"), require_role))
+                .merge(put(calendar::set_calendar).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can set the calendar"), require_role)))
+                .route_layer(axum::middleware::from_fn(jwt_auth)),
+        )
+        .route("/campaigns/:id/calendar/advance", post(calendar::advance_calendar).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can advance time"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route(
+            "/campaigns/:id/calendar/events",
+            get(calendar::list_calendar_events).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Member, "Access denied to this campaign"), require_role))
+                .merge(post(calendar::create_calendar_event).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can add calendar events"), require_role)))
+                .route_layer(axum::middleware::from_fn(jwt_auth)),
+        )
+        .route("/campaigns/:id/calendar/events/:event_id", delete(calendar::delete_calendar_event).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can remove calendar events"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/locations", get(locations::list_locations).post(locations::create_location).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/locations/:id", get(locations::get_location).put(locations::update_location).delete(locations::delete_location).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/compendium", get(compendium::list_compendium).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/compendium/import", post(compendium::import_compendium).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/compendium/:id", put(compendium::update_compendium_entry).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/content-grants", get(content::list_content_grants).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Member, "Access denied to this campaign"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/content-grants", post(content::grant_content_access).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can grant edit rights"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/content-grants/:grant_id", delete(content::revoke_content_grant).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Dm, "Only the DM can revoke edit rights"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/content/:kind/:id/history", get(content::list_contributions).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/quests", get(handlers::list_quests).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Member, "Access denied to this campaign"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/campaigns/:id/loot", get(rewards::list_loot).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Campaign(Role::Member, "Access denied to this campaign"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/presence", get(handlers::get_presence).route_layer(axum::middleware::from_fn(jwt_auth)))
+        // Session routes (protected)
+        .route("/sessions", get(handlers::list_sessions).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions", post(handlers::create_session).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id", get(handlers::get_session).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id", put(handlers::update_session).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Session(Role::Dm, "Only the DM can update sessions"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/start", post(handlers::start_session).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Session(Role::Dm, "Only the DM can start sessions"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/end", post(handlers::end_session).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Session(Role::Dm, "Only the DM can end sessions"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/pause", post(handlers::pause_session).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Session(Role::Dm, "Only the DM can pause sessions"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/resume", post(handlers::resume_session).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Session(Role::Dm, "Only the DM can resume sessions"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/continue", post(handlers::continue_session).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Session(Role::Dm, "Only the DM can continue sessions"), require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/ws-ticket", post(handlers::create_ws_ticket).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/events", get(sse::session_events).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/presence", get(handlers::get_session_presence).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/rewards", get(rewards::list_session_rewards).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/combats", get(combat::list_session_combats).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/combats/:id/events", get(combat::list_combat_events).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/rewards/:id/accept", post(rewards::accept_rewards).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/rewards/:id/dismiss", post(rewards::dismiss_rewards).route_layer(axum::middleware::from_fn(jwt_auth)))
+        // Character routes (protected)
+        .route("/characters", get(handlers::list_characters).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/characters", post(handlers::create_character).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/characters/:id", get(handlers::get_character).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/characters/:id", put(handlers::update_character).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/characters/:id", delete(handlers::delete_character).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/characters/:id/ability-roll", get(handlers::get_ability_score_roll).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/characters/:id/as-of", get(history::get_character_as_of).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/characters/:id/hp", put(handlers::update_character_hp).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/characters/:id/sheet-link", post(printable::create_sheet_link).route_layer(axum::middleware::from_fn(jwt_auth)))
+        // Authenticated by the signed link instead of a token
+        .route("/characters/:id/sheet.html", get(printable::character_sheet_html))
+        // Game state routes (protected)
+        .route("/initiative", put(handlers::update_initiative).route_layer(axum::middleware::from_fn(jwt_auth)))
+        // Event log routes (protected)
+        .route("/event-logs", post(handlers::create_event_log).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:session_id/event-logs", get(handlers::list_event_logs).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/rolls/stats", get(handlers::get_roll_stats).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/rolls/replay", get(handlers::replay_rolls).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/rolls/commitment", get(handlers::get_roll_commitment).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/sessions/:id/rolls/:roll_id/verify", post(handlers::verify_roll).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/event-logs/:event_id", get(handlers::get_event_log).route_layer(axum::middleware::from_fn(jwt_auth)))
+        // AI routes (protected)
+        .route("/ai/generate", post(handlers::ai_generate).route_layer(axum::middleware::from_fn(jwt_auth)))
+        // Offline generators (protected)
+        .route("/generate/name", get(generators::generate_names).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/generate/tavern", get(generators::generate_tavern_handler).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/generate/shop", get(generators::generate_shop_handler).route_layer(axum::middleware::from_fn(jwt_auth)))
+        // File storage: uploads are protected, downloads need a signed URL instead
+        .route(
+            "/files",
+            post(storage::upload_file)
+                .route_layer(axum::middleware::from_fn(jwt_auth))
+                .layer(axum::extract::DefaultBodyLimit::max(storage::MAX_UPLOAD_BYTES)),
+        )
+        .route("/files/*key", get(storage::serve_file))
+        // Operator routes (protected, ADMIN_USER_IDS only)
+        .route("/admin/usage", get(usage::usage_report).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Admin, require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/admin/users/:id/disable", post(revocation::disable_user).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Admin, require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/admin/impersonate/:user_id", post(impersonation::impersonate).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Admin, require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/admin/users/:id/enable", post(revocation::enable_user).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Admin, require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/admin/users/:id/suspend", post(moderation::suspend_user).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Admin, require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/admin/users/:id/ban", post(moderation::ban_user).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Admin, require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        .route("/admin/users/:id/reinstate", post(moderation::reinstate_user).route_layer(axum::middleware::from_fn_with_state(RequiredRole::Admin, require_role)).route_layer(axum::middleware::from_fn(jwt_auth)))
+        // Health check endpoint
+        .route("/health", get(health_check))
+        .route("/metrics", get(metrics::metrics))
+        .route("/docs", get(api::docs::api_docs))
+        .layer(axum::middleware::from_fn(rate_limit::rate_limit))
+        // Every request gets a span; jwt_auth fills in the user once authenticated
+        .layer(TraceLayer::new_for_http().make_span_with(|request: &axum::http::Request<axum::body::Body>| {
+            tracing::info_span!(
+                "request",
+                method = %request.method(),
+                path = %request.uri().path(),
+                user_id = tracing::field::Empty,
+            )
+        }))
+        .layer(Extension(pool))
+        .layer(Extension(session_state))
+        .layer(Extension(storage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{Body, Bytes};
+    use axum::http::{header, Method, Request};
+    use chrono::Utc;
+    use serde_json::{json, Value};
+    use sqlx::postgres::PgPoolOptions;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+    use crate::middleware::{encode_token, Claims};
+    use crate::storage::MemoryStorage;
+
+    fn test_app(pool: PgPool) -> Router {
+        router(pool, SessionState::new(None), Arc::new(MemoryStorage::default()))
+    }
+
+    // For routes that never touch the database; connecting is deferred until first use
+    fn lazy_pool() -> PgPool {
+        PgPoolOptions::new().connect_lazy("postgres://localhost/yoda_unused").unwrap()
+    }
+
+    async fn db_pool() -> PgPool {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        PgPoolOptions::new().max_connections(2).connect(&database_url).await.unwrap()
+    }
+
+    fn token_for(user_id: Uuid, exp: chrono::DateTime<Utc>) -> String {
+        let claims = Claims::new(user_id, exp.timestamp() as usize);
+        encode_token(&claims).unwrap()
+    }
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Bytes) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        (status, axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap())
+    }
+
+    fn request(method: Method, uri: &str, token: Option<&str>, body: Option<Value>) -> Request<Body> {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        match body {
+            Some(body) => builder.header(header::CONTENT_TYPE, "application/json").body(Body::from(body.to_string())).unwrap(),
+            None => builder.body(Body::empty()).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auth_middleware_over_http() {
+        let app = test_app(lazy_pool());
+        let user_id = Uuid::new_v4();
+
+        let (status, body) = send(&app, request(Method::GET, "/health", None, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], b"OK");
+
+        // Missing, malformed, non-bearer and expired credentials are all plain 401s
+        let (status, body) = send(&app, request(Method::GET, "/protected", None, None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(body.is_empty());
+        let (status, _) = send(&app, request(Method::GET, "/protected", Some("not-a-jwt"), None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let basic = Request::get("/protected").header(header::AUTHORIZATION, "Basic dXNlcjpwYXNz").body(Body::empty()).unwrap();
+        assert_eq!(send(&app, basic).await.0, StatusCode::UNAUTHORIZED);
+        let expired = token_for(user_id, Utc::now() - chrono::Duration::hours(2));
+        let (status, _) = send(&app, request(Method::GET, "/protected", Some(&expired), None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let token = token_for(user_id, Utc::now() + chrono::Duration::hours(1));
+        let (status, body) = send(&app, request(Method::GET, "/protected", Some(&token), None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(String::from_utf8(body.to_vec()).unwrap(), format!("User ID: {}", user_id));
+
+        let (status, _) = send(&app, request(Method::GET, "/no-such-route", Some(&token), None)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Admin routes turn other users away before the handler runs
+        let (status, body) = send(&app, request(Method::GET, "/admin/usage", Some(&token), None)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(&body[..], b"Only server admins can do this");
+        let (status, _) = send(&app, request(Method::GET, "/admin/usage", None, None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Impersonation tokens can't take over the account
+        let claims = Claims { impersonated_by: Some(Uuid::new_v4()), ..Claims::new(user_id, (Utc::now() + chrono::Duration::minutes(5)).timestamp() as usize) };
+        let impersonating = encode_token(&claims).unwrap();
+        let change = json!({"current_password": "velvet-otter-lantern", "new_password": "lantern-otter-velvet"});
+        let (status, body) = send(&app, request(Method::POST, "/auth/change-password", Some(&impersonating), Some(change))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(&body[..], b"Not allowed while impersonating a user");
+        let (status, _) = send(&app, request(Method::DELETE, "/users/me", Some(&impersonating), Some(json!({"mode": "delete", "confirm": "x"})))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&app, request(Method::GET, "/protected", Some(&impersonating), None)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_file_upload_and_signed_download_over_http() {
+        let app = test_app(lazy_pool());
+        let token = token_for(Uuid::new_v4(), Utc::now() + chrono::Duration::hours(1));
+        let upload = |token: Option<&str>| {
+            let mut builder = Request::post("/files").header(header::CONTENT_TYPE, "text/plain");
+            if let Some(token) = token {
+                builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            builder.body(Body::from("handout: the sunken keep")).unwrap()
+        };
+
+        assert_eq!(send(&app, upload(None)).await.0, StatusCode::UNAUTHORIZED);
+        let (status, body) = send(&app, upload(Some(&token))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let uploaded: Value = serde_json::from_slice(&body).unwrap();
+        let url = uploaded["url"].as_str().unwrap();
+
+        // The signed link works without a token and only as issued
+        let response = app.clone().oneshot(request(Method::GET, url, None, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"handout: the sunken keep");
+
+        let tampered = url.replace("signature=", "signature=00");
+        let (status, body) = send(&app, request(Method::GET, &tampered, None, None)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(&body[..], b"Invalid or expired link");
+    }
+
+    #[tokio::test]
+    async fn test_campaign_endpoints_over_http() {
+        let pool = db_pool().await;
+        let app = test_app(pool.clone());
+        let timestamp = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+
+        let mut tokens = Vec::new();
+        for name in ["http_dm", "http_outsider"] {
+            let account = json!({
+                "email": format!("{}{}@example.com", name, timestamp),
+                "username": format!("{}{}", name, timestamp),
+                "password": "correct horse battery staple",
+            });
+            let (status, _) = send(&app, request(Method::POST, "/auth/register", None, Some(account.clone()))).await;
+            assert_eq!(status, StatusCode::CREATED);
+            let (status, body) = send(&app, request(Method::POST, "/auth/register", None, Some(account.clone()))).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+            assert_eq!(&body[..], b"Email or username already exists");
+
+            let wrong = json!({"email": account["email"], "password": "wrong"});
+            let (status, body) = send(&app, request(Method::POST, "/auth/login", None, Some(wrong))).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(&body[..], b"Invalid email or password");
+
+            sqlx::query("UPDATE users SET email_verified = TRUE WHERE email = $1").bind(account["email"].as_str()).execute(&pool).await.unwrap();
+            let login = json!({"email": account["email"], "password": account["password"]});
+            let (status, body) = send(&app, request(Method::POST, "/auth/login", None, Some(login))).await;
+            assert_eq!(status, StatusCode::OK);
+            let value: Value = serde_json::from_slice(&body).unwrap();
+            tokens.push(value["token"].as_str().unwrap().to_string());
+        }
+        let (dm, outsider) = (tokens[0].as_str(), tokens[1].as_str());
+
+        // Body rejections come from the extractors before the handler runs
+        let (status, _) = send(&app, request(Method::POST, "/campaigns", Some(dm), Some(json!({"description": "no name"})))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let no_content_type = Request::post("/campaigns")
+            .header(header::AUTHORIZATION, format!("Bearer {}", dm))
+            .body(Body::from(r#"{"name": "Untyped"}"#))
+            .unwrap();
+        assert_eq!(send(&app, no_content_type).await.0, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let (status, body) = send(&app, request(Method::POST, "/campaigns", Some(dm), Some(json!({"name": "Over HTTP"})))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let campaign: Value = serde_json::from_slice(&body).unwrap();
+        let campaign_id = campaign["id"].as_str().unwrap().to_string();
+
+        let (status, body) = send(&app, request(Method::GET, "/campaigns", Some(dm), None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let page: Value = serde_json::from_slice(&body).unwrap();
+        assert!(page["campaigns"].as_array().unwrap().iter().any(|c| c["id"] == campaign["id"]));
+        assert_eq!(page["total"], 1);
+
+        let uri = format!("/campaigns/{}", campaign_id);
+        let (status, body) = send(&app, request(Method::GET, &uri, Some(dm), None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["name"], "Over HTTP");
+        let (status, _) = send(&app, request(Method::GET, "/campaigns/not-a-uuid", Some(dm), None)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Campaigns the caller isn't part of don't exist as far as they can tell
+        let (status, body) = send(&app, request(Method::GET, &uri, Some(outsider), None)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(&body[..], b"Campaign not found");
+        let (status, _) = send(&app, request(Method::GET, &uri, None, None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_campaign_roles_are_checked_on_the_route() {
+        let pool = db_pool().await;
+        let app = test_app(pool.clone());
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let expires = Utc::now() + chrono::Duration::hours(1);
+        let (dm, player) = (token_for(f.dm_id, expires), token_for(f.player_id, expires));
+        let campaign = format!("/campaigns/{}", f.campaign_id);
+
+        // Refused before the body is read, so a bad one doesn't matter
+        let (status, body) = send(&app, request(Method::PUT, &campaign, Some(&player), Some(json!({"name": 7})))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(&body[..], b"Only the DM can update campaigns");
+        let (status, _) = send(&app, request(Method::PUT, "/campaigns/not-a-uuid", Some(&dm), Some(json!({})))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&app, request(Method::PUT, &campaign, Some(&dm), Some(json!({"name": "Renamed"})))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Each method on a path has its own role
+        let calendar = format!("{}/calendar", campaign);
+        let (status, body) = send(&app, request(Method::GET, &calendar, Some(&player), None)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(&body[..], b"This campaign has no calendar yet");
+        let (status, body) = send(&app, request(Method::PUT, &calendar, Some(&player), Some(json!({})))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(&body[..], b"Only the DM can set the calendar");
+
+        // Session routes check the role in the session's campaign
+        let (status, body) = send(&app, request(Method::POST, &format!("/sessions/{}/end", f.session_id), Some(&player), None)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(&body[..], b"Only the DM can end sessions");
+        let (status, _) = send(&app, request(Method::POST, &format!("/sessions/{}/end", f.session_id), Some(&dm), None)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
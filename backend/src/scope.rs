@@ -0,0 +1,488 @@
+use axum::{http::StatusCode, response::{IntoResponse, Response}};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use crate::audit::{self, AuthEvent, ClientInfo};
+use crate::content::ContentKind;
+use crate::errors::db_error;
+use crate::middleware::{CampaignRole, CampaignRoles};
+use crate::usage::{Metric, Subject};
+
+// Campaign-scoped access checks. Handlers, route layers (see require_role) and WebSocket
+// messages resolve the campaign a resource belongs to here instead of writing their own
+// EXISTS queries, so isolation between campaigns is decided (and audited) in one place.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsolationMode {
+    Off,
+    // Log every scope decision under the `campaign_audit` target
+    Audit,
+    // Audit, and answer out-of-scope ids with 404 so other campaigns can't be probed
+    Strict,
+}
+
+pub fn isolation_mode() -> IsolationMode {
+    static MODE: OnceLock<IsolationMode> = OnceLock::new();
+    *MODE.get_or_init(|| match std::env::var("CAMPAIGN_ISOLATION").as_deref() {
+        Ok("audit") => IsolationMode::Audit,
+        Ok("strict") => IsolationMode::Strict,
+        _ => IsolationMode::Off,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Campaign(Uuid),
+    Session(Uuid),
+    Character(Uuid),
+    EventLog(Uuid),
+    Content(ContentKind, Uuid),
+    Note(Uuid),
+    Location(Uuid),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    // Anyone in the campaign, observers included
+    Member,
+    // A seat at the table: anyone but observers
+    Play,
+    // DM or co-DM
+    Dm,
+    // The campaign's own DM, for deleting it and handing out roles
+    Owner,
+    // DM, co-DM, the player who owns the character, or a player granted edit rights on content
+    Modify,
+    // DM, co-DM, or a player the owning DM gave this permission
+    Permitted(Permission),
+}
+
+// Parts of the DM's job the owning DM can hand to individual players
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    // Edit the campaign's NPC compendium entries and import new ones
+    EditNpcs,
+    // See encounter rewards and award their XP and loot
+    AwardXp,
+    // Set initiative, advance turns and end combat
+    ManageInitiative,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 3] = [Permission::EditNpcs, Permission::AwardXp, Permission::ManageInitiative];
+
+    // Stored in campaign_players.permissions
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Permission::EditNpcs => "edit_npcs",
+            Permission::AwardXp => "award_xp",
+            Permission::ManageInitiative => "manage_initiative",
+        }
+    }
+}
+
+// A member's permissions as clients see them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Permissions {
+    pub can_edit_npcs: bool,
+    pub can_award_xp: bool,
+    pub can_manage_initiative: bool,
+}
+
+impl Permissions {
+    pub fn from_stored(stored: &[String]) -> Self {
+        let held = |permission: Permission| stored.iter().any(|name| name == permission.as_str());
+        Permissions {
+            can_edit_npcs: held(Permission::EditNpcs),
+            can_award_xp: held(Permission::AwardXp),
+            can_manage_initiative: held(Permission::ManageInitiative),
+        }
+    }
+
+    pub fn has(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::EditNpcs => self.can_edit_npcs,
+            Permission::AwardXp => self.can_award_xp,
+            Permission::ManageInitiative => self.can_manage_initiative,
+        }
+    }
+
+    pub fn to_stored(self) -> Vec<&'static str> {
+        Permission::ALL.into_iter().filter(|permission| self.has(*permission)).map(Permission::as_str).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CampaignScope {
+    pub campaign_id: Uuid,
+    pub user_id: Uuid,
+    pub role: CampaignRole,
+    // Player who owns the resource, for characters; the user themselves when they hold
+    // an edit grant on content
+    pub owner_id: Option<Uuid>,
+    // The DM is suspended; members can still read, but nothing changes until they're back
+    pub frozen: bool,
+    // What the owning DM handed this member; the DM and co-DMs need none
+    pub permissions: Permissions,
+}
+
+impl CampaignScope {
+    // Co-DMs included
+    pub fn is_dm(&self) -> bool {
+        self.role.is_dm()
+    }
+
+    pub fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Member => true,
+            Access::Play => self.role != CampaignRole::Observer,
+            Access::Dm => self.is_dm(),
+            Access::Owner => self.role == CampaignRole::Dm,
+            Access::Modify => self.is_dm() || (self.role != CampaignRole::Observer && self.owner_id == Some(self.user_id)),
+            Access::Permitted(permission) => self.is_dm() || (self.role != CampaignRole::Observer && self.permissions.has(permission)),
+        }
+    }
+
+    // Checks that a row reached through this scope belongs to the same campaign
+    pub fn contains(&self, campaign_id: Uuid) -> bool {
+        let contained = campaign_id == self.campaign_id;
+        if !contained && isolation_mode() != IsolationMode::Off {
+            tracing::warn!(
+                target: "campaign_audit",
+                user_id = %self.user_id,
+                scope = %self.campaign_id,
+                campaign_id = %campaign_id,
+                "cross-campaign row rejected"
+            );
+        }
+        contained
+    }
+}
+
+#[derive(Debug)]
+pub enum Denied {
+    // Missing, or in a campaign the user isn't part of
+    OutOfScope,
+    // Visible to the user but they lack the role for this action
+    Forbidden,
+    // More than Member access to a frozen campaign
+    Frozen,
+    Database(sqlx::Error),
+}
+
+// Resolves the campaign behind a resource and the user's role in it. None when the
+// resource doesn't exist, its campaign is deleted or the user isn't a member; character owners keep access to
+// their own characters. Takes a transaction too, for checks that must hold until it commits.
+pub async fn resolve<'e>(executor: impl PgExecutor<'e>, user_id: Uuid, resource: Resource) -> Result<Option<CampaignScope>, sqlx::Error> {
+    let (query, id) = match resource {
+        Resource::Campaign(id) => (
+            "SELECT c.id, c.dm_id = $2, cp.role, cp.permissions, NULL::UUID, c.frozen_at IS NOT NULL
+             FROM campaigns c LEFT JOIN campaign_players cp ON cp.campaign_id = c.id AND cp.player_id = $2 WHERE c.id = $1 AND c.deleted_at IS NULL",
+            id,
+        ),
+        Resource::Session(id) => (
+            "SELECT c.id, c.dm_id = $2, cp.role, cp.permissions, NULL::UUID, c.frozen_at IS NOT NULL
+             FROM sessions s INNER JOIN campaigns c ON s.campaign_id = c.id LEFT JOIN campaign_players cp ON cp.campaign_id = c.id AND cp.player_id = $2 WHERE s.id = $1 AND c.deleted_at IS NULL",
+            id,
+        ),
+        Resource::Character(id) => (
+            "SELECT c.id, c.dm_id = $2, cp.role, cp.permissions, ch.player_id, c.frozen_at IS NOT NULL
+             FROM characters ch INNER JOIN campaigns c ON ch.campaign_id = c.id LEFT JOIN campaign_players cp ON cp.campaign_id = c.id AND cp.player_id = $2 WHERE ch.id = $1 AND c.deleted_at IS NULL",
+            id,
+        ),
+        Resource::EventLog(id) => (
+            "SELECT c.id, c.dm_id = $2, cp.role, cp.permissions, NULL::UUID, c.frozen_at IS NOT NULL
+             FROM event_logs e INNER JOIN sessions s ON e.session_id = s.id INNER JOIN campaigns c ON s.campaign_id = c.id LEFT JOIN campaign_players cp ON cp.campaign_id = c.id AND cp.player_id = $2 WHERE e.id = $1 AND c.deleted_at IS NULL",
+            id,
+        ),
+        Resource::Note(id) => (
+            "SELECT c.id, c.dm_id = $2, cp.role, cp.permissions, NULL::UUID, c.frozen_at IS NOT NULL
+             FROM campaign_notes n INNER JOIN campaigns c ON n.campaign_id = c.id LEFT JOIN campaign_players cp ON cp.campaign_id = c.id AND cp.player_id = $2 WHERE n.id = $1 AND c.deleted_at IS NULL",
+            id,
+        ),
+        Resource::Location(id) => (
+            "SELECT c.id, c.dm_id = $2, cp.role, cp.permissions, NULL::UUID, c.frozen_at IS NOT NULL
+             FROM locations l INNER JOIN campaigns c ON l.campaign_id = c.id LEFT JOIN campaign_players cp ON cp.campaign_id = c.id AND cp.player_id = $2 WHERE l.id = $1 AND c.deleted_at IS NULL",
+            id,
+        ),
+        // Grants only count while the grantee is still in the campaign. The edit_npcs permission
+        // works as a grant on every NPC entry.
+        Resource::Content(ContentKind::Compendium, id) => (
+            "SELECT c.id, c.dm_id = $2, cp.role, cp.permissions,
+                 CASE WHEN cp.player_id IS NOT NULL AND (
+                     EXISTS(SELECT 1 FROM content_grants g WHERE g.entity_type = 'compendium' AND g.entity_id = e.id AND g.user_id = $2)
+                     OR (e.kind = 'npc' AND 'edit_npcs' = ANY(cp.permissions))
+                 ) THEN cp.player_id END, c.frozen_at IS NOT NULL
+             FROM compendium_entries e INNER JOIN campaigns c ON e.campaign_id = c.id
+             LEFT JOIN campaign_players cp ON cp.campaign_id = c.id AND cp.player_id = $2 WHERE e.id = $1 AND c.deleted_at IS NULL",
+            id,
+        ),
+    };
+
+    let row = sqlx::query_as::<_, (Uuid, bool, Option<String>, Option<Vec<String>>, Option<Uuid>, bool)>(query)
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(executor)
+        .await?;
+
+    Ok(row.and_then(|(campaign_id, is_dm, member_role, permissions, owner_id, frozen)| {
+        let role = if is_dm {
+            CampaignRole::Dm
+        } else if let Some(member_role) = member_role {
+            CampaignRole::from_member_role(&member_role)
+        } else if owner_id == Some(user_id) {
+            CampaignRole::Player
+        } else {
+            return None;
+        };
+        let permissions = Permissions::from_stored(&permissions.unwrap_or_default());
+        Some(CampaignScope { campaign_id, user_id, role, owner_id, frozen, permissions })
+    }))
+}
+
+// Every campaign the user runs or belongs to, with their role in it; deleted campaigns are
+// left out. Listings filter on these ids rather than joining memberships themselves.
+pub async fn campaign_roles(pool: &PgPool, user_id: Uuid) -> Result<HashMap<Uuid, CampaignRole>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, (Uuid, Option<String>)>(
+        "SELECT id, NULL::TEXT FROM campaigns WHERE dm_id = $1 AND deleted_at IS NULL 
+         UNION ALL 
+         SELECT cp.campaign_id, cp.role FROM campaign_players cp INNER JOIN campaigns c ON c.id = cp.campaign_id 
+         WHERE cp.player_id = $1 AND c.deleted_at IS NULL"
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    let mut roles = HashMap::new();
+    for (campaign_id, member_role) in rows {
+        // DM wins if the user is somehow both
+        let role = member_role.map_or(CampaignRole::Dm, |role| CampaignRole::from_member_role(&role));
+        roles.entry(campaign_id).and_modify(|r| if role == CampaignRole::Dm { *r = role }).or_insert(role);
+    }
+    Ok(roles)
+}
+
+pub async fn check(pool: &PgPool, user_id: Uuid, resource: Resource, access: Access) -> Result<CampaignScope, Denied> {
+    let scope = resolve(pool, user_id, resource).await.map_err(Denied::Database)?;
+    let result = match scope {
+        Some(scope) if scope.frozen && access != Access::Member => Err(Denied::Frozen),
+        Some(scope) if scope.allows(access) => {
+            crate::usage::usage().record(Subject::Campaign(scope.campaign_id), Metric::ApiCalls, 1);
+            Ok(scope)
+        }
+        Some(_) => Err(Denied::Forbidden),
+        None => Err(Denied::OutOfScope),
+    };
+
+    if isolation_mode() != IsolationMode::Off {
+        let campaign_id = scope.map(|scope| scope.campaign_id);
+        match &result {
+            Ok(_) => tracing::info!(target: "campaign_audit", %user_id, ?resource, ?access, ?campaign_id, "access granted"),
+            Err(denied) => tracing::warn!(target: "campaign_audit", %user_id, ?resource, ?access, ?campaign_id, ?denied, "access denied"),
+        }
+    }
+    result
+}
+
+pub const FROZEN_MESSAGE: &str = "Campaign is frozen while its DM is suspended";
+
+fn refusal(denied: Denied, message: &str) -> Response {
+    match denied {
+        Denied::OutOfScope if isolation_mode() == IsolationMode::Strict => (StatusCode::NOT_FOUND, "Not found").into_response(),
+        Denied::OutOfScope | Denied::Forbidden => (StatusCode::FORBIDDEN, message.to_string()).into_response(),
+        Denied::Frozen => (StatusCode::LOCKED, FROZEN_MESSAGE).into_response(),
+        Denied::Database(e) => db_error(e, "Failed to verify access"),
+    }
+}
+
+// Refusals go in the user's audit log, in the background so the answer isn't held up.
+// There's no request here, so they're recorded without an address.
+fn audit_denied(pool: &PgPool, user_id: Uuid, resource: Resource, access: Access) {
+    let pool = pool.clone();
+    let detail = serde_json::json!({ "resource": format!("{:?}", resource), "access": format!("{:?}", access) });
+    tokio::spawn(async move {
+        audit::record(&pool, Some(user_id), AuthEvent::PermissionDenied, &ClientInfo::default(), detail).await;
+    });
+}
+
+// `message` is what the client sees when access is refused
+pub async fn authorize(pool: &PgPool, user_id: Uuid, resource: Resource, access: Access, message: &str) -> Result<CampaignScope, Response> {
+    match check(pool, user_id, resource, access).await {
+        Ok(scope) => Ok(scope),
+        Err(Denied::Database(e)) => Err(refusal(Denied::Database(e), message)),
+        Err(denied) => {
+            audit_denied(pool, user_id, resource, access);
+            Err(refusal(denied, message))
+        }
+    }
+}
+
+// For reads that have always answered 404 for anything outside the user's campaigns;
+// `not_found` is the message, whatever the isolation mode
+pub async fn authorize_member(pool: &PgPool, user_id: Uuid, resource: Resource, not_found: &str) -> Result<CampaignScope, Response> {
+    match check(pool, user_id, resource, Access::Member).await {
+        Ok(scope) => Ok(scope),
+        Err(Denied::Database(e)) => Err(db_error(e, "Failed to verify access")),
+        Err(_) => {
+            audit_denied(pool, user_id, resource, Access::Member);
+            Err((StatusCode::NOT_FOUND, not_found.to_string()).into_response())
+        }
+    }
+}
+
+// For campaign-level checks: the roles embedded in the token are used when the request
+// carried them (jwt_auth only passes them on while current), saving the database a query
+pub async fn authorize_campaign(
+    pool: &PgPool,
+    roles: Option<&CampaignRoles>,
+    user_id: Uuid,
+    campaign_id: Uuid,
+    access: Access,
+    message: &str,
+) -> Result<(), Response> {
+    let Some(roles) = roles else {
+        return authorize(pool, user_id, Resource::Campaign(campaign_id), access, message).await.map(|_| ());
+    };
+    let Some(role) = roles.role_for(campaign_id) else {
+        audit_denied(pool, user_id, Resource::Campaign(campaign_id), access);
+        return Err(refusal(Denied::OutOfScope, message));
+    };
+    // Permissions aren't in tokens
+    if matches!(access, Access::Permitted(_)) && !role.is_dm() {
+        return authorize(pool, user_id, Resource::Campaign(campaign_id), access, message).await.map(|_| ());
+    }
+    // A campaign has no owner besides its DM, so Modify is DM and co-DM only here
+    let scope = CampaignScope { campaign_id, user_id, role, owner_id: None, frozen: false, permissions: Permissions::default() };
+    if !scope.allows(access) {
+        audit_denied(pool, user_id, Resource::Campaign(campaign_id), access);
+        return Err(refusal(Denied::Forbidden, message));
+    }
+    // Token roles don't say whether the campaign is frozen. Only the owning DM, whose tokens
+    // are refused while suspended, gets past Member without the lookup.
+    if access != Access::Member && role != CampaignRole::Dm {
+        return authorize(pool, user_id, Resource::Campaign(campaign_id), access, message).await.map(|_| ());
+    }
+    crate::usage::usage().record(Subject::Campaign(campaign_id), Metric::ApiCalls, 1);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, Query};
+    use axum::http::{HeaderMap, Method};
+    use axum::{Extension, Json};
+    use serde_json::json;
+    use crate::middleware::AuthUser;
+    use crate::socket::SessionState;
+    use crate::test_support::{call_as, campaign_fixture, test_pool};
+    use crate::{combat, compendium, handlers, membership, printable, sse};
+
+    #[tokio::test]
+    async fn test_resolve_roles() {
+        let pool = test_pool().await;
+        let f = campaign_fixture(&pool).await;
+
+        let dm = resolve(&pool, f.dm_id, Resource::Session(f.session_id)).await.unwrap().unwrap();
+        assert!(dm.is_dm() && dm.contains(f.campaign_id));
+        let player = resolve(&pool, f.player_id, Resource::Character(f.character_id)).await.unwrap().unwrap();
+        assert!(player.allows(Access::Modify) && !player.allows(Access::Dm));
+        assert!(!player.contains(Uuid::new_v4()));
+        assert!(resolve(&pool, f.outsider_id, Resource::EventLog(f.event_id)).await.unwrap().is_none());
+        assert!(resolve(&pool, f.dm_id, Resource::Campaign(Uuid::new_v4())).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_embedded_roles_decide_campaign_access() {
+        // Never connects; embedded roles answer without the database
+        let pool = sqlx::postgres::PgPoolOptions::new().connect_lazy("postgres://localhost/yoda_unused").unwrap();
+        let (user_id, campaign_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let roles = CampaignRoles([(campaign_id, CampaignRole::Player)].into_iter().collect());
+        let check = |campaign_id: Uuid, access: Access| authorize_campaign(&pool, Some(&roles), user_id, campaign_id, access, "Only the DM can do that");
+
+        assert!(check(campaign_id, Access::Member).await.is_ok());
+        let refused = check(campaign_id, Access::Dm).await.unwrap_err();
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+        assert!(check(Uuid::new_v4(), Access::Member).await.is_err());
+    }
+
+    // A DM of another campaign tries every endpoint against this campaign's ids
+    #[tokio::test]
+    async fn test_cross_campaign_http_access_is_denied() {
+        let pool = test_pool().await;
+        let state = SessionState::new(None);
+        let f = campaign_fixture(&pool).await;
+        let user = || Extension(AuthUser(f.outsider_id));
+        let p = || Extension(pool.clone());
+        let s = || Extension(state.clone());
+
+        let responses: Vec<(&str, Response)> = vec![
+            ("get_campaign", handlers::get_campaign(p(), user(), Path(f.campaign_id)).await.into_response()),
+            ("update_campaign", call_as(&pool, f.outsider_id, Method::PUT, &format!("/campaigns/{}", f.campaign_id), Some(json!({"name": "Mine"}))).await),
+            ("delete_campaign", call_as(&pool, f.outsider_id, Method::DELETE, &format!("/campaigns/{}", f.campaign_id), None).await),
+            ("list_campaign_members", handlers::list_campaign_members(p(), user(), s(), Path(f.campaign_id)).await.into_response()),
+            ("generate_plot_hooks", call_as(&pool, f.outsider_id, Method::POST, &format!("/campaigns/{}/hooks/generate", f.campaign_id), Some(json!({}))).await),
+            ("accept_plot_hook", call_as(&pool, f.outsider_id, Method::POST, &format!("/campaigns/{}/hooks/accept", f.campaign_id), Some(json!({"hook": "A dragon"}))).await),
+            ("generate_chronicle", call_as(&pool, f.outsider_id, Method::POST, &format!("/campaigns/{}/chronicle", f.campaign_id), None).await),
+            ("get_chronicle", call_as(&pool, f.outsider_id, Method::GET, &format!("/campaigns/{}/chronicle", f.campaign_id), None).await),
+            ("list_quests", call_as(&pool, f.outsider_id, Method::GET, &format!("/campaigns/{}/quests", f.campaign_id), None).await),
+            ("list_join_requests", call_as(&pool, f.outsider_id, Method::GET, &format!("/campaigns/{}/join-requests", f.campaign_id), None).await),
+            ("approve_join_request", call_as(&pool, f.outsider_id, Method::POST, &format!("/campaigns/{}/join-requests/{}/approve", f.campaign_id, Uuid::new_v4()), Some(json!({}))).await),
+            ("deny_join_request", call_as(&pool, f.outsider_id, Method::POST, &format!("/campaigns/{}/join-requests/{}/deny", f.campaign_id, Uuid::new_v4()), Some(json!({}))).await),
+            ("list_waitlist", call_as(&pool, f.outsider_id, Method::GET, &format!("/campaigns/{}/waitlist", f.campaign_id), None).await),
+            ("remove_campaign_player", membership::remove_campaign_player(p(), user(), Path((f.campaign_id, f.player_id))).await.into_response()),
+            ("list_compendium", compendium::list_compendium(p(), user(), Path(f.campaign_id), Query(serde_json::from_value(json!({})).unwrap())).await.into_response()),
+            ("import_compendium", compendium::import_compendium(p(), user(), Path(f.campaign_id), Json(serde_json::from_value(json!({"source": "srd", "entries": []})).unwrap())).await.into_response()),
+            ("create_session", handlers::create_session(p(), user(), Json(serde_json::from_value(json!({"campaign_id": f.campaign_id, "name": "Raid"})).unwrap())).await.into_response()),
+            ("get_session", handlers::get_session(p(), user(), Path(f.session_id)).await.into_response()),
+            ("update_session", call_as(&pool, f.outsider_id, Method::PUT, &format!("/sessions/{}", f.session_id), Some(json!({"name": "Raid"}))).await),
+            ("start_session", call_as(&pool, f.outsider_id, Method::POST, &format!("/sessions/{}/start", f.session_id), None).await),
+            ("end_session", call_as(&pool, f.outsider_id, Method::POST, &format!("/sessions/{}/end", f.session_id), None).await),
+            ("create_ws_ticket", handlers::create_ws_ticket(p(), user(), s(), Path(f.session_id)).await.into_response()),
+            ("session_events", sse::session_events(p(), user(), s(), Path(f.session_id), HeaderMap::new()).await.into_response()),
+            ("get_session_presence", handlers::get_session_presence(p(), user(), s(), Path(f.session_id)).await.into_response()),
+            ("update_initiative", handlers::update_initiative(p(), user(), Json(serde_json::from_value(json!({"session_id": f.session_id, "initiative_order": []})).unwrap())).await.into_response()),
+            ("list_session_combats", combat::list_session_combats(p(), user(), Path(f.session_id)).await.into_response()),
+            ("list_event_logs", handlers::list_event_logs(p(), user(), Path(f.session_id)).await.into_response()),
+            ("get_roll_commitment", handlers::get_roll_commitment(p(), user(), Path(f.session_id)).await.into_response()),
+            ("verify_roll", handlers::verify_roll(p(), user(), Path((f.session_id, f.event_id))).await.into_response()),
+            ("create_event_log", handlers::create_event_log(p(), user(), s(), Json(serde_json::from_value(json!({"session_id": f.session_id, "event_type": "note", "event_data": {}})).unwrap())).await.into_response()),
+            ("get_event_log", handlers::get_event_log(p(), user(), Path(f.event_id)).await.into_response()),
+            ("ai_generate", handlers::ai_generate(p(), user(), Json(serde_json::from_value(json!({"prompt": "Spy", "session_id": f.session_id, "request_type": "chat"})).unwrap())).await.into_response()),
+            ("create_character", handlers::create_character(p(), user(), Json(serde_json::from_value(json!({"campaign_id": f.campaign_id, "name": "Spy"})).unwrap())).await.into_response()),
+            ("get_character", handlers::get_character(p(), user(), Path(f.character_id)).await.into_response()),
+            ("get_ability_score_roll", handlers::get_ability_score_roll(p(), user(), Path(f.character_id)).await.into_response()),
+            ("create_sheet_link", printable::create_sheet_link(p(), user(), Path(f.character_id)).await.into_response()),
+            ("update_character", handlers::update_character(p(), user(), Path(f.character_id), Json(serde_json::from_value(json!({"name": "Spy"})).unwrap())).await.into_response()),
+            ("update_character_hp", handlers::update_character_hp(p(), user(), Path(f.character_id), Json(serde_json::from_value(json!({"hp_current": 0})).unwrap())).await.into_response()),
+            ("delete_character", handlers::delete_character(p(), user(), Path(f.character_id)).await.into_response()),
+        ];
+
+        for (endpoint, response) in responses {
+            let status = response.status();
+            assert!(
+                status == StatusCode::FORBIDDEN || status == StatusCode::NOT_FOUND,
+                "{} answered {} across campaigns",
+                endpoint,
+                status
+            );
+        }
+
+        // Nothing was changed along the way
+        let intact = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM characters WHERE id = $1 AND name = 'Wren' AND hp_current = 10) 
+             AND EXISTS(SELECT 1 FROM campaign_players WHERE campaign_id = $2 AND player_id = $3)"
+        )
+        .bind(f.character_id)
+        .bind(f.campaign_id)
+        .bind(f.player_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(intact);
+    }
+}
@@ -0,0 +1,148 @@
+use axum::{extract::Query, http::StatusCode, response::IntoResponse, Extension, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::handlers::CampaignResponse;
+use crate::middleware::{AuthUser, CampaignRole};
+use crate::scope::campaign_roles;
+use crate::models::Campaign;
+
+// Full-text search over the user's own campaigns, for DMs with too many to scroll through.
+// A campaign matches on its name or description, or on any of its notes the user can read;
+// the notes that matched come back with it. `q` takes web search syntax: quoted phrases,
+// `or` and `-excluded` words.
+
+const MAX_QUERY_CHARS: usize = 200;
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoteMatch {
+    pub id: Uuid,
+    pub title: String,
+}
+
+#[derive(Serialize)]
+pub struct CampaignMatch {
+    #[serde(flatten)]
+    pub campaign: CampaignResponse,
+    pub role: CampaignRole,
+    pub rank: f32,
+    // Best match first
+    pub notes: Vec<NoteMatch>,
+}
+
+#[derive(sqlx::FromRow)]
+struct CampaignMatchRow {
+    #[sqlx(flatten)]
+    campaign: Campaign,
+    rank: f32,
+    notes: sqlx::types::Json<Vec<NoteMatch>>,
+}
+
+pub async fn search_campaigns(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Query(params): Query<SearchQuery>,
+) -> impl IntoResponse {
+    let q = params.q.trim();
+    if q.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Search query can't be empty").into_response();
+    }
+    if q.chars().count() > MAX_QUERY_CHARS {
+        return (StatusCode::BAD_REQUEST, "Search query is too long").into_response();
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT);
+
+    let roles = match campaign_roles(&pool, user.0).await {
+        Ok(roles) => roles,
+        Err(e) => return db_error(e, "Failed to search campaigns"),
+    };
+    let mine: Vec<Uuid> = roles.keys().copied().collect();
+    let running: Vec<Uuid> = roles.iter().filter(|(_, role)| role.is_dm()).map(|(id, _)| *id).collect();
+
+    // DM-only notes count for the DM and co-DMs alone
+    let rows = sqlx::query_as::<_, CampaignMatchRow>(
+        "WITH q AS (SELECT websearch_to_tsquery('english', $2) AS query),
+         mine AS (SELECT * FROM campaigns WHERE id = ANY($1)),
+         note_matches AS (
+             SELECT n.campaign_id, MAX(ts_rank(n.search_vector, q.query)) AS rank,
+                    jsonb_agg(jsonb_build_object('id', n.id, 'title', n.title) ORDER BY ts_rank(n.search_vector, q.query) DESC) AS notes
+             FROM campaign_notes n INNER JOIN mine m ON m.id = n.campaign_id CROSS JOIN q
+             WHERE n.search_vector @@ q.query AND (n.visibility = 'shared' OR n.campaign_id = ANY($4))
+             GROUP BY n.campaign_id
+         )
+         SELECT m.*, GREATEST(ts_rank(m.search_vector, q.query), COALESCE(nm.rank, 0)) AS rank, COALESCE(nm.notes, '[]') AS notes
+         FROM mine m CROSS JOIN q LEFT JOIN note_matches nm ON nm.campaign_id = m.id
+         WHERE m.search_vector @@ q.query OR nm.campaign_id IS NOT NULL
+         ORDER BY rank DESC, m.updated_at DESC, m.id
+         LIMIT $3"
+    )
+    .bind(&mine)
+    .bind(q)
+    .bind(limit)
+    .bind(&running)
+    .fetch_all(&pool)
+    .await;
+
+    match rows {
+        Ok(rows) => {
+            let matches: Vec<CampaignMatch> = rows.into_iter().map(|row| {
+                let role = roles[&row.campaign.id];
+                CampaignMatch { campaign: CampaignResponse::from(row.campaign), role, rank: row.rank, notes: row.notes.0 }
+            }).collect();
+            Json(matches).into_response()
+        }
+        Err(e) => db_error(e, "Failed to search campaigns"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_search_finds_campaigns_by_description_and_readable_notes() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        sqlx::query("UPDATE campaigns SET description = 'A haunted lighthouse on the Sword Coast' WHERE id = $1")
+            .bind(f.campaign_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO campaign_notes (campaign_id, title, content, visibility) VALUES ($1, 'Villain', 'The keeper is secretly a doppelganger', 'dm')")
+            .bind(f.campaign_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let search = |user_id: Uuid, q: &str| {
+            let params = SearchQuery { q: q.to_string(), limit: None };
+            search_campaigns(Extension(pool.clone()), Extension(AuthUser(user_id)), Query(params))
+        };
+        let read = |response: axum::response::Response| async {
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<Vec<serde_json::Value>>(&body).unwrap()
+        };
+
+        let found = read(search(f.player_id, "lighthouses").await.into_response()).await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0]["id"], f.campaign_id.to_string());
+        assert_eq!(found[0]["role"], "player");
+        assert!(read(search(f.outsider_id, "lighthouse").await.into_response()).await.is_empty());
+
+        // The DM-only note only matches for the DM
+        let found = read(search(f.dm_id, "doppelganger").await.into_response()).await;
+        assert_eq!(found[0]["notes"][0]["title"], "Villain");
+        assert!(read(search(f.player_id, "doppelganger").await.into_response()).await.is_empty());
+        assert_eq!(search(f.dm_id, "  ").await.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,304 @@
+use serde::{Deserialize, Serialize};
+use crate::models::Character;
+
+// Values derived from a character's raw stats, computed here so every client and the combat
+// code agree on them. Ability scores are read from `stats` by full name ("strength") or
+// abbreviation ("str"); a missing score counts as 10.
+
+pub const ABILITIES: [&str; 6] = ["strength", "dexterity", "constitution", "intelligence", "wisdom", "charisma"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AbilityModifiers {
+    pub strength: i32,
+    pub dexterity: i32,
+    pub constitution: i32,
+    pub intelligence: i32,
+    pub wisdom: i32,
+    pub charisma: i32,
+}
+
+impl AbilityModifiers {
+    pub fn get(&self, ability: &str) -> Option<i32> {
+        match ability {
+            "strength" => Some(self.strength),
+            "dexterity" => Some(self.dexterity),
+            "constitution" => Some(self.constitution),
+            "intelligence" => Some(self.intelligence),
+            "wisdom" => Some(self.wisdom),
+            "charisma" => Some(self.charisma),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DerivedStats {
+    pub ability_modifiers: AbilityModifiers,
+    pub proficiency_bonus: i32,
+    pub initiative_modifier: i32,
+    // The spell fields are null for classes that don't cast
+    pub spellcasting_ability: Option<String>,
+    pub spell_save_dc: Option<i32>,
+    pub spell_attack_bonus: Option<i32>,
+    // In pounds: strength score x 15
+    pub carrying_capacity: i32,
+}
+
+pub fn ability_score(stats: &serde_json::Value, ability: &str) -> i32 {
+    stats
+        .get(ability)
+        .or_else(|| stats.get(&ability[..3]))
+        .and_then(|score| score.as_i64())
+        .map_or(10, |score| score.clamp(1, 30) as i32)
+}
+
+pub fn ability_modifier(score: i32) -> i32 {
+    (score - 10).div_euclid(2)
+}
+
+// +2 at levels 1-4, rising by one every four levels to +6 at 17-20
+pub fn proficiency_bonus(level: i32) -> i32 {
+    2 + (level.clamp(1, 20) - 1) / 4
+}
+
+// `stats.spellcasting_ability` overrides the class default, e.g. for an Eldritch Knight
+fn spellcasting_ability(character: &Character) -> Option<String> {
+    if let Some(ability) = character.stats.get("spellcasting_ability").and_then(|a| a.as_str()) {
+        let ability = ability.trim().to_lowercase();
+        return ABILITIES.iter().find(|a| **a == ability || a[..3] == ability).map(|a| a.to_string());
+    }
+    let class = character.class.as_deref()?.trim().to_lowercase();
+    let ability = match class.split_whitespace().next()? {
+        "bard" | "paladin" | "sorcerer" | "warlock" => "charisma",
+        "cleric" | "druid" | "ranger" => "wisdom",
+        "wizard" | "artificer" => "intelligence",
+        _ => return None,
+    };
+    Some(ability.to_string())
+}
+
+pub fn derive(character: &Character) -> DerivedStats {
+    let modifier = |ability| ability_modifier(ability_score(&character.stats, ability));
+    let ability_modifiers = AbilityModifiers {
+        strength: modifier("strength"),
+        dexterity: modifier("dexterity"),
+        constitution: modifier("constitution"),
+        intelligence: modifier("intelligence"),
+        wisdom: modifier("wisdom"),
+        charisma: modifier("charisma"),
+    };
+    let proficiency_bonus = proficiency_bonus(character.level);
+    let spellcasting_ability = spellcasting_ability(character);
+    let casting_modifier = spellcasting_ability.as_deref().and_then(|ability| ability_modifiers.get(ability));
+
+    DerivedStats {
+        proficiency_bonus,
+        initiative_modifier: ability_modifiers.dexterity,
+        spell_save_dc: casting_modifier.map(|m| 8 + proficiency_bonus + m),
+        spell_attack_bonus: casting_modifier.map(|m| proficiency_bonus + m),
+        spellcasting_ability,
+        carrying_capacity: ability_score(&character.stats, "strength") * 15,
+        ability_modifiers,
+    }
+}
+
+// Weapon attack bonus, assuming proficiency. Inventory entries may flag a weapon as
+// `"ranged": true` (uses dexterity) or `"finesse": true` (the better of strength and dexterity).
+pub fn weapon_attack_bonus(character: &Character, derived: &DerivedStats, weapon: Option<&str>) -> i32 {
+    let item = weapon.and_then(|weapon| {
+        character.inventory.as_array()?.iter().find(|entry| {
+            entry.get("name").and_then(|n| n.as_str()).is_some_and(|name| name.trim().eq_ignore_ascii_case(weapon.trim()))
+        })
+    });
+    let flag = |key| item.and_then(|item| item.get(key)).and_then(|v| v.as_bool()).unwrap_or(false);
+    let modifiers = &derived.ability_modifiers;
+    let modifier = if flag("ranged") {
+        modifiers.dexterity
+    } else if flag("finesse") {
+        modifiers.strength.max(modifiers.dexterity)
+    } else {
+        modifiers.strength
+    };
+    derived.proficiency_bonus + modifier
+}
+
+// Skill to the ability it uses
+pub const SKILLS: [(&str, &str); 18] = [
+    ("acrobatics", "dexterity"),
+    ("animal_handling", "wisdom"),
+    ("arcana", "intelligence"),
+    ("athletics", "strength"),
+    ("deception", "charisma"),
+    ("history", "intelligence"),
+    ("insight", "wisdom"),
+    ("intimidation", "charisma"),
+    ("investigation", "intelligence"),
+    ("medicine", "wisdom"),
+    ("nature", "intelligence"),
+    ("perception", "wisdom"),
+    ("performance", "charisma"),
+    ("persuasion", "charisma"),
+    ("religion", "intelligence"),
+    ("sleight_of_hand", "dexterity"),
+    ("stealth", "dexterity"),
+    ("survival", "wisdom"),
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CheckModifier {
+    // A skill ("perception"), an ability ("strength"), a save ("wisdom_save") or "initiative"
+    pub check: String,
+    pub label: String,
+    pub ability: String,
+    pub modifier: i32,
+    pub proficient: bool,
+    pub expertise: bool,
+}
+
+fn full_ability(name: &str) -> Option<&'static str> {
+    ABILITIES.iter().copied().find(|ability| *ability == name || (name.len() == 3 && ability[..3] == *name))
+}
+
+// "Sleight of Hand" -> sleight_of_hand, "dex save" and "Dexterity saving throw" -> dexterity_save,
+// along with the ability the check uses
+fn parse_check(check: &str) -> Option<(String, &'static str)> {
+    let key = check
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("_");
+    if let Some(ability) = key.strip_suffix("_saving_throw").or_else(|| key.strip_suffix("_save")) {
+        let ability = full_ability(ability)?;
+        return Some((format!("{}_save", ability), ability));
+    }
+    if key == "initiative" {
+        return Some((key, "dexterity"));
+    }
+    if let Some(ability) = full_ability(&key) {
+        return Some((ability.to_string(), ability));
+    }
+    SKILLS.iter().find(|(skill, _)| *skill == key).map(|(skill, ability)| (skill.to_string(), *ability))
+}
+
+// Whether `stats[list]` names the check, in any spelling parse_check understands
+fn listed(stats: &serde_json::Value, list: &str, check: &str) -> bool {
+    stats.get(list).and_then(|names| names.as_array()).is_some_and(|names| {
+        names.iter().filter_map(|name| name.as_str()).any(|name| parse_check(name).is_some_and(|(key, _)| key == check))
+    })
+}
+
+// The modifier for a d20 check. Proficiencies are listed in `stats.proficiencies` (skills,
+// and saves such as "wisdom_save"); `stats.expertise` doubles the proficiency bonus.
+pub fn check_modifier(character: &Character, derived: &DerivedStats, check: &str) -> Option<CheckModifier> {
+    let (check, ability) = parse_check(check)?;
+    let expertise = listed(&character.stats, "expertise", &check);
+    let proficient = expertise || listed(&character.stats, "proficiencies", &check);
+    let bonus = match (expertise, proficient) {
+        (true, _) => 2 * derived.proficiency_bonus,
+        (false, true) => derived.proficiency_bonus,
+        _ => 0,
+    };
+
+    let mut label = check.replace('_', " ");
+    label[..1].make_ascii_uppercase();
+    if !check.ends_with("_save") && check != "initiative" {
+        label.push_str(" check");
+    }
+    Some(CheckModifier {
+        modifier: derived.ability_modifiers.get(ability)? + bonus,
+        ability: ability.to_string(),
+        label,
+        check,
+        proficient,
+        expertise,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    fn character(class: &str, level: i32, stats: serde_json::Value, inventory: serde_json::Value) -> Character {
+        Character {
+            id: Uuid::new_v4(),
+            campaign_id: Uuid::new_v4(),
+            player_id: None,
+            name: "Wren".to_string(),
+            race: None,
+            class: Some(class.to_string()),
+            level,
+            hp_current: Some(10),
+            hp_max: Some(10),
+            ac: Some(14),
+            speed: Some(30),
+            stats,
+            inventory,
+            spells: json!([]),
+            features: json!([]),
+            xp: 0,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_derived_stats() {
+        assert_eq!([1, 8, 9, 10, 11, 15, 20, 30].map(ability_modifier), [-5, -1, -1, 0, 0, 2, 5, 10]);
+        assert_eq!([1, 4, 5, 8, 9, 12, 13, 16, 17, 20].map(proficiency_bonus), [2, 2, 3, 3, 4, 4, 5, 5, 6, 6]);
+
+        let wizard = character("Wizard", 5, json!({"str": 8, "dexterity": 14, "intelligence": 17}), json!([]));
+        let derived = derive(&wizard);
+        assert_eq!(derived.ability_modifiers.strength, -1);
+        assert_eq!(derived.ability_modifiers.wisdom, 0);
+        assert_eq!(derived.proficiency_bonus, 3);
+        assert_eq!(derived.initiative_modifier, 2);
+        assert_eq!(derived.spellcasting_ability.as_deref(), Some("intelligence"));
+        assert_eq!(derived.spell_save_dc, Some(14));
+        assert_eq!(derived.spell_attack_bonus, Some(6));
+        assert_eq!(derived.carrying_capacity, 120);
+
+        let fighter = character("Fighter", 1, json!({"strength": 16}), json!([]));
+        assert_eq!(derive(&fighter).spell_save_dc, None);
+        let knight = character("Fighter", 3, json!({"strength": 16, "intelligence": 12, "spellcasting_ability": "INT"}), json!([]));
+        assert_eq!(derive(&knight).spell_save_dc, Some(11));
+    }
+
+    #[test]
+    fn test_weapon_attack_bonus() {
+        let rogue = character(
+            "Rogue",
+            1,
+            json!({"strength": 10, "dexterity": 18}),
+            json!([{"name": "Rapier", "finesse": true}, {"name": "Shortbow", "ranged": true}, {"name": "Club"}, "rope"]),
+        );
+        let derived = derive(&rogue);
+        assert_eq!(weapon_attack_bonus(&rogue, &derived, Some("rapier")), 6);
+        assert_eq!(weapon_attack_bonus(&rogue, &derived, Some("Shortbow")), 6);
+        assert_eq!(weapon_attack_bonus(&rogue, &derived, Some("Club")), 2);
+        assert_eq!(weapon_attack_bonus(&rogue, &derived, None), 2);
+    }
+
+    #[test]
+    fn test_check_modifiers() {
+        let rogue = character(
+            "Rogue",
+            5,
+            json!({"dex": 16, "wisdom": 12, "intelligence": 8, "proficiencies": ["Perception", "dex save"], "expertise": ["Sleight of Hand"]}),
+            json!([]),
+        );
+        let derived = derive(&rogue);
+        let check = |name| check_modifier(&rogue, &derived, name).map(|c| (c.check, c.label, c.modifier, c.proficient, c.expertise));
+
+        assert_eq!(check("perception"), Some(("perception".into(), "Perception check".into(), 4, true, false)));
+        assert_eq!(check("sleight of hand"), Some(("sleight_of_hand".into(), "Sleight of hand check".into(), 9, true, true)));
+        assert_eq!(check("Dexterity saving throw"), Some(("dexterity_save".into(), "Dexterity save".into(), 6, true, false)));
+        assert_eq!(check("wis_save"), Some(("wisdom_save".into(), "Wisdom save".into(), 1, false, false)));
+        assert_eq!(check("INT"), Some(("intelligence".into(), "Intelligence check".into(), -1, false, false)));
+        assert_eq!(check("initiative"), Some(("initiative".into(), "Initiative".into(), 3, false, false)));
+        assert_eq!(check("basket weaving"), None);
+    }
+}
@@ -1,28 +1,317 @@
-use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::Query;
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::Extension;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, watch, Mutex, Notify, RwLock};
 use uuid::Uuid;
 use chrono::Utc;
 use chrono::DateTime;
+use crate::usage::{usage, Metric, Subject};
+use futures::stream::SplitSink;
 use futures::{SinkExt, StreamExt};
-use crate::models::InitiativeEntry;
+use redis::AsyncCommands;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+use crate::dice::{fresh_seed, roll_dice, session_roll_seed, with_advantage, Advantage, DieFace, TermResult};
+use crate::fairness::{hidden_client_seed, RollProof};
+use crate::revocation::{Denylist, Revocations};
+use crate::sheet::{check_modifier, derive, weapon_attack_bonus, CheckModifier, DerivedStats};
+use crate::models::{InitiativeEntry, SkippedCombatant};
+use crate::presence::PresenceService;
+use crate::history::record_revision;
+use crate::metrics::RealtimeMetrics;
+use crate::events::DomainEvent;
+use crate::scope::{check, isolation_mode, Access, CampaignScope, Denied, IsolationMode, Permission, Resource, FROZEN_MESSAGE};
 
 // Shared state for managing active sessions and connections
 #[derive(Clone)]
 pub struct SessionState {
     pub sessions: Arc<RwLock<HashMap<Uuid, SessionInfo>>>,
+    // Set when REDIS_URL is configured; presence is mirrored there so every instance sees it
+    pub redis: Option<redis::aio::ConnectionManager>,
+    // Unconsumed WS tickets, used when Redis isn't configured
+    pub tickets: Arc<RwLock<HashMap<String, WsTicket>>>,
+    // App-wide online status, updated on connect/disconnect and heartbeats
+    pub presence: PresenceService,
+    // Flipped to true on shutdown; every socket and SSE stream holds a receiver until it closes
+    pub shutdown: Arc<watch::Sender<bool>>,
+    pub evictions: Arc<EvictionCounters>,
+    pub metrics: Arc<RealtimeMetrics>,
+    // Open sockets on this instance per user, by connection id, so revoking a user's tokens
+    // can close them
+    pub user_connections: Arc<RwLock<HashMap<Uuid, HashMap<Uuid, SocketSender>>>>,
+    pub revocations: Revocations,
+    // Access tokens logged out before they expire
+    pub denylist: Denylist,
 }
 
+// Sessions dropped from memory by the janitor, by reason
+#[derive(Default)]
+pub struct EvictionCounters {
+    pub idle: AtomicU64,
+    pub capacity: AtomicU64,
+}
+
+impl SessionState {
+    pub fn new(redis: Option<redis::aio::ConnectionManager>) -> Self {
+        SessionState {
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            redis: redis.clone(),
+            tickets: Arc::new(RwLock::new(HashMap::new())),
+            presence: PresenceService::new(redis),
+            shutdown: Arc::new(watch::channel(false).0),
+            evictions: Arc::new(EvictionCounters::default()),
+            metrics: Arc::new(RealtimeMetrics::default()),
+            user_connections: Arc::new(RwLock::new(HashMap::new())),
+            revocations: Revocations::default(),
+            denylist: Denylist::default(),
+        }
+    }
+}
+
+// Clients are told to wait this long before reconnecting, to give the next instance time to start
+pub const SHUTDOWN_RECONNECT_AFTER_MS: u64 = 5000;
+
+// How long shutdown waits for sockets and streams to close before giving up on them
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+// Resolves once shutdown has begun, including when it began before the call
+pub async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|down| *down).await;
+}
+
+// Tells every connected client the server is going away, then waits (bounded) for their
+// sockets to finish the message they're handling and close
+pub async fn shut_down(session_state: &SessionState) {
+    session_state.shutdown.send_replace(true);
+    let deadline = Instant::now() + SHUTDOWN_GRACE;
+    while session_state.shutdown.receiver_count() > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let remaining = session_state.shutdown.receiver_count();
+    if remaining > 0 {
+        tracing::warn!(remaining, "Shutting down with connections still open");
+    }
+}
+
+// Short-lived, single-use credential for the /ws upgrade, minted by POST /sessions/:id/ws-ticket
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WsTicket {
+    pub user_id: Uuid,
+    pub username: String,
+    pub session_id: Uuid,
+    pub is_dm: bool,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub const WS_TICKET_TTL_SECS: i64 = 30;
+
 #[derive(Clone)]
 pub struct SessionInfo {
     pub session_id: Uuid,
     pub campaign_id: Uuid,
     pub connections: Arc<RwLock<HashMap<Uuid, ConnectionInfo>>>,
+    pub history: Arc<RwLock<MessageHistory>>,
+    // Mirror of every broadcast as (seq, encoded JSON), for SSE subscribers
+    pub events: broadcast::Sender<(Option<u64>, String)>,
+    pub turn_timer: Arc<Mutex<TurnTimer>>,
+    // Users whose chat the DM muted, until when (None = for the rest of the session)
+    pub muted: Arc<RwLock<HashMap<Uuid, Option<DateTime<Utc>>>>>,
+    // Last join or broadcast; the janitor evicts the least recently active sessions first
+    pub last_active: Arc<Mutex<Instant>>,
+    // Recent table talk, fed by the event bus, that AI requests are given as context
+    pub ai_context: Arc<RwLock<VecDeque<String>>>,
+}
+
+// Per-turn time limit set by the DM with SetTurnTimer; lives only as long as the session entry
+#[derive(Default)]
+pub struct TurnTimer {
+    pub seconds: Option<u32>,
+    // Advance the turn on expiry instead of leaving it to the DM
+    pub auto_advance: bool,
+    task: Option<tokio::task::AbortHandle>,
+    // Turn being counted down and when it runs out, kept up to date by the running task
+    deadline: Option<(Uuid, tokio::time::Instant)>,
+    // Set while the session is paused; holds the turn and time left to resume with
+    paused: bool,
+    frozen: Option<(Uuid, Duration)>,
+}
+
+// Seconds remaining at which countdown warnings are broadcast
+const TURN_TIMER_WARNINGS: [u32; 3] = [30, 10, 5];
+
+// Broadcasts buffered per SSE subscriber before it starts lagging
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+impl SessionInfo {
+    fn new(session_id: Uuid, campaign_id: Uuid) -> Self {
+        SessionInfo {
+            session_id,
+            campaign_id,
+            connections: Arc::new(RwLock::new(HashMap::new())),
+            history: Arc::new(RwLock::new(MessageHistory::default())),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            turn_timer: Arc::new(Mutex::new(TurnTimer::default())),
+            muted: Arc::new(RwLock::new(HashMap::new())),
+            last_active: Arc::new(Mutex::new(Instant::now())),
+            ai_context: Arc::new(RwLock::new(VecDeque::new())),
+        }
+    }
+
+    // Picks up where an evicted entry left off, so sequence numbers never go backwards
+    fn restore(session_id: Uuid, campaign_id: Uuid, live_state: Option<LiveState>) -> Self {
+        let mut session_info = SessionInfo::new(session_id, campaign_id);
+        if let Some(live_state) = live_state {
+            session_info.history = Arc::new(RwLock::new(MessageHistory { last_seq: live_state.last_seq, messages: VecDeque::new() }));
+            session_info.turn_timer = Arc::new(Mutex::new(TurnTimer {
+                seconds: live_state.turn_timer_seconds,
+                auto_advance: live_state.auto_advance,
+                ..TurnTimer::default()
+            }));
+        }
+        session_info
+    }
+}
+
+// What an in-memory session needs to carry on after eviction, saved to sessions.live_state
+#[derive(Debug, Serialize, Deserialize)]
+struct LiveState {
+    last_seq: u64,
+    turn_timer_seconds: Option<u32>,
+    auto_advance: bool,
+}
+
+// Recently broadcast session events, kept so clients can recover from gaps
+#[derive(Default)]
+pub struct MessageHistory {
+    pub last_seq: u64,
+    pub messages: VecDeque<(u64, String)>,
+}
+
+// Number of sequenced messages kept per session for replay
+const HISTORY_CAPACITY: usize = 500;
+
+// Frame encoding negotiated per connection through the Sec-WebSocket-Protocol header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WireFormat {
+    Json,
+    MessagePack,
+}
+
+pub const JSON_PROTOCOL: &str = "yoda.json";
+pub const MSGPACK_PROTOCOL: &str = "yoda.msgpack";
+
+// How frames are encoded for one connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameEncoding {
+    pub format: WireFormat,
+    // Payloads at least this large are zlib-compressed; None when not negotiated
+    pub compress_min_bytes: Option<usize>,
+}
+
+// Server-side compression threshold from WS_COMPRESS_MIN_BYTES; unset or 0 disables compression
+fn compression_threshold() -> Option<usize> {
+    std::env::var("WS_COMPRESS_MIN_BYTES")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|bytes| *bytes > 0)
+}
+
+// Frames a connection may have waiting to be written before it counts as too slow
+const OUTBOUND_QUEUE_CAPACITY: usize = 256;
+
+// How long a closing connection gets to write out its queue
+const WRITER_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Queue into a client socket, shared so broadcasts can reach the connection. Frames are
+// written in queue order by the connection's writer task; a full queue never blocks the
+// sender, it disconnects the client instead.
+#[derive(Clone)]
+pub struct SocketSender {
+    queue: mpsc::Sender<Message>,
+    pub encoding: FrameEncoding,
+    // Signalled when the DM kicks this connection; its read loop closes the socket
+    kick: Arc<Notify>,
+    // Signalled when the queue overflows; its read loop drops the connection
+    overflow: Arc<Notify>,
+    // Signalled when the user's tokens are revoked; its read loop closes the socket
+    revoked: Arc<Notify>,
+}
+
+impl SocketSender {
+    fn new(queue: mpsc::Sender<Message>, encoding: FrameEncoding) -> Self {
+        SocketSender {
+            queue,
+            encoding,
+            kick: Arc::new(Notify::new()),
+            overflow: Arc::new(Notify::new()),
+            revoked: Arc::new(Notify::new()),
+        }
+    }
+
+    // Queues a JSON-encoded message in the connection's wire format
+    fn send(&self, text: String) -> Result<(), String> {
+        let frame = encode_frame(text, self.encoding)?;
+        self.send_frame(frame)
+    }
+
+    fn send_frame(&self, frame: Message) -> Result<(), String> {
+        self.queue.try_send(frame).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => {
+                self.overflow.notify_one();
+                "Outbound queue is full".to_string()
+            }
+            mpsc::error::TrySendError::Closed(_) => "Connection is closed".to_string(),
+        })
+    }
+}
+
+// Writes queued frames until the queue closes, the client goes away, or a close frame is sent
+async fn write_frames(mut sink: SplitSink<WebSocket, Message>, mut frames: mpsc::Receiver<Message>) {
+    while let Some(frame) = frames.recv().await {
+        let closing = matches!(frame, Message::Close(_));
+        if let Err(e) = sink.send(frame).await {
+            tracing::warn!(error = %e, "Failed to write to WebSocket");
+            return;
+        }
+        if closing {
+            break;
+        }
+    }
+    let _ = sink.close().await;
+}
+
+// MessagePack frames mirror the JSON structure (ids and timestamps stay strings).
+// Large payloads are sent as binary zlib frames; MessagePack messages are always maps,
+// so the zlib header byte (0x78) can't be mistaken for an uncompressed one.
+fn encode_frame(text: String, encoding: FrameEncoding) -> Result<Message, String> {
+    let payload = match encoding.format {
+        WireFormat::Json => text.into_bytes(),
+        WireFormat::MessagePack => {
+            let value: serde_json::Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+            rmp_serde::to_vec_named(&value).map_err(|e| format!("Failed to encode MessagePack: {}", e))?
+        }
+    };
+
+    if encoding.compress_min_bytes.is_some_and(|min| payload.len() >= min) {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&payload).map_err(|e| format!("Failed to compress frame: {}", e))?;
+        let compressed = encoder.finish().map_err(|e| format!("Failed to compress frame: {}", e))?;
+        return Ok(Message::Binary(compressed));
+    }
+
+    match encoding.format {
+        WireFormat::Json => String::from_utf8(payload).map(Message::Text).map_err(|e| e.to_string()),
+        WireFormat::MessagePack => Ok(Message::Binary(payload)),
+    }
 }
 
 #[derive(Clone)]
@@ -30,6 +319,82 @@ pub struct ConnectionInfo {
     pub user_id: Uuid,
     pub username: String,
     pub is_dm: bool,
+    pub sender: SocketSender,
+    pub last_typing: Option<Instant>,
+    pub connected_at: DateTime<Utc>,
+    pub last_acked_seq: u64,
+}
+
+// Who a broadcast is delivered to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recipients {
+    All,
+    AllExcept(Uuid),
+    // DM connections only. These are never numbered for replay or sent over SSE,
+    // since neither path knows whether the reader is a DM.
+    DmOnly,
+}
+
+impl Recipients {
+    fn includes(&self, conn: &ConnectionInfo) -> bool {
+        match self {
+            Recipients::All => true,
+            Recipients::AllExcept(user_id) => conn.user_id != *user_id,
+            Recipients::DmOnly => conn.is_dm,
+        }
+    }
+}
+
+// Minimum interval between forwarded "is typing" events from the same user
+const TYPING_DEBOUNCE: Duration = Duration::from_secs(2);
+
+// Per-connection flood guard: messages beyond the limit within a window are rejected
+const MESSAGE_RATE_LIMIT: u32 = 30;
+const MESSAGE_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+struct RateWindow {
+    started: Instant,
+    count: u32,
+}
+
+impl RateWindow {
+    fn new() -> Self {
+        RateWindow { started: Instant::now(), count: 0 }
+    }
+
+    // Counts one message; when over the limit, returns how long until the window resets
+    fn check(&mut self) -> Result<(), Duration> {
+        if self.started.elapsed() >= MESSAGE_RATE_WINDOW {
+            self.started = Instant::now();
+            self.count = 0;
+        }
+        self.count += 1;
+        if self.count > MESSAGE_RATE_LIMIT {
+            return Err(MESSAGE_RATE_WINDOW.saturating_sub(self.started.elapsed()));
+        }
+        Ok(())
+    }
+}
+
+// Incoming frame: the tagged message plus, for connections joined to several sessions,
+// the session it's meant for
+#[derive(Debug)]
+struct ClientEnvelope {
+    message: ClientMessage,
+    session_id: Option<Uuid>,
+}
+
+impl ClientEnvelope {
+    // Split by hand rather than with #[serde(flatten)], which buffers values in a way that
+    // loses MessagePack's string-encoded ids
+    fn from_value(mut value: serde_json::Value) -> Result<Self, String> {
+        let session_id = match value.as_object_mut().and_then(|fields| fields.remove("session_id")) {
+            Some(session_id) => serde_json::from_value(session_id).map_err(|e| e.to_string())?,
+            None => None,
+        };
+        let message = serde_json::from_value(value).map_err(|e| e.to_string())?;
+        Ok(ClientEnvelope { message, session_id })
+    }
 }
 
 // WebSocket message types
@@ -38,34 +403,222 @@ pub struct ConnectionInfo {
 pub enum ClientMessage {
     JoinSession { session_id: Uuid },
     LeaveSession { session_id: Uuid },
-    DiceRoll { dice: String, reason: Option<String> },
+    // DM only; with `ban` the user can't rejoin the session until unbanned
+    KickPlayer { user_id: Uuid, #[serde(default)] ban: bool },
+    UnbanPlayer { user_id: Uuid },
+    // DM only; `seconds: null` mutes for the rest of the session
+    MutePlayer { user_id: Uuid, seconds: Option<u32> },
+    UnmutePlayer { user_id: Uuid },
+    // `dm_only` rolls are seen by the DM and the roller only. `secret` rolls are DM only and
+    // seen by nobody else; with `announce` the players are told a roll happened, not its result.
+    // `advantage` rolls the expression's 1d20 twice.
+    DiceRoll {
+        dice: String,
+        reason: Option<String>,
+        #[serde(default)]
+        advantage: Option<Advantage>,
+        #[serde(default)]
+        dm_only: bool,
+        #[serde(default)]
+        secret: bool,
+        #[serde(default)]
+        announce: bool,
+        // Mixed into the roll's seed, so the server can't pick the result (see fairness.rs)
+        #[serde(default)]
+        client_seed: Option<String>,
+        // Makes this an attack: the damage is rolled with it, with doubled dice on a natural 20
+        #[serde(default)]
+        damage: Option<String>,
+    },
+    // Rolls a d20 check for the character with the modifier worked out by the server, e.g.
+    // "perception", "dexterity_save", "strength" or "initiative"
+    RollCheck {
+        character_id: Uuid,
+        check: String,
+        #[serde(default)]
+        advantage: Option<Advantage>,
+        #[serde(default)]
+        dm_only: bool,
+        #[serde(default)]
+        client_seed: Option<String>,
+    },
+    // DM only; rolls the same dice once for each of the given initiative entries, e.g. a
+    // DEX save for every goblin
+    GroupRoll {
+        session_id: Uuid,
+        entry_ids: Vec<Uuid>,
+        dice: String,
+        reason: Option<String>,
+        #[serde(default)]
+        advantage: Option<Advantage>,
+        #[serde(default)]
+        dm_only: bool,
+    },
+    RollAbilityScores { character_id: Uuid },
     ChatMessage { message: String },
+    // Toggles the sender's `emoji` reaction on a chat message
+    React { message_id: Uuid, emoji: String },
     UpdateGameState { game_state: serde_json::Value },
-    PlayerAction { action: String, data: serde_json::Value },
+    PatchGameState { patch: json_patch::Patch },
+    PlayerAction { character_id: Uuid, action: DeclaredAction },
     UpdateCharacter { character_id: Uuid, updates: serde_json::Value },
     UpdateInitiative { session_id: Uuid, initiative_order: Vec<InitiativeEntry> },
     NextTurn { session_id: Uuid },
+    // DM only; stops combat and suggests XP and loot for what was defeated
+    EndCombat { session_id: Uuid },
+    SetTurnTimer { session_id: Uuid, seconds: Option<u32>, auto_advance: bool },
     UpdateHP { character_id: Uuid, hp_current: i32, hp_max: Option<i32> },
     CreateEventLog { session_id: Uuid, event_type: String, event_data: serde_json::Value },
-    AIRequest { prompt: String, request_type: String, context: Option<String> },
+    AIRequest { prompt: String, request_type: String, context: Option<String>, #[serde(default)] dm_only: bool },
+    Typing { is_typing: bool },
+    Ack { seq: u64 },
+    RequestReplay { since_seq: Option<u64> },
+    Heartbeat,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum ServerMessage {
-    SessionJoined { session_id: Uuid, players: Vec<PlayerInfo> },
+    SessionJoined { session_id: Uuid, players: Vec<PlayerInfo>, last_seq: u64 },
     PlayerJoined { player: PlayerInfo },
     PlayerLeft { player_id: Uuid },
-    DiceRolled { player_id: Uuid, result: DiceResult },
-    ChatMessage { player_id: Uuid, message: String, timestamp: DateTime<Utc> },
+    // Sent only to the kicked player, right before their socket is closed
+    Kicked { session_id: Uuid, banned: bool },
+    PlayerUnbanned { session_id: Uuid, user_id: Uuid },
+    PlayerMuted { session_id: Uuid, user_id: Uuid, until: Option<DateTime<Utc>> },
+    PlayerUnmuted { session_id: Uuid, user_id: Uuid },
+    // `roll_id` is the roll's `dice_roll` event log id, when rolled in a session
+    DiceRolled { player_id: Uuid, roll_id: Option<Uuid>, result: DiceResult, #[serde(default)] secret: bool },
+    // One message for a whole GroupRoll, in the order the entries were given
+    GroupRolled { session_id: Uuid, player_id: Uuid, rolls: Vec<GroupRollResult> },
+    // Sent to everyone but the DM when the DM makes an announced secret roll
+    SecretRollMade { player_id: Uuid, roll_id: Uuid },
+    ActionDeclared { event_id: Uuid, player_id: Uuid, character_id: Uuid, character_name: String, action: DeclaredAction, numbers: ActionNumbers, created_at: DateTime<Utc> },
+    AbilityScoresRolled { roll_id: Uuid, character_id: Uuid, character_name: String, rolled_by: Uuid, rolls: Vec<Vec<i32>>, scores: Vec<i32> },
+    // `message_id` is the chat message's event log id; `rolls` has one result per `[[dice]]` in the text
+    ChatMessage { message_id: Uuid, player_id: Uuid, message: String, rolls: Vec<DiceResult>, timestamp: DateTime<Utc> },
+    // Every reaction on the message, emoji to the users who reacted
+    ReactionsUpdated { message_id: Uuid, reactions: BTreeMap<String, Vec<Uuid>> },
     GameStateUpdated { game_state: serde_json::Value },
+    GameStatePatched { session_id: Uuid, patch: json_patch::Patch },
     CharacterUpdated { character: CharacterInfo },
     InitiativeUpdated { session_id: Uuid, initiative_order: Vec<InitiativeEntry>, current_turn: Option<Uuid> },
-    TurnChanged { session_id: Uuid, current_turn: Uuid, round: i32 },
+    TurnChanged { session_id: Uuid, current_turn: Uuid, round: i32, skipped: Vec<SkippedCombatant> },
+    // `reward_id` is set when something was defeated; the suggestion itself goes to the DM only
+    CombatEnded { session_id: Uuid, reward_id: Option<Uuid> },
+    RewardsSuggested { reward: crate::models::EncounterReward },
+    TurnTimerUpdated { session_id: Uuid, seconds: Option<u32>, auto_advance: bool },
+    TurnTimerStarted { session_id: Uuid, current_turn: Uuid, expires_at: DateTime<Utc> },
+    TurnTimerWarning { session_id: Uuid, current_turn: Uuid, seconds_remaining: u32 },
+    TurnTimerExpired { session_id: Uuid, current_turn: Uuid, auto_advanced: bool },
     HPUpdated { character_id: Uuid, hp_current: i32, hp_max: i32 },
     EventLogCreated { event_id: Uuid, event_type: String, event_data: serde_json::Value, created_by: Uuid, created_at: DateTime<Utc> },
     AIResponse { response: String, request_type: String, tokens_used: Option<i32>, model: String },
-    Error { message: String },
+    PlayerTyping { player_id: Uuid, username: String, is_typing: bool },
+    ReplayUnavailable { session_id: Uuid, oldest_seq: u64, last_seq: u64 },
+    UserPresenceChanged { user_id: Uuid, username: String, online: bool, last_seen_at: Option<DateTime<Utc>> },
+    SessionPaused { session_id: Uuid, paused_at: DateTime<Utc> },
+    // `paused_seconds` is the session's total break time so far
+    SessionResumed { session_id: Uuid, paused_seconds: i32 },
+    // The campaign changed hands; the previous DM stays on as a co-DM
+    CampaignOwnerChanged { campaign_id: Uuid, previous_dm_id: Uuid, dm_id: Uuid },
+    ServerShuttingDown { reconnect_after_ms: u64 },
+    Error {
+        code: ErrorCode,
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        context: Option<serde_json::Value>,
+    },
+}
+
+// Machine-readable reason carried by ServerMessage::Error, so clients don't match on text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    AccessDenied,
+    NotInSession,
+    NotFound,
+    Conflict,
+    InvalidMessage,
+    InvalidRequest,
+    InvalidDice,
+    InvalidAction,
+    RateLimited,
+    Muted,
+    Internal,
+}
+
+// Failure of a client message, sent back to that client as ServerMessage::Error
+#[derive(Debug)]
+pub struct WsError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub context: Option<serde_json::Value>,
+    // What failed on the server; logged with the connection, never sent to the client
+    pub cause: Option<String>,
+}
+
+impl WsError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        WsError { code, message: message.into(), context: None, cause: None }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        WsError::new(ErrorCode::Internal, message)
+    }
+
+    fn database(e: sqlx::Error) -> Self {
+        WsError::internal("Internal server error").caused_by(e)
+    }
+
+    pub fn caused_by(mut self, cause: impl std::fmt::Display) -> Self {
+        self.cause = Some(cause.to_string());
+        self
+    }
+
+    pub fn with_context(mut self, context: serde_json::Value) -> Self {
+        self.context = Some(context);
+        self
+    }
+}
+
+impl std::fmt::Display for WsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl From<WsError> for ServerMessage {
+    fn from(error: WsError) -> Self {
+        ServerMessage::Error { code: error.code, message: error.message, context: error.context }
+    }
+}
+
+impl ServerMessage {
+    // Ephemeral messages are not sequenced or kept for replay
+    fn is_ephemeral(&self) -> bool {
+        matches!(self, ServerMessage::PlayerTyping { .. } | ServerMessage::ServerShuttingDown { .. })
+    }
+}
+
+// Wire format for server messages: the tagged message plus, for broadcasts, the session it
+// came from and its per-session sequence number
+#[derive(Serialize)]
+struct Envelope<'a> {
+    #[serde(flatten)]
+    message: &'a ServerMessage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+}
+
+fn encode_message(message: &ServerMessage, seq: Option<u64>) -> String {
+    serde_json::to_string(&Envelope { message, session_id: None, seq }).unwrap()
+}
+
+fn encode_broadcast(message: &ServerMessage, session_id: Uuid, seq: Option<u64>) -> String {
+    serde_json::to_string(&Envelope { message, session_id: Some(session_id), seq }).unwrap()
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -75,6 +628,14 @@ pub struct PlayerInfo {
     pub is_dm: bool,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PresenceEntry {
+    pub user_id: Uuid,
+    pub username: String,
+    pub is_dm: bool,
+    pub connected_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CharacterInfo {
     pub id: Uuid,
@@ -86,6 +647,34 @@ pub struct CharacterInfo {
     pub hp_max: Option<i32>,
     pub ac: Option<i32>,
     pub speed: Option<i32>,
+    pub stats: serde_json::Value,
+    pub inventory: serde_json::Value,
+    pub spells: serde_json::Value,
+    pub features: serde_json::Value,
+    pub xp: i32,
+    pub derived: DerivedStats,
+}
+
+impl From<crate::models::Character> for CharacterInfo {
+    fn from(c: crate::models::Character) -> Self {
+        CharacterInfo {
+            derived: derive(&c),
+            id: c.id,
+            name: c.name,
+            race: c.race,
+            class: c.class,
+            level: c.level,
+            hp_current: c.hp_current,
+            hp_max: c.hp_max,
+            ac: c.ac,
+            speed: c.speed,
+            stats: c.stats,
+            inventory: c.inventory,
+            spells: c.spells,
+            features: c.features,
+            xp: c.xp,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -94,165 +683,869 @@ pub struct DiceResult {
     pub result: i32,
     pub rolls: Vec<i32>,
     pub reason: Option<String>,
+    // The rolls are drawn from this seed, so clients can run a deterministic animation
+    pub seed: u64,
+    // Position in a seeded session's roll sequence; the seed is derived from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roll_counter: Option<u64>,
+    // Commitment to the server seed the roll was drawn from, for verifying it later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof: Option<RollProof>,
+    // Attacks only: the damage roll, and whether the d20 came up 20 so its dice were doubled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub damage: Option<Box<DiceResult>>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub critical: bool,
+    // One per physical die, in roll order, to land each animated die on its face
+    pub faces: Vec<DieFace>,
+    // Per-term breakdown of the expression
+    pub terms: Vec<TermResult>,
+    // Set when the d20 was rolled twice; the dropped die is flagged in `faces`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub advantage: Option<Advantage>,
+    // Set for checks the server rolled from a character's modifiers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check: Option<CheckRoll>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CheckRoll {
+    pub character_id: Uuid,
+    pub character_name: String,
+    #[serde(flatten)]
+    pub modifier: CheckModifier,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GroupRollResult {
+    pub entry_id: Uuid,
+    pub name: String,
+    pub roll_id: Uuid,
+    pub result: DiceResult,
+}
+
+// What a character declares they're doing; the DM adjudicates the outcome.
+// Target ids refer to initiative entries.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DeclaredAction {
+    Attack { target_id: Option<Uuid>, weapon: Option<String> },
+    Cast { spell: String, level: Option<i32>, #[serde(default)] target_ids: Vec<Uuid> },
+    Move { distance: i32, destination: Option<String> },
+    UseItem { item: String, target_id: Option<Uuid> },
+    Custom { description: String },
+}
+
+const MAX_CUSTOM_ACTION_LEN: usize = 500;
+
+// What the table rolls against for a declared action, from the character's derived stats
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ActionNumbers {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attack_bonus: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save_dc: Option<i32>,
+}
+
+fn action_numbers(character: &crate::models::Character, action: &DeclaredAction) -> ActionNumbers {
+    let derived = derive(character);
+    match action {
+        DeclaredAction::Attack { weapon, .. } => ActionNumbers {
+            attack_bonus: Some(weapon_attack_bonus(character, &derived, weapon.as_deref())),
+            save_dc: None,
+        },
+        DeclaredAction::Cast { .. } => ActionNumbers {
+            attack_bonus: derived.spell_attack_bonus,
+            save_dc: derived.spell_save_dc,
+        },
+        _ => ActionNumbers::default(),
+    }
+}
+
+// Inventory and spell lists hold plain names or objects with a "name" field
+fn has_named(list: &serde_json::Value, name: &str) -> bool {
+    let name = name.trim();
+    list.as_array().is_some_and(|entries| {
+        entries.iter().any(|entry| {
+            entry
+                .as_str()
+                .or_else(|| entry.get("name").and_then(|n| n.as_str()))
+                .is_some_and(|entry_name| entry_name.trim().eq_ignore_ascii_case(name))
+        })
+    })
+}
+
+// Checks a declared action against what the character can actually do
+fn validate_action(character: &crate::models::Character, action: &DeclaredAction) -> Result<(), String> {
+    // Downed characters can still declare free-form things like death saves
+    if character.hp_current == Some(0) && !matches!(action, DeclaredAction::Custom { .. }) {
+        return Err(format!("{} is down and can't act", character.name));
+    }
+
+    match action {
+        DeclaredAction::Attack { weapon: Some(weapon), .. } if !has_named(&character.inventory, weapon) => {
+            Err(format!("{} isn't carrying {}", character.name, weapon))
+        }
+        DeclaredAction::Attack { .. } => Ok(()),
+        DeclaredAction::Cast { level: Some(level), .. } if !(0..=9).contains(level) => {
+            Err("Spell level must be between 0 and 9".to_string())
+        }
+        DeclaredAction::Cast { spell, .. } if !has_named(&character.spells, spell) => {
+            Err(format!("{} doesn't know {}", character.name, spell))
+        }
+        DeclaredAction::Cast { .. } => Ok(()),
+        DeclaredAction::Move { distance, .. } if *distance <= 0 => Err("Distance must be positive".to_string()),
+        DeclaredAction::Move { distance, .. } if character.speed.is_some_and(|speed| *distance > speed) => {
+            Err(format!("{} can move at most {} ft", character.name, character.speed.unwrap_or_default()))
+        }
+        DeclaredAction::Move { .. } => Ok(()),
+        DeclaredAction::UseItem { item, .. } if !has_named(&character.inventory, item) => {
+            Err(format!("{} isn't carrying {}", character.name, item))
+        }
+        DeclaredAction::UseItem { .. } => Ok(()),
+        DeclaredAction::Custom { description } if description.trim().is_empty() => {
+            Err("Describe the action".to_string())
+        }
+        DeclaredAction::Custom { description } if description.len() > MAX_CUSTOM_ACTION_LEN => {
+            Err(format!("Action description is limited to {} characters", MAX_CUSTOM_ACTION_LEN))
+        }
+        DeclaredAction::Custom { .. } => Ok(()),
+    }
+}
+
+fn ticket_key(ticket: &str) -> String {
+    format!("yoda:ws-ticket:{}", ticket)
+}
+
+// Stores a ticket and returns the opaque value the client passes to /ws?ticket=
+pub async fn issue_ws_ticket(session_state: &SessionState, ticket: WsTicket) -> Result<String, String> {
+    let token = Uuid::new_v4().simple().to_string();
+    if let Some(redis) = &session_state.redis {
+        let mut conn = redis.clone();
+        let value = serde_json::to_string(&ticket).map_err(|e| format!("Failed to serialize ticket: {}", e))?;
+        conn.set_ex::<_, _, ()>(ticket_key(&token), value, WS_TICKET_TTL_SECS as u64)
+            .await
+            .map_err(|e| format!("Failed to store ticket: {}", e))?;
+    } else {
+        let mut tickets = session_state.tickets.write().await;
+        let now = Utc::now();
+        tickets.retain(|_, t| t.expires_at > now);
+        tickets.insert(token.clone(), ticket);
+    }
+    Ok(token)
+}
+
+// Removes the ticket so it can only be used once; expired tickets are rejected
+pub async fn consume_ws_ticket(session_state: &SessionState, token: &str) -> Option<WsTicket> {
+    let ticket = if let Some(redis) = &session_state.redis {
+        let mut conn = redis.clone();
+        match conn.get_del::<_, Option<String>>(ticket_key(token)).await {
+            Ok(value) => value.and_then(|v| serde_json::from_str::<WsTicket>(&v).ok()),
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to read ticket from Redis");
+                None
+            }
+        }
+    } else {
+        session_state.tickets.write().await.remove(token)
+    };
+    ticket.filter(|t| t.expires_at > Utc::now())
+}
+
+#[derive(Deserialize)]
+pub struct WsParams {
+    pub ticket: Option<String>,
+    // Client accepts zlib-compressed frames for large payloads
+    pub compress: Option<bool>,
 }
 
 // WebSocket upgrade handler
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
+    Query(params): Query<WsParams>,
     Extension(pool): Extension<PgPool>,
     Extension(session_state): Extension<SessionState>,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_socket(socket, pool, session_state))
+    let ticket = match params.ticket {
+        Some(token) => consume_ws_ticket(&session_state, &token).await,
+        None => None,
+    };
+    let ticket = match ticket {
+        Some(ticket) => ticket,
+        None => return (StatusCode::UNAUTHORIZED, "Missing or invalid WebSocket ticket").into_response(),
+    };
+    // Compression needs both the server threshold and the client's opt-in
+    let compress_min_bytes = if params.compress.unwrap_or(false) { compression_threshold() } else { None };
+    ws.protocols([MSGPACK_PROTOCOL, JSON_PROTOCOL])
+        .on_upgrade(move |socket| handle_socket(socket, pool, session_state, ticket, compress_min_bytes))
+        .into_response()
 }
 
-async fn handle_socket(socket: WebSocket, pool: PgPool, session_state: SessionState) {
-    let (mut sender, mut receiver) = socket.split();
+async fn handle_socket(
+    socket: WebSocket,
+    pool: PgPool,
+    session_state: SessionState,
+    ticket: WsTicket,
+    compress_min_bytes: Option<usize>,
+) {
+    // JSON unless the client negotiated MessagePack
+    let format = match socket.protocol().and_then(|p| p.to_str().ok()) {
+        Some(MSGPACK_PROTOCOL) => WireFormat::MessagePack,
+        _ => WireFormat::Json,
+    };
+    let (sink, mut receiver) = socket.split();
+    let (queue, frames) = mpsc::channel(OUTBOUND_QUEUE_CAPACITY);
+    let mut writer = tokio::spawn(write_frames(sink, frames));
+    let sender = SocketSender::new(queue, FrameEncoding { format, compress_min_bytes });
     
-    // TODO: Extract user info from JWT token in WebSocket upgrade
-    // For now, we'll use a placeholder user
-    let user_id = Uuid::new_v4();
-    let username = "Anonymous".to_string();
-    let is_dm = false;
+    // Identity was resolved when the ticket was minted
+    let user_id = ticket.user_id;
+    let username = ticket.username;
     
-    let mut current_session: Option<Uuid> = None;
+    // Sessions this connection has joined, with whether the user is DM there
+    let mut joined: HashMap<Uuid, bool> = HashMap::new();
+    let mut rate = RateWindow::new();
+    let mut shutdown = session_state.shutdown.subscribe();
+    let _connected = session_state.metrics.track_connection();
+    let connection_id = Uuid::new_v4();
+    session_state.user_connections.write().await.entry(user_id).or_default().insert(connection_id, sender.clone());
+
+    if session_state.presence.connect(user_id).await {
+        broadcast_user_presence(&session_state, &pool, user_id, &username, true).await;
+    }
     
-    while let Some(msg) = receiver.next().await {
-        match msg {
-            Ok(Message::Text(text)) => {
-                match serde_json::from_str::<ClientMessage>(&text) {
-                    Ok(client_msg) => {
-                        match handle_client_message(
-                            client_msg,
-                            &pool,
-                            &session_state,
-                            user_id,
-                            &username,
-                            is_dm,
-                            &mut current_session,
-                        ).await {
-                            Ok(server_msg) => {
-                                if let Err(e) = sender.send(Message::Text(serde_json::to_string(&server_msg).unwrap())).await {
-                                    eprintln!("Failed to send message: {}", e);
-                                    break;
-                                }
-                            }
-                            Err(e) => {
-                                let error_msg = ServerMessage::Error { message: e };
-                                if let Err(e) = sender.send(Message::Text(serde_json::to_string(&error_msg).unwrap())).await {
-                                    eprintln!("Failed to send error message: {}", e);
-                                    break;
-                                }
-                            }
+    loop {
+        // A message already being handled finishes before shutdown is noticed
+        let msg = tokio::select! {
+            msg = receiver.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = shutdown_requested(&mut shutdown) => {
+                let notice = ServerMessage::ServerShuttingDown { reconnect_after_ms: SHUTDOWN_RECONNECT_AFTER_MS };
+                let _ = sender.send(encode_message(&notice, None));
+                let _ = sender.send_frame(Message::Close(Some(CloseFrame {
+                    code: axum::extract::ws::close_code::AWAY,
+                    reason: "Server shutting down".into(),
+                })));
+                break;
+            }
+            _ = sender.kick.notified() => {
+                // Kicked from one session; the socket stays open while others remain
+                joined = still_joined(&session_state, joined, user_id).await;
+                if !joined.is_empty() {
+                    continue;
+                }
+                let _ = sender.send_frame(Message::Close(Some(CloseFrame {
+                    code: axum::extract::ws::close_code::POLICY,
+                    reason: "Removed from session by the DM".into(),
+                })));
+                break;
+            }
+            _ = sender.revoked.notified() => {
+                let _ = sender.send_frame(Message::Close(Some(CloseFrame {
+                    code: REVOKED_CLOSE_CODE,
+                    reason: "SessionRevoked".into(),
+                })));
+                break;
+            }
+            _ = sender.overflow.notified() => {
+                // Too slow to keep up; dropped rather than let it hold up the session
+                tracing::warn!(%user_id, %connection_id, "Disconnecting user, outbound queue overflowed");
+                session_state.metrics.overflow_disconnects.fetch_add(1, Ordering::Relaxed);
+                writer.abort();
+                break;
+            }
+        };
+        let decoded = match msg {
+            Ok(Message::Text(text)) => serde_json::from_str(&text).map_err(|e| e.to_string()).and_then(ClientEnvelope::from_value),
+            Ok(Message::Binary(bytes)) if format == WireFormat::MessagePack => {
+                rmp_serde::from_slice(&bytes).map_err(|e| e.to_string()).and_then(ClientEnvelope::from_value)
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => {
+                session_state.presence.heartbeat(user_id).await;
+                continue;
+            }
+            _ => continue,
+        };
+        session_state.metrics.messages_received.fetch_add(1, Ordering::Relaxed);
+        usage().record(Subject::User(user_id), Metric::WsMessages, 1);
+        if let Err(retry_after) = rate.check() {
+            let error_msg = ServerMessage::from(
+                WsError::new(ErrorCode::RateLimited, "Too many messages")
+                    .with_context(serde_json::json!({ "retry_after_ms": retry_after.as_millis() as u64 })),
+            );
+            if let Err(e) = sender.send(encode_message(&error_msg, None)) {
+                tracing::warn!(error = %e, %user_id, %connection_id, "Failed to send error message");
+                break;
+            }
+            continue;
+        }
+        match decoded {
+            Ok(envelope) => {
+                let target = envelope.session_id;
+                match handle_client_message(
+                    envelope.message,
+                    target,
+                    &pool,
+                    &session_state,
+                    user_id,
+                    &username,
+                    &sender,
+                    &mut joined,
+                ).await {
+                    Ok(Some(server_msg)) => {
+                        if let Err(e) = sender.send(encode_message(&server_msg, None)) {
+                            tracing::warn!(error = %e, %user_id, %connection_id, "Failed to send message");
+                            break;
                         }
                     }
+                    Ok(None) => {}
                     Err(e) => {
-                        eprintln!("Failed to parse client message: {}", e);
-                        let error_msg = ServerMessage::Error { message: "Invalid message format".to_string() };
-                        if let Err(e) = sender.send(Message::Text(serde_json::to_string(&error_msg).unwrap())).await {
-                            eprintln!("Failed to send error message: {}", e);
+                        if let Some(cause) = &e.cause {
+                            tracing::error!(%user_id, %connection_id, session_id = ?target, %cause, "{}", e.message);
+                        }
+                        let error_msg = ServerMessage::from(e);
+                        if let Err(e) = sender.send(encode_message(&error_msg, None)) {
+                            tracing::warn!(error = %e, %user_id, %connection_id, "Failed to send error message");
                             break;
                         }
                     }
                 }
             }
-            Ok(Message::Close(_)) => {
-                // Handle disconnect
-                if let Some(session_id) = current_session {
-                    leave_session(&session_state, session_id, user_id).await;
+            Err(e) => {
+                tracing::warn!(error = %e, %user_id, %connection_id, "Failed to parse client message");
+                let error_msg = ServerMessage::from(
+                    WsError::new(ErrorCode::InvalidMessage, "Invalid message format").with_context(serde_json::json!({ "detail": e })),
+                );
+                if let Err(e) = sender.send(encode_message(&error_msg, None)) {
+                    tracing::warn!(error = %e, %user_id, %connection_id, "Failed to send error message");
+                    break;
                 }
-                break;
             }
-            _ => {}
         }
     }
+
+    // Handle disconnect, whether the client closed cleanly or the stream ended
+    for session_id in joined.into_keys() {
+        leave_session(&session_state, session_id, user_id).await;
+    }
+    {
+        let mut user_connections = session_state.user_connections.write().await;
+        if let Some(connections) = user_connections.get_mut(&user_id) {
+            connections.remove(&connection_id);
+            if connections.is_empty() {
+                user_connections.remove(&user_id);
+            }
+        }
+    }
+    // Let the writer flush what's queued, such as a close frame, then stop it
+    drop(sender);
+    if tokio::time::timeout(WRITER_DRAIN_TIMEOUT, &mut writer).await.is_err() {
+        writer.abort();
+    }
+    if session_state.presence.disconnect(&pool, user_id).await {
+        broadcast_user_presence(&session_state, &pool, user_id, &username, false).await;
+    }
+}
+
+// Close code for sockets whose tokens were revoked, in the range reserved for applications
+pub const REVOKED_CLOSE_CODE: u16 = 4001;
+
+// Closes the user's sockets on this instance with a `SessionRevoked` close frame; returns
+// how many were open
+pub async fn close_user_connections(session_state: &SessionState, user_id: Uuid) -> usize {
+    let user_connections = session_state.user_connections.read().await;
+    let senders = user_connections.get(&user_id).map(|connections| connections.values().collect::<Vec<_>>()).unwrap_or_default();
+    for sender in &senders {
+        sender.revoked.notify_one();
+    }
+    senders.len()
+}
+
+// Tells every active session of the user's campaigns that they came online or went offline
+async fn broadcast_user_presence(session_state: &SessionState, pool: &PgPool, user_id: Uuid, username: &str, online: bool) {
+    let campaigns = crate::scope::campaign_roles(pool, user_id).await.unwrap_or_default();
+
+    let session_ids: Vec<Uuid> = {
+        let sessions = session_state.sessions.read().await;
+        sessions
+            .values()
+            .filter(|info| campaigns.contains_key(&info.campaign_id))
+            .map(|info| info.session_id)
+            .collect()
+    };
+
+    let message = ServerMessage::UserPresenceChanged {
+        user_id,
+        username: username.to_string(),
+        online,
+        last_seen_at: if online { None } else { Some(Utc::now()) },
+    };
+    for session_id in session_ids {
+        broadcast_to_session(session_state, session_id, &message, Recipients::AllExcept(user_id)).await;
+    }
+}
+
+// Campaign resource a message acts on, the access it needs and the error shown when refused
+fn message_resource(msg: &ClientMessage) -> Option<(Resource, Access, &'static str)> {
+    match msg {
+        ClientMessage::JoinSession { session_id } | ClientMessage::CreateEventLog { session_id, .. } => {
+            Some((Resource::Session(*session_id), Access::Member, "Access denied to this session"))
+        }
+        ClientMessage::UpdateInitiative { session_id, .. } => {
+            Some((Resource::Session(*session_id), Access::Permitted(Permission::ManageInitiative), "Only the DM can update initiative"))
+        }
+        ClientMessage::NextTurn { session_id } => {
+            Some((Resource::Session(*session_id), Access::Permitted(Permission::ManageInitiative), "Only the DM can advance turns"))
+        }
+        ClientMessage::EndCombat { session_id } => {
+            Some((Resource::Session(*session_id), Access::Permitted(Permission::ManageInitiative), "Only the DM can end combat"))
+        }
+        ClientMessage::SetTurnTimer { session_id, .. } => {
+            Some((Resource::Session(*session_id), Access::Dm, "Only the DM can set the turn timer"))
+        }
+        ClientMessage::GroupRoll { session_id, .. } => {
+            Some((Resource::Session(*session_id), Access::Dm, "Only the DM can make group rolls"))
+        }
+        ClientMessage::RollAbilityScores { character_id }
+        | ClientMessage::RollCheck { character_id, .. }
+        | ClientMessage::PlayerAction { character_id, .. }
+        | ClientMessage::UpdateCharacter { character_id, .. }
+        | ClientMessage::UpdateHP { character_id, .. } => {
+            Some((Resource::Character(*character_id), Access::Modify, "Access denied to this character"))
+        }
+        _ => None,
+    }
+}
+
+// Checked before dispatch so no message handler can skip its campaign scope
+async fn authorize_message(pool: &PgPool, user_id: Uuid, msg: &ClientMessage) -> Result<Option<CampaignScope>, WsError> {
+    let Some((resource, access, message)) = message_resource(msg) else {
+        return Ok(None);
+    };
+    match check(pool, user_id, resource, access).await {
+        Ok(scope) => Ok(Some(scope)),
+        Err(Denied::OutOfScope) if isolation_mode() == IsolationMode::Strict => Err(WsError::new(ErrorCode::NotFound, "Not found")),
+        Err(Denied::OutOfScope | Denied::Forbidden) => Err(WsError::new(ErrorCode::AccessDenied, message)),
+        Err(Denied::Frozen) => Err(WsError::new(ErrorCode::AccessDenied, FROZEN_MESSAGE)),
+        Err(Denied::Database(e)) => Err(WsError::database(e)),
+    }
+}
+
+// The session a message applies to: the one its envelope names, or the only one joined
+fn target_session(joined: &HashMap<Uuid, bool>, target: Option<Uuid>) -> Result<Option<Uuid>, WsError> {
+    match target {
+        Some(session_id) if joined.contains_key(&session_id) => Ok(Some(session_id)),
+        Some(session_id) => Err(WsError::new(ErrorCode::NotInSession, "Not in that session")
+            .with_context(serde_json::json!({ "session_id": session_id }))),
+        None if joined.len() <= 1 => Ok(joined.keys().next().copied()),
+        None => Err(WsError::new(ErrorCode::InvalidRequest, "Joined to several sessions; set session_id on the message")),
+    }
+}
+
+// Drops sessions the user was removed from (kicked) since joining
+async fn still_joined(session_state: &SessionState, joined: HashMap<Uuid, bool>, user_id: Uuid) -> HashMap<Uuid, bool> {
+    let sessions = session_state.sessions.read().await;
+    let mut remaining = HashMap::new();
+    for (session_id, is_dm) in joined {
+        if let Some(session_info) = sessions.get(&session_id) {
+            if session_info.connections.read().await.contains_key(&user_id) {
+                remaining.insert(session_id, is_dm);
+            }
+        }
+    }
+    remaining
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_client_message(
     msg: ClientMessage,
+    target: Option<Uuid>,
     pool: &PgPool,
     session_state: &SessionState,
     user_id: Uuid,
     username: &str,
-    is_dm: bool,
-    current_session: &mut Option<Uuid>,
-) -> Result<ServerMessage, String> {
+    sender: &SocketSender,
+    joined: &mut HashMap<Uuid, bool>,
+) -> Result<Option<ServerMessage>, WsError> {
+    let scope = authorize_message(pool, user_id, &msg).await?;
+    let current_session = match msg {
+        ClientMessage::JoinSession { .. } | ClientMessage::LeaveSession { .. } => None,
+        _ => target_session(joined, target)?,
+    };
+    let is_dm = current_session.is_some_and(|session_id| joined[&session_id]);
+    let campaign_id = match (&scope, current_session) {
+        (Some(scope), _) => Some(scope.campaign_id),
+        (None, Some(session_id)) => get_session_campaign_id(session_state, session_id).await,
+        (None, None) => None,
+    };
+    if let Some(campaign_id) = campaign_id {
+        usage().record(Subject::Campaign(campaign_id), Metric::WsMessages, 1);
+    }
+    let current_session = &current_session;
+
     match msg {
         ClientMessage::JoinSession { session_id } => {
-            // Verify user has access to this session
-            let has_access = sqlx::query_scalar::<_, bool>(
-                "SELECT EXISTS(SELECT 1 FROM sessions s 
-                 INNER JOIN campaigns c ON s.campaign_id = c.id 
-                 WHERE s.id = $1 AND (c.dm_id = $2 OR s.campaign_id IN (SELECT campaign_id FROM campaign_players WHERE player_id = $2)))"
-            )
-            .bind(session_id)
-            .bind(user_id)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| format!("Database error: {}", e))?;
-
-            if !has_access {
-                return Err("Access denied to this session".to_string());
+            if is_banned(pool, session_id, user_id).await.map_err(WsError::database)? {
+                return Err(WsError::new(ErrorCode::AccessDenied, "You have been banned from this session"));
             }
 
             // Join the session
-            join_session(session_state, session_id, user_id, username, is_dm, pool).await;
-            *current_session = Some(session_id);
+            let is_dm = scope.as_ref().is_some_and(|scope| scope.is_dm());
+            join_session(session_state, session_id, user_id, username, is_dm, sender, pool).await;
+            joined.insert(session_id, is_dm);
 
             // Get current players in session
             let players = get_session_players(session_state, session_id).await;
+            let last_seq = get_last_seq(session_state, session_id).await;
 
-            Ok(ServerMessage::SessionJoined { session_id, players })
+            Ok(Some(ServerMessage::SessionJoined { session_id, players, last_seq }))
         }
         
         ClientMessage::LeaveSession { session_id } => {
             leave_session(session_state, session_id, user_id).await;
-            *current_session = None;
-            Ok(ServerMessage::PlayerLeft { player_id: user_id })
+            joined.remove(&session_id);
+            Ok(Some(ServerMessage::PlayerLeft { player_id: user_id }))
         }
         
-        ClientMessage::DiceRoll { dice, reason } => {
-            let result = roll_dice(&dice)?;
-            let dice_result = DiceResult {
-                dice: dice.clone(),
-                result: result.total,
-                rolls: result.rolls,
-                reason,
+        ClientMessage::KickPlayer { user_id: target_id, ban } => {
+            if !is_dm {
+                return Err(WsError::new(ErrorCode::AccessDenied, "Only the DM can kick players"));
+            }
+            let session_id = current_session.ok_or_else(|| WsError::new(ErrorCode::NotInSession, "Not in a session"))?;
+            if target_id == user_id {
+                return Err(WsError::new(ErrorCode::InvalidRequest, "You can't kick yourself"));
+            }
+
+            if ban {
+                sqlx::query(
+                    "UPDATE sessions SET banned_user_ids = array_append(banned_user_ids, $1), updated_at = $2 
+                     WHERE id = $3 AND NOT ($1 = ANY(banned_user_ids))"
+                )
+                .bind(target_id)
+                .bind(Utc::now())
+                .bind(session_id)
+                .execute(pool)
+                .await
+                .map_err(WsError::database)?;
+            }
+
+            let kicked = remove_connection(session_state, session_id, target_id).await;
+            match kicked {
+                Some(target) => {
+                    let notice = ServerMessage::Kicked { session_id, banned: ban };
+                    let _ = target.send(encode_message(&notice, None));
+                    target.kick.notify_one();
+                }
+                // Banning someone who isn't connected is fine; kicking them is not
+                None if !ban => {
+                    return Err(WsError::new(ErrorCode::NotFound, "Player is not in this session")
+                        .with_context(serde_json::json!({ "user_id": target_id })));
+                }
+                None => {}
+            }
+
+            publish_event(pool, session_state, user_id, DomainEvent::PlayerKicked {
+                event_id: Uuid::new_v4(),
+                session_id,
+                kicked_by: user_id,
+                user_id: target_id,
+                banned: ban,
+                at: Utc::now(),
+            }).await
+        }
+
+        ClientMessage::UnbanPlayer { user_id: target_id } => {
+            if !is_dm {
+                return Err(WsError::new(ErrorCode::AccessDenied, "Only the DM can unban players"));
+            }
+            let session_id = current_session.ok_or_else(|| WsError::new(ErrorCode::NotInSession, "Not in a session"))?;
+
+            sqlx::query("UPDATE sessions SET banned_user_ids = array_remove(banned_user_ids, $1), updated_at = $2 WHERE id = $3")
+                .bind(target_id)
+                .bind(Utc::now())
+                .bind(session_id)
+                .execute(pool)
+                .await
+                .map_err(WsError::database)?;
+
+            Ok(Some(ServerMessage::PlayerUnbanned { session_id, user_id: target_id }))
+        }
+
+        ClientMessage::MutePlayer { user_id: target_id, seconds } => {
+            if !is_dm {
+                return Err(WsError::new(ErrorCode::AccessDenied, "Only the DM can mute players"));
+            }
+            let session_id = current_session.ok_or_else(|| WsError::new(ErrorCode::NotInSession, "Not in a session"))?;
+            if target_id == user_id {
+                return Err(WsError::new(ErrorCode::InvalidRequest, "You can't mute yourself"));
+            }
+            if seconds == Some(0) {
+                return Err(WsError::new(ErrorCode::InvalidRequest, "Mute duration must be at least one second"));
+            }
+
+            let until = seconds.map(|seconds| Utc::now() + chrono::Duration::seconds(seconds as i64));
+            {
+                let sessions = session_state.sessions.read().await;
+                let session_info = sessions.get(&session_id).ok_or_else(|| WsError::new(ErrorCode::NotInSession, "Session is not active"))?;
+                session_info.muted.write().await.insert(target_id, until);
+            }
+            Ok(Some(ServerMessage::PlayerMuted { session_id, user_id: target_id, until }))
+        }
+
+        ClientMessage::UnmutePlayer { user_id: target_id } => {
+            if !is_dm {
+                return Err(WsError::new(ErrorCode::AccessDenied, "Only the DM can unmute players"));
+            }
+            let session_id = current_session.ok_or_else(|| WsError::new(ErrorCode::NotInSession, "Not in a session"))?;
+            {
+                let sessions = session_state.sessions.read().await;
+                if let Some(session_info) = sessions.get(&session_id) {
+                    session_info.muted.write().await.remove(&target_id);
+                }
+            }
+            Ok(Some(ServerMessage::PlayerUnmuted { session_id, user_id: target_id }))
+        }
+
+        ClientMessage::DiceRoll { dice, reason, advantage, dm_only, secret, announce, client_seed, damage } => {
+            if secret && !is_dm {
+                return Err(WsError::new(ErrorCode::AccessDenied, "Only the DM can make secret rolls"));
+            }
+            let dice = match advantage {
+                Some(advantage) => with_advantage(&dice, advantage).map_err(|e| WsError::new(ErrorCode::InvalidDice, e))?,
+                None => dice,
             };
-            
-            // Broadcast to all players in the session
-            if let Some(session_id) = current_session {
-                broadcast_to_session(session_state, *session_id, &ServerMessage::DiceRolled {
-                    player_id: user_id,
-                    result: dice_result.clone(),
-                }).await;
+            check_dice(&dice)?;
+            let damage = damage.map(|damage| attack_damage(&dice, &damage)).transpose()?;
+            let client_seed = client_seed.unwrap_or_else(|| if dm_only || secret { hidden_client_seed() } else { String::new() });
+            let mut seeds = roll_seeds(pool, *current_session, 1 + damage.is_some() as usize, &client_seed).await?.into_iter();
+            let mut dice_result = DiceResult { reason, advantage, ..roll_from(&dice, seeds.next().expect("a seed per roll"))? };
+            if let (Some((damage, doubled)), Some(seed)) = (damage, seeds.next()) {
+                dice_result.critical = natural_20(&dice_result);
+                let damage = if dice_result.critical { doubled } else { damage };
+                dice_result.damage = Some(Box::new(roll_from(&damage, seed)?));
             }
-            
-            Ok(ServerMessage::DiceRolled {
-                player_id: user_id,
-                result: dice_result,
-            })
+
+            let roll_id = match *current_session {
+                Some(session_id) => Some(record_roll(pool, session_id, user_id, &dice_result, dm_only, secret).await?),
+                None => None,
+            };
+            let rolled = ServerMessage::DiceRolled { player_id: user_id, roll_id, result: dice_result, secret };
+
+            // A secret roll only goes back to the DM who made it
+            if secret {
+                if let (true, Some(session_id), Some(roll_id)) = (announce, *current_session, roll_id) {
+                    let notice = ServerMessage::SecretRollMade { player_id: user_id, roll_id };
+                    broadcast_to_session(session_state, session_id, &notice, Recipients::AllExcept(user_id)).await;
+                }
+                return Ok(Some(rolled));
+            }
+            let recipients = if dm_only { Recipients::DmOnly } else { Recipients::All };
+            Ok(publish_to(session_state, *current_session, user_id, rolled, recipients).await)
         }
         
+        ClientMessage::RollCheck { character_id, check, advantage, dm_only, client_seed } => {
+            let character = sqlx::query_as::<_, crate::models::Character>(
+                "SELECT * FROM characters WHERE id = $1"
+            )
+            .bind(character_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(WsError::database)?
+            .ok_or_else(|| WsError::new(ErrorCode::NotFound, "Character not found"))?;
+            if let Some(session_id) = *current_session {
+                ensure_session_campaign(session_state, session_id, scope.as_ref()).await?;
+            }
+
+            let modifier = check_modifier(&character, &derive(&character), &check)
+                .ok_or_else(|| WsError::new(ErrorCode::InvalidRequest, format!("Unknown check: {}", check)))?;
+            let dice = match modifier.modifier {
+                0 => "1d20".to_string(),
+                bonus => format!("1d20{:+}", bonus),
+            };
+            let dice = match advantage {
+                Some(advantage) => with_advantage(&dice, advantage).map_err(|e| WsError::new(ErrorCode::InvalidDice, e))?,
+                None => dice,
+            };
+            let client_seed = client_seed.unwrap_or_else(|| if dm_only { hidden_client_seed() } else { String::new() });
+            let seed = roll_seeds(pool, *current_session, 1, &client_seed).await?.remove(0);
+            let dice_result = DiceResult {
+                reason: Some(modifier.label.clone()),
+                advantage,
+                check: Some(CheckRoll { character_id, character_name: character.name, modifier }),
+                ..roll_from(&dice, seed)?
+            };
+
+            let roll_id = match *current_session {
+                Some(session_id) => Some(record_roll(pool, session_id, user_id, &dice_result, dm_only, false).await?),
+                None => None,
+            };
+            let rolled = ServerMessage::DiceRolled { player_id: user_id, roll_id, result: dice_result, secret: false };
+            let recipients = if dm_only { Recipients::DmOnly } else { Recipients::All };
+            Ok(publish_to(session_state, *current_session, user_id, rolled, recipients).await)
+        }
+
+        ClientMessage::GroupRoll { session_id, entry_ids, dice, reason, advantage, dm_only } => {
+            if entry_ids.is_empty() {
+                return Err(WsError::new(ErrorCode::InvalidRequest, "Pick at least one combatant to roll for"));
+            }
+            let state = sqlx::query_scalar::<_, serde_json::Value>("SELECT state FROM combats WHERE session_id = $1 AND ended_at IS NULL")
+                .bind(session_id)
+                .fetch_optional(pool)
+                .await
+                .map_err(WsError::database)?
+                .ok_or_else(|| WsError::new(ErrorCode::InvalidRequest, "Combat is not active"))?;
+            let game_state: crate::models::GameState = serde_json::from_value(state)
+                .map_err(|e| WsError::internal("Failed to read game state").caused_by(e))?;
+            let dice = match advantage {
+                Some(advantage) => with_advantage(&dice, advantage).map_err(|e| WsError::new(ErrorCode::InvalidDice, e))?,
+                None => dice,
+            };
+
+            // Everything is checked and rolled before anything is saved, so a bad entry or
+            // expression leaves no partial group behind
+            check_dice(&dice)?;
+            let names = entry_ids
+                .iter()
+                .map(|entry_id| -> Result<String, WsError> {
+                    let entry = game_state.initiative_order.iter().find(|entry| entry.id == *entry_id).ok_or_else(|| {
+                        WsError::new(ErrorCode::InvalidRequest, "Not in the initiative order")
+                            .with_context(serde_json::json!({ "entry_id": entry_id }))
+                    })?;
+                    Ok(entry.name.clone())
+                })
+                .collect::<Result<Vec<_>, WsError>>()?;
+            let client_seed = if dm_only { hidden_client_seed() } else { String::new() };
+            let seeds = roll_seeds(pool, Some(session_id), entry_ids.len(), &client_seed).await?;
+            let mut results = Vec::with_capacity(entry_ids.len());
+            for ((entry_id, name), seed) in entry_ids.into_iter().zip(names).zip(seeds) {
+                results.push((entry_id, name, DiceResult { reason: reason.clone(), advantage, ..roll_from(&dice, seed)? }));
+            }
+
+            // Saved as one roll per combatant so dice statistics count each of them
+            let mut rolls = Vec::with_capacity(results.len());
+            for (entry_id, name, result) in results {
+                let roll_id = record_roll(pool, session_id, user_id, &result, dm_only, false).await?;
+                rolls.push(GroupRollResult { entry_id, name, roll_id, result });
+            }
+
+            let recipients = if dm_only { Recipients::DmOnly } else { Recipients::All };
+            Ok(publish_to(session_state, Some(session_id), user_id, ServerMessage::GroupRolled { session_id, player_id: user_id, rolls }, recipients).await)
+        }
+
+        ClientMessage::RollAbilityScores { character_id } => {
+            // Rolled by the server in front of the whole table, so it must happen in a session
+            let session_id = current_session.ok_or_else(|| WsError::new(ErrorCode::NotInSession, "Join a session to roll ability scores"))?;
+            let character = sqlx::query_as::<_, crate::models::Character>(
+                "SELECT * FROM characters WHERE id = $1"
+            )
+            .bind(character_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(WsError::database)?
+            .ok_or_else(|| WsError::new(ErrorCode::NotFound, "Character not found"))?;
+
+            ensure_session_campaign(session_state, session_id, scope.as_ref()).await?;
+
+            let rolls = roll_ability_scores();
+            let scores: Vec<i32> = rolls.iter().map(|dice| ability_score(dice)).collect();
+
+            // The unique character_id locks the first roll in; later attempts are rejected
+            let roll = sqlx::query_as::<_, crate::models::AbilityScoreRoll>(
+                "INSERT INTO ability_score_rolls (character_id, session_id, rolled_by, rolls, scores) 
+                 VALUES ($1, $2, $3, $4, $5) 
+                 ON CONFLICT (character_id) DO NOTHING RETURNING *"
+            )
+            .bind(character_id)
+            .bind(session_id)
+            .bind(user_id)
+            .bind(serde_json::json!(rolls))
+            .bind(serde_json::json!(scores))
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| WsError::internal("Failed to record ability scores").caused_by(e))?
+            .ok_or_else(|| WsError::new(ErrorCode::Conflict, "Ability scores for this character have already been rolled"))?;
+
+            // Keep the rolled pool on the character draft for assigning to abilities
+            let updated = sqlx::query_as::<_, crate::models::Character>(
+                "UPDATE characters SET stats = stats || jsonb_build_object('rolled_scores', $1::jsonb), updated_at = $2 WHERE id = $3 RETURNING *"
+            )
+            .bind(serde_json::json!(scores))
+            .bind(Utc::now())
+            .bind(character_id)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| WsError::internal("Failed to update character").caused_by(e))?;
+            record_revision(pool, &updated, Some(session_id), user_id).await;
+
+            Ok(publish_to_session(session_state, Some(session_id), user_id, ServerMessage::AbilityScoresRolled {
+                roll_id: roll.id,
+                character_id,
+                character_name: character.name,
+                rolled_by: user_id,
+                rolls,
+                scores,
+            }).await)
+        }
+
         ClientMessage::ChatMessage { message } => {
-            let timestamp = Utc::now();
-            let chat_msg = ServerMessage::ChatMessage {
+            let session_id = current_session.ok_or_else(|| WsError::new(ErrorCode::NotInSession, "Not in a session"))?;
+            ensure_not_muted(session_state, session_id, user_id).await?;
+            let dice = inline_dice(&message)?;
+            let seeds = roll_seeds(pool, Some(session_id), dice.len(), "").await?;
+            let rolls = dice.iter().zip(seeds).map(|(dice, seed)| roll_from(dice, seed)).collect::<Result<Vec<_>, _>>()?;
+
+            // Stored so late joiners get the history, and reactions have something to attach to
+            publish_event(pool, session_state, user_id, DomainEvent::ChatPosted {
+                message_id: Uuid::new_v4(),
+                session_id,
                 player_id: user_id,
+                username: username.to_string(),
                 message,
-                timestamp,
-            };
-            
-            // Broadcast to all players in the session
-            if let Some(session_id) = current_session {
-                broadcast_to_session(session_state, *session_id, &chat_msg).await;
-            }
-            
-            Ok(chat_msg)
+                rolls,
+                at: Utc::now(),
+            }).await
+        }
+
+        ClientMessage::React { message_id, emoji } => {
+            let session_id = current_session.ok_or_else(|| WsError::new(ErrorCode::NotInSession, "Not in a session"))?;
+            ensure_not_muted(session_state, session_id, user_id).await?;
+            validate_emoji(&emoji)?;
+
+            let mut tx = pool.begin().await.map_err(WsError::database)?;
+            let event_data = sqlx::query_scalar::<_, serde_json::Value>(
+                "SELECT event_data FROM event_logs WHERE id = $1 AND session_id = $2 AND event_type = 'chat_message' FOR UPDATE"
+            )
+            .bind(message_id)
+            .bind(session_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(WsError::database)?
+            .ok_or_else(|| WsError::new(ErrorCode::NotFound, "Chat message not found in this session"))?;
+
+            let mut reactions: BTreeMap<String, Vec<Uuid>> = event_data
+                .get("reactions")
+                .and_then(|reactions| serde_json::from_value(reactions.clone()).ok())
+                .unwrap_or_default();
+            toggle_reaction(&mut reactions, user_id, &emoji)?;
+
+            sqlx::query("UPDATE event_logs SET event_data = jsonb_set(event_data, '{reactions}', $1) WHERE id = $2")
+                .bind(serde_json::json!(reactions))
+                .bind(message_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| WsError::internal("Failed to save reaction").caused_by(e))?;
+            tx.commit().await.map_err(WsError::database)?;
+
+            Ok(publish_to_session(session_state, Some(session_id), user_id, ServerMessage::ReactionsUpdated { message_id, reactions }).await)
         }
         
         ClientMessage::UpdateGameState { game_state } => {
             // Only DM can update game state
             if !is_dm {
-                return Err("Only the DM can update game state".to_string());
+                return Err(WsError::new(ErrorCode::AccessDenied, "Only the DM can update game state"));
             }
             
             // Update game state in database
@@ -263,253 +1556,227 @@ async fn handle_client_message(
                     .bind(*session_id)
                     .execute(pool)
                     .await
-                    .map_err(|e| format!("Failed to update game state: {}", e))?;
+                    .map_err(|e| WsError::internal("Failed to update game state").caused_by(e))?;
                 
                 // Broadcast to all players
                 let update_msg = ServerMessage::GameStateUpdated { game_state };
-                broadcast_to_session(session_state, *session_id, &update_msg).await;
-                
-                Ok(update_msg)
+                Ok(publish_to_session(session_state, Some(*session_id), user_id, update_msg).await)
             } else {
-                Err("Not in a session".to_string())
+                Err(WsError::new(ErrorCode::NotInSession, "Not in a session"))
             }
         }
         
-        ClientMessage::PlayerAction { action: _, data: _ } => {
-            // Handle various player actions (movement, attacks, etc.)
-            // This is a placeholder for future implementation
-            Ok(ServerMessage::Error { message: "Player actions not yet implemented".to_string() })
+        ClientMessage::PatchGameState { patch } => {
+            // Only DM can update game state
+            if !is_dm {
+                return Err(WsError::new(ErrorCode::AccessDenied, "Only the DM can update game state"));
+            }
+            let session_id = current_session.ok_or_else(|| WsError::new(ErrorCode::NotInSession, "Not in a session"))?;
+
+            // Lock the row so concurrent initiative/HP writes aren't lost
+            let mut tx = pool.begin().await.map_err(|e| WsError::internal("Failed to update game state").caused_by(e))?;
+            let mut game_state = sqlx::query_scalar::<_, serde_json::Value>(
+                "SELECT game_state FROM sessions WHERE id = $1 FOR UPDATE"
+            )
+            .bind(session_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| WsError::internal("Failed to fetch session").caused_by(e))?
+            .ok_or_else(|| WsError::new(ErrorCode::NotFound, "Session not found"))?;
+
+            // Applied atomically: a failing op (including `test`) leaves the state untouched
+            json_patch::patch(&mut game_state, &patch)
+                .map_err(|e| WsError::new(ErrorCode::InvalidRequest, format!("Invalid game state patch: {}", e)))?;
+
+            sqlx::query("UPDATE sessions SET game_state = $1, updated_at = $2 WHERE id = $3")
+                .bind(&game_state)
+                .bind(Utc::now())
+                .bind(session_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| WsError::internal("Failed to update game state").caused_by(e))?;
+            tx.commit().await.map_err(|e| WsError::internal("Failed to update game state").caused_by(e))?;
+
+            // Broadcast only the delta
+            let patch_msg = ServerMessage::GameStatePatched { session_id, patch };
+            Ok(publish_to_session(session_state, Some(session_id), user_id, patch_msg).await)
         }
-        
-        ClientMessage::UpdateCharacter { character_id, updates: _ } => {
-            // Check if user owns this character or is DM of the campaign
-            let has_access = sqlx::query_scalar::<_, bool>(
-                "SELECT EXISTS(SELECT 1 FROM characters c 
-                 INNER JOIN campaigns cam ON c.campaign_id = cam.id 
-                 WHERE c.id = $1 AND (c.player_id = $2 OR cam.dm_id = $2))"
+
+        ClientMessage::PlayerAction { character_id, action } => {
+            let session_id = current_session.ok_or_else(|| WsError::new(ErrorCode::NotInSession, "Not in a session"))?;
+            let character = sqlx::query_as::<_, crate::models::Character>(
+                "SELECT * FROM characters WHERE id = $1"
             )
             .bind(character_id)
-            .bind(user_id)
-            .fetch_one(pool)
+            .fetch_optional(pool)
             .await
-            .map_err(|e| format!("Database error: {}", e))?;
+            .map_err(WsError::database)?
+            .ok_or_else(|| WsError::new(ErrorCode::NotFound, "Character not found"))?;
 
-            if !has_access {
-                return Err("Access denied to this character".to_string());
-            }
+            ensure_session_campaign(session_state, session_id, scope.as_ref()).await?;
+            validate_action(&character, &action).map_err(|e| WsError::new(ErrorCode::InvalidAction, e))?;
+            let numbers = action_numbers(&character, &action);
+
+            publish_event(pool, session_state, user_id, DomainEvent::ActionDeclared {
+                event_id: Uuid::new_v4(),
+                session_id,
+                player_id: user_id,
+                character_id: character.id,
+                character_name: character.name,
+                action,
+                numbers,
+                at: Utc::now(),
+            }).await
+        }
+        
+        ClientMessage::UpdateCharacter { character_id, updates } => {
+            // Same fields as PUT /characters/:id, but only the ones present are changed
+            let updates: crate::handlers::UpdateCharacterRequest = serde_json::from_value(updates)
+                .map_err(|e| WsError::new(ErrorCode::InvalidRequest, format!("Invalid character updates: {}", e)))?;
 
             // Additional validation: Check if character belongs to current session's campaign
             if let Some(session_id) = current_session {
-                if let Some(session_campaign_id) = get_session_campaign_id(session_state, *session_id).await {
-                    let character_campaign_id = sqlx::query_scalar::<_, Option<Uuid>>(
-                        "SELECT campaign_id FROM characters WHERE id = $1"
-                    )
-                    .bind(character_id)
-                    .fetch_one(pool)
-                    .await
-                    .map_err(|e| format!("Database error: {}", e))?;
-
-                    if character_campaign_id != Some(session_campaign_id) {
-                        return Err("Character does not belong to current session's campaign".to_string());
-                    }
-                }
+                ensure_session_campaign(session_state, *session_id, scope.as_ref()).await?;
             }
 
             // Update character in database
             let now = Utc::now();
             let res = sqlx::query_as::<_, crate::models::Character>(
-                "UPDATE characters SET updated_at = $1 WHERE id = $2 RETURNING *"
+                "UPDATE characters SET 
+                 name = COALESCE($1, name), 
+                 race = COALESCE($2, race), 
+                 class = COALESCE($3, class), 
+                 level = COALESCE($4, level), 
+                 hp_current = COALESCE($5, hp_current), 
+                 hp_max = COALESCE($6, hp_max), 
+                 ac = COALESCE($7, ac), 
+                 speed = COALESCE($8, speed), 
+                 stats = COALESCE($9, stats), 
+                 inventory = COALESCE($10, inventory), 
+                 spells = COALESCE($11, spells), 
+                 features = COALESCE($12, features), 
+                 updated_at = $13 
+                 WHERE id = $14 RETURNING *"
             )
+            .bind(&updates.name)
+            .bind(&updates.race)
+            .bind(&updates.class)
+            .bind(updates.level)
+            .bind(updates.hp_current)
+            .bind(updates.hp_max)
+            .bind(updates.ac)
+            .bind(updates.speed)
+            .bind(&updates.stats)
+            .bind(&updates.inventory)
+            .bind(&updates.spells)
+            .bind(&updates.features)
             .bind(now)
             .bind(character_id)
             .fetch_one(pool)
             .await
-            .map_err(|e| format!("Failed to update character: {}", e))?;
-
-            // Create character info for broadcast
-            let character_info = CharacterInfo {
-                id: res.id,
-                name: res.name,
-                race: res.race,
-                class: res.class,
-                level: res.level,
-                hp_current: res.hp_current,
-                hp_max: res.hp_max,
-                ac: res.ac,
-                speed: res.speed,
-            };
-
-            // Broadcast to all players in the session
-            if let Some(session_id) = current_session {
-                broadcast_to_session(session_state, *session_id, &ServerMessage::CharacterUpdated {
-                    character: character_info.clone(),
-                }).await;
-            }
+            .map_err(|e| WsError::internal("Failed to update character").caused_by(e))?;
+            record_revision(pool, &res, *current_session, user_id).await;
 
-            Ok(ServerMessage::CharacterUpdated { character: character_info })
+            // Broadcast the stored character to all players in the session
+            Ok(publish_to_session(session_state, *current_session, user_id, ServerMessage::CharacterUpdated {
+                character: CharacterInfo::from(res),
+            }).await)
         }
         
         ClientMessage::UpdateInitiative { session_id, initiative_order } => {
-            // Check if user is DM of this session's campaign
-            let is_dm = sqlx::query_scalar::<_, bool>(
-                "SELECT EXISTS(SELECT 1 FROM sessions s 
-                 INNER JOIN campaigns c ON s.campaign_id = c.id 
-                 WHERE s.id = $1 AND c.dm_id = $2)"
-            )
-            .bind(session_id)
-            .bind(user_id)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| format!("Database error: {}", e))?;
+            let mut tx = pool.begin().await.map_err(WsError::database)?;
 
-            if !is_dm {
-                return Err("Only the DM can update initiative".to_string());
-            }
+            // Replace the order of the running fight, or start a new one from round 1 so
+            // surprise rules apply
+            let (combat_id, mut game_state) = match crate::combat::lock_active(&mut tx, session_id).await.map_err(WsError::database)? {
+                Some((combat_id, game_state)) => (Some(combat_id), game_state),
+                None => (None, crate::combat::new_state(&mut tx, session_id, Vec::new()).await.map_err(WsError::database)?),
+            };
+            game_state.initiative_order = initiative_order;
 
-            // Get current game state
-            let session = sqlx::query_as::<_, crate::models::Session>(
-                "SELECT * FROM sessions WHERE id = $1"
-            )
-            .bind(session_id)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| format!("Failed to fetch session: {}", e))?;
-
-            // Parse current game state
-            let mut game_state: crate::models::GameState = serde_json::from_value(session.game_state.clone())
-                .unwrap_or_else(|_| crate::models::GameState {
-                    initiative_order: Vec::new(),
-                    current_turn: None,
-                    round: 1,
-                    combat_active: false,
-                    conditions: Vec::new(),
-                });
-
-            // Update initiative order
-            game_state.initiative_order = initiative_order.clone();
-            game_state.combat_active = true;
-
-            // Save updated game state
-            let now = Utc::now();
-            sqlx::query("UPDATE sessions SET game_state = $1, updated_at = $2 WHERE id = $3")
-                .bind(serde_json::to_value(&game_state).unwrap())
-                .bind(now)
-                .bind(session_id)
-                .execute(pool)
+            // The stored copy has HP and AC of linked characters filled in
+            let (_, game_state) = crate::combat::save(&mut tx, session_id, combat_id, &game_state)
                 .await
-                .map_err(|e| format!("Failed to update game state: {}", e))?;
+                .map_err(|e| WsError::internal("Failed to update game state").caused_by(e))?;
+            tx.commit().await.map_err(WsError::database)?;
 
             // Broadcast to all players
-            broadcast_to_session(session_state, session_id, &ServerMessage::InitiativeUpdated {
-                session_id,
-                initiative_order: initiative_order.clone(),
-                current_turn: game_state.current_turn,
-            }).await;
-
-            Ok(ServerMessage::InitiativeUpdated {
+            Ok(publish_to_session(session_state, Some(session_id), user_id, ServerMessage::InitiativeUpdated {
                 session_id,
-                initiative_order,
+                initiative_order: game_state.initiative_order,
                 current_turn: game_state.current_turn,
-            })
+            }).await)
         }
         
         ClientMessage::NextTurn { session_id } => {
-            // Check if user is DM of this session's campaign
-            let is_dm = sqlx::query_scalar::<_, bool>(
-                "SELECT EXISTS(SELECT 1 FROM sessions s 
-                 INNER JOIN campaigns c ON s.campaign_id = c.id 
-                 WHERE s.id = $1 AND c.dm_id = $2)"
-            )
-            .bind(session_id)
-            .bind(user_id)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| format!("Database error: {}", e))?;
+            let (current_turn, message) = advance_session_turn(pool, session_state, session_id).await?;
 
-            if !is_dm {
-                return Err("Only the DM can advance turns".to_string());
-            }
+            // Broadcast to all players, then start the new turn's countdown
+            let reply = publish_to_session(session_state, Some(session_id), user_id, message).await;
+            restart_turn_timer(pool, session_state, session_id, Some(current_turn)).await;
+            Ok(reply)
+        }
 
-            // Get current game state
-            let session = sqlx::query_as::<_, crate::models::Session>(
-                "SELECT * FROM sessions WHERE id = $1"
-            )
-            .bind(session_id)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| format!("Failed to fetch session: {}", e))?;
-
-            // Parse current game state
-            let mut game_state: crate::models::GameState = serde_json::from_value(session.game_state.clone())
-                .unwrap_or_else(|_| crate::models::GameState {
-                    initiative_order: Vec::new(),
-                    current_turn: None,
-                    round: 1,
-                    combat_active: false,
-                    conditions: Vec::new(),
-                });
-
-            // Advance to next turn
-            if !game_state.initiative_order.is_empty() {
-                let current_index = game_state.initiative_order.iter()
-                    .position(|entry| Some(entry.id) == game_state.current_turn)
-                    .unwrap_or(0);
-                
-                let next_index = (current_index + 1) % game_state.initiative_order.len();
-                game_state.current_turn = Some(game_state.initiative_order[next_index].id);
-                
-                // Increment round if we've gone through all entries
-                if next_index == 0 {
-                    game_state.round += 1;
-                }
-            }
+        ClientMessage::EndCombat { session_id } => {
+            let scope = scope.ok_or_else(|| WsError::new(ErrorCode::AccessDenied, "Only the DM can end combat"))?;
+            let mut tx = pool.begin().await.map_err(WsError::database)?;
+            let (combat_id, mut game_state) = crate::combat::lock_active(&mut tx, session_id)
+                .await
+                .map_err(WsError::database)?
+                .ok_or_else(|| WsError::new(ErrorCode::InvalidRequest, "Combat is not active"))?;
 
-            // Save updated game state
-            let now = Utc::now();
-            let round = game_state.round;
-            sqlx::query("UPDATE sessions SET game_state = $1, updated_at = $2 WHERE id = $3")
-                .bind(serde_json::to_value(&game_state).unwrap())
-                .bind(now)
-                .bind(session_id)
-                .execute(pool)
+            game_state.combat_active = false;
+            game_state.current_turn = None;
+            let (_, game_state) = crate::combat::save(&mut tx, session_id, Some(combat_id), &game_state)
                 .await
-                .map_err(|e| format!("Failed to update game state: {}", e))?;
+                .map_err(|e| WsError::internal("Failed to update game state").caused_by(e))?;
+            let reward = crate::rewards::suggest_rewards(&mut tx, session_id, combat_id, scope.campaign_id, &game_state)
+                .await
+                .map_err(WsError::database)?;
+            tx.commit().await.map_err(WsError::database)?;
 
-            // Broadcast to all players
-            if let Some(current_turn) = game_state.current_turn {
-                broadcast_to_session(session_state, session_id, &ServerMessage::TurnChanged {
-                    session_id,
-                    current_turn,
-                    round,
-                }).await;
-
-                Ok(ServerMessage::TurnChanged {
-                    session_id,
-                    current_turn,
-                    round,
-                })
-            } else {
-                Err("No initiative order set".to_string())
+            restart_turn_timer(pool, session_state, session_id, None).await;
+            let ended = ServerMessage::CombatEnded { session_id, reward_id: reward.as_ref().map(|reward| reward.id) };
+            let reply = publish_to_session(session_state, Some(session_id), user_id, ended).await;
+            match reward {
+                Some(reward) => Ok(publish_to(session_state, Some(session_id), user_id, ServerMessage::RewardsSuggested { reward }, Recipients::DmOnly).await),
+                None => Ok(reply),
             }
         }
-        
-        ClientMessage::UpdateHP { character_id, hp_current, hp_max } => {
-            // Check if user owns this character or is DM of the campaign
-            let has_access = sqlx::query_scalar::<_, bool>(
-                "SELECT EXISTS(SELECT 1 FROM characters c 
-                 INNER JOIN campaigns cam ON c.campaign_id = cam.id 
-                 WHERE c.id = $1 AND (c.player_id = $2 OR cam.dm_id = $2))"
-            )
-            .bind(character_id)
-            .bind(user_id)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| format!("Database error: {}", e))?;
 
-            if !has_access {
-                return Err("Access denied to this character".to_string());
+        ClientMessage::SetTurnTimer { session_id, seconds, auto_advance } => {
+            if seconds == Some(0) {
+                return Err(WsError::new(ErrorCode::InvalidRequest, "Turn time limit must be at least one second"));
+            }
+
+            {
+                let sessions = session_state.sessions.read().await;
+                let session_info = sessions.get(&session_id).ok_or_else(|| WsError::new(ErrorCode::NotInSession, "Session is not active"))?;
+                let mut timer = session_info.turn_timer.lock().await;
+                timer.seconds = seconds;
+                timer.auto_advance = auto_advance;
             }
 
+            // Restart the countdown for whoever is up now (or stop it when disabled)
+            let current_turn = sqlx::query_scalar::<_, Option<String>>(
+                "SELECT state->>'current_turn' FROM combats WHERE session_id = $1 AND ended_at IS NULL"
+            )
+            .bind(session_id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| WsError::internal("Failed to fetch combat").caused_by(e))?
+            .flatten()
+            .and_then(|id| id.parse::<Uuid>().ok());
+            restart_turn_timer(pool, session_state, session_id, current_turn).await;
+
+            Ok(publish_to_session(session_state, Some(session_id), user_id, ServerMessage::TurnTimerUpdated {
+                session_id,
+                seconds,
+                auto_advance,
+            }).await)
+        }
+        
+        ClientMessage::UpdateHP { character_id, hp_current, hp_max } => {
             // Update character HP
             let now = Utc::now();
             let res = sqlx::query_as::<_, crate::models::Character>(
@@ -521,105 +1788,89 @@ async fn handle_client_message(
             .bind(character_id)
             .fetch_one(pool)
             .await
-            .map_err(|e| format!("Failed to update character HP: {}", e))?;
+            .map_err(|e| WsError::internal("Failed to update character HP").caused_by(e))?;
+            record_revision(pool, &res, *current_session, user_id).await;
 
             // Broadcast to all players in the session
-            if let Some(session_id) = current_session {
-                broadcast_to_session(session_state, *session_id, &ServerMessage::HPUpdated {
-                    character_id,
-                    hp_current: res.hp_current.unwrap_or(0),
-                    hp_max: res.hp_max.unwrap_or(0),
-                }).await;
-            }
-
-            Ok(ServerMessage::HPUpdated {
+            Ok(publish_to_session(session_state, *current_session, user_id, ServerMessage::HPUpdated {
                 character_id,
                 hp_current: res.hp_current.unwrap_or(0),
                 hp_max: res.hp_max.unwrap_or(0),
-            })
+            }).await)
         }
         
         ClientMessage::CreateEventLog { session_id, event_type, event_data } => {
-            // Check if user has access to this session
-            let has_access = sqlx::query_scalar::<_, bool>(
-                "SELECT EXISTS(SELECT 1 FROM sessions s 
-                 INNER JOIN campaigns c ON s.campaign_id = c.id 
-                 WHERE s.id = $1 AND (c.dm_id = $2 OR s.campaign_id IN (SELECT campaign_id FROM campaign_players WHERE player_id = $2)))"
-            )
-            .bind(session_id)
-            .bind(user_id)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| format!("Database error: {}", e))?;
-
-            if !has_access {
-                return Err("Access denied to this session".to_string());
-            }
-
-            // Create event log in database
-            let event_id = Uuid::new_v4();
-            let now = Utc::now();
-            
-            let event_log = sqlx::query_as::<_, crate::models::EventLog>(
-                "INSERT INTO event_logs (id, session_id, event_type, event_data, created_by, created_at) 
-                 VALUES ($1, $2, $3, $4, $5, $6) RETURNING *"
-            )
-            .bind(event_id)
-            .bind(session_id)
-            .bind(&event_type)
-            .bind(&event_data)
-            .bind(user_id)
-            .bind(now)
-            .fetch_one(pool)
-            .await
-            .map_err(|e| format!("Failed to create event log: {}", e))?;
-
-            // Broadcast to all players in the session
-            let event_msg = ServerMessage::EventLogCreated {
-                event_id: event_log.id,
-                event_type: event_log.event_type,
-                event_data: event_log.event_data,
-                created_by: event_log.created_by.unwrap_or(user_id),
-                created_at: event_log.created_at,
-            };
-            
-            broadcast_to_session(session_state, session_id, &event_msg).await;
-
-            Ok(event_msg)
+            publish_event(pool, session_state, user_id, DomainEvent::EventLogged {
+                event_id: Uuid::new_v4(),
+                session_id,
+                created_by: user_id,
+                event_type,
+                event_data,
+                at: Utc::now(),
+            }).await
         }
         
-        ClientMessage::AIRequest { prompt, request_type, context: _ } => {
-            // For now, return a mock AI response
-            // TODO: Implement actual AI integration
-            let response = match request_type.as_str() {
+        ClientMessage::AIRequest { prompt, request_type, context, dm_only } => {
+            // With a provider configured the AI sees the table's recent events; otherwise,
+            // or if it fails, a canned response stands in
+            let completion = match *current_session {
+                Some(session_id) if crate::demo::ai_enabled_for(pool, user_id).await => {
+                    let table = crate::events::ai_context(session_state, session_id).await;
+                    let full_prompt = format!(
+                        "Recent events at the table:\n{}\n\n{}{}",
+                        table.join("\n"),
+                        context.map(|context| format!("{}\n\n", context)).unwrap_or_default(),
+                        prompt,
+                    );
+                    match crate::ai::complete("You are a creative assistant for a Dungeon Master.", &full_prompt).await {
+                        Ok(completion) => {
+                            if let Some(tokens) = completion.tokens_used {
+                                usage().record(Subject::User(user_id), Metric::AiTokens, tokens as i64);
+                            }
+                            Some(completion)
+                        }
+                        Err(e) => {
+                            tracing::error!(error = %e, %user_id, "AI request failed");
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
+            let mock = match request_type.as_str() {
                 "npc" => {
-                    format!("Generated NPC: A mysterious figure with a weathered cloak and piercing eyes. They seem to know more than they let on...")
+                    "Generated NPC: A mysterious figure with a weathered cloak and piercing eyes. They seem to know more than they let on...".to_string()
                 }
                 "location" => {
-                    format!("Generated Location: A dimly lit tavern with smoke curling from the fireplace. The wooden beams creak with age, and the air is thick with the smell of ale and adventure.")
+                    "Generated Location: A dimly lit tavern with smoke curling from the fireplace. The wooden beams creak with age, and the air is thick with the smell of ale and adventure.".to_string()
                 }
                 "encounter" => {
-                    format!("Generated Encounter: A group of bandits has set up an ambush in the forest. They're well-armed and seem desperate, suggesting they might be open to negotiation.")
+                    "Generated Encounter: A group of bandits has set up an ambush in the forest. They're well-armed and seem desperate, suggesting they might be open to negotiation.".to_string()
                 }
                 "description" => {
-                    format!("Enhanced Description: The ancient castle looms before you, its weathered stone walls bearing the scars of countless battles. Torches flicker in the arrow slits, casting dancing shadows that seem to move of their own accord.")
+                    "Enhanced Description: The ancient castle looms before you, its weathered stone walls bearing the scars of countless battles. Torches flicker in the arrow slits, casting dancing shadows that seem to move of their own accord.".to_string()
                 }
                 "chat" => {
-                    format!("AI Assistant: Based on the current situation, I'd suggest considering the diplomatic approach. The goblins seem nervous and might be more interested in survival than combat.")
+                    "AI Assistant: Based on the current situation, I'd suggest considering the diplomatic approach. The goblins seem nervous and might be more interested in survival than combat.".to_string()
                 }
                 _ => {
-                    format!("AI Response: I'm here to help with your D&D session. What would you like me to assist with?")
+                    "AI Response: I'm here to help with your D&D session. What would you like me to assist with?".to_string()
                 }
             };
+            let (response, tokens_used, model) = match completion {
+                Some(completion) => (completion.text.trim().to_string(), completion.tokens_used, completion.model),
+                None => (mock, Some(150), "gpt-4".to_string()), // Mock values
+            };
 
-            // Log the AI request as an event if we're in a session
-            if let Some(session_id) = current_session {
+            // Log the AI request as an event if we're in a session. Every member can read
+            // the event log, so secret responses stay out of it.
+            if let (Some(session_id), false) = (*current_session, dm_only) {
                 let _ = sqlx::query(
                     "INSERT INTO event_logs (id, session_id, event_type, event_data, created_by, created_at) 
                      VALUES ($1, $2, $3, $4, $5, $6)"
                 )
                 .bind(Uuid::new_v4())
-                .bind(*session_id)
+                .bind(session_id)
                 .bind("ai_request")
                 .bind(serde_json::json!({
                     "prompt": prompt,
@@ -635,16 +1886,55 @@ async fn handle_client_message(
             let ai_response = ServerMessage::AIResponse {
                 response,
                 request_type,
-                tokens_used: Some(150), // Mock value
-                model: "gpt-4".to_string(),
+                tokens_used,
+                model,
             };
 
-            // Broadcast AI response to all players in the session
+            let recipients = if dm_only { Recipients::DmOnly } else { Recipients::All };
+            Ok(publish_to(session_state, *current_session, user_id, ai_response, recipients).await)
+        }
+
+        ClientMessage::Typing { is_typing } => {
             if let Some(session_id) = current_session {
-                broadcast_to_session(session_state, *session_id, &ai_response).await;
+                // Repeated "typing" events inside the debounce window are dropped
+                if update_typing_state(session_state, *session_id, user_id, is_typing).await {
+                    broadcast_to_session(session_state, *session_id, &ServerMessage::PlayerTyping {
+                        player_id: user_id,
+                        username: username.to_string(),
+                        is_typing,
+                    }, Recipients::AllExcept(user_id)).await;
+                }
+                Ok(None)
+            } else {
+                Err(WsError::new(ErrorCode::NotInSession, "Not in a session"))
+            }
+        }
+
+        ClientMessage::Ack { seq } => {
+            if let Some(session_id) = current_session {
+                record_ack(session_state, *session_id, user_id, seq).await;
+                Ok(None)
+            } else {
+                Err(WsError::new(ErrorCode::NotInSession, "Not in a session"))
             }
+        }
 
-            Ok(ai_response)
+        ClientMessage::Heartbeat => {
+            session_state.presence.heartbeat(user_id).await;
+            Ok(None)
+        }
+
+        ClientMessage::RequestReplay { since_seq } => {
+            if let Some(session_id) = current_session {
+                // Default to everything after the last acknowledged message
+                let since_seq = match since_seq {
+                    Some(seq) => seq,
+                    None => get_last_acked_seq(session_state, *session_id, user_id).await,
+                };
+                replay_since(session_state, *session_id, since_seq, sender).await
+            } else {
+                Err(WsError::new(ErrorCode::NotInSession, "Not in a session"))
+            }
         }
     }
 }
@@ -655,6 +1945,7 @@ async fn join_session(
     user_id: Uuid,
     username: &str,
     is_dm: bool,
+    sender: &SocketSender,
     pool: &PgPool,
 ) {
     // Fetch session info from database to get campaign_id
@@ -666,47 +1957,299 @@ async fn join_session(
     .await;
 
     if let Ok(Some(session)) = session_result {
+        let live_state = session.live_state.and_then(|value| serde_json::from_value(value).ok());
         let mut sessions = session_state.sessions.write().await;
-        let session_info = sessions.entry(session_id).or_insert_with(|| SessionInfo {
-            session_id,
-            campaign_id: session.campaign_id,
-            connections: Arc::new(RwLock::new(HashMap::new())),
-        });
+        let session_info = sessions
+            .entry(session_id)
+            .or_insert_with(|| SessionInfo::restore(session_id, session.campaign_id, live_state));
+        *session_info.last_active.lock().await = Instant::now();
+        if session.status == "paused" {
+            session_info.turn_timer.lock().await.paused = true;
+        }
         
         // Use both session_id and campaign_id for logging
-        println!("User {} joining session {} (campaign: {})", user_id, session_info.session_id, session_info.campaign_id);
+        tracing::debug!(%user_id, session_id = %session_info.session_id, campaign_id = %session_info.campaign_id, "User joining session");
         
+        let connected_at = Utc::now();
         let mut connections = session_info.connections.write().await;
         connections.insert(user_id, ConnectionInfo {
             user_id,
             username: username.to_string(),
             is_dm,
+            sender: sender.clone(),
+            last_typing: None,
+            connected_at,
+            last_acked_seq: 0,
         });
+        drop(connections);
+        drop(sessions);
+
+        publish_presence(session_state, session_id, &PresenceEntry {
+            user_id,
+            username: username.to_string(),
+            is_dm,
+            connected_at,
+        }).await;
     }
 }
 
 async fn leave_session(session_state: &SessionState, session_id: Uuid, user_id: Uuid) {
+    remove_connection(session_state, session_id, user_id).await;
+}
+
+// Drops the user's connection from the session and their presence entry, returning the
+// connection's sender if they were connected
+async fn remove_connection(session_state: &SessionState, session_id: Uuid, user_id: Uuid) -> Option<SocketSender> {
+    let mut removed = None;
     let mut sessions = session_state.sessions.write().await;
     if let Some(session_info) = sessions.get_mut(&session_id) {
         // Use session_id and campaign_id for logging
-        println!("User {} leaving session {} (campaign: {})", user_id, session_info.session_id, session_info.campaign_id);
+        tracing::debug!(%user_id, session_id = %session_info.session_id, campaign_id = %session_info.campaign_id, "User leaving session");
         
         let mut connections = session_info.connections.write().await;
-        connections.remove(&user_id);
+        removed = connections.remove(&user_id).map(|connection| connection.sender);
         
-        // Check if session should be removed (no connections left)
-        if connections.is_empty() {
+        // Check if session should be removed (no connections or SSE subscribers left)
+        if connections.is_empty() && session_info.events.receiver_count() == 0 {
             drop(connections); // Release the borrow before removing
             sessions.remove(&session_id);
         }
     }
+    drop(sessions);
+
+    if let Some(redis) = &session_state.redis {
+        let mut conn = redis.clone();
+        if let Err(e) = conn.hdel::<_, _, ()>(presence_key(session_id), user_id.to_string()).await {
+            tracing::warn!(error = %e, "Failed to remove presence from Redis");
+        }
+    }
+    removed
+}
+
+pub async fn is_banned(pool: &PgPool, session_id: Uuid, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM sessions WHERE id = $1 AND $2 = ANY(banned_user_ids))")
+        .bind(session_id)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+}
+
+// Subscribes to a session's broadcasts, creating the session entry if nobody is connected yet.
+// Returns the retained messages after `since_seq`, if given, along with the live receiver.
+pub async fn subscribe_events(
+    session_state: &SessionState,
+    session_id: Uuid,
+    campaign_id: Uuid,
+    since_seq: Option<u64>,
+) -> (Vec<(u64, String)>, broadcast::Receiver<(Option<u64>, String)>) {
+    let mut sessions = session_state.sessions.write().await;
+    let session_info = sessions
+        .entry(session_id)
+        .or_insert_with(|| SessionInfo::new(session_id, campaign_id));
+    let history = session_info.history.read().await;
+    let missed = match since_seq {
+        Some(since_seq) => history
+            .messages
+            .iter()
+            .filter(|(seq, _)| *seq > since_seq)
+            .cloned()
+            .collect(),
+        None => Vec::new(),
+    };
+    (missed, session_info.events.subscribe())
+}
+
+// Drops the session entry once the last SSE subscriber and connection are gone
+pub async fn release_events(session_state: &SessionState, session_id: Uuid) {
+    let mut sessions = session_state.sessions.write().await;
+    if let Some(session_info) = sessions.get(&session_id) {
+        let idle = session_info.connections.read().await.is_empty();
+        if idle && session_info.events.receiver_count() == 0 {
+            sessions.remove(&session_id);
+        }
+    }
+}
+
+// Sessions with no WebSocket connection are evicted after this long without activity
+const SESSION_IDLE_TTL: Duration = Duration::from_secs(30 * 60);
+
+const JANITOR_INTERVAL: Duration = Duration::from_secs(60);
+
+// Default cap on in-memory sessions; override with MAX_LIVE_SESSIONS
+pub const DEFAULT_MAX_LIVE_SESSIONS: usize = 1000;
+
+// Entries normally go away when the last client leaves; this catches the ones that don't
+// and keeps the total under `max_sessions`
+pub fn spawn_session_janitor(pool: PgPool, session_state: SessionState, max_sessions: usize) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(JANITOR_INTERVAL);
+        loop {
+            interval.tick().await;
+            sweep_sessions(&pool, &session_state, max_sessions, SESSION_IDLE_TTL).await;
+        }
+    });
+}
+
+// Evicts idle sessions past the TTL, then the least recently active idle sessions while over
+// the cap. Sessions with connected players are never evicted.
+async fn sweep_sessions(pool: &PgPool, session_state: &SessionState, max_sessions: usize, idle_ttl: Duration) {
+    let (total, mut idle) = {
+        let sessions = session_state.sessions.read().await;
+        let mut idle = Vec::new();
+        for (session_id, session_info) in sessions.iter() {
+            if session_info.connections.read().await.is_empty() {
+                idle.push((*session_id, *session_info.last_active.lock().await));
+            }
+        }
+        (sessions.len(), idle)
+    };
+    idle.sort_by_key(|(_, last_active)| *last_active);
+
+    let now = Instant::now();
+    let expired = idle.iter().take_while(|(_, last_active)| now.duration_since(*last_active) >= idle_ttl).count();
+    let over_cap = total.saturating_sub(max_sessions);
+    if over_cap > idle.len() {
+        tracing::warn!(total, max_sessions, "In-memory sessions over the cap, but the rest have players connected");
+    }
+
+    for (i, (session_id, _)) in idle.into_iter().take(expired.max(over_cap)).enumerate() {
+        if evict_session(pool, session_state, session_id).await {
+            let counter = if i < expired { &session_state.evictions.idle } else { &session_state.evictions.capacity };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// Saves the session's live state, then drops the entry unless a player joined meanwhile
+async fn evict_session(pool: &PgPool, session_state: &SessionState, session_id: Uuid) -> bool {
+    let live_state = {
+        let sessions = session_state.sessions.read().await;
+        let Some(session_info) = sessions.get(&session_id) else {
+            return false;
+        };
+        let last_seq = session_info.history.read().await.last_seq;
+        let timer = session_info.turn_timer.lock().await;
+        LiveState { last_seq, turn_timer_seconds: timer.seconds, auto_advance: timer.auto_advance }
+    };
+    let saved = sqlx::query("UPDATE sessions SET live_state = $1 WHERE id = $2")
+        .bind(serde_json::to_value(&live_state).unwrap_or_default())
+        .bind(session_id)
+        .execute(pool)
+        .await;
+    if let Err(e) = saved {
+        // Keep it in memory rather than lose the state; the next sweep tries again
+        tracing::error!(error = %e, %session_id, "Failed to save live state");
+        return false;
+    }
+
+    let mut sessions = session_state.sessions.write().await;
+    let Some(session_info) = sessions.get(&session_id) else {
+        return false;
+    };
+    if !session_info.connections.read().await.is_empty() {
+        return false;
+    }
+    if let Some(task) = session_info.turn_timer.lock().await.task.take() {
+        task.abort();
+    }
+    sessions.remove(&session_id);
+    true
+}
+
+// Point-in-time counts for the metrics endpoint
+pub struct SessionGauges {
+    pub sessions: usize,
+    pub idle_sessions: usize,
+    // Joined WebSocket connections per in-memory session
+    pub connections: Vec<(Uuid, usize)>,
+}
+
+pub async fn session_gauges(session_state: &SessionState) -> SessionGauges {
+    let sessions = session_state.sessions.read().await;
+    let mut gauges = SessionGauges { sessions: sessions.len(), idle_sessions: 0, connections: Vec::new() };
+    for (session_id, session_info) in sessions.iter() {
+        let connections = session_info.connections.read().await.len();
+        if connections == 0 {
+            gauges.idle_sessions += 1;
+        }
+        gauges.connections.push((*session_id, connections));
+    }
+    gauges
+}
+
+fn presence_key(session_id: Uuid) -> String {
+    format!("yoda:presence:session:{}", session_id)
+}
+
+// How long a Redis presence hash survives without any joins (guards against crashed instances)
+const PRESENCE_TTL_SECS: i64 = 24 * 60 * 60;
+
+async fn publish_presence(session_state: &SessionState, session_id: Uuid, entry: &PresenceEntry) {
+    if let Some(redis) = &session_state.redis {
+        let mut conn = redis.clone();
+        let key = presence_key(session_id);
+        let value = match serde_json::to_string(entry) {
+            Ok(value) => value,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to serialize presence");
+                return;
+            }
+        };
+        let res: redis::RedisResult<()> = redis::pipe()
+            .hset(&key, entry.user_id.to_string(), value)
+            .ignore()
+            .expire(&key, PRESENCE_TTL_SECS)
+            .ignore()
+            .query_async(&mut conn)
+            .await;
+        if let Err(e) = res {
+            tracing::warn!(error = %e, "Failed to publish presence to Redis");
+        }
+    }
+}
+
+// Who is currently connected to a session; reads Redis when clustered, local state otherwise
+pub async fn session_presence(session_state: &SessionState, session_id: Uuid) -> Vec<PresenceEntry> {
+    if let Some(redis) = &session_state.redis {
+        let mut conn = redis.clone();
+        match conn.hgetall::<_, HashMap<String, String>>(presence_key(session_id)).await {
+            Ok(entries) => {
+                let mut presence: Vec<PresenceEntry> = entries
+                    .values()
+                    .filter_map(|value| serde_json::from_str(value).ok())
+                    .collect();
+                presence.sort_by_key(|entry| entry.connected_at);
+                return presence;
+            }
+            Err(e) => tracing::warn!(error = %e, "Failed to read presence from Redis, using local state"),
+        }
+    }
+
+    let sessions = session_state.sessions.read().await;
+    let mut presence: Vec<PresenceEntry> = match sessions.get(&session_id) {
+        Some(session_info) => session_info
+            .connections
+            .read()
+            .await
+            .values()
+            .map(|conn| PresenceEntry {
+                user_id: conn.user_id,
+                username: conn.username.clone(),
+                is_dm: conn.is_dm,
+                connected_at: conn.connected_at,
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    presence.sort_by_key(|entry| entry.connected_at);
+    presence
 }
 
 async fn get_session_players(session_state: &SessionState, session_id: Uuid) -> Vec<PlayerInfo> {
     let sessions = session_state.sessions.read().await;
     if let Some(session_info) = sessions.get(&session_id) {
         // Use session_id and campaign_id for logging/debugging
-        println!("Getting players for session {} (campaign: {})", session_info.session_id, session_info.campaign_id);
+        tracing::trace!(session_id = %session_info.session_id, campaign_id = %session_info.campaign_id, "Getting players for session");
         
         let connections = session_info.connections.read().await;
         connections
@@ -727,60 +2270,1573 @@ async fn get_session_campaign_id(session_state: &SessionState, session_id: Uuid)
     sessions.get(&session_id).map(|session_info| session_info.campaign_id)
 }
 
-async fn broadcast_to_session(
+async fn ensure_not_muted(session_state: &SessionState, session_id: Uuid, user_id: Uuid) -> Result<(), WsError> {
+    match muted_until(session_state, session_id, user_id).await {
+        Some(until) => Err(WsError::new(ErrorCode::Muted, "You are muted in this session")
+            .with_context(serde_json::json!({ "muted_until": until }))),
+        None => Ok(()),
+    }
+}
+
+// Different emoji one chat message can collect
+const MAX_REACTION_KINDS: usize = 20;
+
+// A single emoji, possibly with modifiers or joiners, not free text
+fn validate_emoji(emoji: &str) -> Result<(), WsError> {
+    let valid = !emoji.is_empty()
+        && emoji.len() <= 32
+        && !emoji.chars().any(|c| c.is_alphanumeric() || c.is_whitespace() || c.is_control());
+    if valid {
+        Ok(())
+    } else {
+        Err(WsError::new(ErrorCode::InvalidRequest, "Reactions must be a single emoji"))
+    }
+}
+
+// Adds the user's reaction, or takes it back if they already reacted with that emoji
+fn toggle_reaction(reactions: &mut BTreeMap<String, Vec<Uuid>>, user_id: Uuid, emoji: &str) -> Result<(), WsError> {
+    if let Some(users) = reactions.get_mut(emoji) {
+        if let Some(index) = users.iter().position(|id| *id == user_id) {
+            users.remove(index);
+            if users.is_empty() {
+                reactions.remove(emoji);
+            }
+        } else {
+            users.push(user_id);
+        }
+        return Ok(());
+    }
+    if reactions.len() >= MAX_REACTION_KINDS {
+        return Err(WsError::new(ErrorCode::InvalidRequest, "This message has too many different reactions"));
+    }
+    reactions.insert(emoji.to_string(), vec![user_id]);
+    Ok(())
+}
+
+// Some(until) while the user's chat is muted in the session; expired mutes are dropped
+async fn muted_until(session_state: &SessionState, session_id: Uuid, user_id: Uuid) -> Option<Option<DateTime<Utc>>> {
+    let sessions = session_state.sessions.read().await;
+    let session_info = sessions.get(&session_id)?;
+    let mut muted = session_info.muted.write().await;
+    match muted.get(&user_id).copied() {
+        Some(Some(until)) if until <= Utc::now() => {
+            muted.remove(&user_id);
+            None
+        }
+        entry => entry,
+    }
+}
+
+// Rejects characters from a different campaign than the session the client is in
+async fn ensure_session_campaign(session_state: &SessionState, session_id: Uuid, scope: Option<&CampaignScope>) -> Result<(), WsError> {
+    let campaign_id = get_session_campaign_id(session_state, session_id).await;
+    match (scope, campaign_id) {
+        (Some(scope), Some(campaign_id)) if scope.contains(campaign_id) => Ok(()),
+        _ => Err(WsError::new(ErrorCode::AccessDenied, "Character does not belong to current session's campaign")),
+    }
+}
+
+// Advances the running combat to the next turn and returns the new turn with the
+// TurnChanged message to publish
+async fn advance_session_turn(
+    pool: &PgPool,
     session_state: &SessionState,
     session_id: Uuid,
-    message: &ServerMessage,
+) -> Result<(Uuid, ServerMessage), WsError> {
+    let mut tx = pool.begin().await.map_err(WsError::database)?;
+    let (combat_id, mut game_state) = crate::combat::lock_active(&mut tx, session_id)
+        .await
+        .map_err(WsError::database)?
+        .ok_or_else(|| WsError::new(ErrorCode::InvalidRequest, "Combat is not active"))?;
+
+    // Advance to next turn, skipping surprised (round 1) and downed combatants
+    let advance = game_state.advance_turn();
+    let current_turn = game_state.current_turn.ok_or_else(|| WsError::new(ErrorCode::InvalidRequest, "No initiative order set"))?;
+
+    // Save updated game state
+    crate::combat::save(&mut tx, session_id, Some(combat_id), &game_state)
+        .await
+        .map_err(|e| WsError::internal("Failed to update game state").caused_by(e))?;
+    tx.commit().await.map_err(WsError::database)?;
+
+    // Surprise flags were cleared, so the tracker needs the updated order
+    if advance.surprise_ended {
+        broadcast_to_session(session_state, session_id, &ServerMessage::InitiativeUpdated {
+            session_id,
+            initiative_order: game_state.initiative_order.clone(),
+            current_turn: game_state.current_turn,
+        }, Recipients::All).await;
+    }
+
+    Ok((current_turn, ServerMessage::TurnChanged {
+        session_id,
+        current_turn,
+        round: game_state.round,
+        skipped: advance.skipped,
+    }))
+}
+
+// Cancels any running countdown and, when a limit is set and someone is up, starts a new one
+async fn restart_turn_timer(pool: &PgPool, session_state: &SessionState, session_id: Uuid, current_turn: Option<Uuid>) {
+    let sessions = session_state.sessions.read().await;
+    let Some(session_info) = sessions.get(&session_id) else {
+        return;
+    };
+    let mut timer = session_info.turn_timer.lock().await;
+    if let Some(task) = timer.task.take() {
+        task.abort();
+    }
+    timer.deadline = None;
+    let Some(seconds) = timer.seconds else {
+        timer.frozen = None;
+        return;
+    };
+    let limit = Duration::from_secs(seconds.into());
+    if timer.paused {
+        // The new turn gets its full time once the session resumes
+        timer.frozen = current_turn.map(|turn| (turn, limit));
+        return;
+    }
+    if let Some(current_turn) = current_turn {
+        spawn_turn_timer(pool, session_state, session_info, &mut timer, session_id, current_turn, seconds, limit);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_turn_timer(
+    pool: &PgPool,
+    session_state: &SessionState,
+    session_info: &SessionInfo,
+    timer: &mut TurnTimer,
+    session_id: Uuid,
+    current_turn: Uuid,
+    seconds: u32,
+    turn_limit: Duration,
+) {
+    let task = tokio::spawn(run_turn_timer(
+        pool.clone(),
+        session_state.clone(),
+        session_info.turn_timer.clone(),
+        session_id,
+        current_turn,
+        seconds,
+        turn_limit,
+        timer.auto_advance,
+    ));
+    timer.task = Some(task.abort_handle());
+}
+
+// Stops the countdown where it is, keeping the time left on the current turn
+async fn freeze_turn_timer(session_state: &SessionState, session_id: Uuid) {
+    let sessions = session_state.sessions.read().await;
+    let Some(session_info) = sessions.get(&session_id) else {
+        return;
+    };
+    let mut timer = session_info.turn_timer.lock().await;
+    if let Some(task) = timer.task.take() {
+        task.abort();
+    }
+    timer.paused = true;
+    let now = tokio::time::Instant::now();
+    if let Some((turn, deadline)) = timer.deadline.take() {
+        timer.frozen = Some((turn, deadline.saturating_duration_since(now)));
+    }
+}
+
+async fn thaw_turn_timer(pool: &PgPool, session_state: &SessionState, session_id: Uuid) {
+    let sessions = session_state.sessions.read().await;
+    let Some(session_info) = sessions.get(&session_id) else {
+        return;
+    };
+    let mut timer = session_info.turn_timer.lock().await;
+    timer.paused = false;
+    if let (Some((turn, remaining)), Some(seconds)) = (timer.frozen.take(), timer.seconds) {
+        spawn_turn_timer(pool, session_state, session_info, &mut timer, session_id, turn, seconds, remaining);
+    }
+}
+
+// Called once the session row is marked paused: turn timers stop and the table is told
+pub async fn pause_session(session_state: &SessionState, session_id: Uuid, paused_at: DateTime<Utc>) {
+    freeze_turn_timer(session_state, session_id).await;
+    broadcast_to_session(session_state, session_id, &ServerMessage::SessionPaused { session_id, paused_at }, Recipients::All).await;
+}
+
+pub async fn resume_session(pool: &PgPool, session_state: &SessionState, session_id: Uuid, paused_seconds: i32) {
+    broadcast_to_session(session_state, session_id, &ServerMessage::SessionResumed { session_id, paused_seconds }, Recipients::All).await;
+    thaw_turn_timer(pool, session_state, session_id).await;
+}
+
+// Whether `timer` still belongs to the live session entry (it's dropped when everyone leaves)
+async fn is_current_timer(session_state: &SessionState, session_id: Uuid, timer: &Arc<Mutex<TurnTimer>>) -> bool {
+    let sessions = session_state.sessions.read().await;
+    sessions.get(&session_id).is_some_and(|session_info| Arc::ptr_eq(&session_info.turn_timer, timer))
+}
+
+// Counts down one turn at a time, warning as time runs low. On expiry the turn is advanced
+// and the countdown continues, or the DM is left to decide when auto-advance is off.
+#[allow(clippy::too_many_arguments)]
+async fn run_turn_timer(
+    pool: PgPool,
+    session_state: SessionState,
+    timer: Arc<Mutex<TurnTimer>>,
+    session_id: Uuid,
+    mut current_turn: Uuid,
+    seconds: u32,
+    // Time for the first turn, which is less than `seconds` when resuming after a pause
+    mut turn_limit: Duration,
+    auto_advance: bool,
 ) {
-    // This would broadcast to all connected clients in the session
-    // For now, we'll just log the message with session info
+    loop {
+        let deadline = tokio::time::Instant::now() + turn_limit;
+        timer.lock().await.deadline = Some((current_turn, deadline));
+        let expires_at = Utc::now() + chrono::Duration::from_std(turn_limit).unwrap_or_default();
+        broadcast_to_session(&session_state, session_id, &ServerMessage::TurnTimerStarted { session_id, current_turn, expires_at }, Recipients::All).await;
+
+        for seconds_remaining in TURN_TIMER_WARNINGS.into_iter().filter(|remaining| Duration::from_secs((*remaining).into()) < turn_limit) {
+            tokio::time::sleep_until(deadline - Duration::from_secs(seconds_remaining.into())).await;
+            broadcast_to_session(&session_state, session_id, &ServerMessage::TurnTimerWarning { session_id, current_turn, seconds_remaining }, Recipients::All).await;
+        }
+        tokio::time::sleep_until(deadline).await;
+
+        if !is_current_timer(&session_state, session_id, &timer).await {
+            return;
+        }
+        broadcast_to_session(&session_state, session_id, &ServerMessage::TurnTimerExpired {
+            session_id,
+            current_turn,
+            auto_advanced: auto_advance,
+        }, Recipients::All).await;
+        if !auto_advance {
+            return;
+        }
+
+        match advance_session_turn(&pool, &session_state, session_id).await {
+            Ok((next_turn, message)) => {
+                broadcast_to_session(&session_state, session_id, &message, Recipients::All).await;
+                current_turn = next_turn;
+                turn_limit = Duration::from_secs(seconds.into());
+            }
+            Err(e) => {
+                tracing::error!(error = %e, %session_id, "Failed to auto-advance turn");
+                return;
+            }
+        }
+    }
+}
+
+// Session events go to every connection, the sender included, so everyone sees the same
+// sequence numbers. A sender outside the session gets a direct (unsequenced) reply instead.
+// Saves a roll to the session's event log. Hidden rolls are flagged so the log only shows
+// them to the DM and the roller; secret ones are also marked as such.
+async fn record_roll(
+    pool: &PgPool,
+    session_id: Uuid,
+    user_id: Uuid,
+    result: &DiceResult,
+    dm_only: bool,
+    secret: bool,
+) -> Result<Uuid, WsError> {
+    let mut event_data = serde_json::to_value(result).map_err(|e| WsError::internal("Failed to encode roll").caused_by(e))?;
+    event_data["hidden"] = serde_json::Value::Bool(dm_only || secret);
+    event_data["secret"] = serde_json::Value::Bool(secret);
+    sqlx::query_scalar::<_, Uuid>(
+        "INSERT INTO event_logs (id, session_id, event_type, event_data, created_by, created_at) 
+         VALUES ($1, $2, 'dice_roll', $3, $4, $5) RETURNING id"
+    )
+    .bind(Uuid::new_v4())
+    .bind(session_id)
+    .bind(event_data)
+    .bind(user_id)
+    .bind(Utc::now())
+    .fetch_one(pool)
+    .await
+    .map_err(|e| WsError::internal("Failed to save roll").caused_by(e))
+}
+
+// Publishes through the event bus. Like publish_to_session, the sender gets the broadcast
+// back directly when it won't reach them.
+async fn publish_event(pool: &PgPool, session_state: &SessionState, user_id: Uuid, event: DomainEvent) -> Result<Option<ServerMessage>, WsError> {
+    let reply = match event.broadcast() {
+        Some((session_id, message)) if !receives_broadcast(session_state, session_id, user_id, Recipients::All).await => Some(message),
+        _ => None,
+    };
+    crate::events::publish(pool, session_state, &event).await.map_err(WsError::database)?;
+    Ok(reply)
+}
+
+async fn publish_to_session(
+    session_state: &SessionState,
+    session_id: Option<Uuid>,
+    user_id: Uuid,
+    message: ServerMessage,
+) -> Option<ServerMessage> {
+    publish_to(session_state, session_id, user_id, message, Recipients::All).await
+}
+
+// Broadcasts to `recipients`; the message is handed back for the sender if the broadcast
+// won't reach them, so they always see the result of their own message
+async fn publish_to(
+    session_state: &SessionState,
+    session_id: Option<Uuid>,
+    user_id: Uuid,
+    message: ServerMessage,
+    recipients: Recipients,
+) -> Option<ServerMessage> {
+    match session_id {
+        Some(session_id) => {
+            let sender_receives = receives_broadcast(session_state, session_id, user_id, recipients).await;
+            broadcast_to_session(session_state, session_id, &message, recipients).await;
+            if sender_receives {
+                None
+            } else {
+                Some(message)
+            }
+        }
+        None => Some(message),
+    }
+}
+
+async fn receives_broadcast(session_state: &SessionState, session_id: Uuid, user_id: Uuid, recipients: Recipients) -> bool {
+    let sessions = session_state.sessions.read().await;
+    match sessions.get(&session_id) {
+        Some(session_info) => session_info
+            .connections
+            .read()
+            .await
+            .get(&user_id)
+            .is_some_and(|conn| recipients.includes(conn)),
+        None => false,
+    }
+}
+
+async fn get_last_seq(session_state: &SessionState, session_id: Uuid) -> u64 {
+    let sessions = session_state.sessions.read().await;
+    match sessions.get(&session_id) {
+        Some(session_info) => session_info.history.read().await.last_seq,
+        None => 0,
+    }
+}
+
+async fn record_ack(session_state: &SessionState, session_id: Uuid, user_id: Uuid, seq: u64) {
     let sessions = session_state.sessions.read().await;
     if let Some(session_info) = sessions.get(&session_id) {
-        println!("Broadcasting to session {} (campaign: {}): {:?}", 
-                 session_info.session_id, session_info.campaign_id, message);
-    } else {
-        println!("Broadcasting to session {}: {:?}", session_id, message);
+        if let Some(conn) = session_info.connections.write().await.get_mut(&user_id) {
+            conn.last_acked_seq = conn.last_acked_seq.max(seq);
+        }
     }
 }
 
-// Dice rolling functionality
-struct DiceRoll {
-    total: i32,
-    rolls: Vec<i32>,
+async fn get_last_acked_seq(session_state: &SessionState, session_id: Uuid, user_id: Uuid) -> u64 {
+    let sessions = session_state.sessions.read().await;
+    match sessions.get(&session_id) {
+        Some(session_info) => session_info
+            .connections
+            .read()
+            .await
+            .get(&user_id)
+            .map_or(0, |conn| conn.last_acked_seq),
+        None => 0,
+    }
 }
 
-fn roll_dice(dice: &str) -> Result<DiceRoll, String> {
-    // Simple dice parser for common formats like "2d6+3", "1d20", etc.
-    let parts: Vec<&str> = dice.split('+').collect();
-    let dice_part = parts[0];
-    let modifier = if parts.len() > 1 {
-        parts[1].parse::<i32>().unwrap_or(0)
+// Resends every buffered message after `since_seq`, or tells the client to refetch the
+// full state when the gap is older than the replay buffer
+async fn replay_since(
+    session_state: &SessionState,
+    session_id: Uuid,
+    since_seq: u64,
+    sender: &SocketSender,
+) -> Result<Option<ServerMessage>, WsError> {
+    let (missed, oldest_seq, last_seq) = {
+        let sessions = session_state.sessions.read().await;
+        let session_info = sessions.get(&session_id).ok_or_else(|| WsError::new(ErrorCode::NotInSession, "Not in a session"))?;
+        let history = session_info.history.read().await;
+        let oldest_seq = history.messages.front().map_or(history.last_seq + 1, |(seq, _)| *seq);
+        let missed: Vec<String> = history
+            .messages
+            .iter()
+            .filter(|(seq, _)| *seq > since_seq)
+            .map(|(_, text)| text.clone())
+            .collect();
+        (missed, oldest_seq, history.last_seq)
+    };
+
+    if since_seq + 1 < oldest_seq {
+        return Ok(Some(ServerMessage::ReplayUnavailable { session_id, oldest_seq, last_seq }));
+    }
+
+    for text in missed {
+        sender
+            .send(text)
+            .map_err(|e| WsError::internal("Failed to replay message").caused_by(e))?;
+    }
+    Ok(None)
+}
+
+// Returns true when the typing event should be forwarded to the rest of the session
+async fn update_typing_state(
+    session_state: &SessionState,
+    session_id: Uuid,
+    user_id: Uuid,
+    is_typing: bool,
+) -> bool {
+    let sessions = session_state.sessions.read().await;
+    if let Some(session_info) = sessions.get(&session_id) {
+        let mut connections = session_info.connections.write().await;
+        if let Some(conn) = connections.get_mut(&user_id) {
+            if is_typing {
+                if conn.last_typing.is_some_and(|last| last.elapsed() < TYPING_DEBOUNCE) {
+                    return false;
+                }
+                conn.last_typing = Some(Instant::now());
+                return true;
+            }
+            // Only announce "stopped typing" if we announced that they started
+            return conn.last_typing.take().is_some();
+        }
+    }
+    false
+}
+
+// Sends a message to every connection in the session, optionally skipping one user
+// (usually the sender, who already receives the direct reply)
+pub(crate) async fn broadcast_to_session(
+    session_state: &SessionState,
+    session_id: Uuid,
+    message: &ServerMessage,
+    recipients: Recipients,
+) {
+    let started = Instant::now();
+    let sessions = session_state.sessions.read().await;
+    let Some(session_info) = sessions.get(&session_id) else {
+        return;
+    };
+    // Never the payload: DM-only messages would end up in the logs
+    tracing::trace!(%session_id, campaign_id = %session_info.campaign_id, ?recipients, "Broadcasting to session");
+    *session_info.last_active.lock().await = Instant::now();
+
+    // Assign the next sequence number and remember the message for replay.
+    // SSE subscribers are fed under the history lock so a subscriber never misses
+    // or duplicates a message between its history snapshot and the live stream.
+    // Connections are queued under it too, so every client gets messages in seq order.
+    let mut history = None;
+    let text = if recipients == Recipients::DmOnly {
+        encode_broadcast(message, session_id, None)
+    } else if message.is_ephemeral() {
+        let text = encode_broadcast(message, session_id, None);
+        let _ = session_info.events.send((None, text.clone()));
+        text
     } else {
-        0
+        let history = history.insert(session_info.history.write().await);
+        history.last_seq += 1;
+        let seq = history.last_seq;
+        let text = encode_broadcast(message, session_id, Some(seq));
+        history.messages.push_back((seq, text.clone()));
+        if history.messages.len() > HISTORY_CAPACITY {
+            history.messages.pop_front();
+        }
+        let _ = session_info.events.send((Some(seq), text.clone()));
+        text
     };
-    
-    let dice_parts: Vec<&str> = dice_part.split('d').collect();
-    if dice_parts.len() != 2 {
-        return Err("Invalid dice format. Use format like '2d6+3'".to_string());
+
+    // Encode once per frame encoding rather than once per recipient. Queueing never
+    // waits, so a slow client can't hold up the others.
+    let connections = session_info.connections.read().await;
+    let mut frames: HashMap<FrameEncoding, Message> = HashMap::new();
+    for recipient in connections.values().filter(|conn| recipients.includes(conn)).map(|conn| &conn.sender) {
+        let frame = match frames.get(&recipient.encoding) {
+            Some(frame) => frame.clone(),
+            None => match encode_frame(text.clone(), recipient.encoding) {
+                Ok(frame) => {
+                    frames.insert(recipient.encoding, frame.clone());
+                    frame
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, %session_id, "Failed to encode broadcast");
+                    continue;
+                }
+            },
+        };
+        if let Err(e) = recipient.send_frame(frame) {
+            tracing::warn!(error = %e, %session_id, "Failed to broadcast message");
+        }
     }
-    
-    let count = dice_parts[0].parse::<i32>().map_err(|_| "Invalid dice count".to_string())?;
-    let sides = dice_parts[1].parse::<i32>().map_err(|_| "Invalid dice sides".to_string())?;
-    
-    if count <= 0 || sides <= 0 {
-        return Err("Dice count and sides must be positive".to_string());
+    drop(connections);
+    drop(history);
+    session_state.metrics.broadcast_latency.observe(started.elapsed());
+}
+
+// Most `[[dice]]` one chat message may roll
+const MAX_INLINE_ROLLS: usize = 10;
+
+// Every `[[2d6+3]]` in a chat message, in order of appearance, checked but not yet rolled
+fn inline_dice(message: &str) -> Result<Vec<String>, WsError> {
+    let mut found = Vec::new();
+    let mut rest = message;
+    while let Some(start) = rest.find("[[") {
+        let Some(len) = rest[start + 2..].find("]]") else {
+            break;
+        };
+        let dice = rest[start + 2..start + 2 + len].trim();
+        rest = &rest[start + 2 + len + 2..];
+
+        if found.len() == MAX_INLINE_ROLLS {
+            return Err(WsError::new(ErrorCode::InvalidDice, format!("At most {} inline rolls per message", MAX_INLINE_ROLLS)));
+        }
+        crate::dice::parse(dice).map_err(|e| WsError::new(ErrorCode::InvalidDice, format!("[[{}]]: {}", dice, e)))?;
+        found.push(dice.to_string());
     }
-    
-    let mut rolls = Vec::new();
-    let mut total = 0;
-    
-    for _ in 0..count {
-        let roll = (rand::random::<u32>() % sides as u32 + 1) as i32;
-        rolls.push(roll);
-        total += roll;
+    Ok(found)
+}
+
+// Where a roll's seed comes from: the next counter value of a seeded session, the session's
+// committed server seed, or the OS outside a session
+#[derive(Debug, Clone)]
+struct RollSeed {
+    seed: u64,
+    counter: Option<u64>,
+    proof: Option<RollProof>,
+}
+
+// Seeds for the next `count` rolls. Sessions hand out consecutive counter values or nonces,
+// so check the expressions first to keep failed rolls from leaving gaps in the sequence.
+async fn roll_seeds(pool: &PgPool, session_id: Option<Uuid>, count: usize, client_seed: &str) -> Result<Vec<RollSeed>, WsError> {
+    if let (Some(session_id), true) = (session_id, count > 0) {
+        let reserved = sqlx::query_as::<_, (i64, i64)>(
+            "UPDATE sessions SET roll_counter = roll_counter + $1 WHERE id = $2 AND rng_seed IS NOT NULL
+             RETURNING rng_seed, roll_counter"
+        )
+        .bind(count as i64)
+        .bind(session_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(WsError::database)?;
+        if let Some((rng_seed, last)) = reserved {
+            let first = last as u64 + 1 - count as u64;
+            return Ok((first..=last as u64)
+                .map(|counter| RollSeed { seed: session_roll_seed(rng_seed as u64, counter), counter: Some(counter), proof: None })
+                .collect());
+        }
+
+        let server_seed = crate::fairness::reserve_nonces(pool, session_id, count).await.map_err(WsError::database)?;
+        let last = server_seed.next_nonce as u64;
+        return Ok((last - count as u64..last)
+            .map(|nonce| RollSeed {
+                seed: crate::fairness::roll_seed(&server_seed.server_seed, client_seed, nonce),
+                counter: None,
+                proof: Some(RollProof { commitment: server_seed.commitment.clone(), nonce, client_seed: client_seed.to_string() }),
+            })
+            .collect());
     }
-    
-    total += modifier;
-    
-    Ok(DiceRoll { total, rolls })
-} 
\ No newline at end of file
+    Ok((0..count).map(|_| RollSeed { seed: fresh_seed(), counter: None, proof: None }).collect())
+}
+
+fn check_dice(dice: &str) -> Result<(), WsError> {
+    crate::dice::parse(dice).map(|_| ()).map_err(|e| WsError::new(ErrorCode::InvalidDice, e))
+}
+
+// The damage expression of an attack and its doubled critical form, both checked before any
+// seed is reserved
+fn attack_damage(dice: &str, damage: &str) -> Result<(String, String), WsError> {
+    let terms = crate::dice::parse(dice).map_err(|e| WsError::new(ErrorCode::InvalidDice, e))?;
+    if !terms.iter().any(|term| !term.negative && matches!(term.kind, crate::dice::TermKind::Dice { sides: 20, .. })) {
+        return Err(WsError::new(ErrorCode::InvalidDice, "An attack roll needs a d20"));
+    }
+    let doubled = crate::dice::critical_damage(damage).map_err(|e| WsError::new(ErrorCode::InvalidDice, format!("Damage: {}", e)))?;
+    crate::dice::parse(&doubled).map_err(|e| WsError::new(ErrorCode::InvalidDice, format!("Critical damage: {}", e)))?;
+    Ok((damage.to_string(), doubled))
+}
+
+// A kept d20 showing 20
+fn natural_20(roll: &DiceResult) -> bool {
+    roll.faces.iter().any(|face| face.sides == 20 && face.value == 20 && !face.dropped)
+}
+
+// A plain roll of `dice`; callers fill in the reason, advantage and check
+fn roll_from(dice: &str, seed: RollSeed) -> Result<DiceResult, WsError> {
+    let roll = roll_dice(dice, seed.seed).map_err(|e| WsError::new(ErrorCode::InvalidDice, e))?;
+    Ok(DiceResult {
+        dice: dice.to_string(),
+        result: roll.total,
+        rolls: roll.rolls,
+        reason: None,
+        seed: seed.seed,
+        roll_counter: seed.counter,
+        proof: seed.proof,
+        damage: None,
+        critical: false,
+        faces: roll.faces,
+        terms: roll.terms,
+        advantage: None,
+        check: None,
+    })
+}
+
+// Six sets of 4d6 for the standard "roll 4, drop the lowest" method
+fn roll_ability_scores() -> Vec<Vec<i32>> {
+    (0..6)
+        .map(|_| (0..4).map(|_| (rand::random::<u32>() % 6 + 1) as i32).collect())
+        .collect()
+}
+
+fn ability_score(dice: &[i32]) -> i32 {
+    dice.iter().sum::<i32>() - dice.iter().min().copied().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequenced_message_format() {
+        let player_id = Uuid::new_v4();
+        let text = encode_message(&ServerMessage::PlayerLeft { player_id }, Some(7));
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+
+        assert_eq!(value["type"], "PlayerLeft");
+        assert_eq!(value["data"]["player_id"], player_id.to_string());
+        assert_eq!(value["seq"], 7);
+    }
+
+    #[test]
+    fn test_ability_scores_drop_lowest_die() {
+        assert_eq!(ability_score(&[6, 1, 4, 3]), 13);
+        assert_eq!(ability_score(&[2, 2, 2, 2]), 6);
+
+        let rolls = roll_ability_scores();
+        assert_eq!(rolls.len(), 6);
+        for dice in &rolls {
+            assert_eq!(dice.len(), 4);
+            assert!(dice.iter().all(|d| (1..=6).contains(d)));
+            assert!((3..=18).contains(&ability_score(dice)));
+        }
+    }
+
+    #[test]
+    fn test_unsequenced_message_has_no_seq() {
+        let text = encode_message(&ServerMessage::from(WsError::new(ErrorCode::InvalidRequest, "nope")), None);
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+
+        assert_eq!(value["type"], "Error");
+        assert!(value.get("seq").is_none());
+    }
+
+    #[test]
+    fn test_patch_game_state_message() {
+        let json = r#"{"type":"PatchGameState","data":{"patch":[
+            {"op":"test","path":"/round","value":2},
+            {"op":"replace","path":"/round","value":3}
+        ]}}"#;
+        let msg: ClientMessage = serde_json::from_str(json).unwrap();
+        let patch = match msg {
+            ClientMessage::PatchGameState { patch } => patch,
+            _ => panic!("expected PatchGameState"),
+        };
+
+        let mut state = serde_json::json!({"round": 2, "combat_active": true});
+        json_patch::patch(&mut state, &patch).unwrap();
+        assert_eq!(state, serde_json::json!({"round": 3, "combat_active": true}));
+
+        // A failed test op rejects the whole patch
+        let mut stale = serde_json::json!({"round": 5});
+        assert!(json_patch::patch(&mut stale, &patch).is_err());
+        assert_eq!(stale, serde_json::json!({"round": 5}));
+    }
+
+    #[tokio::test]
+    async fn test_ws_ticket_is_single_use() {
+        let state = SessionState::new(None);
+        let ticket = WsTicket {
+            user_id: Uuid::new_v4(),
+            username: "dm".to_string(),
+            session_id: Uuid::new_v4(),
+            is_dm: true,
+            expires_at: Utc::now() + chrono::Duration::seconds(WS_TICKET_TTL_SECS),
+        };
+
+        let token = issue_ws_ticket(&state, ticket.clone()).await.unwrap();
+        let consumed = consume_ws_ticket(&state, &token).await.unwrap();
+        assert_eq!(consumed.user_id, ticket.user_id);
+        assert!(consumed.is_dm);
+        assert!(consume_ws_ticket(&state, &token).await.is_none());
+
+        let expired = WsTicket { expires_at: Utc::now() - chrono::Duration::seconds(1), ..ticket };
+        let token = issue_ws_ticket(&state, expired).await.unwrap();
+        assert!(consume_ws_ticket(&state, &token).await.is_none());
+    }
+
+    #[test]
+    fn test_msgpack_frames_mirror_json() {
+        let player_id = Uuid::new_v4();
+        let text = encode_message(&ServerMessage::PlayerLeft { player_id }, Some(3));
+        let encoding = FrameEncoding { format: WireFormat::MessagePack, compress_min_bytes: None };
+        let bytes = match encode_frame(text, encoding).unwrap() {
+            Message::Binary(bytes) => bytes,
+            _ => panic!("expected a binary frame"),
+        };
+        let value: serde_json::Value = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(value["type"], "PlayerLeft");
+        assert_eq!(value["data"]["player_id"], player_id.to_string());
+        assert_eq!(value["seq"], 3);
+
+        // Clients encode their messages with the same layout
+        let session_id = Uuid::new_v4();
+        let request = serde_json::json!({"type": "JoinSession", "data": {"session_id": session_id}});
+        let bytes = rmp_serde::to_vec_named(&request).unwrap();
+        match rmp_serde::from_slice::<ClientMessage>(&bytes).unwrap() {
+            ClientMessage::JoinSession { session_id: id } => assert_eq!(id, session_id),
+            _ => panic!("expected JoinSession"),
+        }
+    }
+
+    #[test]
+    fn test_large_frames_are_compressed() {
+        use flate2::read::ZlibDecoder;
+        use std::io::Read;
+
+        let message = ServerMessage::GameStateUpdated { game_state: serde_json::json!({"notes": "x".repeat(2000)}) };
+        let text = encode_message(&message, Some(1));
+        let encoding = FrameEncoding { format: WireFormat::Json, compress_min_bytes: Some(1024) };
+
+        let compressed = match encode_frame(text.clone(), encoding).unwrap() {
+            Message::Binary(bytes) => bytes,
+            _ => panic!("expected a compressed binary frame"),
+        };
+        assert!(compressed.len() < text.len());
+        let mut decompressed = String::new();
+        ZlibDecoder::new(compressed.as_slice()).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, text);
+
+        // Small messages stay plain text
+        let small = encode_message(&ServerMessage::from(WsError::new(ErrorCode::InvalidRequest, "nope")), None);
+        assert!(matches!(encode_frame(small, encoding).unwrap(), Message::Text(_)));
+    }
+
+    #[tokio::test]
+    async fn test_event_subscribers_mirror_broadcasts() {
+        let state = SessionState::new(None);
+        let session_id = Uuid::new_v4();
+        let (missed, mut receiver) = subscribe_events(&state, session_id, Uuid::new_v4(), None).await;
+        assert!(missed.is_empty());
+
+        broadcast_to_session(&state, session_id, &ServerMessage::PlayerLeft { player_id: Uuid::new_v4() }, Recipients::All).await;
+        broadcast_to_session(&state, session_id, &ServerMessage::PlayerLeft { player_id: Uuid::new_v4() }, Recipients::All).await;
+        let (seq, text) = receiver.recv().await.unwrap();
+        assert_eq!(seq, Some(1));
+        assert!(text.contains("PlayerLeft"));
+
+        // A reconnecting subscriber gets what it missed from history
+        let (missed, replay_receiver) = subscribe_events(&state, session_id, Uuid::new_v4(), Some(1)).await;
+        assert_eq!(missed.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(), vec![2]);
+
+        // The session stays around until the last subscriber is gone
+        drop(receiver);
+        drop(replay_receiver);
+        release_events(&state, session_id).await;
+        assert!(state.sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dm_only_broadcasts_skip_replay_and_sse() {
+        let state = SessionState::new(None);
+        let session_id = Uuid::new_v4();
+        let (_, mut receiver) = subscribe_events(&state, session_id, Uuid::new_v4(), None).await;
+
+        let hidden = ServerMessage::DiceRolled {
+            player_id: Uuid::new_v4(),
+            roll_id: None,
+            secret: false,
+            result: DiceResult {
+                dice: "1d20".to_string(),
+                result: 17,
+                rolls: vec![17],
+                reason: Some("Insight".to_string()),
+                seed: 7,
+                roll_counter: None,
+                proof: None,
+                damage: None,
+                critical: false,
+                faces: vec![DieFace { id: "d20-0".to_string(), sides: 20, value: 17, dropped: false }],
+                terms: Vec::new(),
+                advantage: None,
+                check: None,
+            },
+        };
+        broadcast_to_session(&state, session_id, &hidden, Recipients::DmOnly).await;
+        broadcast_to_session(&state, session_id, &ServerMessage::PlayerLeft { player_id: Uuid::new_v4() }, Recipients::All).await;
+
+        let (seq, text) = receiver.recv().await.unwrap();
+        assert_eq!(seq, Some(1));
+        assert!(text.contains("PlayerLeft"));
+        assert_eq!(get_last_seq(&state, session_id).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_janitor_evicts_idle_sessions_and_saves_live_state() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let state = SessionState::new(None);
+
+        // An older session left behind by a stream, and a newer one
+        let (_, receiver) = subscribe_events(&state, f.session_id, f.campaign_id, None).await;
+        state.sessions.read().await[&f.session_id].turn_timer.lock().await.seconds = Some(90);
+        broadcast_to_session(&state, f.session_id, &ServerMessage::PlayerLeft { player_id: f.player_id }, Recipients::All).await;
+        drop(receiver);
+        let newer = Uuid::new_v4();
+        let _ = subscribe_events(&state, newer, f.campaign_id, None).await;
+
+        // Over the cap of one, the least recently active goes first
+        sweep_sessions(&pool, &state, 1, Duration::from_secs(3600)).await;
+        assert!(!state.sessions.read().await.contains_key(&f.session_id));
+        assert!(state.sessions.read().await.contains_key(&newer));
+        assert_eq!(state.evictions.capacity.load(Ordering::Relaxed), 1);
+
+        let live_state = sqlx::query_scalar::<_, Option<serde_json::Value>>("SELECT live_state FROM sessions WHERE id = $1")
+            .bind(f.session_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let live_state: LiveState = serde_json::from_value(live_state.unwrap()).unwrap();
+        let restored = SessionInfo::restore(f.session_id, f.campaign_id, Some(live_state));
+        assert_eq!(restored.history.read().await.last_seq, 1);
+        assert_eq!(restored.turn_timer.lock().await.seconds, Some(90));
+
+        // Past the TTL, idle sessions go regardless of the cap
+        sweep_sessions(&pool, &state, 1000, Duration::ZERO).await;
+        assert!(state.sessions.read().await.is_empty());
+        assert_eq!(state.evictions.idle.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_turn_timer_expires_without_auto_advance() {
+        let state = SessionState::new(None);
+        let session_id = Uuid::new_v4();
+        let (_, mut receiver) = subscribe_events(&state, session_id, Uuid::new_v4(), None).await;
+        state.sessions.read().await[&session_id].turn_timer.lock().await.seconds = Some(1);
+
+        // Without auto-advance the database is never touched
+        let pool = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let current_turn = Uuid::new_v4();
+        restart_turn_timer(&pool, &state, session_id, Some(current_turn)).await;
+
+        let (_, started) = receiver.recv().await.unwrap();
+        assert!(started.contains("TurnTimerStarted"));
+        let (_, expired) = receiver.recv().await.unwrap();
+        let expired: serde_json::Value = serde_json::from_str(&expired).unwrap();
+        assert_eq!(expired["type"], "TurnTimerExpired");
+        assert_eq!(expired["data"]["current_turn"], current_turn.to_string());
+        assert_eq!(expired["data"]["auto_advanced"], false);
+    }
+
+    #[tokio::test]
+    async fn test_pause_freezes_turn_timer() {
+        let state = SessionState::new(None);
+        let session_id = Uuid::new_v4();
+        let (_, mut receiver) = subscribe_events(&state, session_id, Uuid::new_v4(), None).await;
+        state.sessions.read().await[&session_id].turn_timer.lock().await.seconds = Some(2);
+
+        let pool = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let current_turn = Uuid::new_v4();
+        restart_turn_timer(&pool, &state, session_id, Some(current_turn)).await;
+        let (_, started) = receiver.recv().await.unwrap();
+        assert!(started.contains("TurnTimerStarted"));
+
+        pause_session(&state, session_id, Utc::now()).await;
+        let (_, paused) = receiver.recv().await.unwrap();
+        assert!(paused.contains("SessionPaused"));
+        {
+            let sessions = state.sessions.read().await;
+            let timer = sessions[&session_id].turn_timer.lock().await;
+            assert!(timer.task.is_none());
+            let (turn, remaining) = timer.frozen.unwrap();
+            assert_eq!(turn, current_turn);
+            assert!(remaining > Duration::from_secs(1) && remaining <= Duration::from_secs(2));
+        }
+
+        // Nothing expires while the session is paused
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        assert!(receiver.try_recv().is_err());
+
+        resume_session(&pool, &state, session_id, 3).await;
+        let (_, resumed) = receiver.recv().await.unwrap();
+        assert!(resumed.contains("SessionResumed"));
+        let (_, restarted) = receiver.recv().await.unwrap();
+        assert!(restarted.contains("TurnTimerStarted"));
+        let (_, expired) = receiver.recv().await.unwrap();
+        let expired: serde_json::Value = serde_json::from_str(&expired).unwrap();
+        assert_eq!(expired["type"], "TurnTimerExpired");
+        assert_eq!(expired["data"]["current_turn"], current_turn.to_string());
+    }
+
+    #[test]
+    fn test_validate_declared_actions() {
+        let character = crate::models::Character {
+            id: Uuid::new_v4(),
+            campaign_id: Uuid::new_v4(),
+            player_id: None,
+            name: "Thorin".to_string(),
+            race: Some("Dwarf".to_string()),
+            class: Some("Cleric".to_string()),
+            level: 3,
+            hp_current: Some(12),
+            hp_max: Some(24),
+            ac: Some(16),
+            speed: Some(25),
+            stats: serde_json::json!({}),
+            inventory: serde_json::json!(["Warhammer", {"name": "Potion of Healing", "quantity": 2}]),
+            spells: serde_json::json!([{"name": "Cure Wounds", "level": 1}]),
+            features: serde_json::json!([]),
+            xp: 0,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        let action: DeclaredAction = serde_json::from_value(serde_json::json!({"kind": "use_item", "item": "potion of healing"})).unwrap();
+        assert!(validate_action(&character, &action).is_ok());
+        let attack = DeclaredAction::Attack { target_id: Some(Uuid::new_v4()), weapon: Some("warhammer".to_string()) };
+        assert!(validate_action(&character, &attack).is_ok());
+        let cast = DeclaredAction::Cast { spell: "Cure Wounds".to_string(), level: Some(1), target_ids: Vec::new() };
+        assert!(validate_action(&character, &cast).is_ok());
+
+        let unknown = DeclaredAction::Cast { spell: "Fireball".to_string(), level: None, target_ids: Vec::new() };
+        assert!(validate_action(&character, &unknown).is_err());
+        let too_far = DeclaredAction::Move { distance: 30, destination: None };
+        assert!(validate_action(&character, &too_far).is_err());
+        assert!(validate_action(&character, &DeclaredAction::Attack { target_id: None, weapon: Some("Longbow".to_string()) }).is_err());
+
+        // Downed characters can only declare custom actions
+        let downed = crate::models::Character { hp_current: Some(0), ..character };
+        assert!(validate_action(&downed, &attack).is_err());
+        assert!(validate_action(&downed, &DeclaredAction::Custom { description: "Death saving throw".to_string() }).is_ok());
+    }
+
+    #[test]
+    fn test_error_codes_and_rate_window() {
+        let error = WsError::new(ErrorCode::RateLimited, "Too many messages").with_context(serde_json::json!({"retry_after_ms": 250}));
+        let value: serde_json::Value = serde_json::from_str(&encode_message(&ServerMessage::from(error), None)).unwrap();
+        assert_eq!(value["type"], "Error");
+        assert_eq!(value["data"]["code"], "RATE_LIMITED");
+        assert_eq!(value["data"]["context"]["retry_after_ms"], 250);
+
+        // Context is omitted when there is none
+        let value: serde_json::Value = serde_json::from_str(&encode_message(&ServerMessage::from(WsError::new(ErrorCode::NotInSession, "Not in a session")), None)).unwrap();
+        assert_eq!(value["data"]["code"], "NOT_IN_SESSION");
+        assert!(value["data"].get("context").is_none());
+
+        // Server-side failures reach the client without their details
+        let error = WsError::database(sqlx::Error::Protocol("relation \"campaigns\" is locked".to_string()));
+        assert!(error.cause.as_deref().is_some_and(|cause| cause.contains("campaigns")));
+        let text = encode_message(&ServerMessage::from(error), None);
+        assert!(text.contains("INTERNAL") && !text.contains("campaigns"));
+
+        let mut rate = RateWindow::new();
+        for _ in 0..MESSAGE_RATE_LIMIT {
+            assert!(rate.check().is_ok());
+        }
+        assert!(rate.check().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_connections() {
+        let state = SessionState::new(None);
+        let mut shutdown = state.shutdown.subscribe();
+        let connection = tokio::spawn(async move {
+            shutdown_requested(&mut shutdown).await;
+        });
+
+        shut_down(&state).await;
+        assert!(connection.is_finished());
+        assert_eq!(state.shutdown.receiver_count(), 0);
+
+        // Connections opened after shutdown began see it immediately
+        let mut late = state.shutdown.subscribe();
+        tokio::time::timeout(Duration::from_secs(1), shutdown_requested(&mut late)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cross_campaign_messages_are_denied() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+
+        let messages = vec![
+            ClientMessage::JoinSession { session_id: f.session_id },
+            ClientMessage::CreateEventLog { session_id: f.session_id, event_type: "note".to_string(), event_data: serde_json::json!({}) },
+            ClientMessage::UpdateInitiative { session_id: f.session_id, initiative_order: Vec::new() },
+            ClientMessage::NextTurn { session_id: f.session_id },
+            ClientMessage::EndCombat { session_id: f.session_id },
+            ClientMessage::SetTurnTimer { session_id: f.session_id, seconds: Some(60), auto_advance: false },
+            ClientMessage::GroupRoll { session_id: f.session_id, entry_ids: Vec::new(), dice: "1d20".to_string(), reason: None, advantage: None, dm_only: false },
+            ClientMessage::RollAbilityScores { character_id: f.character_id },
+            ClientMessage::RollCheck { character_id: f.character_id, check: "perception".to_string(), advantage: None, dm_only: false, client_seed: None },
+            ClientMessage::PlayerAction { character_id: f.character_id, action: DeclaredAction::Custom { description: "Wave".to_string() } },
+            ClientMessage::UpdateCharacter { character_id: f.character_id, updates: serde_json::json!({"name": "Spy"}) },
+            ClientMessage::UpdateHP { character_id: f.character_id, hp_current: 0, hp_max: None },
+        ];
+
+        for msg in &messages {
+            assert!(message_resource(msg).is_some());
+            let err = authorize_message(&pool, f.outsider_id, msg).await.unwrap_err();
+            assert_eq!(err.code, ErrorCode::AccessDenied);
+        }
+
+        // Members get through on their own characters but not to DM-only messages
+        assert!(authorize_message(&pool, f.player_id, &messages[0]).await.unwrap().is_some());
+        assert!(authorize_message(&pool, f.player_id, &messages[3]).await.is_err());
+        assert!(authorize_message(&pool, f.player_id, &messages[11]).await.is_ok());
+        assert!(authorize_message(&pool, f.dm_id, &messages[3]).await.is_ok());
+        assert!(authorize_message(&pool, f.outsider_id, &ClientMessage::Heartbeat).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_unmapped_messages_stay_in_the_joined_session() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        // The outsider runs a session of their own, so they have a socket to send from
+        let own_session = Uuid::new_v4();
+        sqlx::query("INSERT INTO sessions (id, campaign_id, name, status, game_state, created_at, updated_at) SELECT $1, id, 'Elsewhere', 'active', '{}', NOW(), NOW() FROM campaigns WHERE dm_id = $2")
+            .bind(own_session)
+            .bind(f.outsider_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let chat_id = Uuid::new_v4();
+        sqlx::query("INSERT INTO event_logs (id, session_id, event_type, event_data, created_by, created_at) VALUES ($1, $2, 'chat_message', '{}', $3, NOW())")
+            .bind(chat_id)
+            .bind(f.session_id)
+            .bind(f.player_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let (app, addr) = serve_test_app(pool).await;
+
+        let mut dm = TestClient::connect(&app, addr, f.dm_id, f.session_id).await;
+        dm.join(f.session_id).await;
+        let mut outsider = TestClient::connect(&app, addr, f.outsider_id, own_session).await;
+        outsider.join(own_session).await;
+
+        // Messages without a resource of their own act on a joined session, so pointing them
+        // at another campaign's session gets nowhere
+        let messages = [
+            serde_json::json!({"type": "ChatMessage", "data": {"message": "hello"}}),
+            serde_json::json!({"type": "DiceRoll", "data": {"dice": "1d20", "reason": null}}),
+            serde_json::json!({"type": "React", "data": {"message_id": chat_id, "emoji": "👍"}}),
+            serde_json::json!({"type": "UpdateGameState", "data": {"game_state": {}}}),
+            serde_json::json!({"type": "PatchGameState", "data": {"patch": []}}),
+            serde_json::json!({"type": "KickPlayer", "data": {"user_id": f.player_id}}),
+            serde_json::json!({"type": "MutePlayer", "data": {"user_id": f.player_id, "seconds": null}}),
+            serde_json::json!({"type": "AIRequest", "data": {"prompt": "recap", "request_type": "summary", "context": null}}),
+            serde_json::json!({"type": "Typing", "data": {"is_typing": true}}),
+            serde_json::json!({"type": "RequestReplay", "data": {"since_seq": 0}}),
+        ];
+        for mut message in messages {
+            message["session_id"] = serde_json::json!(f.session_id);
+            outsider.send(message).await;
+            let error = outsider.expect("Error").await;
+            assert_eq!(error["data"]["code"], "NOT_IN_SESSION");
+        }
+
+        // Nor can their own session reach the other campaign's rows by id
+        outsider.send(serde_json::json!({"type": "React", "data": {"message_id": chat_id, "emoji": "👍"}})).await;
+        assert_eq!(outsider.expect("Error").await["data"]["code"], "NOT_FOUND");
+        outsider.send(serde_json::json!({"type": "JoinSession", "data": {"session_id": f.session_id}})).await;
+        assert_eq!(outsider.expect("Error").await["data"]["code"], "ACCESS_DENIED");
+
+        // The DM's table saw none of it
+        dm.send(serde_json::json!({"type": "ChatMessage", "data": {"message": "Quiet night"}})).await;
+        let seen: Vec<_> = dm.until("ChatMessage").await.into_iter().filter(|m| m["type"] != "UserPresenceChanged").collect();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0]["data"]["player_id"], f.dm_id.to_string());
+    }
+
+    #[test]
+    fn test_client_envelope_targets_session() {
+        let session_id = Uuid::new_v4();
+        let json = format!(r#"{{"type":"ChatMessage","data":{{"message":"hi"}},"session_id":"{}"}}"#, session_id);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let envelope = ClientEnvelope::from_value(value.clone()).unwrap();
+        assert!(matches!(envelope.message, ClientMessage::ChatMessage { .. }));
+        assert_eq!(envelope.session_id, Some(session_id));
+
+        let envelope = ClientEnvelope::from_value(serde_json::json!({"type": "Heartbeat"})).unwrap();
+        assert!(matches!(envelope.message, ClientMessage::Heartbeat));
+        assert_eq!(envelope.session_id, None);
+
+        // MessagePack clients send the same map
+        let bytes = rmp_serde::to_vec_named(&serde_json::json!({"type": "LeaveSession", "data": {"session_id": session_id}})).unwrap();
+        let envelope = ClientEnvelope::from_value(rmp_serde::from_slice(&bytes).unwrap()).unwrap();
+        assert!(matches!(envelope.message, ClientMessage::LeaveSession { session_id: id } if id == session_id));
+        let bytes = rmp_serde::to_vec_named(&value).unwrap();
+        let envelope = ClientEnvelope::from_value(rmp_serde::from_slice(&bytes).unwrap()).unwrap();
+        assert_eq!(envelope.session_id, Some(session_id));
+
+        // Untagged messages are fine with one session joined, ambiguous with two
+        let mut joined = HashMap::from([(session_id, true)]);
+        assert_eq!(target_session(&joined, None).unwrap(), Some(session_id));
+        let other = Uuid::new_v4();
+        assert_eq!(target_session(&joined, Some(other)).unwrap_err().code, ErrorCode::NotInSession);
+        joined.insert(other, false);
+        assert_eq!(target_session(&joined, None).unwrap_err().code, ErrorCode::InvalidRequest);
+        assert_eq!(target_session(&joined, Some(other)).unwrap(), Some(other));
+
+        let text = encode_broadcast(&ServerMessage::PlayerLeft { player_id: other }, session_id, Some(3));
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value["session_id"], session_id.to_string());
+        assert_eq!(value["seq"], 3);
+    }
+
+    #[test]
+    fn test_attacks_double_damage_dice_on_a_natural_20() {
+        assert_eq!(attack_damage("1d20+5", "1d8+3").unwrap(), ("1d8+3".to_string(), "2d8+3".to_string()));
+        assert_eq!(attack_damage("2d20kh1+5", "2d6").unwrap().1, "4d6");
+        assert_eq!(attack_damage("2d6", "1d8").unwrap_err().code, ErrorCode::InvalidDice);
+        assert_eq!(attack_damage("1d20", "60d6").unwrap_err().code, ErrorCode::InvalidDice);
+
+        let roll = |seed| roll_from("1d20", RollSeed { seed, counter: None, proof: None }).unwrap();
+        let crit = (0..).map(roll).find(|roll| roll.result == 20).unwrap();
+        assert!(natural_20(&crit));
+        let miss = (0..).map(roll).find(|roll| roll.result != 20).unwrap();
+        assert!(!natural_20(&miss));
+
+        // A 20 on the d20 thrown away by disadvantage is no crit
+        let disadvantage = (0..)
+            .map(|seed| roll_from("2d20kl1", RollSeed { seed, counter: None, proof: None }).unwrap())
+            .find(|roll| roll.rolls.contains(&20) && roll.result != 20)
+            .unwrap();
+        assert!(!natural_20(&disadvantage));
+    }
+
+    #[test]
+    fn test_kick_player_message() {
+        let user_id = Uuid::new_v4();
+        let json = format!(r#"{{"type":"KickPlayer","data":{{"user_id":"{}"}}}}"#, user_id);
+        match serde_json::from_str::<ClientMessage>(&json).unwrap() {
+            ClientMessage::KickPlayer { user_id: id, ban } => {
+                assert_eq!(id, user_id);
+                assert!(!ban);
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        let text = encode_message(&ServerMessage::Kicked { session_id: Uuid::new_v4(), banned: true }, None);
+        let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(value["type"], "Kicked");
+        assert_eq!(value["data"]["banned"], true);
+    }
+
+    #[tokio::test]
+    async fn test_mute_expires() {
+        let state = SessionState::new(None);
+        let (session_id, user_id) = (Uuid::new_v4(), Uuid::new_v4());
+        state.sessions.write().await.insert(session_id, SessionInfo::new(session_id, Uuid::new_v4()));
+        assert_eq!(muted_until(&state, session_id, user_id).await, None);
+
+        {
+            let sessions = state.sessions.read().await;
+            sessions[&session_id].muted.write().await.insert(user_id, None);
+        }
+        assert_eq!(muted_until(&state, session_id, user_id).await, Some(None));
+
+        let expired = Utc::now() - chrono::Duration::seconds(1);
+        {
+            let sessions = state.sessions.read().await;
+            sessions[&session_id].muted.write().await.insert(user_id, Some(expired));
+        }
+        assert_eq!(muted_until(&state, session_id, user_id).await, None);
+        assert!(state.sessions.read().await[&session_id].muted.read().await.is_empty());
+    }
+
+    #[test]
+    fn test_reactions_toggle_per_user() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut reactions = BTreeMap::new();
+        toggle_reaction(&mut reactions, alice, "👍").unwrap();
+        toggle_reaction(&mut reactions, bob, "👍").unwrap();
+        toggle_reaction(&mut reactions, bob, "🎲").unwrap();
+        assert_eq!(reactions["👍"], vec![alice, bob]);
+
+        // Reacting again takes it back, and emptied emoji disappear
+        toggle_reaction(&mut reactions, bob, "🎲").unwrap();
+        toggle_reaction(&mut reactions, alice, "👍").unwrap();
+        assert_eq!(reactions.len(), 1);
+        assert_eq!(reactions["👍"], vec![bob]);
+
+        assert!(validate_emoji("👍🏽").is_ok());
+        assert!(validate_emoji("lol").is_err());
+        assert!(validate_emoji("").is_err());
+    }
+
+    #[test]
+    fn test_inline_rolls_in_chat() {
+        let dice = inline_dice("I swing [[1d20+5]] and hit for [[ 2d6+3 ]]!").unwrap();
+        assert_eq!(dice, vec!["1d20+5", "2d6+3"]);
+        let rolls: Vec<_> = dice.iter().map(|dice| roll_from(dice, RollSeed { seed: fresh_seed(), counter: None, proof: None }).unwrap()).collect();
+        assert!((6..=25).contains(&rolls[0].result));
+        assert_eq!(rolls[1].faces.len(), 2);
+
+        assert!(inline_dice("no dice here, [[unclosed").unwrap().is_empty());
+        assert_eq!(inline_dice("[[banana]]").unwrap_err().code, ErrorCode::InvalidDice);
+        assert!(inline_dice(&"[[1d4]]".repeat(MAX_INLINE_ROLLS + 1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_outbound_queue_keeps_order_and_overflows() {
+        let (queue, mut frames) = mpsc::channel(2);
+        let sender = SocketSender::new(queue, FrameEncoding { format: WireFormat::Json, compress_min_bytes: None });
+        sender.send("first".to_string()).unwrap();
+        sender.send("second".to_string()).unwrap();
+
+        // A full queue fails immediately and flags the connection for disconnect
+        assert!(sender.send("third".to_string()).is_err());
+        tokio::time::timeout(Duration::from_secs(1), sender.overflow.notified()).await.unwrap();
+
+        assert!(matches!(frames.recv().await, Some(Message::Text(text)) if text == "first"));
+        assert!(matches!(frames.recv().await, Some(Message::Text(text)) if text == "second"));
+        drop(frames);
+        assert!(sender.send("fourth".to_string()).is_err());
+    }
+
+    // A scripted player: mints a ticket over HTTP like the web client, then speaks the
+    // JSON protocol over a real socket
+    struct TestClient {
+        stream: tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>,
+    }
+
+    impl TestClient {
+        async fn connect(app: &axum::Router, addr: std::net::SocketAddr, user_id: Uuid, session_id: Uuid) -> Self {
+            use tower::ServiceExt;
+            let exp = (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize;
+            let token = crate::middleware::encode_token(&crate::middleware::Claims::new(user_id, exp)).unwrap();
+            let request = axum::http::Request::post(format!("/sessions/{}/ws-ticket", session_id))
+                .header("authorization", format!("Bearer {}", token))
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let ticket: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+            let url = format!("ws://{}/ws?ticket={}", addr, ticket["ticket"].as_str().unwrap());
+            let (stream, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+            TestClient { stream }
+        }
+
+        async fn send(&mut self, message: serde_json::Value) {
+            self.stream.send(tokio_tungstenite::tungstenite::Message::Text(message.to_string())).await.unwrap();
+        }
+
+        // Everything received up to and including the next message of type `kind`
+        async fn until(&mut self, kind: &str) -> Vec<serde_json::Value> {
+            let wait = async {
+                let mut received = Vec::new();
+                loop {
+                    match self.stream.next().await {
+                        Some(Ok(tokio_tungstenite::tungstenite::Message::Text(text))) => {
+                            let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+                            let done = value["type"] == kind;
+                            received.push(value);
+                            if done {
+                                return received;
+                            }
+                        }
+                        Some(Ok(_)) => {}
+                        other => panic!("socket ended while waiting for {}: {:?}", kind, other),
+                    }
+                }
+            };
+            tokio::time::timeout(Duration::from_secs(5), wait)
+                .await
+                .unwrap_or_else(|_| panic!("timed out waiting for {}", kind))
+        }
+
+        // Next message of type `kind`, skipping unrelated traffic such as presence updates
+        async fn expect(&mut self, kind: &str) -> serde_json::Value {
+            self.until(kind).await.pop().unwrap()
+        }
+
+        async fn join(&mut self, session_id: Uuid) -> serde_json::Value {
+            self.send(serde_json::json!({"type": "JoinSession", "data": {"session_id": session_id}})).await;
+            self.expect("SessionJoined").await
+        }
+
+        // The close frame the server ends the socket with, skipping anything sent before it
+        async fn closed(&mut self) -> tokio_tungstenite::tungstenite::protocol::CloseFrame<'static> {
+            let wait = async {
+                loop {
+                    match self.stream.next().await {
+                        Some(Ok(tokio_tungstenite::tungstenite::Message::Close(frame))) => return frame.unwrap(),
+                        Some(Ok(_)) => {}
+                        other => panic!("socket ended without a close frame: {:?}", other),
+                    }
+                }
+            };
+            tokio::time::timeout(Duration::from_secs(5), wait).await.expect("timed out waiting for the socket to close")
+        }
+    }
+
+    // The full app on a local port, for clients that need a real socket
+    async fn serve_test_app(pool: PgPool) -> (axum::Router, std::net::SocketAddr) {
+        let app = crate::routes::router(pool, SessionState::new(None), Arc::new(crate::storage::MemoryStorage::default()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = app.clone();
+        tokio::spawn(async move { axum::serve(listener, server.into_make_service()).await });
+        (app, addr)
+    }
+
+    #[tokio::test]
+    async fn test_logout_everywhere_closes_open_sockets() {
+        use tower::ServiceExt;
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let (app, addr) = serve_test_app(pool).await;
+
+        let mut player = TestClient::connect(&app, addr, f.player_id, f.session_id).await;
+        player.join(f.session_id).await;
+
+        let exp = (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize;
+        let token = crate::middleware::encode_token(&crate::middleware::Claims::new(f.player_id, exp)).unwrap();
+        let post = |uri: &str| {
+            axum::http::Request::post(uri).header("authorization", format!("Bearer {}", token)).body(axum::body::Body::empty()).unwrap()
+        };
+        assert_eq!(app.clone().oneshot(post("/auth/logout-all")).await.unwrap().status(), StatusCode::OK);
+
+        let closed = player.closed().await;
+        assert_eq!(u16::from(closed.code), REVOKED_CLOSE_CODE);
+        assert_eq!(closed.reason, "SessionRevoked");
+
+        // The token that logged out is dead too
+        assert_eq!(app.clone().oneshot(post("/auth/logout-all")).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_password_change_revokes_old_tokens_and_sockets() {
+        use tower::ServiceExt;
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let old_password = "correct horse battery staple";
+        sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2")
+            .bind(crate::password::hash_password(old_password).unwrap())
+            .bind(f.player_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let (app, addr) = serve_test_app(pool).await;
+
+        let mut player = TestClient::connect(&app, addr, f.player_id, f.session_id).await;
+        player.join(f.session_id).await;
+
+        let exp = (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize;
+        let old_token = crate::middleware::encode_token(&crate::middleware::Claims::new(f.player_id, exp)).unwrap();
+        let change = |token: &str| {
+            let body = serde_json::json!({"current_password": old_password, "new_password": "purple monkey dishwasher lantern"});
+            axum::http::Request::post("/auth/change-password")
+                .header("authorization", format!("Bearer {}", token))
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap()
+        };
+        let response = app.clone().oneshot(change(&old_token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let tokens: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let closed = player.closed().await;
+        assert_eq!(u16::from(closed.code), REVOKED_CLOSE_CODE);
+        assert_eq!(closed.reason, "SessionRevoked");
+
+        // The old access token is refused; the pair handed back with the change works
+        let me = |token: &str| {
+            axum::http::Request::get("/users/me").header("authorization", format!("Bearer {}", token)).body(axum::body::Body::empty()).unwrap()
+        };
+        assert_eq!(app.clone().oneshot(me(&old_token)).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(app.clone().oneshot(me(tokens["token"].as_str().unwrap())).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_scripted_clients_over_websocket() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let (app, addr) = serve_test_app(pool).await;
+
+        let mut dm = TestClient::connect(&app, addr, f.dm_id, f.session_id).await;
+        let mut player = TestClient::connect(&app, addr, f.player_id, f.session_id).await;
+        dm.join(f.session_id).await;
+        let joined = player.join(f.session_id).await;
+        let players = joined["data"]["players"].as_array().unwrap();
+        assert_eq!(players.len(), 2);
+        assert!(players.iter().any(|p| p["user_id"] == f.dm_id.to_string() && p["is_dm"] == true));
+
+        // Chat reaches everyone in the session, the sender included
+        player.send(serde_json::json!({"type": "ChatMessage", "data": {"message": "I check the door for traps"}})).await;
+        for client in [&mut dm, &mut player] {
+            let chat = client.expect("ChatMessage").await;
+            assert_eq!(chat["data"]["message"], "I check the door for traps");
+            assert_eq!(chat["data"]["player_id"], f.player_id.to_string());
+            assert_eq!(chat["session_id"], f.session_id.to_string());
+        }
+
+        // DM-only actions are refused to players
+        player.send(serde_json::json!({"type": "NextTurn", "data": {"session_id": f.session_id}})).await;
+        let error = player.expect("Error").await;
+        assert_eq!(error["data"]["code"], "ACCESS_DENIED");
+        assert_eq!(error["data"]["message"], "Only the DM can advance turns");
+
+        // The DM sets up combat and advances the turn for the whole table
+        let (wren, goblin) = (Uuid::new_v4(), Uuid::new_v4());
+        let order = serde_json::json!([
+            {"id": wren, "name": "Wren", "initiative": 18, "is_player": true, "character_id": f.character_id, "user_id": f.player_id, "hp_current": 10, "hp_max": 10, "ac": 14},
+            {"id": goblin, "name": "Goblin", "initiative": 9, "is_player": false, "character_id": null, "user_id": null, "hp_current": 7, "hp_max": 7, "ac": 15},
+        ]);
+        dm.send(serde_json::json!({"type": "UpdateInitiative", "data": {"session_id": f.session_id, "initiative_order": order}})).await;
+        player.expect("InitiativeUpdated").await;
+        dm.send(serde_json::json!({"type": "NextTurn", "data": {"session_id": f.session_id}})).await;
+        let seen_by_dm = dm.expect("TurnChanged").await;
+        let seen_by_player = player.expect("TurnChanged").await;
+        assert_eq!(seen_by_dm["data"], seen_by_player["data"]);
+        assert_eq!(seen_by_player["data"]["current_turn"], wren.to_string());
+        assert!(seen_by_player["seq"].as_u64().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_secret_rolls_reach_only_the_dm() {
+        use tower::ServiceExt;
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let (app, addr) = serve_test_app(pool).await;
+
+        let mut dm = TestClient::connect(&app, addr, f.dm_id, f.session_id).await;
+        let mut player = TestClient::connect(&app, addr, f.player_id, f.session_id).await;
+        dm.join(f.session_id).await;
+        player.join(f.session_id).await;
+
+        player.send(serde_json::json!({"type": "DiceRoll", "data": {"dice": "1d20", "secret": true}})).await;
+        assert_eq!(player.expect("Error").await["data"]["code"], "ACCESS_DENIED");
+
+        dm.send(serde_json::json!({"type": "DiceRoll", "data": {"dice": "1d20", "reason": "Ambush perception", "secret": true, "announce": true}})).await;
+        let rolled = dm.expect("DiceRolled").await;
+        assert_eq!(rolled["data"]["secret"], true);
+        assert!(rolled.get("seq").is_none());
+        let roll_id = rolled["data"]["roll_id"].as_str().unwrap().to_string();
+
+        // Players hear that the DM rolled, and nothing more
+        let notice = player.expect("SecretRollMade").await;
+        assert_eq!(notice["data"], serde_json::json!({"player_id": f.dm_id, "roll_id": roll_id}));
+        player.send(serde_json::json!({"type": "ChatMessage", "data": {"message": "What was that?"}})).await;
+        let seen = player.until("ChatMessage").await;
+        assert!(seen.iter().all(|m| m["type"] != "DiceRolled"));
+        assert!(!seen.iter().any(|m| m.to_string().contains("Ambush perception")));
+
+        // The roll is in the log, flagged, and only the DM can read it there
+        let exp = (Utc::now() + chrono::Duration::hours(1)).timestamp() as usize;
+        let log = |user_id: Uuid, uri: String| {
+            let token = crate::middleware::encode_token(&crate::middleware::Claims::new(user_id, exp)).unwrap();
+            let request = axum::http::Request::get(uri)
+                .header("authorization", format!("Bearer {}", token))
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<serde_json::Value>(&body).unwrap_or_default())
+            }
+        };
+        let (status, events) = log(f.dm_id, format!("/sessions/{}/event-logs", f.session_id)).await;
+        assert_eq!(status, StatusCode::OK);
+        let roll = events.as_array().unwrap().iter().find(|e| e["id"] == roll_id.as_str()).unwrap();
+        assert_eq!(roll["event_type"], "dice_roll");
+        assert_eq!(roll["event_data"]["secret"], true);
+        assert_eq!(roll["event_data"]["reason"], "Ambush perception");
+
+        let (_, events) = log(f.player_id, format!("/sessions/{}/event-logs", f.session_id)).await;
+        assert!(events.as_array().unwrap().iter().all(|e| e["id"] != roll_id.as_str()));
+        let (status, _) = log(f.player_id, format!("/event-logs/{}", roll_id)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = log(f.dm_id, format!("/event-logs/{}", roll_id)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_checks_use_the_characters_modifiers() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        sqlx::query("UPDATE characters SET stats = $1 WHERE id = $2")
+            .bind(serde_json::json!({"wisdom": 14, "proficiencies": ["perception"]}))
+            .bind(f.character_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let (app, addr) = serve_test_app(pool).await;
+
+        let mut dm = TestClient::connect(&app, addr, f.dm_id, f.session_id).await;
+        let mut player = TestClient::connect(&app, addr, f.player_id, f.session_id).await;
+        dm.join(f.session_id).await;
+        player.join(f.session_id).await;
+
+        player.send(serde_json::json!({"type": "RollCheck", "data": {"character_id": f.character_id, "check": "Perception", "advantage": "advantage"}})).await;
+        let rolled = dm.expect("DiceRolled").await;
+        let result = &rolled["data"]["result"];
+        assert_eq!(result["dice"], "2d20kh1+4");
+        assert_eq!(result["reason"], "Perception check");
+        assert_eq!(result["check"]["character_name"], "Wren");
+        assert_eq!(result["check"]["ability"], "wisdom");
+        assert_eq!(result["check"]["proficient"], true);
+        assert_eq!(result["advantage"], "advantage");
+        let kept: Vec<_> = result["faces"].as_array().unwrap().iter().filter(|face| face.get("dropped").is_none()).collect();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(result["result"], kept[0]["value"].as_i64().unwrap() + 4);
+
+        player.send(serde_json::json!({"type": "RollCheck", "data": {"character_id": f.character_id, "check": "basket weaving"}})).await;
+        assert_eq!(player.expect("Error").await["data"]["code"], "INVALID_REQUEST");
+    }
+
+    #[tokio::test]
+    async fn test_group_rolls_arrive_as_one_message() {
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let goblins: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let initiative_order: Vec<_> = goblins
+            .iter()
+            .enumerate()
+            .map(|(i, id)| serde_json::json!({"id": id, "name": format!("Goblin {}", i + 1), "initiative": 12, "is_player": false, "character_id": null, "user_id": null, "hp_current": 7, "hp_max": 7, "ac": 15}))
+            .collect();
+        sqlx::query("INSERT INTO combats (session_id, state) VALUES ($1, $2)")
+            .bind(f.session_id)
+            .bind(serde_json::json!({"initiative_order": initiative_order, "current_turn": null, "round": 1, "combat_active": true, "conditions": []}))
+            .execute(&pool)
+            .await
+            .unwrap();
+        let (app, addr) = serve_test_app(pool.clone()).await;
+
+        let mut dm = TestClient::connect(&app, addr, f.dm_id, f.session_id).await;
+        let mut player = TestClient::connect(&app, addr, f.player_id, f.session_id).await;
+        dm.join(f.session_id).await;
+        player.join(f.session_id).await;
+
+        player.send(serde_json::json!({"type": "GroupRoll", "data": {"session_id": f.session_id, "entry_ids": goblins, "dice": "1d20+2"}})).await;
+        assert_eq!(player.expect("Error").await["data"]["code"], "ACCESS_DENIED");
+
+        dm.send(serde_json::json!({"type": "GroupRoll", "data": {"session_id": f.session_id, "entry_ids": [goblins[0], Uuid::new_v4()], "dice": "1d20+2"}})).await;
+        assert_eq!(dm.expect("Error").await["data"]["code"], "INVALID_REQUEST");
+
+        dm.send(serde_json::json!({"type": "GroupRoll", "data": {"session_id": f.session_id, "entry_ids": [goblins[2], goblins[0], goblins[1]], "dice": "1d20+2", "reason": "DEX save"}})).await;
+        let grouped = player.expect("GroupRolled").await;
+        let rolls = grouped["data"]["rolls"].as_array().unwrap();
+        let names: Vec<_> = rolls.iter().map(|roll| roll["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["Goblin 3", "Goblin 1", "Goblin 2"]);
+        for roll in rolls {
+            assert_eq!(roll["result"]["reason"], "DEX save");
+            assert_eq!(roll["result"]["result"], roll["result"]["rolls"][0].as_i64().unwrap() + 2);
+            assert!(roll["roll_id"].as_str().is_some());
+        }
+
+        // One saved roll per goblin, and nothing from the rejected attempt
+        let saved = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM event_logs WHERE session_id = $1 AND event_type = 'dice_roll'")
+            .bind(f.session_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(saved, 3);
+    }
+}
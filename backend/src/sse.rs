@@ -0,0 +1,96 @@
+use axum::extract::Path;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::IntoResponse;
+use axum::Extension;
+use futures::stream::{self, StreamExt};
+use sqlx::PgPool;
+use std::convert::Infallible;
+use tokio::sync::{broadcast, watch};
+use uuid::Uuid;
+use crate::middleware::AuthUser;
+use crate::errors::db_error;
+use crate::socket::{is_banned, release_events, shutdown_requested, subscribe_events, ServerMessage, SessionState, SHUTDOWN_RECONNECT_AFTER_MS};
+use crate::scope::{authorize, Access, Resource};
+
+// Read-only Server-Sent Events mirror of a session's WebSocket broadcasts, for networks
+// that block WebSockets. Event ids are the broadcast sequence numbers, so a reconnecting
+// EventSource resumes from its Last-Event-ID using the same replay history.
+
+// Keeps the session entry alive while subscribed and releases it when the stream is dropped
+struct EventSubscription {
+    session_state: SessionState,
+    session_id: Uuid,
+    receiver: Option<broadcast::Receiver<(Option<u64>, String)>>,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl Drop for EventSubscription {
+    fn drop(&mut self) {
+        // Unsubscribe before checking whether the session is idle
+        self.receiver.take();
+        let session_state = self.session_state.clone();
+        let session_id = self.session_id;
+        tokio::spawn(async move {
+            release_events(&session_state, session_id).await;
+        });
+    }
+}
+
+fn to_event(seq: Option<u64>, text: String) -> Event {
+    let event = Event::default().data(text);
+    match seq {
+        Some(seq) => event.id(seq.to_string()),
+        None => event,
+    }
+}
+
+pub async fn session_events(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Extension(session_state): Extension<SessionState>,
+    Path(session_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    // Check access and find the session's campaign
+    let campaign_id = match authorize(&pool, user.0, Resource::Session(session_id), Access::Member, "Access denied to this session").await {
+        Ok(scope) => scope.campaign_id,
+        Err(response) => return response,
+    };
+    match is_banned(&pool, session_id, user.0).await {
+        Ok(false) => {}
+        Ok(true) => return (StatusCode::FORBIDDEN, "You have been banned from this session").into_response(),
+        Err(e) => return db_error(e, "Failed to verify session access"),
+    }
+
+    let since_seq = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let (missed, receiver) = subscribe_events(&session_state, session_id, campaign_id, since_seq).await;
+
+    let replay = stream::iter(missed.into_iter().map(|(seq, text)| Ok::<_, Infallible>(to_event(Some(seq), text))));
+    let shutdown = session_state.shutdown.subscribe();
+    let subscription = EventSubscription { session_state, session_id, receiver: Some(receiver), shutdown };
+    let live = stream::unfold(subscription, |mut subscription| async move {
+        let receiver = subscription.receiver.as_mut()?;
+        let (event, closing) = tokio::select! {
+            received = receiver.recv() => match received {
+                Ok((seq, text)) => (to_event(seq, text), false),
+                // Lagged subscribers end the stream; the client reconnects and replays from Last-Event-ID
+                Err(_) => return None,
+            },
+            // Say goodbye, then end the stream so graceful shutdown isn't held open
+            _ = shutdown_requested(&mut subscription.shutdown) => {
+                let notice = ServerMessage::ServerShuttingDown { reconnect_after_ms: SHUTDOWN_RECONNECT_AFTER_MS };
+                (to_event(None, serde_json::to_string(&notice).unwrap()), true)
+            }
+        };
+        if closing {
+            subscription.receiver = None;
+        }
+        Some((Ok(event), subscription))
+    });
+
+    Sse::new(replay.chain(live)).keep_alive(KeepAlive::default()).into_response()
+}
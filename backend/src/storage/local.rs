@@ -0,0 +1,64 @@
+use async_trait::async_trait;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+use super::{sign_file_url, validate_key, Storage, StoredFile};
+
+// Files under STORAGE_PATH: the bytes in objects/<key>, the content type in types/<key>
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalStorage { root: root.into() }
+    }
+
+    fn path(&self, tree: &str, key: &str) -> Result<PathBuf, String> {
+        validate_key(key)?;
+        Ok(self.root.join(tree).join(key))
+    }
+}
+
+// Written to a temporary file first so readers never see a partial file
+async fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await.map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let tmp = path.with_extension(format!("tmp-{}", Uuid::new_v4()));
+    tokio::fs::write(&tmp, contents).await.map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+    tokio::fs::rename(&tmp, path).await.map_err(|e| format!("Failed to move {} into place: {}", path.display(), e))
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<(), String> {
+        // The type goes first: a file without one is never visible
+        write_atomically(&self.path("types", key)?, content_type.as_bytes()).await?;
+        write_atomically(&self.path("objects", key)?, &bytes).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<StoredFile>, String> {
+        let bytes = match tokio::fs::read(self.path("objects", key)?).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to read {}: {}", key, e)),
+        };
+        let content_type = tokio::fs::read_to_string(self.path("types", key)?)
+            .await
+            .unwrap_or_else(|_| "application/octet-stream".to_string());
+        Ok(Some(StoredFile { bytes, content_type }))
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, String> {
+        tokio::fs::try_exists(self.path("objects", key)?)
+            .await
+            .map_err(|e| format!("Failed to check {}: {}", key, e))
+    }
+
+    fn signed_url(&self, key: &str, expires_in: Duration) -> Result<String, String> {
+        validate_key(key)?;
+        Ok(sign_file_url(key, expires_in))
+    }
+}
@@ -0,0 +1,34 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::Duration;
+use super::{sign_file_url, validate_key, Storage, StoredFile};
+
+// Keeps files in process memory; for tests and throwaway deployments
+#[derive(Default)]
+pub struct MemoryStorage {
+    files: RwLock<HashMap<String, StoredFile>>,
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<(), String> {
+        validate_key(key)?;
+        let file = StoredFile { bytes, content_type: content_type.to_string() };
+        self.files.write().unwrap().insert(key.to_string(), file);
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<StoredFile>, String> {
+        Ok(self.files.read().unwrap().get(key).cloned())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, String> {
+        Ok(self.files.read().unwrap().contains_key(key))
+    }
+
+    fn signed_url(&self, key: &str, expires_in: Duration) -> Result<String, String> {
+        validate_key(key)?;
+        Ok(sign_file_url(key, expires_in))
+    }
+}
@@ -0,0 +1,260 @@
+use async_trait::async_trait;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::middleware::{jwt_secret, AuthUser};
+
+mod local;
+mod memory;
+mod s3;
+
+pub use local::LocalStorage;
+pub use memory::MemoryStorage;
+pub use s3::S3Storage;
+
+// File storage for maps, avatars, handouts, exports and backups. The backend is picked with
+// STORAGE_BACKEND (local, s3 or memory). Files are stored under the SHA-256 of their
+// content, so uploading the same bytes twice stores them once.
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<(), String>;
+    // Ok(None) when nothing is stored under the key
+    async fn get(&self, key: &str) -> Result<Option<StoredFile>, String>;
+    async fn exists(&self, key: &str) -> Result<bool, String>;
+    // A URL anyone can download the file from until it expires
+    fn signed_url(&self, key: &str, expires_in: Duration) -> Result<String, String>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredFile {
+    pub bytes: Vec<u8>,
+    pub content_type: String,
+}
+
+pub type SharedStorage = Arc<dyn Storage>;
+
+pub fn from_env() -> Result<SharedStorage, String> {
+    let backend = env::var("STORAGE_BACKEND").unwrap_or_else(|_| "local".to_string());
+    match backend.as_str() {
+        "local" => {
+            let root = env::var("STORAGE_PATH").unwrap_or_else(|_| "./data/storage".to_string());
+            Ok(Arc::new(LocalStorage::new(root)))
+        }
+        "s3" => Ok(Arc::new(S3Storage::from_env()?)),
+        "memory" => Ok(Arc::new(MemoryStorage::default())),
+        other => Err(format!("Unknown STORAGE_BACKEND '{}'. Supported: local, s3, memory", other)),
+    }
+}
+
+// Keys are relative paths of plain segments, safe to use as file paths and URL paths
+pub fn validate_key(key: &str) -> Result<(), String> {
+    let valid = !key.is_empty()
+        && key.len() <= 512
+        && key.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment != "."
+                && segment != ".."
+                && segment.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid storage key '{}'", key))
+    }
+}
+
+pub fn content_hash(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+#[derive(Debug, Serialize)]
+pub struct StoredObject {
+    pub key: String,
+    pub sha256: String,
+    pub size: usize,
+    // The same content was already stored, so nothing was written
+    pub deduplicated: bool,
+}
+
+pub async fn store(storage: &dyn Storage, bytes: Vec<u8>, content_type: &str) -> Result<StoredObject, String> {
+    let sha256 = content_hash(&bytes);
+    let key = format!("sha256/{}", sha256);
+    let size = bytes.len();
+    let deduplicated = storage.exists(&key).await?;
+    if !deduplicated {
+        storage.put(&key, bytes, content_type).await?;
+    }
+    Ok(StoredObject { key, sha256, size, deduplicated })
+}
+
+// Local and in-memory files are downloaded through GET /files/*key, which checks an HMAC
+// of the key and expiry. Signed with STORAGE_SIGNING_KEY, or the JWT secret if unset.
+
+fn signing_key() -> String {
+    env::var("STORAGE_SIGNING_KEY").unwrap_or_else(|_| jwt_secret())
+}
+
+fn url_mac(key: &str, expires: i64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(signing_key().as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}\n{}", key, expires).as_bytes());
+    mac
+}
+
+pub fn sign_file_url(key: &str, expires_in: Duration) -> String {
+    let expires = Utc::now().timestamp() + expires_in.as_secs() as i64;
+    let signature = hex::encode(url_mac(key, expires).finalize().into_bytes());
+    // STORAGE_PUBLIC_URL makes the URL absolute, e.g. https://yoda.example.com
+    let base = env::var("STORAGE_PUBLIC_URL").unwrap_or_default();
+    format!("{}/files/{}?expires={}&signature={}", base.trim_end_matches('/'), key, expires, signature)
+}
+
+fn verify_file_url(key: &str, expires: i64, signature: &str) -> bool {
+    if expires < Utc::now().timestamp() {
+        return false;
+    }
+    match hex::decode(signature) {
+        Ok(signature) => url_mac(key, expires).verify_slice(&signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SignedFileQuery {
+    pub expires: i64,
+    pub signature: String,
+}
+
+pub async fn serve_file(
+    Extension(storage): Extension<SharedStorage>,
+    Path(key): Path<String>,
+    Query(params): Query<SignedFileQuery>,
+) -> impl IntoResponse {
+    if validate_key(&key).is_err() || !verify_file_url(&key, params.expires, &params.signature) {
+        return (StatusCode::FORBIDDEN, "Invalid or expired link").into_response();
+    }
+    match storage.get(&key).await {
+        Ok(Some(file)) => (
+            [
+                (header::CONTENT_TYPE, file.content_type),
+                (header::CACHE_CONTROL, "private, max-age=3600".to_string()),
+            ],
+            file.bytes,
+        )
+            .into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "File not found").into_response(),
+        Err(e) => {
+            eprintln!("Failed to read {} from storage: {}", key, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read file").into_response()
+        }
+    }
+}
+
+// Largest accepted upload
+pub const MAX_UPLOAD_BYTES: usize = 10 * 1024 * 1024;
+
+// How long the URL returned with an upload stays valid
+const UPLOAD_URL_TTL: Duration = Duration::from_secs(3600);
+
+#[derive(Serialize)]
+pub struct UploadResponse {
+    #[serde(flatten)]
+    pub object: StoredObject,
+    pub content_type: String,
+    pub url: String,
+    pub url_expires_at: DateTime<Utc>,
+}
+
+pub async fn upload_file(
+    Extension(storage): Extension<SharedStorage>,
+    Extension(_user): Extension<AuthUser>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    if body.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Upload is empty").into_response();
+    }
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+
+    let object = match store(storage.as_ref(), body.to_vec(), &content_type).await {
+        Ok(object) => object,
+        Err(e) => {
+            eprintln!("Failed to store upload: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to store file").into_response();
+        }
+    };
+    let url = match storage.signed_url(&object.key, UPLOAD_URL_TTL) {
+        Ok(url) => url,
+        Err(e) => {
+            eprintln!("Failed to sign URL for {}: {}", object.key, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to sign file URL").into_response();
+        }
+    };
+    let url_expires_at = Utc::now() + chrono::Duration::seconds(UPLOAD_URL_TTL.as_secs() as i64);
+
+    (StatusCode::CREATED, Json(UploadResponse { object, content_type, url, url_expires_at })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_identical_uploads_are_stored_once() {
+        let storage = MemoryStorage::default();
+        let first = store(&storage, b"map of the sunken keep".to_vec(), "image/png").await.unwrap();
+        let second = store(&storage, b"map of the sunken keep".to_vec(), "image/png").await.unwrap();
+        assert_eq!(first.key, second.key);
+        assert!(!first.deduplicated);
+        assert!(second.deduplicated);
+        assert_eq!(first.sha256, content_hash(b"map of the sunken keep"));
+
+        let file = storage.get(&first.key).await.unwrap().unwrap();
+        assert_eq!(file.content_type, "image/png");
+        assert!(storage.get("sha256/missing").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_signed_file_urls() {
+        let storage: SharedStorage = Arc::new(MemoryStorage::default());
+        let object = store(storage.as_ref(), b"handout".to_vec(), "text/plain").await.unwrap();
+        let url = storage.signed_url(&object.key, Duration::from_secs(60)).unwrap();
+        let params: HashMap<&str, &str> = url.split_once('?').unwrap().1.split('&').filter_map(|pair| pair.split_once('=')).collect();
+        let params = SignedFileQuery { expires: params["expires"].parse().unwrap(), signature: params["signature"].to_string() };
+
+        let serve = |key: &str, expires: i64, signature: &str| {
+            let query = Query(SignedFileQuery { expires, signature: signature.to_string() });
+            serve_file(Extension(storage.clone()), Path(key.to_string()), query)
+        };
+        let response = serve(&object.key, params.expires, &params.signature).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"handout");
+
+        // Tampering with the expiry or the key breaks the signature
+        let response = serve(&object.key, params.expires + 3600, &params.signature).await.into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = serve("sha256/other", params.expires, &params.signature).await.into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        assert!(validate_key("../etc/passwd").is_err());
+        assert!(validate_key("avatars//a.png").is_err());
+        assert!(validate_key("avatars/a.png").is_ok());
+    }
+}
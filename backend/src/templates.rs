@@ -0,0 +1,296 @@
+use axum::{extract::Path, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+use crate::campaign_settings::CampaignSettings;
+use crate::cloning::{clone_into, ClonedCampaign};
+use crate::errors::db_error;
+use crate::handlers::CampaignResponse;
+use crate::middleware::{bump_token_versions, AuthUser};
+use crate::models::Campaign;
+
+// Campaign templates: starting points for a new campaign, made in one call. Built-in
+// templates ship below; DMs can also offer their own campaigns as templates, which copies
+// their prep the way cloning does. A template brings its house rules (in settings, under
+// "houserules"), starter NPCs (compendium entries of kind "npc") and a session zero
+// checklist (quests with source "session_zero").
+
+struct BuiltinTemplate {
+    key: &'static str,
+    name: &'static str,
+    description: &'static str,
+    // JSON object
+    settings: &'static str,
+    // slug, name, description
+    npcs: &'static [(&'static str, &'static str, &'static str)],
+    checklist: &'static [&'static str],
+}
+
+const SESSION_ZERO: [&str; 4] = [
+    "Agree on lines and veils",
+    "Talk through the house rules",
+    "Tie the characters together",
+    "Settle the schedule and what happens when someone can't make it",
+];
+
+const BUILTIN_TEMPLATES: &[BuiltinTemplate] = &[
+    BuiltinTemplate {
+        key: "classic-fantasy",
+        name: "Classic Fantasy",
+        description: "Taverns, dungeons and a villain with a plan. A gentle start for new groups.",
+        settings: r#"{"max_players": 5, "houserules": {"critical_hits": "max_plus_roll", "potions_bonus_action": true, "flanking": false}}"#,
+        npcs: &[
+            ("innkeeper", "Marta Hollowell", "Runs the Gilded Griffin and hears every rumor in town."),
+            ("patron", "Lord Aldric Vane", "Pays well for discretion, and better for results."),
+            ("villain", "The Ashen Prophet", "Gathers followers in the old ruins, promising the end of the world."),
+        ],
+        checklist: &SESSION_ZERO,
+    },
+    BuiltinTemplate {
+        key: "gothic-horror",
+        name: "Gothic Horror",
+        description: "Dread, scarce light and things that shouldn't be. Lethal; safety tools matter here.",
+        settings: r#"{"max_players": 4, "houserules": {"lingering_injuries": true, "long_rest_requires_safe_haven": true, "inspiration_for_fear": true}}"#,
+        npcs: &[
+            ("village-priest", "Father Anselm", "Keeps the chapel lamps burning all night, and won't say why."),
+            ("stranger", "Madame Ilse", "A fortune teller whose cards are never wrong."),
+            ("lord", "Count Vasile Morrow", "The lord of the manor on the hill, rarely seen by day."),
+        ],
+        checklist: &[
+            "Agree on lines and veils, and how to pause a scene",
+            "Talk through the house rules",
+            "Decide how character death is handled",
+            "Settle the schedule and what happens when someone can't make it",
+        ],
+    },
+    BuiltinTemplate {
+        key: "west-marches",
+        name: "West Marches",
+        description: "A large, open table. Players schedule their own expeditions into the wilds from a safe town.",
+        settings: r#"{"max_players": 12, "discoverable": true, "houserules": {"milestone_xp": false, "expeditions_end_in_town": true, "shared_map": true}}"#,
+        npcs: &[
+            ("quartermaster", "Bram Tollen", "Sells rope, rations and rumors at the edge of the map."),
+            ("cartographer", "Ysolde Fenn", "Buys maps of unexplored hexes, and pays in gold."),
+        ],
+        checklist: &[
+            "Explain how expeditions are scheduled",
+            "Talk through the house rules",
+            "Agree on lines and veils",
+            "Set up the shared map",
+        ],
+    },
+];
+
+fn builtin(key: &str) -> Option<&'static BuiltinTemplate> {
+    BUILTIN_TEMPLATES.iter().find(|template| template.key == key)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TemplateSummary {
+    // The built-in template's key, or the template campaign's id
+    pub template: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub builtin: bool,
+    // Who offers it, for campaign templates
+    pub dm_id: Option<Uuid>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+// Built-in templates first, then campaigns offered as templates, newest first
+pub async fn list_templates(Extension(pool): Extension<PgPool>) -> impl IntoResponse {
+    let campaigns = sqlx::query_as::<_, Campaign>("SELECT * FROM campaigns WHERE is_template AND deleted_at IS NULL ORDER BY created_at DESC")
+        .fetch_all(&pool)
+        .await;
+    let campaigns = match campaigns {
+        Ok(campaigns) => campaigns,
+        Err(e) => return db_error(e, "Failed to fetch templates"),
+    };
+    let builtins = BUILTIN_TEMPLATES.iter().map(|template| TemplateSummary {
+        template: template.key.to_string(),
+        name: template.name.to_string(),
+        description: Some(template.description.to_string()),
+        builtin: true,
+        dm_id: None,
+        created_at: None,
+    });
+    let offered = campaigns.into_iter().map(|campaign| TemplateSummary {
+        template: campaign.id.to_string(),
+        name: campaign.name,
+        description: campaign.description,
+        builtin: false,
+        dm_id: Some(campaign.dm_id),
+        created_at: Some(campaign.created_at),
+    });
+    Json(builtins.chain(offered).collect::<Vec<_>>()).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct MarkTemplateRequest {
+    pub is_template: bool,
+}
+
+// The campaign's own DM offers it as a template, or stops offering it
+pub async fn mark_template(
+    Extension(pool): Extension<PgPool>,
+    Path(campaign_id): Path<Uuid>,
+    Json(payload): Json<MarkTemplateRequest>,
+) -> impl IntoResponse {
+    let res = sqlx::query("UPDATE campaigns SET is_template = $2, updated_at = NOW() WHERE id = $1")
+        .bind(campaign_id)
+        .bind(payload.is_template)
+        .execute(&pool)
+        .await;
+    match res {
+        Ok(_) => Json(serde_json::json!({ "campaign_id": campaign_id, "is_template": payload.is_template })).into_response(),
+        Err(e) => db_error(e, "Failed to update campaign"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FromTemplateRequest {
+    pub template: String,
+    // The template's name when left out
+    pub name: Option<String>,
+}
+
+async fn create_from_builtin(conn: &mut PgConnection, template: &BuiltinTemplate, dm_id: Uuid, name: String) -> Result<ClonedCampaign, sqlx::Error> {
+    let settings: CampaignSettings = serde_json::from_str(template.settings).expect("built-in template settings are valid");
+    let now = Utc::now();
+    let campaign = sqlx::query_as::<_, Campaign>(
+        "INSERT INTO campaigns (id, name, description, dm_id, settings, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6, $6) RETURNING *"
+    )
+    .bind(Uuid::new_v4())
+    .bind(name)
+    .bind(template.description)
+    .bind(dm_id)
+    .bind(sqlx::types::Json(&settings))
+    .bind(now)
+    .fetch_one(&mut *conn)
+    .await?;
+    for (slug, name, description) in template.npcs {
+        sqlx::query(
+            "INSERT INTO compendium_entries (campaign_id, source, slug, kind, name, data, created_at, updated_at) VALUES ($1, 'template', $2, 'npc', $3, $4, $5, $5)"
+        )
+        .bind(campaign.id)
+        .bind(slug)
+        .bind(name)
+        .bind(serde_json::json!({ "description": description }))
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+    }
+    for item in template.checklist {
+        sqlx::query(
+            "INSERT INTO quests (campaign_id, title, status, source, created_by, created_at, updated_at) VALUES ($1, $2, 'open', 'session_zero', $3, $4, $4)"
+        )
+        .bind(campaign.id)
+        .bind(item)
+        .bind(dm_id)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(ClonedCampaign {
+        campaign: CampaignResponse::from(campaign),
+        compendium_entries: template.npcs.len() as u64,
+        quests: template.checklist.len(),
+        characters: 0,
+    })
+}
+
+pub async fn create_from_template(
+    Extension(pool): Extension<PgPool>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<FromTemplateRequest>,
+) -> impl IntoResponse {
+    if payload.name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+        return (StatusCode::BAD_REQUEST, "Name can't be empty").into_response();
+    }
+    let name = payload.name.as_deref().map(|name| name.trim().to_string());
+    let created = async {
+        let mut tx = pool.begin().await?;
+        let created = if let Some(template) = builtin(&payload.template) {
+            let name = name.unwrap_or_else(|| template.name.to_string());
+            Some(create_from_builtin(&mut tx, template, user.0, name).await?)
+        } else if let Ok(template_id) = Uuid::parse_str(&payload.template) {
+            let source = sqlx::query_as::<_, Campaign>("SELECT * FROM campaigns WHERE id = $1 AND is_template AND deleted_at IS NULL")
+                .bind(template_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+            match source {
+                // Characters belong to the template's own table, so they stay behind
+                Some(source) => Some(clone_into(&mut tx, &source, user.0, name.unwrap_or_else(|| source.name.clone()), false).await?),
+                None => None,
+            }
+        } else {
+            None
+        };
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(created)
+    }
+    .await;
+
+    match created {
+        Ok(Some(created)) => {
+            // New DM membership; tokens with embedded roles need refreshing
+            let _ = bump_token_versions(&pool, &[user.0]).await;
+            (StatusCode::CREATED, Json(created)).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, "Template not found").into_response(),
+        Err(e) => db_error(e, "Failed to create campaign"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_campaigns_start_from_builtin_and_shared_templates() {
+        for template in BUILTIN_TEMPLATES {
+            let settings: CampaignSettings = serde_json::from_str(template.settings).unwrap();
+            assert_eq!(settings.validate(), Ok(()), "{} has invalid settings", template.key);
+            assert!(!settings.houserules.is_empty(), "{} has no house rules", template.key);
+        }
+
+        let pool = crate::test_support::test_pool().await;
+        let f = crate::test_support::campaign_fixture(&pool).await;
+        let create = |template: &str| {
+            let payload = Json(FromTemplateRequest { template: template.to_string(), name: None });
+            create_from_template(Extension(pool.clone()), Extension(AuthUser(f.player_id)), payload)
+        };
+        let read = |response: axum::response::Response| async {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        let response = create("gothic-horror").await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let created = read(response).await;
+        assert_eq!(created["campaign"]["name"], "Gothic Horror");
+        assert_eq!(created["campaign"]["dm_id"], f.player_id.to_string());
+        assert_eq!(created["campaign"]["settings"]["houserules"]["lingering_injuries"], true);
+        let checklist = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM quests WHERE campaign_id = $1 AND source = 'session_zero'")
+            .bind(created["campaign"]["id"].as_str().unwrap().parse::<Uuid>().unwrap())
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(checklist, 4);
+
+        // Other campaigns only once their DM shares them
+        let campaign = f.campaign_id.to_string();
+        assert_eq!(create(&campaign).await.into_response().status(), StatusCode::NOT_FOUND);
+        let uri = format!("/campaigns/{}/template", f.campaign_id);
+        let share = |user_id: Uuid| crate::test_support::call_as(&pool, user_id, axum::http::Method::PUT, &uri, Some(serde_json::json!({ "is_template": true })));
+        assert_eq!(share(f.player_id).await.status(), StatusCode::FORBIDDEN);
+        assert_eq!(share(f.dm_id).await.status(), StatusCode::OK);
+        let created = read(create(&campaign).await.into_response()).await;
+        assert_eq!((created["campaign"]["name"].as_str(), created["characters"].as_u64()), (Some("Isolated"), Some(0)));
+        assert_eq!(create("no-such-template").await.into_response().status(), StatusCode::NOT_FOUND);
+
+        let listed = read(list_templates(Extension(pool.clone())).await.into_response()).await;
+        assert!(listed.as_array().unwrap().iter().any(|template| template["template"] == campaign.as_str()));
+    }
+}
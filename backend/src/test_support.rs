@@ -0,0 +1,135 @@
+// Setup shared by the database-backed tests. They run against DATABASE_URL and give every
+// row fresh ids, so tests can share one database
+use axum::body::Body;
+use axum::http::{header, Method, Request};
+use axum::response::Response;
+use chrono::Utc;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tower::ServiceExt;
+use uuid::Uuid;
+use crate::middleware::{encode_token, Claims};
+use crate::socket::SessionState;
+use crate::storage::MemoryStorage;
+
+pub async fn test_pool() -> PgPool {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    sqlx::postgres::PgPoolOptions::new()
+        .max_connections(5)
+        .connect(&database_url)
+        .await
+        .expect("Failed to create test pool")
+}
+
+// Sends a request through the whole router as the given user, for handlers whose role check
+// is layered on their route (see require_role)
+pub async fn call_as(pool: &PgPool, user_id: Uuid, method: Method, uri: &str, body: Option<Value>) -> Response {
+    let app = crate::routes::router(pool.clone(), SessionState::new(None), Arc::new(MemoryStorage::default()));
+    let token = encode_token(&Claims::new(user_id, (Utc::now().timestamp() + 600) as usize)).unwrap();
+    let builder = Request::builder().method(method).uri(uri).header(header::AUTHORIZATION, format!("Bearer {}", token));
+    let request = match body {
+        Some(body) => builder.header(header::CONTENT_TYPE, "application/json").body(Body::from(body.to_string())).unwrap(),
+        None => builder.body(Body::empty()).unwrap(),
+    };
+    app.oneshot(request).await.unwrap()
+}
+
+// Runs outbox delivery until nothing is due. Tests deliver at the same time and a pass skips
+// the rows another one has locked, so a single pass can miss this test's messages
+pub async fn drain_outbox(pool: &PgPool) {
+    for _ in 0..50 {
+        crate::outbox::deliver_pending(pool).await.unwrap();
+        let due = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM outbox WHERE delivered_at IS NULL AND failed_at IS NULL AND next_attempt_at <= NOW()"
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        if due == 0 {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+pub struct Fixture {
+    pub dm_id: Uuid,
+    pub player_id: Uuid,
+    // DM of a second campaign, with no part in the first
+    pub outsider_id: Uuid,
+    pub campaign_id: Uuid,
+    pub session_id: Uuid,
+    pub character_id: Uuid,
+    pub event_id: Uuid,
+}
+
+// Two campaigns: the first has a DM, a player with a character, an active session and a
+// logged event; the second belongs to an outsider with no part in the first
+pub async fn campaign_fixture(pool: &PgPool) -> Fixture {
+    let timestamp = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+    let (dm_id, player_id, outsider_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    for (id, name) in [(dm_id, "iso_dm"), (player_id, "iso_player"), (outsider_id, "iso_outsider")] {
+        sqlx::query("INSERT INTO users (id, email, username, password_hash, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(id)
+            .bind(format!("{}{}@example.com", name, timestamp))
+            .bind(format!("{}{}", name, timestamp))
+            .bind("hashed_password")
+            .bind(Utc::now())
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    let (campaign_id, other_campaign_id) = (Uuid::new_v4(), Uuid::new_v4());
+    for (id, dm) in [(campaign_id, dm_id), (other_campaign_id, outsider_id)] {
+        sqlx::query("INSERT INTO campaigns (id, name, dm_id, settings, created_at, updated_at) VALUES ($1, 'Isolated', $2, '{}', $3, $3)")
+            .bind(id)
+            .bind(dm)
+            .bind(Utc::now())
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+    sqlx::query("INSERT INTO campaign_players (campaign_id, player_id, joined_at) VALUES ($1, $2, $3)")
+        .bind(campaign_id)
+        .bind(player_id)
+        .bind(Utc::now())
+        .execute(pool)
+        .await
+        .unwrap();
+
+    let session_id = Uuid::new_v4();
+    sqlx::query("INSERT INTO sessions (id, campaign_id, name, status, game_state, created_at, updated_at) VALUES ($1, $2, 'Session 1', 'active', $3, $4, $4)")
+        .bind(session_id)
+        .bind(campaign_id)
+        .bind(json!({"initiative_order": [], "current_turn": null, "round": 1, "combat_active": false, "conditions": []}))
+        .bind(Utc::now())
+        .execute(pool)
+        .await
+        .unwrap();
+
+    let character_id = Uuid::new_v4();
+    sqlx::query("INSERT INTO characters (id, campaign_id, player_id, name, level, hp_current, hp_max, stats, inventory, spells, features, created_at, updated_at) VALUES ($1, $2, $3, 'Wren', 1, 10, 10, '{}', '[]', '[]', '[]', $4, $4)")
+        .bind(character_id)
+        .bind(campaign_id)
+        .bind(player_id)
+        .bind(Utc::now())
+        .execute(pool)
+        .await
+        .unwrap();
+
+    let event_id = Uuid::new_v4();
+    sqlx::query("INSERT INTO event_logs (id, session_id, event_type, event_data, created_by, created_at) VALUES ($1, $2, 'note', '{}', $3, $4)")
+        .bind(event_id)
+        .bind(session_id)
+        .bind(dm_id)
+        .bind(Utc::now())
+        .execute(pool)
+        .await
+        .unwrap();
+
+    Fixture { dm_id, player_id, outsider_id, campaign_id, session_id, character_id, event_id }
+}
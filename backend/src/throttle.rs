@@ -0,0 +1,125 @@
+use axum::{http::{header, StatusCode}, response::{IntoResponse, Response}};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::net::IpAddr;
+
+// Login throttling. Failed logins are counted per client address and per email address in
+// Postgres, so every instance shares the counts. Past a few free attempts each further
+// failure locks that key out for twice as long as the last, up to MAX_LOCKOUT_SECS, and
+// login answers 429 with Retry-After until the lockout ends. A successful login clears the
+// account's count; failures older than FAILURE_WINDOW_SECS are forgotten.
+
+// Failures allowed before lockouts start
+const FREE_ACCOUNT_FAILURES: i32 = 5;
+// Higher, since many people can share an address
+const FREE_ADDRESS_FAILURES: i32 = 20;
+
+const BASE_LOCKOUT_SECS: i64 = 30;
+const MAX_LOCKOUT_SECS: i64 = 60 * 60;
+const FAILURE_WINDOW_SECS: i64 = 60 * 60;
+
+fn account_key(email: &str) -> String {
+    format!("account:{}", email.trim().to_lowercase())
+}
+
+fn address_key(address: IpAddr) -> String {
+    format!("address:{}", address)
+}
+
+// Without a peer address only the account is counted
+fn keys(email: &str, address: Option<IpAddr>) -> Vec<String> {
+    let mut keys = vec![account_key(email)];
+    keys.extend(address.map(address_key));
+    keys
+}
+
+// How long the failure that brought the count to `failures` locks the key out for
+fn lockout_secs(failures: i32, free: i32) -> Option<i64> {
+    let over = failures - free;
+    if over <= 0 {
+        return None;
+    }
+    // 2^11 * 30s is already past the cap
+    Some((BASE_LOCKOUT_SECS << (over - 1).min(11)).min(MAX_LOCKOUT_SECS))
+}
+
+// Seconds until every lockout on the email or address has ended, if any is in force
+pub async fn locked_for(pool: &PgPool, email: &str, address: Option<IpAddr>) -> Result<Option<i64>, sqlx::Error> {
+    let until = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+        "SELECT MAX(locked_until) FROM login_throttles WHERE key = ANY($1) AND locked_until > NOW()"
+    )
+    .bind(keys(email, address))
+    .fetch_one(pool)
+    .await?;
+    Ok(until.map(|until| (until - Utc::now()).num_seconds().max(1)))
+}
+
+pub async fn record_failure(pool: &PgPool, email: &str, address: Option<IpAddr>) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for key in keys(email, address) {
+        let free = if key.starts_with("address:") { FREE_ADDRESS_FAILURES } else { FREE_ACCOUNT_FAILURES };
+        let failures = sqlx::query_scalar::<_, i32>(
+            "INSERT INTO login_throttles (key, failures, last_failure_at) VALUES ($1, 1, NOW())
+             ON CONFLICT (key) DO UPDATE SET
+                 failures = CASE WHEN login_throttles.last_failure_at < NOW() - make_interval(secs => $2) THEN 1 ELSE login_throttles.failures + 1 END,
+                 last_failure_at = NOW()
+             RETURNING failures"
+        )
+        .bind(&key)
+        .bind(FAILURE_WINDOW_SECS as f64)
+        .fetch_one(&mut *tx)
+        .await?;
+        if let Some(secs) = lockout_secs(failures, free) {
+            sqlx::query("UPDATE login_throttles SET locked_until = NOW() + make_interval(secs => $2) WHERE key = $1")
+                .bind(&key)
+                .bind(secs as f64)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    tx.commit().await
+}
+
+pub async fn clear_account(pool: &PgPool, email: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM login_throttles WHERE key = $1")
+        .bind(account_key(email))
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// Counts that are past the window and no longer locked
+pub async fn forget_stale(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "DELETE FROM login_throttles
+         WHERE last_failure_at < NOW() - make_interval(secs => $1) AND (locked_until IS NULL OR locked_until < NOW())"
+    )
+    .bind(FAILURE_WINDOW_SECS as f64)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub fn locked_out(retry_after_secs: i64) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after_secs.to_string())],
+        "Too many failed login attempts; try again later",
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockouts_double_up_to_the_cap() {
+        assert_eq!(lockout_secs(FREE_ACCOUNT_FAILURES, FREE_ACCOUNT_FAILURES), None);
+        assert_eq!(lockout_secs(FREE_ACCOUNT_FAILURES + 1, FREE_ACCOUNT_FAILURES), Some(30));
+        assert_eq!(lockout_secs(FREE_ACCOUNT_FAILURES + 2, FREE_ACCOUNT_FAILURES), Some(60));
+        assert_eq!(lockout_secs(FREE_ACCOUNT_FAILURES + 4, FREE_ACCOUNT_FAILURES), Some(240));
+        assert_eq!(lockout_secs(1000, FREE_ACCOUNT_FAILURES), Some(MAX_LOCKOUT_SECS));
+        assert_eq!(account_key(" Wren@Example.com"), "account:wren@example.com");
+    }
+}
@@ -0,0 +1,279 @@
+use axum::{extract::Query, http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::{Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use uuid::Uuid;
+use crate::errors::db_error;
+
+// Per-campaign and per-user usage for self-hosters. Counts are kept in memory and flushed
+// to usage_counters once a minute, so counting never adds a query to a request.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Subject {
+    Campaign(Uuid),
+    User(Uuid),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Metric {
+    ApiCalls,
+    WsMessages,
+    AiTokens,
+}
+
+impl Metric {
+    fn as_str(self) -> &'static str {
+        match self {
+            Metric::ApiCalls => "api_calls",
+            Metric::WsMessages => "ws_messages",
+            Metric::AiTokens => "ai_tokens",
+        }
+    }
+}
+
+type Key = (NaiveDate, Subject, Metric);
+
+#[derive(Default)]
+pub struct UsageCounters {
+    pending: Mutex<HashMap<Key, i64>>,
+}
+
+pub fn usage() -> &'static UsageCounters {
+    static USAGE: OnceLock<UsageCounters> = OnceLock::new();
+    USAGE.get_or_init(UsageCounters::default)
+}
+
+impl UsageCounters {
+    pub fn record(&self, subject: Subject, metric: Metric, amount: i64) {
+        let key = (Utc::now().date_naive(), subject, metric);
+        *self.pending.lock().unwrap().entry(key).or_insert(0) += amount;
+    }
+
+    fn merge(&self, counts: HashMap<Key, i64>) {
+        let mut pending = self.pending.lock().unwrap();
+        for (key, count) in counts {
+            *pending.entry(key).or_insert(0) += count;
+        }
+    }
+
+    // On failure the counts are put back for the next flush
+    pub async fn flush(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let counts = std::mem::take(&mut *self.pending.lock().unwrap());
+        if counts.is_empty() {
+            return Ok(());
+        }
+
+        let mut days = Vec::with_capacity(counts.len());
+        let mut subject_types = Vec::with_capacity(counts.len());
+        let mut subject_ids = Vec::with_capacity(counts.len());
+        let mut metrics = Vec::with_capacity(counts.len());
+        let mut amounts = Vec::with_capacity(counts.len());
+        for ((day, subject, metric), count) in &counts {
+            let (subject_type, subject_id) = match subject {
+                Subject::Campaign(id) => ("campaign", *id),
+                Subject::User(id) => ("user", *id),
+            };
+            days.push(*day);
+            subject_types.push(subject_type);
+            subject_ids.push(subject_id);
+            metrics.push(metric.as_str());
+            amounts.push(*count);
+        }
+
+        let res = sqlx::query(
+            "INSERT INTO usage_counters (day, subject_type, subject_id, metric, count)
+             SELECT * FROM UNNEST($1::DATE[], $2::VARCHAR[], $3::UUID[], $4::VARCHAR[], $5::BIGINT[])
+             ON CONFLICT (day, subject_type, subject_id, metric) DO UPDATE
+             SET count = usage_counters.count + EXCLUDED.count"
+        )
+        .bind(&days)
+        .bind(&subject_types)
+        .bind(&subject_ids)
+        .bind(&metrics)
+        .bind(&amounts)
+        .execute(pool)
+        .await;
+
+        if let Err(e) = res {
+            self.merge(counts);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+pub fn spawn_usage_flusher(pool: PgPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = usage().flush(&pool).await {
+                tracing::error!(error = %e, "Failed to flush usage counters");
+            }
+        }
+    });
+}
+
+// Longest period one report covers
+const MAX_REPORT_DAYS: i64 = 366;
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    // Inclusive dates; the default is the last 30 days
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+#[derive(Serialize, Default)]
+pub struct CampaignUsage {
+    pub campaign_id: Uuid,
+    pub name: Option<String>,
+    pub api_calls: i64,
+    pub ws_messages: i64,
+    pub ai_tokens: i64,
+    // Current size of the campaign's rows, not limited to the period
+    pub storage_bytes: i64,
+}
+
+#[derive(Serialize, Default)]
+pub struct UserUsage {
+    pub user_id: Uuid,
+    pub username: Option<String>,
+    pub api_calls: i64,
+    pub ws_messages: i64,
+    pub ai_tokens: i64,
+}
+
+#[derive(Serialize)]
+pub struct UsageReport {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub campaigns: Vec<CampaignUsage>,
+    pub users: Vec<UserUsage>,
+}
+
+fn add(api_calls: &mut i64, ws_messages: &mut i64, ai_tokens: &mut i64, metric: &str, count: i64) {
+    match metric {
+        "api_calls" => *api_calls += count,
+        "ws_messages" => *ws_messages += count,
+        "ai_tokens" => *ai_tokens += count,
+        _ => {}
+    }
+}
+
+pub async fn usage_report(
+    Extension(pool): Extension<PgPool>,
+    Query(params): Query<UsageQuery>,
+) -> impl IntoResponse {
+    let to = params.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = params.from.unwrap_or(to - Duration::days(29));
+    if from > to {
+        return (StatusCode::BAD_REQUEST, "from must not be after to").into_response();
+    }
+    if (to - from).num_days() >= MAX_REPORT_DAYS {
+        return (StatusCode::BAD_REQUEST, format!("A report covers at most {} days", MAX_REPORT_DAYS)).into_response();
+    }
+
+    // Include what's still in memory
+    if let Err(e) = usage().flush(&pool).await {
+        return db_error(e, "Failed to flush usage counters");
+    }
+
+    let rows = sqlx::query_as::<_, (String, Uuid, String, i64)>(
+        "SELECT subject_type, subject_id, metric, SUM(count)::BIGINT FROM usage_counters
+         WHERE day BETWEEN $1 AND $2
+         GROUP BY subject_type, subject_id, metric"
+    )
+    .bind(from)
+    .bind(to)
+    .fetch_all(&pool)
+    .await;
+    let rows = match rows {
+        Ok(rows) => rows,
+        Err(e) => return db_error(e, "Failed to fetch usage"),
+    };
+
+    // Every campaign is listed, so ones that only take up space show too
+    let storage = sqlx::query_as::<_, (Uuid, String, i64)>(
+        "SELECT c.id, c.name, (
+             pg_column_size(c.*)
+             + COALESCE((SELECT SUM(pg_column_size(ch.*)) FROM characters ch WHERE ch.campaign_id = c.id), 0)
+             + COALESCE((SELECT SUM(pg_column_size(r.*)) FROM character_revisions r JOIN characters ch ON ch.id = r.character_id WHERE ch.campaign_id = c.id), 0)
+             + COALESCE((SELECT SUM(pg_column_size(s.*)) FROM sessions s WHERE s.campaign_id = c.id), 0)
+             + COALESCE((SELECT SUM(pg_column_size(e.*)) FROM event_logs e JOIN sessions s ON s.id = e.session_id WHERE s.campaign_id = c.id), 0)
+             + COALESCE((SELECT SUM(pg_column_size(q.*)) FROM quests q WHERE q.campaign_id = c.id), 0)
+             + COALESCE((SELECT SUM(pg_column_size(ce.*)) FROM compendium_entries ce WHERE ce.campaign_id = c.id), 0)
+         )::BIGINT FROM campaigns c"
+    )
+    .fetch_all(&pool)
+    .await;
+    let storage = match storage {
+        Ok(storage) => storage,
+        Err(e) => return db_error(e, "Failed to measure storage"),
+    };
+
+    let mut campaigns: HashMap<Uuid, CampaignUsage> = HashMap::new();
+    for (campaign_id, name, storage_bytes) in storage {
+        campaigns.insert(campaign_id, CampaignUsage { campaign_id, name: Some(name), storage_bytes, ..Default::default() });
+    }
+    let mut users: HashMap<Uuid, UserUsage> = HashMap::new();
+    for (subject_type, subject_id, metric, count) in rows {
+        if subject_type == "campaign" {
+            let entry = campaigns.entry(subject_id).or_insert_with(|| CampaignUsage { campaign_id: subject_id, ..Default::default() });
+            add(&mut entry.api_calls, &mut entry.ws_messages, &mut entry.ai_tokens, &metric, count);
+        } else {
+            let entry = users.entry(subject_id).or_insert_with(|| UserUsage { user_id: subject_id, ..Default::default() });
+            add(&mut entry.api_calls, &mut entry.ws_messages, &mut entry.ai_tokens, &metric, count);
+        }
+    }
+
+    let user_ids: Vec<Uuid> = users.keys().copied().collect();
+    let usernames = sqlx::query_as::<_, (Uuid, String)>("SELECT id, username FROM users WHERE id = ANY($1)")
+        .bind(&user_ids)
+        .fetch_all(&pool)
+        .await;
+    match usernames {
+        Ok(usernames) => {
+            for (user_id, username) in usernames {
+                if let Some(entry) = users.get_mut(&user_id) {
+                    entry.username = Some(username);
+                }
+            }
+        }
+        Err(e) => return db_error(e, "Failed to fetch users"),
+    }
+
+    // Heaviest first
+    let mut campaigns: Vec<CampaignUsage> = campaigns.into_values().collect();
+    campaigns.sort_by(|a, b| b.api_calls.cmp(&a.api_calls).then(b.storage_bytes.cmp(&a.storage_bytes)));
+    let mut users: Vec<UserUsage> = users.into_values().collect();
+    users.sort_by(|a, b| b.api_calls.cmp(&a.api_calls).then(b.ws_messages.cmp(&a.ws_messages)));
+
+    Json(UsageReport { from, to, campaigns, users }).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_accumulate_until_flushed() {
+        let counters = UsageCounters::default();
+        let user = Subject::User(Uuid::new_v4());
+        counters.record(user, Metric::ApiCalls, 1);
+        counters.record(user, Metric::ApiCalls, 1);
+        counters.record(user, Metric::AiTokens, 150);
+
+        let pending = std::mem::take(&mut *counters.pending.lock().unwrap());
+        let today = Utc::now().date_naive();
+        assert_eq!(pending[&(today, user, Metric::ApiCalls)], 2);
+        assert_eq!(pending[&(today, user, Metric::AiTokens)], 150);
+
+        // A failed flush puts them back
+        counters.merge(pending);
+        counters.record(user, Metric::ApiCalls, 1);
+        assert_eq!(counters.pending.lock().unwrap()[&(today, user, Metric::ApiCalls)], 3);
+    }
+}
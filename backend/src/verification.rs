@@ -0,0 +1,162 @@
+use axum::{http::StatusCode, response::IntoResponse, Extension, Json};
+use chrono::Utc;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool};
+use std::env;
+use uuid::Uuid;
+use crate::errors::db_error;
+use crate::outbox::{enqueue, OutboxMessage};
+
+// Email verification. Registering emails the address a single-use link, and the account
+// can't log in until the link's token is posted to /auth/verify. Set
+// REQUIRE_EMAIL_VERIFICATION=false to let unverified accounts log in anyway, for local
+// development and load tests. APP_URL is the frontend the link opens (default
+// http://localhost:5173).
+
+const VERIFICATION_TTL_HOURS: i64 = 24;
+
+// A new link can't be requested more often than this
+const RESEND_INTERVAL_SECS: i64 = 60;
+
+pub fn verification_required() -> bool {
+    env::var("REQUIRE_EMAIL_VERIFICATION").map(|value| value != "false").unwrap_or(true)
+}
+
+// The frontend, which links in emails and OAuth sign-ins lead back to
+pub fn app_url() -> String {
+    let url = env::var("APP_URL").unwrap_or_else(|_| "http://localhost:5173".to_string());
+    url.trim_end_matches('/').to_string()
+}
+
+pub(crate) fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Stores a token and queues the email, on the caller's transaction so the email only goes out
+// if the account (or resend request) commits
+pub async fn send_verification(conn: &mut PgConnection, user_id: Uuid, email: &str) -> Result<(), sqlx::Error> {
+    let mut token = [0u8; 32];
+    OsRng.fill_bytes(&mut token);
+    let token = hex::encode(token);
+    sqlx::query("INSERT INTO email_verifications (token_hash, user_id, expires_at) VALUES ($1, $2, $3)")
+        .bind(token_hash(&token))
+        .bind(user_id)
+        .bind(Utc::now() + chrono::Duration::hours(VERIFICATION_TTL_HOURS))
+        .execute(&mut *conn)
+        .await?;
+
+    let link = format!("{}/verify-email?token={}", app_url(), token);
+    let message = OutboxMessage::Email {
+        to: email.to_string(),
+        subject: "Confirm your YoDA email address".to_string(),
+        body: format!(
+            "Welcome to YoDA!\n\nConfirm your email address by opening this link within {} hours:\n\n{}\n\nIf you didn't create an account, you can ignore this email.\n",
+            VERIFICATION_TTL_HOURS, link
+        ),
+    };
+    enqueue(&mut *conn, &message).await
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+pub async fn verify_email(
+    Extension(pool): Extension<PgPool>,
+    Json(payload): Json<VerifyEmailRequest>,
+) -> impl IntoResponse {
+    let verified = async {
+        let mut tx = pool.begin().await?;
+        let user_id = sqlx::query_scalar::<_, Uuid>(
+            "UPDATE email_verifications SET used_at = NOW()
+             WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW() RETURNING user_id"
+        )
+        .bind(token_hash(&payload.token))
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(user_id) = user_id {
+            sqlx::query("UPDATE users SET email_verified = TRUE, updated_at = NOW() WHERE id = $1")
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(user_id.is_some())
+    }
+    .await;
+    match verified {
+        Ok(true) => (StatusCode::OK, "Email verified").into_response(),
+        Ok(false) => (StatusCode::BAD_REQUEST, "Invalid or expired verification token").into_response(),
+        Err(e) => db_error(e, "Failed to verify email"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+// Always 202, so the response doesn't tell whether an account exists or is verified
+pub async fn resend_verification(
+    Extension(pool): Extension<PgPool>,
+    Json(payload): Json<ResendVerificationRequest>,
+) -> impl IntoResponse {
+    let sent = async {
+        let mut tx = pool.begin().await?;
+        let user_id = sqlx::query_scalar::<_, Uuid>(
+            "SELECT id FROM users u WHERE email = $1 AND NOT email_verified
+             AND NOT EXISTS (SELECT 1 FROM email_verifications v WHERE v.user_id = u.id AND v.created_at > NOW() - make_interval(secs => $2))
+             FOR UPDATE"
+        )
+        .bind(&payload.email)
+        .bind(RESEND_INTERVAL_SECS as f64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(user_id) = user_id {
+            send_verification(&mut tx, user_id, &payload.email).await?;
+        }
+        tx.commit().await
+    }
+    .await;
+    match sent {
+        Ok(()) => (StatusCode::ACCEPTED, "If the account needs verifying, a new link is on its way").into_response(),
+        Err(e) => db_error(e, "Failed to send verification email"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{login, register, LoginRequest, RegisterRequest};
+
+    #[tokio::test]
+    async fn test_accounts_log_in_once_verified() {
+        let pool = crate::test_support::test_pool().await;
+        let email = format!("verify_{}@example.com", Uuid::new_v4());
+        let account = RegisterRequest { email: email.clone(), username: format!("verify_{}", Uuid::new_v4().simple()), password: "correct horse battery staple".to_string(), captcha_token: None };
+        assert_eq!(register(Extension(pool.clone()), crate::audit::ClientInfo::default(), Json(account.clone())).await.into_response().status(), StatusCode::CREATED);
+
+        let login_as = || login(Extension(pool.clone()), crate::audit::ClientInfo::default(), Json(LoginRequest { email: email.clone(), password: account.password.clone() }));
+        if verification_required() {
+            assert_eq!(login_as().await.into_response().status(), StatusCode::FORBIDDEN);
+        }
+
+        // The token only travels in the email
+        let body = sqlx::query_scalar::<_, String>("SELECT message->>'body' FROM outbox WHERE channel = 'email' AND message->>'to' = $1")
+            .bind(&email)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let token = body.split("token=").nth(1).unwrap().split_whitespace().next().unwrap().to_string();
+
+        let verify = |token: String| verify_email(Extension(pool.clone()), Json(VerifyEmailRequest { token }));
+        assert_eq!(verify("not-a-token".to_string()).await.into_response().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(verify(token.clone()).await.into_response().status(), StatusCode::OK);
+        assert_eq!(verify(token).await.into_response().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(login_as().await.into_response().status(), StatusCode::OK);
+    }
+}